@@ -8,7 +8,7 @@ use crate::{
         connectors::{
             Connectors, HttpPeerConfig, RouteMatcher, UpstreamConfig, UpstreamContextConfig, ALPN,
         },
-        listeners::{ListenerConfig, ListenerKind, Listeners},
+        listeners::{ListenerConfig, ListenerKind, Listeners, SocketOptions},
         simple_response_type::SimpleResponseConfig,
     },
     internal::{Config, ProxyConfig},
@@ -72,6 +72,9 @@ impl CliConfigBuilder {
                 addr: format!("0.0.0.0:{}", port),
                 tls: None,
                 offer_h2: false,
+                proxy_protocol: false,
+                max_conn_rate: None,
+                socket: SocketOptions::default(),
             },
         };
 
@@ -95,6 +98,12 @@ impl CliConfigBuilder {
                     let host = uri
                         .host()
                         .ok_or_else(|| miette::miette!("Proxy url must have a host"))?;
+                    if uri.path_and_query().is_some_and(|p| p.as_str() != "/") {
+                        return Err(miette::miette!(
+                            "Proxy url '{}' must not have a path, it is not sent upstream",
+                            url_str
+                        ));
+                    }
                     let port = uri.port_u16().unwrap_or(80);
                     let addr = format!("{}:{}", host, port);
 
@@ -110,7 +119,6 @@ impl CliConfigBuilder {
                         sni: String::new(),
                         tls: false,
                         prefix_path,
-                        target_path: uri.path().parse().into_diagnostic()?,
                         matcher: route.route_match.match_type,
                     })
                 }
@@ -120,6 +128,19 @@ impl CliConfigBuilder {
                 upstream,
                 chains: vec![],
                 lb_options: None,
+                compression: None,
+                sse: None,
+                cache: None,
+                retry: None,
+                mirror: None,
+                timeout: None,
+                allow_upgrades: true,
+                grpc: false,
+                upstream_tls: None,
+                h2: None,
+                debug_trace: false,
+                conditions: Default::default(),
+                rewrite: None,
             });
         }
 
@@ -128,6 +149,10 @@ impl CliConfigBuilder {
             listeners: Listeners {
                 list_cfgs: vec![listener],
             },
+            access_log: None,
+            limits: None,
+            error_pages: None,
+            maintenance: None,
             connectors: Connectors { upstreams },
         };
 
@@ -138,6 +163,12 @@ impl CliConfigBuilder {
             pid_file: None,
             upgrade_socket: None,
             upgrade: false,
+            production: false,
+            config_version_header: false,
+            admin: None,
+            metrics_listener: None,
+            shutdown_grace: None,
+            acme: None,
             basic_proxies: vec![proxy_config],
             file_servers: vec![],
         })
@@ -2,7 +2,9 @@
 
 use std::path::PathBuf;
 
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
+
+use crate::kdl::schema::{docs::DocsFormat, export::ExportFormat};
 
 #[derive(Parser, Debug)]
 pub struct Cli {
@@ -34,6 +36,11 @@ pub struct Cli {
     #[arg(long)]
     pub pidfile: Option<PathBuf>,
 
+    /// Profile from the `profiles` section to apply over the configuration.
+    /// Defaults to the MOTYA_PROFILE environment variable
+    #[arg(long, global = true)]
+    pub profile: Option<String>,
+
     #[command(subcommand)]
     pub command: Option<Commands>,
 }
@@ -58,6 +65,77 @@ pub enum Commands {
         #[arg(short, long)]
         map: Vec<String>,
     },
+
+    /// Check a configuration and the files it references, print every problem
+    /// found and exit non-zero if there is any. Nothing is started.
+    Validate {
+        /// Path to the entry configuration file in KDL format
+        entry: PathBuf,
+    },
+
+    /// Print what the configuration accepts, generated from the parser itself.
+    Schema {
+        #[command(subcommand)]
+        command: SchemaCommand,
+    },
+
+    /// Tools for working with configuration files. Nothing is started.
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum SchemaCommand {
+    /// Print reference documentation for every configuration node.
+    Docs {
+        #[arg(long, value_enum, default_value_t = DocsFormat::Markdown)]
+        format: DocsFormat,
+
+        /// Language of the descriptions, where they have it
+        #[arg(long, default_value = "en")]
+        lang: String,
+    },
+
+    /// Print the schema for validation tooling: the node tree as JSON, or a
+    /// JSON Schema for configs written as JSON.
+    Export {
+        #[arg(long, value_enum, default_value_t = ExportFormat::JsonSchema)]
+        format: ExportFormat,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum ConfigCommand {
+    /// Load two configurations and print what the second one changes in
+    /// services, listeners, routes and definitions. Exits with 0 when they are
+    /// the same, 1 when they differ and 2 when either fails to load.
+    Diff {
+        /// Path to the entry configuration file in use
+        old: PathBuf,
+
+        /// Path to the entry configuration file to review
+        new: PathBuf,
+    },
+
+    /// Print the routes a configuration ends up with, after includes, nested
+    /// sections and chains are resolved: path, upstream, chains and load
+    /// balancing of each.
+    Render {
+        /// Path to the entry configuration file in KDL format
+        entry: PathBuf,
+
+        #[arg(long, value_enum, default_value_t = RenderFormat::Table)]
+        format: RenderFormat,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum RenderFormat {
+    Table,
+    Kdl,
+    Json,
 }
 
 pub const BANNER: &str = r#"
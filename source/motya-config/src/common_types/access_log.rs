@@ -0,0 +1,241 @@
+use std::{fmt, path::PathBuf, str::FromStr};
+
+use miette::miette;
+
+use crate::kdl::schema::{definitions::ValueKind, value_info::KdlValueInfo};
+
+/// The line written when `access-log` gives no template.
+pub const DEFAULT_TEMPLATE: &str = "$remote_addr $method $path $status $upstream_addr $duration_ms";
+
+/// A value recorded for every request, named `$<name>` in templates and
+/// `<name>` in JSON lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessLogField {
+    RemoteAddr,
+    Method,
+    Path,
+    Query,
+    Host,
+    Status,
+    UpstreamAddr,
+    DurationMs,
+    BytesSent,
+    RequestId,
+    UserAgent,
+    Referer,
+    GrpcStatus,
+}
+
+impl AccessLogField {
+    pub const ALL: &[AccessLogField] = &[
+        Self::RemoteAddr,
+        Self::Method,
+        Self::Path,
+        Self::Query,
+        Self::Host,
+        Self::Status,
+        Self::UpstreamAddr,
+        Self::DurationMs,
+        Self::BytesSent,
+        Self::RequestId,
+        Self::UserAgent,
+        Self::Referer,
+        Self::GrpcStatus,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::RemoteAddr => "remote_addr",
+            Self::Method => "method",
+            Self::Path => "path",
+            Self::Query => "query",
+            Self::Host => "host",
+            Self::Status => "status",
+            Self::UpstreamAddr => "upstream_addr",
+            Self::DurationMs => "duration_ms",
+            Self::BytesSent => "bytes_sent",
+            Self::RequestId => "request_id",
+            Self::UserAgent => "user_agent",
+            Self::Referer => "referer",
+            Self::GrpcStatus => "grpc_status",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AccessLogPart {
+    Literal(String),
+    Field(AccessLogField),
+}
+
+/// A line template such as [`DEFAULT_TEMPLATE`]: text with `$<field>` variables.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessLogTemplate {
+    pub parts: Vec<AccessLogPart>,
+}
+
+impl FromStr for AccessLogTemplate {
+    type Err = miette::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut rest = s;
+
+        while let Some(pos) = rest.find('$') {
+            literal.push_str(&rest[..pos]);
+            rest = &rest[pos + 1..];
+
+            let len = rest
+                .find(|c: char| !(c.is_ascii_lowercase() || c == '_'))
+                .unwrap_or(rest.len());
+            let name = &rest[..len];
+
+            // `$$` writes a literal dollar sign.
+            if name.is_empty() && rest.starts_with('$') {
+                literal.push('$');
+                rest = &rest[1..];
+                continue;
+            }
+
+            let field = AccessLogField::ALL
+                .iter()
+                .find(|field| field.name() == name)
+                .ok_or_else(|| {
+                    let known: Vec<_> = AccessLogField::ALL.iter().map(|f| f.name()).collect();
+                    miette!(
+                        "Unknown access log variable '${name}'. Available: ${}",
+                        known.join(", $")
+                    )
+                })?;
+
+            if !literal.is_empty() {
+                parts.push(AccessLogPart::Literal(std::mem::take(&mut literal)));
+            }
+            parts.push(AccessLogPart::Field(*field));
+            rest = &rest[len..];
+        }
+
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            parts.push(AccessLogPart::Literal(literal));
+        }
+
+        Ok(Self { parts })
+    }
+}
+
+impl Default for AccessLogTemplate {
+    fn default() -> Self {
+        DEFAULT_TEMPLATE
+            .parse()
+            .expect("the default template is valid")
+    }
+}
+
+impl KdlValueInfo for AccessLogTemplate {
+    fn value_kind() -> ValueKind {
+        ValueKind::TypedString("access-log-template".into())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AccessLogFormatKind {
+    Text,
+    Json,
+}
+
+impl FromStr for AccessLogFormatKind {
+    type Err = miette::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            other => Err(miette!(
+                "Available formats: 'text' (a template line) or 'json' (one object per line). got {other}"
+            )),
+        }
+    }
+}
+
+impl KdlValueInfo for AccessLogFormatKind {
+    fn value_kind() -> ValueKind {
+        ValueKind::Enum(vec!["text".into(), "json".into()])
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AccessLogFormat {
+    Text(AccessLogTemplate),
+    /// Every field, as one JSON object per line.
+    Json,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AccessLogSink {
+    Stdout,
+    File(PathBuf),
+}
+
+impl fmt::Display for AccessLogSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Stdout => write!(f, "stdout"),
+            Self::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// Where and how a proxy service logs the requests it handled, from `access-log`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessLogConfig {
+    pub format: AccessLogFormat,
+    pub sink: AccessLogSink,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_template() {
+        let template = AccessLogTemplate::default();
+
+        assert_eq!(template.parts.len(), 11);
+        assert_eq!(
+            template.parts[0],
+            AccessLogPart::Field(AccessLogField::RemoteAddr)
+        );
+        assert_eq!(template.parts[1], AccessLogPart::Literal(" ".into()));
+        assert_eq!(
+            template.parts[10],
+            AccessLogPart::Field(AccessLogField::DurationMs)
+        );
+    }
+
+    #[test]
+    fn test_literals_and_escaped_dollar() {
+        let template: AccessLogTemplate = "[$status] $$$bytes_sent.".parse().unwrap();
+
+        assert_eq!(
+            template.parts,
+            vec![
+                AccessLogPart::Literal("[".into()),
+                AccessLogPart::Field(AccessLogField::Status),
+                AccessLogPart::Literal("] $".into()),
+                AccessLogPart::Field(AccessLogField::BytesSent),
+                AccessLogPart::Literal(".".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unknown_variable() {
+        let err = "$method $uri".parse::<AccessLogTemplate>().unwrap_err();
+
+        let message = err.to_string();
+        assert!(message.contains("'$uri'"), "{message}");
+        assert!(message.contains("$path"), "{message}");
+    }
+}
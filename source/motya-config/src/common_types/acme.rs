@@ -0,0 +1,38 @@
+use std::{path::PathBuf, time::Duration};
+
+/// Let's Encrypt's production directory, used when `acme.directory` is not set.
+pub const LETS_ENCRYPT_DIRECTORY: &str = "https://acme-v02.api.letsencrypt.org/directory";
+
+/// Renew once the certificate expires within this long, unless `renew-before` says otherwise.
+pub const DEFAULT_RENEW_BEFORE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Certificate provisioning over ACME (HTTP-01), from `system.acme`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AcmeConfig {
+    /// Names the certificate is issued for; the first one is its subject.
+    pub domains: Vec<String>,
+    /// Contact address registered with the ACME account.
+    pub email: Option<String>,
+    /// Directory the account, certificate and key are kept in.
+    pub storage: PathBuf,
+    /// URL of the ACME server's directory.
+    pub directory: String,
+    pub renew_before: Duration,
+}
+
+impl AcmeConfig {
+    /// The certificate chain, leaf first. TLS listeners serving it point `cert-path` here.
+    pub fn cert_path(&self) -> PathBuf {
+        self.storage.join("fullchain.pem")
+    }
+
+    /// The certificate's private key. TLS listeners serving it point `key-path` here.
+    pub fn key_path(&self) -> PathBuf {
+        self.storage.join("privkey.pem")
+    }
+
+    /// The ACME account credentials, created on first use.
+    pub fn account_path(&self) -> PathBuf {
+        self.storage.join("account.json")
+    }
+}
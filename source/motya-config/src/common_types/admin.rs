@@ -0,0 +1,28 @@
+use std::net::SocketAddr;
+
+use cidr::IpCidr;
+
+/// Access control shared by the admin socket and the admin/metrics listeners.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdminConfig {
+    /// Address of the admin HTTP listener; the admin API is off when unset.
+    pub listen: Option<SocketAddr>,
+    /// Client networks that may reach admin routes at all.
+    pub allow: Vec<IpCidr>,
+    /// Name of the environment variable holding the bearer token, if one is required.
+    pub auth_token_env: Option<String>,
+}
+
+impl Default for AdminConfig {
+    /// Without an `allow` list only loopback clients are accepted.
+    fn default() -> Self {
+        Self {
+            listen: None,
+            allow: vec![
+                "127.0.0.0/8".parse().expect("valid loopback network"),
+                "::1/128".parse().expect("valid loopback network"),
+            ],
+            auth_token_env: None,
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use std::str::FromStr;
+use std::{str::FromStr, time::Duration};
 
 use miette::miette;
 
@@ -21,6 +21,8 @@ pub enum SelectionKind {
     Random,
     FvnHash,
     KetamaHashing,
+    LeastConnections,
+    PeakEwma,
 }
 
 impl FromStr for SelectionKind {
@@ -32,8 +34,10 @@ impl FromStr for SelectionKind {
             "Random"     => Ok(SelectionKind::Random),
             "FNV"        => Ok(SelectionKind::FvnHash),
             "Ketama"     => Ok(SelectionKind::KetamaHashing),
+            "LeastConnections" => Ok(SelectionKind::LeastConnections),
+            "PeakEWMA"   => Ok(SelectionKind::PeakEwma),
             unknown => Err(miette!(
-                "Unknown selection algorithm '{}'. Expected one of: 'RoundRobin', 'Random', 'FNV', 'Ketama'",
+                "Unknown selection algorithm '{}'. Expected one of: 'RoundRobin', 'Random', 'FNV', 'Ketama', 'LeastConnections', 'PeakEWMA'",
                 unknown
             )),
         }
@@ -47,6 +51,8 @@ impl KdlValueInfo for SelectionKind {
             "Random".into(),
             "FNV".into(),
             "Ketama".into(),
+            "LeastConnections".into(),
+            "PeakEWMA".into(),
         ])
     }
 }
@@ -54,9 +60,78 @@ impl KdlValueInfo for SelectionKind {
 #[derive(Debug, PartialEq, Clone)]
 pub enum HealthCheckKind {
     None,
+    Tcp(TcpHealthCheckConfig),
+}
+
+pub const DEFAULT_HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+pub const DEFAULT_HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Connect-only probe. A backend is healthy when the TCP handshake completes
+/// within `timeout`, and, if `expect` is set, the first bytes it sends back
+/// (after `send`, when given) start with that pattern.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TcpHealthCheckConfig {
+    pub interval: Duration,
+    pub timeout: Duration,
+    pub send: Option<Vec<u8>>,
+    pub expect: Option<Vec<u8>>,
+}
+
+impl Default for TcpHealthCheckConfig {
+    fn default() -> Self {
+        Self {
+            interval: DEFAULT_HEALTH_CHECK_INTERVAL,
+            timeout: DEFAULT_HEALTH_CHECK_TIMEOUT,
+            send: None,
+            expect: None,
+        }
+    }
+}
+
+pub const DEFAULT_DNS_REFRESH: Duration = Duration::from_secs(30);
+
+pub const DEFAULT_OUTLIER_ERRORS: u32 = 5;
+pub const DEFAULT_OUTLIER_WINDOW: Duration = Duration::from_secs(10);
+pub const DEFAULT_OUTLIER_EJECTION: Duration = Duration::from_secs(30);
+
+/// Passive health checks. A backend whose connections fail or that answers
+/// with a `5xx` `errors` times within `window` is left out of the balancer for
+/// `ejection`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct OutlierDetectionConfig {
+    pub errors: u32,
+    pub window: Duration,
+    pub ejection: Duration,
+}
+
+impl Default for OutlierDetectionConfig {
+    fn default() -> Self {
+        Self {
+            errors: DEFAULT_OUTLIER_ERRORS,
+            window: DEFAULT_OUTLIER_WINDOW,
+            ejection: DEFAULT_OUTLIER_EJECTION,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum DiscoveryKind {
+    /// The backends are the configured servers, as written.
     Static,
+    /// The server hostnames are resolved again every `refresh`, and each address
+    /// they resolve to becomes a backend.
+    Dns { refresh: Duration },
+    /// The backends are the ready endpoints of a Kubernetes service, watched
+    /// through the API of the cluster motya runs in.
+    Kubernetes(KubernetesDiscoveryConfig),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct KubernetesDiscoveryConfig {
+    pub service: String,
+    /// Defaults to the namespace of motya's own pod.
+    pub namespace: Option<String>,
+    /// The name of the service port to send requests to. Defaults to the first
+    /// port of the service.
+    pub port: Option<String>,
 }
@@ -0,0 +1,152 @@
+use std::path::PathBuf;
+
+use crate::common_types::secrets::SecretValue;
+
+/// The realm announced in `WWW-Authenticate` when `basic-auth` gives none.
+pub const DEFAULT_REALM: &str = "Restricted";
+
+/// A password hash format understood in htpasswd files and `users` lists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashScheme {
+    /// `$2y$...`, `$2b$...`, `$2a$...`: `htpasswd -B`.
+    Bcrypt,
+    /// `$apr1$salt$hash`: Apache's MD5 variant, the `htpasswd` default.
+    Apr1,
+    /// `{SHA}base64`: unsalted SHA-1, `htpasswd -s`.
+    Sha1,
+}
+
+impl HashScheme {
+    pub fn detect(hash: &str) -> Option<Self> {
+        if ["$2y$", "$2b$", "$2a$"].iter().any(|p| hash.starts_with(p)) {
+            Some(Self::Bcrypt)
+        } else if hash.starts_with("$apr1$") {
+            Some(Self::Apr1)
+        } else if hash.starts_with("{SHA}") {
+            Some(Self::Sha1)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserEntry {
+    pub user: String,
+    pub hash: String,
+}
+
+/// A `user` of a `users` list, whose hash may come from a secret.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InlineUser {
+    pub user: String,
+    pub hash: SecretValue,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CredentialsSource {
+    /// Re-read whenever the file changes.
+    Htpasswd(PathBuf),
+    Inline(Vec<InlineUser>),
+}
+
+/// A named set of users from `definitions.credentials`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CredentialsConfig {
+    pub name: String,
+    pub source: CredentialsSource,
+}
+
+/// A `basic-auth` chain item, with its credentials resolved.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BasicAuthConfig {
+    pub realm: String,
+    pub credentials: CredentialsConfig,
+}
+
+/// Parses the contents of an htpasswd file: one `user:hash` per line, with
+/// blank lines and `#` comments skipped.
+///
+/// Errors name the first line that is malformed or uses an unsupported hash.
+pub fn parse_htpasswd(contents: &str) -> Result<Vec<UserEntry>, String> {
+    let mut users = Vec::new();
+
+    for (idx, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let Some((user, hash)) = line.split_once(':') else {
+            return Err(format!("line {}: expected 'user:hash'", idx + 1));
+        };
+
+        if HashScheme::detect(hash).is_none() {
+            return Err(format!(
+                "line {}: unsupported password hash for user '{user}', use bcrypt, $apr1$ or {{SHA}}",
+                idx + 1
+            ));
+        }
+
+        users.push(UserEntry {
+            user: user.to_string(),
+            hash: hash.to_string(),
+        });
+    }
+
+    Ok(users)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_scheme() {
+        assert_eq!(
+            HashScheme::detect("$2y$05$c4WoMPo3SXsafkva.HHa6uXQZWr7oboPiC2bT/r7q1BB8I2s0BRqC"),
+            Some(HashScheme::Bcrypt)
+        );
+        assert_eq!(
+            HashScheme::detect("$apr1$r31.....$HqJZimcKQFAMYayBlzkrA/"),
+            Some(HashScheme::Apr1)
+        );
+        assert_eq!(
+            HashScheme::detect("{SHA}VBPuJHI7uixaa6LQGWx4s+5GKNE="),
+            Some(HashScheme::Sha1)
+        );
+        assert_eq!(HashScheme::detect("rqXexS6ZhobKA"), None);
+        assert_eq!(HashScheme::detect("myPassword"), None);
+    }
+
+    #[test]
+    fn test_parse_htpasswd() {
+        let users = parse_htpasswd(
+            "# admins\n\nalice:{SHA}VBPuJHI7uixaa6LQGWx4s+5GKNE=\n  bob:$apr1$r31.....$HqJZimcKQFAMYayBlzkrA/  \n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            users,
+            vec![
+                UserEntry {
+                    user: "alice".into(),
+                    hash: "{SHA}VBPuJHI7uixaa6LQGWx4s+5GKNE=".into(),
+                },
+                UserEntry {
+                    user: "bob".into(),
+                    hash: "$apr1$r31.....$HqJZimcKQFAMYayBlzkrA/".into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_htpasswd_errors() {
+        let err = parse_htpasswd("alice:{SHA}abc=\nbob\n").unwrap_err();
+        assert!(err.starts_with("line 2:"), "{err}");
+
+        let err = parse_htpasswd("carol:rqXexS6ZhobKA\n").unwrap_err();
+        assert!(err.contains("'carol'"), "{err}");
+    }
+}
@@ -0,0 +1,308 @@
+use std::{borrow::Cow, collections::BTreeMap};
+
+use regex::bytes::{NoExpand, Regex};
+
+use crate::common_types::{
+    builtin_filters_name::FilterArgError, byte_size::ByteSize, compression::ContentTypePattern,
+    header_ops::string_arg, value::Value,
+};
+
+/// Bodies rewritten unless a filter sets `content-types`: text, and the formats
+/// that are text under another name.
+pub const DEFAULT_REWRITE_CONTENT_TYPES: &[&str] = &[
+    "text/*",
+    "application/javascript",
+    "application/json",
+    "application/xml",
+];
+
+/// Bodies larger than this pass through unless a filter sets `max-body`.
+pub const DEFAULT_REWRITE_MAX_BODY: usize = 1024 * 1024;
+
+/// A parsed `rewrite-body` filter, applied the same way to requests and responses.
+#[derive(Debug, Clone)]
+pub struct BodyRewrite {
+    /// What to replace. A `find` string is escaped into a pattern of its own.
+    pub pattern: Regex,
+    pub replace: String,
+    /// Whether `$1` and `${name}` in `replace` refer to groups of the pattern,
+    /// which they only do with `pattern`.
+    pub expand: bool,
+    pub content_types: Vec<ContentTypePattern>,
+    pub max_body: usize,
+    /// Whether gzip and deflate response bodies are decoded for the rewrite and
+    /// encoded again toward the client. Otherwise the upstream is asked for
+    /// unencoded bodies, and encoded ones pass through untouched.
+    pub decode_upstream: bool,
+}
+
+impl BodyRewrite {
+    /// Parses the arguments of a `rewrite-body` filter:
+    ///
+    /// * `find="..."` or `pattern="..."`, a literal string or a regex
+    /// * `replace="..."`, which may be empty
+    /// * `content-types="text/*,..."`, the bodies to rewrite
+    /// * `max-body="1mb"`, the largest body to rewrite
+    /// * `upstream-encoding="identity"` or `"decode"`, how compressed responses
+    ///   are handled
+    pub fn parse(args: &BTreeMap<String, Value>) -> Result<Self, FilterArgError> {
+        let (pattern, expand) = match (args.contains_key("find"), args.contains_key("pattern")) {
+            (true, true) => {
+                return Err(FilterArgError::at(
+                    "pattern",
+                    "'find' and 'pattern' can't be used together",
+                ))
+            }
+            (true, false) => {
+                let find = string_arg(args, "find")?;
+                if find.is_empty() {
+                    return Err(FilterArgError::at("find", "'find' must not be empty"));
+                }
+                let pattern = Regex::new(&regex::escape(find)).expect("escaped strings are valid");
+                (pattern, false)
+            }
+            (false, true) => {
+                let raw = string_arg(args, "pattern")?;
+                let pattern = Regex::new(raw)
+                    .map_err(|e| FilterArgError::at("pattern", format!("Invalid regex: {e}")))?;
+                (pattern, true)
+            }
+            (false, false) => {
+                return Err(FilterArgError {
+                    arg: None,
+                    message: "Missing argument 'find' or 'pattern'".to_string(),
+                })
+            }
+        };
+
+        let replace = string_arg(args, "replace")?.to_string();
+
+        let content_types = match args.get("content-types") {
+            None => DEFAULT_REWRITE_CONTENT_TYPES
+                .iter()
+                .map(|raw| raw.parse().expect("built-in content types are valid"))
+                .collect(),
+            Some(_) => {
+                let raw = string_arg(args, "content-types")?;
+                let types = raw
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
+                    .map(|s| {
+                        s.parse::<ContentTypePattern>()
+                            .map_err(|e| FilterArgError::at("content-types", e.to_string()))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                if types.is_empty() {
+                    return Err(FilterArgError::at(
+                        "content-types",
+                        "'content-types' lists no content types",
+                    ));
+                }
+                types
+            }
+        };
+
+        let max_body = match args.get("max-body") {
+            None => DEFAULT_REWRITE_MAX_BODY,
+            Some(Value::Integer(n)) => usize::try_from(*n).map_err(|_| {
+                FilterArgError::at("max-body", format!("'{n}' is not a valid size"))
+            })?,
+            Some(_) => string_arg(args, "max-body")?
+                .parse::<ByteSize>()
+                .map_err(|e| FilterArgError::at("max-body", e.to_string()))?
+                .bytes(),
+        };
+        if max_body == 0 {
+            return Err(FilterArgError::at("max-body", "'max-body' must not be 0"));
+        }
+
+        let decode_upstream = match args.get("upstream-encoding") {
+            None => false,
+            Some(_) => match string_arg(args, "upstream-encoding")? {
+                "identity" => false,
+                "decode" => true,
+                other => {
+                    return Err(FilterArgError::at(
+                        "upstream-encoding",
+                        format!("'{other}' is not 'identity' or 'decode'"),
+                    ))
+                }
+            },
+        };
+
+        Ok(Self {
+            pattern,
+            replace,
+            expand,
+            content_types,
+            max_body,
+            decode_upstream,
+        })
+    }
+
+    /// Whether bodies of `content_type` are rewritten. Bodies without a content
+    /// type are left alone.
+    pub fn applies_to(&self, content_type: Option<&str>) -> bool {
+        content_type.is_some_and(|ct| self.content_types.iter().any(|p| p.matches(ct)))
+    }
+
+    /// Replaces every match in `body`.
+    pub fn apply<'a>(&self, body: &'a [u8]) -> Cow<'a, [u8]> {
+        if self.expand {
+            self.pattern.replace_all(body, self.replace.as_bytes())
+        } else {
+            self.pattern
+                .replace_all(body, NoExpand(self.replace.as_bytes()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(pairs: &[(&str, &str)]) -> BTreeMap<String, Value> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), Value::String(v.to_string())))
+            .collect()
+    }
+
+    fn rewrite(pairs: &[(&str, &str)], body: &str) -> String {
+        let rewrite = BodyRewrite::parse(&args(pairs)).unwrap();
+        String::from_utf8(rewrite.apply(body.as_bytes()).into_owned()).unwrap()
+    }
+
+    #[test]
+    fn test_literal() {
+        assert_eq!(
+            rewrite(
+                &[
+                    ("find", "http://legacy.local"),
+                    ("replace", "https://shop.example.com")
+                ],
+                "<a href=\"http://legacy.local/cart\">http://legacy.local</a>",
+            ),
+            "<a href=\"https://shop.example.com/cart\">https://shop.example.com</a>"
+        );
+        // Neither side is a regex.
+        assert_eq!(
+            rewrite(&[("find", "a.b"), ("replace", "$1")], "a.b axb"),
+            "$1 axb"
+        );
+    }
+
+    #[test]
+    fn test_pattern() {
+        assert_eq!(
+            rewrite(
+                &[
+                    ("pattern", "(?i)</body>"),
+                    ("replace", "<div>staging</div></BODY>")
+                ],
+                "<html><body>hi</Body></html>",
+            ),
+            "<html><body>hi<div>staging</div></BODY></html>"
+        );
+        assert_eq!(
+            rewrite(
+                &[
+                    ("pattern", "v(?<major>\\d+)/"),
+                    ("replace", "v${major}-legacy/")
+                ],
+                "/api/v1/users",
+            ),
+            "/api/v1-legacy/users"
+        );
+        assert_eq!(
+            rewrite(&[("pattern", "\\s+"), ("replace", "")], "a b\n c"),
+            "abc"
+        );
+    }
+
+    #[test]
+    fn test_defaults() {
+        let rewrite = BodyRewrite::parse(&args(&[("find", "x"), ("replace", "y")])).unwrap();
+
+        assert_eq!(rewrite.max_body, DEFAULT_REWRITE_MAX_BODY);
+        assert!(!rewrite.decode_upstream);
+        assert!(rewrite.applies_to(Some("text/html; charset=utf-8")));
+        assert!(rewrite.applies_to(Some("application/json")));
+        assert!(!rewrite.applies_to(Some("image/png")));
+        assert!(!rewrite.applies_to(None));
+    }
+
+    #[test]
+    fn test_content_types_and_max_body() {
+        let rewrite = BodyRewrite::parse(&args(&[
+            ("find", "x"),
+            ("replace", "y"),
+            ("content-types", "text/html, application/*"),
+            ("max-body", "64kb"),
+        ]))
+        .unwrap();
+
+        assert_eq!(rewrite.max_body, 64 * 1024);
+        assert!(rewrite.applies_to(Some("application/xhtml+xml")));
+        assert!(!rewrite.applies_to(Some("text/plain")));
+
+        let mut with_integer = args(&[("find", "x"), ("replace", "y")]);
+        with_integer.insert("max-body".to_string(), Value::Integer(512));
+        assert_eq!(BodyRewrite::parse(&with_integer).unwrap().max_body, 512);
+    }
+
+    #[test]
+    fn test_upstream_encoding() {
+        let parse = |strategy: &str| {
+            BodyRewrite::parse(&args(&[
+                ("find", "x"),
+                ("replace", "y"),
+                ("upstream-encoding", strategy),
+            ]))
+        };
+
+        assert!(parse("decode").unwrap().decode_upstream);
+        assert!(!parse("identity").unwrap().decode_upstream);
+        let err = parse("gzip").unwrap_err();
+        assert_eq!(err.arg.as_deref(), Some("upstream-encoding"));
+    }
+
+    #[test]
+    fn test_invalid_args() {
+        let cases: &[(&[(&str, &str)], Option<&str>)] = &[
+            (&[("replace", "y")], None),
+            (&[("find", "x")], None),
+            (
+                &[("find", "x"), ("pattern", "x"), ("replace", "y")],
+                Some("pattern"),
+            ),
+            (&[("find", ""), ("replace", "y")], Some("find")),
+            (
+                &[("pattern", "(unclosed"), ("replace", "y")],
+                Some("pattern"),
+            ),
+            (
+                &[("find", "x"), ("replace", "y"), ("content-types", "html")],
+                Some("content-types"),
+            ),
+            (
+                &[("find", "x"), ("replace", "y"), ("content-types", " , ")],
+                Some("content-types"),
+            ),
+            (
+                &[("find", "x"), ("replace", "y"), ("max-body", "1tb")],
+                Some("max-body"),
+            ),
+            (
+                &[("find", "x"), ("replace", "y"), ("max-body", "0")],
+                Some("max-body"),
+            ),
+        ];
+
+        for (pairs, arg) in cases {
+            let err = BodyRewrite::parse(&args(pairs)).unwrap_err();
+            assert_eq!(err.arg.as_deref(), *arg, "{pairs:?}: {}", err.message);
+        }
+    }
+}
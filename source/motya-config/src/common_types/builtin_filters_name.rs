@@ -1,26 +1,227 @@
+use std::collections::BTreeMap;
+
+use crate::common_types::{
+    body_rewrite::BodyRewrite,
+    cors::Cors,
+    ext_auth::ExtAuth,
+    header_ops::{HeaderOp, HeaderOpKind},
+    throttle::Throttle,
+    value::Value,
+};
+
 #[macro_export]
 macro_rules! define_builtin_filters {
     ($callback:ident) => {
         $callback! {
             actions: {
                 "motya.filters.block-cidr-range" => CidrRangeFilter,
+                "motya.filters.cidr-allow" => CidrAllowFilter,
+                "motya.filters.cidr-deny" => CidrDenyFilter,
+                "motya.filters.delay" => DelayFilter,
+                "motya.filters.ext-auth" => ExtAuthFilter,
             }
 
             requests: {
-                "motya.request.upsert-header" => RequestUpsertHeader,
-                "motya.request.remove-header" => RequestRemoveHeaderKeyRegex,
+                "motya.request.set-header" => SetHeader,
+                "motya.request.upsert-header" => SetHeader,
+                "motya.request.remove-header" => RemoveHeader,
+                "motya.request.remove-header-regex" => RemoveHeaderRegex,
+                "motya.request.rename-header" => RenameHeader,
                 "motya.request.strip-prefix" => StripPrefix,
                 "motya.request.rewrite-path" => RewritePathRegex,
             }
 
             responses: {
-                "motya.response.upsert-header" => ResponseUpsertHeader,
-                "motya.response.remove-header" => ResponseRemoveHeaderKeyRegex,
+                "motya.response.set-header" => SetHeader,
+                "motya.response.upsert-header" => SetHeader,
+                "motya.response.remove-header" => RemoveHeader,
+                "motya.response.remove-header-regex" => RemoveHeaderRegex,
+                "motya.response.rename-header" => RenameHeader,
+                "motya.response.throttle" => ThrottleFilter,
+            }
+
+            request_bodies: {
+                "motya.request.rewrite-body" => RewriteBody,
+            }
+
+            response_bodies: {
+                "motya.response.rewrite-body" => RewriteBody,
+            }
+
+            actions_with_responses: {
+                "motya.filters.cors" => CorsFilter,
             }
         }
     };
 }
 
+/// Built-in filters meant for staging only, e.g. fault injection.
+///
+/// The linker refuses them when the `system` block sets `production #true`.
+pub const STAGING_ONLY_FILTERS: &[&str] = &["motya.filters.delay"];
+
+pub fn is_staging_only(name: &fqdn::FQDN) -> bool {
+    STAGING_ONLY_FILTERS
+        .iter()
+        .any(|f| f.parse::<fqdn::FQDN>().is_ok_and(|f| &f == name))
+}
+
+pub fn staging_only_message(name: &fqdn::FQDN) -> String {
+    format!("Filter '{name}' is for staging only and is not allowed when 'production' is enabled")
+}
+
+/// Built-in filters taking an `addrs` list of CIDR ranges.
+///
+/// The list is checked when the configuration is compiled, so a typo is reported
+/// at the `filter` node instead of when the chain is built.
+pub const CIDR_FILTERS: &[&str] = &[
+    "motya.filters.block-cidr-range",
+    "motya.filters.cidr-allow",
+    "motya.filters.cidr-deny",
+];
+
+/// Built-in filters rewriting bodies, with the arguments of [BodyRewrite::parse].
+pub const BODY_REWRITE_FILTERS: &[&str] =
+    &["motya.request.rewrite-body", "motya.response.rewrite-body"];
+
+/// The built-in filter asking an authorization service, with the arguments of
+/// [ExtAuth::parse].
+pub const EXT_AUTH_FILTER: &str = "motya.filters.ext-auth";
+
+/// The built-in CORS filter, with the arguments of [Cors::parse].
+pub const CORS_FILTER: &str = "motya.filters.cors";
+
+/// The built-in filter pacing response bodies, with the arguments of [Throttle::parse].
+pub const THROTTLE_FILTER: &str = "motya.response.throttle";
+
+/// Built-in filters running a Lua script, in builds with the `lua` feature.
+pub const LUA_FILTERS: &[&str] = &["motya.request.lua", "motya.response.lua"];
+
+pub fn is_lua_filter(name: &fqdn::FQDN) -> bool {
+    LUA_FILTERS
+        .iter()
+        .any(|f| f.parse::<fqdn::FQDN>().is_ok_and(|f| &f == name))
+}
+
+/// A bad argument of a built-in filter, with the argument to point at if there is one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilterArgError {
+    pub arg: Option<String>,
+    pub message: String,
+}
+
+impl FilterArgError {
+    pub fn at(arg: &str, message: impl Into<String>) -> Self {
+        Self {
+            arg: Some(arg.to_string()),
+            message: message.into(),
+        }
+    }
+
+    pub fn missing(arg: &str) -> Self {
+        Self {
+            arg: None,
+            message: format!("Missing argument '{arg}'"),
+        }
+    }
+}
+
+/// Checks the arguments of built-in filters that can be checked without
+/// building them. Other filters always pass.
+pub fn check_builtin_args(
+    name: &fqdn::FQDN,
+    args: &BTreeMap<String, Value>,
+) -> Result<(), FilterArgError> {
+    if let Some(kind) = HeaderOpKind::of_filter(&name.to_string()) {
+        return HeaderOp::parse(kind, args).map(|_| ());
+    }
+
+    if BODY_REWRITE_FILTERS
+        .iter()
+        .any(|f| f.parse::<fqdn::FQDN>().is_ok_and(|f| &f == name))
+    {
+        let on_requests = "motya.request.rewrite-body"
+            .parse::<fqdn::FQDN>()
+            .is_ok_and(|f| &f == name);
+        if on_requests && args.contains_key("upstream-encoding") {
+            return Err(FilterArgError::at(
+                "upstream-encoding",
+                "'upstream-encoding' only applies to 'motya.response.rewrite-body'",
+            ));
+        }
+        return BodyRewrite::parse(args).map(|_| ());
+    }
+
+    if EXT_AUTH_FILTER
+        .parse::<fqdn::FQDN>()
+        .is_ok_and(|f| &f == name)
+    {
+        return ExtAuth::parse(args).map(|_| ());
+    }
+
+    if CORS_FILTER.parse::<fqdn::FQDN>().is_ok_and(|f| &f == name) {
+        return Cors::parse(args).map(|_| ());
+    }
+
+    if THROTTLE_FILTER
+        .parse::<fqdn::FQDN>()
+        .is_ok_and(|f| &f == name)
+    {
+        return Throttle::parse(args).map(|_| ());
+    }
+
+    if is_lua_filter(name) {
+        return match args.get("script") {
+            Some(Value::String(_)) => Ok(()),
+            Some(_) => Err(FilterArgError::at("script", "'script' must be a string")),
+            None => Err(FilterArgError {
+                arg: None,
+                message: format!("Filter '{name}' needs 'script', the path of a Lua script"),
+            }),
+        };
+    }
+
+    let is_cidr_filter = CIDR_FILTERS
+        .iter()
+        .any(|f| f.parse::<fqdn::FQDN>().is_ok_and(|f| &f == name));
+    if !is_cidr_filter {
+        return Ok(());
+    }
+
+    let addrs = match args.get("addrs") {
+        Some(Value::String(addrs)) => addrs,
+        Some(_) => return Err(FilterArgError::at("addrs", "'addrs' must be a string")),
+        None => {
+            return Err(FilterArgError {
+                arg: None,
+                message: format!(
+                    "Filter '{name}' needs 'addrs', a comma separated list of CIDR ranges"
+                ),
+            })
+        }
+    };
+
+    let mut count = 0;
+    for addr in addrs.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        addr.parse::<cidr::IpCidr>().map_err(|e| {
+            FilterArgError::at(
+                "addrs",
+                format!("'{addr}' in 'addrs' is not a CIDR range or address: {e}"),
+            )
+        })?;
+        count += 1;
+    }
+
+    if count == 0 {
+        return Err(FilterArgError::at(
+            "addrs",
+            format!("Filter '{name}' lists no ranges in 'addrs'"),
+        ));
+    }
+
+    Ok(())
+}
+
 macro_rules! impl_definitions_table {
     (
         $(
@@ -0,0 +1,81 @@
+use std::{fmt, str::FromStr};
+
+use miette::miette;
+
+use crate::kdl::schema::{definitions::ValueKind, value_info::KdlValueInfo};
+
+/// A size in bytes, written as a number with an optional unit: `512`, `64kb`, `1mb`, `2gb`.
+///
+/// Units are binary (`1kb` is 1024 bytes) and case-insensitive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ByteSize(pub usize);
+
+impl ByteSize {
+    pub fn bytes(self) -> usize {
+        self.0
+    }
+}
+
+impl FromStr for ByteSize {
+    type Err = miette::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let raw = s.trim();
+        let split = raw.find(|c: char| !c.is_ascii_digit()).unwrap_or(raw.len());
+        let (number, unit) = raw.split_at(split);
+
+        let number: usize = number
+            .parse()
+            .map_err(|_| miette!("Expected a size like '512', '64kb' or '1mb', got '{s}'"))?;
+
+        let multiplier: usize = match unit.trim().to_ascii_lowercase().as_str() {
+            "" | "b" => 1,
+            "k" | "kb" => 1 << 10,
+            "m" | "mb" => 1 << 20,
+            "g" | "gb" => 1 << 30,
+            other => {
+                return Err(miette!(
+                    "Unknown size unit '{other}' in '{s}'. Expected one of: 'b', 'kb', 'mb', 'gb'"
+                ))
+            }
+        };
+
+        number
+            .checked_mul(multiplier)
+            .map(ByteSize)
+            .ok_or_else(|| miette!("Size '{s}' is too large"))
+    }
+}
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}b", self.0)
+    }
+}
+
+impl KdlValueInfo for ByteSize {
+    fn value_kind() -> ValueKind {
+        ValueKind::TypedString("byte-size".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_units() {
+        assert_eq!("512".parse::<ByteSize>().unwrap(), ByteSize(512));
+        assert_eq!("64kb".parse::<ByteSize>().unwrap(), ByteSize(64 * 1024));
+        assert_eq!("1MB".parse::<ByteSize>().unwrap(), ByteSize(1024 * 1024));
+        assert_eq!("2g".parse::<ByteSize>().unwrap(), ByteSize(2 << 30));
+    }
+
+    #[test]
+    fn test_invalid_sizes() {
+        assert!("".parse::<ByteSize>().is_err());
+        assert!("mb".parse::<ByteSize>().is_err());
+        assert!("1tb".parse::<ByteSize>().is_err());
+        assert!("-1kb".parse::<ByteSize>().is_err());
+    }
+}
@@ -0,0 +1,273 @@
+use std::{fmt, str::FromStr};
+
+use miette::miette;
+
+use crate::kdl::schema::{definitions::ValueKind, value_info::KdlValueInfo};
+
+/// Content types that are already compressed by their own format.
+///
+/// Running gzip/brotli over these burns CPU and usually makes the payload
+/// slightly larger, so they are skipped unless a route opts out with
+/// `default-exclusions=#false`. Note that `image/svg+xml` is intentionally
+/// absent: SVG is plain text and compresses well.
+pub const DEFAULT_EXCLUDED_CONTENT_TYPES: &[&str] = &[
+    "image/jpeg",
+    "image/png",
+    "image/gif",
+    "image/webp",
+    "image/avif",
+    "image/heic",
+    "image/heif",
+    "video/*",
+    "audio/*",
+    "font/woff",
+    "font/woff2",
+    "application/zip",
+    "application/gzip",
+    "application/x-gzip",
+    "application/x-bzip2",
+    "application/x-xz",
+    "application/x-7z-compressed",
+    "application/x-rar-compressed",
+    "application/vnd.rar",
+    "application/zstd",
+    "application/x-brotli",
+];
+
+pub const DEFAULT_COMPRESSION_LEVEL: u32 = 6;
+
+/// An encoding the compressor can produce, by its `Content-Encoding` token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionAlgorithm {
+    Brotli,
+    Gzip,
+    Zstd,
+}
+
+impl CompressionAlgorithm {
+    pub const ALL: [Self; 3] = [Self::Brotli, Self::Gzip, Self::Zstd];
+
+    pub fn token(self) -> &'static str {
+        match self {
+            CompressionAlgorithm::Brotli => "br",
+            CompressionAlgorithm::Gzip => "gzip",
+            CompressionAlgorithm::Zstd => "zstd",
+        }
+    }
+}
+
+impl FromStr for CompressionAlgorithm {
+    type Err = miette::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|algorithm| algorithm.token().eq_ignore_ascii_case(s.trim()))
+            .ok_or_else(|| {
+                miette!(
+                    "Unknown compression algorithm '{s}'. Expected one of: 'br', 'gzip', 'zstd'"
+                )
+            })
+    }
+}
+
+impl fmt::Display for CompressionAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.token())
+    }
+}
+
+/// A single `type/subtype` pattern. The subtype may be `*` to match the whole family.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContentTypePattern {
+    Exact { kind: String, subtype: String },
+    AnySubtype { kind: String },
+}
+
+impl ContentTypePattern {
+    /// Checks a `Content-Type` header value against the pattern.
+    ///
+    /// Parameters (`; charset=utf-8`) are ignored and the comparison is case-insensitive.
+    pub fn matches(&self, content_type: &str) -> bool {
+        let essence = content_type.split(';').next().unwrap_or_default().trim();
+
+        let Some((kind, subtype)) = essence.split_once('/') else {
+            return false;
+        };
+
+        match self {
+            ContentTypePattern::Exact {
+                kind: k,
+                subtype: s,
+            } => k.eq_ignore_ascii_case(kind) && s.eq_ignore_ascii_case(subtype),
+            ContentTypePattern::AnySubtype { kind: k } => k.eq_ignore_ascii_case(kind),
+        }
+    }
+}
+
+impl FromStr for ContentTypePattern {
+    type Err = miette::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, subtype) = s
+            .trim()
+            .split_once('/')
+            .ok_or_else(|| miette!("Expected a content type in 'type/subtype' form, got '{s}'"))?;
+
+        if kind.is_empty() || kind == "*" {
+            return Err(miette!(
+                "Content type '{s}' must name a top-level type, e.g. 'image/*'"
+            ));
+        }
+
+        if subtype.is_empty() {
+            return Err(miette!("Content type '{s}' has an empty subtype"));
+        }
+
+        if subtype == "*" {
+            Ok(ContentTypePattern::AnySubtype {
+                kind: kind.to_ascii_lowercase(),
+            })
+        } else {
+            Ok(ContentTypePattern::Exact {
+                kind: kind.to_ascii_lowercase(),
+                subtype: subtype.to_ascii_lowercase(),
+            })
+        }
+    }
+}
+
+impl fmt::Display for ContentTypePattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ContentTypePattern::Exact { kind, subtype } => write!(f, "{kind}/{subtype}"),
+            ContentTypePattern::AnySubtype { kind } => write!(f, "{kind}/*"),
+        }
+    }
+}
+
+impl KdlValueInfo for ContentTypePattern {
+    fn value_kind() -> ValueKind {
+        ValueKind::TypedString("content-type".to_string())
+    }
+}
+
+/// Per-route compression settings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompressionConfig {
+    pub level: u32,
+    /// The encodings offered to clients; they pick among them with `Accept-Encoding`.
+    pub algorithms: Vec<CompressionAlgorithm>,
+    /// Responses announcing a smaller body are sent as they are.
+    pub min_size: usize,
+    /// When not empty, only these content types are compressed.
+    pub content_types: Vec<ContentTypePattern>,
+    pub excluded_types: Vec<ContentTypePattern>,
+}
+
+impl CompressionConfig {
+    pub fn default_exclusions() -> Vec<ContentTypePattern> {
+        DEFAULT_EXCLUDED_CONTENT_TYPES
+            .iter()
+            .map(|raw| raw.parse().expect("built-in content types are valid"))
+            .collect()
+    }
+
+    pub fn is_excluded(&self, content_type: &str) -> bool {
+        self.excluded_types.iter().any(|p| p.matches(content_type))
+    }
+
+    /// Whether `content_type` is in the `content-types` list, if there is one.
+    pub fn is_included(&self, content_type: Option<&str>) -> bool {
+        self.content_types.is_empty()
+            || content_type.is_some_and(|ct| self.content_types.iter().any(|p| p.matches(ct)))
+    }
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            level: DEFAULT_COMPRESSION_LEVEL,
+            algorithms: CompressionAlgorithm::ALL.to_vec(),
+            min_size: 0,
+            content_types: Vec::new(),
+            excluded_types: Self::default_exclusions(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_exclusions_skip_binary_formats() {
+        let cfg = CompressionConfig::default();
+
+        assert!(cfg.is_excluded("image/png"));
+        assert!(cfg.is_excluded("video/mp4"));
+        assert!(cfg.is_excluded("application/zip"));
+        assert!(cfg.is_excluded("Font/WOFF2"));
+    }
+
+    #[test]
+    fn test_default_exclusions_keep_text_formats() {
+        let cfg = CompressionConfig::default();
+
+        assert!(!cfg.is_excluded("text/html; charset=utf-8"));
+        assert!(!cfg.is_excluded("application/json"));
+        assert!(!cfg.is_excluded("image/svg+xml"));
+    }
+
+    #[test]
+    fn test_content_types_allowlist() {
+        let cfg = CompressionConfig {
+            content_types: vec![
+                "text/*".parse().unwrap(),
+                "application/json".parse().unwrap(),
+            ],
+            ..CompressionConfig::default()
+        };
+
+        assert!(cfg.is_included(Some("text/css")));
+        assert!(cfg.is_included(Some("application/json; charset=utf-8")));
+        assert!(!cfg.is_included(Some("application/xml")));
+        assert!(!cfg.is_included(None));
+        assert!(CompressionConfig::default().is_included(None));
+    }
+
+    #[test]
+    fn test_algorithm_parsing() {
+        assert_eq!(
+            "BR".parse::<CompressionAlgorithm>().unwrap(),
+            CompressionAlgorithm::Brotli
+        );
+        assert_eq!(
+            " gzip".parse::<CompressionAlgorithm>().unwrap(),
+            CompressionAlgorithm::Gzip
+        );
+        assert!("deflate".parse::<CompressionAlgorithm>().is_err());
+    }
+
+    #[test]
+    fn test_pattern_parsing() {
+        assert_eq!(
+            "Video/*".parse::<ContentTypePattern>().unwrap(),
+            ContentTypePattern::AnySubtype {
+                kind: "video".to_string()
+            }
+        );
+        assert!("video".parse::<ContentTypePattern>().is_err());
+        assert!("*/*".parse::<ContentTypePattern>().is_err());
+        assert!("image/".parse::<ContentTypePattern>().is_err());
+    }
+
+    #[test]
+    fn test_pattern_ignores_garbage_header() {
+        let pattern: ContentTypePattern = "image/*".parse().unwrap();
+
+        assert!(!pattern.matches(""));
+        assert!(!pattern.matches("image"));
+        assert!(pattern.matches("image/png;q=1"));
+    }
+}
@@ -0,0 +1,130 @@
+use std::str::FromStr;
+
+use crate::common_types::key_template::{KeyPart, KeyTemplate};
+
+/// A condition on the request that a chain item runs under (`when=` on the
+/// item), e.g. `${header-x-debug} == '1'`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    /// Rendered from the request as a key is.
+    pub template: KeyTemplate,
+    pub test: ConditionTest,
+    /// As written, for the configuration views.
+    pub source: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConditionTest {
+    /// `${...}`: the template renders to anything.
+    Present,
+    /// `!${...}`: the template renders to nothing.
+    Absent,
+    /// `${...} == 'value'`
+    Equals(String),
+    /// `${...} != 'value'`
+    NotEquals(String),
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let source = s.trim();
+
+        // The first operator compares, the value may contain another.
+        let operator = ["==", "!="]
+            .into_iter()
+            .filter_map(|op| source.find(op).map(|pos| (pos, op)))
+            .min();
+
+        let (template, test) = match operator {
+            Some((pos, op)) => {
+                let value = parse_quoted(&source[pos + op.len()..])?;
+                let test = if op == "==" {
+                    ConditionTest::Equals(value)
+                } else {
+                    ConditionTest::NotEquals(value)
+                };
+                (&source[..pos], test)
+            }
+            None => match source.strip_prefix('!') {
+                Some(template) => (template, ConditionTest::Absent),
+                None => (source, ConditionTest::Present),
+            },
+        };
+
+        let template = KeyTemplate::new(template.trim())?;
+        if template
+            .parts
+            .iter()
+            .all(|part| matches!(part, KeyPart::Literal(_)))
+        {
+            return Err(format!(
+                "'{source}' reads nothing from the request, expected e.g. \"${{header-x-debug}} == '1'\""
+            ));
+        }
+
+        Ok(Self {
+            template,
+            test,
+            source: source.to_string(),
+        })
+    }
+}
+
+/// The value compared against, in single or double quotes.
+fn parse_quoted(value: &str) -> Result<String, String> {
+    let value = value.trim();
+    ['\'', '"']
+        .iter()
+        .find_map(|quote| value.strip_prefix(*quote)?.strip_suffix(*quote))
+        .map(str::to_string)
+        .ok_or_else(|| format!("Expected a quoted value to compare with, got '{value}'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_condition() {
+        let condition: Condition = "${header-x-debug} == '1'".parse().unwrap();
+        assert_eq!(
+            condition.template.parts,
+            vec![KeyPart::Header {
+                name: "x-debug".to_string(),
+                all: false,
+                raw: false,
+            }]
+        );
+        assert_eq!(condition.test, ConditionTest::Equals("1".to_string()));
+
+        let condition: Condition = r#"${method}!="GET""#.parse().unwrap();
+        assert_eq!(condition.test, ConditionTest::NotEquals("GET".to_string()));
+
+        let condition: Condition = "${cookie-session}".parse().unwrap();
+        assert_eq!(condition.test, ConditionTest::Present);
+        let condition: Condition = " !${cookie-session} ".parse().unwrap();
+        assert_eq!(condition.test, ConditionTest::Absent);
+        assert_eq!(condition.source, "!${cookie-session}");
+
+        let condition: Condition = "${host} == ''".parse().unwrap();
+        assert_eq!(condition.test, ConditionTest::Equals(String::new()));
+        let condition: Condition = "${header-x-op} != '=='".parse().unwrap();
+        assert_eq!(condition.test, ConditionTest::NotEquals("==".to_string()));
+    }
+
+    #[test]
+    fn test_invalid_conditions() {
+        for (condition, error) in [
+            ("${header-x-debug} == 1", "Expected a quoted value"),
+            ("${header-x-debug} == '1", "Expected a quoted value"),
+            ("${header-x-debug} == '", "Expected a quoted value"),
+            ("debug == '1'", "reads nothing from the request"),
+            ("${nope}", "Unknown variable: nope"),
+        ] {
+            let err = condition.parse::<Condition>().unwrap_err();
+            assert!(err.contains(error), "{condition}: {err}");
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use std::fmt;
+
+use xxhash_rust::xxh64::xxh64;
+
+use crate::internal::Config;
+
+/// Fingerprint of a resolved [`Config`], used to tell which rollout a running
+/// instance is serving.
+///
+/// The hash is taken over the `Debug` form of the config. It lists every field in
+/// declaration order and the config holds no hash maps, so loading the same files
+/// twice gives the same version. Versions are only comparable between builds of the
+/// same motya release.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ConfigVersion(pub u64);
+
+impl ConfigVersion {
+    pub fn of(config: &Config) -> Self {
+        Self(xxh64(format!("{config:?}").as_bytes(), 0))
+    }
+}
+
+impl fmt::Display for ConfigVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_is_deterministic() {
+        let a = Config::default();
+        let b = Config::default();
+
+        assert_eq!(ConfigVersion::of(&a), ConfigVersion::of(&b));
+    }
+
+    #[test]
+    fn test_version_changes_with_config() {
+        let base = Config::default();
+        let changed = Config {
+            threads_per_service: base.threads_per_service + 1,
+            ..Config::default()
+        };
+
+        assert_ne!(ConfigVersion::of(&base), ConfigVersion::of(&changed));
+    }
+
+    #[test]
+    fn test_version_display() {
+        assert_eq!(ConfigVersion(0xabc123).to_string(), "0000000000abc123");
+    }
+}
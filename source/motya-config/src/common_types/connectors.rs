@@ -1,12 +1,26 @@
-use std::{fmt::Debug, net::SocketAddr, str::FromStr};
+use std::{
+    fmt::{self, Debug},
+    net::SocketAddr,
+    path::PathBuf,
+    str::FromStr,
+    time::Duration,
+};
 
-use http::uri::PathAndQuery;
+use cidr::IpCidr;
+use http::{uri::PathAndQuery, Method};
 use miette::miette;
+use regex::Regex;
 
 use crate::{
-    common_types::{definitions::Modificator, simple_response_type::SimpleResponseConfig},
+    common_types::{
+        compression::CompressionConfig, definitions::Modificator,
+        simple_response_type::SimpleResponseConfig,
+    },
     internal::UpstreamOptions,
-    kdl::{parser::spanned::Spanned, schema::{definitions::ValueKind, value_info::KdlValueInfo}},
+    kdl::{
+        parser::spanned::Spanned,
+        schema::{definitions::ValueKind, value_info::KdlValueInfo},
+    },
 };
 
 #[derive(Clone, Debug, PartialEq)]
@@ -16,11 +30,185 @@ pub enum ALPN {
     H2H1,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
 pub enum RouteMatcher {
     #[default]
     Exact,
     Prefix,
+    Regex(RoutePattern),
+}
+
+/// The path of an `as="regex"` section, compiled when the config is linked.
+///
+/// It has to match the whole request path, so it needs no `^`/`$` anchors.
+#[derive(Clone)]
+pub struct RoutePattern {
+    source: String,
+    regex: Regex,
+}
+
+impl RoutePattern {
+    pub fn new(source: &str) -> Result<Self, regex::Error> {
+        Ok(Self {
+            regex: Regex::new(&format!("^(?:{source})$"))?,
+            source: source.to_string(),
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    pub fn is_match(&self, path: &str) -> bool {
+        self.regex.is_match(path)
+    }
+}
+
+impl Debug for RoutePattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(&self.source, f)
+    }
+}
+
+impl PartialEq for RoutePattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl Eq for RoutePattern {}
+
+/// The methods and query parameters a section is limited to, from its `method` and
+/// `query` properties. A request that fails them goes on to the other sections
+/// matching its path. Empty lists let every request through.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct RouteConditions {
+    pub methods: Vec<Method>,
+    pub query: Vec<QueryCondition>,
+}
+
+impl RouteConditions {
+    pub fn is_empty(&self) -> bool {
+        self.methods.is_empty() && self.query.is_empty()
+    }
+
+    pub fn matches(&self, method: &Method, query: Option<&str>) -> bool {
+        (self.methods.is_empty() || self.methods.contains(method))
+            && self.query.iter().all(|condition| condition.matches(query))
+    }
+}
+
+impl fmt::Display for RouteConditions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let methods: Vec<_> = self.methods.iter().map(Method::as_str).collect();
+        let query: Vec<_> = self.query.iter().map(ToString::to_string).collect();
+
+        match (methods.is_empty(), query.is_empty()) {
+            (true, true) => Ok(()),
+            (false, true) => write!(f, "method={}", methods.join(",")),
+            (true, false) => write!(f, "query={}", query.join("&")),
+            (false, false) => write!(f, "method={} query={}", methods.join(","), query.join("&")),
+        }
+    }
+}
+
+/// The `method` of a section: one or more methods, separated by commas.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteMethods(pub Vec<Method>);
+
+impl FromStr for RouteMethods {
+    type Err = miette::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let methods = s
+            .split(',')
+            .map(|method| {
+                let method = method.trim().to_ascii_uppercase();
+                if method.is_empty() {
+                    return Err(miette!("Empty method in '{s}'"));
+                }
+                method
+                    .parse::<Method>()
+                    .map_err(|_| miette!("'{method}' is not a valid HTTP method"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self(methods))
+    }
+}
+
+impl KdlValueInfo for RouteMethods {
+    fn value_kind() -> ValueKind {
+        ValueKind::TypedString("methods".into())
+    }
+}
+
+/// One condition of the `query` of a section: `name` must be in the query
+/// string, with `value` when given. Both are compared as they appear in the
+/// URL, without percent-decoding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryCondition {
+    pub name: String,
+    pub value: Option<String>,
+}
+
+impl QueryCondition {
+    fn matches(&self, query: Option<&str>) -> bool {
+        query
+            .into_iter()
+            .flat_map(|query| query.split('&'))
+            .any(|pair| match pair.split_once('=') {
+                Some((name, value)) => {
+                    name == self.name && self.value.as_deref().is_none_or(|v| v == value)
+                }
+                None => pair == self.name && self.value.is_none(),
+            })
+    }
+}
+
+impl fmt::Display for QueryCondition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.value {
+            Some(value) => write!(f, "{}={value}", self.name),
+            None => f.write_str(&self.name),
+        }
+    }
+}
+
+/// The `query` of a section: `name=value` or `name` conditions, separated by `&`,
+/// which must all hold.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteQuery(pub Vec<QueryCondition>);
+
+impl FromStr for RouteQuery {
+    type Err = miette::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let conditions = s
+            .split('&')
+            .map(|pair| {
+                let (name, value) = match pair.split_once('=') {
+                    Some((name, value)) => (name, Some(value.to_string())),
+                    None => (pair, None),
+                };
+                if name.is_empty() {
+                    return Err(miette!("Query condition '{pair}' has no parameter name"));
+                }
+                Ok(QueryCondition {
+                    name: name.to_string(),
+                    value,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self(conditions))
+    }
+}
+
+impl KdlValueInfo for RouteQuery {
+    fn value_kind() -> ValueKind {
+        ValueKind::TypedString("query".into())
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -30,7 +218,6 @@ pub struct HttpPeerConfig {
     pub tls: bool,
     pub sni: String,
     pub prefix_path: PathAndQuery,
-    pub target_path: PathAndQuery,
     pub matcher: RouteMatcher,
 }
 
@@ -44,25 +231,256 @@ pub enum UpstreamConfig {
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct UpstreamServer {
-    pub address: std::net::SocketAddr,
+    pub address: ServerAddress,
     pub weight: usize,
 }
 
+/// The address of a `server`: an IP address and port, or a hostname and port
+/// that `discovery "Dns"` resolves.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerAddress {
+    Socket(SocketAddr),
+    Host { host: String, port: u16 },
+}
+
+impl ServerAddress {
+    pub fn as_socket(&self) -> Option<SocketAddr> {
+        match self {
+            ServerAddress::Socket(addr) => Some(*addr),
+            ServerAddress::Host { .. } => None,
+        }
+    }
+}
+
+impl From<SocketAddr> for ServerAddress {
+    fn from(addr: SocketAddr) -> Self {
+        ServerAddress::Socket(addr)
+    }
+}
+
+impl FromStr for ServerAddress {
+    type Err = miette::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(addr) = s.parse::<SocketAddr>() {
+            return Ok(ServerAddress::Socket(addr));
+        }
+
+        let (host, port) = s
+            .rsplit_once(':')
+            .ok_or_else(|| miette!("Expected 'host:port' or 'ip:port', got '{s}'"))?;
+        let port = port
+            .parse::<u16>()
+            .map_err(|_| miette!("Invalid port '{port}' in '{s}'"))?;
+
+        let valid_host = !host.is_empty()
+            && host.split('.').all(|label| {
+                !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            });
+        if !valid_host {
+            return Err(miette!("'{host}' is not a valid hostname"));
+        }
+
+        Ok(ServerAddress::Host {
+            host: host.to_ascii_lowercase(),
+            port,
+        })
+    }
+}
+
+impl std::fmt::Display for ServerAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ServerAddress::Socket(addr) => addr.fmt(f),
+            ServerAddress::Host { host, port } => write!(f, "{host}:{port}"),
+        }
+    }
+}
+
+impl KdlValueInfo for ServerAddress {
+    fn value_kind() -> ValueKind {
+        ValueKind::TypedString("server-addr".to_string())
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct MultiServerUpstreamConfig {
     pub servers: Vec<UpstreamServer>,
     pub tls_sni: Option<String>,
     pub alpn: ALPN,
     pub prefix_path: PathAndQuery,
-    pub target_path: PathAndQuery,
     pub matcher: RouteMatcher,
 }
 
+/// How the certificate of a TLS upstream is checked, and the certificate motya
+/// shows it, from the `tls` of a `proxy`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UpstreamTlsConfig {
+    /// Whether the certificate chain and the hostname of the upstream are verified.
+    pub verify_cert: bool,
+    /// PEM file with the CAs the upstream certificate must chain to, instead of
+    /// the ones of the system.
+    pub ca_path: Option<PathBuf>,
+    /// Shown to upstreams that ask for a client certificate (mutual TLS).
+    pub client_cert: Option<ClientCertConfig>,
+    /// SHA-256 digests of the certificates the upstream may present. Empty lets
+    /// any certificate through that passes verification.
+    pub cert_sha256: Vec<[u8; 32]>,
+}
+
+impl Default for UpstreamTlsConfig {
+    fn default() -> Self {
+        Self {
+            verify_cert: true,
+            ca_path: None,
+            client_cert: None,
+            cert_sha256: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientCertConfig {
+    /// PEM certificate chain, leaf first.
+    pub cert_path: PathBuf,
+    /// PEM private key of the leaf.
+    pub key_path: PathBuf,
+}
+
+/// The `cert-sha256` of an upstream `tls`: hex SHA-256 digests of certificates,
+/// separated by commas. Bytes may be separated by colons, as `openssl x509
+/// -fingerprint -sha256` prints them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CertPins(pub Vec<[u8; 32]>);
+
+impl FromStr for CertPins {
+    type Err = miette::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let pins = s
+            .split(',')
+            .map(|pin| {
+                let hex: String = pin.trim().chars().filter(|c| *c != ':').collect();
+                let invalid = || miette!("'{}' is not a hex SHA-256 digest", pin.trim());
+                if hex.len() != 64 || !hex.is_ascii() {
+                    return Err(invalid());
+                }
+
+                let mut digest = [0; 32];
+                for (byte, pair) in digest.iter_mut().zip(hex.as_bytes().chunks(2)) {
+                    let pair = std::str::from_utf8(pair).map_err(|_| invalid())?;
+                    *byte = u8::from_str_radix(pair, 16).map_err(|_| invalid())?;
+                }
+                Ok(digest)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self(pins))
+    }
+}
+
+impl KdlValueInfo for CertPins {
+    fn value_kind() -> ValueKind {
+        ValueKind::TypedString("sha256-list".into())
+    }
+}
+
+/// HTTP/2 settings of the connections to an upstream, from the `h2` of a
+/// `proxy`. Unset fields keep the defaults of pingora.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct H2Config {
+    /// Requests sent over one connection at once. Past that another connection
+    /// is opened. pingora's default of 1 sends a single request per connection.
+    pub max_streams: Option<usize>,
+    /// How often a PING is sent on a connection, so that one the upstream
+    /// dropped is noticed before a request is sent on it.
+    pub ping_interval: Option<Duration>,
+    /// How long an idle connection is kept in the pool to be reused.
+    pub idle_timeout: Option<Duration>,
+}
+
+/// When a failed upstream request is tried again, on another backend if the
+/// route has several. Only requests whose response has not started are retried.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryConfig {
+    /// Retries after the first attempt.
+    pub attempts: usize,
+    pub on: RetryOn,
+    /// Wait before the first retry, doubled for each one after it.
+    pub backoff: Duration,
+    /// Retries in flight on the route at once. Past that, failures are returned
+    /// as they are, so an upstream outage does not multiply the load on it.
+    pub max_concurrent: usize,
+}
+
+impl RetryConfig {
+    pub const DEFAULT_MAX_CONCURRENT: usize = 32;
+}
+
+/// The failures a [`RetryConfig`] retries, from a list such as
+/// `"5xx,connect-failure"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RetryOn {
+    /// `connect-failure`: no connection to the backend could be made.
+    pub connect_failure: bool,
+    /// `error`: the connection failed before the response started.
+    pub error: bool,
+    /// `5xx`: the backend answered with a server error.
+    pub server_error: bool,
+}
+
+impl FromStr for RetryOn {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut on = RetryOn::default();
+        for cond in s.split(',').map(str::trim).filter(|c| !c.is_empty()) {
+            match cond {
+                "connect-failure" => on.connect_failure = true,
+                "error" => on.error = true,
+                "5xx" => on.server_error = true,
+                other => {
+                    return Err(format!(
+                        "Unknown retry condition '{other}'. Available: 'connect-failure', 'error', '5xx'"
+                    ))
+                }
+            }
+        }
+
+        if on == RetryOn::default() {
+            return Err("'on' lists no retry conditions".to_string());
+        }
+        Ok(on)
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum ConnectorsLeaf {
     Upstream(UpstreamConfig),
     Modificator(Modificator),
     LoadBalance(UpstreamOptions),
+    Compression(CompressionConfig),
+    Sse(SseConfig),
+    Cache(CacheConfig),
+    Retry(RetryConfig),
+    Mirror(MirrorConfig),
+    Timeout(TimeoutConfig),
+    Rewrite(RewriteConfig),
+    /// `tls` of the `proxy` of a section.
+    UpstreamTls(UpstreamTlsConfig),
+    /// `h2` of the `proxy` of a section.
+    H2(H2Config),
+    /// `allow-upgrades` of a section.
+    AllowUpgrades(bool),
+    /// `debug-trace` of a section.
+    DebugTrace(bool),
+    /// `method` and `query` of a section.
+    Conditions(RouteConditions),
+    /// `grpc=#true` on the `proxy` of a section.
+    Grpc,
+    /// A `use-group` on the `proxy` of a section that names no upstream group,
+    /// which is already reported.
+    UnresolvedGroup,
     Section(Vec<Spanned<ConnectorsLeaf>>),
 }
 
@@ -76,12 +494,122 @@ pub struct UpstreamContextConfig {
     pub upstream: UpstreamConfig,
     pub chains: Vec<Modificator>,
     pub lb_options: Option<UpstreamOptions>,
+    pub compression: Option<CompressionConfig>,
+    pub sse: Option<SseConfig>,
+    pub cache: Option<CacheConfig>,
+    pub retry: Option<RetryConfig>,
+    pub mirror: Option<MirrorConfig>,
+    pub timeout: Option<TimeoutConfig>,
+    pub rewrite: Option<RewriteConfig>,
+    /// Whether `Connection: Upgrade` requests, such as WebSockets, are passed
+    /// on as upgrades. When not, the upgrade headers are dropped.
+    pub allow_upgrades: bool,
+    /// The upstream speaks gRPC: it is reached over HTTP/2 and its responses
+    /// end with trailers, which pass through untouched.
+    pub grpc: bool,
+    /// Checks of the upstream certificate and the client certificate, when the
+    /// `proxy` has a `tls`.
+    pub upstream_tls: Option<UpstreamTlsConfig>,
+    /// Multiplexing of the HTTP/2 connections to the upstream, when the `proxy`
+    /// has an `h2`.
+    pub h2: Option<H2Config>,
+    /// Every request of the route records the decisions taken for it, see
+    /// `debug-trace` in the manual.
+    pub debug_trace: bool,
+    /// The methods and query parameters the route is limited to.
+    pub conditions: RouteConditions,
+}
+
+/// A route that carries server-sent events: responses are streamed through
+/// without compression, and the upstream may stay silent for `idle_timeout`
+/// between events.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SseConfig {
+    pub idle_timeout: Duration,
+}
+
+impl Default for SseConfig {
+    fn default() -> Self {
+        Self {
+            idle_timeout: Duration::from_secs(300),
+        }
+    }
+}
+
+/// A route whose upstream responses are kept in memory for `ttl` and served to
+/// later requests with the same method, path and `Vary`-listed headers.
+/// Responses larger than `max_body` bytes are passed through uncached.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheConfig {
+    pub ttl: Duration,
+    pub max_body: usize,
+    /// Clients whose `Cache-Control: no-cache` requests skip the cache.
+    pub bypass_from: Vec<IpCidr>,
+    /// Whether responses carry `X-Cache`, `X-Cache-Age` and `X-Cache-TTL`.
+    pub debug_headers: bool,
+}
+
+impl CacheConfig {
+    pub const DEFAULT_MAX_BODY: usize = 1024 * 1024;
+}
+
+/// A shadow upstream that gets a copy of a `sample` of the route's requests.
+/// Its responses are thrown away, and the real request never waits for it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MirrorConfig {
+    /// Scheme and address of the shadow upstream, such as `http://staging:8080`.
+    pub url: http::Uri,
+    /// The share of requests that are copied, from 0 to 1.
+    pub sample: f64,
+}
+
+/// Time limits of a route. A request that runs past `request` is answered with
+/// `504 Gateway Timeout`, and a client that stays silent for `read_header` with
+/// `408 Request Timeout`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimeoutConfig {
+    /// How long the whole request may take, from its arrival to the end of the
+    /// upstream response.
+    pub request: Option<Duration>,
+    /// How long each read from the client may take once the route is known: the
+    /// chunks of the request body, and the header of the next request on the
+    /// same connection.
+    pub read_header: Option<Duration>,
+}
+
+/// How the path of a request is changed before it is sent upstream, from the
+/// `rewrite` of a section. The query string is passed on as it is.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RewriteConfig {
+    /// Drops `prefix`, the path of the section, from the front of the path.
+    StripPrefix { prefix: String },
+    /// Replaces the first match of `pattern` with `to`, in which `$1` or `${name}`
+    /// stand for the groups of the match.
+    Pattern { pattern: RewritePattern, to: String },
+}
+
+/// The `pattern` of a `rewrite`. Unlike a [`RoutePattern`] it is not anchored, so
+/// it can match part of the path.
+#[derive(Clone)]
+pub struct RewritePattern(pub Regex);
+
+impl Debug for RewritePattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self.0.as_str(), f)
+    }
+}
+
+impl PartialEq for RewritePattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
 }
 
 #[derive(Clone, Debug)]
 pub enum RoutingMode {
     Exact,
     Prefix,
+    Regex,
 }
 
 impl FromStr for RoutingMode {
@@ -90,8 +618,9 @@ impl FromStr for RoutingMode {
         match s {
             "exact" => Ok(RoutingMode::Exact),
             "prefix" => Ok(RoutingMode::Prefix),
+            "regex" => Ok(RoutingMode::Regex),
             other => Err(miette!(
-                "Available modes: 'exact' (full path match) or 'prefix' (base path match). got {}",
+                "Available modes: 'exact' (full path match), 'prefix' (base path match) or 'regex' (path pattern match). got {}",
                 other
             )),
         }
@@ -100,9 +629,50 @@ impl FromStr for RoutingMode {
 
 impl KdlValueInfo for RoutingMode {
     fn value_kind() -> ValueKind {
-        ValueKind::Enum(vec![
-            "exact".into(),
-            "prefix".into(),
-        ])
+        ValueKind::Enum(vec!["exact".into(), "prefix".into(), "regex".into()])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_route_conditions() {
+        let conditions = RouteConditions {
+            methods: "get, post".parse::<RouteMethods>().unwrap().0,
+            query: "version=2&beta".parse::<RouteQuery>().unwrap().0,
+        };
+        let shown = conditions.to_string();
+        assert_eq!(shown, "method=GET,POST query=version=2&beta");
+
+        assert!(conditions.matches(&Method::POST, Some("beta&version=2")));
+        assert!(conditions.matches(&Method::GET, Some("version=2&beta=1&x=y")));
+        assert!(!conditions.matches(&Method::PUT, Some("version=2&beta")));
+        assert!(!conditions.matches(&Method::GET, Some("version=3&beta")));
+        assert!(!conditions.matches(&Method::GET, Some("version=2")));
+        assert!(!conditions.matches(&Method::GET, None));
+
+        assert!(RouteConditions::default().matches(&Method::DELETE, None));
+    }
+
+    #[test]
+    fn test_cert_pins() {
+        let digest = "ab".repeat(32);
+        let colons = vec!["CD"; 32].join(":");
+        let pins: CertPins = format!("{digest}, {colons}").parse().unwrap();
+        assert_eq!(pins.0, vec![[0xab; 32], [0xcd; 32]]);
+
+        for bad in ["", "abcd", &"zz".repeat(32), &"é".repeat(32)] {
+            assert!(bad.parse::<CertPins>().is_err(), "{bad:?}");
+        }
+    }
+
+    #[test]
+    fn test_bad_route_conditions() {
+        assert!("GET,".parse::<RouteMethods>().is_err());
+        assert!("G(ET".parse::<RouteMethods>().is_err());
+        assert!("=2".parse::<RouteQuery>().is_err());
+        assert!("a=1&&b".parse::<RouteQuery>().is_err());
     }
 }
@@ -0,0 +1,314 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use http::{HeaderName, Method};
+use regex::Regex;
+
+use crate::common_types::{
+    builtin_filters_name::FilterArgError, duration, header_ops::string_arg, value::Value,
+};
+
+/// Methods allowed unless a filter sets `methods`.
+pub const DEFAULT_CORS_METHODS: &[Method] = &[Method::GET, Method::HEAD, Method::POST];
+
+/// A parsed `cors` filter.
+#[derive(Debug, Clone)]
+pub struct Cors {
+    /// Any origin, from `origins="*"`.
+    pub any_origin: bool,
+    /// Exact origins, such as `https://shop.example.com`.
+    pub origins: Vec<String>,
+    /// Origins matching it are allowed too.
+    pub origin_pattern: Option<Regex>,
+    pub methods: Vec<Method>,
+    /// Request headers allowed, `None` for any, from `headers="*"`.
+    pub headers: Option<Vec<HeaderName>>,
+    /// Response headers scripts may read.
+    pub expose_headers: Vec<HeaderName>,
+    pub credentials: bool,
+    /// How long browsers may keep the answer to a preflight.
+    pub max_age: Option<Duration>,
+}
+
+impl Cors {
+    /// Parses the arguments of a `cors` filter:
+    ///
+    /// * `origins="..."`, comma separated origins or `*`, and/or
+    ///   `origin-pattern="..."`, a regex the origin matches
+    /// * `methods="..."` and `headers="..."`, what requests may use
+    /// * `expose-headers="..."`, what scripts may read of responses
+    /// * `credentials=#true`, to allow cookies and authorization
+    /// * `max-age="10m"`, how long a preflight answer is valid
+    pub fn parse(args: &BTreeMap<String, Value>) -> Result<Self, FilterArgError> {
+        let mut any_origin = false;
+        let mut origins = vec![];
+        if args.contains_key("origins") {
+            for origin in list(string_arg(args, "origins")?) {
+                if origin == "*" {
+                    any_origin = true;
+                } else if origin.contains("://") && !origin.ends_with('/') {
+                    origins.push(origin.to_ascii_lowercase());
+                } else {
+                    return Err(FilterArgError::at(
+                        "origins",
+                        format!("'{origin}' is not an origin such as \"https://shop.example.com\""),
+                    ));
+                }
+            }
+        }
+
+        let origin_pattern = match args.get("origin-pattern") {
+            None => None,
+            Some(_) => {
+                let raw = string_arg(args, "origin-pattern")?;
+                Some(Regex::new(raw).map_err(|e| {
+                    FilterArgError::at("origin-pattern", format!("Invalid regex: {e}"))
+                })?)
+            }
+        };
+
+        if !any_origin && origins.is_empty() && origin_pattern.is_none() {
+            return Err(FilterArgError {
+                arg: None,
+                message: "Missing argument 'origins' or 'origin-pattern'".to_string(),
+            });
+        }
+
+        let methods = match args.get("methods") {
+            None => DEFAULT_CORS_METHODS.to_vec(),
+            Some(_) => list(string_arg(args, "methods")?)
+                .map(|method| {
+                    Method::from_bytes(method.to_ascii_uppercase().as_bytes()).map_err(|_| {
+                        FilterArgError::at(
+                            "methods",
+                            format!("'{method}' in 'methods' is not a method"),
+                        )
+                    })
+                })
+                .collect::<Result<_, _>>()?,
+        };
+
+        let headers = match args.get("headers") {
+            None => Some(vec![]),
+            Some(_) if string_arg(args, "headers")?.trim() == "*" => None,
+            Some(_) => Some(header_list(args, "headers")?),
+        };
+
+        let expose_headers = match args.get("expose-headers") {
+            None => vec![],
+            Some(_) => header_list(args, "expose-headers")?,
+        };
+
+        let credentials = match args.get("credentials") {
+            None => false,
+            Some(Value::Bool(credentials)) => *credentials,
+            Some(other) => {
+                return Err(FilterArgError::at(
+                    "credentials",
+                    format!("'credentials' must be #true or #false, got {other}"),
+                ))
+            }
+        };
+        if credentials && any_origin {
+            return Err(FilterArgError::at(
+                "credentials",
+                "'credentials' can't be used with any origin, list them instead",
+            ));
+        }
+
+        let max_age = match args.get("max-age") {
+            None => None,
+            Some(_) => {
+                let raw = string_arg(args, "max-age")?;
+                Some(
+                    raw.parse::<duration::Duration>()
+                        .map_err(|e| FilterArgError::at("max-age", e.to_string()))?
+                        .get(),
+                )
+            }
+        };
+
+        Ok(Self {
+            any_origin,
+            origins,
+            origin_pattern,
+            methods,
+            headers,
+            expose_headers,
+            credentials,
+            max_age,
+        })
+    }
+
+    pub fn allows_origin(&self, origin: &str) -> bool {
+        self.any_origin
+            || self
+                .origins
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(origin))
+            || self
+                .origin_pattern
+                .as_ref()
+                .is_some_and(|pattern| pattern.is_match(origin))
+    }
+
+    /// What `Access-Control-Allow-Origin` says to `origin`, an allowed one.
+    pub fn allow_origin_value<'a>(&self, origin: &'a str) -> &'a str {
+        if self.any_origin {
+            "*"
+        } else {
+            origin
+        }
+    }
+
+    pub fn allows_method(&self, method: &str) -> bool {
+        self.methods.iter().any(|m| m.as_str() == method)
+    }
+
+    /// Whether every header of an `Access-Control-Request-Headers` is allowed.
+    pub fn allows_headers(&self, requested: &str) -> bool {
+        let Some(allowed) = &self.headers else {
+            return true;
+        };
+        list(requested).all(|name| {
+            allowed
+                .iter()
+                .any(|h| h.as_str().eq_ignore_ascii_case(name))
+        })
+    }
+}
+
+fn list(raw: &str) -> impl Iterator<Item = &str> {
+    raw.split(',').map(str::trim).filter(|s| !s.is_empty())
+}
+
+fn header_list(
+    args: &BTreeMap<String, Value>,
+    key: &str,
+) -> Result<Vec<HeaderName>, FilterArgError> {
+    list(string_arg(args, key)?)
+        .map(|name| {
+            HeaderName::from_bytes(name.as_bytes()).map_err(|_| {
+                FilterArgError::at(
+                    key,
+                    format!("'{name}' in '{key}' is not a valid header name"),
+                )
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(pairs: &[(&str, Value)]) -> Result<Cors, FilterArgError> {
+        let args = pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.clone()))
+            .collect();
+        Cors::parse(&args)
+    }
+
+    fn string(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+
+    #[test]
+    fn test_origins() {
+        let cors = parse(&[
+            (
+                "origins",
+                string("https://shop.example.com, http://localhost:3000"),
+            ),
+            (
+                "origin-pattern",
+                string(r"^https://[a-z]+\.preview\.example\.com$"),
+            ),
+        ])
+        .unwrap();
+        assert!(cors.allows_origin("https://shop.example.com"));
+        assert!(cors.allows_origin("HTTPS://Shop.Example.com"));
+        assert!(cors.allows_origin("http://localhost:3000"));
+        assert!(cors.allows_origin("https://pr.preview.example.com"));
+        assert!(!cors.allows_origin("https://evil.com"));
+        assert!(!cors.allows_origin("https://shop.example.com.evil.com"));
+        assert_eq!(
+            cors.allow_origin_value("https://shop.example.com"),
+            "https://shop.example.com"
+        );
+
+        let cors = parse(&[("origins", string("*"))]).unwrap();
+        assert!(cors.allows_origin("https://anything"));
+        assert_eq!(cors.allow_origin_value("https://anything"), "*");
+    }
+
+    #[test]
+    fn test_methods_and_headers() {
+        let cors = parse(&[("origins", string("*"))]).unwrap();
+        assert!(cors.allows_method("POST"));
+        assert!(!cors.allows_method("DELETE"));
+        assert!(cors.allows_headers(""));
+        assert!(!cors.allows_headers("x-token"));
+
+        let cors = parse(&[
+            ("origins", string("*")),
+            ("methods", string("get, delete")),
+            ("headers", string("Content-Type, X-Token")),
+            ("max-age", string("10m")),
+        ])
+        .unwrap();
+        assert!(cors.allows_method("DELETE"));
+        assert!(!cors.allows_method("POST"));
+        assert!(cors.allows_headers("x-token, content-type"));
+        assert!(!cors.allows_headers("x-token, authorization"));
+        assert_eq!(cors.max_age, Some(Duration::from_secs(600)));
+
+        let cors = parse(&[("origins", string("*")), ("headers", string("*"))]).unwrap();
+        assert!(cors.allows_headers("anything, at-all"));
+    }
+
+    #[test]
+    fn test_invalid() {
+        for (pairs, arg, error) in [
+            (
+                vec![],
+                None,
+                "Missing argument 'origins' or 'origin-pattern'",
+            ),
+            (
+                vec![("origins", string("shop.example.com"))],
+                Some("origins"),
+                "is not an origin",
+            ),
+            (
+                vec![("origins", string("https://shop.example.com/"))],
+                Some("origins"),
+                "is not an origin",
+            ),
+            (
+                vec![("origin-pattern", string("("))],
+                Some("origin-pattern"),
+                "Invalid regex",
+            ),
+            (
+                vec![("origins", string("*")), ("credentials", Value::Bool(true))],
+                Some("credentials"),
+                "can't be used with any origin",
+            ),
+            (
+                vec![("origins", string("*")), ("credentials", string("yes"))],
+                Some("credentials"),
+                "must be #true or #false",
+            ),
+            (
+                vec![("origins", string("*")), ("headers", string("x token"))],
+                Some("headers"),
+                "is not a valid header name",
+            ),
+        ] {
+            let err = parse(&pairs).unwrap_err();
+            assert_eq!(err.arg.as_deref(), arg, "{pairs:?}");
+            assert!(err.message.contains(error), "{pairs:?}: {}", err.message);
+        }
+    }
+}
@@ -1,8 +1,12 @@
 use std::{collections::BTreeMap, path::PathBuf};
 
 use fqdn::FQDN;
+use miette::{NamedSource, SourceSpan};
 
 use crate::common_types::{
+    basic_auth::BasicAuthConfig,
+    condition::Condition,
+    error::{ConfigError, ParseError},
     rate_limiter::RateLimitPolicy,
     value::Value,
 };
@@ -11,6 +15,26 @@ use crate::common_types::{
 pub enum ChainItem {
     Filter(ConfiguredFilter),
     RateLimiter(RateLimitPolicy),
+    BasicAuth(BasicAuthConfig),
+    /// An item with a `when`, which only runs for the requests that match.
+    /// Never nested.
+    When {
+        condition: Condition,
+        item: Box<ChainItem>,
+    },
+}
+
+impl ChainItem {
+    /// Guards the item with `condition`, if there is one.
+    pub fn when(self, condition: Option<Condition>) -> Self {
+        match condition {
+            Some(condition) => Self::When {
+                condition,
+                item: Box::new(self),
+            },
+            None => self,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -28,6 +52,10 @@ pub struct ConfiguredFilter {
 pub struct PluginDefinition {
     pub name: FQDN,
     pub source: PluginSource,
+    /// Required for `url` sources.
+    pub checksum: Option<PluginChecksum>,
+    /// Largest request body buffered for the plugin; `None` if it never sees bodies.
+    pub request_body_limit: Option<usize>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -36,6 +64,35 @@ pub enum PluginSource {
     Url(String),
 }
 
+/// The `sha256` a plugin module must have. The module is only checked once it is
+/// fetched, long after parsing, so it keeps where it was written to point at.
+#[derive(Debug, Clone)]
+pub struct PluginChecksum {
+    /// Lower-case hex.
+    pub sha256: String,
+    pub src: NamedSource<String>,
+    pub span: SourceSpan,
+}
+
+impl PartialEq for PluginChecksum {
+    fn eq(&self, other: &Self) -> bool {
+        self.sha256 == other.sha256
+    }
+}
+
+impl PluginChecksum {
+    /// A configuration error pointing at the checksum, for a module whose digest
+    /// turned out to be `actual`.
+    pub fn mismatch(&self, plugin: &FQDN, actual: &str) -> ConfigError {
+        ConfigError::from_list(vec![ParseError::new(
+            format!("Checksum mismatch for plugin '{plugin}': the module has sha256 {actual}"),
+            Some(self.span),
+            Some("Update 'sha256' if the module was changed on purpose".to_string()),
+            self.src.clone(),
+        )])
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct NamedFilterChain {
     pub name: String,
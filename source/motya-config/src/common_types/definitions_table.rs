@@ -1,14 +1,38 @@
 use std::collections::{HashMap, HashSet};
 
 use fqdn::FQDN;
+use miette::SourceSpan;
 
 use crate::common_types::{
     balancer::BalancerConfig,
+    basic_auth::CredentialsConfig,
     builtin_filters_name::load_definitions_table,
+    connectors::UpstreamServer,
     definitions::{FilterChain, PluginDefinition},
     rate_limiter::{RateLimitPolicy, StorageConfig},
+    secrets::SecretConfig,
 };
 
+/// The kinds of named definitions whose location [`DefinitionsTable`] keeps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DefinitionKind {
+    /// A `chain-filters` of `modifiers`, referenced by `use-chain`.
+    Chain,
+    /// A `template` of `key-profiles`, referenced by `use-key-profile`.
+    KeyProfile,
+    /// A `redis` or `memory` of `storages`, referenced by `storage`.
+    Storage,
+    /// An entry of `secrets`, referenced by `secret=`.
+    Secret,
+}
+
+/// Where a definition is declared: the name of its file and the span of its node.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DefinitionSpan {
+    pub source_name: String,
+    pub span: SourceSpan,
+}
+
 /// Definitions Table (Intermediate Representation).
 ///
 /// This structure accumulates all "blueprints" and configurations from all loaded KDL files.
@@ -66,6 +90,19 @@ pub struct DefinitionsTable {
     rate_storages: HashMap<String, StorageConfig>,
 
     rate_policies: HashMap<String, RateLimitPolicy>,
+
+    /// Named backend lists from `upstream-groups`, referenced by `proxy use-group="..."`.
+    upstream_groups: HashMap<String, Vec<UpstreamServer>>,
+
+    /// Named user lists from `credentials`, referenced by `basic-auth` chain items.
+    credentials: HashMap<String, CredentialsConfig>,
+
+    /// Named secrets from `secrets`, referenced by `secret="..."` in place of an
+    /// inline password or hash.
+    secrets: HashMap<String, SecretConfig>,
+
+    /// Where the named definitions were declared, for tooling such as the LSP.
+    spans: HashMap<(DefinitionKind, String), DefinitionSpan>,
 }
 
 impl DefinitionsTable {
@@ -88,6 +125,10 @@ impl DefinitionsTable {
             key_templates: key_profiles,
             rate_storages,
             rate_policies,
+            upstream_groups: HashMap::default(),
+            credentials: HashMap::default(),
+            secrets: HashMap::default(),
+            spans: HashMap::default(),
         }
     }
 
@@ -95,6 +136,10 @@ impl DefinitionsTable {
         self.rate_policies.get(name).cloned()
     }
 
+    pub fn get_rate_limits(&self) -> &HashMap<String, RateLimitPolicy> {
+        &self.rate_policies
+    }
+
     pub fn has_rate_storage(&self, name: &str) -> bool {
         self.rate_storages.contains_key(name)
     }
@@ -135,6 +180,50 @@ impl DefinitionsTable {
         self.key_templates.insert(name, profile)
     }
 
+    pub fn insert_upstream_group(
+        &mut self,
+        name: String,
+        servers: Vec<UpstreamServer>,
+    ) -> Option<Vec<UpstreamServer>> {
+        self.upstream_groups.insert(name, servers)
+    }
+
+    pub fn get_upstream_group(&self, name: &str) -> Option<&[UpstreamServer]> {
+        self.upstream_groups.get(name).map(Vec::as_slice)
+    }
+
+    pub fn get_upstream_groups(&self) -> &HashMap<String, Vec<UpstreamServer>> {
+        &self.upstream_groups
+    }
+
+    pub fn insert_credentials(
+        &mut self,
+        name: String,
+        credentials: CredentialsConfig,
+    ) -> Option<CredentialsConfig> {
+        self.credentials.insert(name, credentials)
+    }
+
+    pub fn get_credentials(&self, name: &str) -> Option<&CredentialsConfig> {
+        self.credentials.get(name)
+    }
+
+    pub fn get_all_credentials(&self) -> &HashMap<String, CredentialsConfig> {
+        &self.credentials
+    }
+
+    pub fn insert_secret(&mut self, name: String, secret: SecretConfig) -> Option<SecretConfig> {
+        self.secrets.insert(name, secret)
+    }
+
+    pub fn get_secret(&self, name: &str) -> Option<&SecretConfig> {
+        self.secrets.get(name)
+    }
+
+    pub fn get_secrets(&self) -> &HashMap<String, SecretConfig> {
+        &self.secrets
+    }
+
     pub fn insert_filter(&mut self, filter_name: FQDN) -> bool {
         self.available_filters.insert(filter_name)
     }
@@ -159,6 +248,21 @@ impl DefinitionsTable {
         self.chains.extend(chains);
     }
 
+    /// Records where the definition `name` is declared. The first declaration
+    /// wins, as duplicates are reported and dropped.
+    pub fn insert_span(
+        &mut self,
+        kind: DefinitionKind,
+        name: impl Into<String>,
+        span: DefinitionSpan,
+    ) {
+        self.spans.entry((kind, name.into())).or_insert(span);
+    }
+
+    pub fn get_span(&self, kind: DefinitionKind, name: &str) -> Option<&DefinitionSpan> {
+        self.spans.get(&(kind, name.to_string()))
+    }
+
     pub fn get_plugins(&self) -> &HashMap<FQDN, PluginDefinition> {
         &self.plugins
     }
@@ -197,6 +301,30 @@ impl DefinitionsTable {
             self.plugins.insert(name, plugin);
         }
 
+        for (name, credentials) in other.credentials {
+            if self.credentials.contains_key(&name) {
+                return Err(miette::miette!(
+                    "Duplicate credentials definition across files: '{}'",
+                    name
+                ));
+            }
+            self.credentials.insert(name, credentials);
+        }
+
+        for (name, secret) in other.secrets {
+            if self.secrets.contains_key(&name) {
+                return Err(miette::miette!(
+                    "Duplicate secret definition across files: '{}'",
+                    name
+                ));
+            }
+            self.secrets.insert(name, secret);
+        }
+
+        for (key, span) in other.spans {
+            self.spans.entry(key).or_insert(span);
+        }
+
         Ok(())
     }
 }
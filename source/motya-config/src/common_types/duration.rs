@@ -0,0 +1,109 @@
+use std::{fmt, ops::Deref, str::FromStr};
+
+use miette::miette;
+
+use crate::kdl::schema::{definitions::ValueKind, value_info::KdlValueInfo};
+
+/// A length of time, written as a number with a unit: `250ms`, `5s`, `30m`, `1h`, `7d`,
+/// or several of them, as in `1h30m`.
+///
+/// The longer spellings of humantime, such as `5min` or `2 hours`, are accepted too.
+/// A number without a unit is an error rather than a guess between seconds and
+/// milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct Duration(pub std::time::Duration);
+
+impl Duration {
+    pub fn get(self) -> std::time::Duration {
+        self.0
+    }
+}
+
+impl Deref for Duration {
+    type Target = std::time::Duration;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl From<Duration> for std::time::Duration {
+    fn from(duration: Duration) -> Self {
+        duration.0
+    }
+}
+
+impl From<std::time::Duration> for Duration {
+    fn from(duration: std::time::Duration) -> Self {
+        Self(duration)
+    }
+}
+
+impl FromStr for Duration {
+    type Err = miette::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let raw = s.trim();
+
+        if !raw.is_empty() && raw.chars().all(|c| c.is_ascii_digit()) {
+            return Err(miette!(
+                "'{raw}' has no unit. Write '{raw}s' for seconds or '{raw}ms' for milliseconds"
+            ));
+        }
+
+        humantime::parse_duration(raw)
+            .map(Self)
+            .map_err(|e| miette!("Expected a duration like '250ms', '5s' or '1m', got '{s}': {e}"))
+    }
+}
+
+impl fmt::Display for Duration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", humantime::format_duration(self.0))
+    }
+}
+
+impl KdlValueInfo for Duration {
+    fn value_kind() -> ValueKind {
+        ValueKind::TypedString("duration".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> std::time::Duration {
+        s.parse::<Duration>().unwrap().get()
+    }
+
+    #[test]
+    fn test_units() {
+        assert_eq!(parse("250ms"), std::time::Duration::from_millis(250));
+        assert_eq!(parse("5s"), std::time::Duration::from_secs(5));
+        assert_eq!(parse("30m"), std::time::Duration::from_secs(30 * 60));
+        assert_eq!(parse("5min"), std::time::Duration::from_secs(5 * 60));
+        assert_eq!(parse("1h30m"), std::time::Duration::from_secs(90 * 60));
+        assert_eq!(parse(" 7d "), std::time::Duration::from_secs(7 * 24 * 3600));
+    }
+
+    #[test]
+    fn test_invalid_durations() {
+        for (raw, error) in [
+            ("30", "'30' has no unit. Write '30s' for seconds or '30ms'"),
+            ("", "Expected a duration"),
+            ("5x", "unknown time unit"),
+            ("-1s", "Expected a duration"),
+            ("fast", "Expected a duration"),
+        ] {
+            let err = raw.parse::<Duration>().unwrap_err().to_string();
+            assert!(err.contains(error), "{raw:?}: {err}");
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Duration(parse("1h30m")).to_string(), "1h 30m");
+        assert_eq!(Duration(parse("90s")).to_string(), "1m 30s");
+    }
+}
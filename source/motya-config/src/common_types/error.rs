@@ -7,7 +7,7 @@ use thiserror::Error;
 use crate::kdl::parser::ctx::ParseContext;
 
 #[derive(Debug, Error, Diagnostic, Clone)]
-#[error("{message}")]
+#[error("{message}{}", in_path(.path))]
 pub struct ParseError {
     pub message: String,
 
@@ -19,6 +19,16 @@ pub struct ParseError {
 
     #[source_code]
     pub src: NamedSource<String>,
+
+    /// The nodes down to the one the error is about, e.g.
+    /// `services > Example > connectors > proxy`, see [`ParseContext::path`].
+    pub path: Option<String>,
+}
+
+fn in_path(path: &Option<String>) -> String {
+    path.as_ref()
+        .map(|path| format!(" (in {path})"))
+        .unwrap_or_default()
 }
 
 #[derive(Error, Diagnostic, Default, Clone)]
@@ -28,6 +38,10 @@ pub struct ConfigError {
 
     #[related]
     pub errors: Vec<ParseError>,
+
+    /// Problems that don't stop the configuration from loading, such as deprecated
+    /// spellings. They are reported apart from the errors, and don't count.
+    pub warnings: Vec<ParseError>,
 }
 
 impl fmt::Debug for ConfigError {
@@ -50,8 +64,17 @@ impl ConfigError {
         self.push(ParseError::from_report(report, ctx));
     }
 
+    pub fn warn(&mut self, warning: ParseError) {
+        self.warnings.push(warning);
+    }
+
+    pub fn warn_report(&mut self, report: miette::Report, ctx: &ParseContext) {
+        self.warn(ParseError::from_report(report, ctx));
+    }
+
     pub fn merge(&mut self, other: ConfigError) {
         self.errors.extend(other.errors);
+        self.warnings.extend(other.warnings);
         self.count = self.errors.len();
     }
 
@@ -63,8 +86,26 @@ impl ConfigError {
         Self {
             count: errors.len(),
             errors,
+            warnings: Vec::new(),
         }
     }
+
+    /// Splits the errors by the name of the file they were found in. Files come in
+    /// the order their first error was reported, and keep the order of their errors.
+    /// The warnings are left out.
+    pub fn into_sources(self) -> Vec<(String, Vec<ParseError>)> {
+        let mut sources: Vec<(String, Vec<ParseError>)> = Vec::new();
+
+        for err in self.errors {
+            let name = err.src.name();
+            match sources.iter_mut().find(|(source, _)| source == name) {
+                Some((_, errors)) => errors.push(err),
+                None => sources.push((name.to_string(), vec![err])),
+            }
+        }
+
+        sources
+    }
 }
 
 impl ParseError {
@@ -79,9 +120,30 @@ impl ParseError {
             label,
             help,
             src,
+            path: None,
         }
     }
 
+    /// Places the error at the current node of `ctx`, see [`ParseContext::path`].
+    pub fn with_path(mut self, ctx: &ParseContext) -> Self {
+        self.path = ctx.path();
+        self
+    }
+
+    /// Where the error is, as `file:line:column`, or the file alone when the
+    /// error has no span.
+    pub fn location(&self) -> String {
+        let Some(label) = self.label else {
+            return self.src.name().to_string();
+        };
+
+        let text = self.src.inner();
+        let before = text.get(..label.offset()).unwrap_or(text);
+        let line = before.matches('\n').count() + 1;
+        let column = before.chars().rev().take_while(|&c| c != '\n').count() + 1;
+        format!("{}:{line}:{column}", self.src.name())
+    }
+
     pub fn from_report(e: miette::Report, ctx: &ParseContext) -> Self {
         let help = e.help().map(|h| h.to_string());
 
@@ -96,6 +158,7 @@ impl ParseError {
             label: Some(label),
             help,
             src: ctx.source().clone(),
+            path: ctx.path(),
         }
     }
 
@@ -107,6 +170,7 @@ impl ParseError {
                 label: Some(d.span),
                 help: d.help,
                 src: src.clone(),
+                path: None,
             })
             .collect()
     }
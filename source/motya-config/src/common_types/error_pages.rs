@@ -0,0 +1,142 @@
+use std::{path::PathBuf, str::FromStr};
+
+use miette::miette;
+
+use crate::kdl::schema::{definitions::ValueKind, value_info::KdlValueInfo};
+
+/// A value filled into an error page, named `${<name>}` in templates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorPageField {
+    Status,
+    RequestId,
+}
+
+impl ErrorPageField {
+    pub const ALL: &[ErrorPageField] = &[Self::Status, Self::RequestId];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Status => "status",
+            Self::RequestId => "request_id",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorPagePart {
+    Literal(String),
+    Field(ErrorPageField),
+}
+
+/// The body of an error page: HTML, JSON or any text, with `${<field>}`
+/// placeholders. A `$` that doesn't start one is written as is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorPageTemplate {
+    pub parts: Vec<ErrorPagePart>,
+}
+
+impl FromStr for ErrorPageTemplate {
+    type Err = miette::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut rest = s;
+
+        while let Some(pos) = rest.find("${") {
+            literal.push_str(&rest[..pos]);
+            rest = &rest[pos + 2..];
+
+            let end = rest
+                .find('}')
+                .ok_or_else(|| miette!("Unclosed '${{' in error page template"))?;
+            let name = &rest[..end];
+
+            let field = ErrorPageField::ALL
+                .iter()
+                .find(|field| field.name() == name)
+                .ok_or_else(|| {
+                    let known: Vec<_> = ErrorPageField::ALL.iter().map(|f| f.name()).collect();
+                    miette!(
+                        "Unknown error page placeholder '${{{name}}}'. Available: ${{{}}}",
+                        known.join("}, ${")
+                    )
+                })?;
+
+            if !literal.is_empty() {
+                parts.push(ErrorPagePart::Literal(std::mem::take(&mut literal)));
+            }
+            parts.push(ErrorPagePart::Field(*field));
+            rest = &rest[end + 1..];
+        }
+
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            parts.push(ErrorPagePart::Literal(literal));
+        }
+
+        Ok(Self { parts })
+    }
+}
+
+impl KdlValueInfo for ErrorPageTemplate {
+    fn value_kind() -> ValueKind {
+        ValueKind::TypedString("error-page-template".into())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorPageSource {
+    Inline(ErrorPageTemplate),
+    /// A template file, read when the service starts.
+    File(PathBuf),
+}
+
+/// The page sent in place of the empty body of an error response with `status`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorPage {
+    pub status: u16,
+    pub source: ErrorPageSource,
+    pub content_type: String,
+}
+
+/// The pages a proxy service answers its own errors with, from `error-pages`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorPagesConfig {
+    pub pages: Vec<ErrorPage>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_placeholders() {
+        let template: ErrorPageTemplate =
+            r#"{"status": ${status}, "id": "${request_id}", "price": "$5"}"#
+                .parse()
+                .unwrap();
+
+        assert_eq!(
+            template.parts,
+            vec![
+                ErrorPagePart::Literal(r#"{"status": "#.into()),
+                ErrorPagePart::Field(ErrorPageField::Status),
+                ErrorPagePart::Literal(r#", "id": ""#.into()),
+                ErrorPagePart::Field(ErrorPageField::RequestId),
+                ErrorPagePart::Literal(r#"", "price": "$5"}"#.into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unknown_placeholder() {
+        let err = "<p>${path}</p>".parse::<ErrorPageTemplate>().unwrap_err();
+
+        let message = err.to_string();
+        assert!(message.contains("'${path}'"), "{message}");
+        assert!(message.contains("${request_id}"), "{message}");
+
+        assert!("<p>${status</p>".parse::<ErrorPageTemplate>().is_err());
+    }
+}
@@ -0,0 +1,225 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use http::{HeaderName, Uri};
+
+use crate::common_types::{
+    builtin_filters_name::FilterArgError, duration, header_ops::string_arg, value::Value,
+};
+
+/// How long the authorization service has to answer unless a filter sets `timeout`.
+pub const DEFAULT_EXT_AUTH_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// What happens to a request the authorization service gives no answer for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FailureMode {
+    /// It goes through unchecked.
+    Open,
+    /// It is refused with a `503`.
+    Closed,
+}
+
+/// A parsed `ext-auth` filter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtAuth {
+    /// The service to ask over HTTP, such as `http://authz:9000/check`. The path
+    /// and query of the request are appended to it.
+    pub url: Uri,
+    pub timeout: Duration,
+    pub failure_mode: FailureMode,
+    /// The headers of the request sent along, or all of them if `None`.
+    pub request_headers: Option<Vec<HeaderName>>,
+    /// Headers of a reply allowing the request that are added to it.
+    pub upstream_headers: Vec<HeaderName>,
+    /// Headers of a reply denying the request that are passed to the client.
+    pub client_headers: Vec<HeaderName>,
+}
+
+impl ExtAuth {
+    /// Parses the arguments of an `ext-auth` filter:
+    ///
+    /// * `url="http://..."` or `"https://..."`, the authorization service
+    /// * `timeout="500ms"`, how long it has to answer
+    /// * `failure-mode="open"` or `"closed"`, for requests it gives no answer for
+    /// * `request-headers="..."`, `upstream-headers="..."` and
+    ///   `client-headers="..."`, comma separated lists of header names
+    pub fn parse(args: &BTreeMap<String, Value>) -> Result<Self, FilterArgError> {
+        let raw = string_arg(args, "url")?;
+        let url = raw
+            .parse::<Uri>()
+            .ok()
+            .filter(|url| url.scheme().is_some() && url.authority().is_some())
+            .ok_or_else(|| {
+                FilterArgError::at(
+                    "url",
+                    format!("'{raw}' is not a URL such as \"http://authz:9000/check\""),
+                )
+            })?;
+        match url.scheme_str() {
+            Some("http" | "https") => {}
+            Some("grpc" | "grpcs") => return Err(FilterArgError::at(
+                "url",
+                "'url' names a gRPC service, but only HTTP authorization services are supported",
+            )),
+            _ => {
+                return Err(FilterArgError::at(
+                    "url",
+                    format!("'{raw}' is not an http:// or https:// URL"),
+                ))
+            }
+        }
+        if url.query().is_some() {
+            return Err(FilterArgError::at(
+                "url",
+                "'url' can't have a query, the one of the request is appended",
+            ));
+        }
+
+        let timeout = match args.get("timeout") {
+            None => DEFAULT_EXT_AUTH_TIMEOUT,
+            Some(_) => string_arg(args, "timeout")?
+                .parse::<duration::Duration>()
+                .map_err(|e| FilterArgError::at("timeout", e.to_string()))?
+                .get(),
+        };
+        if timeout.is_zero() {
+            return Err(FilterArgError::at("timeout", "'timeout' must not be 0"));
+        }
+
+        let failure_mode = match args.get("failure-mode") {
+            None => FailureMode::Closed,
+            Some(_) => match string_arg(args, "failure-mode")? {
+                "open" => FailureMode::Open,
+                "closed" => FailureMode::Closed,
+                other => {
+                    return Err(FilterArgError::at(
+                        "failure-mode",
+                        format!("'failure-mode' is \"open\" or \"closed\", not '{other}'"),
+                    ))
+                }
+            },
+        };
+
+        let request_headers = match args.get("request-headers") {
+            None => None,
+            Some(_) => Some(header_list(args, "request-headers")?),
+        };
+
+        Ok(Self {
+            url,
+            timeout,
+            failure_mode,
+            request_headers,
+            upstream_headers: header_list(args, "upstream-headers")?,
+            client_headers: header_list(args, "client-headers")?,
+        })
+    }
+}
+
+/// The comma separated header names of `key`, none if it is missing.
+fn header_list(
+    args: &BTreeMap<String, Value>,
+    key: &str,
+) -> Result<Vec<HeaderName>, FilterArgError> {
+    if !args.contains_key(key) {
+        return Ok(vec![]);
+    }
+
+    string_arg(args, key)?
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|name| {
+            HeaderName::from_bytes(name.as_bytes()).map_err(|_| {
+                FilterArgError::at(
+                    key,
+                    format!("'{name}' in '{key}' is not a valid header name"),
+                )
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(pairs: &[(&str, &str)]) -> BTreeMap<String, Value> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), Value::String(v.to_string())))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let auth = ExtAuth::parse(&args(&[("url", "http://authz:9000/check")])).unwrap();
+        assert_eq!(auth.timeout, DEFAULT_EXT_AUTH_TIMEOUT);
+        assert_eq!(auth.failure_mode, FailureMode::Closed);
+        assert_eq!(auth.request_headers, None);
+        assert!(auth.upstream_headers.is_empty());
+
+        let auth = ExtAuth::parse(&args(&[
+            ("url", "https://authz"),
+            ("timeout", "250ms"),
+            ("failure-mode", "open"),
+            ("request-headers", "Authorization, cookie"),
+            ("upstream-headers", "x-user-id,x-user-role"),
+            ("client-headers", "www-authenticate"),
+        ]))
+        .unwrap();
+        assert_eq!(auth.timeout, Duration::from_millis(250));
+        assert_eq!(auth.failure_mode, FailureMode::Open);
+        assert_eq!(
+            auth.request_headers,
+            Some(vec![
+                HeaderName::from_static("authorization"),
+                HeaderName::from_static("cookie")
+            ])
+        );
+        assert_eq!(auth.upstream_headers.len(), 2);
+        assert_eq!(auth.client_headers.len(), 1);
+    }
+
+    #[test]
+    fn test_invalid() {
+        for (pairs, arg, error) in [
+            (vec![], None, "Missing argument 'url'"),
+            (vec![("url", "authz:9000")], Some("url"), "is not a URL"),
+            (vec![("url", "/check")], Some("url"), "is not a URL"),
+            (
+                vec![("url", "grpc://authz:9001")],
+                Some("url"),
+                "only HTTP authorization services are supported",
+            ),
+            (
+                vec![("url", "ftp://authz/check")],
+                Some("url"),
+                "is not an http:// or https:// URL",
+            ),
+            (
+                vec![("url", "http://authz?a=1")],
+                Some("url"),
+                "can't have a query",
+            ),
+            (
+                vec![("url", "http://authz"), ("timeout", "soon")],
+                Some("timeout"),
+                "Expected a duration like '250ms', '5s' or '1m', got 'soon'",
+            ),
+            (
+                vec![("url", "http://authz"), ("failure-mode", "ajar")],
+                Some("failure-mode"),
+                "is \"open\" or \"closed\"",
+            ),
+            (
+                vec![("url", "http://authz"), ("upstream-headers", "x-user id")],
+                Some("upstream-headers"),
+                "'x-user id' in 'upstream-headers' is not a valid header name",
+            ),
+        ] {
+            let err = ExtAuth::parse(&args(&pairs)).unwrap_err();
+            assert_eq!(err.arg.as_deref(), arg, "{pairs:?}");
+            assert!(err.message.contains(error), "{pairs:?}: {}", err.message);
+        }
+    }
+}
@@ -1,6 +1,6 @@
-use std::path::PathBuf;
+use std::{collections::BTreeMap, path::PathBuf};
 
-use crate::common_types::listeners::Listeners;
+use crate::common_types::{compression::CompressionAlgorithm, listeners::Listeners};
 
 //
 // File Server Configuration
@@ -10,6 +10,15 @@ pub struct FileServerConfig {
     pub name: String,
     pub listeners: Listeners,
     pub base_path: Option<PathBuf>,
+    /// Files served for a request of a directory, the first one found wins.
+    pub index_files: Vec<String>,
+    /// Whether a directory without an index file is answered with a listing.
+    pub autoindex: bool,
+    /// Content types by lowercase file extension, over the built-in ones.
+    pub mime_types: BTreeMap<String, String>,
+    pub cache_control: Option<String>,
+    /// Encodings of sibling files served in place of a file, in order of preference.
+    pub precompressed: Vec<CompressionAlgorithm>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
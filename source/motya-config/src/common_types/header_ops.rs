@@ -0,0 +1,212 @@
+use std::collections::BTreeMap;
+
+use http::{HeaderName, HeaderValue};
+use regex::Regex;
+
+use crate::common_types::{builtin_filters_name::FilterArgError, value::Value};
+
+/// What a built-in header filter does; the direction comes from its namespace
+/// (`motya.request.*` or `motya.response.*`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderOpKind {
+    Set,
+    Remove,
+    RemoveRegex,
+    Rename,
+}
+
+impl HeaderOpKind {
+    /// The kind of header filter `name` is, by the last label of its name.
+    pub fn of_filter(name: &str) -> Option<Self> {
+        let (namespace, op) = name.trim_end_matches('.').rsplit_once('.')?;
+        if namespace != "motya.request" && namespace != "motya.response" {
+            return None;
+        }
+
+        match op {
+            // `upsert-header` is the name `set-header` had before.
+            "set-header" | "upsert-header" => Some(Self::Set),
+            "remove-header" => Some(Self::Remove),
+            "remove-header-regex" => Some(Self::RemoveRegex),
+            "rename-header" => Some(Self::Rename),
+            _ => None,
+        }
+    }
+}
+
+/// A parsed header filter, applied the same way to requests and responses.
+#[derive(Debug, Clone)]
+pub enum HeaderOp {
+    /// Replaces every value of `name` with `value`.
+    Set {
+        name: HeaderName,
+        value: HeaderValue,
+    },
+    Remove {
+        name: HeaderName,
+    },
+    /// Removes every header whose name matches.
+    RemoveRegex {
+        pattern: Regex,
+    },
+    /// Moves every value of `from` to `to`, replacing what `to` had.
+    Rename {
+        from: HeaderName,
+        to: HeaderName,
+    },
+}
+
+impl HeaderOp {
+    /// Parses the arguments of a header filter:
+    ///
+    /// * `set-header name="..." value="..."`
+    /// * `remove-header name="..."`
+    /// * `remove-header-regex pattern="..."`
+    /// * `rename-header from="..." to="..."`
+    ///
+    /// For configurations written before these names, `key` is accepted in place
+    /// of `name`, and `remove-header pattern="..."` removes by regex.
+    pub fn parse(
+        kind: HeaderOpKind,
+        args: &BTreeMap<String, Value>,
+    ) -> Result<Self, FilterArgError> {
+        match kind {
+            HeaderOpKind::Set => {
+                let legacy = args.contains_key("key") && !args.contains_key("name");
+                let name = header_name(args, if legacy { "key" } else { "name" })?;
+
+                let raw = string_arg(args, "value")?;
+                let value = HeaderValue::from_str(raw).map_err(|_| {
+                    FilterArgError::at("value", format!("'{raw}' is not a valid header value"))
+                })?;
+
+                Ok(Self::Set { name, value })
+            }
+            HeaderOpKind::Remove if args.contains_key("pattern") && !args.contains_key("name") => {
+                Self::parse(HeaderOpKind::RemoveRegex, args)
+            }
+            HeaderOpKind::Remove => Ok(Self::Remove {
+                name: header_name(args, "name")?,
+            }),
+            HeaderOpKind::RemoveRegex => {
+                let raw = string_arg(args, "pattern")?;
+                let pattern = Regex::new(raw)
+                    .map_err(|e| FilterArgError::at("pattern", format!("Invalid regex: {e}")))?;
+
+                Ok(Self::RemoveRegex { pattern })
+            }
+            HeaderOpKind::Rename => {
+                let from = header_name(args, "from")?;
+                let to = header_name(args, "to")?;
+                if from == to {
+                    return Err(FilterArgError::at(
+                        "to",
+                        "'from' and 'to' are the same header",
+                    ));
+                }
+
+                Ok(Self::Rename { from, to })
+            }
+        }
+    }
+}
+
+pub(crate) fn string_arg<'a>(
+    args: &'a BTreeMap<String, Value>,
+    key: &str,
+) -> Result<&'a str, FilterArgError> {
+    match args.get(key) {
+        Some(Value::String(s)) => Ok(s),
+        Some(other) => Err(FilterArgError::at(
+            key,
+            format!("'{key}' must be a string, got {other}"),
+        )),
+        None => Err(FilterArgError::missing(key)),
+    }
+}
+
+fn header_name(args: &BTreeMap<String, Value>, key: &str) -> Result<HeaderName, FilterArgError> {
+    let raw = string_arg(args, key)?;
+    HeaderName::from_bytes(raw.as_bytes())
+        .map_err(|_| FilterArgError::at(key, format!("'{raw}' is not a valid header name")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(pairs: &[(&str, &str)]) -> BTreeMap<String, Value> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), Value::String(v.to_string())))
+            .collect()
+    }
+
+    #[test]
+    fn test_kind_of_filter() {
+        assert_eq!(
+            HeaderOpKind::of_filter("motya.request.set-header"),
+            Some(HeaderOpKind::Set)
+        );
+        assert_eq!(
+            HeaderOpKind::of_filter("motya.response.upsert-header."),
+            Some(HeaderOpKind::Set)
+        );
+        assert_eq!(
+            HeaderOpKind::of_filter("motya.response.remove-header-regex"),
+            Some(HeaderOpKind::RemoveRegex)
+        );
+        assert_eq!(HeaderOpKind::of_filter("acme.request.set-header"), None);
+        assert_eq!(HeaderOpKind::of_filter("motya.filters.delay"), None);
+    }
+
+    #[test]
+    fn test_parse_ops() {
+        let op = HeaderOp::parse(
+            HeaderOpKind::Set,
+            &args(&[("name", "X-Proxy"), ("value", "motya")]),
+        )
+        .unwrap();
+        assert!(
+            matches!(op, HeaderOp::Set { name, value } if name == "x-proxy" && value == "motya")
+        );
+
+        let op = HeaderOp::parse(
+            HeaderOpKind::Rename,
+            &args(&[("from", "X-Old"), ("to", "X-New")]),
+        )
+        .unwrap();
+        assert!(matches!(op, HeaderOp::Rename { from, to } if from == "x-old" && to == "x-new"));
+    }
+
+    #[test]
+    fn test_legacy_arguments() {
+        let op = HeaderOp::parse(
+            HeaderOpKind::Set,
+            &args(&[("key", "X-Proxy"), ("value", "motya")]),
+        )
+        .unwrap();
+        assert!(matches!(op, HeaderOp::Set { name, .. } if name == "x-proxy"));
+
+        let op = HeaderOp::parse(HeaderOpKind::Remove, &args(&[("pattern", ".*ETag.*")])).unwrap();
+        assert!(matches!(op, HeaderOp::RemoveRegex { .. }));
+    }
+
+    #[test]
+    fn test_errors_name_the_argument() {
+        let err = HeaderOp::parse(
+            HeaderOpKind::Set,
+            &args(&[("name", "X Bad"), ("value", "v")]),
+        )
+        .unwrap_err();
+        assert_eq!(err.arg.as_deref(), Some("name"));
+
+        let err =
+            HeaderOp::parse(HeaderOpKind::RemoveRegex, &args(&[("pattern", "(")])).unwrap_err();
+        assert_eq!(err.arg.as_deref(), Some("pattern"));
+
+        let err = HeaderOp::parse(HeaderOpKind::Rename, &args(&[("from", "X-Old")])).unwrap_err();
+        assert_eq!(err.arg, None);
+        assert!(err.message.contains("'to'"), "{}", err.message);
+    }
+}
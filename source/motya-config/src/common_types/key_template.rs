@@ -13,7 +13,16 @@ pub enum KeyPart {
     UriPath,
     ClientIp,
     UserAgent,
-    Header(String),
+    Method,
+    Host,
+    /// `${header-NAME}`. `:all` joins the values of a repeated header with `, `
+    /// instead of taking the first one, and `:raw` takes values that aren't
+    /// visible ASCII byte for byte instead of skipping them.
+    Header {
+        name: String,
+        all: bool,
+        raw: bool,
+    },
     Cookie(String),
     QueryParams(String),
 }
@@ -97,12 +106,28 @@ fn parse_variable(var: &str) -> Result<KeyPart, String> {
         "uri-path" => Ok(KeyPart::UriPath),
         "client-ip" => Ok(KeyPart::ClientIp),
         "user-agent" => Ok(KeyPart::UserAgent),
+        "method" => Ok(KeyPart::Method),
+        "host" => Ok(KeyPart::Host),
         s if s.starts_with("header-") => {
-            let name = s.strip_prefix("header-").unwrap().to_lowercase();
+            let mut modifiers = s.strip_prefix("header-").unwrap().split(':');
+            let name = modifiers.next().unwrap_or_default().to_lowercase();
             if name.is_empty() {
                 return Err("Empty header name".to_string());
             }
-            Ok(KeyPart::Header(name))
+
+            let (mut all, mut raw) = (false, false);
+            for modifier in modifiers {
+                match modifier {
+                    "all" => all = true,
+                    "raw" => raw = true,
+                    other => {
+                        return Err(format!(
+                            "Unknown header modifier '{other}', expected 'all' or 'raw'"
+                        ))
+                    }
+                }
+            }
+            Ok(KeyPart::Header { name, all, raw })
         }
         s if s.starts_with("cookie-") => {
             let name = s.strip_prefix("cookie-").unwrap().to_string();
@@ -193,7 +218,11 @@ mod tests {
         assert_eq!(template.parts.len(), 3);
         assert_eq!(
             template.parts[0],
-            KeyPart::Header("x-my-custom-id".to_string())
+            KeyPart::Header {
+                name: "x-my-custom-id".to_string(),
+                all: false,
+                raw: false,
+            }
         );
         assert_eq!(template.parts[1], KeyPart::Literal(":".to_string()));
         assert_eq!(template.parts[2], KeyPart::Cookie("session-id".to_string()));
@@ -221,6 +250,47 @@ mod tests {
         assert_eq!(template.parts[2], KeyPart::UserAgent);
     }
 
+    #[test]
+    fn test_method_and_host() {
+        let template = KeyTemplate::new("${method} ${host}").unwrap();
+
+        assert_eq!(template.parts[0], KeyPart::Method);
+        assert_eq!(template.parts[1], KeyPart::Literal(" ".to_string()));
+        assert_eq!(template.parts[2], KeyPart::Host);
+    }
+
+    #[test]
+    fn test_header_modifiers() {
+        let template =
+            KeyTemplate::new("${header-X-Forwarded-For:all}${header-x-token:raw:all}").unwrap();
+
+        assert_eq!(
+            template.parts[0],
+            KeyPart::Header {
+                name: "x-forwarded-for".to_string(),
+                all: true,
+                raw: false,
+            }
+        );
+        assert_eq!(
+            template.parts[1],
+            KeyPart::Header {
+                name: "x-token".to_string(),
+                all: true,
+                raw: true,
+            }
+        );
+
+        assert_eq!(
+            KeyTemplate::new("${header-x-token:first}").unwrap_err(),
+            "Unknown header modifier 'first', expected 'all' or 'raw'"
+        );
+        assert_eq!(
+            KeyTemplate::new("${header-:raw}").unwrap_err(),
+            "Empty header name"
+        );
+    }
+
     #[test]
     fn test_empty_template_gives_empty_literal() {
         let template = KeyTemplate::new("").unwrap();
@@ -0,0 +1,11 @@
+/// Concurrency caps of a proxy service, from its `limits` block.
+///
+/// Past either cap new work is shed with `503 Service Unavailable` and a
+/// `Retry-After` header, instead of waiting for a free slot.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LimitsConfig {
+    /// Client connections open at once, over all listeners of the service.
+    pub max_connections: Option<usize>,
+    /// Requests being handled at once.
+    pub max_inflight: Option<usize>,
+}
@@ -1,4 +1,8 @@
-use std::path::PathBuf;
+use std::{fmt, path::PathBuf, str::FromStr, time::Duration};
+
+use miette::miette;
+
+use crate::kdl::schema::{definitions::ValueKind, value_info::KdlValueInfo};
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct TlsConfig {
@@ -6,14 +10,103 @@ pub struct TlsConfig {
     pub key_path: PathBuf,
 }
 
+/// Socket-level options applied before the listener is bound.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct SocketOptions {
+    /// Let other processes bind the same address and share its connections (`SO_REUSEPORT`).
+    pub reuse_port: bool,
+    /// Accept data in the SYN of returning clients (`TCP_FASTOPEN`).
+    pub tcp_fast_open: bool,
+    /// Only accept IPv6 connections on an IPv6 address, or IPv4-mapped ones too (`IPV6_V6ONLY`).
+    pub ipv6_only: Option<bool>,
+}
+
+impl SocketOptions {
+    pub fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum ListenerKind {
     Tcp {
         addr: String,
         tls: Option<TlsConfig>,
         offer_h2: bool,
+        /// Every connection starts with a PROXY protocol (v1 or v2) header,
+        /// which carries the address of the client behind a load balancer.
+        proxy_protocol: bool,
+        /// New connections accepted at most, before any byte of them is read.
+        max_conn_rate: Option<ConnectionRate>,
+        socket: SocketOptions,
     },
-    Uds(PathBuf),
+    Uds(UdsConfig),
+}
+
+/// A number of connections per unit of time, written as `"1000/s"`, `"600/m"` or
+/// `"3600/h"`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConnectionRate {
+    pub count: u32,
+    pub per: Duration,
+}
+
+impl ConnectionRate {
+    pub fn per_second(&self) -> f64 {
+        f64::from(self.count) / self.per.as_secs_f64()
+    }
+}
+
+impl FromStr for ConnectionRate {
+    type Err = miette::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || miette!("Expected a rate like '1000/s', '600/m' or '3600/h', got '{s}'");
+        let (count, unit) = s.trim().split_once('/').ok_or_else(invalid)?;
+
+        let count: u32 = count.trim().parse().map_err(|_| invalid())?;
+        if count == 0 {
+            return Err(miette!("A rate of 0 connections accepts none, got '{s}'"));
+        }
+
+        let per = match unit.trim() {
+            "s" => Duration::from_secs(1),
+            "m" => Duration::from_secs(60),
+            "h" => Duration::from_secs(3600),
+            _ => return Err(invalid()),
+        };
+
+        Ok(Self { count, per })
+    }
+}
+
+impl fmt::Display for ConnectionRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unit = match self.per.as_secs() {
+            1 => "s",
+            60 => "m",
+            _ => "h",
+        };
+        write!(f, "{}/{unit}", self.count)
+    }
+}
+
+impl KdlValueInfo for ConnectionRate {
+    fn value_kind() -> ValueKind {
+        ValueKind::TypedString("rate".to_string())
+    }
+}
+
+/// A listener on a unix domain socket.
+#[derive(Debug, PartialEq, Clone)]
+pub struct UdsConfig {
+    pub path: PathBuf,
+    /// Permission bits set on the socket file once it is bound, e.g. `0o660`.
+    pub mode: Option<u32>,
+    /// User name or numeric id the socket file is handed to once it is bound.
+    pub owner: Option<String>,
+    /// Group name or numeric id the socket file is handed to once it is bound.
+    pub group: Option<String>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -25,3 +118,23 @@ pub struct ListenerConfig {
 pub struct Listeners {
     pub list_cfgs: Vec<ListenerConfig>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connection_rate() {
+        let rate: ConnectionRate = "1000/s".parse().unwrap();
+        assert_eq!(rate.per_second(), 1000.0);
+        assert_eq!(rate.to_string(), "1000/s");
+
+        let rate: ConnectionRate = "600 / m".parse().unwrap();
+        assert_eq!(rate.per_second(), 10.0);
+        assert_eq!(rate.to_string(), "600/m");
+
+        for invalid in ["1000", "0/s", "-1/s", "10/d", "/s", "ten/s"] {
+            assert!(invalid.parse::<ConnectionRate>().is_err(), "{invalid}");
+        }
+    }
+}
@@ -0,0 +1,35 @@
+use std::{path::PathBuf, time::Duration};
+
+/// The status maintenance answers with unless `status` says otherwise.
+pub const DEFAULT_MAINTENANCE_STATUS: u16 = 503;
+
+/// The maintenance switch of a proxy service, from its `maintenance` block.
+///
+/// While it is on, every request is answered with `status` and the page
+/// `error-pages` has for it, except those under `allow`. It can be turned on
+/// and off through the admin API, or by creating and removing `flag-file`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MaintenanceConfig {
+    /// Whether the service starts in maintenance.
+    pub enabled: bool,
+    /// The service is in maintenance for as long as this file exists.
+    pub flag_file: Option<PathBuf>,
+    pub status: u16,
+    /// Sent as `Retry-After`, in seconds.
+    pub retry_after: Option<Duration>,
+    /// Path prefixes still served, such as health endpoints.
+    pub allow: Vec<String>,
+}
+
+impl Default for MaintenanceConfig {
+    /// Off, and only switched through the admin API.
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            flag_file: None,
+            status: DEFAULT_MAINTENANCE_STATUS,
+            retry_after: None,
+            allow: vec![],
+        }
+    }
+}
@@ -1,16 +1,34 @@
+pub mod access_log;
+pub mod acme;
+pub mod admin;
 pub mod bad;
 pub mod balancer;
+pub mod basic_auth;
+pub mod body_rewrite;
 pub mod builtin_filters_name;
+pub mod byte_size;
+pub mod compression;
+pub mod condition;
+pub mod config_version;
 pub mod connectors;
+pub mod cors;
 pub mod definitions;
 pub mod definitions_table;
+pub mod duration;
 pub mod error;
+pub mod error_pages;
+pub mod ext_auth;
 pub mod file_server;
+pub mod header_ops;
 pub mod key_template;
+pub mod limits;
 pub mod listeners;
+pub mod maintenance;
 pub mod rate_limiter;
+pub mod secrets;
 pub mod section_parser;
 pub mod services;
 pub mod simple_response_type;
 pub mod system_data;
+pub mod throttle;
 pub mod value;
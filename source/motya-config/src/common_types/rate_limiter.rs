@@ -1,6 +1,14 @@
-use std::time::Duration;
+use std::{fmt, str::FromStr, time::Duration};
 
-use crate::common_types::key_template::{KeyTemplate, TransformOp};
+use miette::miette;
+
+use crate::{
+    common_types::{
+        key_template::{KeyPart, KeyTemplate, TransformOp},
+        secrets::SecretValue,
+    },
+    kdl::schema::{definitions::ValueKind, value_info::KdlValueInfo},
+};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum StorageConfig {
@@ -10,18 +18,232 @@ pub enum StorageConfig {
     },
     Redis {
         addresses: Vec<String>,
-        password: Option<String>,
+        password: Option<SecretValue>,
         timeout: Option<Duration>,
     },
 }
 
+/// How a rate-limit policy decides whether a request goes through, from its
+/// `algorithm`. All of them let `burst` requests through at once and `rate`
+/// requests per second after that.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RateLimitAlgorithm {
+    /// A bucket of `burst` tokens, refilled at `rate`.
+    #[default]
+    TokenBucket,
+    /// Counts the requests of the last `burst / rate` seconds, estimated from the
+    /// counts of the current and the previous window.
+    SlidingWindow,
+    /// The generic cell rate algorithm, the limits of the token bucket kept as
+    /// the time the next request is expected at.
+    Gcra,
+}
+
+impl RateLimitAlgorithm {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::TokenBucket => "token-bucket",
+            Self::SlidingWindow => "sliding-window",
+            Self::Gcra => "gcra",
+        }
+    }
+}
+
+impl fmt::Display for RateLimitAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for RateLimitAlgorithm {
+    type Err = miette::Report;
+
+    /// Takes `_` for `-` too, as in the `token_bucket` of older configurations.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.replace('_', "-").as_str() {
+            "token-bucket" => Ok(Self::TokenBucket),
+            "sliding-window" => Ok(Self::SlidingWindow),
+            "gcra" => Ok(Self::Gcra),
+            _ => Err(miette!(
+                "Unknown rate-limit algorithm '{s}'. Expected one of: 'token-bucket', 'sliding-window', 'gcra'"
+            )),
+        }
+    }
+}
+
+/// Which requests share the buckets of a rate-limit policy, from its `scope`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RateLimitScope {
+    /// Every service using the policy has buckets of its own.
+    #[default]
+    Service,
+    /// One set of buckets for the policy, whichever services and routes use it.
+    Global,
+}
+
+impl RateLimitScope {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Service => "service",
+            Self::Global => "global",
+        }
+    }
+}
+
+impl fmt::Display for RateLimitScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for RateLimitScope {
+    type Err = miette::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "service" => Ok(Self::Service),
+            "global" => Ok(Self::Global),
+            _ => Err(miette!(
+                "Unknown rate-limit scope '{s}'. Expected one of: 'service', 'global'"
+            )),
+        }
+    }
+}
+
+impl KdlValueInfo for RateLimitScope {
+    fn value_kind() -> ValueKind {
+        ValueKind::Enum(vec!["service".into(), "global".into()])
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct RateLimitPolicy {
     pub name: String,
-    pub algorithm: String,
+    pub algorithm: RateLimitAlgorithm,
+    pub scope: RateLimitScope,
     pub storage_key: String,
     pub transforms: Vec<TransformOp>,
     pub key_template: KeyTemplate,
     pub rate_req_per_sec: f64,
     pub burst: usize,
+    /// Gives the policy its own key table of this size, evicting the least recently
+    /// used keys once it is full.
+    pub max_keys: Option<usize>,
+    /// Whether responses tell clients where they stand with the `RateLimit-*` headers.
+    pub expose_headers: bool,
+}
+
+impl RateLimitPolicy {
+    /// Template variables whose values are chosen by the client, so every request can
+    /// create a new key. Empty when a `truncate` transform caps the key.
+    pub fn unbounded_key_parts(&self) -> Vec<&'static str> {
+        let truncated = self
+            .transforms
+            .iter()
+            .any(|t| matches!(t, TransformOp::Truncate { .. }));
+
+        if truncated {
+            return vec![];
+        }
+
+        self.key_template
+            .parts
+            .iter()
+            .filter_map(|part| match part {
+                KeyPart::UriPath => Some("${uri-path}"),
+                KeyPart::UserAgent => Some("${user-agent}"),
+                KeyPart::Host => Some("${host}"),
+                KeyPart::QueryParams(_) => Some("${query?..}"),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// A warning about the key when [`Self::unbounded_key_parts`] is not empty.
+    pub fn unbounded_key_warning(&self) -> Option<String> {
+        let parts = self.unbounded_key_parts();
+
+        (!parts.is_empty()).then(|| {
+            format!(
+                "Rate limit policy '{}' builds its key from {}, so clients can create a new \
+                 key with every request. Add a 'truncate' transform to bound the number of keys",
+                self.name,
+                parts.join(", ")
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroUsize;
+
+    use super::*;
+
+    fn policy(template: &str, transforms: Vec<TransformOp>) -> RateLimitPolicy {
+        RateLimitPolicy {
+            name: "test".into(),
+            algorithm: RateLimitAlgorithm::TokenBucket,
+            scope: RateLimitScope::Service,
+            storage_key: "mem".into(),
+            transforms,
+            key_template: KeyTemplate::new(template).unwrap(),
+            rate_req_per_sec: 1.0,
+            burst: 1,
+            max_keys: None,
+            expose_headers: false,
+        }
+    }
+
+    #[test]
+    fn test_unbounded_key_parts() {
+        assert!(policy("${client-ip}", vec![])
+            .unbounded_key_parts()
+            .is_empty());
+        assert_eq!(
+            policy("${client-ip}:${uri-path}:${user-agent}", vec![]).unbounded_key_parts(),
+            vec!["${uri-path}", "${user-agent}"]
+        );
+        assert_eq!(
+            policy("${method}:${host}", vec![]).unbounded_key_parts(),
+            vec!["${host}"]
+        );
+    }
+
+    #[test]
+    fn test_algorithm_names() {
+        for algorithm in [
+            RateLimitAlgorithm::TokenBucket,
+            RateLimitAlgorithm::SlidingWindow,
+            RateLimitAlgorithm::Gcra,
+        ] {
+            assert_eq!(
+                algorithm.name().parse::<RateLimitAlgorithm>().unwrap(),
+                algorithm
+            );
+        }
+        assert_eq!(
+            "token_bucket".parse::<RateLimitAlgorithm>().unwrap(),
+            RateLimitAlgorithm::TokenBucket
+        );
+        assert!("leaky-bucket"
+            .parse::<RateLimitAlgorithm>()
+            .unwrap_err()
+            .to_string()
+            .contains("Unknown rate-limit algorithm 'leaky-bucket'"));
+    }
+
+    #[test]
+    fn test_truncate_bounds_the_key() {
+        let truncate = TransformOp::Truncate {
+            length: NonZeroUsize::new(16).unwrap(),
+        };
+
+        assert!(policy("${uri-path}", vec![truncate])
+            .unbounded_key_parts()
+            .is_empty());
+        assert!(!policy("${uri-path}", vec![TransformOp::Lowercase])
+            .unbounded_key_parts()
+            .is_empty());
+    }
 }
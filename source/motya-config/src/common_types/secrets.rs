@@ -0,0 +1,40 @@
+use std::{fmt, path::PathBuf};
+
+/// Where a secret of `definitions.secrets` is read from.
+///
+/// Secrets are only read when the server starts, never while the config is
+/// parsed, so tools that check configs do not need them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SecretSource {
+    /// An environment variable.
+    Env(String),
+    /// The `KEY=VALUE` line of a file with this key, or else the whole file
+    /// without its trailing line break.
+    File { path: PathBuf, key: Option<String> },
+    /// The output of a command run with `sh -c`, without its trailing line break.
+    Command(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SecretConfig {
+    pub name: String,
+    pub source: SecretSource,
+}
+
+/// A value written in the config, or the secret it names.
+///
+/// The `Debug` output leaves inline values out, so they do not end up in logs.
+#[derive(Clone, PartialEq)]
+pub enum SecretValue {
+    Inline(String),
+    Secret(SecretConfig),
+}
+
+impl fmt::Debug for SecretValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecretValue::Inline(_) => f.write_str("Inline(<redacted>)"),
+            SecretValue::Secret(secret) => f.debug_tuple("Secret").field(&secret.name).finish(),
+        }
+    }
+}
@@ -1,7 +1,9 @@
-use std::{net::SocketAddr, path::PathBuf};
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
 
 use http::uri::PathAndQuery;
 
+use crate::common_types::{acme::AcmeConfig, admin::AdminConfig};
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum ConfigProvider {
     Files(FilesProviderConfig),
@@ -37,6 +39,12 @@ pub struct SystemData {
     pub upgrade_socket: Option<PathBuf>,
     pub pid_file: Option<PathBuf>,
     pub provider: Option<ConfigProvider>,
+    pub production: bool,
+    pub config_version_header: bool,
+    pub admin: Option<AdminConfig>,
+    pub metrics_listener: Option<SocketAddr>,
+    pub shutdown_grace: Option<Duration>,
+    pub acme: Option<AcmeConfig>,
 }
 
 impl Default for SystemData {
@@ -47,6 +55,12 @@ impl Default for SystemData {
             upgrade_socket: None,
             pid_file: None,
             provider: None,
+            production: false,
+            config_version_header: false,
+            admin: None,
+            metrics_listener: None,
+            shutdown_grace: None,
+            acme: None,
         }
     }
 }
@@ -0,0 +1,125 @@
+use std::collections::BTreeMap;
+
+use crate::common_types::{
+    builtin_filters_name::FilterArgError, byte_size::ByteSize, header_ops::string_arg,
+    key_template::KeyTemplate, value::Value,
+};
+
+/// A parsed `throttle` filter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Throttle {
+    /// Bytes per second.
+    pub rate: usize,
+    /// Bytes sent at once before the pace sets in, a second of `rate` by default.
+    pub burst: usize,
+    /// Responses with the same key share `rate`. Without one, every response
+    /// gets its own.
+    pub key: Option<KeyTemplate>,
+}
+
+impl Throttle {
+    /// Parses the arguments of a `throttle` filter:
+    ///
+    /// * `rate="1mb/s"`, the bandwidth
+    /// * `burst="256kb"`, what goes out before the pace sets in
+    /// * `key="${client-ip}"`, the responses sharing the bandwidth
+    pub fn parse(args: &BTreeMap<String, Value>) -> Result<Self, FilterArgError> {
+        let raw = string_arg(args, "rate")?;
+        let rate = raw
+            .strip_suffix("/s")
+            .ok_or_else(|| {
+                FilterArgError::at(
+                    "rate",
+                    format!("'{raw}' is not a rate such as \"1mb/s\" or \"512kb/s\""),
+                )
+            })?
+            .parse::<ByteSize>()
+            .map_err(|e| FilterArgError::at("rate", e.to_string()))?
+            .bytes();
+        if rate == 0 {
+            return Err(FilterArgError::at("rate", "'rate' must not be 0"));
+        }
+
+        let burst = match args.get("burst") {
+            None => rate,
+            Some(_) => string_arg(args, "burst")?
+                .parse::<ByteSize>()
+                .map_err(|e| FilterArgError::at("burst", e.to_string()))?
+                .bytes(),
+        };
+        if burst == 0 {
+            return Err(FilterArgError::at("burst", "'burst' must not be 0"));
+        }
+
+        let key = match args.get("key") {
+            None => None,
+            Some(_) => Some(
+                string_arg(args, "key")?
+                    .parse::<KeyTemplate>()
+                    .map_err(|e| FilterArgError::at("key", e))?,
+            ),
+        };
+
+        Ok(Self { rate, burst, key })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common_types::key_template::KeyPart;
+
+    fn args(pairs: &[(&str, &str)]) -> BTreeMap<String, Value> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), Value::String(v.to_string())))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let throttle = Throttle::parse(&args(&[("rate", "1mb/s")])).unwrap();
+        assert_eq!(
+            throttle,
+            Throttle {
+                rate: 1 << 20,
+                burst: 1 << 20,
+                key: None
+            }
+        );
+
+        let throttle = Throttle::parse(&args(&[
+            ("rate", "512kb/s"),
+            ("burst", "64kb"),
+            ("key", "${client-ip}"),
+        ]))
+        .unwrap();
+        assert_eq!(throttle.rate, 512 << 10);
+        assert_eq!(throttle.burst, 64 << 10);
+        assert_eq!(throttle.key.unwrap().parts, vec![KeyPart::ClientIp]);
+    }
+
+    #[test]
+    fn test_invalid() {
+        for (pairs, arg, error) in [
+            (vec![], None, "Missing argument 'rate'"),
+            (vec![("rate", "1mb")], Some("rate"), "is not a rate"),
+            (vec![("rate", "fast/s")], Some("rate"), "Expected a size"),
+            (vec![("rate", "0/s")], Some("rate"), "must not be 0"),
+            (
+                vec![("rate", "1mb/s"), ("burst", "0")],
+                Some("burst"),
+                "must not be 0",
+            ),
+            (
+                vec![("rate", "1mb/s"), ("key", "${nope}")],
+                Some("key"),
+                "Unknown variable: nope",
+            ),
+        ] {
+            let err = Throttle::parse(&args(&pairs)).unwrap_err();
+            assert_eq!(err.arg.as_deref(), arg, "{pairs:?}");
+            assert!(err.message.contains(error), "{pairs:?}: {}", err.message);
+        }
+    }
+}
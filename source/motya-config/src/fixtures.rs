@@ -0,0 +1,154 @@
+//! Golden tests for the configuration grammar.
+//!
+//! Every `*.kdl` file directly in `tests/fixtures` is loaded as an entry point,
+//! the way `motya` loads its config file. The outcome is rendered as text, either
+//! the resolved [`Config`](crate::internal::Config) in its `Debug` form or every
+//! error with its location, and compared with the `.golden` file of the same name.
+//!
+//! After an intended change, rewrite the golden files with
+//! `MOTYA_BLESS=1 cargo test -p motya-config fixtures` and review their diff.
+
+use std::{
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use miette::{IntoDiagnostic, Result};
+
+use crate::{
+    common_types::{
+        definitions_table::DefinitionsTable,
+        error::{ConfigError, ParseError},
+    },
+    kdl::fs_loader::{AsyncFs, FileCollector},
+    loader::ConfigLoader,
+};
+
+const BLESS_VAR: &str = "MOTYA_BLESS";
+
+#[derive(Clone, Default)]
+struct TokioFs;
+
+impl AsyncFs for TokioFs {
+    async fn canonicalize(path: &Path) -> Result<PathBuf> {
+        tokio::fs::canonicalize(path).await.into_diagnostic()
+    }
+
+    async fn read_to_string(path: &Path) -> Result<String> {
+        tokio::fs::read_to_string(path).await.into_diagnostic()
+    }
+
+    async fn read_dir(path: &Path) -> Result<Vec<PathBuf>> {
+        let mut entries = tokio::fs::read_dir(path).await.into_diagnostic()?;
+        let mut files = Vec::new();
+        while let Some(entry) = entries.next_entry().await.into_diagnostic()? {
+            files.push(entry.path());
+        }
+        Ok(files)
+    }
+}
+
+fn fixtures_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+fn fixtures() -> Vec<PathBuf> {
+    let mut paths: Vec<_> = fs::read_dir(fixtures_dir())
+        .expect("tests/fixtures should exist")
+        .map(|entry| entry.expect("readable fixtures dir").path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "kdl"))
+        .collect();
+    paths.sort();
+    paths
+}
+
+async fn render(path: &Path) -> String {
+    let loader = ConfigLoader::new(FileCollector::<TokioFs>::default());
+    let mut table = DefinitionsTable::new_with_global();
+
+    let (config, errors) = loader
+        .load_lossy(Some(path.to_path_buf()), &mut table)
+        .await;
+
+    match config {
+        Some(config) if errors.is_empty() => format!("{config:#?}\n"),
+        _ if !errors.is_empty() => render_errors(&errors),
+        _ => panic!("{}: neither a config nor errors", path.display()),
+    }
+}
+
+/// Renders errors like `rustc` does, without colors or terminal-dependent layout.
+fn render_errors(errors: &ConfigError) -> String {
+    let mut out = String::new();
+
+    for (idx, error) in errors.errors.iter().enumerate() {
+        if idx > 0 {
+            out.push('\n');
+        }
+        render_error(&mut out, error);
+    }
+
+    out
+}
+
+fn render_error(out: &mut String, error: &ParseError) {
+    writeln!(out, "error: {}", error.message).unwrap();
+
+    if let Some(span) = error.label {
+        let source = error.src.inner();
+        let start = span.offset().min(source.len());
+
+        let line_start = source[..start].rfind('\n').map_or(0, |idx| idx + 1);
+        let line_end = source[start..]
+            .find('\n')
+            .map_or(source.len(), |idx| start + idx);
+        let line = &source[line_start..line_end];
+
+        let line_no = source[..start].matches('\n').count() + 1;
+        let column = source[line_start..start].chars().count() + 1;
+        let width = source[start..(start + span.len()).min(line_end)]
+            .chars()
+            .count()
+            .max(1);
+
+        writeln!(out, " --> {}:{line_no}:{column}", error.src.name()).unwrap();
+        writeln!(out, "  | {line}").unwrap();
+        writeln!(out, "  | {}{}", " ".repeat(column - 1), "^".repeat(width)).unwrap();
+    }
+
+    if let Some(help) = &error.help {
+        writeln!(out, "help: {help}").unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_fixtures_match_golden_files() {
+    let bless = std::env::var_os(BLESS_VAR).is_some();
+    let mut mismatches = Vec::new();
+
+    for path in fixtures() {
+        let rendered = render(&path).await;
+        let golden_path = path.with_extension("golden");
+
+        if bless {
+            fs::write(&golden_path, &rendered).expect("golden file should be writable");
+            continue;
+        }
+
+        let golden = fs::read_to_string(&golden_path).unwrap_or_default();
+        if golden != rendered {
+            mismatches.push(format!(
+                "--- {}\n{golden}\n+++ rendered\n{rendered}",
+                golden_path.display()
+            ));
+        }
+    }
+
+    assert!(
+        mismatches.is_empty(),
+        "{} fixture(s) differ from their golden files, rerun with {BLESS_VAR}=1 to update them:\n\n{}",
+        mismatches.len(),
+        mismatches.join("\n")
+    );
+}
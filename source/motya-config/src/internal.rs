@@ -1,11 +1,20 @@
-use std::path::PathBuf;
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
 
 use crate::
     common_types::{
-        balancer::{BalancerConfig, DiscoveryKind, HealthCheckKind, SelectionKind},
+        access_log::AccessLogConfig,
+        acme::AcmeConfig,
+        admin::AdminConfig,
+        balancer::{
+            BalancerConfig, DiscoveryKind, HealthCheckKind, OutlierDetectionConfig, SelectionKind,
+        },
+        config_version::ConfigVersion,
         connectors::Connectors,
+        error_pages::ErrorPagesConfig,
         file_server::FileServerConfig,
+        limits::LimitsConfig,
         listeners::Listeners,
+        maintenance::MaintenanceConfig,
     }
 ;
 
@@ -18,6 +27,16 @@ pub struct Config {
     pub pid_file: Option<PathBuf>,
     pub upgrade_socket: Option<PathBuf>,
     pub upgrade: bool,
+    pub production: bool,
+    pub config_version_header: bool,
+    /// Access control for admin routes; `None` when no `system.admin` block is given.
+    pub admin: Option<AdminConfig>,
+    /// Address of the Prometheus scrape listener, from `system.metrics-listener`.
+    pub metrics_listener: Option<SocketAddr>,
+    /// How long in-flight requests may finish on shutdown, from `system.shutdown-grace`.
+    pub shutdown_grace: Option<Duration>,
+    /// Certificate provisioning, from `system.acme`.
+    pub acme: Option<AcmeConfig>,
     pub basic_proxies: Vec<ProxyConfig>,
     pub file_servers: Vec<FileServerConfig>,
 }
@@ -29,6 +48,10 @@ pub struct Config {
 pub struct ProxyConfig {
     pub name: String,
     pub listeners: Listeners,
+    pub access_log: Option<AccessLogConfig>,
+    pub limits: Option<LimitsConfig>,
+    pub error_pages: Option<ErrorPagesConfig>,
+    pub maintenance: Option<MaintenanceConfig>,
     pub connectors: Connectors,
 }
 
@@ -38,6 +61,10 @@ pub struct UpstreamOptions {
     pub template: Option<BalancerConfig>,
     pub health_checks: HealthCheckKind,
     pub discovery: DiscoveryKind,
+    /// How long a server that was added or came back healthy takes to get its
+    /// full share of requests.
+    pub slow_start: Option<Duration>,
+    pub outlier_detection: Option<OutlierDetectionConfig>,
 }
 
 impl Default for UpstreamOptions {
@@ -47,6 +74,8 @@ impl Default for UpstreamOptions {
             template: None,
             health_checks: HealthCheckKind::None,
             discovery: DiscoveryKind::Static,
+            slow_start: None,
+            outlier_detection: None,
         }
     }
 }
@@ -62,6 +91,18 @@ impl Default for Config {
             pid_file: None,
             upgrade_socket: Some(PathBuf::from("/tmp/motya-upgrade.sock")),
             upgrade: false,
+            production: false,
+            config_version_header: false,
+            admin: None,
+            metrics_listener: None,
+            shutdown_grace: None,
+            acme: None,
         }
     }
 }
+
+impl Config {
+    pub fn version(&self) -> ConfigVersion {
+        ConfigVersion::of(self)
+    }
+}
@@ -1,34 +1,51 @@
 use std::{
     collections::BTreeMap,
+    fs::File,
     net::SocketAddr,
+    path::PathBuf,
     sync::atomic::{AtomicUsize, Ordering},
 };
 
+use cidr::IpCidr;
 use http::{uri::PathAndQuery, StatusCode};
 use miette::Result;
+use regex::Regex;
 
 use crate::{
     common_types::{
-        balancer::{BalancerConfig, DiscoveryKind, HealthCheckKind, SelectionKind},
+        balancer::{
+            BalancerConfig, DiscoveryKind, HealthCheckKind, KubernetesDiscoveryConfig,
+            OutlierDetectionConfig, SelectionKind, TcpHealthCheckConfig, DEFAULT_DNS_REFRESH,
+        },
+        builtin_filters_name::{check_builtin_args, is_staging_only, staging_only_message},
+        byte_size::ByteSize,
+        compression::{CompressionAlgorithm, CompressionConfig, ContentTypePattern},
         connectors::{
-            Connectors, ConnectorsLeaf, HttpPeerConfig, MultiServerUpstreamConfig, RouteMatcher,
-            RoutingMode, UpstreamConfig, UpstreamContextConfig, UpstreamServer, ALPN,
+            CacheConfig, ClientCertConfig, Connectors, ConnectorsLeaf, H2Config, HttpPeerConfig,
+            MirrorConfig, MultiServerUpstreamConfig, RetryConfig, RetryOn, RewriteConfig,
+            RewritePattern, RouteConditions, RouteMatcher, RoutePattern, RoutingMode, SseConfig,
+            TimeoutConfig, UpstreamConfig, UpstreamContextConfig, UpstreamServer,
+            UpstreamTlsConfig, ALPN,
         },
         definitions::{ChainItem, ConfiguredFilter, FilterChain, Modificator, NamedFilterChain},
         definitions_table::DefinitionsTable,
         error::ConfigError,
         key_template::{parse_hasher, HashAlgorithm, HashOp, KeyPart},
-        rate_limiter::RateLimitPolicy,
+        rate_limiter::{RateLimitPolicy, RateLimitScope},
         simple_response_type::SimpleResponseConfig,
         value::Value,
     },
     internal::UpstreamOptions,
     kdl::{
+        definitions::DefinitionsCompiler,
         models::{
             chains::{ChainItemDefData, RateLimitDefData, UseChainDef, UseChainDefData},
             connectors::{
-                ConnectorLeafDef, ConnectorLeafDefData, ConnectorsDef, LoadBalanceDef,
-                ProxyDefData, SectionDef, SelectionAlgDefData, SelectionDef, SelectionDefData,
+                CacheDef, CompressionDef, ConnectorLeafDef, ConnectorLeafDefData, ConnectorsDef,
+                DiscoveryDef, H2Def, HealthCheckDef, LoadBalanceDef, MirrorDef,
+                OutlierDetectionDef, ProxyDefData, RetryDef, RewriteDef, SectionDef,
+                SelectionAlgDefData, SelectionDef, SelectionDefData, SseDef, TimeoutDef,
+                UpstreamTlsDef,
             },
         },
         parser::{ctx::ParseContext, spanned::Spanned},
@@ -37,13 +54,15 @@ use crate::{
 
 pub struct ConnectorsLinker<'a> {
     table: &'a DefinitionsTable,
+    production: bool,
     anon_counter: AtomicUsize,
 }
 
 impl<'a> ConnectorsLinker<'a> {
-    pub fn new(table: &'a DefinitionsTable) -> Self {
+    pub fn new(table: &'a DefinitionsTable, production: bool) -> Self {
         Self {
             table,
+            production,
             anon_counter: AtomicUsize::new(0),
         }
     }
@@ -60,7 +79,25 @@ impl<'a> ConnectorsLinker<'a> {
             RouteMatcher::Exact,
         );
 
-        let upstreams = flatten_nodes(root_nodes, &[], &mut errors);
+        let mut upstreams = flatten_nodes(root_nodes, &[], &mut errors);
+
+        // Event streams are left out, rather than rejected as in a section.
+        if let Some(compression_def) = data.compression {
+            let compression = self.compile_compression(compression_def, &mut errors);
+            for upstream in &mut upstreams {
+                if upstream.compression.is_none() && upstream.sse.is_none() {
+                    upstream.compression = Some(compression.data.clone());
+                }
+            }
+        }
+
+        // A section's own `timeout` wins over the one of the whole service.
+        if let Some(timeout_def) = data.timeout {
+            let timeout = self.compile_timeout(timeout_def, &mut errors);
+            for upstream in &mut upstreams {
+                upstream.timeout.get_or_insert_with(|| timeout.data.clone());
+            }
+        }
 
         (Connectors { upstreams }, errors)
     }
@@ -80,16 +117,35 @@ impl<'a> ConnectorsLinker<'a> {
             let next_matcher = match data.routing_mode {
                 Some(RoutingMode::Prefix) => RouteMatcher::Prefix,
                 Some(RoutingMode::Exact) => RouteMatcher::Exact,
-                None => parent_matcher,
+                Some(RoutingMode::Regex) => {
+                    match RoutePattern::new(&regex_route(&base_path, data.path.as_str())) {
+                        Ok(pattern) => RouteMatcher::Regex(pattern),
+                        Err(e) => {
+                            errors.push_report(
+                                ctx.err_path(format!("Invalid route pattern: {e}")),
+                                &ctx.ctx,
+                            );
+                            continue;
+                        }
+                    }
+                }
+                None => parent_matcher.clone(),
             };
 
-            if next_matcher == RouteMatcher::Exact && !data.sections.is_empty() {
-                errors.push_report(
-                    ctx.err_sections(
-                        "A section with 'exact' routing mode cannot contain nested sections",
-                    ),
-                    &ctx.ctx,
-                );
+            if !data.sections.is_empty() {
+                let mode = match next_matcher {
+                    RouteMatcher::Exact => Some("exact"),
+                    RouteMatcher::Regex(_) => Some("regex"),
+                    RouteMatcher::Prefix => None,
+                };
+                if let Some(mode) = mode {
+                    errors.push_report(
+                        ctx.err_sections(format!(
+                            "A section with '{mode}' routing mode cannot contain nested sections"
+                        )),
+                        &ctx.ctx,
+                    );
+                }
             }
 
             let path_str = data.path.path();
@@ -130,14 +186,78 @@ impl<'a> ConnectorsLinker<'a> {
                 }
             }
 
-            let leaf_node = self.compile_connector_leaf(
+            if let Some(compression_def) = data.compression {
+                let compression = self.compile_compression(compression_def, errors);
+                section_elements.push(Spanned::new(
+                    ConnectorsLeaf::Compression(compression.data),
+                    compression.ctx,
+                ));
+            }
+
+            if let Some(sse_def) = data.sse {
+                if let Some(sse_node) = self.compile_sse(sse_def, errors) {
+                    section_elements.push(sse_node);
+                }
+            }
+
+            if let Some(cache_def) = data.cache {
+                section_elements.push(self.compile_cache(cache_def, errors));
+            }
+
+            if let Some(mirror_def) = data.mirror {
+                section_elements.push(self.compile_mirror(mirror_def, errors));
+            }
+
+            if let Some(timeout_def) = data.timeout {
+                let timeout = self.compile_timeout(timeout_def, errors);
+                section_elements.push(Spanned::new(
+                    ConnectorsLeaf::Timeout(timeout.data),
+                    timeout.ctx,
+                ));
+            }
+
+            if let Some(rewrite_def) = data.rewrite {
+                if let Some(rewrite) =
+                    self.compile_rewrite(rewrite_def, errors, &current_path, &next_matcher)
+                {
+                    section_elements.push(rewrite);
+                }
+            }
+
+            if let Some(allow) = data.allow_upgrades {
+                section_elements.push(Spanned::new(
+                    ConnectorsLeaf::AllowUpgrades(allow),
+                    ctx.ctx.clone(),
+                ));
+            }
+
+            if let Some(trace) = data.debug_trace {
+                section_elements.push(Spanned::new(
+                    ConnectorsLeaf::DebugTrace(trace),
+                    ctx.ctx.clone(),
+                ));
+            }
+
+            let conditions = RouteConditions {
+                methods: data.method.map(|methods| methods.0).unwrap_or_default(),
+                query: data.query.map(|query| query.0).unwrap_or_default(),
+            };
+            if !conditions.is_empty() {
+                section_elements.push(Spanned::new(
+                    ConnectorsLeaf::Conditions(conditions),
+                    ctx.ctx.clone(),
+                ));
+            }
+
+            let (leaf_node, proxy_nodes) = self.compile_connector_leaf(
                 data.leaf,
                 &ctx.ctx,
                 errors,
                 current_path.clone(),
-                next_matcher,
+                next_matcher.clone(),
             );
 
+            section_elements.extend(proxy_nodes);
             section_elements.push(leaf_node);
 
             let children_sections =
@@ -163,13 +283,28 @@ impl<'a> ConnectorsLinker<'a> {
         errors: &mut ConfigError,
         current_path: PathAndQuery,
         matcher: RouteMatcher,
-    ) -> Spanned<ConnectorsLeaf> {
+    ) -> (Spanned<ConnectorsLeaf>, Vec<Spanned<ConnectorsLeaf>>) {
         let (leaf_data, leaf_ctx) = leaf_def.into_parts();
+        let mut retry_def = None;
+        let mut tls_def = None;
+        let mut tls_on = false;
+        let mut h2_def = None;
+        let mut h2_on = false;
+        let mut proxy_nodes = Vec::new();
 
         let leaf_content = match leaf_data {
             ConnectorLeafDefData::Return(ret_def) => {
                 let (data, ctx) = ret_def.into_parts();
 
+                if let RouteMatcher::Regex(_) = matcher {
+                    errors.push_report(
+                        ctx.err_self(
+                            "'return' is not supported in a section with 'regex' routing mode",
+                        ),
+                        &ctx.ctx,
+                    );
+                }
+
                 let http_code = match StatusCode::from_u16(data.code) {
                     Ok(c) => c,
                     Err(_) => {
@@ -186,13 +321,23 @@ impl<'a> ConnectorsLinker<'a> {
             }
             ConnectorLeafDefData::Proxy(proxy_def) => {
                 let (proxy_data, proxy_ctx) = proxy_def.into_parts();
+                let mut grpc_def = false;
 
                 let config = match proxy_data {
                     ProxyDefData::Single {
                         url,
                         tls_sni,
                         proto,
+                        grpc,
+                        retry,
+                        tls: tls_props,
+                        h2,
                     } => {
+                        retry_def = retry;
+                        tls_def = tls_props;
+                        h2_def = h2;
+                        grpc_def = grpc.unwrap_or(false);
+
                         let host_addr = match url
                             .authority()
                             .and_then(|host| host.as_str().parse::<SocketAddr>().ok())
@@ -207,15 +352,28 @@ impl<'a> ConnectorsLinker<'a> {
                             }
                         };
 
-                        let (tls, sni, alpn) = match self
-                            .resolve_proto_settings(proto.as_deref(), tls_sni.as_deref())
-                        {
+                        if url.path_and_query().is_some_and(|p| p.as_str() != "/") {
+                            errors.push_report(
+                                proxy_ctx.err_self(
+                                    "The path of a 'proxy' URL is not sent upstream, change the upstream path with 'rewrite'",
+                                ),
+                                parent_ctx,
+                            );
+                        }
+
+                        let (tls, sni, alpn) = match self.resolve_proto_settings(
+                            proto.as_deref(),
+                            tls_sni.as_deref(),
+                            grpc_def,
+                        ) {
                             Ok(res) => res,
                             Err(msg) => {
                                 errors.push_report(proxy_ctx.err_self(msg), &proxy_ctx.ctx);
                                 (false, String::new(), ALPN::H1)
                             }
                         };
+                        tls_on = tls;
+                        h2_on = alpn != ALPN::H1;
 
                         UpstreamConfig::Service(HttpPeerConfig {
                             peer_address: host_addr,
@@ -223,10 +381,6 @@ impl<'a> ConnectorsLinker<'a> {
                             sni,
                             tls,
                             prefix_path: current_path,
-                            target_path: url
-                                .path()
-                                .parse()
-                                .unwrap_or(PathAndQuery::from_static("/")),
                             matcher,
                         })
                     }
@@ -234,25 +388,44 @@ impl<'a> ConnectorsLinker<'a> {
                         servers,
                         tls_sni,
                         proto,
+                        grpc,
+                        retry,
+                        tls: tls_props,
+                        h2,
                     } => {
+                        retry_def = retry;
+                        tls_def = tls_props;
+                        h2_def = h2;
+                        grpc_def = grpc.unwrap_or(false);
+
                         let mut upstream_servers = Vec::new();
                         for s_def in servers {
-                            let (s_data, _) = s_def.into_parts();
+                            let (s_data, s_ctx) = s_def.into_parts();
+                            if s_data.weight == Some(0) {
+                                errors.push_report(
+                                    s_ctx.err_weight("Server 'weight' must be at least 1"),
+                                    &s_ctx.ctx,
+                                );
+                            }
                             upstream_servers.push(UpstreamServer {
                                 address: s_data.address,
                                 weight: s_data.weight.unwrap_or(1),
                             });
                         }
 
-                        let (_tls, sni, alpn) = match self
-                            .resolve_proto_settings(proto.as_deref(), tls_sni.as_deref())
-                        {
+                        let (tls, sni, alpn) = match self.resolve_proto_settings(
+                            proto.as_deref(),
+                            tls_sni.as_deref(),
+                            grpc_def,
+                        ) {
                             Ok(res) => res,
                             Err(msg) => {
                                 errors.push_report(proxy_ctx.err_self(msg), &proxy_ctx.ctx);
                                 (false, String::new(), ALPN::H1)
                             }
                         };
+                        tls_on = tls;
+                        h2_on = alpn != ALPN::H1;
 
                         let final_sni = if sni.is_empty() { None } else { Some(sni) };
 
@@ -261,339 +434,2570 @@ impl<'a> ConnectorsLinker<'a> {
                             tls_sni: final_sni,
                             alpn,
                             prefix_path: current_path,
-                            target_path: PathAndQuery::from_static("/"),
+                            matcher,
+                        })
+                    }
+                    ProxyDefData::Group {
+                        name,
+                        tls_sni,
+                        proto,
+                        grpc,
+                        retry,
+                        tls: tls_props,
+                        h2,
+                    } => {
+                        retry_def = retry;
+                        tls_def = tls_props;
+                        h2_def = h2;
+                        grpc_def = grpc.unwrap_or(false);
+
+                        let servers = match self.table.get_upstream_group(&name) {
+                            Some(servers) => servers.to_vec(),
+                            None => {
+                                errors.push_report(
+                                    proxy_ctx.err_group_name(format!(
+                                        "Upstream group '{name}' not found in definitions"
+                                    )),
+                                    &proxy_ctx.ctx,
+                                );
+                                proxy_nodes.push(Spanned::new(
+                                    ConnectorsLeaf::UnresolvedGroup,
+                                    proxy_ctx.ctx.clone(),
+                                ));
+                                Vec::new()
+                            }
+                        };
+
+                        let (tls, sni, alpn) = match self.resolve_proto_settings(
+                            proto.as_deref(),
+                            tls_sni.as_deref(),
+                            grpc_def,
+                        ) {
+                            Ok(res) => res,
+                            Err(msg) => {
+                                errors.push_report(proxy_ctx.err_self(msg), &proxy_ctx.ctx);
+                                (false, String::new(), ALPN::H1)
+                            }
+                        };
+                        tls_on = tls;
+                        h2_on = alpn != ALPN::H1;
+
+                        UpstreamConfig::MultiServer(MultiServerUpstreamConfig {
+                            servers,
+                            tls_sni: if sni.is_empty() { None } else { Some(sni) },
+                            alpn,
+                            prefix_path: current_path,
                             matcher,
                         })
                     }
                 };
+
+                if grpc_def {
+                    proxy_nodes.push(Spanned::new(ConnectorsLeaf::Grpc, proxy_ctx.ctx.clone()));
+                }
                 ConnectorsLeaf::Upstream(config)
             }
         };
 
-        Spanned::new(leaf_content, leaf_ctx.ctx)
+        proxy_nodes.extend(retry_def.map(|def| self.compile_retry(def, errors)));
+        proxy_nodes.extend(tls_def.map(|def| self.compile_upstream_tls(def, tls_on, errors)));
+        proxy_nodes.extend(h2_def.map(|def| self.compile_h2(def, h2_on, errors)));
+
+        (Spanned::new(leaf_content, leaf_ctx.ctx), proxy_nodes)
     }
 
-    fn compile_load_balance(
+    fn compile_upstream_tls(
         &self,
-        lb_def: LoadBalanceDef,
+        tls_def: UpstreamTlsDef,
+        tls_on: bool,
         errors: &mut ConfigError,
-    ) -> Option<Spanned<ConnectorsLeaf>> {
-        let (data, ctx) = lb_def.into_parts();
+    ) -> Spanned<ConnectorsLeaf> {
+        let (data, ctx) = tls_def.into_parts();
+
+        if !tls_on {
+            errors.push_report(
+                ctx.err_self("'tls' needs a TLS upstream, set 'tls-sni' on the 'proxy'"),
+                &ctx.ctx,
+            );
+        }
+
+        // The files are read when the service starts, they must be there already.
+        let mut file = |path: Option<String>, prop: &str| {
+            let path = PathBuf::from(path?);
+            if let Err(e) = File::open(&path) {
+                let message = format!("Cannot read the '{prop}' file {}: {e}", path.display());
+                let report = match prop {
+                    "ca-path" => ctx.err_ca_path(message),
+                    "client-cert" => ctx.err_client_cert(message),
+                    _ => ctx.err_client_key(message),
+                };
+                errors.push_report(report, &ctx.ctx);
+            }
+            Some(path)
+        };
+        let ca_path = file(data.ca_path, "ca-path");
+        let cert_path = file(data.client_cert, "client-cert");
+        let key_path = file(data.client_key, "client-key");
 
-        let health_checks = match data.health_check.as_deref() {
-            Some("None") | None => HealthCheckKind::None,
-            Some(val) => {
+        let client_cert = match (cert_path, key_path) {
+            (Some(cert_path), Some(key_path)) => Some(ClientCertConfig {
+                cert_path,
+                key_path,
+            }),
+            (None, None) => None,
+            (Some(_), None) => {
                 errors.push_report(
-                    ctx.err_health_check(format!("Unknown health-check kind: '{val}'")),
+                    ctx.err_client_cert("'client-key' is missing, but 'client-cert' is provided"),
                     &ctx.ctx,
                 );
-                HealthCheckKind::None
+                None
             }
-        };
-
-        let discovery = match data.discovery.as_deref() {
-            Some("Static") | None => DiscoveryKind::Static,
-            Some(val) => {
+            (None, Some(_)) => {
                 errors.push_report(
-                    ctx.err_discovery(format!("Unknown discovery kind: '{val}'")),
+                    ctx.err_client_key("'client-cert' is missing, but 'client-key' is provided"),
                     &ctx.ctx,
                 );
-                DiscoveryKind::Static
+                None
+            }
+        };
+
+        let verify_cert = data.verify_cert.unwrap_or(true);
+        if !verify_cert && ca_path.is_some() {
+            errors.push_report(
+                ctx.err_ca_path("'ca-path' has no use with verify-cert=#false"),
+                &ctx.ctx,
+            );
+        }
+
+        Spanned::new(
+            ConnectorsLeaf::UpstreamTls(UpstreamTlsConfig {
+                verify_cert,
+                ca_path,
+                client_cert,
+                cert_sha256: data.cert_sha256.map(|pins| pins.0).unwrap_or_default(),
+            }),
+            ctx.ctx,
+        )
+    }
+
+    fn compile_h2(
+        &self,
+        h2_def: H2Def,
+        h2_on: bool,
+        errors: &mut ConfigError,
+    ) -> Spanned<ConnectorsLeaf> {
+        let (data, ctx) = h2_def.into_parts();
+
+        if !h2_on {
+            errors.push_report(
+                ctx.err_self(
+                    "'h2' needs an HTTP/2 upstream, set proto=\"h2-only\" or proto=\"h2-or-h1\" on the 'proxy'",
+                ),
+                &ctx.ctx,
+            );
+        }
+
+        let config = H2Config {
+            max_streams: data.max_streams,
+            ping_interval: data.ping_interval.map(Into::into),
+            idle_timeout: data.idle_timeout.map(Into::into),
+        };
+        if config == H2Config::default() {
+            errors.push_report(
+                ctx.err_self(
+                    "'h2' needs at least one of 'max-streams', 'ping-interval' or 'idle-timeout'",
+                ),
+                &ctx.ctx,
+            );
+        }
+
+        Spanned::new(ConnectorsLeaf::H2(config), ctx.ctx)
+    }
+
+    fn compile_retry(
+        &self,
+        retry_def: RetryDef,
+        errors: &mut ConfigError,
+    ) -> Spanned<ConnectorsLeaf> {
+        let (data, ctx) = retry_def.into_parts();
+
+        let on = match data.on.as_deref().map(str::parse::<RetryOn>) {
+            Some(Ok(on)) => on,
+            Some(Err(msg)) => {
+                errors.push_report(ctx.err_on(msg), &ctx.ctx);
+                RetryOn::default()
             }
+            // Safe for any request: nothing reached the backend.
+            None => RetryOn {
+                connect_failure: true,
+                ..Default::default()
+            },
         };
 
+        let max_concurrent = data
+            .max_concurrent
+            .unwrap_or(RetryConfig::DEFAULT_MAX_CONCURRENT);
+        if max_concurrent == 0 {
+            errors.push_report(
+                ctx.err_max_concurrent("'max-concurrent' must be greater than zero"),
+                &ctx.ctx,
+            );
+        }
+
+        Spanned::new(
+            ConnectorsLeaf::Retry(RetryConfig {
+                attempts: data.attempts,
+                on,
+                backoff: data.backoff.map(Into::into).unwrap_or_default(),
+                max_concurrent,
+            }),
+            ctx.ctx,
+        )
+    }
+
+    fn compile_load_balance(
+        &self,
+        lb_def: LoadBalanceDef,
+        errors: &mut ConfigError,
+    ) -> Option<Spanned<ConnectorsLeaf>> {
+        let (data, ctx) = lb_def.into_parts();
+
+        let health_checks = data
+            .health_check
+            .map(|hc| self.compile_health_check(hc, errors))
+            .unwrap_or(HealthCheckKind::None);
+
+        let discovery = data
+            .discovery
+            .map(|disco| self.compile_discovery(disco, errors))
+            .unwrap_or(DiscoveryKind::Static);
+
         let (selection, template) = if let Some(sel_def) = data.selection {
             self.compile_selection(sel_def, errors)
         } else {
             (SelectionKind::RoundRobin, None)
         };
 
+        if data.slow_start.is_some_and(|window| window.is_zero()) {
+            errors.push_report(
+                ctx.err_slow_start("'slow-start' must be greater than zero"),
+                &ctx.ctx,
+            );
+        }
+
+        let outlier_detection = data
+            .outlier_detection
+            .map(|od| self.compile_outlier_detection(od, errors));
+
         Some(Spanned::new(
             ConnectorsLeaf::LoadBalance(UpstreamOptions {
                 selection,
                 template,
                 health_checks,
                 discovery,
+                slow_start: data.slow_start.map(Into::into),
+                outlier_detection,
             }),
             ctx.ctx,
         ))
     }
 
-    fn compile_use_chain(
+    fn compile_outlier_detection(
         &self,
-        chain_def: UseChainDef,
+        od_def: OutlierDetectionDef,
         errors: &mut ConfigError,
-        path: &PathAndQuery,
-    ) -> Option<Spanned<ConnectorsLeaf>> {
-        let (chain_data, ctx) = chain_def.into_parts();
+    ) -> OutlierDetectionConfig {
+        let (data, ctx) = od_def.into_parts();
+        let defaults = OutlierDetectionConfig::default();
 
-        let leaf = match chain_data {
-            UseChainDefData::Reference { name } => {
-                if let Some(chain) = self.table.get_chain_by_name(&name) {
-                    ConnectorsLeaf::Modificator(Modificator::Chain(NamedFilterChain {
-                        chain,
-                        name,
-                    }))
-                } else {
+        let window = data.window.map(Into::into).unwrap_or(defaults.window);
+        if window.is_zero() {
+            errors.push_report(
+                ctx.err_window("'window' must be greater than zero"),
+                &ctx.ctx,
+            );
+        }
+        let ejection = data.ejection.map(Into::into).unwrap_or(defaults.ejection);
+        if ejection.is_zero() {
+            errors.push_report(
+                ctx.err_ejection("'ejection' must be greater than zero"),
+                &ctx.ctx,
+            );
+        }
+
+        OutlierDetectionConfig {
+            errors: data.errors.unwrap_or(defaults.errors),
+            window,
+            ejection,
+        }
+    }
+
+    fn compile_discovery(
+        &self,
+        disco_def: DiscoveryDef,
+        errors: &mut ConfigError,
+    ) -> DiscoveryKind {
+        let (data, ctx) = disco_def.into_parts();
+
+        if data.kind != "Dns" && data.refresh.is_some() {
+            errors.push_report(
+                ctx.err_refresh("'refresh' only applies to 'Dns' discovery"),
+                &ctx.ctx,
+            );
+        }
+        if data.kind != "Kubernetes" {
+            const KUBERNETES_ONLY: &str = "only applies to 'Kubernetes' discovery";
+            if data.service.is_some() {
+                errors.push_report(
+                    ctx.err_service(format!("'service' {KUBERNETES_ONLY}")),
+                    &ctx.ctx,
+                );
+            }
+            if data.namespace.is_some() {
+                errors.push_report(
+                    ctx.err_namespace(format!("'namespace' {KUBERNETES_ONLY}")),
+                    &ctx.ctx,
+                );
+            }
+            if data.port.is_some() {
+                errors.push_report(ctx.err_port(format!("'port' {KUBERNETES_ONLY}")), &ctx.ctx);
+            }
+        }
+
+        match data.kind.as_str() {
+            "Static" => DiscoveryKind::Static,
+            "Dns" => {
+                let refresh = data.refresh.map(Into::into).unwrap_or(DEFAULT_DNS_REFRESH);
+                if refresh.is_zero() {
                     errors.push_report(
-                        ctx.err_reference_name(format!(
-                            "Chain '{}' not found in definitions",
-                            name
-                        )),
+                        ctx.err_refresh("'refresh' must be greater than zero"),
                         &ctx.ctx,
                     );
-                    return None;
                 }
+                DiscoveryKind::Dns { refresh }
             }
-            UseChainDefData::Inline { items } => {
-                let mut runtime_items = Vec::new();
-                for item in items {
-                    let (item_data, _) = item.into_parts();
-                    match item_data {
-                        ChainItemDefData::Filter(def) => {
-                            runtime_items.push(ChainItem::Filter(ConfiguredFilter {
-                                args: def
-                                    .params
-                                    .into_iter()
-                                    .map(|(k, v)| (k, v.value().into()))
-                                    .collect::<BTreeMap<String, Value>>(),
-                                name: def.name,
-                            }));
-                        }
-                        ChainItemDefData::RateLimit(def) => {
-                            let (rl_data, rl_ctx) = def.into_parts();
-                            match rl_data {
-                                RateLimitDefData::Reference(name) => {
-                                    if let Some(policy) = self.table.get_rate_limit(&name) {
-                                        runtime_items.push(ChainItem::RateLimiter(policy));
-                                    } else {
-                                        errors.push_report(
-                                            rl_ctx.err_reference_ref(format!(
-                                                "Rate limit policy '{}' not found in definitions",
-                                                name
-                                            )),
-                                            &rl_ctx.ctx,
-                                        );
-                                    }
-                                }
-                                RateLimitDefData::Inline {
-                                    algorithm,
-                                    storage_key,
-                                    key_template,
-                                    transforms,
-                                    burst,
-                                    raw_rate,
-                                } => {
-                                    let key_template = key_template.into_inner();
-
-                                    runtime_items.push(ChainItem::RateLimiter(RateLimitPolicy {
-                                        name: format!("__anon_rl_conn_{}", runtime_items.len()),
-                                        algorithm,
-                                        burst,
-                                        key_template: key_template.template,
-                                        rate_req_per_sec: raw_rate,
-                                        storage_key,
-                                        transforms: transforms
-                                            .map(|v| v.into())
-                                            .unwrap_or_default(),
-                                    }))
-                                }
-                            }
-                        }
-                    }
+            "Kubernetes" => match data.service {
+                Some(service) => DiscoveryKind::Kubernetes(KubernetesDiscoveryConfig {
+                    service,
+                    namespace: data.namespace,
+                    port: data.port,
+                }),
+                None => {
+                    errors.push_report(
+                        ctx.err_self("'discovery \"Kubernetes\"' needs a 'service'"),
+                        &ctx.ctx,
+                    );
+                    DiscoveryKind::Static
                 }
-                let chain = FilterChain {
-                    items: runtime_items,
-                };
-                let id = self.anon_counter.fetch_add(1, Ordering::Relaxed);
-                let path_slug = path.path().replace('/', "_");
-                let generated_name = format!("__anon_{id}_{path_slug}");
-
-                ConnectorsLeaf::Modificator(Modificator::Chain(NamedFilterChain {
-                    chain,
-                    name: generated_name,
-                }))
-            }
-        };
-
-        Some(Spanned::new(leaf, ctx.ctx))
+            },
+            other => {
+                errors.push_report(
+                    ctx.err_kind(format!(
+                        "Unknown discovery kind: '{other}'. Expected one of: 'Static', 'Dns', 'Kubernetes'"
+                    )),
+                    &ctx.ctx,
+                );
+                DiscoveryKind::Static
+            }
+        }
     }
 
-    fn resolve_proto_settings(
+    fn compile_health_check(
         &self,
-        proto: Option<&str>,
-        tls_sni: Option<&str>,
-    ) -> Result<(bool, String, ALPN), String> {
-        let alpn = match proto {
-            Some(p) => Some(parse_proto_value(p)?),
-            None => None,
-        };
-        match (alpn, tls_sni) {
-            (None, None) | (Some(ALPN::H1), None) => Ok((false, String::new(), ALPN::H1)),
-            (None, Some(sni)) => Ok((true, sni.to_string(), ALPN::H2H1)),
-            (Some(_), None) => Err("'tls-sni' is required for HTTP2 support".to_string()),
-            (Some(p), Some(sni)) => Ok((true, sni.to_string(), p)),
+        hc_def: HealthCheckDef,
+        errors: &mut ConfigError,
+    ) -> HealthCheckKind {
+        let (data, ctx) = hc_def.into_parts();
+
+        match data.kind.as_str() {
+            "None" => HealthCheckKind::None,
+            "Tcp" => {
+                let defaults = TcpHealthCheckConfig::default();
+                let interval = data.interval.map(Into::into).unwrap_or(defaults.interval);
+                let timeout = data.timeout.map(Into::into).unwrap_or(defaults.timeout);
+
+                if interval.is_zero() {
+                    errors.push_report(
+                        ctx.err_interval("Health-check interval must be greater than zero"),
+                        &ctx.ctx,
+                    );
+                }
+
+                if timeout.is_zero() || timeout > interval {
+                    errors.push_report(
+                        ctx.err_timeout(format!(
+                            "Health-check timeout must be non-zero and not exceed the interval ({})",
+                            humantime::format_duration(interval)
+                        )),
+                        &ctx.ctx,
+                    );
+                }
+
+                HealthCheckKind::Tcp(TcpHealthCheckConfig {
+                    interval,
+                    timeout,
+                    send: data.send.map(String::into_bytes),
+                    expect: data.expect.map(String::into_bytes),
+                })
+            }
+            val => {
+                errors.push_report(
+                    ctx.err_kind(format!(
+                        "Unknown health-check kind: '{val}'. Expected one of: 'None', 'Tcp'"
+                    )),
+                    &ctx.ctx,
+                );
+                HealthCheckKind::None
+            }
         }
     }
 
-    fn compile_selection(
+    fn compile_compression(
         &self,
-        sel_def: SelectionDef,
+        compression_def: CompressionDef,
         errors: &mut ConfigError,
-    ) -> (SelectionKind, Option<BalancerConfig>) {
-        let (sel_data, sel_ctx) = sel_def.into_parts();
+    ) -> Spanned<CompressionConfig> {
+        let (data, ctx) = compression_def.into_parts();
 
-        match sel_data {
-            SelectionDefData::Reference { kind, profile_ref } => {
-                if let Some(t) = self.table.get_key_templates().get(&profile_ref) {
-                    (kind, Some(t.clone()))
-                } else {
+        let level = match data.level {
+            None => CompressionConfig::default().level,
+            Some(level @ 1..=9) => level as u32,
+            Some(level) => {
+                errors.push_report(
+                    ctx.err_level(format!(
+                        "Compression level must be between 1 and 9, got {level}"
+                    )),
+                    &ctx.ctx,
+                );
+                CompressionConfig::default().level
+            }
+        };
+
+        let mut algorithms = Vec::new();
+        match &data.algorithms {
+            None => algorithms = CompressionAlgorithm::ALL.to_vec(),
+            Some(raw) => {
+                let names = comma_list(raw);
+                if names.is_empty() {
                     errors.push_report(
-                        sel_ctx.err_reference_profile_ref(format!(
-                            "Key profile '{}' not found",
-                            profile_ref
-                        )),
-                        &sel_ctx.ctx,
+                        ctx.err_algorithms("'algorithms' lists no compression algorithms"),
+                        &ctx.ctx,
                     );
-                    (kind, None)
+                }
+                for name in names {
+                    match name.parse::<CompressionAlgorithm>() {
+                        Ok(algorithm) if algorithms.contains(&algorithm) => {}
+                        Ok(algorithm) => algorithms.push(algorithm),
+                        Err(e) => errors.push_report(ctx.err_algorithms(e.to_string()), &ctx.ctx),
+                    }
                 }
             }
-            SelectionDefData::Simple { kind } => (kind, None),
-            SelectionDefData::Inline(alg_wrapper) => {
-                let (alg_data, _) = alg_wrapper.into_parts();
+        }
 
-                match alg_data {
-                    SelectionAlgDefData::None => (SelectionKind::RoundRobin, None),
-                    SelectionAlgDefData::Inline {
-                        kind,
-                        key,
-                        algorithm,
-                        transforms,
-                    } => {
-                        let (key_def, key_ctx) = key.into_parts();
-                        let source = key_def.template;
-                        let fallback = key_def.fallback;
+        let mut content_types = Vec::new();
+        if let Some(raw) = &data.content_types {
+            let patterns = comma_list(raw);
+            if patterns.is_empty() {
+                errors.push_report(
+                    ctx.err_content_types("'content-types' lists no content types"),
+                    &ctx.ctx,
+                );
+            }
+            for pattern in patterns {
+                match pattern.parse::<ContentTypePattern>() {
+                    Ok(pattern) => content_types.push(pattern),
+                    Err(e) => errors.push_report(ctx.err_content_types(e.to_string()), &ctx.ctx),
+                }
+            }
+        }
 
-                        let (alg_def, alg_inner_ctx) = algorithm.into_parts();
+        let mut excluded_types = if data.default_exclusions.unwrap_or(true) {
+            CompressionConfig::default_exclusions()
+        } else {
+            Vec::new()
+        };
 
-                        let runtime_alg_struct = HashAlgorithm {
-                            name: alg_def.name,
-                            seed: alg_def.seed,
-                        };
+        if let Some(exclude) = data.exclude {
+            let (exclude_data, _) = exclude.into_parts();
+            for content_type in exclude_data.types {
+                let pattern = content_type.into_inner().value;
+                if !excluded_types.contains(&pattern) {
+                    excluded_types.push(pattern);
+                }
+            }
+        }
 
-                        let alg_op = match parse_hasher(&runtime_alg_struct) {
-                            Ok(op) => op,
-                            Err(e) => {
-                                errors.push_report(alg_inner_ctx.err_self(e), &alg_inner_ctx.ctx);
-                                HashOp::XxHash64(0)
-                            }
-                        };
+        Spanned::new(
+            CompressionConfig {
+                level,
+                algorithms,
+                min_size: data.min_size.map_or(0, ByteSize::bytes),
+                content_types,
+                excluded_types,
+            },
+            ctx.ctx,
+        )
+    }
 
-                        let mut transform_ops = Vec::new();
-                        if let Some(tr_def) = transforms {
-                            transform_ops = tr_def.into();
-                        }
+    fn compile_sse(
+        &self,
+        sse_def: SseDef,
+        errors: &mut ConfigError,
+    ) -> Option<Spanned<ConnectorsLeaf>> {
+        let (data, ctx) = sse_def.into_parts();
 
-                        match kind {
-                            SelectionKind::KetamaHashing | SelectionKind::FvnHash => {
-                                let is_effectively_empty = source.parts.is_empty()
-                                    || (source.parts.len() == 1
-                                        && matches!(source.parts[0], KeyPart::Literal(ref s) if s.is_empty()));
+        if !data.enabled {
+            return None;
+        }
 
-                                if is_effectively_empty {
-                                    errors.push_report(
-                                        key_ctx.err_self(format!(
-                                            "Selection kind '{:?}' requires a non-empty key source",
-                                            kind
-                                        )),
-                                        &key_ctx.ctx,
-                                    );
-                                }
-                            }
-                            _ => {}
-                        }
+        let idle_timeout = data
+            .idle_timeout
+            .map(Into::into)
+            .unwrap_or(SseConfig::default().idle_timeout);
 
-                        let config = BalancerConfig {
-                            source,
-                            fallback,
-                            algorithm: alg_op,
-                            transforms: transform_ops,
-                        };
+        if idle_timeout.is_zero() {
+            errors.push_report(
+                ctx.err_idle_timeout("SSE idle timeout must be greater than zero"),
+                &ctx.ctx,
+            );
+        }
 
-                        (kind, Some(config))
-                    }
-                }
+        Some(Spanned::new(
+            ConnectorsLeaf::Sse(SseConfig { idle_timeout }),
+            ctx.ctx,
+        ))
+    }
+
+    fn compile_cache(
+        &self,
+        cache_def: CacheDef,
+        errors: &mut ConfigError,
+    ) -> Spanned<ConnectorsLeaf> {
+        let (data, ctx) = cache_def.into_parts();
+
+        let ttl: std::time::Duration = data.ttl.into();
+        if ttl.is_zero() {
+            errors.push_report(
+                ctx.err_ttl("Cache 'ttl' must be greater than zero"),
+                &ctx.ctx,
+            );
+        }
+
+        let max_body = data
+            .max_body
+            .map(|size| size.bytes())
+            .unwrap_or(CacheConfig::DEFAULT_MAX_BODY);
+
+        if max_body == 0 {
+            errors.push_report(
+                ctx.err_max_body("Cache 'max-body' must be greater than zero"),
+                &ctx.ctx,
+            );
+        }
+
+        let mut bypass_from = Vec::new();
+        for net in data.bypass_from.map(|def| def.networks).unwrap_or_default() {
+            match net.parse_as::<IpCidr>() {
+                Ok(net) => bypass_from.push(net),
+                Err(e) => errors.push_report(
+                    ctx.err_bypass_from(format!("Invalid 'bypass-from' network: {e}")),
+                    &ctx.ctx,
+                ),
             }
         }
+
+        Spanned::new(
+            ConnectorsLeaf::Cache(CacheConfig {
+                ttl,
+                max_body,
+                bypass_from,
+                debug_headers: data.debug_headers.unwrap_or(false),
+            }),
+            ctx.ctx,
+        )
     }
-}
-fn flatten_nodes(
-    nodes: Vec<Spanned<ConnectorsLeaf>>,
-    base_parent_chains: &[Modificator],
-    errors: &mut ConfigError,
-) -> Vec<UpstreamContextConfig> {
-    let mut results = Vec::new();
 
-    let mut block_chains = base_parent_chains.to_vec();
-    let mut block_lb_options: Option<Spanned<UpstreamOptions>> = None;
-    let mut block_elements = Vec::new();
+    fn compile_timeout(
+        &self,
+        timeout_def: TimeoutDef,
+        errors: &mut ConfigError,
+    ) -> Spanned<TimeoutConfig> {
+        let (data, ctx) = timeout_def.into_parts();
 
-    for node in nodes {
-        match &node.data {
-            ConnectorsLeaf::Modificator(m) => {
-                block_chains.push(m.clone());
-            }
-            ConnectorsLeaf::LoadBalance(lb) => {
-                if block_lb_options.is_some() {
+        let request = data.request.map(std::time::Duration::from);
+        let read_header = data.read_header.map(std::time::Duration::from);
+
+        if request.is_none() && read_header.is_none() {
+            errors.push_report(
+                ctx.err_self(
+                    "'timeout' needs 'request' or 'read-header', e.g. timeout request=\"30s\"",
+                ),
+                &ctx.ctx,
+            );
+        }
+        if request.is_some_and(|d| d.is_zero()) {
+            errors.push_report(
+                ctx.err_request("Timeout 'request' must be greater than zero"),
+                &ctx.ctx,
+            );
+        }
+        if read_header.is_some_and(|d| d.is_zero()) {
+            errors.push_report(
+                ctx.err_read_header("Timeout 'read-header' must be greater than zero"),
+                &ctx.ctx,
+            );
+        }
+
+        Spanned::new(
+            TimeoutConfig {
+                request,
+                read_header,
+            },
+            ctx.ctx,
+        )
+    }
+
+    fn compile_rewrite(
+        &self,
+        rewrite_def: RewriteDef,
+        errors: &mut ConfigError,
+        path: &PathAndQuery,
+        matcher: &RouteMatcher,
+    ) -> Option<Spanned<ConnectorsLeaf>> {
+        let (data, ctx) = rewrite_def.into_parts();
+
+        let rewrite = match (data.strip_prefix, data.pattern, data.to) {
+            (Some(true), None, None) => {
+                if let RouteMatcher::Regex(_) = matcher {
                     errors.push_report(
-                        node.err_node("Duplicate 'load-balance' directive"),
-                        &node.ctx,
+                        ctx.err_strip_prefix(
+                            "'strip-prefix' has no path to strip in a section with 'regex' routing mode, use 'pattern' and 'to'",
+                        ),
+                        &ctx.ctx,
                     );
+                    return None;
+                }
+                RewriteConfig::StripPrefix {
+                    prefix: path.path().trim_end_matches('/').to_string(),
                 }
-                block_lb_options = Some(Spanned::new(lb.clone(), node.ctx.clone()));
             }
+            (None | Some(false), Some(pattern), Some(to)) => match Regex::new(&pattern) {
+                Ok(regex) => RewriteConfig::Pattern {
+                    pattern: RewritePattern(regex),
+                    to,
+                },
+                Err(e) => {
+                    errors.push_report(
+                        ctx.err_pattern(format!("Invalid rewrite pattern: {e}")),
+                        &ctx.ctx,
+                    );
+                    return None;
+                }
+            },
             _ => {
-                block_elements.push(node);
+                errors.push_report(
+                    ctx.err_self(
+                        "'rewrite' needs either strip-prefix=#true, or 'pattern' and 'to', e.g. rewrite pattern=\"^/old/(.*)$\" to=\"/new/$1\"",
+                    ),
+                    &ctx.ctx,
+                );
+                return None;
             }
-        }
+        };
+
+        Some(Spanned::new(ConnectorsLeaf::Rewrite(rewrite), ctx.ctx))
     }
 
-    for node in block_elements {
-        match &node.data {
-            ConnectorsLeaf::Upstream(up) => {
-                if let Some(ref lb_span) = block_lb_options {
-                    if !matches!(up, UpstreamConfig::MultiServer(_)) {
-                        errors.push_report(
-                            lb_span.err_node("'load-balance' requires multiple servers"),
-                            &lb_span.ctx,
-                        );
-                    }
-                }
+    fn compile_mirror(
+        &self,
+        mirror_def: MirrorDef,
+        errors: &mut ConfigError,
+    ) -> Spanned<ConnectorsLeaf> {
+        let (data, ctx) = mirror_def.into_parts();
 
-                results.push(UpstreamContextConfig {
-                    upstream: up.clone(),
-                    chains: block_chains.clone(),
-                    lb_options: block_lb_options.as_ref().map(|s| s.data.clone()),
-                });
-            }
-            ConnectorsLeaf::Section(children) => {
-                let children_flat = flatten_nodes(children.clone(), base_parent_chains, errors);
-                results.extend(children_flat);
-            }
-            _ => unreachable!(),
+        let scheme_ok = matches!(data.url.scheme_str(), Some("http" | "https"));
+        let path_ok = data.url.path_and_query().is_none_or(|p| p.as_str() == "/");
+        if !scheme_ok || data.url.authority().is_none() || !path_ok {
+            errors.push_report(
+                ctx.err_url(
+                    "'mirror' takes a scheme and an address only, such as \"http://staging:8080\"",
+                ),
+                &ctx.ctx,
+            );
         }
-    }
 
-    results
+        let sample = data.sample.unwrap_or(1.0);
+        if !(0.0..=1.0).contains(&sample) {
+            errors.push_report(
+                ctx.err_sample("Mirror 'sample' must be between 0 and 1"),
+                &ctx.ctx,
+            );
+        }
+
+        Spanned::new(
+            ConnectorsLeaf::Mirror(MirrorConfig {
+                url: data.url,
+                sample,
+            }),
+            ctx.ctx,
+        )
+    }
+
+    fn compile_use_chain(
+        &self,
+        chain_def: UseChainDef,
+        errors: &mut ConfigError,
+        path: &PathAndQuery,
+    ) -> Option<Spanned<ConnectorsLeaf>> {
+        let (chain_data, ctx) = chain_def.into_parts();
+
+        let leaf = match chain_data {
+            UseChainDefData::Reference { name } => {
+                if let Some(chain) = self.table.get_chain_by_name(&name) {
+                    ConnectorsLeaf::Modificator(Modificator::Chain(NamedFilterChain {
+                        chain,
+                        name,
+                    }))
+                } else {
+                    errors.push_report(
+                        ctx.err_reference_name(format!(
+                            "Chain '{}' not found in definitions",
+                            name
+                        )),
+                        &ctx.ctx,
+                    );
+                    return None;
+                }
+            }
+            UseChainDefData::Inline { items } => {
+                let mut runtime_items = Vec::new();
+                for item in items {
+                    let (item_data, item_ctx) = item.into_parts();
+                    match item_data {
+                        ChainItemDefData::Filter(mut def) => {
+                            if self.production && is_staging_only(&def.name) {
+                                errors.push_report(
+                                    item_ctx.ctx.error(staging_only_message(&def.name)),
+                                    &item_ctx.ctx,
+                                );
+                                continue;
+                            }
+                            let when = DefinitionsCompiler::take_filter_when(
+                                &mut def.params,
+                                &item_ctx.ctx,
+                                errors,
+                            );
+                            let spans = def
+                                .params
+                                .iter()
+                                .map(|(k, v)| (k.clone(), v.span()))
+                                .collect::<BTreeMap<_, _>>();
+                            let args = def
+                                .params
+                                .into_iter()
+                                .map(|(k, v)| (k, v.value().into()))
+                                .collect::<BTreeMap<String, Value>>();
+                            if let Err(e) = check_builtin_args(&def.name, &args) {
+                                let report = match e.arg.as_ref().and_then(|arg| spans.get(arg)) {
+                                    Some(span) => item_ctx.ctx.error_with_span(e.message, *span),
+                                    None => item_ctx.ctx.error(e.message),
+                                };
+                                errors.push_report(report, &item_ctx.ctx);
+                                continue;
+                            }
+                            runtime_items.push(
+                                ChainItem::Filter(ConfiguredFilter {
+                                    args,
+                                    name: def.name,
+                                })
+                                .when(when),
+                            );
+                        }
+                        ChainItemDefData::RateLimit(def) => {
+                            let (rl_data, rl_ctx) = def.into_parts();
+                            match rl_data {
+                                RateLimitDefData::Reference(name, when) => {
+                                    let when = DefinitionsCompiler::compile_when(
+                                        when,
+                                        |msg| rl_ctx.err_reference_when(msg),
+                                        &rl_ctx.ctx,
+                                        errors,
+                                    );
+                                    if let Some(policy) = self.table.get_rate_limit(&name) {
+                                        runtime_items
+                                            .push(ChainItem::RateLimiter(policy).when(when));
+                                    } else {
+                                        errors.push_report(
+                                            rl_ctx.err_reference_ref(format!(
+                                                "Rate limit policy '{}' not found in definitions",
+                                                name
+                                            )),
+                                            &rl_ctx.ctx,
+                                        );
+                                    }
+                                }
+                                RateLimitDefData::Inline {
+                                    when,
+                                    expose_headers,
+                                    algorithm,
+                                    storage_key,
+                                    key_template,
+                                    transforms,
+                                    burst,
+                                    raw_rate,
+                                } => {
+                                    let key_template = key_template.into_inner();
+
+                                    let Some(algorithm) =
+                                        DefinitionsCompiler::compile_rate_limit_algorithm(
+                                            Some(&algorithm),
+                                            &storage_key,
+                                            self.table,
+                                            |msg| rl_ctx.err_inline_algorithm(msg),
+                                            &rl_ctx.ctx,
+                                            errors,
+                                        )
+                                    else {
+                                        continue;
+                                    };
+
+                                    let policy = RateLimitPolicy {
+                                        // The route path keeps the buckets of one route
+                                        // apart from those of the other routes of the service.
+                                        name: format!(
+                                            "__anon_rl_conn{}_{}",
+                                            path.path().replace('/', "_"),
+                                            runtime_items.len()
+                                        ),
+                                        algorithm,
+                                        scope: RateLimitScope::Service,
+                                        burst,
+                                        key_template: key_template.template,
+                                        rate_req_per_sec: raw_rate,
+                                        storage_key,
+                                        transforms: transforms
+                                            .map(|v| v.into())
+                                            .unwrap_or_default(),
+                                        max_keys: None,
+                                        expose_headers: expose_headers.unwrap_or(false),
+                                    };
+                                    if let Some(warning) = policy.unbounded_key_warning() {
+                                        errors.warn_report(
+                                            rl_ctx.err_inline_key_template(warning),
+                                            &rl_ctx.ctx,
+                                        );
+                                    }
+
+                                    let when = DefinitionsCompiler::compile_when(
+                                        when,
+                                        |msg| rl_ctx.err_inline_when(msg),
+                                        &rl_ctx.ctx,
+                                        errors,
+                                    );
+                                    runtime_items.push(ChainItem::RateLimiter(policy).when(when))
+                                }
+                            }
+                        }
+                        ChainItemDefData::BasicAuth(def) => {
+                            if let Some(auth) =
+                                DefinitionsCompiler::compile_basic_auth(def, self.table, errors)
+                            {
+                                runtime_items.push(auth);
+                            }
+                        }
+                        ChainItemDefData::Use(def) => {
+                            let (use_data, use_ctx) = def.into_parts();
+                            match self.table.get_chain_by_name(&use_data.name) {
+                                Some(chain) => runtime_items.extend(chain.items),
+                                None => errors.push_report(
+                                    use_ctx.err_name(format!(
+                                        "Chain '{}' not found in definitions",
+                                        use_data.name
+                                    )),
+                                    &use_ctx.ctx,
+                                ),
+                            }
+                        }
+                    }
+                }
+                let chain = FilterChain {
+                    items: runtime_items,
+                };
+                let id = self.anon_counter.fetch_add(1, Ordering::Relaxed);
+                let path_slug = path.path().replace('/', "_");
+                let generated_name = format!("__anon_{id}_{path_slug}");
+
+                ConnectorsLeaf::Modificator(Modificator::Chain(NamedFilterChain {
+                    chain,
+                    name: generated_name,
+                }))
+            }
+        };
+
+        Some(Spanned::new(leaf, ctx.ctx))
+    }
+
+    fn resolve_proto_settings(
+        &self,
+        proto: Option<&str>,
+        tls_sni: Option<&str>,
+        grpc: bool,
+    ) -> Result<(bool, String, ALPN), String> {
+        let alpn = match proto {
+            Some(p) => Some(parse_proto_value(p)?),
+            None => None,
+        };
+
+        // gRPC needs HTTP/2, in cleartext (h2c) unless a 'tls-sni' is given.
+        if grpc {
+            return match (alpn, tls_sni) {
+                (None | Some(ALPN::H2), sni) => {
+                    Ok((sni.is_some(), sni.unwrap_or_default().to_string(), ALPN::H2))
+                }
+                (Some(_), _) => Err("'grpc' needs 'proto=\"h2-only\"'".to_string()),
+            };
+        }
+
+        match (alpn, tls_sni) {
+            (None, None) | (Some(ALPN::H1), None) => Ok((false, String::new(), ALPN::H1)),
+            (None, Some(sni)) => Ok((true, sni.to_string(), ALPN::H2H1)),
+            (Some(_), None) => Err("'tls-sni' is required for HTTP2 support".to_string()),
+            (Some(p), Some(sni)) => Ok((true, sni.to_string(), p)),
+        }
+    }
+
+    fn compile_selection(
+        &self,
+        sel_def: SelectionDef,
+        errors: &mut ConfigError,
+    ) -> (SelectionKind, Option<BalancerConfig>) {
+        let (sel_data, sel_ctx) = sel_def.into_parts();
+
+        match sel_data {
+            SelectionDefData::Reference { kind, profile_ref } => {
+                if let Some(t) = self.table.get_key_templates().get(&profile_ref) {
+                    (kind, Some(t.clone()))
+                } else {
+                    errors.push_report(
+                        sel_ctx.err_reference_profile_ref(format!(
+                            "Key profile '{}' not found",
+                            profile_ref
+                        )),
+                        &sel_ctx.ctx,
+                    );
+                    (kind, None)
+                }
+            }
+            SelectionDefData::Simple { kind } => (kind, None),
+            SelectionDefData::Inline(alg_wrapper) => {
+                let (alg_data, _) = alg_wrapper.into_parts();
+
+                match alg_data {
+                    SelectionAlgDefData::None => (SelectionKind::RoundRobin, None),
+                    SelectionAlgDefData::Inline {
+                        kind,
+                        key,
+                        algorithm,
+                        transforms,
+                    } => {
+                        let (key_def, key_ctx) = key.into_parts();
+                        let source = key_def.template;
+                        let fallback = key_def.fallback;
+
+                        let (alg_def, alg_inner_ctx) = algorithm.into_parts();
+
+                        let runtime_alg_struct = HashAlgorithm {
+                            name: alg_def.name,
+                            seed: alg_def.seed,
+                        };
+
+                        let alg_op = match parse_hasher(&runtime_alg_struct) {
+                            Ok(op) => op,
+                            Err(e) => {
+                                errors.push_report(alg_inner_ctx.err_self(e), &alg_inner_ctx.ctx);
+                                HashOp::XxHash64(0)
+                            }
+                        };
+
+                        let mut transform_ops = Vec::new();
+                        if let Some(tr_def) = transforms {
+                            transform_ops = tr_def.into();
+                        }
+
+                        match kind {
+                            SelectionKind::KetamaHashing | SelectionKind::FvnHash => {
+                                let is_effectively_empty = source.parts.is_empty()
+                                    || (source.parts.len() == 1
+                                        && matches!(source.parts[0], KeyPart::Literal(ref s) if s.is_empty()));
+
+                                if is_effectively_empty {
+                                    errors.push_report(
+                                        key_ctx.err_self(format!(
+                                            "Selection kind '{:?}' requires a non-empty key source",
+                                            kind
+                                        )),
+                                        &key_ctx.ctx,
+                                    );
+                                }
+                            }
+                            _ => {}
+                        }
+
+                        let config = BalancerConfig {
+                            source,
+                            fallback,
+                            algorithm: alg_op,
+                            transforms: transform_ops,
+                        };
+
+                        (kind, Some(config))
+                    }
+                }
+            }
+        }
+    }
 }
+fn flatten_nodes(
+    nodes: Vec<Spanned<ConnectorsLeaf>>,
+    base_parent_chains: &[Modificator],
+    errors: &mut ConfigError,
+) -> Vec<UpstreamContextConfig> {
+    let mut results = Vec::new();
 
-fn parse_proto_value(value: &str) -> Result<ALPN, String> {
-    match value {
-        "h1-only" => Ok(ALPN::H1),
-        "h2-only" => Ok(ALPN::H2),
-        "h1-or-h2" | "h2-or-h1" => Ok(ALPN::H2H1),
-        other => Err(format!(
-            "'proto' should be 'h1-only', 'h2-only', or 'h2-or-h1', found '{other}'"
-        )),
+    let mut block_chains = base_parent_chains.to_vec();
+    let mut block_lb_options: Option<Spanned<UpstreamOptions>> = None;
+    let mut block_compression: Option<Spanned<CompressionConfig>> = None;
+    let mut block_sse: Option<Spanned<SseConfig>> = None;
+    let mut block_cache: Option<Spanned<CacheConfig>> = None;
+    let mut block_retry: Option<RetryConfig> = None;
+    let mut block_mirror: Option<Spanned<MirrorConfig>> = None;
+    let mut block_timeout: Option<TimeoutConfig> = None;
+    let mut block_rewrite: Option<Spanned<RewriteConfig>> = None;
+    let mut block_allow_upgrades = true;
+    let mut block_grpc = false;
+    let mut block_unresolved_group = false;
+    let mut block_upstream_tls: Option<UpstreamTlsConfig> = None;
+    let mut block_h2: Option<H2Config> = None;
+    let mut block_debug_trace = false;
+    let mut block_conditions = RouteConditions::default();
+    let mut block_elements = Vec::new();
+
+    for node in nodes {
+        match &node.data {
+            ConnectorsLeaf::Modificator(m) => {
+                block_chains.push(m.clone());
+            }
+            ConnectorsLeaf::LoadBalance(lb) => {
+                if block_lb_options.is_some() {
+                    errors.push_report(
+                        node.err_node("Duplicate 'load-balance' directive"),
+                        &node.ctx,
+                    );
+                }
+                block_lb_options = Some(Spanned::new(lb.clone(), node.ctx.clone()));
+            }
+            ConnectorsLeaf::Compression(compression) => {
+                block_compression = Some(Spanned::new(compression.clone(), node.ctx.clone()));
+            }
+            ConnectorsLeaf::Sse(sse) => {
+                block_sse = Some(Spanned::new(sse.clone(), node.ctx.clone()));
+            }
+            ConnectorsLeaf::Cache(cache) => {
+                block_cache = Some(Spanned::new(cache.clone(), node.ctx.clone()));
+            }
+            ConnectorsLeaf::Retry(retry) => {
+                block_retry = Some(retry.clone());
+            }
+            ConnectorsLeaf::Mirror(mirror) => {
+                block_mirror = Some(Spanned::new(mirror.clone(), node.ctx.clone()));
+            }
+            ConnectorsLeaf::Timeout(timeout) => {
+                block_timeout = Some(timeout.clone());
+            }
+            ConnectorsLeaf::Rewrite(rewrite) => {
+                block_rewrite = Some(Spanned::new(rewrite.clone(), node.ctx.clone()));
+            }
+            ConnectorsLeaf::AllowUpgrades(allow) => {
+                block_allow_upgrades = *allow;
+            }
+            ConnectorsLeaf::Grpc => {
+                block_grpc = true;
+            }
+            ConnectorsLeaf::UnresolvedGroup => {
+                block_unresolved_group = true;
+            }
+            ConnectorsLeaf::UpstreamTls(tls) => {
+                block_upstream_tls = Some(tls.clone());
+            }
+            ConnectorsLeaf::H2(h2) => {
+                block_h2 = Some(h2.clone());
+            }
+            ConnectorsLeaf::DebugTrace(trace) => {
+                block_debug_trace = *trace;
+            }
+            ConnectorsLeaf::Conditions(conditions) => {
+                block_conditions = conditions.clone();
+            }
+            _ => {
+                block_elements.push(node);
+            }
+        }
+    }
+
+    if let (Some(compression), Some(_)) = (&block_compression, &block_sse) {
+        errors.push_report(
+            compression.err_node(
+                "'compression' cannot be combined with 'sse': event streams must not be buffered",
+            ),
+            &compression.ctx,
+        );
+    }
+
+    if let (Some(cache), Some(_)) = (&block_cache, &block_sse) {
+        errors.push_report(
+            cache.err_node(
+                "'cache' cannot be combined with 'sse': event streams are never complete",
+            ),
+            &cache.ctx,
+        );
+    }
+
+    for node in block_elements {
+        match &node.data {
+            ConnectorsLeaf::Upstream(up) => {
+                if let (Some(mirror), UpstreamConfig::Static(_)) = (&block_mirror, up) {
+                    errors.push_report(
+                        mirror.err_node("'mirror' needs a 'proxy' to copy requests from"),
+                        &mirror.ctx,
+                    );
+                }
+
+                if let (Some(rewrite), UpstreamConfig::Static(_)) = (&block_rewrite, up) {
+                    errors.push_report(
+                        rewrite.err_node("'rewrite' needs a 'proxy' to send the path to"),
+                        &rewrite.ctx,
+                    );
+                }
+
+                if let Some(ref lb_span) = block_lb_options {
+                    if !matches!(up, UpstreamConfig::MultiServer(_)) {
+                        errors.push_report(
+                            lb_span.err_node("'load-balance' requires multiple servers"),
+                            &lb_span.ctx,
+                        );
+                    }
+                }
+
+                if let UpstreamConfig::MultiServer(multi) = up {
+                    let discovery = block_lb_options.as_ref().map(|lb| &lb.data.discovery);
+                    check_servers(multi, discovery, block_unresolved_group, &node, errors);
+                }
+
+                results.push(UpstreamContextConfig {
+                    upstream: up.clone(),
+                    chains: block_chains.clone(),
+                    lb_options: block_lb_options.as_ref().map(|s| s.data.clone()),
+                    compression: block_compression.as_ref().map(|s| s.data.clone()),
+                    sse: block_sse.as_ref().map(|s| s.data.clone()),
+                    cache: block_cache.as_ref().map(|s| s.data.clone()),
+                    retry: block_retry.clone(),
+                    mirror: block_mirror.as_ref().map(|s| s.data.clone()),
+                    timeout: block_timeout.clone(),
+                    rewrite: block_rewrite.as_ref().map(|s| s.data.clone()),
+                    allow_upgrades: block_allow_upgrades,
+                    grpc: block_grpc,
+                    upstream_tls: block_upstream_tls.clone(),
+                    h2: block_h2.clone(),
+                    debug_trace: block_debug_trace,
+                    conditions: block_conditions.clone(),
+                });
+            }
+            ConnectorsLeaf::Section(children) => {
+                let children_flat = flatten_nodes(children.clone(), base_parent_chains, errors);
+                results.extend(children_flat);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    results
+}
+
+/// Checks the servers of a multi-server `proxy` against the discovery of its
+/// `load-balance`, which decides where the backends come from. The servers of
+/// an `unresolved_group` are missing for a reason that is already reported.
+fn check_servers(
+    multi: &MultiServerUpstreamConfig,
+    discovery: Option<&DiscoveryKind>,
+    unresolved_group: bool,
+    node: &Spanned<ConnectorsLeaf>,
+    errors: &mut ConfigError,
+) {
+    match discovery {
+        Some(DiscoveryKind::Kubernetes(_)) => {
+            if !multi.servers.is_empty() {
+                errors.push_report(
+                    node.err_node(
+                        "'discovery \"Kubernetes\"' takes the servers from the cluster, remove the 'server' entries",
+                    ),
+                    &node.ctx,
+                );
+            }
+        }
+        _ if unresolved_group => {}
+        _ if multi.servers.is_empty() => {
+            errors.push_report(
+                node.err_node("'proxy' needs a URL, 'use-group' or a 'server'"),
+                &node.ctx,
+            );
+        }
+        Some(DiscoveryKind::Dns { .. }) => {}
+        _ => {
+            if let Some(server) = multi
+                .servers
+                .iter()
+                .find(|s| s.address.as_socket().is_none())
+            {
+                errors.push_report(
+                    node.err_node(format!(
+                        "Server '{}' is a hostname, which needs 'discovery \"Dns\"' in 'load-balance'",
+                        server.address
+                    )),
+                    &node.ctx,
+                );
+            }
+        }
+    }
+}
+
+/// The entries of a comma separated list, without the blank ones.
+fn comma_list(raw: &str) -> Vec<&str> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+fn parse_proto_value(value: &str) -> Result<ALPN, String> {
+    match value {
+        "h1-only" => Ok(ALPN::H1),
+        "h2-only" => Ok(ALPN::H2),
+        "h1-or-h2" | "h2-or-h1" => Ok(ALPN::H2H1),
+        other => Err(format!(
+            "'proto' should be 'h1-only', 'h2-only', or 'h2-or-h1', found '{other}'"
+        )),
+    }
+}
+
+/// The pattern of a `regex` section nested in `base_path`. The paths of the
+/// enclosing sections are matched literally.
+fn regex_route(base_path: &PathAndQuery, pattern: &str) -> String {
+    let base = regex::escape(base_path.path().trim_end_matches('/'));
+    format!("{base}/{}", pattern.trim_start_matches('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, time::Duration};
+
+    use crate::{
+        common_types::{
+            balancer::{
+                DiscoveryKind, KubernetesDiscoveryConfig, OutlierDetectionConfig, SelectionKind,
+            },
+            connectors::{
+                CacheConfig, ClientCertConfig, H2Config, RetryConfig, RetryOn, RewriteConfig,
+                RouteMatcher, ServerAddress, UpstreamConfig, ALPN,
+            },
+        },
+        loader::test_support::{load, load_errors, DELAY_SERVICES},
+    };
+
+    #[tokio::test]
+    async fn test_delay_filter_allowed_outside_production() {
+        let config = load(vec![("main.kdl", DELAY_SERVICES)]).await;
+
+        assert!(!config.production);
+    }
+
+    #[tokio::test]
+    async fn test_delay_filter_rejected_in_production() {
+        let errors = load_errors(vec![
+            ("system.kdl", "system { production #true; }"),
+            ("main.kdl", DELAY_SERVICES),
+        ])
+        .await;
+        assert_eq!(errors.count, 1);
+        assert!(errors.errors[0].message.contains("is for staging only"));
+    }
+
+    #[tokio::test]
+    async fn test_regex_sections() {
+        let services = r#"
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        section "/svc.v1" as="prefix" {
+                            proxy "http://127.0.0.1:3000"
+                            section "/items/[0-9]+" as="regex" {
+                                proxy "http://127.0.0.1:3001"
+                            }
+                        }
+                    }
+                }
+            }
+        "#;
+        let config = load(vec![("main.kdl", services)]).await;
+
+        let patterns: Vec<_> = config.basic_proxies[0]
+            .connectors
+            .upstreams
+            .iter()
+            .filter_map(|u| match &u.upstream {
+                UpstreamConfig::Service(peer) => match &peer.matcher {
+                    RouteMatcher::Regex(pattern) => Some(pattern.clone()),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(patterns.len(), 1);
+        assert_eq!(patterns[0].as_str(), r"/svc\.v1/items/[0-9]+");
+        assert!(patterns[0].is_match("/svc.v1/items/42"));
+        assert!(!patterns[0].is_match("/svcXv1/items/42"));
+        assert!(!patterns[0].is_match("/svc.v1/items/42/details"));
+    }
+
+    #[tokio::test]
+    async fn test_regex_section_invalid_pattern() {
+        let services = r#"
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        section "/api/v[0-9" as="regex" {
+                            proxy "http://127.0.0.1:3000"
+                        }
+                    }
+                }
+            }
+        "#;
+        let errors = load_errors(vec![("main.kdl", services)]).await;
+        let error = errors
+            .errors
+            .iter()
+            .find(|e| e.message.contains("Invalid route pattern"))
+            .expect("Should report the pattern");
+
+        let span = error.label.expect("Error should point at the path");
+        let labeled = &services[span.offset()..span.offset() + span.len()];
+        assert!(labeled.contains("/api/v[0-9"), "labeled: {labeled:?}");
+    }
+
+    #[tokio::test]
+    async fn test_server_weight_must_be_positive() {
+        let services = r#"
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        section "/" {
+                            proxy {
+                                server "10.0.0.1:8080" weight=0
+                                server "10.0.0.2:8080" weight=2
+                            }
+                        }
+                    }
+                }
+            }
+        "#;
+        let errors = load_errors(vec![("main.kdl", services)]).await;
+        assert_eq!(errors.count, 1);
+
+        let error = &errors.errors[0];
+        assert!(error.message.contains("'weight' must be at least 1"));
+
+        let span = error.label.expect("Error should point at the weight");
+        let labeled = &services[span.offset()..span.offset() + span.len()];
+        assert!(labeled.contains("weight=0"), "labeled: {labeled:?}");
+        assert!(!labeled.contains("10.0.0.1"), "labeled: {labeled:?}");
+    }
+
+    #[tokio::test]
+    async fn test_cache_section() {
+        let services = r#"
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        section "/catalog" {
+                            cache ttl="30s" max-body="64kb" debug-headers=#true {
+                                bypass-from "10.0.0.0/8" "::1/128"
+                            }
+                            proxy "http://127.0.0.1:3000"
+                        }
+                        section "/live" {
+                            proxy "http://127.0.0.1:3001"
+                        }
+                    }
+                }
+            }
+        "#;
+        let config = load(vec![("main.kdl", services)]).await;
+
+        let upstreams = &config.basic_proxies[0].connectors.upstreams;
+        assert_eq!(
+            upstreams[0].cache,
+            Some(CacheConfig {
+                ttl: Duration::from_secs(30),
+                max_body: 64 * 1024,
+                bypass_from: vec!["10.0.0.0/8".parse().unwrap(), "::1/128".parse().unwrap()],
+                debug_headers: true,
+            })
+        );
+        assert_eq!(upstreams[1].cache, None);
+    }
+
+    #[tokio::test]
+    async fn test_cache_bypass_from_must_be_networks() {
+        let services = r#"
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        section "/" {
+                            cache ttl="30s" {
+                                bypass-from "10.0.0.0/8" "office"
+                            }
+                            proxy "http://127.0.0.1:3000"
+                        }
+                    }
+                }
+            }
+        "#;
+        let errors = load_errors(vec![("main.kdl", services)]).await;
+        let messages: Vec<_> = errors.errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages.len(), 1, "{messages:?}");
+        assert!(messages[0].contains("Invalid 'bypass-from' network"));
+    }
+
+    #[tokio::test]
+    async fn test_compression() {
+        use crate::common_types::compression::CompressionAlgorithm;
+
+        let services = r#"
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        compression algorithms="br,gzip" min-size="1kb" content-types="text/*,application/json"
+                        section "/api" {
+                            proxy "http://127.0.0.1:3000"
+                        }
+                        section "/assets" {
+                            compression level=9
+                            proxy "http://127.0.0.1:3001"
+                        }
+                        section "/events" {
+                            sse #true
+                            proxy "http://127.0.0.1:3002"
+                        }
+                    }
+                }
+            }
+        "#;
+        let config = load(vec![("main.kdl", services)]).await;
+
+        let upstreams = &config.basic_proxies[0].connectors.upstreams;
+        let api = upstreams[0].compression.as_ref().unwrap();
+        assert_eq!(
+            api.algorithms,
+            [CompressionAlgorithm::Brotli, CompressionAlgorithm::Gzip]
+        );
+        assert_eq!(api.min_size, 1024);
+        assert_eq!(api.content_types.len(), 2);
+
+        // A section's own directive replaces the service-wide one.
+        let assets = upstreams[1].compression.as_ref().unwrap();
+        assert_eq!(assets.level, 9);
+        assert_eq!(assets.algorithms, CompressionAlgorithm::ALL);
+        assert_eq!(assets.min_size, 0);
+
+        assert_eq!(upstreams[2].compression, None);
+    }
+
+    #[tokio::test]
+    async fn test_compression_errors() {
+        let services = r#"
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        section "/api" {
+                            compression algorithms="br,deflate" content-types="html"
+                            proxy "http://127.0.0.1:3000"
+                        }
+                        section "/assets" {
+                            compression algorithms=" , "
+                            proxy "http://127.0.0.1:3001"
+                        }
+                    }
+                }
+            }
+        "#;
+        let errors = load_errors(vec![("main.kdl", services)]).await;
+        let messages: Vec<_> = errors.errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages.len(), 3, "{messages:?}");
+        assert!(messages[0].contains("Unknown compression algorithm 'deflate'"));
+        assert!(messages[1].contains("'type/subtype'"));
+        assert!(messages[2].contains("'algorithms' lists no compression algorithms"));
+    }
+
+    #[tokio::test]
+    async fn test_section_allow_upgrades() {
+        let services = r#"
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        section "/ws" {
+                            proxy "http://127.0.0.1:3000"
+                        }
+                        section "/download" allow-upgrades=#false {
+                            proxy "http://127.0.0.1:3001"
+                        }
+                    }
+                }
+            }
+        "#;
+        let config = load(vec![("main.kdl", services)]).await;
+
+        let upstreams = &config.basic_proxies[0].connectors.upstreams;
+        assert!(upstreams[0].allow_upgrades);
+        assert!(!upstreams[1].allow_upgrades);
+    }
+
+    #[tokio::test]
+    async fn test_section_debug_trace() {
+        let services = r#"
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        section "/api" debug-trace=#true {
+                            proxy "http://127.0.0.1:3000"
+                            section "/v2" {
+                                proxy "http://127.0.0.1:3002"
+                            }
+                        }
+                        section "/" {
+                            proxy "http://127.0.0.1:3001"
+                        }
+                    }
+                }
+            }
+        "#;
+        let config = load(vec![("main.kdl", services)]).await;
+
+        let traced: Vec<_> = config.basic_proxies[0]
+            .connectors
+            .upstreams
+            .iter()
+            .map(|upstream| upstream.debug_trace)
+            .collect();
+        assert_eq!(traced, vec![true, false, false]);
+    }
+
+    #[tokio::test]
+    async fn test_section_conditions() {
+        let services = r#"
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        section "/api" method="post,PUT" query="version=2&beta" {
+                            proxy "http://127.0.0.1:3000"
+                            section "/v2" {
+                                proxy "http://127.0.0.1:3002"
+                            }
+                        }
+                        section "/" {
+                            proxy "http://127.0.0.1:3001"
+                        }
+                    }
+                }
+            }
+        "#;
+        let config = load(vec![("main.kdl", services)]).await;
+
+        let conditions: Vec<_> = config.basic_proxies[0]
+            .connectors
+            .upstreams
+            .iter()
+            .map(|upstream| upstream.conditions.to_string())
+            .collect();
+        assert_eq!(
+            conditions,
+            vec!["method=POST,PUT query=version=2&beta", "", ""]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_section_conditions_errors() {
+        let services = r#"
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        section "/api" method="GET," {
+                            proxy "http://127.0.0.1:3000"
+                        }
+                        section "/" query="=1" {
+                            proxy "http://127.0.0.1:3001"
+                        }
+                    }
+                }
+            }
+        "#;
+        let errors = load_errors(vec![("main.kdl", services)]).await;
+        let messages: Vec<_> = errors.errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages.len(), 2, "{messages:?}");
+        assert!(messages[0].contains("Empty method in 'GET,'"));
+        assert!(messages[1].contains("Query condition '=1' has no parameter name"));
+    }
+
+    #[tokio::test]
+    async fn test_section_rewrite() {
+        let services = r#"
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        section "/api/" as="prefix" {
+                            rewrite strip-prefix=#true
+                            proxy "http://127.0.0.1:3000"
+                        }
+                        section "/old" as="prefix" {
+                            rewrite pattern="^/old/(.*)$" to="/new/$1"
+                            proxy "http://127.0.0.1:3001"
+                        }
+                    }
+                }
+            }
+        "#;
+        let config = load(vec![("main.kdl", services)]).await;
+
+        let upstreams = &config.basic_proxies[0].connectors.upstreams;
+        assert_eq!(
+            upstreams[0].rewrite,
+            Some(RewriteConfig::StripPrefix {
+                prefix: "/api".to_string()
+            })
+        );
+        match &upstreams[1].rewrite {
+            Some(RewriteConfig::Pattern { pattern, to }) => {
+                assert_eq!(pattern.0.as_str(), "^/old/(.*)$");
+                assert_eq!(to, "/new/$1");
+            }
+            other => panic!("Expected a pattern rewrite, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_section_rewrite_errors() {
+        let services = r#"
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        section "/api" as="prefix" {
+                            rewrite strip-prefix=#true pattern="^/api" to="/"
+                            proxy "http://127.0.0.1:3000/v2"
+                        }
+                        section "/files/[a-z]+" as="regex" {
+                            rewrite strip-prefix=#true
+                            proxy "http://127.0.0.1:3001"
+                        }
+                        section "/old" {
+                            rewrite pattern="^/old/(" to="/new"
+                            proxy "http://127.0.0.1:3002"
+                        }
+                        section "/health" {
+                            rewrite strip-prefix=#true
+                            return 200
+                        }
+                    }
+                }
+            }
+        "#;
+        let errors = load_errors(vec![("main.kdl", services)]).await;
+        let messages: Vec<_> = errors.errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages.len(), 5, "{messages:?}");
+        assert!(messages[0].contains("'rewrite' needs either strip-prefix=#true"));
+        assert!(messages[1].contains("The path of a 'proxy' URL is not sent upstream"));
+        assert!(messages[2].contains("'strip-prefix' has no path to strip"));
+        assert!(messages[3].contains("Invalid rewrite pattern"));
+        assert!(messages[4].contains("'rewrite' needs a 'proxy'"));
+    }
+
+    #[tokio::test]
+    async fn test_section_mirror() {
+        let services = r#"
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        section "/api" {
+                            mirror "http://127.0.0.1:4000" sample=0.25
+                            proxy "http://127.0.0.1:3000"
+                        }
+                        section "/orders" {
+                            mirror "https://staging.internal:8443"
+                            proxy "http://127.0.0.1:3001"
+                        }
+                    }
+                }
+            }
+        "#;
+        let config = load(vec![("main.kdl", services)]).await;
+
+        let upstreams = &config.basic_proxies[0].connectors.upstreams;
+        let api = upstreams[0].mirror.as_ref().unwrap();
+        assert_eq!(api.url, "http://127.0.0.1:4000");
+        assert_eq!(api.sample, 0.25);
+        assert_eq!(upstreams[1].mirror.as_ref().unwrap().sample, 1.0);
+    }
+
+    #[tokio::test]
+    async fn test_section_mirror_errors() {
+        let services = r#"
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        section "/api" {
+                            mirror "http://127.0.0.1:4000/shadow" sample=1.5
+                            proxy "http://127.0.0.1:3000"
+                        }
+                        section "/health" {
+                            mirror "http://127.0.0.1:4000"
+                            return 200
+                        }
+                    }
+                }
+            }
+        "#;
+        let errors = load_errors(vec![("main.kdl", services)]).await;
+        let messages: Vec<_> = errors.errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages.len(), 3, "{messages:?}");
+        assert!(messages[0].contains("'mirror' takes a scheme and an address only"));
+        assert!(messages[1].contains("'sample' must be between 0 and 1"));
+        assert!(messages[2].contains("'mirror' needs a 'proxy'"));
+    }
+
+    #[tokio::test]
+    async fn test_section_timeout() {
+        let services = r#"
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        timeout request="30s"
+                        section "/api" {
+                            timeout request="5s" read-header="2s"
+                            proxy "http://127.0.0.1:3000"
+                        }
+                        section "/orders" {
+                            proxy "http://127.0.0.1:3001"
+                        }
+                    }
+                }
+            }
+        "#;
+        let config = load(vec![("main.kdl", services)]).await;
+
+        let upstreams = &config.basic_proxies[0].connectors.upstreams;
+        let api = upstreams[0].timeout.as_ref().unwrap();
+        assert_eq!(api.request, Some(Duration::from_secs(5)));
+        assert_eq!(api.read_header, Some(Duration::from_secs(2)));
+
+        let orders = upstreams[1].timeout.as_ref().unwrap();
+        assert_eq!(orders.request, Some(Duration::from_secs(30)));
+        assert_eq!(orders.read_header, None);
+    }
+
+    #[tokio::test]
+    async fn test_section_timeout_errors() {
+        let services = r#"
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        section "/api" {
+                            timeout
+                            proxy "http://127.0.0.1:3000"
+                        }
+                        section "/orders" {
+                            timeout request="0s"
+                            proxy "http://127.0.0.1:3001"
+                        }
+                    }
+                }
+            }
+        "#;
+        let errors = load_errors(vec![("main.kdl", services)]).await;
+        let messages: Vec<_> = errors.errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages.len(), 2, "{messages:?}");
+        assert!(messages[0].contains("'timeout' needs 'request' or 'read-header'"));
+        assert!(messages[1].contains("'request' must be greater than zero"));
+    }
+
+    #[tokio::test]
+    async fn test_cache_cannot_be_combined_with_sse() {
+        let services = r#"
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        section "/events" {
+                            sse #true
+                            cache ttl="30s"
+                            proxy "http://127.0.0.1:3000"
+                        }
+                    }
+                }
+            }
+        "#;
+        let errors = load_errors(vec![("main.kdl", services)]).await;
+        let error = errors
+            .errors
+            .iter()
+            .find(|e| e.message.contains("'cache' cannot be combined with 'sse'"))
+            .expect("Should report the conflict");
+
+        let span = error.label.expect("Error should point at the cache node");
+        let labeled = &services[span.offset()..span.offset() + span.len()];
+        assert!(labeled.starts_with("cache"), "labeled: {labeled:?}");
+    }
+
+    #[tokio::test]
+    async fn test_proxy_retry() {
+        let services = r#"
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        section "/api" {
+                            load-balance { selection "RoundRobin" }
+                            proxy {
+                                server "10.0.0.1:8080"
+                                server "10.0.0.2:8080"
+                                retry attempts=2 on="5xx,connect-failure" backoff="50ms"
+                            }
+                        }
+                        section "/single" {
+                            proxy "http://127.0.0.1:3000" { retry attempts=1; }
+                        }
+                        section "/plain" {
+                            proxy "http://127.0.0.1:3001"
+                        }
+                    }
+                }
+            }
+        "#;
+        let config = load(vec![("main.kdl", services)]).await;
+
+        let upstreams = &config.basic_proxies[0].connectors.upstreams;
+        assert_eq!(
+            upstreams[0].retry,
+            Some(RetryConfig {
+                attempts: 2,
+                on: RetryOn {
+                    connect_failure: true,
+                    error: false,
+                    server_error: true,
+                },
+                backoff: Duration::from_millis(50),
+                max_concurrent: RetryConfig::DEFAULT_MAX_CONCURRENT,
+            })
+        );
+
+        let single = upstreams[1].retry.as_ref().expect("Should have a retry");
+        assert_eq!(single.attempts, 1);
+        assert!(single.on.connect_failure && !single.on.server_error);
+        assert!(matches!(upstreams[1].upstream, UpstreamConfig::Service(_)));
+
+        assert_eq!(upstreams[2].retry, None);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_retry_errors() {
+        let services = r#"
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        section "/a" {
+                            proxy "http://127.0.0.1:3000" { retry attempts=1 on="4xx"; }
+                        }
+                        section "/b" {
+                            proxy "http://127.0.0.1:3000" { retry attempts=50; }
+                        }
+                    }
+                }
+            }
+        "#;
+        let errors = load_errors(vec![("main.kdl", services)]).await;
+
+        let error = errors
+            .errors
+            .iter()
+            .find(|e| e.message.contains("Unknown retry condition '4xx'"))
+            .expect("Should reject the condition");
+        let span = error.label.expect("Error should point at 'on'");
+        let labeled = &services[span.offset()..span.offset() + span.len()];
+        assert!(labeled.contains("4xx"), "labeled: {labeled:?}");
+
+        assert!(errors
+            .errors
+            .iter()
+            .any(|e| e.message.contains("at most 10")));
+    }
+
+    #[tokio::test]
+    async fn test_proxy_grpc() {
+        let services = r#"
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        section "/greeter.Greeter" as="prefix" {
+                            proxy "http://127.0.0.1:50051" grpc=#true
+                        }
+                        section "/secure.Service" as="prefix" {
+                            proxy tls-sni="grpc.internal" proto="h2-only" grpc=#true {
+                                server "10.0.0.1:443"
+                            }
+                        }
+                        section "/rest" {
+                            proxy "http://127.0.0.1:3000"
+                        }
+                    }
+                }
+            }
+        "#;
+        let config = load(vec![("main.kdl", services)]).await;
+
+        let upstreams = &config.basic_proxies[0].connectors.upstreams;
+        assert!(upstreams[0].grpc);
+        let UpstreamConfig::Service(peer) = &upstreams[0].upstream else {
+            panic!("Expected a single upstream");
+        };
+        // Cleartext HTTP/2 without a 'tls-sni'.
+        assert_eq!((peer.tls, peer.alpn.clone()), (false, ALPN::H2));
+
+        assert!(upstreams[1].grpc);
+        let UpstreamConfig::MultiServer(multi) = &upstreams[1].upstream else {
+            panic!("Expected a multi-server upstream");
+        };
+        assert_eq!(multi.tls_sni.as_deref(), Some("grpc.internal"));
+        assert_eq!(multi.alpn, ALPN::H2);
+
+        assert!(!upstreams[2].grpc);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_grpc_needs_h2() {
+        let services = r#"
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        section "/greeter.Greeter" as="prefix" {
+                            proxy "http://127.0.0.1:50051" tls-sni="grpc.internal" proto="h2-or-h1" grpc=#true
+                        }
+                    }
+                }
+            }
+        "#;
+        let errors = load_errors(vec![("main.kdl", services)]).await;
+        assert_eq!(errors.count, 1, "{:?}", errors.errors);
+        assert!(errors.errors[0]
+            .message
+            .contains("'grpc' needs 'proto=\"h2-only\"'"));
+    }
+
+    #[tokio::test]
+    async fn test_proxy_tls() {
+        let services = r#"
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        section "/internal" {
+                            proxy "http://10.0.0.5:8443" tls-sni="internal.example.com" {
+                                tls ca-path="../motya/assets/test.crt" \
+                                    client-cert="../motya/assets/test.crt" \
+                                    client-key="../motya/assets/test.key" \
+                                    cert-sha256="AB:CD:EF:01:23:45:67:89:ab:cd:ef:01:23:45:67:89:ab:cd:ef:01:23:45:67:89:ab:cd:ef:01:23:45:67:89"
+                            }
+                        }
+                        section "/staging" {
+                            proxy tls-sni="staging.internal" {
+                                server "10.0.0.6:443"
+                                tls verify-cert=#false
+                            }
+                        }
+                        section "/public" {
+                            proxy "http://127.0.0.1:3000"
+                        }
+                    }
+                }
+            }
+        "#;
+        let config = load(vec![("main.kdl", services)]).await;
+
+        let upstreams = &config.basic_proxies[0].connectors.upstreams;
+        let internal = upstreams[0].upstream_tls.as_ref().unwrap();
+        assert!(internal.verify_cert);
+        assert_eq!(
+            internal.ca_path,
+            Some(PathBuf::from("../motya/assets/test.crt"))
+        );
+        assert_eq!(
+            internal.client_cert,
+            Some(ClientCertConfig {
+                cert_path: PathBuf::from("../motya/assets/test.crt"),
+                key_path: PathBuf::from("../motya/assets/test.key"),
+            })
+        );
+        assert_eq!(internal.cert_sha256.len(), 1);
+        assert_eq!(internal.cert_sha256[0][..4], [0xab, 0xcd, 0xef, 0x01]);
+
+        let staging = upstreams[1].upstream_tls.as_ref().unwrap();
+        assert!(!staging.verify_cert);
+        assert_eq!(staging.ca_path, None);
+
+        assert_eq!(upstreams[2].upstream_tls, None);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_tls_errors() {
+        let services = r#"
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        section "/plain" {
+                            proxy "http://127.0.0.1:3000" { tls verify-cert=#false; }
+                        }
+                        section "/half" {
+                            proxy "http://127.0.0.1:3001" tls-sni="half.internal" {
+                                tls client-cert="../motya/assets/test.crt"
+                            }
+                        }
+                        section "/missing" {
+                            proxy "http://127.0.0.1:3002" tls-sni="missing.internal" {
+                                tls ca-path="../motya/assets/missing.crt"
+                            }
+                        }
+                        section "/unverified" {
+                            proxy "http://127.0.0.1:3003" tls-sni="unverified.internal" {
+                                tls verify-cert=#false ca-path="../motya/assets/test.crt"
+                            }
+                        }
+                    }
+                }
+            }
+        "#;
+        let errors = load_errors(vec![("main.kdl", services)]).await;
+        let messages: Vec<_> = errors.errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages.len(), 4, "{messages:?}");
+        assert!(messages[0].contains("'tls' needs a TLS upstream"));
+        assert!(messages[1].contains("'client-key' is missing"));
+        assert!(messages[2].contains("Cannot read the 'ca-path' file ../motya/assets/missing"));
+        assert!(messages[3].contains("'ca-path' has no use with verify-cert=#false"));
+    }
+
+    #[tokio::test]
+    async fn test_proxy_h2() {
+        let services = r#"
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        section "/greeter.Greeter" as="prefix" {
+                            proxy "http://127.0.0.1:50051" grpc=#true {
+                                h2 max-streams=100 ping-interval="30s" idle-timeout="90s"
+                            }
+                        }
+                        section "/api" {
+                            proxy tls-sni="api.internal" {
+                                server "10.0.0.1:443"
+                                h2 max-streams=20
+                            }
+                        }
+                    }
+                }
+            }
+        "#;
+        let config = load(vec![("main.kdl", services)]).await;
+
+        let upstreams = &config.basic_proxies[0].connectors.upstreams;
+        assert_eq!(
+            upstreams[0].h2,
+            Some(H2Config {
+                max_streams: Some(100),
+                ping_interval: Some(Duration::from_secs(30)),
+                idle_timeout: Some(Duration::from_secs(90)),
+            })
+        );
+        assert_eq!(
+            upstreams[1].h2,
+            Some(H2Config {
+                max_streams: Some(20),
+                ..Default::default()
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_proxy_h2_errors() {
+        let services = r#"
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        section "/plain" {
+                            proxy "http://127.0.0.1:3000" { h2 max-streams=10; }
+                        }
+                        section "/empty" {
+                            proxy "http://127.0.0.1:50051" grpc=#true { h2; }
+                        }
+                        section "/zero" {
+                            proxy "http://127.0.0.1:50052" grpc=#true { h2 max-streams=0; }
+                        }
+                    }
+                }
+            }
+        "#;
+        let errors = load_errors(vec![("main.kdl", services)]).await;
+        let messages: Vec<_> = errors.errors.iter().map(|e| e.message.as_str()).collect();
+        for expected in [
+            "'h2' needs an HTTP/2 upstream",
+            "'h2' needs at least one of",
+            "Value must be at least 1",
+        ] {
+            assert!(
+                messages.iter().any(|m| m.contains(expected)),
+                "{expected:?} not in {messages:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dns_discovery() {
+        let services = r#"
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        section "/api" {
+                            load-balance {
+                                selection "RoundRobin"
+                                discovery "Dns" refresh="10s"
+                            }
+                            proxy {
+                                server "API.internal:8080" weight=2
+                                server "10.0.0.1:8080"
+                            }
+                        }
+                        section "/static" {
+                            load-balance { discovery "Static"; }
+                            proxy {
+                                server "10.0.0.2:8080"
+                            }
+                        }
+                    }
+                }
+            }
+        "#;
+        let config = load(vec![("main.kdl", services)]).await;
+
+        let upstreams = &config.basic_proxies[0].connectors.upstreams;
+        let lb = upstreams[0].lb_options.as_ref().expect("Should balance");
+        assert_eq!(
+            lb.discovery,
+            DiscoveryKind::Dns {
+                refresh: Duration::from_secs(10)
+            }
+        );
+
+        let UpstreamConfig::MultiServer(multi) = &upstreams[0].upstream else {
+            panic!("Expected a multi-server upstream");
+        };
+        assert_eq!(
+            multi.servers[0].address,
+            ServerAddress::Host {
+                host: "api.internal".to_string(),
+                port: 8080
+            }
+        );
+        assert_eq!(
+            multi.servers[1].address,
+            ServerAddress::Socket("10.0.0.1:8080".parse().unwrap())
+        );
+
+        let lb = upstreams[1].lb_options.as_ref().expect("Should balance");
+        assert_eq!(lb.discovery, DiscoveryKind::Static);
+    }
+
+    #[tokio::test]
+    async fn test_kubernetes_discovery() {
+        let services = r#"
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        section "/api" {
+                            load-balance {
+                                discovery "Kubernetes" service="api" namespace="prod" port="http"
+                            }
+                            proxy
+                        }
+                        section "/web" {
+                            load-balance { discovery "Kubernetes" service="web"; }
+                            proxy tls-sni="web.example.com" {}
+                        }
+                    }
+                }
+            }
+        "#;
+        let config = load(vec![("main.kdl", services)]).await;
+
+        let upstreams = &config.basic_proxies[0].connectors.upstreams;
+        let lb = upstreams[0].lb_options.as_ref().expect("Should balance");
+        assert_eq!(
+            lb.discovery,
+            DiscoveryKind::Kubernetes(KubernetesDiscoveryConfig {
+                service: "api".to_string(),
+                namespace: Some("prod".to_string()),
+                port: Some("http".to_string()),
+            })
+        );
+        assert!(matches!(
+            &upstreams[0].upstream,
+            UpstreamConfig::MultiServer(multi) if multi.servers.is_empty()
+        ));
+
+        let lb = upstreams[1].lb_options.as_ref().expect("Should balance");
+        assert!(matches!(
+            &lb.discovery,
+            DiscoveryKind::Kubernetes(k8s) if k8s.service == "web" && k8s.namespace.is_none()
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_load_based_selection() {
+        let services = r#"
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        section "/api" {
+                            load-balance { selection "LeastConnections"; }
+                            proxy {
+                                server "10.0.0.1:8080" weight=2
+                                server "10.0.0.2:8080"
+                            }
+                        }
+                        section "/search" {
+                            load-balance { selection "PeakEWMA"; }
+                            proxy {
+                                server "10.0.0.3:8080"
+                                server "10.0.0.4:8080"
+                            }
+                        }
+                    }
+                }
+            }
+        "#;
+        let config = load(vec![("main.kdl", services)]).await;
+
+        let upstreams = &config.basic_proxies[0].connectors.upstreams;
+        let selections: Vec<_> = upstreams
+            .iter()
+            .map(|upstream| &upstream.lb_options.as_ref().unwrap().selection)
+            .collect();
+        assert_eq!(
+            selections,
+            vec![&SelectionKind::LeastConnections, &SelectionKind::PeakEwma]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_slow_start() {
+        let services = r#"
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        section "/api" {
+                            load-balance {
+                                selection "LeastConnections"
+                                slow-start "90s"
+                            }
+                            proxy {
+                                server "10.0.0.1:8080"
+                                server "10.0.0.2:8080"
+                            }
+                        }
+                        section "/zero" {
+                            load-balance { slow-start "0s"; }
+                            proxy {
+                                server "10.0.0.3:8080"
+                            }
+                        }
+                    }
+                }
+            }
+        "#;
+        let errors = load_errors(vec![("main.kdl", services)]).await;
+        assert_eq!(errors.errors.len(), 1, "{errors:?}");
+        assert!(errors.errors[0]
+            .message
+            .contains("'slow-start' must be greater than zero"));
+
+        let config = load(vec![(
+            "main.kdl",
+            r#"
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        section "/api" {
+                            load-balance { slow-start "90s"; }
+                            proxy {
+                                server "10.0.0.1:8080"
+                            }
+                        }
+                    }
+                }
+            }
+            "#,
+        )])
+        .await;
+
+        let lb = config.basic_proxies[0].connectors.upstreams[0]
+            .lb_options
+            .as_ref()
+            .expect("Should balance");
+        assert_eq!(lb.slow_start, Some(Duration::from_secs(90)));
+    }
+
+    #[tokio::test]
+    async fn test_outlier_detection() {
+        let services = r#"
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        section "/api" {
+                            load-balance {
+                                outlier-detection errors=3 window="5s"
+                            }
+                            proxy {
+                                server "10.0.0.1:8080"
+                                server "10.0.0.2:8080"
+                            }
+                        }
+                        section "/defaults" {
+                            load-balance {
+                                outlier-detection
+                            }
+                            proxy {
+                                server "10.0.0.3:8080"
+                            }
+                        }
+                    }
+                }
+            }
+        "#;
+        let config = load(vec![("main.kdl", services)]).await;
+
+        let outliers = |idx: usize| {
+            config.basic_proxies[0].connectors.upstreams[idx]
+                .lb_options
+                .as_ref()
+                .expect("Should balance")
+                .outlier_detection
+                .clone()
+        };
+        assert_eq!(
+            outliers(0),
+            Some(OutlierDetectionConfig {
+                errors: 3,
+                window: Duration::from_secs(5),
+                ejection: Duration::from_secs(30),
+            })
+        );
+        assert_eq!(outliers(1), Some(OutlierDetectionConfig::default()));
+
+        let services = r#"
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        section "/api" {
+                            load-balance {
+                                outlier-detection window="0s" ejection="0s"
+                            }
+                            proxy {
+                                server "10.0.0.1:8080"
+                            }
+                        }
+                    }
+                }
+            }
+        "#;
+        let errors = load_errors(vec![("main.kdl", services)]).await;
+        let messages: Vec<_> = errors.errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "'window' must be greater than zero",
+                "'ejection' must be greater than zero",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dns_discovery_errors() {
+        let services = r#"
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        section "/hostname" {
+                            load-balance { selection "RoundRobin"; }
+                            proxy {
+                                server "api.internal:8080"
+                            }
+                        }
+                        section "/unknown" {
+                            load-balance { discovery "Consul"; }
+                            proxy {
+                                server "10.0.0.1:8080"
+                            }
+                        }
+                        section "/refresh" {
+                            load-balance { discovery "Static" refresh="5s"; }
+                            proxy {
+                                server "10.0.0.1:8080"
+                            }
+                        }
+                        section "/kubernetes" {
+                            load-balance { discovery "Kubernetes" service="api"; }
+                            proxy {
+                                server "10.0.0.1:8080"
+                            }
+                        }
+                        section "/no-service" {
+                            load-balance { discovery "Kubernetes" namespace="prod"; }
+                            proxy
+                        }
+                    }
+                }
+            }
+        "#;
+        let errors = load_errors(vec![("main.kdl", services)]).await;
+
+        let error = errors
+            .errors
+            .iter()
+            .find(|e| e.message.contains("needs 'discovery \"Dns\"'"))
+            .expect("Should reject the hostname");
+        assert!(error.message.contains("api.internal:8080"));
+
+        let error = errors
+            .errors
+            .iter()
+            .find(|e| e.message.contains("Unknown discovery kind: 'Consul'"))
+            .expect("Should reject the kind");
+        let span = error.label.expect("Error should point at the kind");
+        let labeled = &services[span.offset()..span.offset() + span.len()];
+        assert!(labeled.contains("Consul"), "labeled: {labeled:?}");
+
+        assert!(errors.errors.iter().any(|e| e
+            .message
+            .contains("'refresh' only applies to 'Dns' discovery")));
+        assert!(errors
+            .errors
+            .iter()
+            .any(|e| e.message.contains("remove the 'server' entries")));
+        assert!(errors
+            .errors
+            .iter()
+            .any(|e| e.message.contains("needs a 'service'")));
+    }
+
+    #[tokio::test]
+    async fn test_upstream_group_unknown_reference() {
+        let services = r#"
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        section "/api" {
+                            proxy use-group="api-missing"
+                        }
+                    }
+                }
+            }
+        "#;
+        let errors = load_errors(vec![("main.kdl", services)]).await;
+        assert_eq!(errors.count, 1);
+
+        let error = &errors.errors[0];
+        assert!(error
+            .message
+            .contains("Upstream group 'api-missing' not found"));
+
+        let span = error.label.expect("Error should point at the reference");
+        let labeled = &services[span.offset()..span.offset() + span.len()];
+        assert!(labeled.contains("api-missing"), "labeled: {labeled:?}");
+    }
+
+    #[tokio::test]
+    async fn test_cidr_filter_addrs_are_checked() {
+        let services = r#"
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        section "/admin" {
+                            use-chain {
+                                filter "motya.filters.cidr-allow" addrs="10.0.0.0/8, 10.0.0.300"
+                            }
+                            proxy "http://127.0.0.1:3000"
+                        }
+                    }
+                }
+            }
+        "#;
+        let errors = load_errors(vec![("main.kdl", services)]).await;
+        assert_eq!(errors.count, 1);
+
+        let error = &errors.errors[0];
+        assert!(error.message.contains("'10.0.0.300'"), "{}", error.message);
+
+        let span = error.label.expect("Error should point at the argument");
+        let labeled = &services[span.offset()..span.offset() + span.len()];
+        assert!(
+            labeled.trim_start().starts_with("addrs="),
+            "labeled: {labeled:?}"
+        );
     }
 }
@@ -3,23 +3,36 @@ use std::{collections::BTreeMap, time::Duration as StdDuration};
 use crate::{
     common_types::{
         balancer::BalancerConfig,
+        basic_auth::{
+            BasicAuthConfig, CredentialsConfig, CredentialsSource, HashScheme, InlineUser,
+            DEFAULT_REALM,
+        },
+        builtin_filters_name::{check_builtin_args, is_staging_only, staging_only_message},
+        condition::Condition,
+        connectors::UpstreamServer,
         definitions::{
-            ChainItem, ConfiguredFilter, FilterChain, PluginDefinition,
+            ChainItem, ConfiguredFilter, FilterChain, PluginChecksum, PluginDefinition,
             PluginSource as RuntimePluginSource,
         },
-        definitions_table::DefinitionsTable,
+        definitions_table::{DefinitionKind, DefinitionSpan, DefinitionsTable},
         error::ConfigError,
         key_template::{parse_hasher, HashAlgorithm},
-        rate_limiter::{RateLimitPolicy, StorageConfig},
+        rate_limiter::{RateLimitAlgorithm, RateLimitPolicy, RateLimitScope, StorageConfig},
+        secrets::{SecretConfig, SecretSource, SecretValue},
         value::Value,
     },
-    kdl::models::{
-        chains::{ChainItemDefData, RateLimitDefData},
-        definitions::{
-            DefinitionsDef, KeyProfileNamespaceDef, KeyProfileTemplateDef,
-            KeyProfilesSectionDefData, ModifiersNamespaceDef, ModifiersSectionDefData, PluginDef,
-            RateLimitPolicyDef, StorageDef, StorageDefData,
+    kdl::{
+        models::{
+            chains::{BasicAuthDef, ChainItemDefData, RateLimitDefData},
+            definitions::{
+                ChainFiltersDef, CredentialsDef, CredentialsDefData, DefinitionsDef,
+                KeyProfileNamespaceDef, KeyProfileTemplateDef, KeyProfilesSectionDefData,
+                ModifiersNamespaceDef, ModifiersSectionDefData, PasswordDef, PluginDef,
+                RateLimitPolicyDef, SecretDef, SecretDefData, StorageDef, StorageDefData,
+                UpstreamGroupDef,
+            },
         },
+        parser::{ctx::ParseContext, typed_value::TypedValue},
     },
 };
 
@@ -35,6 +48,12 @@ impl DefinitionsCompiler {
     ) {
         let ast = ast.into_inner();
 
+        // Storages and credentials refer to secrets.
+        if let Some(section) = ast.secrets {
+            let section = section.into_inner();
+            self.compile_secrets(section.sources, table, errors);
+        }
+
         if let Some(section) = ast.storages {
             let section = section.into_inner();
             self.compile_storages(section.storages, table, errors);
@@ -52,85 +71,226 @@ impl DefinitionsCompiler {
             let section = section.into_inner();
             self.compile_key_profiles(section, table, errors);
         }
+        if let Some(section) = ast.upstream_groups {
+            let section = section.into_inner();
+            self.compile_upstream_groups(section.groups, table, errors);
+        }
+        if let Some(section) = ast.credentials {
+            let section = section.into_inner();
+            self.compile_credentials(section.sources, table, errors);
+        }
     }
 
+    /// Compiles the filters and chains of every `modifiers` section. A chain can
+    /// `use` another one from any of them, which is compiled first.
     pub fn compile_modifiers(
         &self,
-        section: ModifiersSectionDefData,
+        sections: Vec<ModifiersSectionDefData>,
         table: &mut DefinitionsTable,
+        production: bool,
         errors: &mut ConfigError,
     ) {
-        for ns in section.namespaces {
-            self.compile_modifier_namespace(table, errors, ns, "");
+        let mut pending: Vec<Option<ChainFiltersDef>> = vec![];
+        for section in sections {
+            for ns in section.namespaces {
+                self.compile_modifier_namespace(table, errors, ns, "");
+            }
+
+            for chain_def in section.chains {
+                let duplicate = table.get_chains().contains_key(&chain_def.name)
+                    || pending
+                        .iter()
+                        .flatten()
+                        .any(|def| def.name == chain_def.name);
+                if duplicate {
+                    let (data, ctx) = chain_def.into_parts();
+                    errors.push_report(
+                        ctx.err_name(format!("Duplicate chain-filters name: '{}'", data.name)),
+                        &ctx.ctx,
+                    );
+                    continue;
+                }
+                pending.push(Some(chain_def));
+            }
         }
 
-        for chain_def in section.chains {
-            let (data, ctx) = chain_def.into_parts();
+        let mut compiling = vec![];
+        for idx in 0..pending.len() {
+            self.compile_chain(idx, &mut pending, &mut compiling, table, production, errors);
+        }
+    }
 
-            if table.get_chains().contains_key(&data.name) {
-                errors.push_report(
-                    ctx.err_name(format!("Duplicate chain-filters name: '{}'", data.name)),
-                    &ctx.ctx,
-                );
-                continue;
-            }
+    /// Compiles `pending[idx]` unless it already was, with the chains it uses
+    /// flattened into it. `compiling` holds the chains that wait for it.
+    fn compile_chain(
+        &self,
+        idx: usize,
+        pending: &mut [Option<ChainFiltersDef>],
+        compiling: &mut Vec<String>,
+        table: &mut DefinitionsTable,
+        production: bool,
+        errors: &mut ConfigError,
+    ) {
+        let Some(chain_def) = pending[idx].take() else {
+            return;
+        };
+        let (data, ctx) = chain_def.into_parts();
+        compiling.push(data.name.clone());
 
-            let mut items = vec![];
-            for item in data.filters {
-                let (item, _) = item.into_parts();
-                match item {
-                    ChainItemDefData::Filter(def) => {
-                        items.push(ChainItem::Filter(ConfiguredFilter {
-                            args: def
-                                .params
-                                .into_iter()
-                                .map(|(k, v)| (k, v.value().into()))
-                                .collect::<BTreeMap<String, Value>>(),
+        let mut items = vec![];
+        for item in data.filters {
+            let (item, item_ctx) = item.into_parts();
+            match item {
+                ChainItemDefData::Filter(mut def) => {
+                    if production && is_staging_only(&def.name) {
+                        errors.push_report(
+                            item_ctx.ctx.error(staging_only_message(&def.name)),
+                            &item_ctx.ctx,
+                        );
+                        continue;
+                    }
+                    let when = Self::take_filter_when(&mut def.params, &item_ctx.ctx, errors);
+                    let spans = def
+                        .params
+                        .iter()
+                        .map(|(k, v)| (k.clone(), v.span()))
+                        .collect::<BTreeMap<_, _>>();
+                    let args = def
+                        .params
+                        .into_iter()
+                        .map(|(k, v)| (k, v.value().into()))
+                        .collect::<BTreeMap<String, Value>>();
+                    if let Err(e) = check_builtin_args(&def.name, &args) {
+                        // Point at the offending argument when there is one.
+                        let report = match e.arg.as_ref().and_then(|arg| spans.get(arg)) {
+                            Some(span) => item_ctx.ctx.error_with_span(e.message, *span),
+                            None => item_ctx.ctx.error(e.message),
+                        };
+                        errors.push_report(report, &item_ctx.ctx);
+                        continue;
+                    }
+                    items.push(
+                        ChainItem::Filter(ConfiguredFilter {
+                            args,
                             name: def.name,
-                        }));
-                    }
-                    ChainItemDefData::RateLimit(def) => {
-                        let (rl_data, ctx) = def.into_parts();
-                        match rl_data {
-                            RateLimitDefData::Reference(name) => {
-                                if let Some(policy) = table.get_rate_limit(&name) {
-                                    items.push(ChainItem::RateLimiter(policy));
-                                } else {
-                                    errors.push_report(
-                                        ctx.err_reference_ref(format!(
-                                            "Rate limit policy '{}' not found",
-                                            name
-                                        )),
-                                        &ctx.ctx,
-                                    );
-                                }
+                        })
+                        .when(when),
+                    );
+                }
+                ChainItemDefData::RateLimit(def) => {
+                    let (rl_data, ctx) = def.into_parts();
+                    match rl_data {
+                        RateLimitDefData::Reference(name, when) => {
+                            let when = Self::compile_when(
+                                when,
+                                |msg| ctx.err_reference_when(msg),
+                                &ctx.ctx,
+                                errors,
+                            );
+                            if let Some(policy) = table.get_rate_limit(&name) {
+                                items.push(ChainItem::RateLimiter(policy).when(when));
+                            } else {
+                                errors.push_report(
+                                    ctx.err_reference_ref(format!(
+                                        "Rate limit policy '{}' not found",
+                                        name
+                                    )),
+                                    &ctx.ctx,
+                                );
                             }
-                            RateLimitDefData::Inline {
+                        }
+                        RateLimitDefData::Inline {
+                            when,
+                            expose_headers,
+                            algorithm,
+                            storage_key,
+                            key_template,
+                            transforms,
+                            burst,
+                            raw_rate,
+                        } => {
+                            let (key_template, _) = key_template.into_parts();
+
+                            let Some(algorithm) = Self::compile_rate_limit_algorithm(
+                                Some(&algorithm),
+                                &storage_key,
+                                table,
+                                |msg| ctx.err_inline_algorithm(msg),
+                                &ctx.ctx,
+                                errors,
+                            ) else {
+                                continue;
+                            };
+
+                            let policy = RateLimitPolicy {
+                                name: format!("__anon_rl_{}_{}", data.name, items.len()),
                                 algorithm,
-                                storage_key,
-                                key_template,
-                                transforms,
+                                scope: RateLimitScope::Service,
                                 burst,
-                                raw_rate,
-                            } => {
-                                let (key_template, _) = key_template.into_parts();
-
-                                items.push(ChainItem::RateLimiter(RateLimitPolicy {
-                                    name: format!("__anon_rl_{}_{}", data.name, items.len()),
-                                    algorithm,
-                                    burst,
-                                    key_template: key_template.template,
-                                    rate_req_per_sec: raw_rate,
-                                    storage_key,
-                                    transforms: transforms.map(|v| v.into()).unwrap_or_default(),
-                                }))
+                                key_template: key_template.template,
+                                rate_req_per_sec: raw_rate,
+                                storage_key,
+                                transforms: transforms.map(|v| v.into()).unwrap_or_default(),
+                                max_keys: None,
+                                expose_headers: expose_headers.unwrap_or(false),
+                            };
+                            if let Some(warning) = policy.unbounded_key_warning() {
+                                errors.warn_report(ctx.err_inline_key_template(warning), &ctx.ctx);
                             }
+
+                            let when = Self::compile_when(
+                                when,
+                                |msg| ctx.err_inline_when(msg),
+                                &ctx.ctx,
+                                errors,
+                            );
+                            items.push(ChainItem::RateLimiter(policy).when(when))
                         }
                     }
                 }
+                ChainItemDefData::BasicAuth(def) => {
+                    if let Some(auth) = Self::compile_basic_auth(def, table, errors) {
+                        items.push(auth);
+                    }
+                }
+                ChainItemDefData::Use(def) => {
+                    let (use_data, use_ctx) = def.into_parts();
+
+                    if let Some(pos) = compiling.iter().position(|name| *name == use_data.name) {
+                        let mut cycle = compiling[pos..].to_vec();
+                        cycle.push(use_data.name);
+                        errors.push_report(
+                            use_ctx.err_name(format!(
+                                "Chain-filters use each other in a cycle: {}",
+                                cycle.join(" -> ")
+                            )),
+                            &use_ctx.ctx,
+                        );
+                        continue;
+                    }
+                    let used = pending
+                        .iter()
+                        .position(|def| def.as_ref().is_some_and(|def| def.name == use_data.name));
+                    if let Some(used) = used {
+                        self.compile_chain(used, pending, compiling, table, production, errors);
+                    }
+
+                    match table.get_chain_by_name(&use_data.name) {
+                        Some(chain) => items.extend(chain.items),
+                        None => errors.push_report(
+                            use_ctx.err_name(format!(
+                                "Chain '{}' not found in definitions",
+                                use_data.name
+                            )),
+                            &use_ctx.ctx,
+                        ),
+                    }
+                }
             }
-            table.insert_chain(data.name, FilterChain { items });
         }
+        compiling.pop();
+        table.insert_span(DefinitionKind::Chain, &data.name, definition_span(&ctx.ctx));
+        table.insert_chain(data.name, FilterChain { items });
     }
 
     fn compile_modifier_namespace(
@@ -186,11 +346,18 @@ impl DefinitionsCompiler {
             let (name, config) = match data {
                 StorageDefData::Redis(inner) => {
                     let inner = inner.into_inner();
+                    let password = match inner.password {
+                        Some(password) => match Self::compile_password(password, table, errors) {
+                            Some(password) => Some(password),
+                            None => continue,
+                        },
+                        None => None,
+                    };
                     (
                         inner.name,
                         StorageConfig::Redis {
                             addresses: inner.addresses,
-                            password: inner.password,
+                            password,
                             timeout: inner.timeout.map(|d| d.into()),
                         },
                     )
@@ -210,6 +377,7 @@ impl DefinitionsCompiler {
                 }
             };
 
+            table.insert_span(DefinitionKind::Storage, &name, definition_span(&ctx.ctx));
             if table.insert_storage(name.clone(), config).is_some() {
                 errors.push_report(
                     ctx.err_self(format!("Duplicate storage definition: '{}'", name)),
@@ -219,6 +387,310 @@ impl DefinitionsCompiler {
         }
     }
 
+    /// Resolves a `basic-auth` chain item against the `credentials` of `table`.
+    /// Parses the `when` of a chain item, reporting it with `err` if it is invalid.
+    pub fn compile_when(
+        when: Option<String>,
+        err: impl FnOnce(String) -> miette::Error,
+        ctx: &ParseContext,
+        errors: &mut ConfigError,
+    ) -> Option<Condition> {
+        match when?.parse() {
+            Ok(condition) => Some(condition),
+            Err(e) => {
+                errors.push_report(err(format!("Invalid 'when': {e}")), ctx);
+                None
+            }
+        }
+    }
+
+    /// Parses the `algorithm` of a rate-limit policy keeping its buckets in `storage`,
+    /// reporting it with `err` if it is unknown or the storage can't run it.
+    pub fn compile_rate_limit_algorithm(
+        algorithm: Option<&str>,
+        storage: &str,
+        table: &DefinitionsTable,
+        err: impl Fn(String) -> miette::Error,
+        ctx: &ParseContext,
+        errors: &mut ConfigError,
+    ) -> Option<RateLimitAlgorithm> {
+        let algorithm = match algorithm {
+            None => RateLimitAlgorithm::default(),
+            Some(raw) => match raw.parse::<RateLimitAlgorithm>() {
+                Ok(algorithm) => {
+                    if raw != algorithm.name() {
+                        errors.warn_report(
+                            err(format!(
+                                "The algorithm name '{raw}' is deprecated, use '{algorithm}'"
+                            )),
+                            ctx,
+                        );
+                    }
+                    algorithm
+                }
+                Err(e) => {
+                    errors.push_report(err(e.to_string()), ctx);
+                    return None;
+                }
+            },
+        };
+
+        // Redis counts in fixed windows, whatever the policy asks for.
+        if algorithm != RateLimitAlgorithm::TokenBucket
+            && matches!(
+                table.get_storage_by_name(storage),
+                Some(StorageConfig::Redis { .. })
+            )
+        {
+            errors.push_report(
+                err(format!(
+                    "The '{algorithm}' algorithm needs a memory storage, but '{storage}' is a redis storage"
+                )),
+                ctx,
+            );
+            return None;
+        }
+
+        Some(algorithm)
+    }
+
+    /// Takes the `when` of a `filter` out of the arguments the filter gets.
+    pub fn take_filter_when(
+        params: &mut BTreeMap<String, TypedValue>,
+        ctx: &ParseContext,
+        errors: &mut ConfigError,
+    ) -> Option<Condition> {
+        let value = params.remove("when")?;
+        let span = value.span();
+        let Some(when) = value.value().as_string().map(str::to_string) else {
+            errors.push_report(ctx.error_with_span("'when' must be a string", span), ctx);
+            return None;
+        };
+
+        Self::compile_when(
+            Some(when),
+            |msg| ctx.error_with_span(msg, span),
+            ctx,
+            errors,
+        )
+    }
+
+    pub fn compile_basic_auth(
+        def: BasicAuthDef,
+        table: &DefinitionsTable,
+        errors: &mut ConfigError,
+    ) -> Option<ChainItem> {
+        let (data, ctx) = def.into_parts();
+
+        let Some(credentials) = table.get_credentials(&data.credentials) else {
+            errors.push_report(
+                ctx.err_credentials(format!(
+                    "Credentials '{}' not found in definitions",
+                    data.credentials
+                )),
+                &ctx.ctx,
+            );
+            return None;
+        };
+
+        let realm = data.realm.unwrap_or_else(|| DEFAULT_REALM.to_string());
+        // The realm goes into a quoted-string of `WWW-Authenticate` as is.
+        if realm
+            .chars()
+            .any(|c| c == '"' || c == '\\' || c.is_control())
+        {
+            errors.push_report(
+                ctx.err_realm("'realm' must not contain quotes, backslashes or control characters"),
+                &ctx.ctx,
+            );
+            return None;
+        }
+
+        let when = Self::compile_when(data.when, |msg| ctx.err_when(msg), &ctx.ctx, errors);
+
+        Some(
+            ChainItem::BasicAuth(BasicAuthConfig {
+                realm,
+                credentials: credentials.clone(),
+            })
+            .when(when),
+        )
+    }
+
+    fn compile_credentials(
+        &self,
+        items: Vec<CredentialsDef>,
+        table: &mut DefinitionsTable,
+        errors: &mut ConfigError,
+    ) {
+        for credentials_def in items {
+            let (data, ctx) = credentials_def.into_parts();
+
+            let (name, source) = match data {
+                CredentialsDefData::Htpasswd(inner) => {
+                    let inner = inner.into_inner();
+                    (inner.name, CredentialsSource::Htpasswd(inner.path))
+                }
+                CredentialsDefData::Users(inner) => {
+                    let (inner, inner_ctx) = inner.into_parts();
+
+                    if inner.users.is_empty() {
+                        errors.push_report(
+                            inner_ctx.err_users(format!(
+                                "Credentials '{}' must contain at least one 'user'",
+                                inner.name
+                            )),
+                            &inner_ctx.ctx,
+                        );
+                        continue;
+                    }
+
+                    let mut users: Vec<InlineUser> = Vec::new();
+                    for user in inner.users {
+                        let (user, user_ctx) = user.into_parts();
+
+                        if user.name.is_empty() || user.name.contains(':') {
+                            errors.push_report(
+                                user_ctx
+                                    .err_name("User names must be non-empty and contain no ':'"),
+                                &user_ctx.ctx,
+                            );
+                        } else if users.iter().any(|u| u.user == user.name) {
+                            errors.push_report(
+                                user_ctx.err_name(format!("Duplicate user: '{}'", user.name)),
+                                &user_ctx.ctx,
+                            );
+                        } else {
+                            let hash = match (user.hash, user.secret) {
+                                (Some(hash), None) if HashScheme::detect(&hash).is_none() => {
+                                    errors.push_report(
+                                        user_ctx.err_hash(
+                                            "Unsupported password hash. Use a bcrypt, $apr1$ or {SHA} hash, e.g. from 'htpasswd -nB USER'",
+                                        ),
+                                        &user_ctx.ctx,
+                                    );
+                                    continue;
+                                }
+                                (Some(hash), None) => SecretValue::Inline(hash),
+                                (None, Some(name)) => match table.get_secret(&name) {
+                                    Some(secret) => SecretValue::Secret(secret.clone()),
+                                    None => {
+                                        errors.push_report(
+                                            user_ctx.err_secret(format!(
+                                                "Secret '{name}' not found in definitions"
+                                            )),
+                                            &user_ctx.ctx,
+                                        );
+                                        continue;
+                                    }
+                                },
+                                _ => {
+                                    errors.push_report(
+                                        user_ctx.err_self(
+                                            "A user needs either a hash or a 'secret', not both",
+                                        ),
+                                        &user_ctx.ctx,
+                                    );
+                                    continue;
+                                }
+                            };
+                            users.push(InlineUser {
+                                user: user.name,
+                                hash,
+                            });
+                        }
+                    }
+
+                    (inner.name, CredentialsSource::Inline(users))
+                }
+            };
+
+            let config = CredentialsConfig {
+                name: name.clone(),
+                source,
+            };
+
+            if table.insert_credentials(name.clone(), config).is_some() {
+                errors.push_report(
+                    ctx.err_self(format!("Duplicate credentials definition: '{}'", name)),
+                    &ctx.ctx,
+                );
+            }
+        }
+    }
+
+    fn compile_secrets(
+        &self,
+        items: Vec<SecretDef>,
+        table: &mut DefinitionsTable,
+        errors: &mut ConfigError,
+    ) {
+        for secret_def in items {
+            let (data, ctx) = secret_def.into_parts();
+
+            let (name, source) = match data {
+                SecretDefData::Env(inner) => {
+                    let inner = inner.into_inner();
+                    (inner.name, SecretSource::Env(inner.var))
+                }
+                SecretDefData::File(inner) => {
+                    let inner = inner.into_inner();
+                    let source = SecretSource::File {
+                        path: inner.path,
+                        key: inner.key,
+                    };
+                    (inner.name, source)
+                }
+                SecretDefData::Command(inner) => {
+                    let inner = inner.into_inner();
+                    (inner.name, SecretSource::Command(inner.run))
+                }
+            };
+
+            table.insert_span(DefinitionKind::Secret, &name, definition_span(&ctx.ctx));
+            let secret = SecretConfig {
+                name: name.clone(),
+                source,
+            };
+            if table.insert_secret(name.clone(), secret).is_some() {
+                errors.push_report(
+                    ctx.err_self(format!("Duplicate secret definition: '{}'", name)),
+                    &ctx.ctx,
+                );
+            }
+        }
+    }
+
+    /// A `password` given inline or as a reference to `secrets`.
+    fn compile_password(
+        def: PasswordDef,
+        table: &DefinitionsTable,
+        errors: &mut ConfigError,
+    ) -> Option<SecretValue> {
+        let (data, ctx) = def.into_parts();
+
+        match (data.value, data.secret) {
+            (Some(value), None) => Some(SecretValue::Inline(value)),
+            (None, Some(name)) => match table.get_secret(&name) {
+                Some(secret) => Some(SecretValue::Secret(secret.clone())),
+                None => {
+                    errors.push_report(
+                        ctx.err_secret(format!("Secret '{name}' not found in definitions")),
+                        &ctx.ctx,
+                    );
+                    None
+                }
+            },
+            _ => {
+                errors.push_report(
+                    ctx.err_self("'password' needs either a value or a 'secret', not both"),
+                    &ctx.ctx,
+                );
+                None
+            }
+        }
+    }
+
     fn compile_rate_limits(
         &self,
         items: Vec<RateLimitPolicyDef>,
@@ -227,17 +699,60 @@ impl DefinitionsCompiler {
     ) {
         for policy_def in items {
             let (data, ctx) = policy_def.into_parts();
+            let storage_key = data.storage_ref.unwrap_or_default();
+
+            let Some(algorithm) = Self::compile_rate_limit_algorithm(
+                data.algorithm.as_deref(),
+                &storage_key,
+                table,
+                |msg| ctx.err_algorithm(msg),
+                &ctx.ctx,
+                errors,
+            ) else {
+                continue;
+            };
 
             let policy = RateLimitPolicy {
                 name: data.name.clone(),
-                algorithm: data.algorithm.unwrap_or_else(|| "token_bucket".to_string()),
-                storage_key: data.storage_ref.unwrap_or_default(),
+                algorithm,
+                scope: data.scope.unwrap_or_default(),
+                storage_key,
                 key_template: data.key,
                 rate_req_per_sec: data.rate.as_secs_f64(),
                 burst: data.burst.unwrap_or(1),
                 transforms: data.transforms.map(|v| v.into()).unwrap_or_default(),
+                max_keys: data.max_keys,
+                expose_headers: data.expose_headers.unwrap_or(false),
             };
 
+            if policy.max_keys == Some(0) {
+                errors.push_report(
+                    ctx.err_max_keys("'max-keys' must be greater than zero"),
+                    &ctx.ctx,
+                );
+                continue;
+            }
+
+            if policy.max_keys.is_some()
+                && matches!(
+                    table.get_storage_by_name(&policy.storage_key),
+                    Some(StorageConfig::Redis { .. })
+                )
+            {
+                errors.push_report(
+                    ctx.err_max_keys(format!(
+                        "'max-keys' needs a memory storage, but rate-limit policy '{}' uses the redis storage '{}'",
+                        policy.name, policy.storage_key
+                    )),
+                    &ctx.ctx,
+                );
+                continue;
+            }
+
+            if let Some(warning) = policy.unbounded_key_warning() {
+                errors.warn_report(ctx.err_key(warning), &ctx.ctx);
+            }
+
             if table
                 .insert_rate_limit(policy.name.clone(), policy)
                 .is_some()
@@ -250,6 +765,56 @@ impl DefinitionsCompiler {
         }
     }
 
+    fn compile_upstream_groups(
+        &self,
+        items: Vec<UpstreamGroupDef>,
+        table: &mut DefinitionsTable,
+        errors: &mut ConfigError,
+    ) {
+        for group_def in items {
+            let (data, ctx) = group_def.into_parts();
+
+            if table.get_upstream_group(&data.name).is_some() {
+                errors.push_report(
+                    ctx.err_name(format!("Duplicate upstream group: '{}'", data.name)),
+                    &ctx.ctx,
+                );
+                continue;
+            }
+
+            if data.servers.is_empty() {
+                errors.push_report(
+                    ctx.err_servers(format!(
+                        "Upstream group '{}' must contain at least one 'server'",
+                        data.name
+                    )),
+                    &ctx.ctx,
+                );
+                continue;
+            }
+
+            let servers = data
+                .servers
+                .into_iter()
+                .map(|s| {
+                    let (s, s_ctx) = s.into_parts();
+                    if s.weight == Some(0) {
+                        errors.push_report(
+                            s_ctx.err_weight("Server 'weight' must be at least 1"),
+                            &s_ctx.ctx,
+                        );
+                    }
+                    UpstreamServer {
+                        address: s.address,
+                        weight: s.weight.unwrap_or(1),
+                    }
+                })
+                .collect();
+
+            table.insert_upstream_group(data.name, servers);
+        }
+    }
+
     fn compile_plugins(
         &self,
         items: Vec<PluginDef>,
@@ -267,7 +832,8 @@ impl DefinitionsCompiler {
                 continue;
             }
 
-            let source = match (data.load.path, data.load.url) {
+            let (load, load_ctx) = data.load.into_parts();
+            let source = match (load.path, load.url) {
                 (Some(path), None) => RuntimePluginSource::File(path),
                 (None, Some(url)) => RuntimePluginSource::Url(url),
                 _ => {
@@ -282,11 +848,55 @@ impl DefinitionsCompiler {
                 }
             };
 
+            let checksum = match load.sha256 {
+                Some(sha256) => {
+                    let sha256 = sha256.to_ascii_lowercase();
+                    if sha256.len() != 64 || !sha256.bytes().all(|b| b.is_ascii_hexdigit()) {
+                        errors.push_report(
+                            load_ctx.err_sha256("'sha256' must be 64 hexadecimal characters"),
+                            &load_ctx.ctx,
+                        );
+                        continue;
+                    }
+                    Some(PluginChecksum {
+                        sha256,
+                        src: load_ctx.ctx.source(),
+                        span: load_ctx.span_sha256(),
+                    })
+                }
+                // A module fetched over the network is only trusted with a checksum.
+                None if matches!(source, RuntimePluginSource::Url(_)) => {
+                    errors.push_report(
+                        load_ctx.err_self(format!(
+                            "Plugin '{}' is loaded from a URL and needs its 'sha256'",
+                            data.name
+                        )),
+                        &load_ctx.ctx,
+                    );
+                    continue;
+                }
+                None => None,
+            };
+
+            let request_body_limit = match data.request_body.map(|b| b.into_parts()) {
+                Some((body, body_ctx)) if body.max_size == 0 => {
+                    errors.push_report(
+                        body_ctx.err_max_size("'max-size' must be greater than zero"),
+                        &body_ctx.ctx,
+                    );
+                    continue;
+                }
+                Some((body, _)) => Some(body.max_size),
+                None => None,
+            };
+
             table.insert_plugin(
                 data.name.clone(),
                 PluginDefinition {
                     name: data.name,
                     source,
+                    checksum,
+                    request_body_limit,
                 },
             );
         }
@@ -391,6 +1001,889 @@ impl DefinitionsCompiler {
             transforms,
         };
 
+        table.insert_span(
+            DefinitionKind::KeyProfile,
+            &full_name,
+            definition_span(&ctx.ctx),
+        );
         table.insert_key_profile(full_name, config);
     }
 }
+
+fn definition_span(ctx: &ParseContext) -> DefinitionSpan {
+    DefinitionSpan {
+        source_name: ctx.source_name().to_string(),
+        span: ctx.current_span(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::{
+        common_types::{
+            basic_auth::CredentialsSource,
+            condition::ConditionTest,
+            connectors::UpstreamConfig,
+            definitions::{ChainItem, Modificator},
+            definitions_table::{DefinitionKind, DefinitionsTable},
+            rate_limiter::{RateLimitAlgorithm, RateLimitScope, StorageConfig},
+            secrets::{SecretSource, SecretValue},
+        },
+        loader::{
+            test_support::{
+                load, load_definitions, load_errors, MockConfigSource, DELAY_SERVICES,
+                RATE_LIMIT_STORAGES,
+            },
+            ConfigLoader, FileConfigLoaderProvider,
+        },
+    };
+
+    const UPSTREAM_GROUPS: &str = r#"
+            definitions {
+                upstream-groups {
+                    group "api-prod" {
+                        server "10.0.0.1:8080" weight=3
+                        server "10.0.0.2:8080"
+                    }
+                }
+            }
+        "#;
+
+    #[tokio::test]
+    async fn test_upstream_group_shared_across_services() {
+        let services = r#"
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        section "/api" {
+                            proxy use-group="api-prod"
+                        }
+                    }
+                }
+                Internal {
+                    listeners { "127.0.0.1:9090" }
+                    connectors {
+                        section "/" {
+                            proxy use-group="api-prod"
+                        }
+                    }
+                }
+            }
+        "#;
+        let config = load(vec![
+            ("groups.kdl", UPSTREAM_GROUPS),
+            ("services.kdl", services),
+        ])
+        .await;
+
+        assert_eq!(config.basic_proxies.len(), 2);
+        for proxy in &config.basic_proxies {
+            let UpstreamConfig::MultiServer(multi) = &proxy.connectors.upstreams[0].upstream else {
+                panic!("Expected a multi-server upstream");
+            };
+            let servers: Vec<_> = multi
+                .servers
+                .iter()
+                .map(|s| (s.address.to_string(), s.weight))
+                .collect();
+            assert_eq!(
+                servers,
+                vec![
+                    ("10.0.0.1:8080".to_string(), 3),
+                    ("10.0.0.2:8080".to_string(), 1)
+                ]
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_upstream_group_duplicate_name() {
+        let errors =
+            load_errors(vec![("a.kdl", UPSTREAM_GROUPS), ("b.kdl", UPSTREAM_GROUPS)]).await;
+        assert_eq!(errors.count, 1);
+        assert!(errors.errors[0]
+            .message
+            .contains("Duplicate upstream group: 'api-prod'"));
+    }
+
+    #[tokio::test]
+    async fn test_definition_spans() {
+        let definitions = r#"
+            definitions {
+                storages {
+                    memory "local" { max-keys 100; }
+                }
+                modifiers {
+                    chain-filters "security" {
+                        filter "motya.request.upsert-header" key="X-Secure" value="1"
+                    }
+                }
+            }
+        "#;
+        let services = r#"
+            services {
+                Api {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        section "/" {
+                            use-chain "security"
+                            proxy "http://127.0.0.1:3000"
+                        }
+                    }
+                }
+            }
+        "#;
+        let table = load_definitions(vec![
+            ("definitions.kdl", definitions),
+            ("services.kdl", services),
+        ])
+        .await;
+
+        let declared = |kind, name| {
+            let span = table.get_span(kind, name).expect("Should record the span");
+            assert_eq!(span.source_name, "definitions.kdl");
+            definitions[span.span.offset()..].trim_start().to_string()
+        };
+        let chain = declared(DefinitionKind::Chain, "security");
+        assert!(chain.starts_with("chain-filters \"security\""), "{chain}");
+        let storage = declared(DefinitionKind::Storage, "local");
+        assert!(storage.starts_with("memory \"local\""), "{storage}");
+
+        let profile = table.get_span(DefinitionKind::KeyProfile, "security");
+        assert!(profile.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_policy_max_keys() {
+        let policies = r#"
+            definitions {
+                rate-limits {
+                    policy "per-path" {
+                        storage "local"
+                        key "${client-ip}${uri-path}"
+                        rate "1s"
+                        max-keys 500
+                    }
+                }
+            }
+        "#;
+        let table = load_definitions(vec![
+            ("storages.kdl", RATE_LIMIT_STORAGES),
+            ("policies.kdl", policies),
+            ("main.kdl", DELAY_SERVICES),
+        ])
+        .await;
+
+        let policy = table
+            .get_rate_limit("per-path")
+            .expect("Policy should exist");
+        assert_eq!(policy.max_keys, Some(500));
+        assert_eq!(policy.unbounded_key_parts(), vec!["${uri-path}"]);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_policy_max_keys_needs_memory_storage() {
+        let policies = r#"
+            definitions {
+                rate-limits {
+                    policy "per-ip" {
+                        storage "shared"
+                        key "${client-ip}"
+                        rate "1s"
+                        max-keys 500
+                    }
+                }
+            }
+        "#;
+        let errors = load_errors(vec![
+            ("storages.kdl", RATE_LIMIT_STORAGES),
+            ("policies.kdl", policies),
+            ("main.kdl", DELAY_SERVICES),
+        ])
+        .await;
+        assert_eq!(errors.count, 1);
+        assert!(errors.errors[0]
+            .message
+            .contains("'max-keys' needs a memory storage"));
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_algorithms() {
+        let policies = r#"
+            definitions {
+                rate-limits {
+                    policy "default" {
+                        storage "local"
+                        key "${client-ip}"
+                        rate "1s"
+                    }
+                    policy "smooth" expose-headers=#true {
+                        algorithm "sliding-window"
+                        storage "local"
+                        key "${client-ip}"
+                        rate "1s"
+                    }
+                    policy "exact" {
+                        algorithm "gcra"
+                        storage "local"
+                        key "${client-ip}"
+                        rate "1s"
+                    }
+                    policy "unknown" {
+                        algorithm "leaky-bucket"
+                        storage "local"
+                        key "${client-ip}"
+                        rate "1s"
+                    }
+                    policy "shared" {
+                        algorithm "gcra"
+                        storage "shared"
+                        key "${client-ip}"
+                        rate "1s"
+                    }
+                }
+            }
+        "#;
+        let source = MockConfigSource::new(vec![
+            ("storages.kdl", RATE_LIMIT_STORAGES),
+            ("policies.kdl", policies),
+            ("main.kdl", DELAY_SERVICES),
+        ]);
+        let loader = ConfigLoader::new(source);
+        let mut table = DefinitionsTable::new_with_global();
+
+        let (config, errors) = loader
+            .load_lossy(Some(PathBuf::from("dummy")), &mut table)
+            .await;
+
+        assert!(config.is_none());
+        assert_eq!(errors.count, 2);
+        assert!(errors.errors[0]
+            .message
+            .contains("Unknown rate-limit algorithm 'leaky-bucket'"));
+        assert!(errors.errors[1]
+            .message
+            .contains("The 'gcra' algorithm needs a memory storage"));
+
+        let algorithm = |name| table.get_rate_limit(name).unwrap().algorithm;
+        assert_eq!(algorithm("default"), RateLimitAlgorithm::TokenBucket);
+        assert_eq!(algorithm("smooth"), RateLimitAlgorithm::SlidingWindow);
+        assert_eq!(algorithm("exact"), RateLimitAlgorithm::Gcra);
+
+        assert!(table.get_rate_limit("smooth").unwrap().expose_headers);
+        assert!(!table.get_rate_limit("default").unwrap().expose_headers);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_scopes() {
+        let policies = r#"
+            definitions {
+                rate-limits {
+                    policy "default" {
+                        storage "local"
+                        key "${client-ip}"
+                        rate "1s"
+                    }
+                    policy "everywhere" scope="global" {
+                        storage "local"
+                        key "${client-ip}"
+                        rate "1s"
+                    }
+                    policy "unknown" scope="cluster" {
+                        storage "local"
+                        key "${client-ip}"
+                        rate "1s"
+                    }
+                }
+            }
+        "#;
+        let source = MockConfigSource::new(vec![
+            ("storages.kdl", RATE_LIMIT_STORAGES),
+            ("policies.kdl", policies),
+            ("main.kdl", DELAY_SERVICES),
+        ]);
+        let loader = ConfigLoader::new(source);
+        let mut table = DefinitionsTable::new_with_global();
+
+        let (config, errors) = loader
+            .load_lossy(Some(PathBuf::from("dummy")), &mut table)
+            .await;
+
+        assert!(config.is_none());
+        assert_eq!(errors.count, 1);
+        assert!(errors.errors[0]
+            .message
+            .contains("Unknown rate-limit scope 'cluster'"));
+
+        let scope = |name| table.get_rate_limit(name).unwrap().scope;
+        assert_eq!(scope("default"), RateLimitScope::Service);
+        assert_eq!(scope("everywhere"), RateLimitScope::Global);
+    }
+
+    #[tokio::test]
+    async fn test_basic_auth() {
+        let config = r#"
+            definitions {
+                credentials {
+                    htpasswd "admins" path="/etc/motya/admins.htpasswd"
+                    users "ops" {
+                        user "alice" "{SHA}VBPuJHI7uixaa6LQGWx4s+5GKNE="
+                    }
+                }
+                modifiers {
+                    chain-filters "admin-only" {
+                        basic-auth "admins" realm="Admin area"
+                    }
+                }
+            }
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        section "/ops" {
+                            use-chain {
+                                basic-auth "ops"
+                            }
+                            proxy "http://127.0.0.1:3000"
+                        }
+                    }
+                }
+            }
+        "#;
+        let table = load_definitions(vec![("main.kdl", config)]).await;
+
+        let chain = table.get_chain_by_name("admin-only").unwrap();
+        let ChainItem::BasicAuth(auth) = &chain.items[0] else {
+            panic!("expected basic-auth, got {:?}", chain.items[0]);
+        };
+        assert_eq!(auth.realm, "Admin area");
+        assert_eq!(
+            auth.credentials.source,
+            CredentialsSource::Htpasswd(PathBuf::from("/etc/motya/admins.htpasswd"))
+        );
+
+        let CredentialsSource::Inline(users) = &table.get_credentials("ops").unwrap().source else {
+            panic!("expected inline users");
+        };
+        assert_eq!(users[0].user, "alice");
+    }
+
+    #[tokio::test]
+    async fn test_basic_auth_errors() {
+        let config = r#"
+            definitions {
+                credentials {
+                    users "ops" {
+                        user "alice" "hunter2"
+                    }
+                }
+                modifiers {
+                    chain-filters "admin-only" {
+                        basic-auth "admins"
+                    }
+                }
+            }
+        "#;
+        let errors =
+            load_errors(vec![("main.kdl", config), ("services.kdl", DELAY_SERVICES)]).await;
+        assert_eq!(errors.count, 2);
+        assert!(errors
+            .errors
+            .iter()
+            .any(|e| e.message.contains("Unsupported password hash")));
+        assert!(errors
+            .errors
+            .iter()
+            .any(|e| e.message.contains("Credentials 'admins' not found")));
+    }
+
+    #[tokio::test]
+    async fn test_chain_item_conditions() {
+        let config = r#"
+            definitions {
+                storages {
+                    memory "main_mem" {
+                        max-keys 1000
+                        cleanup-interval "60s"
+                    }
+                }
+                rate-limits {
+                    policy "api_limit" {
+                        storage "main_mem"
+                        key "${client-ip}"
+                        rate "1s"
+                    }
+                }
+                credentials {
+                    users "ops" {
+                        user "alice" "{SHA}VBPuJHI7uixaa6LQGWx4s+5GKNE="
+                    }
+                }
+                modifiers {
+                    chain-filters "debug" {
+                        filter "motya.request.upsert-header" key="X-Debug" value="1" when="${header-x-debug} == '1'"
+                        rate-limit "api_limit" when="${header-x-internal} != 'yes'"
+                        basic-auth "ops" when="!${cookie-session}"
+                        filter "motya.request.upsert-header" key="X-Always" value="1"
+                    }
+                }
+            }
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        section "/" {
+                            use-chain "debug"
+                            proxy "http://127.0.0.1:3000"
+                        }
+                    }
+                }
+            }
+        "#;
+        let table = load_definitions(vec![("main.kdl", config)]).await;
+
+        let chain = table.get_chain_by_name("debug").unwrap();
+        let conditions: Vec<_> = chain
+            .items
+            .iter()
+            .map(|item| match item {
+                ChainItem::When { condition, .. } => Some(condition.test.clone()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            conditions,
+            vec![
+                Some(ConditionTest::Equals("1".to_string())),
+                Some(ConditionTest::NotEquals("yes".to_string())),
+                Some(ConditionTest::Absent),
+                None,
+            ]
+        );
+
+        // The filter doesn't get `when` as an argument.
+        let ChainItem::When { item, .. } = &chain.items[0] else {
+            unreachable!();
+        };
+        let ChainItem::Filter(filter) = item.as_ref() else {
+            panic!("expected a filter, got {item:?}");
+        };
+        assert_eq!(filter.args.keys().collect::<Vec<_>>(), vec!["key", "value"]);
+
+        let config = r#"
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        section "/" {
+                            use-chain {
+                                filter "motya.request.upsert-header" key="X-Debug" value="1" when="debug == '1'"
+                                filter "motya.request.upsert-header" key="X-Debug" value="1" when=#true
+                            }
+                            proxy "http://127.0.0.1:3000"
+                        }
+                    }
+                }
+            }
+        "#;
+        let errors = load_errors(vec![("main.kdl", config)]).await;
+        let messages: Vec<_> = errors.errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages.len(), 2, "{messages:?}");
+        assert!(messages[0].starts_with("Invalid 'when': 'debug == '1'' reads nothing"));
+        assert_eq!(messages[1], "'when' must be a string");
+    }
+
+    #[tokio::test]
+    async fn test_chain_filters_use() {
+        // A chain can use one defined after it, or in another file.
+        let security = r#"
+            definitions {
+                modifiers {
+                    chain-filters "site" {
+                        use "security"
+                        filter "motya.request.upsert-header" key="X-Site" value="1"
+                    }
+                    chain-filters "security" {
+                        use "headers"
+                        filter "motya.request.upsert-header" key="X-Secure" value="1"
+                    }
+                }
+            }
+        "#;
+        let headers = r#"
+            definitions {
+                modifiers {
+                    chain-filters "headers" {
+                        filter "motya.request.upsert-header" key="X-Frame-Options" value="DENY"
+                    }
+                }
+            }
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        section "/" {
+                            use-chain {
+                                use "headers"
+                                filter "motya.request.upsert-header" key="X-Inline" value="1"
+                            }
+                            proxy "http://127.0.0.1:3000"
+                        }
+                    }
+                }
+            }
+        "#;
+        let source =
+            MockConfigSource::new(vec![("security.kdl", security), ("headers.kdl", headers)]);
+        let mut table = DefinitionsTable::new_with_global();
+        let config = ConfigLoader::new(source)
+            .load_entry_point(Some(PathBuf::from("dummy")), &mut table)
+            .await
+            .expect("Should compile without errors")
+            .expect("Should return config");
+
+        let header_keys = |items: &[ChainItem]| -> Vec<String> {
+            items
+                .iter()
+                .map(|item| match item {
+                    ChainItem::Filter(filter) => filter.args["key"].to_string(),
+                    _ => panic!("expected a filter, got {item:?}"),
+                })
+                .collect()
+        };
+        assert_eq!(
+            header_keys(&table.get_chain_by_name("site").unwrap().items),
+            vec!["X-Frame-Options", "X-Secure", "X-Site"]
+        );
+
+        let Modificator::Chain(inline) = &config.basic_proxies[0].connectors.upstreams[0].chains[0];
+        assert_eq!(
+            header_keys(&inline.chain.items),
+            vec!["X-Frame-Options", "X-Inline"]
+        );
+
+        let config = r#"
+            definitions {
+                modifiers {
+                    chain-filters "a" {
+                        use "b"
+                    }
+                    chain-filters "b" {
+                        use "a"
+                    }
+                    chain-filters "c" {
+                        use "missing"
+                    }
+                }
+            }
+        "#;
+        let errors = load_errors(vec![("main.kdl", config)]).await;
+        let messages: Vec<_> = errors.errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Chain-filters use each other in a cycle: a -> b -> a",
+                "Chain 'missing' not found in definitions",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_secret_references() {
+        let config = r#"
+            definitions {
+                secrets {
+                    env "redis-pass" var="REDIS_PASSWORD"
+                    file "alice-hash" path="/run/secrets/motya.env" key="ALICE_HASH"
+                }
+                storages {
+                    redis "shared" {
+                        addresses "redis://127.0.0.1:6379"
+                        password secret="redis-pass"
+                    }
+                }
+                credentials {
+                    users "ops" {
+                        user "alice" secret="alice-hash"
+                    }
+                }
+            }
+        "#;
+        let table =
+            load_definitions(vec![("main.kdl", config), ("services.kdl", DELAY_SERVICES)]).await;
+
+        let Some(StorageConfig::Redis {
+            password: Some(SecretValue::Secret(secret)),
+            ..
+        }) = table.get_storage_by_name("shared")
+        else {
+            panic!("expected a redis password from a secret");
+        };
+        assert_eq!(
+            secret.source,
+            SecretSource::Env("REDIS_PASSWORD".to_string())
+        );
+
+        let CredentialsSource::Inline(users) = &table.get_credentials("ops").unwrap().source else {
+            panic!("expected inline users");
+        };
+        let SecretValue::Secret(secret) = &users[0].hash else {
+            panic!("expected a hash from a secret");
+        };
+        assert_eq!(
+            secret.source,
+            SecretSource::File {
+                path: PathBuf::from("/run/secrets/motya.env"),
+                key: Some("ALICE_HASH".to_string()),
+            }
+        );
+        assert!(table
+            .get_span(DefinitionKind::Secret, "alice-hash")
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn test_secret_reference_errors() {
+        let config = r#"
+            definitions {
+                storages {
+                    redis "shared" {
+                        addresses "redis://127.0.0.1:6379"
+                        password secret="missing"
+                    }
+                }
+                credentials {
+                    users "ops" {
+                        user "alice" "$2y$05$abcdefghijklmnopqrstuv" secret="alice-hash"
+                    }
+                }
+            }
+        "#;
+        let errors =
+            load_errors(vec![("main.kdl", config), ("services.kdl", DELAY_SERVICES)]).await;
+        assert!(errors
+            .errors
+            .iter()
+            .any(|e| e.message.contains("Secret 'missing' not found")));
+        assert!(errors
+            .errors
+            .iter()
+            .any(|e| e.message.contains("either a hash or a 'secret'")));
+    }
+
+    #[tokio::test]
+    async fn test_header_filter_args_are_checked() {
+        let definitions = r#"
+            definitions {
+                modifiers {
+                    chain-filters "headers" {
+                        filter "motya.request.set-header" name="X-Proxy" value="motya"
+                        filter "motya.request.upsert-header" key="X-Legacy" value="yes"
+                        filter "motya.response.remove-header-regex" pattern="(unclosed"
+                        filter "motya.response.rename-header" from="Server"
+                    }
+                }
+            }
+        "#;
+        let errors = load_errors(vec![
+            ("main.kdl", definitions),
+            ("services.kdl", DELAY_SERVICES),
+        ])
+        .await;
+
+        assert_eq!(errors.count, 2, "{:?}", errors.errors);
+
+        let regex_error = &errors.errors[0];
+        assert!(
+            regex_error.message.contains("Invalid regex"),
+            "{}",
+            regex_error.message
+        );
+        let span = regex_error
+            .label
+            .expect("Error should point at the argument");
+        let labeled = &definitions[span.offset()..span.offset() + span.len()];
+        assert!(
+            labeled.trim_start().starts_with("pattern="),
+            "labeled: {labeled:?}"
+        );
+
+        // Nothing to point at for a missing argument but the filter itself.
+        let rename_error = &errors.errors[1];
+        assert!(
+            rename_error.message.contains("'to'"),
+            "{}",
+            rename_error.message
+        );
+        let span = rename_error
+            .label
+            .expect("Error should point at the filter");
+        let labeled = &definitions[span.offset()..span.offset() + span.len()];
+        assert!(labeled.starts_with("filter"), "labeled: {labeled:?}");
+    }
+
+    #[tokio::test]
+    async fn test_rewrite_body_filter_args_are_checked() {
+        let definitions = r#"
+            definitions {
+                modifiers {
+                    chain-filters "rewrite" {
+                        filter "motya.response.rewrite-body" find="http://legacy.local" replace="" max-body="256kb"
+                        filter "motya.request.rewrite-body" pattern="v([0-9]+)" replace="v$1" content-types="application/json"
+                        filter "motya.response.rewrite-body" find="x" replace="y" content-types="html"
+                    }
+                }
+            }
+        "#;
+        let errors = load_errors(vec![
+            ("main.kdl", definitions),
+            ("services.kdl", DELAY_SERVICES),
+        ])
+        .await;
+
+        assert_eq!(errors.count, 1, "{:?}", errors.errors);
+
+        let span = errors.errors[0]
+            .label
+            .expect("Error should point at the argument");
+        let labeled = &definitions[span.offset()..span.offset() + span.len()];
+        assert!(
+            labeled.trim_start().starts_with("content-types="),
+            "labeled: {labeled:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rewrite_body_upstream_encoding_is_for_responses() {
+        let definitions = r#"
+            definitions {
+                modifiers {
+                    chain-filters "rewrite" {
+                        filter "motya.response.rewrite-body" find="x" replace="y" upstream-encoding="decode"
+                        filter "motya.request.rewrite-body" find="x" replace="y" upstream-encoding="decode"
+                    }
+                }
+            }
+        "#;
+        let errors = load_errors(vec![
+            ("main.kdl", definitions),
+            ("services.kdl", DELAY_SERVICES),
+        ])
+        .await;
+
+        assert_eq!(errors.count, 1, "{:?}", errors.errors);
+        assert!(errors.errors[0]
+            .message
+            .contains("only applies to 'motya.response.rewrite-body'"));
+
+        let span = errors.errors[0]
+            .label
+            .expect("Error should point at the argument");
+        let labeled = &definitions[span.offset()..span.offset() + span.len()];
+        assert!(
+            labeled.trim_start().starts_with("upstream-encoding="),
+            "labeled: {labeled:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_plugin_request_body() {
+        let config = r#"
+            definitions {
+                plugins {
+                    plugin {
+                        name "signer"
+                        load path="/opt/motya/signer.wasm"
+                        request-body max-size=65536
+                    }
+                    plugin {
+                        name "broken"
+                        load path="/opt/motya/broken.wasm"
+                        request-body max-size=0
+                    }
+                    plugin {
+                        name "headers-only"
+                        load path="/opt/motya/headers.wasm"
+                    }
+                }
+            }
+        "#;
+        let source =
+            MockConfigSource::new(vec![("main.kdl", config), ("services.kdl", DELAY_SERVICES)]);
+        let loader = ConfigLoader::new(source);
+        let mut table = DefinitionsTable::new_with_global();
+
+        let (_, errors) = loader
+            .load_lossy(Some(PathBuf::from("dummy")), &mut table)
+            .await;
+
+        assert_eq!(errors.count, 1, "{:?}", errors.errors);
+        assert!(errors.errors[0].message.contains("'max-size'"));
+
+        let plugins = table.get_plugins();
+        let limit = |name: &str| plugins[&name.parse::<fqdn::FQDN>().unwrap()].request_body_limit;
+        assert_eq!(limit("signer"), Some(65536));
+        assert_eq!(limit("headers-only"), None);
+        assert!(!plugins.contains_key(&"broken".parse::<fqdn::FQDN>().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_plugin_checksum() {
+        let config = r#"
+            definitions {
+                plugins {
+                    plugin {
+                        name "signer"
+                        load url="https://plugins.example.com/signer.wasm" sha256="9F86D081884C7D659A2FEAA0C55AD015A3BF4F1B2B0B822CD15D6C15B0F00A08"
+                    }
+                    plugin {
+                        name "unpinned"
+                        load url="https://plugins.example.com/unpinned.wasm"
+                    }
+                    plugin {
+                        name "short"
+                        load url="https://plugins.example.com/short.wasm" sha256="abc"
+                    }
+                    plugin {
+                        name "local"
+                        load path="/opt/motya/local.wasm"
+                    }
+                }
+            }
+        "#;
+        let source =
+            MockConfigSource::new(vec![("main.kdl", config), ("services.kdl", DELAY_SERVICES)]);
+        let loader = ConfigLoader::new(source);
+        let mut table = DefinitionsTable::new_with_global();
+
+        let (_, errors) = loader
+            .load_lossy(Some(PathBuf::from("dummy")), &mut table)
+            .await;
+
+        assert_eq!(errors.count, 2, "{:?}", errors.errors);
+        assert!(errors.errors[0].message.contains("needs its 'sha256'"));
+
+        let span = errors.errors[1]
+            .label
+            .expect("Error should point at the checksum");
+        let labeled = &config[span.offset()..span.offset() + span.len()];
+        assert!(labeled.contains("\"abc\""), "labeled: {labeled:?}");
+
+        let plugins = table.get_plugins();
+        let checksum = |name: &str| {
+            plugins[&name.parse::<fqdn::FQDN>().unwrap()]
+                .checksum
+                .as_ref()
+                .map(|c| c.sha256.clone())
+        };
+        assert_eq!(
+            checksum("signer").as_deref(),
+            Some("9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08")
+        );
+        assert_eq!(checksum("local"), None);
+        assert!(!plugins.contains_key(&"unpinned".parse::<fqdn::FQDN>().unwrap()));
+    }
+}
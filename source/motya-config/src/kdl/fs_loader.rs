@@ -1,32 +1,67 @@
 use std::{
-    collections::HashSet,
+    collections::{BTreeMap, HashMap, HashSet},
     future::Future,
     marker::PhantomData,
     path::{Path, PathBuf},
+    sync::{Arc, Mutex},
 };
 
-use async_recursion::async_recursion;
+use futures_util::{stream, StreamExt};
 use kdl::KdlDocument;
 use miette::{miette, Context, IntoDiagnostic, Result};
+use xxhash_rust::xxh64::xxh64;
 
 use crate::{
     config_source::ConfigSource,
     kdl::{
-        models::imports::ImportsDef,
+        models::root::PartialParsedRoot,
         parser::{ctx::ParseContext, parsable::KdlParsable},
     },
 };
 
+/// How many files are read and parsed at the same time.
+const MAX_PARALLEL_READS: usize = 16;
+
+/// Parsed files by the hash of their content, so that a reload only parses the
+/// files that changed. Only the files of the latest collect are kept.
+static PARSED: Mutex<BTreeMap<u64, Arc<ParsedFile>>> = Mutex::new(BTreeMap::new());
+
 pub trait AsyncFs: Send + Sync + Clone + Default {
     fn canonicalize(path: &Path) -> impl Future<Output = Result<PathBuf>> + Send;
     fn read_to_string(path: &Path) -> impl Future<Output = Result<String>> + Send;
+    /// The files directly in a directory, in any order.
+    fn read_dir(path: &Path) -> impl Future<Output = Result<Vec<PathBuf>>> + Send;
 }
 
+/// Collects the entry file and everything it imports, transitively.
+///
+/// Files are read level by level, up to [`MAX_PARALLEL_READS`] at once, and come
+/// out in the same order as a depth-first walk: every file after its imports.
+///
+/// An import with `*` or `?` in its file name imports every matching file of its
+/// directory, sorted by name, and one with `if-env` only when that holds.
 #[derive(Default, Clone)]
 pub struct FileCollector<F: AsyncFs> {
     fs: PhantomData<F>,
-    documents: Vec<(KdlDocument, String)>,
-    visited_paths: HashSet<PathBuf>,
+}
+
+struct ParsedFile {
+    content: String,
+    doc: KdlDocument,
+    imports: Vec<Import>,
+}
+
+struct Import {
+    path: String,
+    if_env: Option<String>,
+}
+
+struct LoadedFile {
+    name: String,
+    hash: u64,
+    parsed: Arc<ParsedFile>,
+    /// The imports that apply, with wildcards expanded.
+    imports: Vec<PathBuf>,
 }
 
 impl<F: AsyncFs> ConfigSource for FileCollector<F> {
@@ -36,49 +71,410 @@ impl<F: AsyncFs> ConfigSource for FileCollector<F> {
 }
 
 impl<Fs: AsyncFs> FileCollector<Fs> {
-    pub async fn collect(mut self, entry_path: PathBuf) -> Result<Vec<(KdlDocument, String)>> {
+    pub async fn collect(self, entry_path: PathBuf) -> Result<Vec<(KdlDocument, String)>> {
         let root_path = Fs::canonicalize(&entry_path)
             .await
             .context("Failed to resolve entry point")?;
 
-        self.load_recursive(root_path).await?;
+        let mut files = HashMap::new();
+        let mut queued = HashSet::from([root_path.clone()]);
+        let mut level = vec![root_path.clone()];
 
-        Ok(self.documents)
-    }
+        while !level.is_empty() {
+            let loaded: Vec<_> = stream::iter(level.drain(..))
+                .map(|path| async move {
+                    let file = Self::load(&path).await;
+                    (path, file)
+                })
+                .buffered(MAX_PARALLEL_READS)
+                .collect()
+                .await;
 
-    #[async_recursion]
-    async fn load_recursive(&mut self, path: PathBuf) -> Result<()> {
-        if self.visited_paths.contains(&path) {
-            return Ok(());
+            for (path, file) in loaded {
+                let file = file?;
+
+                for import in &file.imports {
+                    if queued.insert(import.clone()) {
+                        level.push(import.clone());
+                    }
+                }
+                files.insert(path, file);
+            }
         }
-        self.visited_paths.insert(path.clone());
 
-        let content = Fs::read_to_string(&path)
+        let mut documents = Vec::with_capacity(files.len());
+        push_in_order(&root_path, &files, &mut HashSet::new(), &mut documents);
+
+        let used: HashSet<u64> = files.values().map(|file| file.hash).collect();
+        PARSED
+            .lock()
+            .expect("parsed files lock poisoned")
+            .retain(|hash, _| used.contains(hash));
+
+        Ok(documents)
+    }
+
+    async fn load(path: &Path) -> Result<LoadedFile> {
+        let content = Fs::read_to_string(path)
             .await
             .wrap_err_with(|| format!("Failed to read file: {:?}", path))?;
 
-        let doc: KdlDocument = content
-            .parse()
-            .into_diagnostic()
-            .wrap_err_with(|| format!("Failed to parse KDL: {:?}", path))?;
-
         let name = path
             .file_name()
-            .map(|s| s.to_string_lossy())
+            .map(|s| s.to_string_lossy().to_string())
             .ok_or_else(|| miette!("It's not a file: {:?}", path))?;
 
-        let raw_includes =
-            ImportsDef::parse_node(&ParseContext::new(doc.clone(), &name), &()).unwrap_or_default();
+        let hash = xxh64(content.as_bytes(), 0);
+        let cached = PARSED
+            .lock()
+            .expect("parsed files lock poisoned")
+            .get(&hash)
+            .filter(|parsed| parsed.content == content)
+            .cloned();
+
+        let parsed = match cached {
+            Some(parsed) => parsed,
+            None => {
+                let doc: KdlDocument = content
+                    .parse()
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("Failed to parse KDL: {:?}", path))?;
+
+                let imports =
+                    PartialParsedRoot::parse_node(&ParseContext::new(doc.clone(), &name), &())
+                        .ok()
+                        .and_then(|root| root.imports)
+                        .map(|imports| {
+                            imports
+                                .paths
+                                .iter()
+                                .map(|v| Import {
+                                    path: v.value.clone(),
+                                    if_env: v.if_env.clone(),
+                                })
+                                .collect()
+                        })
+                        .unwrap_or_default();
+
+                let parsed = Arc::new(ParsedFile {
+                    content,
+                    doc,
+                    imports,
+                });
+                PARSED
+                    .lock()
+                    .expect("parsed files lock poisoned")
+                    .insert(hash, Arc::clone(&parsed));
+                parsed
+            }
+        };
 
+        let imports = Self::resolve_imports(path, &parsed.imports).await?;
+
+        Ok(LoadedFile {
+            name,
+            hash,
+            parsed,
+            imports,
+        })
+    }
+
+    /// The imports of the file at `path` that apply now, canonicalized so that
+    /// a file reached through different relative paths is only loaded once.
+    ///
+    /// Resolved on every collect rather than cached with the parsed file, since
+    /// both the environment and the files matching a wildcard can change.
+    async fn resolve_imports(path: &Path, imports: &[Import]) -> Result<Vec<PathBuf>> {
         let base_dir = path.parent().unwrap_or_else(|| Path::new("."));
+        let mut resolved = Vec::with_capacity(imports.len());
+
+        for import in imports {
+            if !import.if_env.as_deref().is_none_or(env_condition_holds) {
+                continue;
+            }
+            if !has_wildcards(&import.path) {
+                let target = base_dir.join(&import.path);
+                let target = Fs::canonicalize(&target)
+                    .await
+                    .wrap_err_with(|| format!("Failed to read file: {:?}", target))?;
+                resolved.push(target);
+                continue;
+            }
+
+            let (dir, pattern) =
+                split_wildcards(&import.path).map_err(|msg| miette!("{msg}, in {:?}", path))?;
+            let dir = base_dir.join(dir);
+            let dir = Fs::canonicalize(&dir)
+                .await
+                .wrap_err_with(|| format!("Failed to list {:?} for '{}'", dir, import.path))?;
+
+            let mut matched: Vec<_> = Fs::read_dir(&dir)
+                .await
+                .wrap_err_with(|| format!("Failed to list {:?} for '{}'", dir, import.path))?
+                .into_iter()
+                .filter(|file| {
+                    file.file_name()
+                        .and_then(|name| name.to_str())
+                        .is_some_and(|name| matches_wildcards(pattern, name))
+                })
+                .collect();
+            matched.sort();
+            resolved.extend(matched);
+        }
+
+        Ok(resolved)
+    }
+}
+
+/// Whether an `if-env` condition holds: `VAR=value` when the variable has that
+/// value, a bare `VAR` when it is set at all.
+pub fn env_condition_holds(condition: &str) -> bool {
+    match condition.split_once('=') {
+        Some((var, value)) => std::env::var(var).is_ok_and(|actual| actual == value),
+        None => std::env::var_os(condition).is_some(),
+    }
+}
+
+pub fn has_wildcards(import: &str) -> bool {
+    import.contains(['*', '?'])
+}
+
+/// Splits an import with wildcards into its directory and its file name pattern.
+/// Only the file name may have wildcards.
+pub fn split_wildcards(import: &str) -> std::result::Result<(&Path, &str), String> {
+    let path = Path::new(import);
+    let pattern = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .filter(|name| has_wildcards(name));
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    match pattern {
+        Some(pattern) if !has_wildcards(&dir.to_string_lossy()) => Ok((dir, pattern)),
+        _ => Err(format!(
+            "Only the file name of import '{import}' can have wildcards"
+        )),
+    }
+}
+
+/// Whether a file name matches a pattern where `*` stands for any run of
+/// characters and `?` for a single one. As in a shell, names starting with `.`
+/// only match patterns that do too, which keeps editor swap files out.
+pub fn matches_wildcards(pattern: &str, name: &str) -> bool {
+    if name.starts_with('.') && !pattern.starts_with('.') {
+        return false;
+    }
+
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Where the last `*` was, and how much of the name it took so far.
+    let mut star = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    star = Some((star_p, star_n + 1));
+                    p = star_p + 1;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
 
-        for path_str in raw_includes.paths.iter().map(|v| v.value.clone()) {
-            let include_path = base_dir.join(path_str);
+/// Depth-first from `path`, pushing every file after the files it imports.
+fn push_in_order(
+    path: &Path,
+    files: &HashMap<PathBuf, LoadedFile>,
+    visited: &mut HashSet<PathBuf>,
+    documents: &mut Vec<(KdlDocument, String)>,
+) {
+    if !visited.insert(path.to_path_buf()) {
+        return;
+    }
+    let file = &files[path];
+
+    for import in &file.imports {
+        push_in_order(import, files, visited, documents);
+    }
+    documents.push((file.parsed.doc.clone(), file.name.clone()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct TokioFs;
 
-            self.load_recursive(include_path).await?;
+    impl AsyncFs for TokioFs {
+        async fn canonicalize(path: &Path) -> Result<PathBuf> {
+            tokio::fs::canonicalize(path).await.into_diagnostic()
         }
 
-        self.documents.push((doc, name.to_string()));
-        Ok(())
+        async fn read_to_string(path: &Path) -> Result<String> {
+            tokio::fs::read_to_string(path).await.into_diagnostic()
+        }
+
+        async fn read_dir(path: &Path) -> Result<Vec<PathBuf>> {
+            let mut entries = tokio::fs::read_dir(path).await.into_diagnostic()?;
+            let mut files = Vec::new();
+            while let Some(entry) = entries.next_entry().await.into_diagnostic()? {
+                files.push(entry.path());
+            }
+            Ok(files)
+        }
+    }
+
+    fn names(documents: &[(KdlDocument, String)]) -> Vec<&str> {
+        documents.iter().map(|(_, name)| name.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_imports_come_before_their_importer() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, content: &str| {
+            std::fs::write(dir.path().join(name), content).unwrap();
+        };
+
+        write("main.kdl", "imports {\n    \"a.kdl\"\n    \"b.kdl\"\n}\n");
+        write("a.kdl", "imports {\n    \"c.kdl\"\n}\n");
+        write("b.kdl", "imports {\n    \"c.kdl\"\n    \"main.kdl\"\n}\n");
+        write("c.kdl", "system {\n}\n");
+
+        let documents = FileCollector::<TokioFs>::default()
+            .collect(dir.path().join("main.kdl"))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            names(&documents),
+            vec!["c.kdl", "a.kdl", "b.kdl", "main.kdl"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_parsed_files_are_reused() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cached-main.kdl");
+        std::fs::write(&path, "system {\n    threads-per-service 3\n}\n").unwrap();
+
+        let cached = |content: &str| {
+            let hash = xxh64(content.as_bytes(), 0);
+            PARSED.lock().unwrap().get(&hash).cloned()
+        };
+
+        FileCollector::<TokioFs>::default()
+            .collect(path.clone())
+            .await
+            .unwrap();
+        let first = cached("system {\n    threads-per-service 3\n}\n");
+
+        FileCollector::<TokioFs>::default()
+            .collect(path.clone())
+            .await
+            .unwrap();
+        let second = cached("system {\n    threads-per-service 3\n}\n");
+
+        let (Some(first), Some(second)) = (first, second) else {
+            // Another test pruned the cache in between.
+            return;
+        };
+        assert!(Arc::ptr_eq(&first, &second));
+    }
+
+    #[tokio::test]
+    async fn test_missing_import() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("main.kdl"),
+            "imports {\n    \"missing.kdl\"\n}\n",
+        )
+        .unwrap();
+
+        let err = FileCollector::<TokioFs>::default()
+            .collect(dir.path().join("main.kdl"))
+            .await
+            .unwrap_err();
+
+        assert!(err.to_string().contains("missing.kdl"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_wildcard_imports_are_sorted() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, content: &str| {
+            std::fs::write(dir.path().join(name), content).unwrap();
+        };
+
+        std::fs::create_dir(dir.path().join("conf.d")).unwrap();
+        write(
+            "main.kdl",
+            "imports {\n    \"conf.d/*.kdl\"\n    \"*.kdl\"\n}\n",
+        );
+        write("other.kdl", "system {\n}\n");
+        write("conf.d/20-b.kdl", "system {\n}\n");
+        write("conf.d/10-a.kdl", "imports {\n    \"../main.kdl\"\n}\n");
+        write("conf.d/30-c.kdl.bak", "not kdl {");
+        write("conf.d/.40-d.kdl", "not kdl {");
+
+        let documents = FileCollector::<TokioFs>::default()
+            .collect(dir.path().join("main.kdl"))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            names(&documents),
+            vec!["10-a.kdl", "20-b.kdl", "other.kdl", "main.kdl"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_conditional_imports() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, content: &str| {
+            std::fs::write(dir.path().join(name), content).unwrap();
+        };
+
+        std::env::set_var("MOTYA_TEST_IMPORTS_ENV", "dev");
+        write(
+            "main.kdl",
+            "imports {\n    \"dev.kdl\" if-env=\"MOTYA_TEST_IMPORTS_ENV=dev\"\n    \"prod.kdl\" if-env=\"MOTYA_TEST_IMPORTS_ENV=prod\"\n    \"set.kdl\" if-env=\"MOTYA_TEST_IMPORTS_ENV\"\n}\n",
+        );
+        write("dev.kdl", "system {\n}\n");
+        write("set.kdl", "system {\n}\n");
+
+        let documents = FileCollector::<TokioFs>::default()
+            .collect(dir.path().join("main.kdl"))
+            .await
+            .unwrap();
+
+        assert_eq!(names(&documents), vec!["dev.kdl", "set.kdl", "main.kdl"]);
+    }
+
+    #[test]
+    fn test_matches_wildcards() {
+        assert!(matches_wildcards("*.kdl", "a.kdl"));
+        assert!(!matches_wildcards("*.kdl", ".hidden.kdl"));
+        assert!(matches_wildcards("a*b*c", "aXbYbZc"));
+        assert!(matches_wildcards("??.kdl", "10.kdl"));
+        assert!(!matches_wildcards("??.kdl", "100.kdl"));
+        assert!(!matches_wildcards("*.kdl", "a.kdl.bak"));
+
+        assert!(split_wildcards("conf.d/*.kdl").is_ok());
+        assert!(split_wildcards("conf.*/a.kdl").is_err());
     }
 }
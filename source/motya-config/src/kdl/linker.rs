@@ -1,9 +1,18 @@
+use std::collections::BTreeMap;
+
+use http::HeaderValue;
+
 use crate::{
     common_types::{
+        access_log::AccessLogConfig,
+        compression::CompressionAlgorithm,
         definitions_table::DefinitionsTable,
         error::ConfigError,
+        error_pages::ErrorPagesConfig,
         file_server::FileServerConfig,
+        limits::LimitsConfig,
         listeners::{ListenerConfig, Listeners},
+        maintenance::MaintenanceConfig,
         system_data::SystemData,
     },
     internal::{Config, ProxyConfig},
@@ -11,6 +20,7 @@ use crate::{
         connectors::ConnectorsLinker,
         definitions::DefinitionsCompiler,
         models::{
+            file_server::FileServerDef,
             listeners::ListenersDef,
             root::RootDef,
             services::{ServiceDef, ServiceModeData},
@@ -31,7 +41,9 @@ impl<'a> ConfigLinker<'a> {
         }
     }
 
-    pub fn link(mut self, roots: Vec<RootDef>) -> Result<Config, ConfigError> {
+    /// Compiles `roots` into one configuration, along with the errors and the
+    /// warnings found on the way. There is no configuration when there are errors.
+    pub fn link(mut self, roots: Vec<RootDef>) -> (Option<Config>, ConfigError) {
         let mut final_config = Config::default();
 
         let mut system_defined = false;
@@ -54,6 +66,12 @@ impl<'a> ConfigLinker<'a> {
                             final_config.daemonize = sys_data.daemonize;
                             final_config.upgrade_socket = sys_data.upgrade_socket;
                             final_config.pid_file = sys_data.pid_file;
+                            final_config.production = sys_data.production;
+                            final_config.config_version_header = sys_data.config_version_header;
+                            final_config.admin = sys_data.admin;
+                            final_config.metrics_listener = sys_data.metrics_listener;
+                            final_config.shutdown_grace = sys_data.shutdown_grace;
+                            final_config.acme = sys_data.acme;
                             // final_config.provider = sys_data.provider;
                         }
                         Err(e) => {
@@ -73,17 +91,20 @@ impl<'a> ConfigLinker<'a> {
         }
 
         if !self.errors.is_empty() {
-            return Err(self.errors);
+            return (None, self.errors);
         }
 
-        for root in &roots {
-            if let Some(defs_def) = &root.definitions {
-                if let Some(modifiers) = &defs_def.modifiers {
-                    let (data, _) = modifiers.clone().into_parts();
-                    DefinitionsCompiler.compile_modifiers(data, self.table, &mut self.errors);
-                }
-            }
-        }
+        let modifiers = roots
+            .iter()
+            .filter_map(|root| root.definitions.as_ref()?.modifiers.clone())
+            .map(|modifiers| modifiers.into_inner())
+            .collect();
+        DefinitionsCompiler.compile_modifiers(
+            modifiers,
+            self.table,
+            final_config.production,
+            &mut self.errors,
+        );
 
         for root in roots {
             for services_section in root.services.clone() {
@@ -96,9 +117,9 @@ impl<'a> ConfigLinker<'a> {
         }
 
         if !self.errors.is_empty() {
-            Err(self.errors)
+            (None, self.errors)
         } else {
-            Ok(final_config)
+            (Some(final_config), self.errors)
         }
     }
 
@@ -123,18 +144,54 @@ impl<'a> ConfigLinker<'a> {
     }
 
     fn compile_service(&mut self, service_def: ServiceDef, config: &mut Config) {
-        let (data, _) = service_def.into_parts();
+        let (data, ctx) = service_def.into_parts();
         let name = data.name;
 
         let (listeners, l_err) = self.compile_listeners(data.listeners);
 
         self.errors.merge(l_err);
 
+        let access_log = match data.access_log.map(AccessLogConfig::try_from).transpose() {
+            Ok(access_log) => access_log,
+            Err(e) => {
+                self.errors.push_report(e, &ctx.ctx);
+                None
+            }
+        };
+
+        let limits = match data.limits.map(LimitsConfig::try_from).transpose() {
+            Ok(limits) => limits,
+            Err(e) => {
+                self.errors.push_report(e, &ctx.ctx);
+                None
+            }
+        };
+
+        let error_pages = match data.error_pages.map(ErrorPagesConfig::try_from).transpose() {
+            Ok(error_pages) => error_pages,
+            Err(e) => {
+                self.errors.push_report(e, &ctx.ctx);
+                None
+            }
+        };
+
+        let maintenance = match data
+            .maintenance
+            .map(MaintenanceConfig::try_from)
+            .transpose()
+        {
+            Ok(maintenance) => maintenance,
+            Err(e) => {
+                self.errors.push_report(e, &ctx.ctx);
+                None
+            }
+        };
+
         let mode = data.mode.into_inner();
 
         match mode {
             ServiceModeData::Connectors(connectors_def) => {
-                let connectors_linker = ConnectorsLinker::new(self.table);
+                let connectors_linker = ConnectorsLinker::new(self.table, config.production);
 
                 let (connectors, c_err) = connectors_linker.link(connectors_def);
                 self.errors.merge(c_err);
@@ -142,18 +199,258 @@ impl<'a> ConfigLinker<'a> {
                 config.basic_proxies.push(ProxyConfig {
                     name,
                     listeners,
+                    access_log,
+                    limits,
+                    error_pages,
+                    maintenance,
                     connectors,
                 });
             }
             ServiceModeData::FileServer(fs_def) => {
-                let fs_data = fs_def;
+                if access_log.is_some() {
+                    self.errors.push_report(
+                        ctx.err_access_log("'access-log' is only supported by proxy services, not by 'file-server'"),
+                        &ctx.ctx,
+                    );
+                }
+                if limits.is_some() {
+                    self.errors.push_report(
+                        ctx.err_limits(
+                            "'limits' is only supported by proxy services, not by 'file-server'",
+                        ),
+                        &ctx.ctx,
+                    );
+                }
+                if error_pages.is_some() {
+                    self.errors.push_report(
+                        ctx.err_error_pages(
+                            "'error-pages' is only supported by proxy services, not by 'file-server'",
+                        ),
+                        &ctx.ctx,
+                    );
+                }
 
-                config.file_servers.push(FileServerConfig {
-                    name,
-                    listeners,
-                    base_path: fs_data.root,
-                });
+                if maintenance.is_some() {
+                    self.errors.push_report(
+                        ctx.err_maintenance(
+                            "'maintenance' is only supported by proxy services, not by 'file-server'",
+                        ),
+                        &ctx.ctx,
+                    );
+                }
+
+                let file_server = self.compile_file_server(name, listeners, fs_def);
+                config.file_servers.push(file_server);
+            }
+        }
+    }
+
+    fn compile_file_server(
+        &mut self,
+        name: String,
+        listeners: Listeners,
+        def: FileServerDef,
+    ) -> FileServerConfig {
+        let (data, ctx) = def.into_parts();
+
+        if let Some(cache_control) = &data.cache_control {
+            if HeaderValue::from_str(cache_control).is_err() {
+                self.errors.push_report(
+                    ctx.err_cache_control(format!(
+                        "'{cache_control}' is not a valid Cache-Control value"
+                    )),
+                    &ctx.ctx,
+                );
+            }
+        }
+
+        let mut index_files = Vec::new();
+        if let Some(index_def) = data.index_files {
+            let (index_data, index_ctx) = index_def.into_parts();
+            if index_data.names.is_empty() {
+                self.errors.push_report(
+                    index_ctx.err_names(
+                        "'index-files' must list at least one file name, e.g. index-files \"index.html\"",
+                    ),
+                    &index_ctx.ctx,
+                );
+            }
+            for name in index_data.names {
+                match name.as_str() {
+                    Ok(file) if file.is_empty() || file.contains('/') => {
+                        self.errors.push_report(
+                            index_ctx.err_names(format!(
+                                "'{file}' is not a file name, index files are looked up in the requested directory"
+                            )),
+                            &index_ctx.ctx,
+                        );
+                    }
+                    Ok(file) => index_files.push(file),
+                    Err(e) => self.errors.push_report(e, &index_ctx.ctx),
+                }
+            }
+        }
+
+        let mut mime_types = BTreeMap::new();
+        if let Some(mime_def) = data.mime_types {
+            let (mime_data, _) = mime_def.into_parts();
+            for mime_type in mime_data.types.into_values() {
+                let (type_data, type_ctx) = mime_type.into_parts();
+                let extension = type_data.extension.trim_start_matches('.').to_lowercase();
+                if extension.is_empty() {
+                    self.errors.push_report(
+                        type_ctx
+                            .err_extension("Expected a file extension, e.g. svg \"image/svg+xml\""),
+                        &type_ctx.ctx,
+                    );
+                    continue;
+                }
+                let valid = type_data
+                    .mime
+                    .split_once('/')
+                    .is_some_and(|(kind, subtype)| !kind.is_empty() && !subtype.is_empty())
+                    && HeaderValue::from_str(&type_data.mime).is_ok();
+                if !valid {
+                    self.errors.push_report(
+                        type_ctx.err_mime(format!(
+                            "'{}' is not a content type, expected e.g. \"image/svg+xml\"",
+                            type_data.mime
+                        )),
+                        &type_ctx.ctx,
+                    );
+                    continue;
+                }
+                mime_types.insert(extension, type_data.mime);
             }
         }
+
+        let mut precompressed = Vec::new();
+        if let Some(precompressed_def) = data.precompressed {
+            let (precompressed_data, precompressed_ctx) = precompressed_def.into_parts();
+            if precompressed_data.algorithms.is_empty() {
+                self.errors.push_report(
+                    precompressed_ctx.err_algorithms(
+                        "'precompressed' must list at least one encoding, e.g. precompressed \"br\" \"gzip\"",
+                    ),
+                    &precompressed_ctx.ctx,
+                );
+            }
+            for algorithm in precompressed_data.algorithms {
+                match algorithm.parse_as::<CompressionAlgorithm>() {
+                    Ok(algorithm) if precompressed.contains(&algorithm) => {}
+                    Ok(algorithm) => precompressed.push(algorithm),
+                    Err(e) => self.errors.push_report(e, &precompressed_ctx.ctx),
+                }
+            }
+        }
+
+        FileServerConfig {
+            name,
+            listeners,
+            base_path: data.root,
+            index_files,
+            autoindex: data.autoindex.unwrap_or(false),
+            mime_types,
+            cache_control: data.cache_control,
+            precompressed,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::loader::test_support::{load, load_errors};
+
+    #[tokio::test]
+    async fn test_file_server_options() {
+        use crate::common_types::compression::CompressionAlgorithm;
+
+        let services = r#"
+            services {
+                Static {
+                    listeners { "0.0.0.0:8080" }
+                    file-server root="/var/www" autoindex=#true cache-control="public, max-age=3600" {
+                        index-files "index.html" "index.htm"
+                        precompressed "br" "gzip"
+                        mime-types {
+                            wasm "application/wasm"
+                            ".MJS" "text/javascript"
+                        }
+                    }
+                }
+            }
+        "#;
+        let config = load(vec![("main.kdl", services)]).await;
+
+        let file_server = &config.file_servers[0];
+        assert_eq!(file_server.index_files, ["index.html", "index.htm"]);
+        assert!(file_server.autoindex);
+        assert_eq!(
+            file_server.precompressed,
+            [CompressionAlgorithm::Brotli, CompressionAlgorithm::Gzip]
+        );
+        assert_eq!(
+            file_server.cache_control.as_deref(),
+            Some("public, max-age=3600")
+        );
+        assert_eq!(
+            file_server.mime_types,
+            BTreeMap::from([
+                ("mjs".to_string(), "text/javascript".to_string()),
+                ("wasm".to_string(), "application/wasm".to_string()),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_file_server_errors() {
+        let services = r#"
+            services {
+                Static {
+                    listeners { "0.0.0.0:8080" }
+                    file-server root="/var/www" cache-control="no-cache\n" {
+                        index-files "pages/index.html"
+                        precompressed "gzip" "deflate"
+                        mime-types {
+                            svg "svg"
+                        }
+                    }
+                }
+            }
+        "#;
+        let errors = load_errors(vec![("main.kdl", services)]).await;
+        let messages: Vec<_> = errors.errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages.len(), 4, "{messages:?}");
+        assert!(messages[0].contains("not a valid Cache-Control value"));
+        assert!(messages[1].contains("'pages/index.html' is not a file name"));
+        assert!(messages[2].contains("'svg' is not a content type"));
+        assert!(messages[3].contains("Unknown compression algorithm 'deflate'"));
+    }
+
+    #[tokio::test]
+    async fn test_file_server_duplicate_mime_type() {
+        let services = r#"
+            services {
+                Static {
+                    listeners { "0.0.0.0:8080" }
+                    file-server root="/var/www" {
+                        mime-types {
+                            wasm "application/wasm"
+                            wasm "application/octet-stream"
+                        }
+                    }
+                }
+            }
+        "#;
+        let errors = load_errors(vec![("main.kdl", services)]).await;
+        assert_eq!(errors.count, 1);
+        let error = &errors.errors[0];
+        assert_eq!(error.message, "Duplicate 'wasm'");
+
+        let span = error.label.expect("Error should point at the duplicate");
+        let duplicate = services.rfind("wasm").unwrap();
+        assert_eq!(span.offset(), duplicate);
     }
 }
@@ -4,4 +4,5 @@ pub mod fs_loader;
 pub mod linker;
 pub mod models;
 pub mod parser;
+pub mod profiles;
 pub mod schema;
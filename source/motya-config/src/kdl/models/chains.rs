@@ -24,17 +24,32 @@ pub enum ChainItemDef {
     Filter(ConfiguredFilterDef),
     #[node(name = "rate-limit")]
     RateLimit(RateLimitDef),
+    #[node(name = "basic-auth")]
+    BasicAuth(BasicAuthDef),
+    #[node(name = "use")]
+    Use(UseDef),
+}
+
+/// Runs the items of another chain-filters definition in its place.
+#[motya_node]
+#[derive(Parser, Clone, Debug, NodeSchema)]
+#[node(name = "use")]
+pub struct UseDef {
+    #[node(arg)]
+    pub name: String,
 }
 
 #[motya_node]
 #[derive(Parser, Clone, Debug, NodeSchema)]
 #[node(name = "use-chain")]
 pub enum UseChainDef {
+    /// Runs the filters of the chain-filters definition with this name.
     Reference {
         #[node(arg)]
         name: String,
     },
 
+    /// Runs the filters listed in the block in order.
     Inline {
         #[node(dynamic_child)]
         items: Vec<ChainItemDef>,
@@ -49,8 +64,17 @@ pub enum RateLimitDef {
         #[node(arg)]
         #[err(name = "ref")]
         String,
+        #[node(prop, name = "when")]
+        #[err(name = "when", prop = "when")]
+        Option<String>,
     ),
     Inline {
+        #[node(prop)]
+        when: Option<String>,
+
+        #[node(prop, name = "expose-headers")]
+        expose_headers: Option<bool>,
+
         #[node(child)]
         algorithm: String,
 
@@ -70,3 +94,17 @@ pub enum RateLimitDef {
         raw_rate: f64,
     },
 }
+
+#[motya_node]
+#[derive(Parser, Clone, Debug, NodeSchema)]
+#[node(name = "basic-auth")]
+pub struct BasicAuthDef {
+    #[node(arg)]
+    pub credentials: String,
+
+    #[node(prop)]
+    pub realm: Option<String>,
+
+    #[node(prop)]
+    pub when: Option<String>,
+}
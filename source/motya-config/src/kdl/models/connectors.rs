@@ -1,14 +1,21 @@
-use std::net::SocketAddr;
-
 use http::{uri::PathAndQuery, Uri};
 use motya_macro::{motya_node, NodeSchema, Parser};
 
 use crate::{
-    common_types::{balancer::SelectionKind, connectors::RoutingMode},
-    kdl::models::{
-        chains::UseChainDef,
-        key_profile::{HashAlgDef, KeyDef},
-        transforms_order::TransformsOrderDef,
+    common_types::{
+        balancer::SelectionKind,
+        byte_size::ByteSize,
+        compression::ContentTypePattern,
+        connectors::{CertPins, RouteMethods, RouteQuery, RoutingMode, ServerAddress},
+        duration::Duration,
+    },
+    kdl::{
+        models::{
+            chains::UseChainDef,
+            key_profile::{HashAlgDef, KeyDef},
+            transforms_order::TransformsOrderDef,
+        },
+        parser::typed_value::TypedValue,
     },
 };
 
@@ -22,6 +29,12 @@ use crate::{
 pub struct ConnectorsDef {
     #[node(child, name = "section")]
     pub sections: Vec<SectionDef>,
+
+    #[node(child)]
+    pub compression: Option<CompressionDef>,
+
+    #[node(child)]
+    pub timeout: Option<TimeoutDef>,
 }
 
 // =============================================================================
@@ -38,12 +51,42 @@ pub struct SectionDef {
     #[node(prop, name = "as")]
     pub routing_mode: Option<RoutingMode>,
 
+    #[node(prop, name = "allow-upgrades")]
+    pub allow_upgrades: Option<bool>,
+
+    #[node(prop, name = "debug-trace")]
+    pub debug_trace: Option<bool>,
+
+    #[node(prop)]
+    pub method: Option<RouteMethods>,
+
+    #[node(prop)]
+    pub query: Option<RouteQuery>,
+
     #[node(child)]
     pub leaf: ConnectorLeafDef,
 
     #[node(child, name = "load-balance")]
     pub load_balance: Option<LoadBalanceDef>,
 
+    #[node(child)]
+    pub compression: Option<CompressionDef>,
+
+    #[node(child)]
+    pub sse: Option<SseDef>,
+
+    #[node(child)]
+    pub cache: Option<CacheDef>,
+
+    #[node(child)]
+    pub mirror: Option<MirrorDef>,
+
+    #[node(child)]
+    pub timeout: Option<TimeoutDef>,
+
+    #[node(child)]
+    pub rewrite: Option<RewriteDef>,
+
     #[node(child, min = 0)]
     pub chains: Vec<UseChainDef>,
 
@@ -70,7 +113,7 @@ pub enum ConnectorLeafDef {
 
 #[motya_node]
 #[derive(Parser, Clone, Debug, NodeSchema)]
-#[node(name = "proxy")]
+#[node(name = "proxy", allow_empty)]
 pub enum ProxyDef {
     Single {
         #[node(arg)]
@@ -80,6 +123,17 @@ pub enum ProxyDef {
         tls_sni: Option<String>,
         #[node(prop)]
         proto: Option<String>,
+        #[node(prop)]
+        grpc: Option<bool>,
+
+        #[node(child)]
+        retry: Option<RetryDef>,
+
+        #[node(child)]
+        tls: Option<UpstreamTlsDef>,
+
+        #[node(child)]
+        h2: Option<H2Def>,
     },
 
     Multi {
@@ -90,6 +144,39 @@ pub enum ProxyDef {
         tls_sni: Option<String>,
         #[node(prop)]
         proto: Option<String>,
+        #[node(prop)]
+        grpc: Option<bool>,
+
+        #[node(child)]
+        retry: Option<RetryDef>,
+
+        #[node(child)]
+        tls: Option<UpstreamTlsDef>,
+
+        #[node(child)]
+        h2: Option<H2Def>,
+    },
+
+    Group {
+        #[node(prop, name = "use-group")]
+        #[err(prop = "use-group")]
+        name: String,
+
+        #[node(prop, name = "tls-sni")]
+        tls_sni: Option<String>,
+        #[node(prop)]
+        proto: Option<String>,
+        #[node(prop)]
+        grpc: Option<bool>,
+
+        #[node(child)]
+        retry: Option<RetryDef>,
+
+        #[node(child)]
+        tls: Option<UpstreamTlsDef>,
+
+        #[node(child)]
+        h2: Option<H2Def>,
     },
 }
 
@@ -98,11 +185,53 @@ pub enum ProxyDef {
 #[node(name = "server")]
 pub struct UpstreamServerDef {
     #[node(arg)]
-    pub address: SocketAddr,
+    pub address: ServerAddress,
     #[node(prop)]
     pub weight: Option<usize>,
 }
 
+#[motya_node]
+#[derive(Parser, Clone, Debug, NodeSchema)]
+#[node(name = "retry")]
+pub struct RetryDef {
+    #[node(prop, min = 1, max = 10)]
+    pub attempts: usize,
+    #[node(prop)]
+    pub on: Option<String>,
+    #[node(prop)]
+    pub backoff: Option<Duration>,
+    #[node(prop, name = "max-concurrent")]
+    pub max_concurrent: Option<usize>,
+}
+
+#[motya_node]
+#[derive(Parser, Clone, Debug, NodeSchema)]
+#[node(name = "tls")]
+pub struct UpstreamTlsDef {
+    #[node(prop, name = "verify-cert")]
+    pub verify_cert: Option<bool>,
+    #[node(prop, name = "ca-path")]
+    pub ca_path: Option<String>,
+    #[node(prop, name = "client-cert")]
+    pub client_cert: Option<String>,
+    #[node(prop, name = "client-key")]
+    pub client_key: Option<String>,
+    #[node(prop, name = "cert-sha256")]
+    pub cert_sha256: Option<CertPins>,
+}
+
+#[motya_node]
+#[derive(Parser, Clone, Debug, NodeSchema)]
+#[node(name = "h2")]
+pub struct H2Def {
+    #[node(prop, name = "max-streams", min = 1)]
+    pub max_streams: Option<usize>,
+    #[node(prop, name = "ping-interval")]
+    pub ping_interval: Option<Duration>,
+    #[node(prop, name = "idle-timeout")]
+    pub idle_timeout: Option<Duration>,
+}
+
 // =============================================================================
 // RETURN
 // =============================================================================
@@ -117,10 +246,144 @@ pub struct ReturnDef {
     pub body: Option<String>,
 }
 
+// =============================================================================
+// COMPRESSION
+// =============================================================================
+
+#[motya_node]
+#[derive(Parser, Clone, Debug, NodeSchema)]
+#[node(name = "compression")]
+pub struct CompressionDef {
+    #[node(prop)]
+    pub level: Option<usize>,
+
+    #[node(prop)]
+    pub algorithms: Option<String>,
+
+    #[node(prop, name = "min-size")]
+    pub min_size: Option<ByteSize>,
+
+    #[node(prop, name = "content-types")]
+    pub content_types: Option<String>,
+
+    #[node(prop, name = "default-exclusions")]
+    pub default_exclusions: Option<bool>,
+
+    #[node(child)]
+    pub exclude: Option<ExcludeContentTypesDef>,
+}
+
+#[motya_node]
+#[derive(Parser, Clone, Debug, NodeSchema)]
+#[node(name = "exclude")]
+pub struct ExcludeContentTypesDef {
+    #[node(dynamic_child)]
+    pub types: Vec<ContentTypeDef>,
+}
+
+#[motya_node]
+#[derive(Parser, Clone, Debug, NodeSchema)]
+pub struct ContentTypeDef {
+    #[node(node_name)]
+    pub value: ContentTypePattern,
+}
+
+// =============================================================================
+// SERVER-SENT EVENTS
+// =============================================================================
+
+#[motya_node]
+#[derive(Parser, Clone, Debug, NodeSchema)]
+#[node(name = "sse")]
+pub struct SseDef {
+    #[node(arg)]
+    pub enabled: bool,
+
+    #[node(prop, name = "idle-timeout")]
+    pub idle_timeout: Option<Duration>,
+}
+
+// =============================================================================
+// RESPONSE CACHE
+// =============================================================================
+
+#[motya_node]
+#[derive(Parser, Clone, Debug, NodeSchema)]
+#[node(name = "cache")]
+pub struct CacheDef {
+    #[node(prop)]
+    pub ttl: Duration,
+
+    #[node(prop, name = "max-body")]
+    pub max_body: Option<ByteSize>,
+
+    #[node(prop, name = "debug-headers")]
+    pub debug_headers: Option<bool>,
+
+    #[node(child)]
+    pub bypass_from: Option<CacheBypassDef>,
+}
+
+#[derive(Parser, Clone, Debug, NodeSchema)]
+#[node(name = "bypass-from")]
+pub struct CacheBypassDef {
+    #[node(all_args)]
+    pub networks: Vec<TypedValue>,
+}
+
+// =============================================================================
+// MIRROR
+// =============================================================================
+
+#[motya_node]
+#[derive(Parser, Clone, Debug, NodeSchema)]
+#[node(name = "mirror")]
+pub struct MirrorDef {
+    #[node(arg)]
+    pub url: Uri,
+
+    #[node(prop)]
+    pub sample: Option<f64>,
+}
+
+// =============================================================================
+// TIMEOUT
+// =============================================================================
+
+#[motya_node]
+#[derive(Parser, Clone, Debug, NodeSchema)]
+#[node(name = "timeout")]
+pub struct TimeoutDef {
+    #[node(prop)]
+    pub request: Option<Duration>,
+
+    #[node(prop, name = "read-header")]
+    pub read_header: Option<Duration>,
+}
+
+// =============================================================================
+// REWRITE
+// =============================================================================
+
+#[motya_node]
+#[derive(Parser, Clone, Debug, NodeSchema)]
+#[node(name = "rewrite")]
+pub struct RewriteDef {
+    #[node(prop, name = "strip-prefix")]
+    pub strip_prefix: Option<bool>,
+
+    #[node(prop)]
+    pub pattern: Option<String>,
+
+    #[node(prop)]
+    pub to: Option<String>,
+}
+
 // =============================================================================
 // LOAD BALANCE & OTHERS
 // =============================================================================
 
+/// How requests are spread over the servers of the upstream.
 #[motya_node]
 #[derive(Parser, Clone, Debug, NodeSchema)]
 #[node(name = "load-balance")]
@@ -129,10 +392,70 @@ pub struct LoadBalanceDef {
     pub selection: Option<SelectionDef>,
 
     #[node(child, name = "health-check")]
-    pub health_check: Option<String>,
+    pub health_check: Option<HealthCheckDef>,
+
+    #[node(child)]
+    pub discovery: Option<DiscoveryDef>,
+
+    #[node(child, flat, name = "slow-start")]
+    pub slow_start: Option<Duration>,
+
+    #[node(child, name = "outlier-detection")]
+    pub outlier_detection: Option<OutlierDetectionDef>,
+}
+
+#[motya_node]
+#[derive(Parser, Clone, Debug, NodeSchema)]
+#[node(name = "outlier-detection")]
+pub struct OutlierDetectionDef {
+    #[node(prop, min = 1)]
+    pub errors: Option<u32>,
+
+    #[node(prop)]
+    pub window: Option<Duration>,
+
+    #[node(prop)]
+    pub ejection: Option<Duration>,
+}
 
-    #[node(child, name = "discovery")]
-    pub discovery: Option<String>,
+#[motya_node]
+#[derive(Parser, Clone, Debug, NodeSchema)]
+#[node(name = "discovery")]
+pub struct DiscoveryDef {
+    #[node(arg)]
+    pub kind: String,
+
+    #[node(prop)]
+    pub refresh: Option<Duration>,
+
+    #[node(prop)]
+    pub service: Option<String>,
+
+    #[node(prop)]
+    pub namespace: Option<String>,
+
+    #[node(prop)]
+    pub port: Option<String>,
+}
+
+#[motya_node]
+#[derive(Parser, Clone, Debug, NodeSchema)]
+#[node(name = "health-check")]
+pub struct HealthCheckDef {
+    #[node(arg)]
+    pub kind: String,
+
+    #[node(child, flat)]
+    pub interval: Option<Duration>,
+
+    #[node(child, flat)]
+    pub timeout: Option<Duration>,
+
+    #[node(child)]
+    pub send: Option<String>,
+
+    #[node(child)]
+    pub expect: Option<String>,
 }
 
 #[motya_node]
@@ -1,13 +1,13 @@
 use std::path::PathBuf;
 
 use fqdn::FQDN;
-use humantime::Duration;
 use motya_macro::{motya_node, NodeSchema, Parser};
 
 use crate::{
-    common_types::key_template::KeyTemplate,
+    common_types::{duration::Duration, key_template::KeyTemplate, rate_limiter::RateLimitScope},
     kdl::models::{
         chains::ChainItemDef,
+        connectors::UpstreamServerDef,
         key_profile::{HashAlgDef, KeyDef},
         transforms_order::TransformsOrderDef,
     },
@@ -35,6 +35,15 @@ pub struct DefinitionsDef {
 
     #[node(child, name = "rate-limits")]
     pub rate_limits: Option<RateLimitsSectionDef>,
+
+    #[node(child, name = "upstream-groups")]
+    pub upstream_groups: Option<UpstreamGroupsSectionDef>,
+
+    #[node(child)]
+    pub credentials: Option<CredentialsSectionDef>,
+
+    #[node(child)]
+    pub secrets: Option<SecretsSectionDef>,
 }
 
 // =============================================================================
@@ -106,8 +115,12 @@ pub struct PluginDef {
 
     #[node(child)]
     pub load: PluginLoadDef,
+
+    #[node(child, name = "request-body")]
+    pub request_body: Option<PluginRequestBodyDef>,
 }
 
+#[motya_node]
 #[derive(Parser, Clone, Debug, NodeSchema)]
 #[node(name = "load")]
 pub struct PluginLoadDef {
@@ -115,6 +128,16 @@ pub struct PluginLoadDef {
     pub path: Option<PathBuf>,
     #[node(prop)]
     pub url: Option<String>,
+    #[node(prop)]
+    pub sha256: Option<String>,
+}
+
+#[motya_node]
+#[derive(Parser, Clone, Debug, NodeSchema)]
+#[node(name = "request-body")]
+pub struct PluginRequestBodyDef {
+    #[node(prop, name = "max-size")]
+    pub max_size: usize,
 }
 
 // =============================================================================
@@ -194,12 +217,24 @@ pub struct RedisStorageDef {
     pub addresses: Vec<String>,
 
     #[node(child)]
-    pub password: Option<String>,
+    pub password: Option<PasswordDef>,
 
     #[node(child, flat)]
     pub timeout: Option<Duration>,
 }
 
+/// The password of a redis storage, written inline or read from a secret.
+#[motya_node]
+#[derive(Parser, Clone, Debug, NodeSchema)]
+#[node(name = "password")]
+pub struct PasswordDef {
+    #[node(arg)]
+    pub value: Option<String>,
+
+    #[node(prop)]
+    pub secret: Option<String>,
+}
+
 #[motya_node]
 #[derive(Parser, Clone, Debug, NodeSchema)]
 pub struct MemoryStorageDef {
@@ -232,6 +267,12 @@ pub struct RateLimitPolicyDef {
     #[node(arg)]
     pub name: String,
 
+    #[node(prop, name = "expose-headers")]
+    pub expose_headers: Option<bool>,
+
+    #[node(prop)]
+    pub scope: Option<RateLimitScope>,
+
     #[node(child)]
     pub algorithm: Option<String>,
 
@@ -247,6 +288,149 @@ pub struct RateLimitPolicyDef {
     #[node(child)]
     pub burst: Option<usize>,
 
+    #[node(child, name = "max-keys")]
+    pub max_keys: Option<usize>,
+
     #[node(child, name = "transforms-order")]
     pub transforms: Option<TransformsOrderDef>,
 }
+
+// =============================================================================
+// UPSTREAM GROUPS SECTION
+// =============================================================================
+
+#[motya_node]
+#[derive(Parser, Clone, Debug, NodeSchema)]
+#[node(name = "upstream-groups")]
+pub struct UpstreamGroupsSectionDef {
+    #[node(child)]
+    pub groups: Vec<UpstreamGroupDef>,
+}
+
+#[motya_node]
+#[derive(Parser, Clone, Debug, NodeSchema)]
+#[node(name = "group")]
+pub struct UpstreamGroupDef {
+    #[node(arg)]
+    #[err(arg = 0)]
+    pub name: String,
+
+    #[node(child)]
+    pub servers: Vec<UpstreamServerDef>,
+}
+
+// =============================================================================
+// CREDENTIALS SECTION
+// =============================================================================
+
+#[motya_node]
+#[derive(Parser, Clone, Debug, NodeSchema)]
+#[node(name = "credentials")]
+pub struct CredentialsSectionDef {
+    #[node(child)]
+    pub sources: Vec<CredentialsDef>,
+}
+
+#[motya_node]
+#[derive(Parser, Clone, Debug, NodeSchema)]
+pub enum CredentialsDef {
+    #[node(name = "htpasswd")]
+    Htpasswd(HtpasswdDef),
+    #[node(name = "users")]
+    Users(UsersDef),
+}
+
+#[motya_node]
+#[derive(Parser, Clone, Debug, NodeSchema)]
+pub struct HtpasswdDef {
+    #[node(arg)]
+    pub name: String,
+
+    #[node(prop)]
+    pub path: PathBuf,
+}
+
+#[motya_node]
+#[derive(Parser, Clone, Debug, NodeSchema)]
+pub struct UsersDef {
+    #[node(arg)]
+    pub name: String,
+
+    #[node(child, name = "user")]
+    pub users: Vec<UserDef>,
+}
+
+#[motya_node]
+#[derive(Parser, Clone, Debug, NodeSchema)]
+#[node(name = "user")]
+pub struct UserDef {
+    #[node(arg)]
+    pub name: String,
+
+    #[node(arg)]
+    pub hash: Option<String>,
+
+    /// Reads the hash from the secret with this name instead.
+    #[node(prop)]
+    pub secret: Option<String>,
+}
+
+// =============================================================================
+// SECRETS SECTION
+// =============================================================================
+
+/// Named values kept out of the config, read when the server starts.
+#[motya_node]
+#[derive(Parser, Clone, Debug, NodeSchema)]
+#[node(name = "secrets")]
+pub struct SecretsSectionDef {
+    #[node(child)]
+    pub sources: Vec<SecretDef>,
+}
+
+#[motya_node]
+#[derive(Parser, Clone, Debug, NodeSchema)]
+pub enum SecretDef {
+    #[node(name = "env")]
+    Env(EnvSecretDef),
+    #[node(name = "file")]
+    File(FileSecretDef),
+    #[node(name = "command")]
+    Command(CommandSecretDef),
+}
+
+/// A secret read from an environment variable.
+#[motya_node]
+#[derive(Parser, Clone, Debug, NodeSchema)]
+pub struct EnvSecretDef {
+    #[node(arg)]
+    pub name: String,
+
+    #[node(prop)]
+    pub var: String,
+}
+
+/// A secret read from a file, or from one KEY=VALUE line of it.
+#[motya_node]
+#[derive(Parser, Clone, Debug, NodeSchema)]
+pub struct FileSecretDef {
+    #[node(arg)]
+    pub name: String,
+
+    #[node(prop)]
+    pub path: PathBuf,
+
+    #[node(prop)]
+    pub key: Option<String>,
+}
+
+/// A secret printed by a command run with sh -c.
+#[motya_node]
+#[derive(Parser, Clone, Debug, NodeSchema)]
+pub struct CommandSecretDef {
+    #[node(arg)]
+    pub name: String,
+
+    #[node(prop)]
+    pub run: String,
+}
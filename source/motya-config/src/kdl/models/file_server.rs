@@ -1,10 +1,63 @@
-use std::path::PathBuf;
+use std::{collections::BTreeMap, path::PathBuf};
 
-use motya_macro::{NodeSchema, Parser};
+use motya_macro::{motya_node, NodeSchema, Parser};
 
+use crate::kdl::parser::typed_value::TypedValue;
+
+#[motya_node]
 #[derive(Parser, Clone, Debug, NodeSchema)]
 #[node(name = "file-server")]
 pub struct FileServerDef {
     #[node(prop)]
     pub root: Option<PathBuf>,
+
+    #[node(prop)]
+    pub autoindex: Option<bool>,
+
+    #[node(prop, name = "cache-control")]
+    pub cache_control: Option<String>,
+
+    #[node(child, name = "index-files")]
+    pub index_files: Option<IndexFilesDef>,
+
+    #[node(child, name = "mime-types")]
+    pub mime_types: Option<MimeTypesDef>,
+
+    #[node(child)]
+    pub precompressed: Option<PrecompressedDef>,
+}
+
+#[motya_node]
+#[derive(Parser, Clone, Debug, NodeSchema)]
+#[node(name = "index-files")]
+pub struct IndexFilesDef {
+    #[node(all_args)]
+    pub names: Vec<TypedValue>,
+}
+
+#[motya_node]
+#[derive(Parser, Clone, Debug, NodeSchema)]
+#[node(name = "mime-types")]
+pub struct MimeTypesDef {
+    /// Keyed by extension, which may be given only once.
+    #[node(dynamic_child)]
+    pub types: BTreeMap<String, MimeTypeDef>,
+}
+
+#[motya_node]
+#[derive(Parser, Clone, Debug, NodeSchema)]
+pub struct MimeTypeDef {
+    #[node(node_name)]
+    pub extension: String,
+
+    #[node(arg)]
+    pub mime: String,
+}
+
+#[motya_node]
+#[derive(Parser, Clone, Debug, NodeSchema)]
+#[node(name = "precompressed")]
+pub struct PrecompressedDef {
+    #[node(all_args)]
+    pub algorithms: Vec<TypedValue>,
 }
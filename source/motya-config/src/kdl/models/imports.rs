@@ -5,6 +5,9 @@ use motya_macro::{motya_node, NodeSchema, Parser};
 pub struct ImportPath {
     #[node(node_name)]
     pub value: String,
+    /// Only import when the environment matches, as `VAR=value` or a bare `VAR` to require it set.
+    #[node(prop, name = "if-env")]
+    pub if_env: Option<String>,
 }
 
 #[derive(Parser, Clone, Debug, Default, NodeSchema)]
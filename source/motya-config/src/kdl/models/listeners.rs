@@ -1,14 +1,56 @@
-use std::net::SocketAddr;
+use std::{net::SocketAddr, path::PathBuf, str::FromStr};
 
 use motya_macro::{motya_node, NodeSchema, Parser};
 
-use crate::common_types::listeners::{ListenerConfig, ListenerKind, TlsConfig};
+use crate::common_types::listeners::{
+    ConnectionRate, ListenerConfig, ListenerKind, SocketOptions, TlsConfig, UdsConfig,
+};
+
+const SOCKET_OPTIONS_SUPPORTED: bool = cfg!(any(target_os = "linux", target_os = "android"));
+
+/// Properties that only apply to TCP listeners.
+const TCP_ONLY_PROPS: &[&str] = &[
+    "cert-path",
+    "key-path",
+    "offer-h2",
+    "offer-h3",
+    "reuse-port",
+    "tcp-fast-open",
+    "ipv6-only",
+    "proxy-protocol",
+    "max-conn-rate",
+];
+
+/// Properties that only apply to unix socket listeners.
+const UDS_ONLY_PROPS: &[&str] = &["mode", "owner", "group"];
+
+/// What a listener binds: `"ADDR:PORT"`, or `"unix:PATH"` for a unix domain socket.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ListenerAddr {
+    Tcp(SocketAddr),
+    Uds(PathBuf),
+}
+
+impl FromStr for ListenerAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("unix:") {
+            Some("") => Err("a unix socket listener needs a path after 'unix:'".to_string()),
+            Some(path) => Ok(ListenerAddr::Uds(path.into())),
+            None => s
+                .parse()
+                .map(ListenerAddr::Tcp)
+                .map_err(|e| format!("{e}, expected ADDR:PORT or unix:PATH")),
+        }
+    }
+}
 
 #[motya_node]
 #[derive(Parser, Clone, Debug, NodeSchema)]
 pub struct ListenerDef {
     #[node(node_name)]
-    pub addr: SocketAddr,
+    pub addr: ListenerAddr,
 
     #[node(prop, name = "cert-path")]
     pub cert_path: Option<String>,
@@ -18,6 +60,33 @@ pub struct ListenerDef {
 
     #[node(prop, name = "offer-h2")]
     pub offer_h2: Option<bool>,
+
+    #[node(prop, name = "offer-h3")]
+    pub offer_h3: Option<bool>,
+
+    #[node(prop, name = "reuse-port")]
+    pub reuse_port: Option<bool>,
+
+    #[node(prop, name = "tcp-fast-open")]
+    pub tcp_fast_open: Option<bool>,
+
+    #[node(prop, name = "ipv6-only")]
+    pub ipv6_only: Option<bool>,
+
+    #[node(prop, name = "proxy-protocol")]
+    pub proxy_protocol: Option<bool>,
+
+    #[node(prop, name = "max-conn-rate")]
+    pub max_conn_rate: Option<ConnectionRate>,
+
+    #[node(prop)]
+    pub mode: Option<String>,
+
+    #[node(prop)]
+    pub owner: Option<String>,
+
+    #[node(prop)]
+    pub group: Option<String>,
 }
 
 #[motya_node]
@@ -34,15 +103,73 @@ impl TryFrom<ListenerDef> for ListenerConfig {
     fn try_from(def: ListenerDef) -> Result<Self, Self::Error> {
         let (data, ctx) = def.into_parts();
 
+        let addr = match &data.addr {
+            ListenerAddr::Tcp(addr) => *addr,
+            ListenerAddr::Uds(path) => {
+                reject_props(&ctx, TCP_ONLY_PROPS, "unix socket")?;
+                return uds_listener(path.clone(), data, ctx);
+            }
+        };
+
+        reject_props(&ctx, UDS_ONLY_PROPS, "TCP")?;
+
+        let reuse_port = data.reuse_port.unwrap_or(false);
+
+        if reuse_port && !SOCKET_OPTIONS_SUPPORTED {
+            return Err(ctx.err_reuse_port(format!(
+                "'reuse-port' is only supported on Linux, not on '{}'",
+                std::env::consts::OS
+            )));
+        }
+
+        let tcp_fast_open = data.tcp_fast_open.unwrap_or(false);
+
+        if tcp_fast_open && !SOCKET_OPTIONS_SUPPORTED {
+            return Err(ctx.err_tcp_fast_open(format!(
+                "'tcp-fast-open' is only supported on Linux, not on '{}'",
+                std::env::consts::OS
+            )));
+        }
+
+        if data.ipv6_only.is_some() && !addr.is_ipv6() {
+            return Err(ctx.err_ipv6_only(format!(
+                "'ipv6-only' only applies to IPv6 addresses, not to {}",
+                addr
+            )));
+        }
+
+        // HTTP/3 needs a QUIC listener on the UDP side, which pingora doesn't have.
+        if data.offer_h3 == Some(true) {
+            return Err(ctx.err_offer_h3(
+                "'offer-h3' is not supported yet, the listener backend has no QUIC transport",
+            ));
+        }
+
+        let proxy_protocol = data.proxy_protocol.unwrap_or(false);
+
+        let socket = SocketOptions {
+            reuse_port,
+            tcp_fast_open,
+            ipv6_only: data.ipv6_only,
+        };
+
         match (data.cert_path, data.key_path) {
+            // pingora finishes the TLS handshake before the connection reaches the
+            // app, which is too late to read the PROXY header sent ahead of it.
+            (Some(_), Some(_)) if proxy_protocol => Err(ctx.err_proxy_protocol(
+                "'proxy-protocol' can't be combined with TLS yet, the listener backend terminates TLS before the PROXY header can be read",
+            )),
             (Some(cpath), Some(kpath)) => Ok(ListenerConfig {
                 source: ListenerKind::Tcp {
-                    addr: data.addr.to_string(),
+                    addr: addr.to_string(),
                     tls: Some(TlsConfig {
                         cert_path: cpath.into(),
                         key_path: kpath.into(),
                     }),
                     offer_h2: data.offer_h2.unwrap_or(true),
+                    proxy_protocol,
+                    max_conn_rate: data.max_conn_rate,
+                    socket,
                 },
             }),
 
@@ -52,12 +179,20 @@ impl TryFrom<ListenerDef> for ListenerConfig {
                         "'offer-h2' requires TLS. Please specify 'cert-path' and 'key-path' or remove 'offer-h2'.",
                     ));
                 }
+                if data.offer_h3.is_some() {
+                    return Err(ctx.err_offer_h3(
+                        "'offer-h3' requires TLS. Please specify 'cert-path' and 'key-path' or remove 'offer-h3'.",
+                    ));
+                }
 
                 Ok(ListenerConfig {
                     source: ListenerKind::Tcp {
-                        addr: data.addr.to_string(),
+                        addr: addr.to_string(),
                         tls: None,
                         offer_h2: false,
+                        proxy_protocol,
+                        max_conn_rate: data.max_conn_rate,
+                        socket,
                     },
                 })
             }
@@ -71,3 +206,303 @@ impl TryFrom<ListenerDef> for ListenerConfig {
         }
     }
 }
+
+fn reject_props(ctx: &ListenerDefErrCtx, props: &[&str], kind: &str) -> miette::Result<()> {
+    for prop in props {
+        if let Some(span) = ctx.ctx.prop_span(prop) {
+            return Err(ctx
+                .ctx
+                .error_with_span(format!("'{prop}' does not apply to {kind} listeners"), span));
+        }
+    }
+    Ok(())
+}
+
+fn uds_listener(
+    path: PathBuf,
+    data: ListenerDefData,
+    ctx: ListenerDefErrCtx,
+) -> miette::Result<ListenerConfig> {
+    if !path.is_absolute() {
+        return Err(ctx.err_addr(format!(
+            "The unix socket path '{}' must be absolute",
+            path.display()
+        )));
+    }
+
+    let mode = match &data.mode {
+        Some(mode) => match u32::from_str_radix(mode, 8) {
+            Ok(bits) if bits <= 0o777 => Some(bits),
+            _ => {
+                return Err(ctx.err_mode(format!(
+                    "'{mode}' is not a file mode, expected octal permission bits like \"0660\""
+                )))
+            }
+        },
+        None => None,
+    };
+
+    if data.owner.as_deref() == Some("") {
+        return Err(ctx.err_owner("'owner' must name a user or give its numeric id"));
+    }
+    if data.group.as_deref() == Some("") {
+        return Err(ctx.err_group("'group' must name a group or give its numeric id"));
+    }
+
+    Ok(ListenerConfig {
+        source: ListenerKind::Uds(UdsConfig {
+            path,
+            mode,
+            owner: data.owner,
+            group: data.group,
+        }),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, time::Duration};
+
+    use crate::loader::test_support::{load, load_errors};
+
+    #[tokio::test]
+    async fn test_listener_offer_h3() {
+        let services = r#"
+            services {
+                Public {
+                    listeners {
+                        "0.0.0.0:443" cert-path="cert.pem" key-path="key.pem" offer-h3=#true
+                        "0.0.0.0:8443" cert-path="cert.pem" key-path="key.pem" offer-h3=#false
+                    }
+                    connectors {
+                        proxy "http://127.0.0.1:8000"
+                    }
+                }
+            }
+        "#;
+        let errors = load_errors(vec![("main.kdl", services)]).await;
+        assert_eq!(errors.count, 1, "{:?}", errors.errors);
+        assert!(errors.errors[0]
+            .message
+            .contains("'offer-h3' is not supported yet"));
+    }
+
+    #[tokio::test]
+    async fn test_listener_offer_h3_needs_tls() {
+        let services = r#"
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" offer-h3=#false }
+                    connectors {
+                        proxy "http://127.0.0.1:8000"
+                    }
+                }
+            }
+        "#;
+        let errors = load_errors(vec![("main.kdl", services)]).await;
+        assert_eq!(errors.count, 1, "{:?}", errors.errors);
+        assert!(errors.errors[0].message.contains("'offer-h3' requires TLS"));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_listener_socket_options() {
+        use crate::common_types::listeners::{ListenerKind, SocketOptions};
+
+        let services = r#"
+            services {
+                Public {
+                    listeners {
+                        "[::]:8080" reuse-port=#true tcp-fast-open=#true ipv6-only=#true
+                    }
+                    connectors {
+                        proxy "http://127.0.0.1:8000"
+                    }
+                }
+            }
+        "#;
+        let config = load(vec![("main.kdl", services)]).await;
+
+        let ListenerKind::Tcp { socket, .. } =
+            &config.basic_proxies[0].listeners.list_cfgs[0].source
+        else {
+            panic!("expected a TCP listener");
+        };
+        assert_eq!(
+            socket,
+            &SocketOptions {
+                reuse_port: true,
+                tcp_fast_open: true,
+                ipv6_only: Some(true),
+                ..SocketOptions::default()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_listener_socket_option_errors() {
+        let services = r#"
+            services {
+                Public {
+                    listeners {
+                        "0.0.0.0:8080" ipv6-only=#true
+                        "0.0.0.0:8081" cert-path="cert.pem" key-path="key.pem" proxy-protocol=#true
+                    }
+                    connectors {
+                        proxy "http://127.0.0.1:8000"
+                    }
+                }
+            }
+        "#;
+        let errors = load_errors(vec![("main.kdl", services)]).await;
+        assert_eq!(errors.count, 2, "{:?}", errors.errors);
+        assert!(errors.errors[0]
+            .message
+            .contains("'ipv6-only' only applies to IPv6 addresses"));
+        assert!(errors.errors[1]
+            .message
+            .contains("'proxy-protocol' can't be combined with TLS"));
+    }
+
+    #[tokio::test]
+    async fn test_listener_max_conn_rate() {
+        use crate::common_types::listeners::{ConnectionRate, ListenerKind};
+
+        let services = r#"
+            services {
+                Public {
+                    listeners {
+                        "0.0.0.0:8080" max-conn-rate="1000/s"
+                        "0.0.0.0:8081"
+                    }
+                    connectors {
+                        proxy "http://127.0.0.1:8000"
+                    }
+                }
+            }
+        "#;
+        let config = load(vec![("main.kdl", services)]).await;
+
+        let rates: Vec<_> = config.basic_proxies[0]
+            .listeners
+            .list_cfgs
+            .iter()
+            .map(|listener| match &listener.source {
+                ListenerKind::Tcp { max_conn_rate, .. } => *max_conn_rate,
+                ListenerKind::Uds(_) => panic!("expected a TCP listener"),
+            })
+            .collect();
+        assert_eq!(
+            rates,
+            [
+                Some(ConnectionRate {
+                    count: 1000,
+                    per: Duration::from_secs(1)
+                }),
+                None
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_listener_max_conn_rate_errors() {
+        let bad_rate = r#"
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" max-conn-rate="1000/d"; }
+                    connectors { proxy "http://127.0.0.1:8000"; }
+                }
+            }
+        "#;
+        let unix_socket = r#"
+            services {
+                Public {
+                    listeners { "unix:/run/motya.sock" max-conn-rate="10/s"; }
+                    connectors { proxy "http://127.0.0.1:8000"; }
+                }
+            }
+        "#;
+
+        for (services, expected) in [
+            (bad_rate, "Expected a rate like '1000/s'"),
+            (
+                unix_socket,
+                "'max-conn-rate' does not apply to unix socket listeners",
+            ),
+        ] {
+            let errors = load_errors(vec![("main.kdl", services)]).await;
+            assert_eq!(errors.count, 1, "{:?}", errors.errors);
+            assert!(errors.errors[0].message.contains(expected));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unix_socket_listeners() {
+        use crate::common_types::listeners::{ListenerKind, UdsConfig};
+
+        let services = r#"
+            services {
+                Public {
+                    listeners {
+                        "unix:/run/motya/http.sock" mode="0660" owner="motya" group="www-data"
+                        "unix:/run/motya/plain.sock"
+                    }
+                    connectors {
+                        proxy "http://127.0.0.1:8000"
+                    }
+                }
+            }
+        "#;
+        let config = load(vec![("main.kdl", services)]).await;
+
+        let sources: Vec<_> = config.basic_proxies[0]
+            .listeners
+            .list_cfgs
+            .iter()
+            .map(|list_cfg| list_cfg.source.clone())
+            .collect();
+        assert_eq!(
+            sources,
+            vec![
+                ListenerKind::Uds(UdsConfig {
+                    path: PathBuf::from("/run/motya/http.sock"),
+                    mode: Some(0o660),
+                    owner: Some("motya".into()),
+                    group: Some("www-data".into()),
+                }),
+                ListenerKind::Uds(UdsConfig {
+                    path: PathBuf::from("/run/motya/plain.sock"),
+                    mode: None,
+                    owner: None,
+                    group: None,
+                }),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unix_socket_listener_errors() {
+        let services = r#"
+            services {
+                Public {
+                    listeners {
+                        "unix:/run/motya/tls.sock" cert-path="cert.pem" key-path="key.pem"
+                        "0.0.0.0:8080" mode="0660"
+                        "unix:relative.sock"
+                        "unix:/run/motya/http.sock" mode="0999"
+                    }
+                    connectors {
+                        proxy "http://127.0.0.1:8000"
+                    }
+                }
+            }
+        "#;
+        let errors = load_errors(vec![("main.kdl", services)]).await;
+        let messages: Vec<_> = errors.errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages.len(), 4, "{messages:?}");
+        assert!(messages[0].contains("'cert-path' does not apply to unix socket listeners"));
+        assert!(messages[1].contains("'mode' does not apply to TCP listeners"));
+        assert!(messages[2].contains("must be absolute"));
+        assert!(messages[3].contains("'0999' is not a file mode"));
+    }
+}
@@ -6,6 +6,7 @@ pub mod imports;
 pub mod key_profile;
 pub mod listeners;
 pub mod lol;
+pub mod profiles;
 pub mod root;
 pub mod services;
 pub mod system;
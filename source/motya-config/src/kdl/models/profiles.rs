@@ -0,0 +1,30 @@
+use motya_macro::{motya_node, NodeSchema, Parser};
+
+use crate::kdl::models::{
+    definitions::DefinitionsDef, services::ServicesSectionDef, system::SystemDataDef,
+};
+
+#[motya_node]
+#[derive(Parser, Clone, Debug, NodeSchema)]
+#[node(name = "profiles")]
+pub struct ProfilesDef {
+    #[node(dynamic_child)]
+    pub items: Vec<ProfileDef>,
+}
+
+/// Applied over the rest of the configuration when this profile is selected.
+#[motya_node]
+#[derive(Parser, Clone, Debug, NodeSchema)]
+pub struct ProfileDef {
+    #[node(node_name)]
+    pub name: String,
+
+    #[node(child)]
+    pub system: Option<SystemDataDef>,
+
+    #[node(child)]
+    pub definitions: Option<DefinitionsDef>,
+
+    #[node(child)]
+    pub services: Vec<ServicesSectionDef>,
+}
@@ -2,8 +2,8 @@ use motya_macro::{motya_node, NodeSchema, Parser};
 
 use crate::kdl::{
     models::{
-        definitions::DefinitionsDef, imports::ImportsDef, services::ServicesSectionDef,
-        system::SystemDataDef,
+        definitions::DefinitionsDef, imports::ImportsDef, profiles::ProfilesDef,
+        services::ServicesSectionDef, system::SystemDataDef,
     },
 };
 
@@ -22,6 +22,9 @@ pub struct RootDef {
 
     #[node(child)]
     pub services: Vec<ServicesSectionDef>,
+
+    #[node(child)]
+    pub profiles: Option<ProfilesDef>,
 }
 
 #[derive(Parser, Clone, Debug, Default)]
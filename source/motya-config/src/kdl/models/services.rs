@@ -1,7 +1,21 @@
+use std::path::PathBuf;
+
 use motya_macro::{motya_node, NodeSchema, Parser};
 
-use crate::kdl::models::{
-    connectors::ConnectorsDef, file_server::FileServerDef, listeners::ListenersDef,
+use crate::{
+    common_types::{
+        access_log::{
+            AccessLogConfig, AccessLogFormat, AccessLogFormatKind, AccessLogSink, AccessLogTemplate,
+        },
+        duration::Duration,
+        error_pages::{ErrorPage, ErrorPageSource, ErrorPageTemplate, ErrorPagesConfig},
+        limits::LimitsConfig,
+        maintenance::{MaintenanceConfig, DEFAULT_MAINTENANCE_STATUS},
+    },
+    kdl::{
+        models::{connectors::ConnectorsDef, file_server::FileServerDef, listeners::ListenersDef},
+        parser::typed_value::TypedValue,
+    },
 };
 
 #[motya_node]
@@ -21,6 +35,18 @@ pub struct ServiceDef {
     #[node(child)]
     pub listeners: ListenersDef,
 
+    #[node(child, name = "access-log")]
+    pub access_log: Option<AccessLogDef>,
+
+    #[node(child)]
+    pub limits: Option<LimitsDef>,
+
+    #[node(child, name = "error-pages")]
+    pub error_pages: Option<ErrorPagesDef>,
+
+    #[node(child)]
+    pub maintenance: Option<MaintenanceDef>,
+
     #[node(child, flatten)]
     pub mode: ServiceMode,
 }
@@ -33,3 +59,487 @@ pub enum ServiceMode {
     #[node(name = "connectors")]
     Connectors(ConnectorsDef),
 }
+
+#[motya_node]
+#[derive(Parser, Clone, Debug, NodeSchema)]
+#[node(name = "access-log")]
+pub struct AccessLogDef {
+    #[node(arg)]
+    pub template: Option<AccessLogTemplate>,
+
+    #[node(prop)]
+    pub format: Option<AccessLogFormatKind>,
+
+    #[node(prop)]
+    pub path: Option<PathBuf>,
+}
+
+impl TryFrom<AccessLogDef> for AccessLogConfig {
+    type Error = miette::Report;
+
+    fn try_from(def: AccessLogDef) -> Result<Self, Self::Error> {
+        let (data, ctx) = def.into_parts();
+
+        let format = match (
+            data.format.unwrap_or(AccessLogFormatKind::Text),
+            data.template,
+        ) {
+            (AccessLogFormatKind::Text, template) => {
+                AccessLogFormat::Text(template.unwrap_or_default())
+            }
+            (AccessLogFormatKind::Json, None) => AccessLogFormat::Json,
+            (AccessLogFormatKind::Json, Some(_)) => {
+                return Err(ctx.err_template(
+                    "A template only applies to format=\"text\"; JSON lines always carry every field",
+                ));
+            }
+        };
+
+        let sink = match data.path {
+            Some(path) if path.as_os_str() != "-" => AccessLogSink::File(path),
+            _ => AccessLogSink::Stdout,
+        };
+
+        Ok(AccessLogConfig { format, sink })
+    }
+}
+
+#[motya_node]
+#[derive(Parser, Clone, Debug, NodeSchema)]
+#[node(name = "limits")]
+pub struct LimitsDef {
+    #[node(child, flat, name = "max-connections")]
+    pub max_connections: Option<usize>,
+
+    #[node(child, flat, name = "max-inflight")]
+    pub max_inflight: Option<usize>,
+}
+
+impl TryFrom<LimitsDef> for LimitsConfig {
+    type Error = miette::Report;
+
+    fn try_from(def: LimitsDef) -> Result<Self, Self::Error> {
+        let (data, ctx) = def.into_parts();
+
+        if data.max_connections.is_none() && data.max_inflight.is_none() {
+            return Err(ctx.err_self(
+                "'limits' needs 'max-connections' or 'max-inflight', e.g. limits { max-inflight 500; }",
+            ));
+        }
+        if data.max_connections == Some(0) {
+            return Err(ctx.err_max_connections("'max-connections' must be greater than zero"));
+        }
+        if data.max_inflight == Some(0) {
+            return Err(ctx.err_max_inflight("'max-inflight' must be greater than zero"));
+        }
+
+        Ok(LimitsConfig {
+            max_connections: data.max_connections,
+            max_inflight: data.max_inflight,
+        })
+    }
+}
+
+#[motya_node]
+#[derive(Parser, Clone, Debug, NodeSchema)]
+#[node(name = "maintenance")]
+pub struct MaintenanceDef {
+    #[node(child, flat)]
+    pub enabled: Option<bool>,
+
+    #[node(child, flat, name = "flag-file")]
+    pub flag_file: Option<PathBuf>,
+
+    #[node(child, flat)]
+    pub status: Option<u16>,
+
+    #[node(child, flat, name = "retry-after")]
+    pub retry_after: Option<Duration>,
+
+    #[node(child)]
+    pub allow: Option<MaintenanceAllowDef>,
+}
+
+#[motya_node]
+#[derive(Parser, Clone, Debug, NodeSchema)]
+#[node(name = "allow")]
+pub struct MaintenanceAllowDef {
+    #[node(all_args)]
+    pub paths: Vec<TypedValue>,
+}
+
+impl TryFrom<MaintenanceDef> for MaintenanceConfig {
+    type Error = miette::Report;
+
+    fn try_from(def: MaintenanceDef) -> Result<Self, Self::Error> {
+        let (data, ctx) = def.into_parts();
+
+        let status = data.status.unwrap_or(DEFAULT_MAINTENANCE_STATUS);
+        if !(400..=599).contains(&status) {
+            return Err(ctx.err_status("Maintenance answers with a 4xx or 5xx status"));
+        }
+
+        let mut allow = Vec::new();
+        if let Some(allow_def) = data.allow {
+            let (allow_data, allow_ctx) = allow_def.into_parts();
+            if allow_data.paths.is_empty() {
+                return Err(allow_ctx
+                    .err_paths("'allow' must list at least one path, e.g. allow \"/healthz\""));
+            }
+            for path in allow_data.paths {
+                let path = path.as_str()?;
+                if !path.starts_with('/') {
+                    return Err(allow_ctx.err_paths(format!(
+                        "'{path}' is not a path, allowed paths start with '/'"
+                    )));
+                }
+                allow.push(path);
+            }
+        }
+
+        Ok(MaintenanceConfig {
+            enabled: data.enabled.unwrap_or(false),
+            flag_file: data.flag_file,
+            status,
+            retry_after: data.retry_after.map(Into::into),
+            allow,
+        })
+    }
+}
+
+#[motya_node]
+#[derive(Parser, Clone, Debug, NodeSchema)]
+#[node(name = "error-pages")]
+pub struct ErrorPagesDef {
+    #[node(child, name = "page")]
+    pub pages: Vec<ErrorPageDef>,
+}
+
+#[motya_node]
+#[derive(Parser, Clone, Debug, NodeSchema)]
+#[node(name = "page")]
+pub struct ErrorPageDef {
+    #[node(arg)]
+    pub status: u16,
+
+    #[node(arg)]
+    pub template: Option<ErrorPageTemplate>,
+
+    #[node(prop)]
+    pub path: Option<PathBuf>,
+
+    #[node(prop, name = "content-type")]
+    pub content_type: Option<String>,
+}
+
+impl TryFrom<ErrorPagesDef> for ErrorPagesConfig {
+    type Error = miette::Report;
+
+    fn try_from(def: ErrorPagesDef) -> Result<Self, Self::Error> {
+        let (data, _ctx) = def.into_parts();
+
+        let mut pages: Vec<ErrorPage> = Vec::with_capacity(data.pages.len());
+        for page in data.pages {
+            if pages.iter().any(|other| other.status == page.status) {
+                let (data, ctx) = page.into_parts();
+                return Err(ctx.err_status(format!("The page for {} is given twice", data.status)));
+            }
+            pages.push(ErrorPage::try_from(page)?);
+        }
+
+        Ok(ErrorPagesConfig { pages })
+    }
+}
+
+impl TryFrom<ErrorPageDef> for ErrorPage {
+    type Error = miette::Report;
+
+    fn try_from(def: ErrorPageDef) -> Result<Self, Self::Error> {
+        let (data, ctx) = def.into_parts();
+
+        if !(400..=599).contains(&data.status) {
+            return Err(ctx.err_status("Error pages are for 4xx and 5xx statuses"));
+        }
+
+        let (source, default_type) = match (data.template, data.path) {
+            (Some(template), None) => (ErrorPageSource::Inline(template), HTML),
+            (None, Some(path)) => {
+                let default_type = match path.extension().and_then(|ext| ext.to_str()) {
+                    Some("json") => "application/json",
+                    Some("txt") => "text/plain; charset=utf-8",
+                    _ => HTML,
+                };
+                (ErrorPageSource::File(path), default_type)
+            }
+            (Some(_), Some(_)) => {
+                return Err(
+                    ctx.err_path("A page is either written inline or read from 'path', not both")
+                );
+            }
+            (None, None) => {
+                return Err(ctx.err_self(
+                    "A page needs its template, inline or from a file: page 502 path=\"/etc/motya/502.html\"",
+                ));
+            }
+        };
+
+        Ok(ErrorPage {
+            status: data.status,
+            source,
+            content_type: data
+                .content_type
+                .unwrap_or_else(|| default_type.to_string()),
+        })
+    }
+}
+
+const HTML: &str = "text/html; charset=utf-8";
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        common_types::{
+            access_log::{AccessLogFormat, AccessLogSink},
+            error_pages::{ErrorPageSource, ErrorPagesConfig},
+            limits::LimitsConfig,
+            maintenance::MaintenanceConfig,
+        },
+        loader::test_support::{load, load_errors},
+    };
+
+    #[tokio::test]
+    async fn test_access_log() {
+        let services = r#"
+            services {
+                Text {
+                    listeners { "127.0.0.1:8080" }
+                    access-log "$method $path -> $status" path="/var/log/motya/text.log"
+                    connectors { section "/" { return 200 "OK"; } }
+                }
+                Json {
+                    listeners { "127.0.0.1:8081" }
+                    access-log format="json"
+                    connectors { section "/" { return 200 "OK"; } }
+                }
+            }
+        "#;
+        let config = load(vec![("main.kdl", services)]).await;
+
+        let text = config.basic_proxies[0]
+            .access_log
+            .clone()
+            .expect("Text should log");
+        assert_eq!(
+            text.format,
+            AccessLogFormat::Text("$method $path -> $status".parse().unwrap())
+        );
+        assert_eq!(
+            text.sink,
+            AccessLogSink::File("/var/log/motya/text.log".into())
+        );
+
+        let json = config.basic_proxies[1]
+            .access_log
+            .clone()
+            .expect("Json should log");
+        assert_eq!(json.format, AccessLogFormat::Json);
+        assert_eq!(json.sink, AccessLogSink::Stdout);
+    }
+
+    #[tokio::test]
+    async fn test_access_log_json_takes_no_template() {
+        let services = r#"
+            services {
+                Api {
+                    listeners { "127.0.0.1:8080" }
+                    access-log "$status" format="json"
+                    connectors { section "/" { return 200 "OK"; } }
+                }
+            }
+        "#;
+        let errors = load_errors(vec![("main.kdl", services)]).await;
+        assert!(errors.errors[0].message.contains("format=\"text\""));
+    }
+
+    #[tokio::test]
+    async fn test_limits() {
+        let services = r#"
+            services {
+                Api {
+                    listeners { "127.0.0.1:8080" }
+                    limits {
+                        max-connections 10000
+                        max-inflight 500
+                    }
+                    connectors { section "/" { return 200 "OK"; } }
+                }
+                Internal {
+                    listeners { "127.0.0.1:8081" }
+                    limits { max-inflight 50; }
+                    connectors { section "/" { return 200 "OK"; } }
+                }
+            }
+        "#;
+        let config = load(vec![("main.kdl", services)]).await;
+
+        assert_eq!(
+            config.basic_proxies[0].limits,
+            Some(LimitsConfig {
+                max_connections: Some(10000),
+                max_inflight: Some(500),
+            })
+        );
+        assert_eq!(
+            config.basic_proxies[1].limits,
+            Some(LimitsConfig {
+                max_connections: None,
+                max_inflight: Some(50),
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_limits_errors() {
+        let services = r#"
+            services {
+                Zero {
+                    listeners { "127.0.0.1:8080" }
+                    limits { max-connections 0; }
+                    connectors { section "/" { return 200 "OK"; } }
+                }
+                Empty {
+                    listeners { "127.0.0.1:8081" }
+                    limits
+                    connectors { section "/" { return 200 "OK"; } }
+                }
+            }
+        "#;
+        let errors = load_errors(vec![("main.kdl", services)]).await;
+        assert_eq!(errors.errors.len(), 2);
+        let (zero, empty) = (&errors.errors[0].message, &errors.errors[1].message);
+        assert!(zero.contains("must be greater than zero"));
+        assert!(empty.contains("'max-connections' or 'max-inflight'"));
+    }
+
+    #[tokio::test]
+    async fn test_error_pages() {
+        let services = r#"
+            services {
+                Api {
+                    listeners { "127.0.0.1:8080" }
+                    error-pages {
+                        page 502 path="/etc/motya/errors/502.html"
+                        page 429 "{\"status\": ${status}}" content-type="application/json"
+                    }
+                    connectors { section "/" { return 200 "OK"; } }
+                }
+            }
+        "#;
+        let config = load(vec![("main.kdl", services)]).await;
+
+        let ErrorPagesConfig { pages } = config.basic_proxies[0].error_pages.clone().unwrap();
+        assert_eq!(pages.len(), 2);
+
+        assert_eq!(pages[0].status, 502);
+        assert_eq!(
+            pages[0].source,
+            ErrorPageSource::File("/etc/motya/errors/502.html".into())
+        );
+        assert_eq!(pages[0].content_type, "text/html; charset=utf-8");
+
+        assert_eq!(pages[1].status, 429);
+        assert!(matches!(pages[1].source, ErrorPageSource::Inline(_)));
+        assert_eq!(pages[1].content_type, "application/json");
+    }
+
+    #[tokio::test]
+    async fn test_error_pages_errors() {
+        let services = r#"
+            services {
+                Ok {
+                    listeners { "127.0.0.1:8080" }
+                    error-pages { page 200 "<p>fine</p>"; }
+                    connectors { section "/" { return 200 "OK"; } }
+                }
+                Twice {
+                    listeners { "127.0.0.1:8081" }
+                    error-pages {
+                        page 502 "<p>one</p>"
+                        page 502 "<p>two</p>"
+                    }
+                    connectors { section "/" { return 200 "OK"; } }
+                }
+                Both {
+                    listeners { "127.0.0.1:8082" }
+                    error-pages { page 503 "<p>inline</p>" path="/etc/motya/503.html"; }
+                    connectors { section "/" { return 200 "OK"; } }
+                }
+            }
+        "#;
+        let errors = load_errors(vec![("main.kdl", services)]).await;
+        let messages: Vec<_> = errors.errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages.len(), 3, "{messages:?}");
+        assert!(messages[0].contains("4xx and 5xx"));
+        assert!(messages[1].contains("given twice"));
+        assert!(messages[2].contains("not both"));
+    }
+
+    #[tokio::test]
+    async fn test_maintenance() {
+        let services = r#"
+            services {
+                Api {
+                    listeners { "127.0.0.1:8080" }
+                    maintenance {
+                        flag-file "/run/motya/api.maintenance"
+                        retry-after "10m"
+                        allow "/healthz" "/ready"
+                    }
+                    connectors { section "/" { return 200 "OK"; } }
+                }
+                Plain {
+                    listeners { "127.0.0.1:8081" }
+                    connectors { section "/" { return 200 "OK"; } }
+                }
+            }
+        "#;
+        let config = load(vec![("main.kdl", services)]).await;
+
+        assert_eq!(
+            config.basic_proxies[0].maintenance,
+            Some(MaintenanceConfig {
+                enabled: false,
+                flag_file: Some("/run/motya/api.maintenance".into()),
+                status: 503,
+                retry_after: Some(Duration::from_secs(600)),
+                allow: vec!["/healthz".to_string(), "/ready".to_string()],
+            })
+        );
+        assert_eq!(config.basic_proxies[1].maintenance, None);
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_errors() {
+        let services = r#"
+            services {
+                Status {
+                    listeners { "127.0.0.1:8080" }
+                    maintenance { status 200; }
+                    connectors { section "/" { return 200 "OK"; } }
+                }
+                Allow {
+                    listeners { "127.0.0.1:8081" }
+                    maintenance { allow "healthz"; }
+                    connectors { section "/" { return 200 "OK"; } }
+                }
+            }
+        "#;
+        let errors = load_errors(vec![("main.kdl", services)]).await;
+        let messages: Vec<_> = errors.errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages.len(), 2, "{messages:?}");
+        assert!(messages[0].contains("4xx or 5xx"));
+        assert!(messages[1].contains("'healthz' is not a path"));
+    }
+}
@@ -1,10 +1,19 @@
 use std::{net::SocketAddr, path::PathBuf};
 
+use cidr::IpCidr;
 use miette::Report;
 use motya_macro::{NodeSchema, Parser};
 
-use crate::common_types::system_data::{
-    ConfigProvider, FilesProviderConfig, HttpProviderConfig, S3ProviderConfig, SystemData,
+use crate::{
+    common_types::{
+        acme::{AcmeConfig, DEFAULT_RENEW_BEFORE, LETS_ENCRYPT_DIRECTORY},
+        admin::AdminConfig,
+        duration::Duration,
+        system_data::{
+            ConfigProvider, FilesProviderConfig, HttpProviderConfig, S3ProviderConfig, SystemData,
+        },
+    },
+    kdl::parser::typed_value::TypedValue,
 };
 
 #[derive(Parser, Clone, Debug, NodeSchema)]
@@ -47,6 +56,52 @@ pub struct ProvidersContainerDef {
     pub providers: Vec<ConfigProviderDef>,
 }
 
+#[derive(Parser, Clone, Debug, NodeSchema)]
+#[node(name = "allow")]
+pub struct AdminAllowDef {
+    #[node(all_args)]
+    pub networks: Vec<TypedValue>,
+
+    #[node(prop, name = "auth-token-env")]
+    pub auth_token_env: Option<String>,
+}
+
+#[derive(Parser, Clone, Debug, NodeSchema)]
+#[node(name = "admin")]
+pub struct AdminDef {
+    #[node(child, flat)]
+    pub listen: Option<SocketAddr>,
+
+    #[node(child)]
+    pub allow: Option<AdminAllowDef>,
+}
+
+#[derive(Parser, Clone, Debug, NodeSchema)]
+#[node(name = "domains")]
+pub struct AcmeDomainsDef {
+    #[node(all_args)]
+    pub names: Vec<TypedValue>,
+}
+
+#[derive(Parser, Clone, Debug, NodeSchema)]
+#[node(name = "acme")]
+pub struct AcmeDef {
+    #[node(child)]
+    pub domains: Option<AcmeDomainsDef>,
+
+    #[node(child, flat)]
+    pub email: Option<String>,
+
+    #[node(child, flat)]
+    pub storage: Option<PathBuf>,
+
+    #[node(child, flat)]
+    pub directory: Option<String>,
+
+    #[node(child, flat, name = "renew-before")]
+    pub renew_before: Option<Duration>,
+}
+
 #[derive(Parser, Clone, Debug, NodeSchema)]
 #[node(name = "system")]
 pub struct SystemDataDef {
@@ -64,6 +119,24 @@ pub struct SystemDataDef {
 
     #[node(child)]
     pub providers: Option<ProvidersContainerDef>,
+
+    #[node(child)]
+    pub production: Option<bool>,
+
+    #[node(child, name = "config-version-header")]
+    pub config_version_header: Option<bool>,
+
+    #[node(child)]
+    pub admin: Option<AdminDef>,
+
+    #[node(child, flat, name = "metrics-listener")]
+    pub metrics_listener: Option<SocketAddr>,
+
+    #[node(child, flat, name = "shutdown-grace")]
+    pub shutdown_grace: Option<Duration>,
+
+    #[node(child)]
+    pub acme: Option<AcmeDef>,
 }
 
 impl TryFrom<SystemDataDef> for SystemData {
@@ -110,12 +183,189 @@ impl TryFrom<SystemDataDef> for SystemData {
             None
         };
 
+        let admin = data.admin.map(admin_config).transpose()?;
+        let acme = data.acme.map(acme_config).transpose()?;
+
         Ok(SystemData {
             threads_per_service: data.tps.unwrap_or(8),
             daemonize: data.daemonize.unwrap_or(false),
             upgrade_socket: data.upgrade,
             pid_file: data.pid,
             provider,
+            production: data.production.unwrap_or(false),
+            config_version_header: data.config_version_header.unwrap_or(false),
+            admin,
+            metrics_listener: data.metrics_listener,
+            shutdown_grace: data.shutdown_grace.map(Into::into),
+            acme,
         })
     }
 }
+
+fn admin_config(def: AdminDef) -> Result<AdminConfig, Report> {
+    let Some(allow) = def.allow else {
+        return Ok(AdminConfig {
+            listen: def.listen,
+            ..AdminConfig::default()
+        });
+    };
+
+    if allow.networks.is_empty() {
+        return Err(miette::miette!(
+            "'admin.allow' must list at least one network, e.g. allow \"127.0.0.1/32\""
+        ));
+    }
+
+    let networks = allow
+        .networks
+        .into_iter()
+        .map(|net| net.parse_as::<IpCidr>())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(AdminConfig {
+        listen: def.listen,
+        allow: networks,
+        auth_token_env: allow.auth_token_env,
+    })
+}
+
+fn acme_config(def: AcmeDef) -> Result<AcmeConfig, Report> {
+    let domains = def
+        .domains
+        .map(|domains| domains.names)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|name| name.as_str())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if domains.is_empty() {
+        return Err(miette::miette!(
+            "'acme' must list the names to certify, e.g. domains \"example.com\" \"www.example.com\""
+        ));
+    }
+
+    if let Some(wildcard) = domains.iter().find(|name| name.starts_with("*.")) {
+        return Err(miette::miette!(
+            "'{wildcard}' cannot be certified: wildcards need a DNS-01 challenge, and 'acme' only answers HTTP-01"
+        ));
+    }
+
+    let Some(storage) = def.storage else {
+        return Err(miette::miette!(
+            "'acme' needs a 'storage' directory for the account, certificate and key"
+        ));
+    };
+
+    Ok(AcmeConfig {
+        domains,
+        email: def.email,
+        storage,
+        directory: def
+            .directory
+            .unwrap_or_else(|| LETS_ENCRYPT_DIRECTORY.to_string()),
+        renew_before: def
+            .renew_before
+            .map(Into::into)
+            .unwrap_or(DEFAULT_RENEW_BEFORE),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, time::Duration};
+
+    use cidr::IpCidr;
+
+    use crate::{
+        common_types::acme::LETS_ENCRYPT_DIRECTORY,
+        loader::test_support::{load, load_errors, DELAY_SERVICES},
+    };
+
+    #[tokio::test]
+    async fn test_admin_allowlist() {
+        let system = r#"
+            system {
+                admin {
+                    listen "127.0.0.1:9901"
+                    allow "127.0.0.1/32" "10.0.0.0/8" auth-token-env="ADMIN_TOKEN"
+                }
+            }
+        "#;
+        let config = load(vec![("system.kdl", system), ("main.kdl", DELAY_SERVICES)]).await;
+
+        let admin = config.admin.expect("Admin access should be configured");
+        assert_eq!(admin.listen, Some("127.0.0.1:9901".parse().unwrap()));
+        let expected: Vec<IpCidr> = vec![
+            "127.0.0.1/32".parse().unwrap(),
+            "10.0.0.0/8".parse().unwrap(),
+        ];
+        assert_eq!(admin.allow, expected);
+        assert_eq!(admin.auth_token_env.as_deref(), Some("ADMIN_TOKEN"));
+    }
+
+    #[tokio::test]
+    async fn test_metrics_listener() {
+        let system = r#"system { metrics-listener "0.0.0.0:9090"; }"#;
+        let config = load(vec![("system.kdl", system), ("main.kdl", DELAY_SERVICES)]).await;
+
+        assert_eq!(
+            config.metrics_listener,
+            Some("0.0.0.0:9090".parse().unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_grace() {
+        let system = r#"system { shutdown-grace "30s"; }"#;
+        let config = load(vec![("system.kdl", system), ("main.kdl", DELAY_SERVICES)]).await;
+
+        assert_eq!(config.shutdown_grace, Some(Duration::from_secs(30)));
+    }
+
+    #[tokio::test]
+    async fn test_acme() {
+        let system = r#"
+            system {
+                acme {
+                    domains "example.com" "www.example.com"
+                    email "ops@example.com"
+                    storage "/var/lib/motya/acme"
+                    renew-before "14d"
+                }
+            }
+        "#;
+        let config = load(vec![("system.kdl", system), ("main.kdl", DELAY_SERVICES)]).await;
+
+        let acme = config.acme.expect("ACME should be configured");
+        assert_eq!(acme.domains, vec!["example.com", "www.example.com"]);
+        assert_eq!(acme.email.as_deref(), Some("ops@example.com"));
+        assert_eq!(acme.directory, LETS_ENCRYPT_DIRECTORY);
+        assert_eq!(acme.renew_before, Duration::from_secs(14 * 24 * 60 * 60));
+        assert_eq!(
+            acme.cert_path(),
+            PathBuf::from("/var/lib/motya/acme/fullchain.pem")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_acme_rejects_wildcards() {
+        let system = r#"
+            system {
+                acme {
+                    domains "*.example.com"
+                    storage "/var/lib/motya/acme"
+                }
+            }
+        "#;
+        let errors = load_errors(vec![("system.kdl", system), ("main.kdl", DELAY_SERVICES)]).await;
+        assert!(errors.errors[0].message.contains("DNS-01"));
+    }
+
+    #[tokio::test]
+    async fn test_admin_allowlist_invalid_network() {
+        let system = r#"system { admin { allow "10.0.0.0/8" "not-a-network"; }; }"#;
+        let errors = load_errors(vec![("system.kdl", system), ("main.kdl", DELAY_SERVICES)]).await;
+        assert_eq!(errors.count, 1);
+        assert!(errors.errors[0].message.contains("'not-a-network'"));
+    }
+}
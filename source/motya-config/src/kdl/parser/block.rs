@@ -2,11 +2,13 @@ use std::collections::HashMap;
 
 use miette::Result;
 
-use super::ctx::ParseContext;
+use super::{ctx::ParseContext, suggest};
 
 pub struct BlockParser {
     ctx: ParseContext,
     children: HashMap<String, Vec<ParseContext>>,
+    /// Every directive name asked for so far, to suggest from in [`Self::exhaust`].
+    known: Vec<String>,
 }
 
 impl BlockParser {
@@ -19,7 +21,11 @@ impl BlockParser {
             children.entry(name.to_string()).or_default().push(child);
         }
 
-        Ok(Self { ctx, children })
+        Ok(Self {
+            ctx,
+            children,
+            known: Vec::new(),
+        })
     }
 
     /// Creates a BlockParser, passes it to the provided function,
@@ -57,6 +63,8 @@ impl BlockParser {
     where
         F: FnOnce(ParseContext) -> Result<T>,
     {
+        self.known.push(name.to_string());
+
         match self.children.remove(name) {
             Some(mut nodes) if nodes.len() == 1 => Ok(Some(f(nodes.pop().unwrap())?)),
             Some(nodes) => {
@@ -91,6 +99,8 @@ impl BlockParser {
     where
         F: FnMut(ParseContext) -> Result<T>,
     {
+        self.known.push(name.to_string());
+
         let mut results = Vec::new();
         if let Some(nodes) = self.children.remove(name) {
             for node in nodes {
@@ -157,7 +167,12 @@ impl BlockParser {
     pub fn exhaust(self) -> Result<()> {
         if let Some((name, nodes)) = self.children.into_iter().next() {
             let first = &nodes[0];
-            return Err(first.error(format!("Unknown directive: '{name}'")));
+            let msg = suggest::with_suggestion(
+                format!("Unknown directive: '{name}'"),
+                &name,
+                self.known.iter().map(String::as_str),
+            );
+            return Err(first.error_with_span(msg, first.name_span()));
         }
         Ok(())
     }
@@ -1,6 +1,162 @@
-use miette::Result;
+use miette::SourceSpan;
+
+use crate::{common_types::error::ParseError, kdl::parser::ctx::ParseContext};
 
 pub trait Convert<S>: Sized {
     type Output;
-    fn convert(self, state: &S) -> Result<Self::Output>;
+    fn convert(self, state: &S) -> Result<Self::Output, ConvertError>;
+}
+
+/// The error of a failed [`Convert::convert`].
+///
+/// A plain report keeps its own label, or points at the converted node when it
+/// has none. [`ConvertError::with_field`] pins it to a span taken from the node's
+/// `ErrCtx` instead, e.g. `ctx.span_port()`, so the error lands on the exact
+/// property or child even when it travels up through the conversion of the
+/// enclosing nodes.
+#[derive(Debug)]
+pub struct ConvertError {
+    report: miette::Report,
+    span: Option<SourceSpan>,
+}
+
+impl ConvertError {
+    pub fn with_field(span: SourceSpan, msg: impl Into<String>) -> Self {
+        Self {
+            report: miette::miette!("{}", msg.into()),
+            span: Some(span),
+        }
+    }
+
+    pub fn into_parse_error(self, ctx: &ParseContext) -> ParseError {
+        let mut error = ParseError::from_report(self.report, ctx);
+        if let Some(span) = self.span {
+            error.label = Some(span);
+        }
+        error
+    }
+}
+
+impl From<miette::Report> for ConvertError {
+    fn from(report: miette::Report) -> Self {
+        Self { report, span: None }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use motya_macro::{motya_node, NodeSchema, Parser};
+
+    use super::*;
+
+    #[derive(Parser, Clone, Debug, NodeSchema)]
+    #[node(name = "limits")]
+    pub struct LimitsDef {
+        #[node(prop)]
+        pub min: usize,
+        #[node(prop)]
+        pub max: usize,
+    }
+
+    #[motya_node]
+    #[derive(Parser, Clone, Debug, NodeSchema)]
+    #[node(name = "pool")]
+    pub struct PoolDef {
+        #[node(arg)]
+        pub name: String,
+        #[node(child)]
+        pub limits: LimitsDef,
+    }
+
+    impl Convert<()> for PoolDef {
+        type Output = (usize, usize);
+
+        fn convert(self, _: &()) -> Result<Self::Output, ConvertError> {
+            let (data, ctx) = self.into_parts();
+
+            if data.limits.min > data.limits.max {
+                return Err(ConvertError::with_field(
+                    ctx.span_limits(),
+                    "'min' must not exceed 'max'",
+                ));
+            }
+            if data.name.is_empty() {
+                return Err(miette::miette!("pool name must not be empty").into());
+            }
+
+            Ok((data.limits.min, data.limits.max))
+        }
+    }
+
+    #[motya_node]
+    #[derive(Parser, Clone, Debug, NodeSchema)]
+    #[node(name = "cluster")]
+    pub struct ClusterDef {
+        #[node(child)]
+        pub pool: PoolDef,
+    }
+
+    impl Convert<()> for ClusterDef {
+        type Output = (usize, usize);
+
+        fn convert(self, state: &()) -> Result<Self::Output, ConvertError> {
+            self.into_inner().pool.convert(state)
+        }
+    }
+
+    fn node(source: &str) -> ParseContext {
+        let doc: kdl::KdlDocument = source.parse().unwrap();
+        ParseContext::new(doc, "test.kdl")
+            .nodes()
+            .unwrap()
+            .remove(0)
+    }
+
+    fn labeled<'a>(source: &'a str, error: &ParseError) -> &'a str {
+        let span = error.label.expect("errors are labeled");
+        &source[span.offset()..span.offset() + span.len()]
+    }
+
+    #[test]
+    fn test_field_error_points_at_the_field() {
+        let source = r#"pool "db" { limits min=10 max=2; }"#;
+
+        let error = PoolDef::parse_as(&node(source), &())
+            .unwrap_err()
+            .errors
+            .remove(0);
+        assert_eq!(error.message, "'min' must not exceed 'max'");
+        assert!(labeled(source, &error).contains("limits min=10 max=2"));
+    }
+
+    #[test]
+    fn test_field_error_survives_nesting() {
+        let source = r#"cluster { pool "db" { limits min=10 max=2; }; }"#;
+
+        let error = ClusterDef::parse_as(&node(source), &())
+            .unwrap_err()
+            .errors
+            .remove(0);
+        let labeled = labeled(source, &error);
+        assert!(labeled.contains("limits"));
+        assert!(!labeled.contains("pool"), "labeled: {labeled:?}");
+    }
+
+    #[test]
+    fn test_plain_error_points_at_the_node() {
+        let source = r#"pool "" { limits min=1 max=2; }"#;
+
+        let error = PoolDef::parse_as(&node(source), &())
+            .unwrap_err()
+            .errors
+            .remove(0);
+        assert_eq!(error.message, "pool name must not be empty");
+        assert!(labeled(source, &error).contains("pool \"\""));
+    }
+
+    #[test]
+    fn test_conversion_result() {
+        let source = r#"cluster { pool "db" { limits min=1 max=2; }; }"#;
+        assert_eq!(ClusterDef::parse_as(&node(source), &()).unwrap(), (1, 2));
+    }
 }
@@ -3,7 +3,7 @@ use std::{
     fmt::Debug,
     ops::{Range, RangeFrom, RangeFull, RangeTo},
     str::FromStr,
-    sync::Arc,
+    sync::{Arc, Mutex},
     vec::IntoIter,
 };
 
@@ -12,7 +12,9 @@ use kdl::{KdlDocument, KdlEntry, KdlNode};
 use miette::{NamedSource, Result, SourceSpan};
 
 use crate::{
-    common_types::bad::Bad, kdl::parser::typed_value::TypedValue, var_registry::VarRegistry,
+    common_types::{bad::Bad, error::ParseError},
+    kdl::parser::{suggest, typed_value::TypedValue},
+    var_registry::VarRegistry,
 };
 
 #[derive(Debug, Clone)]
@@ -21,6 +23,9 @@ pub struct ParseContext {
     source_name: Arc<str>,
     current: Current,
     pub(crate) registry: Option<Arc<VarRegistry>>,
+    warnings: Arc<Mutex<Vec<ParseError>>>,
+    /// The names of the nodes from the top of the document down to the current one.
+    path: Arc<[Arc<str>]>,
 }
 
 #[derive(Debug, Clone)]
@@ -40,6 +45,8 @@ impl ParseContext {
             doc,
             registry: Some(registry),
             source_name,
+            warnings: Default::default(),
+            path: Arc::from(Vec::new()),
         }
     }
 
@@ -51,6 +58,8 @@ impl ParseContext {
             doc: arc_doc,
             source_name: Arc::from("<unknown>"),
             registry: None,
+            warnings: Default::default(),
+            path: Arc::from(Vec::new()),
         }
     }
 
@@ -61,15 +70,43 @@ impl ParseContext {
             doc,
             source_name: Arc::from(source_name),
             registry: None,
+            warnings: Default::default(),
+            path: Arc::from(Vec::new()),
         }
     }
 
     fn derive(&self, current: Current) -> Self {
+        let path = match &current {
+            Current::Node(node) => self
+                .path
+                .iter()
+                .cloned()
+                .chain([Arc::from(node.name().value())])
+                .collect(),
+            Current::Document(_) => Arc::clone(&self.path),
+        };
+
         Self {
             doc: Arc::clone(&self.doc),
             source_name: Arc::clone(&self.source_name),
             current,
             registry: self.registry.as_ref().map(Arc::clone),
+            warnings: Arc::clone(&self.warnings),
+            path,
+        }
+    }
+
+    pub fn source_name(&self) -> &str {
+        &self.source_name
+    }
+
+    /// Where the current node is in the document, as the names of the nodes down to
+    /// it, e.g. `services > Example > connectors > proxy`. `None` at the top.
+    pub fn path(&self) -> Option<String> {
+        if self.path.is_empty() {
+            None
+        } else {
+            Some(self.path.join(" > "))
         }
     }
 
@@ -121,6 +158,20 @@ impl ParseContext {
         }
     }
 
+    /// Returns the source span of the first child node named `name`.
+    pub fn child_span(&self, name: &str) -> Option<SourceSpan> {
+        let children = match &self.current {
+            Current::Node(node) => node.children()?,
+            Current::Document(doc) => doc.as_ref(),
+        };
+
+        children
+            .nodes()
+            .iter()
+            .find(|n| n.name().value() == name)
+            .map(|n| n.span())
+    }
+
     /// Returns the span of the current node's name.
     pub fn name_span(&self) -> SourceSpan {
         match &self.current {
@@ -138,6 +189,21 @@ impl ParseContext {
         self.error_with_span(msg, self.current_span())
     }
 
+    /// Records a warning at `span`, which doesn't fail the parse. The contexts of
+    /// one document share their warnings, see [`Self::take_warnings`].
+    pub fn warn(&self, msg: impl Into<String>, help: Option<String>, span: SourceSpan) {
+        let warning = ParseError::new(msg, Some(span), help, self.source()).with_path(self);
+        self.warnings
+            .lock()
+            .expect("parse warnings lock poisoned")
+            .push(warning);
+    }
+
+    /// Takes the warnings recorded so far while parsing the document.
+    pub fn take_warnings(&self) -> Vec<ParseError> {
+        std::mem::take(&mut *self.warnings.lock().expect("parse warnings lock poisoned"))
+    }
+
     /// Returns the source span of the current element (Node or Document).
     pub fn current_span(&self) -> SourceSpan {
         match &self.current {
@@ -290,9 +356,12 @@ impl<V> HashMapValidationExt for HashMap<&str, V> {
     ) -> miette::Result<Self> {
         if let Some(bad_key) = self.keys().find(|k| !allowed.contains(k)) {
             return Err(Bad::docspan(
-                format!(
-                    "Unknown configuration key: '{bad_key}'. Allowed keys are: {:?}",
-                    allowed
+                suggest::with_suggestion(
+                    format!(
+                        "Unknown configuration key: '{bad_key}'. Allowed keys are: {allowed:?}"
+                    ),
+                    bad_key,
+                    allowed.iter().copied(),
                 ),
                 doc,
                 span,
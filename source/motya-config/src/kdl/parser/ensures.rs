@@ -1,10 +1,11 @@
 use std::{net::SocketAddr, str::FromStr};
 
 use kdl::KdlValue;
-use miette::Result;
+use miette::{Result, SourceSpan};
 
 use crate::kdl::parser::{
     ctx::ParseContext,
+    suggest,
     utils::{get_kdl_type_name, PrimitiveType},
 };
 
@@ -61,10 +62,7 @@ impl ParseContext {
                 match schema.iter().find(|(k, _)| *k == key) {
                     None => {
                         let allowed_keys: Vec<&str> = schema.iter().map(|(k, _)| *k).collect();
-                        return Err(self.error(format!(
-                            "Unknown configuration key: '{key}'. Allowed keys are: {:?}",
-                            allowed_keys
-                        )));
+                        return Err(self.unknown_key_error(key, name_node.span(), &allowed_keys));
                     }
                     Some((_, expected_type)) => {
                         let value = arg.value();
@@ -180,16 +178,20 @@ impl ParseContext {
             if let Some(name) = arg.name() {
                 let key = name.value();
                 if !allowed.contains(&key) {
-                    return Err(self.error(format!(
-                        "Unknown configuration key: '{key}'. Allowed keys are: {:?}",
-                        allowed
-                    )));
+                    return Err(self.unknown_key_error(key, name.span(), allowed));
                 }
             }
         }
         Ok(())
     }
 
+    /// Points at the unknown property `key`, suggesting the closest allowed key.
+    fn unknown_key_error(&self, key: &str, span: SourceSpan, allowed: &[&str]) -> miette::Error {
+        let msg = format!("Unknown configuration key: '{key}'. Allowed keys are: {allowed:?}");
+        let msg = suggest::with_suggestion(msg, key, allowed.iter().copied());
+        self.error_with_span(msg, span)
+    }
+
     /// Enforces that the node has no arguments (positional or named).
     pub fn ensure_no_args(&self) -> Result<()> {
         if !self.args()?.is_empty() {
@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+
+use crate::{
+    common_types::error::ParseError,
+    kdl::{
+        parser::{ctx::ParseContext, utils::PrimitiveType},
+        schema::{
+            definitions::{NodeSchema, PropSchema},
+            schema_context::SchemaContext,
+        },
+    },
+};
+
+/// Props and children shared by several nodes.
+///
+/// Derived by `Parser` for a struct with `#[node(field_group)]`, and embedded into
+/// a node with a `#[node(flatten)]` field. The group has no node of its own: its
+/// props are read off the embedding node, and its children are taken out of the
+/// children of that node, so they are written as if the node declared them itself.
+pub trait FieldGroup: Sized {
+    /// The props of the group, with their types.
+    const PROPS: &'static [(&'static str, PrimitiveType)];
+
+    /// The names of the children of the group.
+    const CHILDREN: &'static [&'static str];
+
+    /// Parses the group off `ctx`, taking its children out of `children`.
+    ///
+    /// Returns `None` once the errors are pushed to `errors`.
+    fn parse_group<S>(
+        ctx: &ParseContext,
+        state: &S,
+        children: &mut HashMap<String, Vec<ParseContext>>,
+        errors: &mut Vec<ParseError>,
+    ) -> Option<Self>;
+}
+
+/// The schema of a [`FieldGroup`], derived by `NodeSchema` and merged into the
+/// schema of the nodes embedding the group.
+pub trait GroupSchema {
+    fn props(ctx: &mut SchemaContext) -> Vec<PropSchema>;
+
+    fn children(ctx: &mut SchemaContext) -> Vec<NodeSchema>;
+}
+
+// The checks below run at compile time, for conflicts between a node and the
+// groups it embeds.
+
+const fn str_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+pub const fn has_prop(props: &[(&str, PrimitiveType)], key: &str) -> bool {
+    let mut i = 0;
+    while i < props.len() {
+        if str_eq(props[i].0, key) {
+            return true;
+        }
+        i += 1;
+    }
+    false
+}
+
+pub const fn has_child(children: &[&str], name: &str) -> bool {
+    let mut i = 0;
+    while i < children.len() {
+        if str_eq(children[i], name) {
+            return true;
+        }
+        i += 1;
+    }
+    false
+}
+
+pub const fn props_overlap(a: &[(&str, PrimitiveType)], b: &[(&str, PrimitiveType)]) -> bool {
+    let mut i = 0;
+    while i < a.len() {
+        if has_prop(b, a[i].0) {
+            return true;
+        }
+        i += 1;
+    }
+    false
+}
+
+pub const fn children_overlap(a: &[&str], b: &[&str]) -> bool {
+    let mut i = 0;
+    while i < a.len() {
+        if has_child(b, a[i]) {
+            return true;
+        }
+        i += 1;
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use motya_macro::{motya_node, NodeSchema, Parser};
+
+    use super::*;
+    use crate::{
+        common_types::{duration::Duration, error::ConfigError},
+        kdl::{
+            parser::parsable::KdlParsable,
+            schema::definitions::{ChildrenSchema, GetSchema},
+        },
+    };
+
+    /// Timeouts shared by the test nodes.
+    #[derive(Parser, Clone, Debug, NodeSchema)]
+    #[node(field_group)]
+    pub struct TimeoutsGroup {
+        #[node(prop, name = "connect-timeout")]
+        pub connect: Option<Duration>,
+
+        #[node(prop, name = "read-timeout")]
+        pub read: Option<Duration>,
+
+        #[node(child)]
+        pub retries: Option<usize>,
+    }
+
+    #[motya_node]
+    #[derive(Parser, Clone, Debug, NodeSchema)]
+    #[node(name = "backend")]
+    pub struct BackendDef {
+        #[node(arg)]
+        pub address: String,
+
+        #[node(flatten)]
+        pub timeouts: TimeoutsGroup,
+    }
+
+    #[motya_node]
+    #[derive(Parser, Clone, Debug, NodeSchema)]
+    #[node(name = "probe")]
+    pub struct ProbeDef {
+        #[node(prop)]
+        pub path: String,
+
+        #[node(flatten)]
+        pub timeouts: TimeoutsGroup,
+
+        #[node(child, flat)]
+        pub interval: Option<Duration>,
+    }
+
+    fn parse<T: KdlParsable<()>>(source: &str) -> Result<T, ConfigError> {
+        let doc: kdl::KdlDocument = source.parse().unwrap();
+        let ctx = ParseContext::new(doc, "test.kdl")
+            .nodes()
+            .unwrap()
+            .remove(0);
+        T::parse_node(&ctx, &())
+    }
+
+    #[test]
+    fn test_group_fields() {
+        let backend: BackendDef =
+            parse(r#"backend "10.0.0.1:80" connect-timeout="1s" { retries 3; }"#).unwrap();
+        assert_eq!(backend.address, "10.0.0.1:80");
+        assert_eq!(
+            backend.timeouts.connect.map(Duration::get),
+            Some(std::time::Duration::from_secs(1))
+        );
+        assert_eq!(backend.timeouts.read, None);
+        assert_eq!(backend.timeouts.retries, Some(3));
+
+        let probe: ProbeDef =
+            parse(r#"probe path="/health" read-timeout="250ms" { interval "5s"; }"#).unwrap();
+        assert_eq!(
+            probe.timeouts.read.map(Duration::get),
+            Some(std::time::Duration::from_millis(250))
+        );
+        assert_eq!(probe.timeouts.retries, None);
+        assert!(probe.interval.is_some());
+    }
+
+    #[test]
+    fn test_group_errors() {
+        let error = parse::<BackendDef>(r#"backend "a" connect-timeout="1""#).unwrap_err();
+        assert!(error.errors[0].message.contains("has no unit"), "{error:?}");
+
+        let error = parse::<BackendDef>(r#"backend "a" write-timeout="1s""#).unwrap_err();
+        assert!(
+            error.errors[0].message.contains("write-timeout"),
+            "{error:?}"
+        );
+
+        let error = parse::<ProbeDef>(r#"probe path="/" { retry 3; }"#).unwrap_err();
+        let unknown = &error.errors[0];
+        assert_eq!(unknown.message, "Unknown child node 'retry'");
+        assert!(unknown.help.as_deref().unwrap().contains("retries"));
+    }
+
+    #[test]
+    fn test_group_schema() {
+        let schema = BackendDef::schemas(&mut SchemaContext::default()).remove(0);
+
+        let props: Vec<_> = schema.props.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(props, ["connect-timeout", "read-timeout"]);
+
+        let ChildrenSchema::Fixed(children) = schema.children else {
+            panic!("the children of the group are part of the schema");
+        };
+        assert_eq!(children.len(), 1);
+    }
+
+    #[test]
+    fn test_overlaps() {
+        const A: &[(&str, PrimitiveType)] = &[("timeout", PrimitiveType::String)];
+        const B: &[(&str, PrimitiveType)] = &[("retries", PrimitiveType::Integer)];
+
+        assert!(has_prop(A, "timeout"));
+        assert!(!has_prop(A, "time"));
+        assert!(!props_overlap(A, B));
+        assert!(props_overlap(A, &[B[0], A[0]]));
+        assert!(children_overlap(&["a", "b"], &["b"]));
+        assert!(!children_overlap(&["a"], &[]));
+    }
+}
@@ -2,8 +2,10 @@ pub mod block;
 pub mod convert;
 pub mod ctx;
 pub mod ensures;
+pub mod field_group;
 pub mod node_schema;
 pub mod parsable;
 pub mod spanned;
+pub mod suggest;
 pub mod typed_value;
 pub mod utils;
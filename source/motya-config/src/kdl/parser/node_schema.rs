@@ -3,5 +3,10 @@ use crate::kdl::parser::ctx::ParseContext;
 pub trait NodeSchema {
     fn applicable_node_names() -> &'static [&'static str];
 
+    /// Old names the node is still parsed under, each reported as deprecated.
+    fn node_name_aliases() -> &'static [&'static str] {
+        &[]
+    }
+
     fn match_score(ctx: &ParseContext) -> (isize, Option<String>);
 }
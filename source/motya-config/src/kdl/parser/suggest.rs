@@ -0,0 +1,112 @@
+//! "Did you mean" suggestions for misspelled node and property names.
+//!
+//! The suggestion is written into the error text as `Did you mean 'name'?`, and
+//! [`suggested_name`] reads it back, so the LSP can offer it as a quick fix without
+//! the errors having to carry it separately.
+
+const PREFIX: &str = "Did you mean '";
+const SUFFIX: &str = "'?";
+
+/// The number of single-character edits that turn `a` into `b`.
+pub fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+/// The candidate closest to `input`, if one is close enough to be a likely typo.
+///
+/// Up to a third of the characters of `input` may differ, and at least one.
+pub fn closest<'a>(input: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let max_distance = (input.chars().count() / 3).max(1);
+
+    candidates
+        .into_iter()
+        .filter(|candidate| *candidate != input)
+        .map(|candidate| (levenshtein(input, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// `Did you mean 'name'?` for the candidate closest to `input`.
+pub fn did_you_mean<'a>(
+    input: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Option<String> {
+    closest(input, candidates).map(|name| format!("{PREFIX}{name}{SUFFIX}"))
+}
+
+/// Appends the suggestion for `input`, if there is one, to `msg`.
+pub fn with_suggestion<'a>(
+    msg: String,
+    input: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> String {
+    match did_you_mean(input, candidates) {
+        Some(suggestion) => format!("{msg}. {suggestion}"),
+        None => msg,
+    }
+}
+
+/// Whether `text` is nothing but a suggestion made by [`did_you_mean`].
+pub fn is_suggestion(text: &str) -> bool {
+    text.starts_with(PREFIX) && text.ends_with(SUFFIX)
+}
+
+/// The name suggested somewhere in `text` by [`did_you_mean`].
+pub fn suggested_name(text: &str) -> Option<&str> {
+    let start = text.find(PREFIX)? + PREFIX.len();
+    let len = text[start..].find(SUFFIX)?;
+    Some(&text[start..start + len])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_levenshtein() {
+        assert_eq!(levenshtein("section", "section"), 0);
+        assert_eq!(levenshtein("secton", "section"), 1);
+        assert_eq!(levenshtein("listners", "listeners"), 1);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+        assert_eq!(levenshtein("", "abc"), 3);
+    }
+
+    #[test]
+    fn test_closest_picks_the_nearest_likely_typo() {
+        let names = ["section", "proxy", "return", "load-balance"];
+
+        assert_eq!(closest("secton", names), Some("section"));
+        assert_eq!(closest("prxy", names), Some("proxy"));
+        assert_eq!(closest("load-balanse", names), Some("load-balance"));
+        assert_eq!(closest("upstream", names), None);
+        assert_eq!(closest("section", names), None);
+    }
+
+    #[test]
+    fn test_suggestion_round_trip() {
+        let msg = with_suggestion("Unknown child node 'secton'".into(), "secton", ["section"]);
+        assert_eq!(msg, "Unknown child node 'secton'. Did you mean 'section'?");
+        assert_eq!(suggested_name(&msg), Some("section"));
+        assert!(!is_suggestion(&msg));
+        assert!(is_suggestion("Did you mean 'section'?"));
+
+        let msg = with_suggestion("Unknown child node 'zzz'".into(), "zzz", ["section"]);
+        assert_eq!(msg, "Unknown child node 'zzz'");
+        assert_eq!(suggested_name(&msg), None);
+    }
+}
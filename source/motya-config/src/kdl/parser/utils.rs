@@ -89,18 +89,19 @@ pub fn get_kdl_type_name(val: &KdlValue) -> &'static str {
 
 pub mod macros_helpers {
 
-    use miette::{NamedSource, SourceSpan};
+    use miette::SourceSpan;
 
     use crate::{
         common_types::error::{ConfigError, ParseError},
         kdl::parser::{ctx::ParseContext, typed_value::TypedValue},
     };
 
+    /// The error of `e` in the document of `ctx`, at the current node of `ctx`.
     #[allow(unused)]
     pub fn to_parse_error(
         e: miette::Report,
         fallback_span: SourceSpan,
-        source: NamedSource<String>,
+        ctx: &ParseContext,
     ) -> ParseError {
         let help = e.help().map(|h| h.to_string());
 
@@ -114,7 +115,8 @@ pub mod macros_helpers {
             message: e.to_string(),
             label: Some(label),
             help,
-            src: source,
+            src: ctx,
+            path: ctx.path(),
         }
     }
 
@@ -123,9 +125,9 @@ pub mod macros_helpers {
         errors: &mut Vec<ParseError>,
         e: miette::Report,
         fallback_span: SourceSpan,
-        source: NamedSource<String>,
+        ctx: &ParseContext,
     ) {
-        errors.push(to_parse_error(e, fallback_span, source));
+        errors.push(to_parse_error(e, fallback_span, ctx));
     }
 
     #[allow(unused)]
@@ -134,13 +136,14 @@ pub mod macros_helpers {
         msg: impl Into<String>,
         help: Option<String>,
         span: SourceSpan,
-        source: NamedSource<String>,
+        ctx: &ParseContext,
     ) {
         errors.push(ParseError {
             message: msg.into(),
             label: Some(span),
             help,
-            src: source,
+            src: ctx,
+            path: ctx.path(),
         });
     }
 
@@ -158,7 +161,7 @@ pub mod macros_helpers {
             Ok(Some(val_ref)) => match parser_logic(val_ref.clone()) {
                 Ok(parsed) => Some(parsed),
                 Err(e) => {
-                    push_report(errors, e, val_ref.span(), ctx.source().clone());
+                    push_report(errors, e, val_ref.span(), ctx);
                     None
                 }
             },
@@ -173,7 +176,7 @@ pub mod macros_helpers {
                             format!("Missing required {}", desc),
                             None,
                             ctx.current_span(),
-                            ctx.source().clone(),
+                            ctx,
                         );
                     }
                     None
@@ -185,7 +188,7 @@ pub mod macros_helpers {
                     Some(def)
                 } else {
                     if is_required {
-                        push_report(errors, e, ctx.current_span(), ctx.source().clone());
+                        push_report(errors, e, ctx.current_span(), ctx);
                     }
                     None
                 }
@@ -193,6 +196,83 @@ pub mod macros_helpers {
         }
     }
 
+    /// Warns that `name` is deprecated, with `note` as help, or else a pointer to
+    /// the `replacement`.
+    #[allow(unused)]
+    pub fn warn_deprecated(
+        ctx: &ParseContext,
+        name: &str,
+        replacement: Option<&str>,
+        note: Option<&str>,
+        span: SourceSpan,
+    ) {
+        let help = note
+            .map(str::to_string)
+            .or_else(|| replacement.map(|r| format!("Use '{r}' instead")));
+        ctx.warn(format!("'{name}' is deprecated"), help, span);
+    }
+
+    /// Reads the prop `key`, or else the prop under its old name `alias`, which is
+    /// reported as deprecated. Setting both names is an error.
+    #[allow(unused)]
+    pub fn opt_prop_aliased(
+        ctx: &ParseContext,
+        key: &str,
+        alias: Option<&str>,
+        note: Option<&str>,
+        errors: &mut Vec<ParseError>,
+    ) -> miette::Result<Option<TypedValue>> {
+        let value = ctx.opt_prop(key)?;
+
+        let Some(alias) = alias else {
+            if let (Some(value), Some(_)) = (&value, note) {
+                warn_deprecated(ctx, key, None, note, value.span());
+            }
+            return Ok(value);
+        };
+
+        let Some(old) = ctx.opt_prop(alias)? else {
+            return Ok(value);
+        };
+        if value.is_some() {
+            push_custom(
+                errors,
+                format!("'{alias}' is the old name of '{key}'"),
+                Some(format!("Set only '{key}'")),
+                old.span(),
+                ctx,
+            );
+            return Ok(value);
+        }
+
+        warn_deprecated(ctx, alias, Some(key), note, old.span());
+        Ok(Some(old))
+    }
+
+    /// The key of a child in a map-typed `dynamic_child`, with the span to report
+    /// a duplicate at: the node name, or with `from_arg` its first argument.
+    #[allow(unused)]
+    pub fn child_key(ctx: &ParseContext, from_arg: bool) -> miette::Result<(String, SourceSpan)> {
+        let name = ctx.name()?;
+        if !from_arg {
+            return Ok((name.to_string(), ctx.name_span()));
+        }
+
+        let Some(entry) = ctx.args()?.iter().find(|e| e.name().is_none()) else {
+            return Err(ctx.error(format!("'{name}' needs a name as its first argument")));
+        };
+        match entry.value().as_string() {
+            Some(key) => Ok((key.to_string(), entry.span())),
+            None => Err(ctx.error_with_span(
+                format!(
+                    "The name of '{name}' must be a string, got {}",
+                    entry.value()
+                ),
+                entry.span(),
+            )),
+        }
+    }
+
     #[allow(unused)]
     pub fn merge_child_errors(
         parent_errors: &mut Vec<ParseError>,
@@ -203,3 +283,104 @@ pub mod macros_helpers {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use motya_macro::{NodeSchema, Parser};
+
+    use crate::{
+        common_types::error::{ConfigError, ParseError},
+        kdl::parser::{ctx::ParseContext, parsable::KdlParsable},
+    };
+
+    #[derive(Parser, Clone, Debug, NodeSchema)]
+    #[node(
+        name = "upstream",
+        alias = "backend",
+        deprecated = "'backend' was renamed to 'upstream'"
+    )]
+    pub struct UpstreamDef {
+        #[node(prop, alias = "timeout")]
+        pub connect_timeout: Option<String>,
+
+        #[node(prop, deprecated = "The balancer sets the weights")]
+        pub weight: Option<usize>,
+
+        #[node(child, alias = "retry")]
+        pub retries: Option<usize>,
+    }
+
+    #[derive(Parser, Clone, Debug, NodeSchema)]
+    #[node(name = "pool")]
+    pub struct PoolDef {
+        #[node(child)]
+        pub upstreams: Vec<UpstreamDef>,
+    }
+
+    fn parse(source: &str) -> (Result<PoolDef, ConfigError>, Vec<ParseError>) {
+        let doc: kdl::KdlDocument = source.parse().unwrap();
+        let ctx = ParseContext::new(doc, "test.kdl")
+            .nodes()
+            .unwrap()
+            .remove(0);
+        (PoolDef::parse_node(&ctx, &()), ctx.take_warnings())
+    }
+
+    #[test]
+    fn test_current_names() {
+        let (pool, warnings) = parse(r#"pool { upstream connect-timeout="1s" { retries 3; } }"#);
+        let upstream = &pool.unwrap().upstreams[0];
+        assert_eq!(upstream.connect_timeout.as_deref(), Some("1s"));
+        assert_eq!(upstream.retries, Some(3));
+        assert!(warnings.is_empty(), "{warnings:?}");
+    }
+
+    #[test]
+    fn test_deprecated_names() {
+        let (pool, warnings) = parse(r#"pool { backend timeout="1s" weight=2 { retry 3; } }"#);
+        let upstream = &pool.unwrap().upstreams[0];
+        assert_eq!(upstream.connect_timeout.as_deref(), Some("1s"));
+        assert_eq!(upstream.weight, Some(2));
+        assert_eq!(upstream.retries, Some(3));
+
+        let warnings: Vec<_> = warnings
+            .iter()
+            .map(|w| (w.message.as_str(), w.help.as_deref().unwrap()))
+            .collect();
+        assert_eq!(
+            warnings,
+            [
+                (
+                    "'backend' is deprecated",
+                    "'backend' was renamed to 'upstream'"
+                ),
+                ("'timeout' is deprecated", "Use 'connect-timeout' instead"),
+                ("'weight' is deprecated", "The balancer sets the weights"),
+                ("'retry' is deprecated", "Use 'retries' instead"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_old_and_new_name() {
+        let (pool, _) =
+            parse(r#"pool { upstream connect-timeout="1s" timeout="2s" { retries 3; } }"#);
+        let error = &pool.unwrap_err().errors[0];
+        assert_eq!(
+            error.message,
+            "'timeout' is the old name of 'connect-timeout'"
+        );
+    }
+
+    #[test]
+    fn test_error_path_and_location() {
+        let (pool, _) = parse("pool {\n    upstream {\n        retires 3\n    }\n}\n");
+        let error = &pool.unwrap_err().errors[0];
+        assert_eq!(error.path.as_deref(), Some("pool > upstream > retires"));
+        assert_eq!(error.location(), "test.kdl:3:9");
+        assert_eq!(
+            error.to_string(),
+            "Unknown child node 'retires' (in pool > upstream > retires)"
+        );
+    }
+}
@@ -0,0 +1,256 @@
+//! Applies the selected profile of the `profiles` section over the rest of the
+//! configuration, before it is linked.
+
+use std::collections::HashSet;
+
+use miette::NamedSource;
+
+use crate::{
+    common_types::error::{ConfigError, ParseError},
+    kdl::{
+        models::{
+            profiles::ProfileDef,
+            root::{RootDef, RootDefData},
+            system::SystemDataDef,
+        },
+        parser::spanned::Spanned,
+    },
+};
+
+/// Applies every `profile` block of the given name, in file order:
+///
+/// * the settings of its `system` replace the same settings of the base `system`,
+/// * its services replace the base services of the same name, or are added,
+/// * its definitions are added to the base ones.
+///
+/// Without a profile, the `profiles` section is only checked, never applied.
+pub fn apply_profile(
+    mut roots: Vec<RootDef>,
+    profile: Option<&str>,
+    errors: &mut ConfigError,
+) -> Vec<RootDef> {
+    let Some(profile) = profile else {
+        return roots;
+    };
+
+    let selected: Vec<ProfileDef> = declared(&roots)
+        .filter(|item| item.name == profile)
+        .cloned()
+        .collect();
+
+    if selected.is_empty() {
+        report_unknown(&roots, profile, errors);
+        return roots;
+    }
+
+    for item in selected {
+        let (data, ctx) = item.into_parts();
+
+        let replaced: HashSet<String> = data
+            .services
+            .iter()
+            .flat_map(|section| section.items.iter().map(|service| service.name.clone()))
+            .collect();
+
+        for root in &mut roots {
+            for section in &mut root.0.data.services {
+                section
+                    .0
+                    .data
+                    .items
+                    .retain(|service| !replaced.contains(&service.name));
+            }
+        }
+
+        let mut system = data.system;
+        if let Some(overlay) = system.take() {
+            match roots
+                .iter_mut()
+                .find_map(|root| root.0.data.system.as_mut())
+            {
+                Some(base) => overlay_system(base, overlay),
+                None => system = Some(overlay),
+            }
+        }
+
+        roots.push(RootDef(Spanned::new(
+            RootDefData {
+                system,
+                includes: None,
+                definitions: data.definitions,
+                services: data.services,
+                profiles: None,
+            },
+            ctx.ctx,
+        )));
+    }
+
+    roots
+}
+
+fn declared(roots: &[RootDef]) -> impl Iterator<Item = &ProfileDef> {
+    roots
+        .iter()
+        .filter_map(|root| root.profiles.as_ref())
+        .flat_map(|profiles| profiles.items.iter())
+}
+
+fn overlay_system(base: &mut SystemDataDef, overlay: SystemDataDef) {
+    let SystemDataDef {
+        tps,
+        daemonize,
+        upgrade,
+        pid,
+        providers,
+        production,
+        config_version_header,
+        admin,
+        metrics_listener,
+        shutdown_grace,
+        acme,
+    } = overlay;
+
+    base.tps = tps.or(base.tps.take());
+    base.daemonize = daemonize.or(base.daemonize.take());
+    base.upgrade = upgrade.or(base.upgrade.take());
+    base.pid = pid.or(base.pid.take());
+    base.providers = providers.or(base.providers.take());
+    base.production = production.or(base.production.take());
+    base.config_version_header = config_version_header.or(base.config_version_header.take());
+    base.admin = admin.or(base.admin.take());
+    base.metrics_listener = metrics_listener.or(base.metrics_listener.take());
+    base.shutdown_grace = shutdown_grace.or(base.shutdown_grace.take());
+    base.acme = acme.or(base.acme.take());
+}
+
+fn report_unknown(roots: &[RootDef], profile: &str, errors: &mut ConfigError) {
+    let known: Vec<&str> = declared(roots).map(|item| item.name.as_str()).collect();
+
+    let help = if known.is_empty() {
+        "Profiles are declared in a top-level 'profiles' section".to_string()
+    } else {
+        format!("Known profiles: {}", known.join(", "))
+    };
+
+    let message = format!("Profile '{profile}' is not defined");
+
+    match roots.iter().find_map(|root| root.profiles.as_ref()) {
+        Some(profiles) => {
+            let (_, ctx) = profiles.clone().into_parts();
+            let report = ctx.err_self(message);
+            let mut err = ParseError::from_report(report, &ctx.ctx);
+            err.help = Some(help);
+            errors.push(err);
+        }
+        None => {
+            let src = match roots.first() {
+                Some(root) => root.span().source(),
+                None => NamedSource::new("<config>", String::new()),
+            };
+            errors.push(ParseError::new(message, None, Some(help), src));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::{
+        common_types::definitions_table::DefinitionsTable,
+        loader::{test_support::MockConfigSource, ConfigLoader},
+    };
+
+    #[tokio::test]
+    async fn test_profiles() {
+        let config = r#"
+            system {
+                threads-per-service 8
+                pid-file "/tmp/motya.pid"
+            }
+
+            services {
+                Api {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        section "/" {
+                            proxy "http://10.0.0.1:3000"
+                        }
+                    }
+                }
+                Admin {
+                    listeners { "0.0.0.0:9000" }
+                    connectors {
+                        section "/" {
+                            return 200 "OK"
+                        }
+                    }
+                }
+            }
+
+            profiles {
+                dev {
+                    system {
+                        threads-per-service 2
+                    }
+                    services {
+                        Api {
+                            listeners { "127.0.0.1:8080" }
+                            connectors {
+                                section "/" {
+                                    proxy "http://127.0.0.1:3000"
+                                }
+                            }
+                        }
+                    }
+                }
+                prod {
+                    system {
+                        production #true
+                    }
+                }
+            }
+        "#;
+
+        let load = |profile: Option<&str>| {
+            let source = MockConfigSource::new(vec![("main.kdl", config)]);
+            let loader = ConfigLoader::new(source).with_profile(profile.map(String::from));
+            async move {
+                let mut table = DefinitionsTable::new_with_global();
+                loader
+                    .load_lossy(Some(PathBuf::from("dummy")), &mut table)
+                    .await
+            }
+        };
+
+        let (base, errors) = load(None).await;
+        assert!(errors.is_empty(), "{errors:?}");
+        let base = base.unwrap();
+        assert_eq!(base.threads_per_service, 8);
+        assert!(!base.production);
+        assert!(format!("{:?}", base.basic_proxies[0].listeners).contains("0.0.0.0:8080"));
+
+        let (dev, errors) = load(Some("dev")).await;
+        assert!(errors.is_empty(), "{errors:?}");
+        let dev = dev.unwrap();
+        assert_eq!(dev.threads_per_service, 2);
+        assert_eq!(dev.pid_file, Some(PathBuf::from("/tmp/motya.pid")));
+        let names: Vec<_> = dev.basic_proxies.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["Admin", "Api"]);
+        assert!(format!("{:?}", dev.basic_proxies[1].listeners).contains("127.0.0.1:8080"));
+
+        let (prod, errors) = load(Some("prod")).await;
+        assert!(errors.is_empty(), "{errors:?}");
+        let prod = prod.unwrap();
+        assert!(prod.production);
+        assert_eq!(prod.threads_per_service, 8);
+
+        let (_, errors) = load(Some("stage")).await;
+        let err = errors
+            .errors
+            .iter()
+            .find(|e| e.message.contains("Profile 'stage' is not defined"))
+            .expect("unknown profiles are reported");
+        assert_eq!(err.help.as_deref(), Some("Known profiles: dev, prod"));
+    }
+}
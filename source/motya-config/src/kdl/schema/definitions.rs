@@ -24,7 +24,7 @@
 //! - **Parser Macros**: Implement `GetSchema` for Rust structs to automatically generate this schema.
 //! - **WASM Plugins**: Export `CatalogItemDefinition` (as JSON) to register new functionality dynamically.
 
-use std::borrow::Cow;
+use std::{borrow::Cow, fmt};
 
 use serde::{Deserialize, Serialize};
 
@@ -36,6 +36,15 @@ pub struct DocEntry {
     pub text: Cow<'static, str>,
 }
 
+/// The doc in `lang`, or else the English one, or else any.
+pub fn localized<'a>(docs: &'a [DocEntry], lang: &str) -> Option<&'a str> {
+    docs.iter()
+        .find(|doc| doc.lang == lang)
+        .or_else(|| docs.iter().find(|doc| doc.lang == "en"))
+        .or_else(|| docs.first())
+        .map(|doc| doc.text.as_ref())
+}
+
 // =============================================================================
 // 1. PRIMITIVES & VALUE KINDS
 // =============================================================================
@@ -108,6 +117,20 @@ pub enum ValueKind {
     },
 }
 
+impl fmt::Display for ValueKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValueKind::String => f.write_str("string"),
+            ValueKind::Int => f.write_str("integer"),
+            ValueKind::Float => f.write_str("number"),
+            ValueKind::Bool => f.write_str("#true or #false"),
+            ValueKind::Enum(options) => f.write_str(&options.join(" | ")),
+            ValueKind::TypedString(name) => f.write_str(name),
+            ValueKind::Catalog { name } => write!(f, "one of the {name}"),
+        }
+    }
+}
+
 /// Defines how a KDL Node is identified and matched against this schema.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -0,0 +1,427 @@
+//! Reference documentation generated from the schema of the configuration.
+//!
+//! Every node the parser accepts gets a section named after its path, e.g.
+//! `services.$NAME.connectors.section`, with its arguments, properties, children
+//! and doc comments. Nodes that take several forms, such as `use-chain`, list each
+//! form in the same section.
+
+use clap::ValueEnum;
+
+use crate::kdl::schema::definitions::{
+    localized, ArgSchema, ChildrenSchema, NodeNameMatcher, NodeSchema, PropSchema,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DocsFormat {
+    Markdown,
+    Html,
+}
+
+/// The reference of every node below `root`, with the docs in `lang` where they
+/// have it.
+pub fn render_docs(root: &NodeSchema, format: DocsFormat, lang: &str) -> String {
+    let sections = sections(root);
+
+    match format {
+        DocsFormat::Markdown => render_markdown(&sections, lang),
+        DocsFormat::Html => render_html(&sections, lang),
+    }
+}
+
+/// The nodes found at one path.
+pub(crate) struct Section<'a> {
+    pub path: String,
+    pub forms: Vec<&'a NodeSchema>,
+}
+
+/// Every node below `root` grouped by path, in document order.
+pub(crate) fn sections(root: &NodeSchema) -> Vec<Section<'_>> {
+    let mut sections = Vec::new();
+    for node in children(root) {
+        collect(node, "", &mut sections);
+    }
+    sections
+}
+
+fn collect<'a>(node: &'a NodeSchema, parent: &str, sections: &mut Vec<Section<'a>>) {
+    let path = if parent.is_empty() {
+        name(node)
+    } else {
+        format!("{parent}.{}", name(node))
+    };
+
+    match sections.iter_mut().find(|section| section.path == path) {
+        Some(section) => section.forms.push(node),
+        None => sections.push(Section {
+            path: path.clone(),
+            forms: vec![node],
+        }),
+    }
+
+    for child in children(node) {
+        collect(child, &path, sections);
+    }
+}
+
+pub(crate) fn children(node: &NodeSchema) -> Vec<&NodeSchema> {
+    match &node.children {
+        ChildrenSchema::Fixed(list) => list.iter().collect(),
+        ChildrenSchema::Dynamic(child) => vec![child.as_ref()],
+        ChildrenSchema::None | ChildrenSchema::Recursive(_) => Vec::new(),
+    }
+}
+
+/// The keyword of a node, or `$LABEL` for a name the user picks.
+pub(crate) fn name(node: &NodeSchema) -> String {
+    match &node.matcher {
+        NodeNameMatcher::Keyword(keyword) => keyword.clone(),
+        NodeNameMatcher::Variable { label } => format!("${}", label.to_uppercase()),
+    }
+}
+
+/// How the node is written, e.g. `section <path> { ... }`.
+fn signature(node: &NodeSchema) -> String {
+    let mut text = name(node);
+    for arg in &node.args {
+        if arg.required {
+            text.push_str(&format!(" <{}>", arg.name));
+        } else {
+            text.push_str(&format!(" [{}]", arg.name));
+        }
+    }
+    if !matches!(node.children, ChildrenSchema::None) {
+        text.push_str(" { ... }");
+    }
+    text
+}
+
+/// One row of an argument or property table.
+struct Row<'a> {
+    name: &'a str,
+    kind: String,
+    required: bool,
+    default: Option<&'a str>,
+    doc: Option<&'a str>,
+}
+
+fn arg_rows<'a>(args: &'a [ArgSchema], lang: &str) -> Vec<Row<'a>> {
+    args.iter()
+        .map(|arg| Row {
+            name: &arg.name,
+            kind: arg.kind.to_string(),
+            required: arg.required,
+            default: arg.default.as_deref(),
+            doc: localized(&arg.description, lang),
+        })
+        .collect()
+}
+
+fn prop_rows<'a>(props: &'a [PropSchema], lang: &str) -> Vec<Row<'a>> {
+    props
+        .iter()
+        .map(|prop| Row {
+            name: &prop.name,
+            kind: prop.kind.to_string(),
+            required: prop.required,
+            default: prop.default.as_deref(),
+            doc: localized(&prop.description, lang),
+        })
+        .collect()
+}
+
+/// What the children block of `node` holds, as names of child nodes or the
+/// node whose children it repeats.
+enum Children {
+    None,
+    Nodes(Vec<String>),
+    SameAs(String),
+}
+
+fn child_names(node: &NodeSchema) -> Children {
+    match &node.children {
+        ChildrenSchema::None => Children::None,
+        ChildrenSchema::Recursive(name) => Children::SameAs(name.clone()),
+        _ => {
+            let mut names: Vec<String> = Vec::new();
+            for child in children(node) {
+                let child_name = name(child);
+                if !names.contains(&child_name) {
+                    names.push(child_name);
+                }
+            }
+            Children::Nodes(names)
+        }
+    }
+}
+
+fn render_markdown(sections: &[Section<'_>], lang: &str) -> String {
+    let mut out = String::from("# Configuration reference\n");
+
+    for section in sections {
+        out.push_str(&format!("\n## `{}`\n", section.path));
+
+        for node in &section.forms {
+            out.push_str(&format!("\n```kdl\n{}\n```\n", signature(node)));
+
+            if let Some(doc) = localized(&node.description, lang) {
+                out.push_str(&format!("\n{doc}\n"));
+            }
+            markdown_table(&mut out, "Argument", &arg_rows(&node.args, lang));
+            markdown_table(&mut out, "Property", &prop_rows(&node.props, lang));
+
+            match child_names(node) {
+                Children::None => {}
+                Children::Nodes(names) if names.is_empty() => {}
+                Children::Nodes(names) => {
+                    let names: Vec<String> = names.iter().map(|name| format!("`{name}`")).collect();
+                    out.push_str(&format!("\nChildren: {}.\n", names.join(", ")));
+                }
+                Children::SameAs(name) => {
+                    out.push_str(&format!(
+                        "\nChildren: the same as the enclosing `{name}`.\n"
+                    ));
+                }
+            }
+        }
+    }
+
+    out
+}
+
+fn markdown_table(out: &mut String, title: &str, rows: &[Row<'_>]) {
+    if rows.is_empty() {
+        return;
+    }
+
+    out.push_str(&format!(
+        "\n| {title} | Type | Required | Default | Description |\n|---|---|---|---|---|\n"
+    ));
+    for row in rows {
+        let cell = |text: &str| text.replace('|', "\\|").replace('\n', " ");
+        out.push_str(&format!(
+            "| `{}` | {} | {} | {} | {} |\n",
+            row.name,
+            cell(&row.kind),
+            if row.required { "yes" } else { "no" },
+            row.default
+                .map(|d| format!("`{}`", cell(d)))
+                .unwrap_or_default(),
+            row.doc.map(cell).unwrap_or_default(),
+        ));
+    }
+}
+
+fn render_html(sections: &[Section<'_>], lang: &str) -> String {
+    let mut out = format!(
+        "<!DOCTYPE html>\n<html lang=\"{}\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>Configuration reference</title>\n</head>\n<body>\n\
+         <h1>Configuration reference</h1>\n",
+        escape(lang)
+    );
+
+    for section in sections {
+        out.push_str(&format!(
+            "<section id=\"{0}\">\n<h2><code>{0}</code></h2>\n",
+            escape(&section.path)
+        ));
+
+        for node in &section.forms {
+            out.push_str(&format!(
+                "<pre><code class=\"language-kdl\">{}</code></pre>\n",
+                escape(&signature(node))
+            ));
+
+            if let Some(doc) = localized(&node.description, lang) {
+                out.push_str(&format!("<p>{}</p>\n", escape(doc)));
+            }
+            html_table(&mut out, "Argument", &arg_rows(&node.args, lang));
+            html_table(&mut out, "Property", &prop_rows(&node.props, lang));
+
+            match child_names(node) {
+                Children::None => {}
+                Children::Nodes(names) if names.is_empty() => {}
+                Children::Nodes(names) => {
+                    let links: Vec<String> = names
+                        .iter()
+                        .map(|name| {
+                            let target = escape(&format!("{}.{name}", section.path));
+                            format!("<a href=\"#{target}\"><code>{}</code></a>", escape(name))
+                        })
+                        .collect();
+                    out.push_str(&format!("<p>Children: {}.</p>\n", links.join(", ")));
+                }
+                Children::SameAs(name) => {
+                    out.push_str(&format!(
+                        "<p>Children: the same as the enclosing <code>{}</code>.</p>\n",
+                        escape(&name)
+                    ));
+                }
+            }
+        }
+
+        out.push_str("</section>\n");
+    }
+
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+fn html_table(out: &mut String, title: &str, rows: &[Row<'_>]) {
+    if rows.is_empty() {
+        return;
+    }
+
+    out.push_str(&format!(
+        "<table>\n<tr><th>{title}</th><th>Type</th><th>Required</th><th>Default</th>\
+         <th>Description</th></tr>\n"
+    ));
+    for row in rows {
+        out.push_str(&format!(
+            "<tr><td><code>{}</code></td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+            escape(row.name),
+            escape(&row.kind),
+            if row.required { "yes" } else { "no" },
+            row.default
+                .map(|d| format!("<code>{}</code>", escape(d)))
+                .unwrap_or_default(),
+            row.doc.map(escape).unwrap_or_default(),
+        ));
+    }
+    out.push_str("</table>\n");
+}
+
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use super::*;
+    use crate::kdl::{
+        models::root::RootDef,
+        schema::{
+            definitions::{DocEntry, GetSchema, ValueKind},
+            schema_context::SchemaContext,
+        },
+    };
+
+    fn doc(lang: &'static str, text: &'static str) -> DocEntry {
+        DocEntry {
+            lang: Cow::Borrowed(lang),
+            text: Cow::Borrowed(text),
+        }
+    }
+
+    fn sample() -> NodeSchema {
+        let mode = NodeSchema {
+            matcher: NodeNameMatcher::Keyword("mode".to_string()),
+            description: Cow::Owned(vec![doc("en", "How paths match."), doc("ru", "Режим.")]),
+            examples: vec![],
+            args: vec![ArgSchema {
+                name: "kind".to_string(),
+                description: Cow::Borrowed(&[]),
+                kind: ValueKind::Enum(vec!["exact".to_string(), "prefix".to_string()]),
+                required: true,
+                default: None,
+            }],
+            props: vec![PropSchema {
+                name: "strict".to_string(),
+                description: Cow::Borrowed(&[]),
+                kind: ValueKind::Bool,
+                required: false,
+                default: Some("#false".to_string()),
+            }],
+            children: ChildrenSchema::None,
+        };
+        let route = NodeSchema {
+            matcher: NodeNameMatcher::Variable {
+                label: "name".to_string(),
+            },
+            description: Cow::Borrowed(&[]),
+            examples: vec![],
+            args: vec![],
+            props: vec![],
+            children: ChildrenSchema::Fixed(vec![mode]),
+        };
+        let routes = NodeSchema {
+            matcher: NodeNameMatcher::Keyword("routes".to_string()),
+            description: Cow::Borrowed(&[]),
+            examples: vec![],
+            args: vec![],
+            props: vec![],
+            children: ChildrenSchema::Dynamic(Box::new(route)),
+        };
+
+        NodeSchema {
+            matcher: NodeNameMatcher::Keyword("<unknown>".to_string()),
+            description: Cow::Borrowed(&[]),
+            examples: vec![],
+            args: vec![],
+            props: vec![],
+            children: ChildrenSchema::Fixed(vec![routes]),
+        }
+    }
+
+    #[test]
+    fn test_markdown_docs() {
+        let docs = render_docs(&sample(), DocsFormat::Markdown, "en");
+
+        assert!(docs.contains("## `routes.$NAME.mode`"), "{docs}");
+        assert!(docs.contains("```kdl\nmode <kind>\n```"), "{docs}");
+        assert!(docs.contains("How paths match."), "{docs}");
+        assert!(
+            docs.contains("| `kind` | exact \\| prefix | yes |  |  |"),
+            "{docs}"
+        );
+        assert!(
+            docs.contains("| `strict` | #true or #false | no | `#false` |  |"),
+            "{docs}"
+        );
+        assert!(docs.contains("Children: `$NAME`."), "{docs}");
+
+        let docs = render_docs(&sample(), DocsFormat::Markdown, "ru");
+        assert!(docs.contains("Режим."), "{docs}");
+    }
+
+    #[test]
+    fn test_html_docs() {
+        let docs = render_docs(&sample(), DocsFormat::Html, "en");
+
+        assert!(
+            docs.contains("<section id=\"routes.$NAME.mode\">"),
+            "{docs}"
+        );
+        assert!(
+            docs.contains("<code class=\"language-kdl\">mode &lt;kind&gt;</code>"),
+            "{docs}"
+        );
+        assert!(
+            docs.contains("<a href=\"#routes.$NAME\"><code>$NAME</code></a>"),
+            "{docs}"
+        );
+        assert!(docs.ends_with("</html>\n"));
+    }
+
+    #[test]
+    fn test_docs_cover_the_config() {
+        let root = RootDef::schemas(&mut SchemaContext::default()).remove(0);
+        let docs = render_docs(&root, DocsFormat::Markdown, "en");
+
+        assert!(docs.contains("## `services.$NAME.connectors.section`"));
+        assert!(docs.contains("## `services.$NAME.connectors.section.use-chain`"));
+        assert!(docs.contains("Runs the filters listed in the block in order."));
+        assert!(docs.contains("Children: the same as the enclosing `section`."));
+    }
+}
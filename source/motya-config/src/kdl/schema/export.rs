@@ -0,0 +1,297 @@
+//! Machine-readable exports of the schema of the configuration.
+//!
+//! `json` is the [`NodeSchema`] tree as the LSP sees it. `json-schema` is a
+//! JSON Schema (draft 2020-12) for configs written as JSON: a document is an
+//! array of nodes, and a node is an object with `name`, `args`, `props` and
+//! `children`, e.g.
+//!
+//! ```json
+//! [{ "name": "system", "children": [{ "name": "threads-per-service", "args": [8] }] }]
+//! ```
+//!
+//! The JSON Schema checks node names, argument and property types, required
+//! entries and which children a block allows. Properties are not closed, as
+//! nodes such as `filter` take any property.
+
+use clap::ValueEnum;
+use serde_json::{json, Map, Value};
+
+use crate::kdl::schema::{
+    definitions::{localized, ChildrenSchema, NodeNameMatcher, NodeSchema, ValueKind},
+    docs::{children, name, sections, Section},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    Json,
+    JsonSchema,
+}
+
+pub fn export_schema(root: &NodeSchema, format: ExportFormat) -> serde_json::Result<Value> {
+    match format {
+        ExportFormat::Json => serde_json::to_value(root),
+        ExportFormat::JsonSchema => Ok(json_schema(root)),
+    }
+}
+
+fn json_schema(root: &NodeSchema) -> Value {
+    let sections = sections(root);
+    let mut defs = Map::new();
+
+    defs.insert(children_key(""), children_def(&sections, "", &[root]));
+    for section in &sections {
+        defs.insert(section.path.clone(), node_def(section));
+        if section
+            .forms
+            .iter()
+            .any(|node| !matches!(node.children, ChildrenSchema::None))
+        {
+            defs.insert(
+                children_key(&section.path),
+                children_def(&sections, &section.path, &section.forms),
+            );
+        }
+    }
+
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "Motya configuration",
+        "$ref": reference(&children_key("")),
+        "$defs": defs,
+    })
+}
+
+/// The key of the children of the nodes at `path`; the top level of a document
+/// has the empty path.
+fn children_key(path: &str) -> String {
+    if path.is_empty() {
+        "document".to_string()
+    } else {
+        format!("{path}:children")
+    }
+}
+
+fn reference(key: &str) -> Value {
+    let pointer = key.replace('~', "~0").replace('/', "~1");
+    json!({ "$ref": format!("#/$defs/{pointer}") })
+}
+
+fn node_def(section: &Section<'_>) -> Value {
+    let mut forms: Vec<Value> = section
+        .forms
+        .iter()
+        .map(|node| form_def(node, &section.path))
+        .collect();
+
+    match forms.len() {
+        1 => forms.remove(0),
+        _ => json!({ "anyOf": forms }),
+    }
+}
+
+fn form_def(node: &NodeSchema, path: &str) -> Value {
+    let mut properties = Map::new();
+    let mut required = vec![json!("name")];
+
+    let name = match &node.matcher {
+        NodeNameMatcher::Keyword(keyword) => json!({ "const": keyword }),
+        NodeNameMatcher::Variable { .. } => json!({ "type": "string" }),
+    };
+    properties.insert("name".to_string(), name);
+
+    if !node.args.is_empty() {
+        let items: Vec<Value> = node
+            .args
+            .iter()
+            .map(|arg| described(value_def(&arg.kind), localized(&arg.description, "en")))
+            .collect();
+        let min_items = node.args.iter().take_while(|arg| arg.required).count();
+
+        properties.insert(
+            "args".to_string(),
+            json!({ "type": "array", "prefixItems": items, "minItems": min_items }),
+        );
+        if min_items > 0 {
+            required.push(json!("args"));
+        }
+    }
+
+    if !node.props.is_empty() {
+        let props: Map<String, Value> = node
+            .props
+            .iter()
+            .map(|prop| {
+                let def = described(value_def(&prop.kind), localized(&prop.description, "en"));
+                (prop.name.clone(), def)
+            })
+            .collect();
+        let required_props: Vec<&str> = node
+            .props
+            .iter()
+            .filter(|prop| prop.required)
+            .map(|prop| prop.name.as_str())
+            .collect();
+
+        properties.insert(
+            "props".to_string(),
+            json!({ "type": "object", "properties": props, "required": required_props }),
+        );
+        if !required_props.is_empty() {
+            required.push(json!("props"));
+        }
+    }
+
+    let children = match node.children {
+        ChildrenSchema::None => json!({ "type": "array", "maxItems": 0 }),
+        _ => reference(&children_key(path)),
+    };
+    properties.insert("children".to_string(), children);
+
+    let def = json!({
+        "type": "object",
+        "properties": properties,
+        "required": required,
+    });
+    described(def, localized(&node.description, "en"))
+}
+
+/// The children the nodes at `path` allow. A recursive block allows those of
+/// the nearest enclosing node of the same name.
+fn children_def(sections: &[Section<'_>], path: &str, forms: &[&NodeSchema]) -> Value {
+    let mut paths: Vec<String> = Vec::new();
+
+    for node in forms {
+        let (parent, found) = match &node.children {
+            ChildrenSchema::Recursive(origin) => {
+                let parent = enclosing(sections, path, origin);
+                let found = sections
+                    .iter()
+                    .filter(|section| section.path == parent)
+                    .flat_map(|section| section.forms.iter().flat_map(|form| children(form)))
+                    .collect();
+                (parent, found)
+            }
+            _ => (path.to_string(), children(node)),
+        };
+
+        for child in found {
+            let child_path = match parent.as_str() {
+                "" => name(child),
+                parent => format!("{parent}.{}", name(child)),
+            };
+            if !paths.contains(&child_path) {
+                paths.push(child_path);
+            }
+        }
+    }
+
+    let items: Vec<Value> = paths.iter().map(|path| reference(path)).collect();
+    json!({ "type": "array", "items": { "anyOf": items } })
+}
+
+/// The path of the nearest node named `origin` around `path` that is not
+/// itself recursive.
+fn enclosing(sections: &[Section<'_>], path: &str, origin: &str) -> String {
+    let segments: Vec<&str> = path.split('.').collect();
+
+    (1..segments.len())
+        .rev()
+        .map(|len| segments[..len].join("."))
+        .find(|candidate| {
+            candidate.rsplit('.').next() == Some(origin)
+                && sections.iter().any(|section| {
+                    section.path == *candidate
+                        && section
+                            .forms
+                            .iter()
+                            .any(|form| !matches!(form.children, ChildrenSchema::Recursive(_)))
+                })
+        })
+        .unwrap_or_default()
+}
+
+fn value_def(kind: &ValueKind) -> Value {
+    match kind {
+        ValueKind::String => json!({ "type": "string" }),
+        ValueKind::Int => json!({ "type": "integer" }),
+        ValueKind::Float => json!({ "type": "number" }),
+        ValueKind::Bool => json!({ "type": "boolean" }),
+        ValueKind::Enum(options) => json!({ "enum": options }),
+        ValueKind::TypedString(format) => json!({ "type": "string", "format": format }),
+        ValueKind::Catalog { .. } => json!({ "type": "string", "description": kind.to_string() }),
+    }
+}
+
+fn described(mut def: Value, doc: Option<&str>) -> Value {
+    if let (Some(doc), Some(object)) = (doc, def.as_object_mut()) {
+        object.entry("description").or_insert_with(|| json!(doc));
+    }
+    def
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kdl::{
+        models::root::RootDef,
+        schema::{definitions::GetSchema, schema_context::SchemaContext},
+    };
+
+    fn export() -> Value {
+        let root = RootDef::schemas(&mut SchemaContext::default()).remove(0);
+        export_schema(&root, ExportFormat::JsonSchema).unwrap()
+    }
+
+    #[test]
+    fn test_json_schema_nodes() {
+        let schema = export();
+        let defs = &schema["$defs"];
+
+        assert_eq!(schema["$ref"], "#/$defs/document");
+        assert!(defs["document"]["items"]["anyOf"]
+            .as_array()
+            .unwrap()
+            .contains(&json!({ "$ref": "#/$defs/services" })));
+
+        let threads = &defs["system.threads-per-service"];
+        assert_eq!(
+            threads["properties"]["name"],
+            json!({ "const": "threads-per-service" })
+        );
+        assert_eq!(
+            threads["properties"]["args"]["prefixItems"][0]["type"],
+            "integer"
+        );
+        assert_eq!(threads["properties"]["args"]["minItems"], 1);
+        assert_eq!(threads["required"], json!(["name", "args"]));
+
+        let service = &defs["services.$NAME"];
+        assert_eq!(service["properties"]["name"], json!({ "type": "string" }));
+
+        // `use-chain` is either a reference or an inline block.
+        let use_chain = &defs["services.$NAME.connectors.section.use-chain"];
+        assert_eq!(use_chain["anyOf"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_json_schema_recursive_children() {
+        let schema = export();
+        let defs = &schema["$defs"];
+
+        let nested = defs["services.$NAME.connectors.section.section:children"]["items"]["anyOf"]
+            .as_array()
+            .unwrap();
+        assert!(nested.contains(&json!({
+            "$ref": "#/$defs/services.$NAME.connectors.section.proxy"
+        })));
+    }
+
+    #[test]
+    fn test_json_export() {
+        let root = RootDef::schemas(&mut SchemaContext::default()).remove(0);
+        let value = export_schema(&root, ExportFormat::Json).unwrap();
+
+        assert_eq!(value["matcher"]["keyword"], "<unknown>");
+        assert!(value["children"]["fixed"].is_array());
+    }
+}
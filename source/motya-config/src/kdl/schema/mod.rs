@@ -1,3 +1,7 @@
 pub mod definitions;
+pub mod docs;
+pub mod export;
 pub mod value_info;
 pub mod schema_context;
+#[cfg(any(test, feature = "schema-snapshots"))]
+pub mod snapshot;
@@ -0,0 +1,153 @@
+//! A plain-text rendering of the schema of a node, for snapshot tests of the
+//! grammar of the configuration.
+//!
+//! The text has one line per node, with its arguments, then a line per property
+//! and the children nested below it:
+//!
+//! ```text
+//! backend <address: string> {
+//!   connect-timeout=duration
+//!   weight=integer (required)
+//!   retries <value: integer>
+//! }
+//! ```
+//!
+//! Docs and examples are left out, so only changes to what a config may say show
+//! up in a snapshot. `Parser` implements [`SchemaSnapshot`] for every node in
+//! tests, and in other builds with the `schema-snapshots` feature.
+
+use crate::kdl::schema::{
+    definitions::{ChildrenSchema, GetSchema, NodeSchema},
+    docs::name,
+    schema_context::SchemaContext,
+};
+
+pub trait SchemaSnapshot {
+    /// Whether this is the root of a document, whose children are written at
+    /// the top level.
+    const ROOT: bool;
+
+    /// The grammar of the node, see [`render`].
+    fn schema_snapshot() -> String
+    where
+        Self: GetSchema + Sized,
+    {
+        let schemas = Self::schemas(&mut SchemaContext::default());
+        if !Self::ROOT {
+            return render(&schemas);
+        }
+
+        let mut out = String::new();
+        for schema in &schemas {
+            write_children(&mut out, schema, 0);
+        }
+        out
+    }
+}
+
+/// The grammar of `nodes`, in the order of the schema.
+pub fn render(nodes: &[NodeSchema]) -> String {
+    let mut out = String::new();
+    for node in nodes {
+        write_node(&mut out, node, 0, false);
+    }
+    out
+}
+
+fn write_node(out: &mut String, node: &NodeSchema, depth: usize, repeated: bool) {
+    let indent = "  ".repeat(depth);
+
+    out.push_str(&indent);
+    if repeated {
+        out.push_str("* ");
+    }
+    out.push_str(&name(node));
+    for arg in &node.args {
+        let mut text = format!("{}: {}", arg.name, arg.kind);
+        if let Some(default) = &arg.default {
+            text.push_str(&format!(" = {default}"));
+        }
+        if arg.required {
+            out.push_str(&format!(" <{text}>"));
+        } else {
+            out.push_str(&format!(" [{text}]"));
+        }
+    }
+    match &node.children {
+        ChildrenSchema::None => out.push('\n'),
+        ChildrenSchema::Recursive(name) => out.push_str(&format!(" {{ same as {name} }}\n")),
+        ChildrenSchema::Fixed(_) | ChildrenSchema::Dynamic(_) => out.push_str(" {\n"),
+    }
+
+    for prop in &node.props {
+        out.push_str(&format!("{indent}  {}={}", prop.name, prop.kind));
+        if prop.required {
+            out.push_str(" (required)");
+        }
+        if let Some(default) = &prop.default {
+            out.push_str(&format!(" = {default}"));
+        }
+        out.push('\n');
+    }
+
+    if matches!(
+        node.children,
+        ChildrenSchema::Fixed(_) | ChildrenSchema::Dynamic(_)
+    ) {
+        write_children(out, node, depth + 1);
+        out.push_str(&format!("{indent}}}\n"));
+    }
+}
+
+fn write_children(out: &mut String, node: &NodeSchema, depth: usize) {
+    match &node.children {
+        ChildrenSchema::Fixed(list) => {
+            for child in list {
+                write_node(out, child, depth, false);
+            }
+        }
+        ChildrenSchema::Dynamic(child) => write_node(out, child, depth, true),
+        ChildrenSchema::None | ChildrenSchema::Recursive(_) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use motya_macro::{motya_node, NodeSchema, Parser};
+
+    use super::*;
+    use crate::{common_types::duration::Duration, kdl::models::root::RootDef};
+
+    #[motya_node]
+    #[derive(Parser, Clone, Debug, NodeSchema)]
+    #[node(name = "backend")]
+    pub struct BackendDef {
+        #[node(arg)]
+        pub address: String,
+
+        #[node(prop, name = "connect-timeout")]
+        pub connect_timeout: Option<Duration>,
+
+        #[node(prop)]
+        pub weight: usize,
+
+        #[node(child)]
+        pub retries: Option<usize>,
+    }
+
+    #[test]
+    fn test_node_snapshot() {
+        insta::assert_snapshot!(BackendDef::schema_snapshot(), @r"
+        backend <address: string> {
+          connect-timeout=duration
+          weight=integer (required)
+          retries <value: integer>
+        }
+        ");
+    }
+
+    #[test]
+    fn test_config_grammar_snapshot() {
+        insta::assert_snapshot!(RootDef::schema_snapshot());
+    }
+}
@@ -2,6 +2,8 @@
 pub mod cli;
 pub mod common_types;
 pub mod config_source;
+#[cfg(test)]
+mod fixtures;
 pub mod internal;
 pub mod kdl;
 pub mod loader;
@@ -10,6 +10,7 @@ use crate::{
         linker::ConfigLinker,
         models::root::RootDef,
         parser::{ctx::ParseContext, parsable::KdlParsable},
+        profiles::apply_profile,
     },
 };
 
@@ -25,6 +26,7 @@ pub trait FileConfigLoaderProvider {
 #[derive(Clone)]
 pub struct ConfigLoader<S: ConfigSource> {
     source: S,
+    profile: Option<String>,
 }
 
 impl<S: ConfigSource> FileConfigLoaderProvider for ConfigLoader<S> {
@@ -39,13 +41,27 @@ impl<S: ConfigSource> FileConfigLoaderProvider for ConfigLoader<S> {
             return Err(miette::Report::new(errors));
         }
 
+        for warning in &errors.warnings {
+            tracing::warn!("{}: {warning}", warning.location());
+        }
+
         Ok(config)
     }
 }
 
 impl<S: ConfigSource> ConfigLoader<S> {
     pub fn new(source: S) -> Self {
-        Self { source }
+        Self {
+            source,
+            profile: None,
+        }
+    }
+
+    /// Applies the profile of this name from the `profiles` section over the
+    /// rest of the configuration.
+    pub fn with_profile(mut self, profile: Option<String>) -> Self {
+        self.profile = profile;
+        self
     }
 
     pub async fn load_lossy(
@@ -70,17 +86,16 @@ impl<S: ConfigSource> ConfigLoader<S> {
                     errors.merge(config_error);
                 }
             }
+            errors.warnings.extend(ctx.take_warnings());
         }
 
+        let roots = apply_profile(roots, self.profile.as_deref(), &mut errors);
+
         let config = if !roots.is_empty() {
             let linker = ConfigLinker::new(global_definitions);
-            match linker.link(roots) {
-                Ok(cfg) => Some(cfg),
-                Err(config_error) => {
-                    errors.merge(config_error);
-                    None
-                }
-            }
+            let (config, link_errors) = linker.link(roots);
+            errors.merge(link_errors);
+            config
         } else {
             None
         };
@@ -89,24 +104,29 @@ impl<S: ConfigSource> ConfigLoader<S> {
     }
 }
 
+/// Configurations loaded from memory, for the tests of the loader and of the
+/// compilers it runs.
 #[cfg(test)]
-mod tests {
+pub(crate) mod test_support {
     use std::path::PathBuf;
 
     use kdl::KdlDocument;
     use miette::Result;
 
     use crate::{
-        common_types::definitions_table::DefinitionsTable, config_source::ConfigSource, kdl::schema::schema_context::SchemaContext, loader::{ConfigLoader, FileConfigLoaderProvider}
+        common_types::{definitions_table::DefinitionsTable, error::ConfigError},
+        config_source::ConfigSource,
+        internal::Config,
+        loader::{ConfigLoader, FileConfigLoaderProvider},
     };
 
     #[derive(Clone, Default)]
-    struct MockConfigSource {
+    pub struct MockConfigSource {
         files: Vec<(&'static str, &'static str)>,
     }
 
     impl MockConfigSource {
-        fn new(files: Vec<(&'static str, &'static str)>) -> Self {
+        pub fn new(files: Vec<(&'static str, &'static str)>) -> Self {
             Self { files }
         }
     }
@@ -122,6 +142,89 @@ mod tests {
         }
     }
 
+    /// Loads `files`, which must compile without errors.
+    pub async fn load(files: Vec<(&'static str, &'static str)>) -> Config {
+        ConfigLoader::new(MockConfigSource::new(files))
+            .load_entry_point(
+                Some(PathBuf::from("dummy")),
+                &mut DefinitionsTable::new_with_global(),
+            )
+            .await
+            .expect("Should compile without errors")
+            .expect("Should return config")
+    }
+
+    /// Loads `files`, which must compile without errors, for the definitions
+    /// they declare.
+    pub async fn load_definitions(files: Vec<(&'static str, &'static str)>) -> DefinitionsTable {
+        let mut table = DefinitionsTable::new_with_global();
+        ConfigLoader::new(MockConfigSource::new(files))
+            .load_entry_point(Some(PathBuf::from("dummy")), &mut table)
+            .await
+            .expect("Should compile without errors")
+            .expect("Should return config");
+        table
+    }
+
+    /// Loads `files`, which must fail to compile, and returns what was reported.
+    pub async fn load_errors(files: Vec<(&'static str, &'static str)>) -> ConfigError {
+        let (_, errors) = ConfigLoader::new(MockConfigSource::new(files))
+            .load_lossy(
+                Some(PathBuf::from("dummy")),
+                &mut DefinitionsTable::new_with_global(),
+            )
+            .await;
+
+        assert!(!errors.is_empty(), "Should fail to compile");
+        errors
+    }
+
+    /// A service whose route uses the staging-only `delay` filter, to load
+    /// definitions along with.
+    pub const DELAY_SERVICES: &str = r#"
+            services {
+                Staging {
+                    listeners {
+                        "0.0.0.0:8080"
+                    }
+                    connectors {
+                        section "/api" {
+                            use-chain {
+                                filter "motya.filters.delay" duration="150ms" jitter="50ms"
+                            }
+                            proxy "http://127.0.0.1:3000"
+                        }
+                    }
+                }
+            }
+        "#;
+
+    /// The `local` and `shared` storages, for rate-limit policies.
+    pub const RATE_LIMIT_STORAGES: &str = r#"
+            definitions {
+                storages {
+                    memory "local" { max-keys 1000; }
+                    redis "shared" { addresses "127.0.0.1:6379"; }
+                }
+            }
+        "#;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::{
+        common_types::definitions_table::DefinitionsTable,
+        kdl::schema::schema_context::SchemaContext,
+        loader::{
+            test_support::{
+                load, load_errors, MockConfigSource, DELAY_SERVICES, RATE_LIMIT_STORAGES,
+            },
+            ConfigLoader,
+        },
+    };
+
     #[tokio::test]
     async fn test_full_configuration_snapshot() {
         let kdl_content = r#"
@@ -183,16 +286,7 @@ mod tests {
             }
         "#;
 
-        let source = MockConfigSource::new(vec![("main.kdl", kdl_content)]);
-        let loader = ConfigLoader { source };
-
-        let mut table = DefinitionsTable::new_with_global();
-
-        let config = loader
-            .load_entry_point(Some(PathBuf::from("dummy")), &mut table)
-            .await
-            .expect("Should compile without errors")
-            .expect("Should return config");
+        let config = load(vec![("main.kdl", kdl_content)]).await;
 
         insta::assert_debug_snapshot!(config);
     }
@@ -207,4 +301,146 @@ mod tests {
         insta::assert_yaml_snapshot!(schema);
     }
 
+    #[tokio::test]
+    async fn test_errors_by_source() {
+        let public = r#"
+            services {
+                Public {
+                    listeners { "0.0.0.0:8080" }
+                    connectors {
+                        section "/a" {
+                            compression level=12
+                            proxy "http://127.0.0.1:3000"
+                        }
+                        section "/b" {
+                            compression algorithms="deflate"
+                            proxy "http://127.0.0.1:3001"
+                        }
+                    }
+                }
+            }
+        "#;
+        let internal = r#"
+            services {
+                Internal {
+                    listeners { "127.0.0.1:9090" }
+                    connectors {
+                        compression level=0
+                        section "/" {
+                            proxy "http://127.0.0.1:3002"
+                        }
+                    }
+                }
+            }
+        "#;
+        let errors = load_errors(vec![("public.kdl", public), ("internal.kdl", internal)]).await;
+
+        let sources: Vec<_> = errors
+            .into_sources()
+            .into_iter()
+            .map(|(name, errors)| (name, errors.len()))
+            .collect();
+        assert_eq!(
+            sources,
+            [
+                ("public.kdl".to_string(), 2),
+                ("internal.kdl".to_string(), 1)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_misspelled_node_suggests_the_closest_name() {
+        let system = r#"system { metrics-listner "0.0.0.0:9090"; }"#;
+        let errors = load_errors(vec![("system.kdl", system), ("main.kdl", DELAY_SERVICES)]).await;
+        let error = errors
+            .errors
+            .iter()
+            .find(|e| e.message.contains("'metrics-listner'"))
+            .expect("Should report the unknown node");
+        assert_eq!(
+            error.help.as_deref(),
+            Some("Did you mean 'metrics-listener'?")
+        );
+
+        let span = error.label.expect("Should point at the node");
+        assert_eq!(
+            &system[span.offset()..span.offset() + span.len()],
+            "metrics-listner"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_duration_without_unit() {
+        let storages = r#"
+            definitions {
+                storages {
+                    memory "local" {
+                        max-keys 1000
+                        cleanup-interval "60"
+                    }
+                }
+            }
+        "#;
+        let errors = load_errors(vec![("storages.kdl", storages)]).await;
+        assert_eq!(errors.count, 1);
+
+        let error = &errors.errors[0];
+        assert!(
+            error
+                .message
+                .contains("'60' has no unit. Write '60s' for seconds or '60ms' for milliseconds"),
+            "{}",
+            error.message
+        );
+
+        let span = error.label.expect("Error should point at the value");
+        let labeled = &storages[span.offset()..span.offset() + span.len()];
+        assert!(labeled.contains("\"60\""), "labeled: {labeled:?}");
+        assert!(!labeled.contains("max-keys"), "labeled: {labeled:?}");
+    }
+
+    #[tokio::test]
+    async fn test_warnings_do_not_fail_the_load() {
+        let policies = r#"
+            definitions {
+                rate-limits {
+                    policy "old" {
+                        algorithm "token_bucket"
+                        storage "local"
+                        key "${client-ip}"
+                        rate "1s"
+                    }
+                    policy "per-path" {
+                        storage "local"
+                        key "${client-ip}${uri-path}"
+                        rate "1s"
+                    }
+                }
+            }
+        "#;
+        let source = MockConfigSource::new(vec![
+            ("storages.kdl", RATE_LIMIT_STORAGES),
+            ("policies.kdl", policies),
+            ("main.kdl", DELAY_SERVICES),
+        ]);
+        let loader = ConfigLoader::new(source);
+        let mut table = DefinitionsTable::new_with_global();
+
+        let (config, errors) = loader
+            .load_lossy(Some(PathBuf::from("dummy")), &mut table)
+            .await;
+
+        assert!(config.is_some());
+        assert!(errors.is_empty());
+        assert_eq!(errors.count, 0);
+        assert_eq!(errors.warnings.len(), 2);
+        assert!(errors.warnings[0]
+            .message
+            .contains("The algorithm name 'token_bucket' is deprecated, use 'token-bucket'"));
+        assert!(errors.warnings[1]
+            .message
+            .contains("builds its key from ${uri-path}"));
+        assert_eq!(errors.warnings[1].src.name(), "policies.kdl");
+    }
 }
@@ -1,38 +1,375 @@
-use std::sync::Arc;
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::{
+        Arc, Mutex, OnceLock,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::Duration,
+};
 
 use dashmap::DashMap;
-use motya_config::{common_types::definitions_table::DefinitionsTable, loader::ConfigLoader};
+use kdl::KdlDocument;
+use motya_config::{
+    common_types::definitions_table::{DefinitionKind, DefinitionSpan, DefinitionsTable},
+    loader::ConfigLoader,
+};
 use ropey::Rope;
-use tower_lsp::{Client, lsp_types::Url};
+use tokio::{runtime::Handle, sync::Semaphore};
+use tower_lsp::{
+    Client,
+    lsp_types::{Diagnostic, DiagnosticSeverity, Position, TextDocumentContentChangeEvent, Url},
+};
 
 use crate::{diagnostics::DiagnosticConverter, loader::LspConfigSource};
 
-#[derive(Debug)]
+/// How long a single validation may run before its diagnostics are given up on.
+const VALIDATION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long typing must pause before a changed document is validated.
+const DEBOUNCE: Duration = Duration::from_millis(150);
+
+#[derive(Debug, Clone)]
 pub struct Backend {
     pub client: Client,
     pub documents: Arc<DashMap<Url, Rope>>,
+    /// The validation scheduled for the latest version of each open document.
+    jobs: Arc<DashMap<Url, Job>>,
+    /// Bounds the number of documents parsed at the same time.
+    workers: Arc<Semaphore>,
+    /// The included files each open document last published diagnostics to.
+    published: Arc<DashMap<Url, Vec<Url>>>,
+    /// The definitions each open document saw in its latest validation.
+    definitions: Arc<DashMap<Url, DefinitionsTable>>,
+    /// The other files each open document read in its latest validation.
+    dependencies: Arc<DashMap<Url, HashSet<Url>>>,
+    /// The parsed files, shared by all validations.
+    parsed: Arc<DashMap<PathBuf, (String, KdlDocument)>>,
+    /// The language of the client, e.g. `en`, used to pick localized docs.
+    pub language: Arc<OnceLock<String>>,
+}
+
+/// A validation of one version of a document.
+///
+/// It is cancelled as soon as a newer version arrives; the parser checks the flag
+/// between files and a cancelled job never publishes its diagnostics.
+#[derive(Debug, Clone)]
+struct Job {
+    version: i32,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Job {
+    fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
 }
 
 impl Backend {
-    pub async fn validate(&self, uri: Url) {
+    pub fn new(client: Client) -> Self {
+        let workers = thread::available_parallelism().map_or(2, |n| n.get());
+
+        Self {
+            client,
+            documents: Arc::new(DashMap::new()),
+            jobs: Arc::new(DashMap::new()),
+            workers: Arc::new(Semaphore::new(workers)),
+            published: Arc::new(DashMap::new()),
+            definitions: Arc::new(DashMap::new()),
+            dependencies: Arc::new(DashMap::new()),
+            parsed: Arc::new(DashMap::new()),
+            language: Arc::new(OnceLock::new()),
+        }
+    }
+
+    /// Where the definition `name` is declared, as seen by the document `uri`
+    /// or else by any other open document.
+    pub fn definition(
+        &self,
+        uri: &Url,
+        kind: DefinitionKind,
+        name: &str,
+    ) -> Option<DefinitionSpan> {
+        if let Some(span) = self
+            .definitions
+            .get(uri)
+            .and_then(|defs| defs.get_span(kind, name).cloned())
+        {
+            return Some(span);
+        }
+        self.definitions
+            .iter()
+            .find_map(|defs| defs.get_span(kind, name).cloned())
+    }
+
+    /// Validates `version` of the document in the background, and again the open
+    /// documents that include it. Older validations still running are cancelled.
+    pub fn schedule(&self, uri: Url, version: i32) {
+        let dependents: Vec<(Url, i32)> = self
+            .dependencies
+            .iter()
+            .filter(|entry| entry.value().contains(&uri))
+            .filter_map(|entry| {
+                let job = self.jobs.get(entry.key())?;
+                Some((entry.key().clone(), job.version))
+            })
+            .collect();
+
+        self.start(uri, version);
+        for (dependent, version) in dependents {
+            self.start(dependent, version);
+        }
+    }
+
+    fn start(&self, uri: Url, version: i32) {
+        let job = Job {
+            version,
+            cancelled: Arc::new(AtomicBool::new(false)),
+        };
+
+        if let Some(previous) = self.jobs.insert(uri.clone(), job.clone()) {
+            previous.cancel();
+        }
+
+        let backend = self.clone();
+        tokio::spawn(async move { backend.validate(uri, job).await });
+    }
+
+    /// Cancels the validation of a closed document. Returns the included files
+    /// whose diagnostics came from it.
+    pub fn forget(&self, uri: &Url) -> Vec<Url> {
+        if let Some((_, job)) = self.jobs.remove(uri) {
+            job.cancel();
+        }
+        self.definitions.remove(uri);
+        self.dependencies.remove(uri);
+
+        // Keep only the parsed files an open document still reads.
+        self.parsed.retain(|path, _| {
+            Url::from_file_path(path).is_ok_and(|file| {
+                self.jobs.contains_key(&file)
+                    || self.dependencies.iter().any(|files| files.contains(&file))
+            })
+        });
+
+        self.published
+            .remove(uri)
+            .map(|(_, files)| files)
+            .unwrap_or_default()
+    }
+
+    async fn validate(&self, uri: Url, job: Job) {
         let path = match uri.to_file_path() {
             Ok(p) => p,
             Err(_) => return,
         };
 
+        // Wait for the typing to pause: a newer version cancels this job meanwhile.
+        tokio::time::sleep(DEBOUNCE).await;
+        if job.is_cancelled() {
+            return;
+        }
+
+        let Ok(permit) = self.workers.clone().acquire_owned().await else {
+            return;
+        };
+
+        if job.is_cancelled() {
+            return;
+        }
+
+        let loaded = Arc::new(Mutex::new(HashSet::new()));
         let source = LspConfigSource {
             documents: self.documents.clone(),
+            cancelled: job.cancelled.clone(),
+            parsed: self.parsed.clone(),
+            loaded: loaded.clone(),
+        };
+
+        // Parsing and linking are CPU-bound, so they run on a blocking thread and the
+        // server keeps answering the editor meanwhile. The permit travels with the work:
+        // a job that timed out still occupies its worker until it notices the cancellation.
+        let handle = Handle::current();
+        let work = tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            let loader = ConfigLoader::new(source);
+            let mut defs = DefinitionsTable::new_with_global();
+
+            let (_, error) = handle.block_on(loader.load_lossy(Some(path), &mut defs));
+            (error, defs)
+        });
+
+        let mut definitions = None;
+        let mut diagnostics = match tokio::time::timeout(VALIDATION_TIMEOUT, work).await {
+            Ok(Ok((error, defs))) => {
+                definitions = Some(defs);
+                let converter = DiagnosticConverter::new(self.documents.clone());
+                converter.errors_to_diagnostics(error, &uri)
+            }
+            Ok(Err(_)) => return,
+            Err(_) => {
+                job.cancel();
+                let timeout = Diagnostic {
+                    message: format!(
+                        "Validation took longer than {}s and was stopped",
+                        VALIDATION_TIMEOUT.as_secs()
+                    ),
+                    severity: Some(DiagnosticSeverity::WARNING),
+                    source: Some("motya-lsp".to_string()),
+                    ..Default::default()
+                };
+                HashMap::from([(uri.clone(), vec![timeout])])
+            }
         };
-        let loader = ConfigLoader::new(source);
-        let mut defs = DefinitionsTable::new_with_global();
 
-        let (_, error) = loader.load_lossy(Some(path.clone()), &mut defs).await;
+        if !self.is_latest(&uri, &job) {
+            return;
+        }
 
-        let converter = DiagnosticConverter::new(self.documents.clone());
-        let diagnostics = converter.errors_to_diagnostics(error, &uri);
+        if let Some(defs) = definitions {
+            self.definitions.insert(uri.clone(), defs);
 
+            let files = loaded
+                .lock()
+                .map(|paths| {
+                    paths
+                        .iter()
+                        .filter_map(|path| Url::from_file_path(path).ok())
+                        .filter(|file| *file != uri)
+                        .collect()
+                })
+                .unwrap_or_default();
+            self.dependencies.insert(uri.clone(), files);
+        }
+
+        let own = diagnostics.remove(&uri).unwrap_or_default();
         self.client
-            .publish_diagnostics(uri, diagnostics, None)
+            .publish_diagnostics(uri.clone(), own, Some(job.version))
             .await;
+
+        // An included file that is open is validated on its own, and publishes
+        // its diagnostics itself.
+        diagnostics.retain(|file, _| !self.jobs.contains_key(file));
+
+        let files = diagnostics.keys().cloned().collect();
+        let previous = self.published.insert(uri, files).unwrap_or_default();
+        for file in previous {
+            if !diagnostics.contains_key(&file) && !self.jobs.contains_key(&file) {
+                self.client.publish_diagnostics(file, vec![], None).await;
+            }
+        }
+
+        for (file, file_diagnostics) in diagnostics {
+            self.client
+                .publish_diagnostics(file, file_diagnostics, None)
+                .await;
+        }
+    }
+
+    fn is_latest(&self, uri: &Url, job: &Job) -> bool {
+        self.jobs
+            .get(uri)
+            .is_some_and(|latest| Arc::ptr_eq(&latest.cancelled, &job.cancelled))
+    }
+}
+
+/// Applies an edit of the editor to `rope`. A change without a range replaces
+/// the whole text.
+pub fn apply_change(rope: &mut Rope, change: TextDocumentContentChangeEvent) {
+    let Some(range) = change.range else {
+        *rope = Rope::from_str(&change.text);
+        return;
+    };
+
+    let start = char_index(rope, range.start);
+    let end = char_index(rope, range.end).max(start);
+    rope.remove(start..end);
+    rope.insert(start, &change.text);
+}
+
+/// The char index of `position`, clamped to the end of its line. The column of
+/// an LSP position counts UTF-16 code units, so a char outside the BMP, like an
+/// emoji, takes two of them.
+pub fn char_index(rope: &Rope, position: Position) -> usize {
+    let line = position.line as usize;
+    if line >= rope.len_lines() {
+        return rope.len_chars();
+    }
+
+    let text = rope.line(line);
+    let mut len = text.len_chars();
+    while len > 0 && matches!(text.char(len - 1), '\n' | '\r') {
+        len -= 1;
+    }
+    let units = (position.character as usize).min(text.slice(..len).len_utf16_cu());
+    rope.line_to_char(line) + text.utf16_cu_to_char(units)
+}
+
+#[cfg(test)]
+mod tests {
+    use tower_lsp::lsp_types::Range;
+
+    use super::*;
+
+    fn edit(start: (u32, u32), end: (u32, u32), text: &str) -> TextDocumentContentChangeEvent {
+        TextDocumentContentChangeEvent {
+            range: Some(Range::new(
+                Position::new(start.0, start.1),
+                Position::new(end.0, end.1),
+            )),
+            range_length: None,
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_apply_change() {
+        let mut rope = Rope::from_str("services {\n  Api {\n  }\n}\n");
+
+        apply_change(&mut rope, edit((1, 2), (1, 5), "Web"));
+        assert_eq!(rope.to_string(), "services {\n  Web {\n  }\n}\n");
+
+        apply_change(&mut rope, edit((1, 7), (1, 7), "\n    file-server"));
+        assert_eq!(
+            rope.to_string(),
+            "services {\n  Web {\n    file-server\n  }\n}\n"
+        );
+
+        apply_change(&mut rope, edit((0, 8), (5, 0), ""));
+        assert_eq!(rope.to_string(), "services");
+
+        apply_change(&mut rope, edit((0, 20), (0, 20), " {"));
+        assert_eq!(rope.to_string(), "services {");
+
+        apply_change(&mut rope, edit((7, 0), (9, 0), "}"));
+        assert_eq!(rope.to_string(), "services {}");
+
+        let full = TextDocumentContentChangeEvent {
+            range: None,
+            range_length: None,
+            text: "system {}".to_string(),
+        };
+        apply_change(&mut rope, full);
+        assert_eq!(rope.to_string(), "system {}");
+    }
+
+    #[test]
+    fn test_apply_change_after_astral_char() {
+        // The emoji is one char, but two UTF-16 code units of the column.
+        let mut rope = Rope::from_str("// 😀 Api\nservices {}\n");
+
+        apply_change(&mut rope, edit((0, 6), (0, 9), "Web"));
+        assert_eq!(rope.to_string(), "// 😀 Web\nservices {}\n");
+
+        apply_change(&mut rope, edit((0, 3), (0, 5), "🚀🚀"));
+        assert_eq!(rope.to_string(), "// 🚀🚀 Web\nservices {}\n");
+
+        apply_change(&mut rope, edit((0, 20), (1, 8), ""));
+        assert_eq!(rope.to_string(), "// 🚀🚀 Web {}\n");
     }
 }
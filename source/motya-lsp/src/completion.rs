@@ -0,0 +1,422 @@
+//! Completions from the schema the config models describe.
+//!
+//! The document is usually invalid while it is typed, so the cursor is placed with
+//! a scan of the text before it rather than with the KDL parser: the scan keeps the
+//! names of the enclosing blocks and the entries of the node the cursor is in.
+
+use std::{collections::HashSet, sync::OnceLock};
+
+use motya_config::kdl::{
+    models::root::RootDef,
+    schema::{
+        definitions::{ChildrenSchema, GetSchema, NodeNameMatcher, NodeSchema, ValueKind},
+        schema_context::SchemaContext,
+    },
+};
+use ropey::Rope;
+use tower_lsp::lsp_types::{CompletionItem, CompletionItemKind, Position};
+
+use crate::backend::char_index;
+
+/// The schema of a whole config file.
+pub fn root_schema() -> &'static NodeSchema {
+    static SCHEMA: OnceLock<NodeSchema> = OnceLock::new();
+
+    SCHEMA.get_or_init(|| {
+        RootDef::schemas(&mut SchemaContext::default())
+            .into_iter()
+            .next()
+            .expect("The root has a schema")
+    })
+}
+
+/// Completions for the cursor at `position` of `rope`.
+pub fn complete(rope: &Rope, position: Position) -> Vec<CompletionItem> {
+    if rope.try_line_to_char(position.line as usize).is_err() {
+        return Vec::new();
+    }
+    let cursor = char_index(rope, position);
+    let before = rope.slice(..cursor).to_string();
+
+    completions(root_schema(), &CursorContext::scan(&before))
+}
+
+/// Where the cursor is, as far as the text before it tells.
+#[derive(Debug, Default, PartialEq)]
+pub struct CursorContext {
+    /// The names of the blocks around the cursor, outermost first.
+    pub path: Vec<String>,
+    /// The complete entries of the node the cursor is in: its name, arguments
+    /// and `key=value` properties.
+    pub entries: Vec<String>,
+    /// The entry under the cursor, up to it.
+    pub word: String,
+    /// Whether the cursor is inside a string.
+    pub in_string: bool,
+}
+
+impl CursorContext {
+    pub fn scan(text: &str) -> Self {
+        let mut ctx = Self::default();
+        let mut chars = text.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => {
+                    ctx.in_string = !read_string(&mut chars, &mut ctx.word, 0);
+                }
+                '#' => {
+                    let mut hashes = 1;
+                    while chars.next_if_eq(&'#').is_some() {
+                        hashes += 1;
+                    }
+                    if chars.next_if_eq(&'"').is_some() {
+                        ctx.in_string = !read_string(&mut chars, &mut ctx.word, hashes);
+                    } else {
+                        ctx.word.extend(std::iter::repeat_n('#', hashes));
+                    }
+                }
+                '/' if chars.peek() == Some(&'/') => {
+                    while chars.next_if(|&c| c != '\n').is_some() {}
+                }
+                '/' if chars.peek() == Some(&'*') => {
+                    chars.next();
+                    skip_block_comment(&mut chars);
+                }
+                '/' if chars.peek() == Some(&'-') => {
+                    chars.next();
+                }
+                '\\' => {
+                    // A line continuation: the node goes on after the newline.
+                    while chars.next_if(|&c| c != '\n').is_some() {}
+                    chars.next();
+                }
+                '{' => {
+                    ctx.end_entry();
+                    let name = ctx.entries.first().cloned().unwrap_or_default();
+                    ctx.path.push(name);
+                    ctx.entries.clear();
+                }
+                '}' => {
+                    ctx.end_entry();
+                    ctx.path.pop();
+                    ctx.entries.clear();
+                }
+                ';' | '\n' => {
+                    ctx.end_entry();
+                    ctx.entries.clear();
+                }
+                c if c.is_whitespace() => ctx.end_entry(),
+                c => ctx.word.push(c),
+            }
+        }
+
+        ctx
+    }
+
+    fn end_entry(&mut self) {
+        if !self.word.is_empty() {
+            self.entries.push(std::mem::take(&mut self.word));
+        }
+    }
+}
+
+/// Reads a string up to its closing quote into `word`. Returns `false` if the
+/// text ends first.
+fn read_string(
+    chars: &mut std::iter::Peekable<std::str::Chars<'_>>,
+    word: &mut String,
+    hashes: usize,
+) -> bool {
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if hashes == 0 => {
+                if let Some(escaped) = chars.next() {
+                    word.push(escaped);
+                }
+            }
+            '"' => {
+                let mut closing = 0;
+                while closing < hashes && chars.next_if_eq(&'#').is_some() {
+                    closing += 1;
+                }
+                if closing == hashes {
+                    return true;
+                }
+                word.push('"');
+                word.extend(std::iter::repeat_n('#', closing));
+            }
+            c => word.push(c),
+        }
+    }
+    false
+}
+
+fn skip_block_comment(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) {
+    let mut depth = 1;
+    while let Some(c) = chars.next() {
+        match c {
+            '/' if chars.next_if_eq(&'*').is_some() => depth += 1,
+            '*' if chars.next_if_eq(&'/').is_some() => {
+                depth -= 1;
+                if depth == 0 {
+                    return;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn completions(root: &NodeSchema, ctx: &CursorContext) -> Vec<CompletionItem> {
+    let (scope, ancestors) = resolve(root, &ctx.path);
+
+    let Some(name) = ctx.entries.first() else {
+        if ctx.in_string {
+            return Vec::new();
+        }
+        return child_items(&children(&scope, &ancestors));
+    };
+
+    let nodes = named(children(&scope, &ancestors), name);
+
+    if let Some((key, _)) = ctx.word.split_once('=') {
+        let kinds = nodes
+            .iter()
+            .flat_map(|node| &node.props)
+            .filter(|prop| prop.name == key)
+            .map(|prop| &prop.kind);
+        return value_items(kinds, ctx.in_string);
+    }
+    if ctx.in_string {
+        return arg_items(&nodes, ctx, true);
+    }
+
+    let mut items = prop_items(&nodes, ctx);
+    items.extend(arg_items(&nodes, ctx, false));
+    items
+}
+
+/// The schemas the block at `path` may have, and those of the blocks around it.
+pub fn resolve<'a>(
+    root: &'a NodeSchema,
+    path: &[String],
+) -> (Vec<&'a NodeSchema>, Vec<&'a NodeSchema>) {
+    let mut ancestors = vec![root];
+    let mut scope = vec![root];
+
+    for name in path {
+        scope = named(children(&scope, &ancestors), name);
+        if scope.is_empty() {
+            break;
+        }
+        ancestors.extend(&scope);
+    }
+
+    (scope, ancestors)
+}
+
+/// The children the schemas of `scope` allow. A recursive block allows the
+/// children of its nearest ancestor of the same name.
+pub fn children<'a>(scope: &[&'a NodeSchema], ancestors: &[&'a NodeSchema]) -> Vec<&'a NodeSchema> {
+    let mut found = Vec::new();
+
+    for node in scope {
+        match &node.children {
+            ChildrenSchema::None => {}
+            ChildrenSchema::Fixed(list) => found.extend(list),
+            ChildrenSchema::Dynamic(child) => found.push(child.as_ref()),
+            ChildrenSchema::Recursive(name) => {
+                let origin = ancestors.iter().rev().find(|ancestor| {
+                    keyword(ancestor) == Some(name)
+                        && !matches!(ancestor.children, ChildrenSchema::Recursive(_))
+                });
+                if let Some(origin) = origin {
+                    found.extend(children(&[origin], ancestors));
+                }
+            }
+        }
+    }
+
+    found
+}
+
+/// The schemas among `candidates` a node called `name` may have. A keyword wins
+/// over a user-defined name.
+pub fn named<'a>(candidates: Vec<&'a NodeSchema>, name: &str) -> Vec<&'a NodeSchema> {
+    let keywords: Vec<_> = candidates
+        .iter()
+        .copied()
+        .filter(|node| keyword(node) == Some(name))
+        .collect();
+    if !keywords.is_empty() {
+        return keywords;
+    }
+
+    candidates
+        .into_iter()
+        .filter(|node| matches!(node.matcher, NodeNameMatcher::Variable { .. }))
+        .collect()
+}
+
+pub fn keyword(node: &NodeSchema) -> Option<&str> {
+    match &node.matcher {
+        NodeNameMatcher::Keyword(keyword) => Some(keyword),
+        NodeNameMatcher::Variable { .. } => None,
+    }
+}
+
+fn child_items(nodes: &[&NodeSchema]) -> Vec<CompletionItem> {
+    let mut seen = HashSet::new();
+
+    nodes
+        .iter()
+        .filter_map(|node| {
+            let name = keyword(node)?;
+            if !seen.insert(name) {
+                return None;
+            }
+            let signature = node
+                .args
+                .iter()
+                .map(|arg| format!(" <{}>", arg.name))
+                .collect::<String>();
+            Some(CompletionItem {
+                label: name.to_string(),
+                kind: Some(if matches!(node.children, ChildrenSchema::None) {
+                    CompletionItemKind::FIELD
+                } else {
+                    CompletionItemKind::MODULE
+                }),
+                detail: Some(format!("{name}{signature}")),
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+fn prop_items(nodes: &[&NodeSchema], ctx: &CursorContext) -> Vec<CompletionItem> {
+    let used: HashSet<&str> = ctx.entries[1..]
+        .iter()
+        .filter_map(|entry| entry.split_once('=').map(|(key, _)| key))
+        .collect();
+    let mut seen = HashSet::new();
+
+    nodes
+        .iter()
+        .flat_map(|node| &node.props)
+        .filter(|prop| !used.contains(prop.name.as_str()) && seen.insert(&prop.name))
+        .map(|prop| CompletionItem {
+            label: prop.name.clone(),
+            kind: Some(CompletionItemKind::PROPERTY),
+            detail: Some(prop.kind.to_string()),
+            insert_text: Some(format!("{}=", prop.name)),
+            ..Default::default()
+        })
+        .collect()
+}
+
+/// Values for the argument the cursor is at.
+fn arg_items(nodes: &[&NodeSchema], ctx: &CursorContext, in_string: bool) -> Vec<CompletionItem> {
+    let index = ctx.entries[1..]
+        .iter()
+        .filter(|entry| !entry.contains('='))
+        .count();
+    let kinds = nodes
+        .iter()
+        .filter_map(|node| node.args.get(index))
+        .map(|arg| &arg.kind);
+
+    value_items(kinds, in_string)
+}
+
+fn value_items<'a>(
+    kinds: impl Iterator<Item = &'a ValueKind>,
+    in_string: bool,
+) -> Vec<CompletionItem> {
+    let mut values = Vec::new();
+    for kind in kinds {
+        match kind {
+            ValueKind::Enum(options) if in_string => values.extend(options.clone()),
+            ValueKind::Enum(options) => {
+                values.extend(options.iter().map(|option| format!("\"{option}\"")));
+            }
+            ValueKind::Bool if !in_string => {
+                values.extend(["#true".to_string(), "#false".to_string()]);
+            }
+            _ => {}
+        }
+    }
+
+    let mut seen = HashSet::new();
+    values
+        .into_iter()
+        .filter(|value| seen.insert(value.clone()))
+        .map(|value| CompletionItem {
+            label: value,
+            kind: Some(CompletionItemKind::ENUM_MEMBER),
+            ..Default::default()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(text: &str) -> Vec<String> {
+        completions(root_schema(), &CursorContext::scan(text))
+            .into_iter()
+            .map(|item| item.label)
+            .collect()
+    }
+
+    #[test]
+    fn test_scan() {
+        let ctx = CursorContext::scan(
+            "services {\n  Api {\n    // a { comment\n    access-log \"$path {\" format=te",
+        );
+        assert_eq!(ctx.path, ["services", "Api"]);
+        assert_eq!(ctx.entries, ["access-log", "$path {"]);
+        assert_eq!(ctx.word, "format=te");
+        assert!(!ctx.in_string);
+
+        let ctx =
+            CursorContext::scan("system { threads-per-service 2; }\nservices { Api { proxy \"ht");
+        assert_eq!(ctx.path, ["services", "Api"]);
+        assert_eq!(ctx.entries, ["proxy"]);
+        assert_eq!(ctx.word, "ht");
+        assert!(ctx.in_string);
+    }
+
+    #[test]
+    fn test_child_names() {
+        let root = labels("");
+        assert!(root.contains(&"services".to_string()), "{root:?}");
+        assert!(root.contains(&"definitions".to_string()), "{root:?}");
+
+        let service = labels("services {\n  Api {\n    ");
+        assert!(service.contains(&"listeners".to_string()), "{service:?}");
+        assert!(service.contains(&"connectors".to_string()), "{service:?}");
+        assert!(service.contains(&"file-server".to_string()), "{service:?}");
+
+        let nested = labels("services { Api { connectors { section \"/a\" { section \"/b\" { ");
+        assert!(nested.contains(&"proxy".to_string()), "{nested:?}");
+    }
+
+    #[test]
+    fn test_props_and_values() {
+        let props = labels("services { Api { access-log format=\"json\" ");
+        assert!(props.contains(&"path".to_string()), "{props:?}");
+        assert!(!props.contains(&"format".to_string()), "{props:?}");
+
+        assert_eq!(
+            labels("services { Api { access-log format="),
+            ["\"text\"", "\"json\""]
+        );
+        assert_eq!(
+            labels("services { Api { access-log format=\"js"),
+            ["text", "json"]
+        );
+    }
+}
@@ -0,0 +1,112 @@
+//! Go to the definition of the chain, key profile, storage or secret a node
+//! refers to.
+
+use dashmap::DashMap;
+use motya_config::common_types::definitions_table::{DefinitionKind, DefinitionSpan};
+use ropey::Rope;
+use tower_lsp::lsp_types::{Location, Position, Url};
+
+use crate::{
+    backend::char_index, completion::CursorContext, diagnostics::span_range, hover::is_delimiter,
+};
+
+/// The definition the entry at `position` of `rope` refers to, e.g. the chain
+/// `security` for `use-chain "security"`.
+pub fn reference(rope: &Rope, position: Position) -> Option<(DefinitionKind, String)> {
+    rope.try_line_to_char(position.line as usize).ok()?;
+    let cursor = char_index(rope, position);
+    let mut ctx = CursorContext::scan(&rope.slice(..cursor).to_string());
+
+    // The scan stops at the cursor, the rest of the entry follows it.
+    let mut rest = rope.slice(cursor..).chars().peekable();
+    if !ctx.in_string && (ctx.word.is_empty() || ctx.word.ends_with('=')) {
+        ctx.in_string = rest.next_if_eq(&'"').is_some();
+    }
+    if ctx.in_string {
+        while let Some(c) = rest.next() {
+            match c {
+                '\\' => ctx.word.extend(rest.next()),
+                '"' | '\n' => break,
+                c => ctx.word.push(c),
+            }
+        }
+    } else {
+        ctx.word.extend(rest.take_while(|&c| !is_delimiter(c)));
+    }
+
+    let name = ctx.entries.first()?;
+    let kind = match ctx.word.split_once('=') {
+        Some(("use-key-profile", profile)) => {
+            return Some((DefinitionKind::KeyProfile, profile.to_string()));
+        }
+        Some(("secret", secret)) => {
+            return Some((DefinitionKind::Secret, secret.to_string()));
+        }
+        Some(_) => return None,
+        None if ctx.entries.len() > 1 || ctx.word.is_empty() => return None,
+        None => match name.as_str() {
+            "use-chain" => DefinitionKind::Chain,
+            "storage" => DefinitionKind::Storage,
+            _ => return None,
+        },
+    };
+
+    Some((kind, ctx.word))
+}
+
+/// The location of `span`, read from the open document of its file or else
+/// from the disk.
+pub fn location(span: &DefinitionSpan, documents: &DashMap<Url, Rope>) -> Option<Location> {
+    let uri = Url::from_file_path(&span.source_name).ok()?;
+    let rope = match documents.get(&uri) {
+        Some(rope) => rope.clone(),
+        None => Rope::from_str(&std::fs::read_to_string(&span.source_name).ok()?),
+    };
+
+    Some(Location::new(uri, span_range(&rope, span.span)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reference_at(text: &str, line: u32, character: u32) -> Option<(DefinitionKind, String)> {
+        reference(&Rope::from_str(text), Position::new(line, character))
+    }
+
+    #[test]
+    fn test_reference() {
+        let text = "section \"/\" {\n  use-chain \"security\"\n  proxy \"http://a\"\n}\n";
+        let chain = Some((DefinitionKind::Chain, "security".to_string()));
+        assert_eq!(reference_at(text, 1, 12), chain);
+        assert_eq!(reference_at(text, 1, 12 + 7), chain);
+        assert_eq!(reference_at(text, 1, 4), None);
+        assert_eq!(reference_at(text, 2, 10), None);
+
+        let text = "policy \"api\" {\n  storage \"local\"\n}\n";
+        let storage = Some((DefinitionKind::Storage, "local".to_string()));
+        assert_eq!(reference_at(text, 1, 11), storage);
+    }
+
+    #[test]
+    fn test_reference_in_property() {
+        let text = "load-balance {\n  selection \"ketama\" use-key-profile=\"ip-profile\"\n}\n";
+        let profile = Some((DefinitionKind::KeyProfile, "ip-profile".to_string()));
+        assert_eq!(reference_at(text, 1, 40), profile);
+        assert_eq!(reference_at(text, 1, 37), profile);
+        assert_eq!(reference_at(text, 1, 15), None);
+
+        let text = "redis \"shared\" {\n  password secret=\"redis-pass\"\n}\n";
+        let secret = Some((DefinitionKind::Secret, "redis-pass".to_string()));
+        assert_eq!(reference_at(text, 1, 22), secret);
+    }
+
+    #[test]
+    fn test_reference_after_astral_chars() {
+        // Each emoji takes two UTF-16 code units of the column.
+        let text = "section \"/\" {\n  /* 😀😀😀😀 */ use-chain \"security\"\n}\n";
+        let chain = Some((DefinitionKind::Chain, "security".to_string()));
+        assert_eq!(reference_at(text, 1, 23), None);
+        assert_eq!(reference_at(text, 1, 28), chain);
+    }
+}
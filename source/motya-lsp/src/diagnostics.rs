@@ -1,10 +1,18 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
 use dashmap::DashMap;
-use motya_config::common_types::error::{ConfigError, ParseError};
+use miette::SourceSpan;
+use motya_config::{
+    common_types::error::{ConfigError, ParseError},
+    kdl::parser::suggest,
+};
 use ropey::Rope;
+use serde_json::json;
 use tower_lsp::lsp_types::{Diagnostic, DiagnosticSeverity, Position, Range, Url};
 
+/// The key of [`Diagnostic::data`] holding a name to replace the labeled text with.
+pub const SUGGESTION: &str = "suggestion";
+
 pub struct DiagnosticConverter {
     documents: Arc<DashMap<Url, Rope>>,
 }
@@ -14,72 +22,106 @@ impl DiagnosticConverter {
         Self { documents }
     }
 
-    pub fn errors_to_diagnostics(&self, config_error: ConfigError, uri: &Url) -> Vec<Diagnostic> {
-        let mut diagnostics = Vec::new();
-
-        for err in config_error.errors {
-            if let Some(diag) = self.parse_error_to_diagnostic(&err, uri) {
-                diagnostics.push(diag);
+    /// Converts every error and warning of a validation of `entry` into diagnostics,
+    /// grouped by the file they belong to. Those without a file name belong to `entry`.
+    pub fn errors_to_diagnostics(
+        &self,
+        mut config_error: ConfigError,
+        entry: &Url,
+    ) -> HashMap<Url, Vec<Diagnostic>> {
+        let mut diagnostics = HashMap::new();
+        let warnings = ConfigError::from_list(std::mem::take(&mut config_error.warnings));
+
+        for (severity, found) in [
+            (DiagnosticSeverity::ERROR, config_error),
+            (DiagnosticSeverity::WARNING, warnings),
+        ] {
+            for (source_name, errors) in found.into_sources() {
+                let uri = if source_name.is_empty() {
+                    entry.clone()
+                } else {
+                    match Url::from_file_path(&source_name) {
+                        Ok(uri) => uri,
+                        Err(()) => continue,
+                    }
+                };
+
+                let rope = self.source_text(&uri, &errors);
+                let file_diagnostics = diagnostics.entry(uri).or_default();
+                for err in &errors {
+                    if let Some(diag) = parse_error_to_diagnostic(err, rope.as_ref(), severity) {
+                        file_diagnostics.push(diag);
+                    }
+                }
             }
         }
 
         diagnostics
     }
 
-    fn parse_error_to_diagnostic(&self, err: &ParseError, current_uri: &Url) -> Option<Diagnostic> {
-        let source_name = err.src.name();
-
-        let current_path_lossy = current_uri
-            .to_file_path()
-            .ok()?
-            .to_string_lossy()
-            .to_string();
-
-        if !source_name.is_empty() && source_name != current_path_lossy {
-            return None;
+    /// The text the spans of `errors` point into: the one the errors carry, or
+    /// the open document for errors that carry none.
+    fn source_text(&self, uri: &Url, errors: &[ParseError]) -> Option<Rope> {
+        match errors.iter().find(|err| !err.src.inner().is_empty()) {
+            Some(err) => Some(Rope::from_str(err.src.inner())),
+            None => self.documents.get(uri).map(|rope| rope.clone()),
         }
-        let msg = if let Some(help) = &err.help {
-            help
-        } else {
-            &err.message
-        };
-
-        let Some(span) = err.label else {
-            return Some(Diagnostic {
-                message: msg.clone(),
-                range: Range::default(),
-                severity: Some(DiagnosticSeverity::ERROR),
-                ..Default::default()
-            });
-        };
-
-        let rope = self.documents.get(current_uri)?;
-
-        let start_byte = span.offset();
-        let end_byte = start_byte + span.len();
-
-        if end_byte > rope.len_bytes() {
-            return None;
-        }
-
-        let start_char = rope.try_byte_to_char(start_byte).ok()?;
-        let end_char = rope.try_byte_to_char(end_byte).ok()?;
+    }
+}
 
-        let start_line = rope.try_char_to_line(start_char).ok()?;
-        let start_col = start_char - rope.try_line_to_char(start_line).ok()?;
+fn parse_error_to_diagnostic(
+    err: &ParseError,
+    rope: Option<&Rope>,
+    severity: DiagnosticSeverity,
+) -> Option<Diagnostic> {
+    let msg = match &err.help {
+        Some(help) if suggest::is_suggestion(help) => format!("{}. {help}", err.message),
+        Some(help) => help.clone(),
+        None => err.message.clone(),
+    };
+
+    let (Some(span), Some(rope)) = (err.label, rope) else {
+        return Some(Diagnostic {
+            message: msg,
+            range: Range::default(),
+            severity: Some(severity),
+            ..Default::default()
+        });
+    };
+
+    let range = span_range(rope, span)?;
+
+    // A suggested name can only replace the labeled text if that is a bare name.
+    let labeled = rope
+        .byte_slice(span.offset()..span.offset() + span.len())
+        .to_string();
+    let data = suggest::suggested_name(&msg)
+        .filter(|_| !labeled.is_empty())
+        .filter(|_| !labeled.contains(|c: char| c.is_whitespace() || c == '"' || c == '='))
+        .map(|name| json!({ SUGGESTION: name }));
+
+    Some(Diagnostic {
+        range,
+        severity: Some(severity),
+        message: msg,
+        source: Some("motya-lsp".to_string()),
+        data,
+        ..Default::default()
+    })
+}
 
-        let end_line = rope.try_char_to_line(end_char).ok()?;
-        let end_col = end_char - rope.try_line_to_char(end_line).ok()?;
+/// The range of `span`, a byte span of the text of `rope`.
+pub fn span_range(rope: &Rope, span: SourceSpan) -> Option<Range> {
+    let start = byte_position(rope, span.offset())?;
+    let end = byte_position(rope, span.offset() + span.len())?;
+    Some(Range { start, end })
+}
 
-        Some(Diagnostic {
-            range: Range {
-                start: Position::new(start_line as u32, start_col as u32),
-                end: Position::new(end_line as u32, end_col as u32),
-            },
-            severity: Some(DiagnosticSeverity::ERROR),
-            message: msg.clone(),
-            source: Some("motya-lsp".to_string()),
-            ..Default::default()
-        })
-    }
+/// The position of `byte`, with its column in UTF-16 code units as LSP counts them.
+fn byte_position(rope: &Rope, byte: usize) -> Option<Position> {
+    let char_idx = rope.try_byte_to_char(byte).ok()?;
+    let line = rope.try_char_to_line(char_idx).ok()?;
+    let line_start = rope.try_line_to_char(line).ok()?;
+    let column = rope.slice(line_start..char_idx).len_utf16_cu();
+    Some(Position::new(line as u32, column as u32))
 }
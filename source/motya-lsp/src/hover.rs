@@ -0,0 +1,176 @@
+//! Hover documentation from the doc comments of the config models.
+
+use motya_config::kdl::schema::definitions::{ChildrenSchema, NodeSchema, PropSchema, localized};
+use ropey::Rope;
+use tower_lsp::lsp_types::{Hover, HoverContents, MarkupContent, MarkupKind, Position};
+
+use crate::{
+    backend::char_index,
+    completion::{CursorContext, children, keyword, named, resolve, root_schema},
+};
+
+/// Documentation of the node or property at `position` of `rope`, in `lang` when
+/// the docs have it.
+pub fn hover(rope: &Rope, position: Position, lang: &str) -> Option<Hover> {
+    rope.try_line_to_char(position.line as usize).ok()?;
+    let mut end = char_index(rope, position);
+    while end < rope.len_chars() && !is_delimiter(rope.char(end)) {
+        end += 1;
+    }
+    let is_key = end < rope.len_chars() && rope.char(end) == '=';
+
+    let ctx = CursorContext::scan(&rope.slice(..end).to_string());
+    let (scope, ancestors) = resolve(root_schema(), &ctx.path);
+    let candidates = children(&scope, &ancestors);
+
+    let text = match ctx.entries.first() {
+        None if ctx.in_string || ctx.word.is_empty() => return None,
+        None => render_nodes(&named(candidates, &ctx.word), lang)?,
+        Some(name) => {
+            let nodes = named(candidates, name);
+            let key = match ctx.word.split_once('=') {
+                Some((key, _)) => Some(key),
+                None if is_key => Some(ctx.word.as_str()),
+                None => None,
+            };
+            match key {
+                Some(key) => {
+                    let prop = nodes
+                        .iter()
+                        .flat_map(|node| &node.props)
+                        .find(|p| p.name == key)?;
+                    render_prop(prop, lang)
+                }
+                None => render_nodes(&nodes, lang)?,
+            }
+        }
+    };
+
+    Some(Hover {
+        contents: HoverContents::Markup(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value: text,
+        }),
+        range: None,
+    })
+}
+
+pub fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '{' | '}' | ';' | '=' | '"')
+}
+
+/// Renders the forms a node may take, e.g. `use-chain "name"` and `use-chain { ... }`.
+fn render_nodes(nodes: &[&NodeSchema], lang: &str) -> Option<String> {
+    let sections: Vec<String> = nodes
+        .iter()
+        .filter_map(|node| {
+            let name = keyword(node)?;
+            let mut text = format!("```kdl\n{name}");
+            for arg in &node.args {
+                text.push_str(&format!(" <{}>", arg.name));
+            }
+            if !matches!(node.children, ChildrenSchema::None) {
+                text.push_str(" { ... }");
+            }
+            text.push_str("\n```");
+
+            if let Some(doc) = localized(&node.description, lang) {
+                text.push_str(&format!("\n\n{doc}"));
+            }
+            if !node.args.is_empty() {
+                text.push_str("\n\nArguments:\n");
+                for arg in &node.args {
+                    let required = if arg.required { "" } else { ", optional" };
+                    text.push_str(&format!("\n- `{}`: {}{required}", arg.name, arg.kind));
+                    if let Some(doc) = localized(&arg.description, lang) {
+                        text.push_str(&format!(". {doc}"));
+                    }
+                }
+            }
+            if !node.props.is_empty() {
+                text.push_str("\n\nProperties:\n");
+                for prop in &node.props {
+                    text.push_str(&format!("\n- `{}`: {}", prop.name, prop.kind));
+                    if let Some(doc) = localized(&prop.description, lang) {
+                        text.push_str(&format!(". {doc}"));
+                    }
+                }
+            }
+            Some(text)
+        })
+        .collect();
+
+    (!sections.is_empty()).then(|| sections.join("\n\n---\n\n"))
+}
+
+fn render_prop(prop: &PropSchema, lang: &str) -> String {
+    let mut text = format!("`{}`: {}", prop.name, prop.kind);
+    if prop.required {
+        text.push_str(", required");
+    }
+    if let Some(default) = &prop.default {
+        text.push_str(&format!(", `{default}` by default"));
+    }
+    if let Some(doc) = localized(&prop.description, lang) {
+        text.push_str(&format!("\n\n{doc}"));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use motya_config::kdl::schema::definitions::DocEntry;
+
+    use super::*;
+
+    fn hover_text(text: &str, line: u32, character: u32) -> Option<String> {
+        let rope = Rope::from_str(text);
+        let hover = hover(&rope, Position::new(line, character), "en")?;
+        let HoverContents::Markup(content) = hover.contents else {
+            panic!("Expected markdown");
+        };
+        Some(content.value)
+    }
+
+    #[test]
+    fn test_hover_node() {
+        let text = "services {\n  Api {\n    connectors {\n      section \"/\" {\n        use-chain \"security\"\n";
+        let doc = hover_text(text, 4, 11).unwrap();
+
+        assert!(doc.contains("use-chain <name>"), "{doc}");
+        assert!(doc.contains("use-chain { ... }"), "{doc}");
+        assert!(
+            doc.contains("Runs the filters of the chain-filters definition"),
+            "{doc}"
+        );
+    }
+
+    #[test]
+    fn test_hover_prop() {
+        let text = "services {\n  Api {\n    access-log format=\"json\"\n";
+        let doc = hover_text(text, 2, 16).unwrap();
+        assert_eq!(doc, "`format`: text | json");
+
+        assert!(hover_text(text, 1, 0).is_none());
+    }
+
+    #[test]
+    fn test_localized() {
+        let docs = [
+            DocEntry {
+                lang: Cow::Borrowed("en"),
+                text: Cow::Borrowed("Hello"),
+            },
+            DocEntry {
+                lang: Cow::Borrowed("ru"),
+                text: Cow::Borrowed("Привет"),
+            },
+        ];
+
+        assert_eq!(localized(&docs, "ru"), Some("Привет"));
+        assert_eq!(localized(&docs, "de"), Some("Hello"));
+        assert_eq!(localized(&[], "en"), None);
+    }
+}
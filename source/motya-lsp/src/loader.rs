@@ -1,7 +1,11 @@
 use std::{
     collections::HashSet,
+    fmt,
     path::{Path, PathBuf},
-    sync::Arc,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
 };
 
 use async_recursion::async_recursion;
@@ -12,6 +16,7 @@ use motya_config::{
     common_types::error::{ConfigError, ParseError},
     config_source::ConfigSource,
     kdl::{
+        fs_loader::{env_condition_holds, has_wildcards, matches_wildcards, split_wildcards},
         models::root::PartialParsedRoot,
         parser::{ctx::ParseContext, parsable::KdlParsable},
     },
@@ -20,9 +25,20 @@ use path_clean::PathClean;
 use ropey::Rope;
 use tower_lsp::lsp_types::Url;
 
+/// Files larger than this are not parsed, so that a huge file can't stall the server.
+pub const MAX_FILE_BYTES: usize = 2 * 1024 * 1024;
+
 #[derive(Clone, Default)]
 pub struct LspConfigSource {
     pub documents: Arc<DashMap<Url, Rope>>,
+    /// Set when the document being validated has changed again; collection stops
+    /// at the next file.
+    pub cancelled: Arc<AtomicBool>,
+    /// The parsed files, shared between validations: a file whose text did not
+    /// change since is not parsed again.
+    pub parsed: Arc<DashMap<PathBuf, (String, KdlDocument)>>,
+    /// The files the validation read, filled in once collection is done.
+    pub loaded: Arc<Mutex<HashSet<PathBuf>>>,
 }
 
 impl ConfigSource for LspConfigSource {
@@ -42,6 +58,8 @@ impl ConfigSource for LspConfigSource {
     ) -> (Vec<(KdlDocument, String)>, ConfigError) {
         let mut runner = Runner {
             documents: self.documents.clone(),
+            cancelled: self.cancelled.clone(),
+            parsed: self.parsed.clone(),
             visited: HashSet::new(),
             found_docs: Vec::new(),
             errors: ConfigError::default(),
@@ -54,21 +72,81 @@ impl ConfigSource for LspConfigSource {
             Err(e) => {
                 let name = entry_path.to_string_lossy().to_string();
                 let src = NamedSource::new(name, String::new());
-                runner.errors.push(ParseError::new(
-                    e,
-                    None,
-                    Some("Check if the entry point file exists".to_string()),
-                    src,
-                ));
+                let help = match &e {
+                    ReadError::Io(_) => Some("Check if the entry point file exists".to_string()),
+                    ReadError::TooLarge { .. } => None,
+                };
+                runner
+                    .errors
+                    .push(ParseError::new(e.to_string(), None, help, src));
             }
         }
 
+        // The results of a cancelled run are never shown, so don't spend time linking them.
+        if runner.is_cancelled() {
+            return (Vec::new(), ConfigError::default());
+        }
+
+        if let Ok(mut loaded) = self.loaded.lock() {
+            loaded.clone_from(&runner.visited);
+        }
+
         (runner.found_docs, runner.errors)
     }
 }
 
+/// The files an import names: itself, or the files matching its wildcards
+/// sorted by name, as the server loads them.
+async fn expand_import(base_dir: &Path, import: &str) -> Result<Vec<PathBuf>, String> {
+    if !has_wildcards(import) {
+        return Ok(vec![base_dir.join(import).clean()]);
+    }
+
+    let (dir, pattern) = split_wildcards(import)?;
+    let dir = base_dir.join(dir).clean();
+    let list_error =
+        |e: std::io::Error| format!("Failed to list directory '{}': {}", dir.display(), e);
+
+    let mut entries = tokio::fs::read_dir(&dir).await.map_err(list_error)?;
+    let mut paths = Vec::new();
+    while let Some(entry) = entries.next_entry().await.map_err(list_error)? {
+        let matches = entry
+            .file_name()
+            .to_str()
+            .is_some_and(|name| matches_wildcards(pattern, name));
+        if matches && entry.file_type().await.is_ok_and(|t| t.is_file()) {
+            paths.push(entry.path());
+        }
+    }
+    paths.sort();
+
+    Ok(paths)
+}
+
+enum ReadError {
+    Io(String),
+    TooLarge { path: PathBuf, bytes: usize },
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadError::Io(msg) => f.write_str(msg),
+            ReadError::TooLarge { path, bytes } => write!(
+                f,
+                "File '{}' is {} KiB, over the {} KiB the language server checks",
+                path.display(),
+                bytes / 1024,
+                MAX_FILE_BYTES / 1024
+            ),
+        }
+    }
+}
+
 struct Runner {
     documents: Arc<DashMap<Url, Rope>>,
+    cancelled: Arc<AtomicBool>,
+    parsed: Arc<DashMap<PathBuf, (String, KdlDocument)>>,
     visited: HashSet<PathBuf>,
     found_docs: Vec<(KdlDocument, String)>,
     errors: ConfigError,
@@ -77,7 +155,7 @@ struct Runner {
 impl Runner {
     #[async_recursion]
     async fn process_file(&mut self, path: PathBuf, content: String) {
-        if self.visited.contains(&path) {
+        if self.is_cancelled() || self.visited.contains(&path) {
             return;
         }
         self.visited.insert(path.clone());
@@ -85,15 +163,27 @@ impl Runner {
         let name = path.to_string_lossy().to_string();
         let named_source = NamedSource::new(&name, content.clone());
 
-        let doc: KdlDocument = match content.parse() {
-            Ok(d) => d,
-            Err(kdl_error) => {
-                let errors = ParseError::from_kdl_error(kdl_error, named_source);
-                for err in errors {
-                    self.errors.push(err);
+        let cached = self
+            .parsed
+            .get(&path)
+            .filter(|entry| entry.0 == content)
+            .map(|entry| entry.1.clone());
+
+        let doc = match cached {
+            Some(doc) => doc,
+            None => match content.parse::<KdlDocument>() {
+                Ok(doc) => {
+                    self.parsed.insert(path.clone(), (content, doc.clone()));
+                    doc
                 }
-                return;
-            }
+                Err(kdl_error) => {
+                    let errors = ParseError::from_kdl_error(kdl_error, named_source);
+                    for err in errors {
+                        self.errors.push(err);
+                    }
+                    return;
+                }
+            },
         };
 
         let ctx = ParseContext::new(doc.clone(), &name);
@@ -107,19 +197,37 @@ impl Runner {
 
                     for path_node in imports.paths {
                         let (path_str, node_ctx) = path_node.into_parts();
-                        let resolved_path = base_dir.join(&path_str.value).clean();
 
-                        if self.visited.contains(&resolved_path) {
+                        if self.is_cancelled() {
+                            break;
+                        }
+
+                        if !path_str.if_env.as_deref().is_none_or(env_condition_holds) {
                             continue;
                         }
 
-                        match self.read_content(&resolved_path).await {
-                            Ok(sub_content) => {
-                                self.process_file(resolved_path, sub_content).await;
-                            }
+                        let resolved_paths = match expand_import(base_dir, &path_str.value).await {
+                            Ok(paths) => paths,
                             Err(msg) => {
                                 let report = node_ctx.err_value(msg);
                                 self.errors.push_report(report, &node_ctx.ctx);
+                                continue;
+                            }
+                        };
+
+                        for resolved_path in resolved_paths {
+                            if self.visited.contains(&resolved_path) {
+                                continue;
+                            }
+
+                            match self.read_content(&resolved_path).await {
+                                Ok(sub_content) => {
+                                    self.process_file(resolved_path, sub_content).await;
+                                }
+                                Err(e) => {
+                                    let report = node_ctx.err_value(e.to_string());
+                                    self.errors.push_report(report, &node_ctx.ctx);
+                                }
                             }
                         }
                     }
@@ -133,15 +241,34 @@ impl Runner {
         self.found_docs.push((doc, name));
     }
 
-    async fn read_content(&self, path: &Path) -> Result<String, String> {
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    async fn read_content(&self, path: &Path) -> Result<String, ReadError> {
+        let too_large = |bytes: usize| ReadError::TooLarge {
+            path: path.to_path_buf(),
+            bytes,
+        };
+
         if let Ok(url) = Url::from_file_path(path)
             && let Some(rope) = self.documents.get(&url)
         {
+            if rope.len_bytes() > MAX_FILE_BYTES {
+                return Err(too_large(rope.len_bytes()));
+            }
             return Ok(rope.to_string());
         }
 
-        tokio::fs::read_to_string(path)
-            .await
-            .map_err(|e| format!("Failed to read file '{}': {}", path.display(), e))
+        let io_error = |e: std::io::Error| {
+            ReadError::Io(format!("Failed to read file '{}': {}", path.display(), e))
+        };
+
+        let len = tokio::fs::metadata(path).await.map_err(io_error)?.len();
+        if len > MAX_FILE_BYTES as u64 {
+            return Err(too_large(len as usize));
+        }
+
+        tokio::fs::read_to_string(path).await.map_err(io_error)
     }
 }
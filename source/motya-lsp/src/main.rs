@@ -1,23 +1,44 @@
 mod backend;
+mod completion;
+mod definition;
 mod diagnostics;
+mod hover;
 mod loader;
 
-use std::sync::Arc;
+use std::collections::HashMap;
 
-use dashmap::DashMap;
 use ropey::Rope;
 use tower_lsp::{LanguageServer, LspService, Server, jsonrpc::Result, lsp_types::*};
 
-use crate::backend::Backend;
+use crate::{
+    backend::{Backend, apply_change},
+    diagnostics::SUGGESTION,
+};
 
 #[tower_lsp::async_trait]
 impl LanguageServer for Backend {
-    async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+    async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
+        let language = params
+            .locale
+            .as_deref()
+            .and_then(|locale| locale.split(['-', '_']).next())
+            .filter(|language| !language.is_empty())
+            .unwrap_or("en")
+            .to_lowercase();
+        let _ = self.language.set(language);
+
         Ok(InitializeResult {
             capabilities: ServerCapabilities {
                 text_document_sync: Some(TextDocumentSyncCapability::Kind(
-                    TextDocumentSyncKind::FULL,
+                    TextDocumentSyncKind::INCREMENTAL,
                 )),
+                code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
+                completion_provider: Some(CompletionOptions {
+                    trigger_characters: Some(vec!["=".to_string()]),
+                    ..Default::default()
+                }),
+                hover_provider: Some(HoverProviderCapability::Simple(true)),
+                definition_provider: Some(OneOf::Left(true)),
                 ..Default::default()
             },
             ..Default::default()
@@ -39,24 +60,102 @@ impl LanguageServer for Backend {
         let text = params.text_document.text;
 
         self.documents.insert(uri.clone(), Rope::from_str(&text));
-        self.validate(uri).await;
+        self.schedule(uri, params.text_document.version);
     }
 
     async fn did_change(&self, params: DidChangeTextDocumentParams) {
         let uri = params.text_document.uri;
 
-        if let Some(change) = params.content_changes.into_iter().last() {
-            self.documents
-                .insert(uri.clone(), Rope::from_str(&change.text));
+        if let Some(mut rope) = self.documents.get_mut(&uri) {
+            for change in params.content_changes {
+                apply_change(&mut rope, change);
+            }
         }
-        self.validate(uri).await;
+        self.schedule(uri, params.text_document.version);
+    }
+
+    async fn code_action(&self, params: CodeActionParams) -> Result<Option<CodeActionResponse>> {
+        let uri = params.text_document.uri;
+
+        let actions = params
+            .context
+            .diagnostics
+            .into_iter()
+            .filter_map(|diagnostic| {
+                let name = diagnostic
+                    .data
+                    .as_ref()?
+                    .get(SUGGESTION)?
+                    .as_str()?
+                    .to_string();
+                let edit = TextEdit::new(diagnostic.range, name.clone());
+
+                Some(CodeActionOrCommand::CodeAction(CodeAction {
+                    title: format!("Replace with '{name}'"),
+                    kind: Some(CodeActionKind::QUICKFIX),
+                    diagnostics: Some(vec![diagnostic]),
+                    edit: Some(WorkspaceEdit {
+                        changes: Some(HashMap::from([(uri.clone(), vec![edit])])),
+                        ..Default::default()
+                    }),
+                    is_preferred: Some(true),
+                    ..Default::default()
+                }))
+            })
+            .collect::<Vec<_>>();
+
+        Ok((!actions.is_empty()).then_some(actions))
+    }
+
+    async fn completion(&self, params: CompletionParams) -> Result<Option<CompletionResponse>> {
+        let position = params.text_document_position;
+        let Some(rope) = self.documents.get(&position.text_document.uri) else {
+            return Ok(None);
+        };
+
+        let items = completion::complete(&rope, position.position);
+        Ok(Some(CompletionResponse::Array(items)))
+    }
+
+    async fn hover(&self, params: HoverParams) -> Result<Option<Hover>> {
+        let position = params.text_document_position_params;
+        let Some(rope) = self.documents.get(&position.text_document.uri) else {
+            return Ok(None);
+        };
+        let language = self.language.get().map_or("en", String::as_str);
+
+        Ok(hover::hover(&rope, position.position, language))
+    }
+
+    async fn goto_definition(
+        &self,
+        params: GotoDefinitionParams,
+    ) -> Result<Option<GotoDefinitionResponse>> {
+        let position = params.text_document_position_params;
+        let uri = position.text_document.uri;
+        let Some((kind, name)) = self
+            .documents
+            .get(&uri)
+            .and_then(|rope| definition::reference(&rope, position.position))
+        else {
+            return Ok(None);
+        };
+
+        let location = self
+            .definition(&uri, kind, &name)
+            .and_then(|span| definition::location(&span, &self.documents));
+        Ok(location.map(GotoDefinitionResponse::Scalar))
     }
 
     async fn did_close(&self, params: DidCloseTextDocumentParams) {
         let uri = params.text_document.uri;
+        let included = self.forget(&uri);
         self.client
             .publish_diagnostics(uri.clone(), vec![], None)
             .await;
+        for file in included {
+            self.client.publish_diagnostics(file, vec![], None).await;
+        }
         self.documents.remove(&uri);
     }
 }
@@ -66,10 +165,7 @@ async fn main() {
     let stdin = tokio::io::stdin();
     let stdout = tokio::io::stdout();
 
-    let (service, socket) = LspService::new(|client| Backend {
-        client,
-        documents: Arc::new(DashMap::new()),
-    });
+    let (service, socket) = LspService::new(Backend::new);
 
     Server::new(stdin, stdout, socket).serve(service).await;
 }
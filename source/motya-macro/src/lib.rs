@@ -56,6 +56,10 @@ pub fn derive_node_definition(input: TokenStream) -> TokenStream {
 /// ### Struct Level:
 /// - `name = "..."`: Overrides the expected KDL node name (defaults to `kebab-case` of the struct name).
 /// - `allow_empty`: Allows the node's children block to be empty even if children are defined.
+/// - `field_group`: Makes the struct a group of props and children for other nodes to embed
+///   with `#[node(flatten)]`, implementing `FieldGroup` instead of `KdlParsable`.
+/// - `alias = "..."`: An old name the node is still found under by its parent, with a warning.
+/// - `deprecated = "..."`: The help of that warning. Without an `alias`, any use of the node warns.
 ///
 /// ### Enum Support (Polymorphic Nodes):
 /// Enums allow parsing a child node that can be one of several types. Two modes are supported:
@@ -82,7 +86,17 @@ pub fn derive_node_definition(input: TokenStream) -> TokenStream {
 ///   - Supports primitives (e.g., `algorithm: String` parses `algorithm "sha256"`).
 /// - `#[node(dynamic_child)]`: Maps a block of varied children into a collection (e.g., `Vec<T>`).
 ///   - Supports `min = N` and `max = N` to restrict the number of children.
+///   - Also collects into a `HashMap<String, T>` or `BTreeMap<String, T>`, keyed by the
+///     node name of each child, or its first argument with `key = "arg"`.
+///     A key given twice is reported at the second child.
 /// - `#[node(node_name)]`: Captures the actual KDL tag/identifier as the field's value.
+/// - `#[node(flatten)]`: Embeds a `field_group` struct, whose props and children are written
+///   as if the node declared them. A prop or child declared by both the node and the group,
+///   or by two of its groups, fails to compile. Not supported in enum variants.
+/// - `alias = "..."` on a prop or child: An old key that still parses, recorded as a
+///   deprecation warning on the `ParseContext`. Setting both the old and the new key is an error.
+/// - `deprecated = "..."` on a prop or child: The help of that warning. Without an `alias`,
+///   setting the prop or child at all warns.
 /// - `#[node(default)]`: Uses `Default::default()` (or specific value) if the field is missing.
 /// - `#[node(proxy = "Type")]`: Specifies that this field should be parsed using `Type`'s schema.
 ///   Useful when `Type` is a "Schema Definition" (struct with `#[motya_node]`) and the field
@@ -104,7 +118,7 @@ pub fn derive_node_definition(input: TokenStream) -> TokenStream {
 /// 1. Define a **Schema Struct** (`ServerDef`) mirroring the KDL structure.
 /// 2. Implement `crate::kdl::traits::Convert<S>` for `ServerDef`.
 ///    - `type Output = Server;` (Your Domain Model).
-///    - `fn convert(self, state: &S) -> miette::Result<Server>`.
+///    - `fn convert(self, state: &S) -> Result<Server, ConvertError>`.
 /// 3. The macro generates a `parse_as<S, T>` method that parses the KDL and automatically
 ///    runs your conversion logic, binding errors to the source code spans.
 ///
@@ -114,6 +128,11 @@ pub fn derive_node_definition(input: TokenStream) -> TokenStream {
 /// 3. Argument/Property type checking (including `NonZero` checks).
 /// 4. Value constraints (`min`, `max`, custom validators).
 /// 5. Required field/child presence.
+///
+/// # Schema Snapshots
+/// In tests, and with the `schema-snapshots` feature, every node also implements
+/// `SchemaSnapshot`: `T::schema_snapshot()` renders the grammar of the node as text
+/// for `insta::assert_snapshot!`, so a change to what a config may say shows up in review.
 #[proc_macro_derive(Parser, attributes(node))]
 pub fn derive_parser(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as syn::DeriveInput);
@@ -141,7 +160,8 @@ pub fn derive_parser(input: TokenStream) -> TokenStream {
 /// - **`Deref` to `TData`**: Transparent access to the parsed fields.
 /// - **Specific Error Helpers**: Generates methods like `err_{field_name}(msg)` for every field.
 ///   - For a field `port`: `def.err_port("Invalid port")` creates an error pointing exactly to that property in the KDL file.
-///   - Handles both named properties (via `err_prop`) and positional arguments (via `err_arg`).
+///   - Handles named properties, child nodes and positional arguments (overridable with `#[err(prop = "...")]` / `#[err(arg = N)]`).
+///   - `span_{field_name}()` returns the same span, for building a `ConvertError::with_field`.
 ///
 /// ## 2. Proxy Pattern (Schema $\to$ Domain)
 /// Instead of validating inside the parser, you implement the `Convert` trait to transform the
@@ -154,7 +174,9 @@ pub fn derive_parser(input: TokenStream) -> TokenStream {
 /// - `parse_as<S, T>(&ctx, &state) -> Result<T, ConfigError>`:
 ///   1. Parses the raw KDL into `Self` (the Schema Type).
 ///   2. Calls `Convert::convert(self, state)`.
-///   3. Automatically maps any `miette::Report` returned by `convert` into the parser's `ConfigError`.
+///   3. Maps the `ConvertError` returned by `convert` into the parser's `ConfigError`. An error made
+///      with `ConvertError::with_field` keeps its field span, even when it is passed up through the
+///      `convert` of enclosing nodes; any other error points at the converted node.
 #[proc_macro_attribute]
 pub fn motya_node(_: TokenStream, item: TokenStream) -> TokenStream {
     let input = parse_macro_input!(item as DeriveInput);
@@ -245,6 +267,10 @@ pub fn motya_node(_: TokenStream, item: TokenStream) -> TokenStream {
                 #data_ident::applicable_node_names()
             }
 
+            fn node_name_aliases() -> &'static [&'static str] {
+                #data_ident::node_name_aliases()
+            }
+
             fn match_score(ctx: &crate::kdl::parser::ctx::ParseContext) -> (isize, Option<String>) {
                 #data_ident::match_score(ctx)
             }
@@ -255,6 +281,11 @@ pub fn motya_node(_: TokenStream, item: TokenStream) -> TokenStream {
                 #data_ident::schemas(ctx)
             }
         }
+
+        #[cfg(any(test, feature = "schema-snapshots"))]
+        impl crate::kdl::schema::snapshot::SchemaSnapshot for #original_ident {
+            const ROOT: bool = <#data_ident as crate::kdl::schema::snapshot::SchemaSnapshot>::ROOT;
+        }
     };
 
     let proxy_method = quote! {
@@ -272,13 +303,7 @@ pub fn motya_node(_: TokenStream, item: TokenStream) -> TokenStream {
                 Ok(val) => Ok(val),
 
                 Err(e) => {
-                    let report: miette::Report = e.into();
-
-                    let parse_err = crate::kdl::parser::utils::macros_helpers::to_parse_error(
-                        report,
-                        ctx.current_span(),
-                        ctx.source().clone()
-                    );
+                    let parse_err = e.into_parse_error(ctx);
                     Err(crate::common_types::error::ConfigError::from_list(vec![parse_err]))
                 }
             }
@@ -317,40 +342,42 @@ fn gen_context_error_helpers(
         };
 
         let method_suffix_clean = method_suffix.replace('-', "_").to_lowercase();
-        let method_name = if let Some(prefix) = &variant_prefix {
-            format_ident!("err_{}_{}", prefix, method_suffix_clean)
+        let (method_name, span_method_name) = if let Some(prefix) = &variant_prefix {
+            (
+                format_ident!("err_{}_{}", prefix, method_suffix_clean),
+                format_ident!("span_{}_{}", prefix, method_suffix_clean),
+            )
         } else {
-            format_ident!("err_{}", method_suffix_clean)
+            (
+                format_ident!("err_{}", method_suffix_clean),
+                format_ident!("span_{}", method_suffix_clean),
+            )
         };
 
-        let body = if let Some(arg_idx) = meta.target_arg {
-            quote! {
-                let span = self.ctx.arg_span(#arg_idx).unwrap_or_else(|| self.ctx.current_span());
-                self.ctx.error_with_span(msg, span)
-            }
+        let lookup = if let Some(arg_idx) = meta.target_arg {
+            quote!(self.ctx.arg_span(#arg_idx))
         } else if let Some(prop_key) = meta.target_prop {
-            quote! {
-                let span = self.ctx.prop_span(#prop_key).unwrap_or_else(|| self.ctx.current_span());
-                self.ctx.error_with_span(msg, span)
-            }
+            quote!(self.ctx.prop_span(#prop_key))
         } else if field.ident.is_none() {
-            quote! {
-                let span = self.ctx.arg_span(#index).unwrap_or_else(|| self.ctx.current_span());
-                self.ctx.error_with_span(msg, span)
-            }
+            quote!(self.ctx.arg_span(#index))
         } else {
-            let key = field.ident.as_ref().unwrap().to_string();
-            let kdl_key = key.replace('_', "-");
-            quote! {
-                let span = self.ctx.prop_span(#kdl_key).unwrap_or_else(|| self.ctx.current_span());
-                self.ctx.error_with_span(msg, span)
-            }
+            // Named fields are either properties or child nodes, so look for both.
+            let kdl_key = meta
+                .node_name
+                .clone()
+                .unwrap_or_else(|| field.ident.as_ref().unwrap().to_string().replace('_', "-"));
+            quote!(self.ctx.prop_span(#kdl_key).or_else(|| self.ctx.child_span(#kdl_key)))
         };
 
         methods.push(quote! {
+            #[doc = concat!("Returns the span of `", #method_suffix, "`, or of the whole node if it is absent.")]
+            pub fn #span_method_name(&self) -> miette::SourceSpan {
+                #lookup.unwrap_or_else(|| self.ctx.current_span())
+            }
+
             #[doc = concat!("Returns an error associated with `", #method_suffix, "`.")]
             pub fn #method_name(&self, msg: impl Into<String>) -> miette::Error {
-                #body
+                self.ctx.error_with_span(msg, self.#span_method_name())
             }
         });
     };
@@ -389,6 +416,8 @@ struct ErrMeta {
     method_suffix: Option<String>,
     target_prop: Option<String>,
     target_arg: Option<usize>,
+    /// The KDL name given by `#[node(name = "...")]`.
+    node_name: Option<String>,
 }
 
 fn parse_err_meta(field: &syn::Field) -> ErrMeta {
@@ -408,6 +437,16 @@ fn parse_err_meta(field: &syn::Field) -> ErrMeta {
                 }
                 Ok(())
             });
+        } else if attr.path().is_ident("node") {
+            let _ = attr.parse_nested_meta(|m| {
+                if m.path.is_ident("name") {
+                    let s: syn::LitStr = m.value()?.parse()?;
+                    meta.node_name = Some(s.value());
+                } else if m.input.peek(syn::Token![=]) {
+                    let _: syn::Expr = m.value()?.parse()?;
+                }
+                Ok(())
+            });
         }
     }
     meta
@@ -2,7 +2,7 @@ use proc_macro2::TokenStream;
 use quote::quote;
 
 use crate::node_parser::model::{
-    ArgSpec, BlockSpec, NodeModel, NodeModelKind, PropSpec, VariantFields, VariantSpec,
+    ArgSpec, BlockSpec, GroupSpec, NodeModel, NodeModelKind, PropSpec, VariantFields, VariantSpec,
 };
 
 pub struct ScoreGenerator;
@@ -18,11 +18,14 @@ impl ScoreGenerator {
             NodeModelKind::Struct => {
                 let logic = Self::gen_struct_score(
                     model.kdl_name.as_deref(),
+                    model.deprecation.alias.as_deref(),
                     &model.props,
                     &model.args,
                     &model.block,
+                    model.allow_empty_block,
                     model.all_args_field.is_some(),
                     model.all_props_field.is_some(),
+                    &model.groups,
                 );
                 quote! {
                     #logic
@@ -30,7 +33,7 @@ impl ScoreGenerator {
             }
             NodeModelKind::Enum(variants) => {
                 let variant_scores = variants.iter().map(|v| {
-                    let logic = Self::gen_variant_score(v);
+                    let logic = Self::gen_variant_score(v, model.allow_empty_block);
                     quote! { #logic }
                 });
 
@@ -42,7 +45,7 @@ impl ScoreGenerator {
         }
     }
 
-    pub fn gen_variant_score(v: &VariantSpec) -> TokenStream {
+    pub fn gen_variant_score(v: &VariantSpec, allow_empty_block: bool) -> TokenStream {
         let disqualify = Self::DISQUALIFY;
 
         let name_check = if let Some(name) = &v.kdl_name {
@@ -92,8 +95,8 @@ impl ScoreGenerator {
                         ..
                     } => {
                         let args_check = Self::gen_args_check(args, all_args.is_some());
-                        let props_check = Self::gen_props_check(props, all_props.is_some());
-                        let block_check = Self::gen_block_check(block);
+                        let props_check = Self::gen_props_check(props, all_props.is_some(), &[]);
+                        let block_check = Self::gen_block_check(block, allow_empty_block);
                         quote! {
                             #args_check
                             #props_check
@@ -135,17 +138,21 @@ impl ScoreGenerator {
 
     fn gen_struct_score(
         kdl_name: Option<&str>,
+        alias: Option<&str>,
         props: &[PropSpec],
         args: &[ArgSpec],
         block: &BlockSpec,
+        allow_empty_block: bool,
         has_all_args: bool,
         has_all_props: bool,
+        groups: &[GroupSpec],
     ) -> TokenStream {
         let disqualify = Self::DISQUALIFY;
 
         let name_check = if let Some(name) = kdl_name {
+            let names = std::iter::once(name).chain(alias);
             quote! {
-                if ctx.name()? != #name {
+                if ![ #(#names),* ].contains(&ctx.name()?) {
                     return Ok((#disqualify, Some(format!("Name mismatch: expected '{}', got '{}'", #name, ctx.name()?))));
                 }
                 score += 10;
@@ -155,8 +162,12 @@ impl ScoreGenerator {
         };
 
         let args_check = Self::gen_args_check(args, has_all_args);
-        let props_check = Self::gen_props_check(props, has_all_props);
-        let block_check = Self::gen_block_check(block);
+        let props_check = Self::gen_props_check(props, has_all_props, groups);
+        let block_check = match block {
+            // Field groups may bring children along.
+            BlockSpec::Empty if !groups.is_empty() => quote!(),
+            _ => Self::gen_block_check(block, allow_empty_block),
+        };
 
         quote! {
             {
@@ -221,12 +232,17 @@ impl ScoreGenerator {
         }
     }
 
-    fn gen_props_check(props: &[PropSpec], has_all_props: bool) -> TokenStream {
+    fn gen_props_check(
+        props: &[PropSpec],
+        has_all_props: bool,
+        groups: &[GroupSpec],
+    ) -> TokenStream {
         let disqualify = Self::DISQUALIFY;
         let match_req = Self::MATCH_REQUIRED;
         let match_opt = Self::MATCH_OPTIONAL;
 
-        let allowed_keys: Vec<_> = props.iter().map(|p| &p.key).collect();
+        let allowed_keys: Vec<_> = props.iter().flat_map(PropSpec::keys).collect();
+        let group_types = groups.iter().map(|g| &g.base.inner_type);
 
         let unknown_keys_check = if has_all_props {
             quote! {
@@ -234,7 +250,10 @@ impl ScoreGenerator {
             }
         } else {
             quote! {
-                let allowed = [ #(#allowed_keys),* ];
+                let allowed: Vec<&str> = [ #(#allowed_keys),* ]
+                    .into_iter()
+                    #(.chain(<#group_types as crate::kdl::parser::field_group::FieldGroup>::PROPS.iter().map(|(k, _)| *k)))*
+                    .collect();
                 for p in &props_list {
                     if let Some(n) = p.name() {
                         if !allowed.contains(&n) {
@@ -247,8 +266,9 @@ impl ScoreGenerator {
 
         let checks = props.iter().map(|p| {
             let key = &p.key;
+            let keys = p.keys();
             let find_expr = quote! {
-                props_list.iter().any(|p| p.name() == Some(#key))
+                props_list.iter().any(|p| [ #(#keys),* ].iter().any(|k| p.name() == Some(*k)))
             };
 
             if p.required {
@@ -276,7 +296,7 @@ impl ScoreGenerator {
         }
     }
 
-    fn gen_block_check(block: &BlockSpec) -> TokenStream {
+    fn gen_block_check(block: &BlockSpec, allow_empty_block: bool) -> TokenStream {
         let disqualify = Self::DISQUALIFY;
         let match_children = Self::MATCH_CHILDREN;
 
@@ -305,6 +325,12 @@ impl ScoreGenerator {
                     }
                 }
             }
+            // `allow_empty` nodes may leave the block out, just as parsing allows.
+            BlockSpec::Strict(_) if allow_empty_block => quote! {
+                if ctx.has_children_block() && !ctx.nodes()?.is_empty() {
+                    score += #match_children;
+                }
+            },
             BlockSpec::Strict(_) => quote! {
                 if ctx.has_children_block() {
                     let count = ctx.nodes()?.len();
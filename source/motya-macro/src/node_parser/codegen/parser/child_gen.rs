@@ -3,8 +3,8 @@ use quote::quote;
 
 use super::{Namespaces, validation::ValidationGenerator};
 use crate::node_parser::{
-    codegen::utils::gen_value_parser,
-    model::{BlockSpec, ChildMode, ChildSpec, ParseOptions},
+    codegen::utils::{gen_value_parser, opt_str},
+    model::{BlockSpec, ChildMode, ChildSpec, GroupSpec, MapKey, MapSpec, ParseOptions},
 };
 
 pub struct ChildGenerator<'a> {
@@ -20,18 +20,35 @@ impl<'a> ChildGenerator<'a> {
         }
     }
 
-    pub fn generate(&self, block: &BlockSpec, ignore_unknown: bool) -> TokenStream {
+    pub fn generate(
+        &self,
+        block: &BlockSpec,
+        groups: &[GroupSpec],
+        ignore_unknown: bool,
+    ) -> TokenStream {
         match block {
+            BlockSpec::Empty if !groups.is_empty() => {
+                self.gen_strict_block(&[], groups, ignore_unknown)
+            }
             BlockSpec::Empty => quote!(),
 
             BlockSpec::Dynamic {
                 field_ident,
                 inner_type,
                 opts,
+                map: None,
                 ..
             } => self.gen_dynamic_block(field_ident, inner_type, opts),
 
-            BlockSpec::Strict(children) => self.gen_strict_block(children, ignore_unknown),
+            BlockSpec::Dynamic {
+                field_ident,
+                inner_type,
+                opts,
+                map: Some(map),
+                ..
+            } => self.gen_map_block(field_ident, inner_type, opts, map),
+
+            BlockSpec::Strict(children) => self.gen_strict_block(children, groups, ignore_unknown),
         }
     }
 
@@ -65,24 +82,76 @@ impl<'a> ChildGenerator<'a> {
         }
     }
 
-    fn gen_strict_block(&self, children: &[ChildSpec], ignore_unknown: bool) -> TokenStream {
+    fn gen_map_block(
+        &self,
+        ident: &syn::Ident,
+        inner_type: &syn::Type,
+        opts: &ParseOptions,
+        map: &MapSpec,
+    ) -> TokenStream {
         let helpers = &self.namespaces.helpers;
-        let error_mod = &self.namespaces.error_mod;
+        let map_type = &map.ty;
+        let from_arg = map.key == MapKey::FirstArg;
 
-        let mut decls = Vec::new();
-        let mut processing = Vec::new();
-        let mut finals = Vec::new();
+        let parse_call = if let Some(func) = &opts.parse_with {
+            quote!(#func(&child_ctx, state))
+        } else {
+            quote!(<#inner_type as crate::kdl::parser::parsable::KdlParsable<S>>::parse_node(&child_ctx, state))
+        };
 
-        for child in children {
-            let ident = &child.base.ident;
-            if child.is_vec {
-                decls.push(quote!(let mut #ident = Vec::new();));
-            } else {
-                decls.push(quote!(let mut #ident = None;));
+        let vec_checks = self.validator.gen_vec_bounds(ident, opts, "Children block");
+
+        quote! {
+            let mut #ident: #map_type = Default::default();
+            let mut __seen_keys = std::collections::HashSet::new();
+            if let Ok(iter) = ctx.nodes() {
+                for child_ctx in iter {
+                    let (__key, __key_span) = match #helpers::child_key(&child_ctx, #from_arg) {
+                        Ok(key) => key,
+                        Err(e) => {
+                            #helpers::push_report(&mut __errors, e, child_ctx.current_span(), &child_ctx);
+                            continue;
+                        }
+                    };
+
+                    if !__seen_keys.insert(__key.clone()) {
+                        #helpers::push_custom(
+                            &mut __errors,
+                            format!("Duplicate '{}'", __key),
+                            Some(format!("'{}' is already defined in this block", __key)),
+                            __key_span,
+                            &child_ctx,
+                        );
+                        continue;
+                    }
+
+                    match #parse_call {
+                        Ok(v) => {
+                            #ident.insert(__key, v);
+                        }
+                        Err(e) => #helpers::merge_child_errors(&mut __errors, Err(e)),
+                    }
+                }
             }
+            #vec_checks
         }
+    }
+
+    fn gen_strict_block(
+        &self,
+        children: &[ChildSpec],
+        groups: &[GroupSpec],
+        ignore_unknown: bool,
+    ) -> TokenStream {
+        let helpers = &self.namespaces.helpers;
 
-        decls.push(quote! {
+        let mut finals = Vec::new();
+        let (processing, mut known_names) = match self.gen_children_processing(children) {
+            Ok(parts) => parts,
+            Err(e) => return e,
+        };
+
+        let map_decl = quote! {
             let mut __children_map: std::collections::HashMap<String, Vec<crate::kdl::parser::ctx::ParseContext>> =
                 std::collections::HashMap::new();
 
@@ -93,7 +162,70 @@ impl<'a> ChildGenerator<'a> {
                     }
                 }
             }
-        });
+        };
+
+        let mut group_parsing = Vec::new();
+        for group in groups {
+            let ident = &group.base.ident;
+            let ty = &group.base.inner_type;
+            group_parsing.push(quote! {
+                let #ident = <#ty as crate::kdl::parser::field_group::FieldGroup>::parse_group(
+                    ctx,
+                    state,
+                    &mut __children_map,
+                    &mut __errors,
+                );
+            });
+            known_names
+                .push(quote!(<#ty as crate::kdl::parser::field_group::FieldGroup>::CHILDREN));
+        }
+
+        if !ignore_unknown {
+            finals.push(quote! {
+                let __known_names: &[&[&str]] = &[ #(#known_names),* ];
+                for (name, nodes) in __children_map {
+                    if !nodes.is_empty() {
+                        let first = &nodes[0];
+                        let msg = format!("Unknown child node '{}'", name);
+                        let help = crate::kdl::parser::suggest::did_you_mean(
+                            &name,
+                            __known_names.iter().flat_map(|names| names.iter().copied()),
+                        );
+                        #helpers::push_custom(&mut __errors, msg, help, first.name_span(), first);
+                    }
+                }
+            });
+        }
+
+        quote! {
+            #map_decl
+            #processing
+            #(#group_parsing)*
+            #(#finals)*
+        }
+    }
+
+    /// Parses `children` out of `__children_map`, and returns the code with the
+    /// names each child is looked up by.
+    pub fn gen_children_processing(
+        &self,
+        children: &[ChildSpec],
+    ) -> Result<(TokenStream, Vec<TokenStream>), TokenStream> {
+        let helpers = &self.namespaces.helpers;
+        let error_mod = &self.namespaces.error_mod;
+
+        let mut decls = Vec::new();
+        let mut processing = Vec::new();
+        let mut known_names = Vec::new();
+
+        for child in children {
+            let ident = &child.base.ident;
+            if child.is_vec {
+                decls.push(quote!(let mut #ident = Vec::new();));
+            } else {
+                decls.push(quote!(let mut #ident = None;));
+            }
+        }
 
         for child in children {
             let ident = &child.base.ident;
@@ -101,11 +233,11 @@ impl<'a> ChildGenerator<'a> {
             let opts = &child.base.opts;
 
             if child.name.is_some() && opts.flatten {
-                return syn::Error::new_spanned(
+                return Err(syn::Error::new_spanned(
                     ident,
                     "Attributes `name` and `flatten` are mutually exclusive.",
                 )
-                .to_compile_error();
+                .to_compile_error());
             }
 
             let names_expr = if let Some(n) = &child.name {
@@ -134,6 +266,10 @@ impl<'a> ChildGenerator<'a> {
                 }
             };
 
+            known_names.push(names_expr.clone());
+
+            let alias_lookup = self.gen_alias_lookup(child);
+
             let parse_call = if let Some(func) = &opts.parse_with {
                 quote!(#func(&child_ctx, state))
             } else {
@@ -149,7 +285,7 @@ impl<'a> ChildGenerator<'a> {
                                 #val_validator
                                 Ok(val)
                             })().map_err(|e| #error_mod::ConfigError::from_list(vec![
-                                #helpers::to_parse_error(e, child_ctx.current_span(), child_ctx.source())
+                                #helpers::to_parse_error(e, child_ctx.current_span(), &child_ctx)
                             ]))
                         }
                     }
@@ -175,7 +311,7 @@ impl<'a> ChildGenerator<'a> {
                     checks.push(quote! {
                         if #ident.len() < #min {
                             let msg = format!("Expected at least {} nodes of '{}', found {}", #min, #error_node_name_literal, #ident.len());
-                            #helpers::push_custom(&mut __errors, msg, None, ctx.current_span(), ctx.source().clone());
+                            #helpers::push_custom(&mut __errors, msg, None, ctx.current_span(), ctx);
                         }
                     });
                 }
@@ -183,7 +319,7 @@ impl<'a> ChildGenerator<'a> {
                     checks.push(quote! {
                         if #ident.len() > #max as usize {
                             let msg = format!("Expected at most {} nodes of '{}', found {}", #max, #error_node_name_literal, #ident.len());
-                            #helpers::push_custom(&mut __errors, msg, None, ctx.current_span(), ctx.source().clone());
+                            #helpers::push_custom(&mut __errors, msg, None, ctx.current_span(), ctx);
                         }
                     });
                 }
@@ -201,7 +337,7 @@ impl<'a> ChildGenerator<'a> {
                     } else {
                         format!("Missing required node(s). Expected one of: {:?}. Found other nodes: {:?}", __lookup_names, available_nodes)
                     };
-                    #helpers::push_custom(&mut __errors, msg, None, ctx.current_span(), ctx.source().clone());
+                    #helpers::push_custom(&mut __errors, msg, None, ctx.current_span(), ctx);
                 }
             } else {
                 quote!()
@@ -241,6 +377,7 @@ impl<'a> ChildGenerator<'a> {
                             __extracted_nodes.append(&mut nodes);
                         }
                     }
+                    #alias_lookup
 
                     if __extracted_nodes.is_empty() {
                         #missing_error_logic
@@ -251,22 +388,61 @@ impl<'a> ChildGenerator<'a> {
             });
         }
 
-        if !ignore_unknown {
-            finals.push(quote! {
-                for (name, nodes) in __children_map {
-                    if !nodes.is_empty() {
-                        let first = &nodes[0];
-                        let msg = format!("Unknown child node '{}'", name);
-                        #helpers::push_custom(&mut __errors, msg, None, first.current_span(), first.source());
+        Ok((
+            quote! {
+                #(#decls)*
+                #(#processing)*
+            },
+            known_names,
+        ))
+    }
+
+    /// Takes the children written under an old name out of `__children_map`, after
+    /// those found under `__lookup_names`, and warns about deprecated ones.
+    fn gen_alias_lookup(&self, child: &ChildSpec) -> TokenStream {
+        let helpers = &self.namespaces.helpers;
+        let note = opt_str(&child.deprecation.note);
+        let mut lookups = Vec::new();
+
+        if child.deprecation.note.is_some() && child.deprecation.alias.is_none() {
+            lookups.push(quote! {
+                for node in &__extracted_nodes {
+                    if let Ok(name) = node.name() {
+                        #helpers::warn_deprecated(node, name, None, #note, node.name_span());
                     }
                 }
             });
         }
 
-        quote! {
-            #(#decls)*
-            #(#processing)*
-            #(#finals)*
+        if let Some(alias) = &child.deprecation.alias {
+            lookups.push(quote! {
+                if let Some(mut nodes) = __children_map.remove(#alias) {
+                    for node in &nodes {
+                        #helpers::warn_deprecated(
+                            node,
+                            #alias,
+                            __lookup_names.first().copied(),
+                            #note,
+                            node.name_span(),
+                        );
+                    }
+                    __extracted_nodes.append(&mut nodes);
+                }
+            });
         }
+
+        // A node type renamed with a struct-level `alias` warns as it is parsed.
+        if child.mode == ChildMode::Node && child.name.is_none() && !child.base.opts.flatten {
+            let inner = &child.base.inner_type;
+            lookups.push(quote! {
+                for alias in <#inner as crate::kdl::parser::node_schema::NodeSchema>::node_name_aliases() {
+                    if let Some(mut nodes) = __children_map.remove(*alias) {
+                        __extracted_nodes.append(&mut nodes);
+                    }
+                }
+            });
+        }
+
+        quote!( #(#lookups)* )
     }
 }
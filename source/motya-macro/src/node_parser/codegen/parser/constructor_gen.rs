@@ -52,6 +52,9 @@ impl ConstructorGenerator {
         if let Some(aa) = target.all_args {
             add(aa, false);
         }
+        for g in target.groups {
+            add(&g.base, true);
+        }
 
         match target.block {
             BlockSpec::Dynamic { field_ident, .. } => {
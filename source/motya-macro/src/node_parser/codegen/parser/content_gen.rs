@@ -35,6 +35,7 @@ impl<'a> ContentGenerator<'a> {
             target.props,
             target.args,
             target.block,
+            target.groups,
             target.all_props.is_some(),
             target.all_args.is_some(),
             self.model.allow_empty_block,
@@ -44,9 +45,11 @@ impl<'a> ContentGenerator<'a> {
         let parse_args = field_gen.gen_args(target.args);
         let parse_props = field_gen.gen_props(target.props);
         let parse_all_args = field_gen.gen_all_args(target.all_args, target.args.len());
-        let parse_all_props = field_gen.gen_all_props(target.all_props, target.props);
+        let parse_all_props =
+            field_gen.gen_all_props(target.all_props, target.props, target.groups);
 
-        let parse_children = child_gen.generate(target.block, self.model.ignore_unknown);
+        let parse_children =
+            child_gen.generate(target.block, target.groups, self.model.ignore_unknown);
 
         let constructor = ctor_gen.generate(target);
 
@@ -73,13 +73,17 @@ impl<'a> EnumGenerator<'a> {
                         }
                     }
 
-                    let msg = if relevant_errors.is_empty() {
-
-                        format!(
-                            "Unexpected node '{}'. Expected one of the following nodes: {:?}",
+                    let (msg, span) = if relevant_errors.is_empty() {
+                        let msg = crate::kdl::parser::suggest::with_suggestion(
+                            format!(
+                                "Unexpected node '{}'. Expected one of the following nodes: {:?}",
+                                node_name,
+                                v_names
+                            ),
                             node_name,
-                            v_names
-                        )
+                            v_names,
+                        );
+                        (msg, ctx.name_span())
                     } else {
                         let mut s = format!("Invalid usage of node '{}'. Errors:", node_name);
 
@@ -90,11 +94,11 @@ impl<'a> EnumGenerator<'a> {
                                 s.push_str(&format!("\n  - Variant '{}': {}", v_name, reason));
                             }
                         }
-                        s
+                        (s, ctx.current_span())
                     };
 
                     Err(#error_mod::ConfigError::from_list(vec![
-                        #helpers::to_parse_error(ctx.error(msg), ctx.current_span(), ctx.source())
+                        #helpers::to_parse_error(ctx.error_with_span(msg, span), span, ctx)
                     ]))
                 }
             }
@@ -127,6 +131,7 @@ impl<'a> EnumGenerator<'a> {
                     all_props,
                     all_args,
                     node_name,
+                    groups: &[],
                     ctor_path: quote!(Self::#ident),
                     is_tuple: *is_tuple,
                 };
@@ -148,7 +153,7 @@ impl<'a> EnumGenerator<'a> {
         let calcs = self
             .variants
             .iter()
-            .map(ScoreGenerator::gen_variant_score);
+            .map(|v| ScoreGenerator::gen_variant_score(v, self.model.allow_empty_block));
 
         quote! {
             impl #struct_name {
@@ -3,8 +3,8 @@ use quote::quote;
 
 use super::{Namespaces, validation::ValidationGenerator};
 use crate::node_parser::{
-    codegen::utils::gen_value_parser,
-    model::{ArgSpec, BaseField, PropSpec},
+    codegen::utils::{gen_value_parser, opt_str},
+    model::{ArgSpec, BaseField, GroupSpec, PropSpec},
 };
 
 pub struct FieldGenerator<'a> {
@@ -34,7 +34,14 @@ impl<'a> FieldGenerator<'a> {
         let mut streams = Vec::new();
         for prop in props {
             let key = &prop.key;
-            let fetch = quote!(ctx.opt_prop(#key));
+            let fetch = if prop.deprecation.is_set() {
+                let helpers = &self.namespaces.helpers;
+                let alias = opt_str(&prop.deprecation.alias);
+                let note = opt_str(&prop.deprecation.note);
+                quote!(#helpers::opt_prop_aliased(ctx, #key, #alias, #note, &mut __errors))
+            } else {
+                quote!(ctx.opt_prop(#key))
+            };
             let desc = format!("property '{}'", key);
             streams.push(self.gen_binding(&prop.base, fetch, desc, prop.required));
         }
@@ -53,20 +60,38 @@ impl<'a> FieldGenerator<'a> {
                     .skip(#skip)
                     .collect(),
                 Err(e) => {
-                     #helpers::push_report(&mut __errors, e, ctx.current_span(), ctx.source().clone());
+                     #helpers::push_report(&mut __errors, e, ctx.current_span(), ctx);
                      Vec::new()
                 }
             };
         }
     }
 
-    pub fn gen_all_props(&self, field: &Option<BaseField>, props: &[PropSpec]) -> TokenStream {
+    pub fn gen_all_props(
+        &self,
+        field: &Option<BaseField>,
+        props: &[PropSpec],
+        groups: &[GroupSpec],
+    ) -> TokenStream {
         let Some(f) = field else { return quote!() };
         let ident = &f.ident;
 
-        let known_keys: Vec<&String> = props.iter().map(|p| &p.key).collect();
+        let known_keys: Vec<&String> = props.iter().flat_map(PropSpec::keys).collect();
+        let group_types: Vec<_> = groups.iter().map(|g| &g.base.inner_type).collect();
 
-        let loop_body = if known_keys.is_empty() {
+        let loop_body = if !group_types.is_empty() {
+            quote! {
+                if let Some(k_str) = val.name() {
+                    let known = &[ #(#known_keys),* ];
+                    let in_group = [ #(<#group_types as crate::kdl::parser::field_group::FieldGroup>::PROPS),* ]
+                        .iter()
+                        .any(|group| group.iter().any(|(key, _)| *key == k_str.as_str()));
+                    if !known.contains(&k_str.as_str()) && !in_group {
+                        #ident.insert(k_str.to_string(), val);
+                    }
+                }
+            }
+        } else if known_keys.is_empty() {
             quote! {
                 if let Some(k_str) = val.name() {
                     #ident.insert(k_str.to_string(), val);
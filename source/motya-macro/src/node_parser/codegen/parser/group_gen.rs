@@ -0,0 +1,98 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+
+use super::{
+    Namespaces, child_gen::ChildGenerator, constructor_gen::ConstructorGenerator,
+    field_gen::FieldGenerator, types::ParseTarget, validation::ValidationGenerator,
+};
+use crate::node_parser::model::{BlockSpec, NodeModel};
+
+/// Generates the `FieldGroup` impl of a `#[node(field_group)]` struct.
+///
+/// A group has no node of its own: it reads its props off the node embedding it,
+/// and takes its children out of the children of that node. Unknown props and
+/// children are left to the embedding node to report.
+pub struct GroupGenerator<'a> {
+    model: &'a NodeModel,
+    namespaces: Namespaces,
+}
+
+impl<'a> GroupGenerator<'a> {
+    pub fn new(model: &'a NodeModel, namespaces: Namespaces) -> Self {
+        Self { model, namespaces }
+    }
+
+    pub fn generate(&self) -> TokenStream {
+        let struct_name = &self.model.struct_name;
+        let error_mod = &self.namespaces.error_mod;
+
+        let validator = ValidationGenerator::new(&self.namespaces);
+        let field_gen = FieldGenerator::new(&self.namespaces, &validator);
+        let child_gen = ChildGenerator::new(&self.namespaces, &validator);
+
+        let children = match &self.model.block {
+            BlockSpec::Strict(children) => children.as_slice(),
+            _ => &[],
+        };
+
+        let prop_schema = self.model.props.iter().flat_map(|p| {
+            let pt = &p.primitive_kind;
+            p.keys()
+                .map(move |k| quote!((#k, crate::kdl::parser::utils::PrimitiveType::#pt)))
+        });
+
+        // The names of `flatten` children are only known at runtime.
+        let child_names = children.iter().filter(|c| !c.base.opts.flatten).map(|c| {
+            c.name
+                .clone()
+                .unwrap_or_else(|| c.base.ident.to_string().replace('_', "-"))
+        });
+
+        let parse_props = field_gen.gen_props(&self.model.props);
+        let parse_children = match child_gen.gen_children_processing(children) {
+            Ok((processing, _)) => processing,
+            Err(e) => return e,
+        };
+
+        let target = ParseTarget {
+            props: &self.model.props,
+            args: &[],
+            block: &self.model.block,
+            all_props: &None,
+            all_args: &None,
+            node_name: &None,
+            groups: &[],
+            ctor_path: quote!(Self),
+            is_tuple: false,
+        };
+        let constructor = ConstructorGenerator::new().generate(&target);
+
+        quote! {
+            #[allow(clippy::all, unused_mut, unused_variables)]
+            impl crate::kdl::parser::field_group::FieldGroup for #struct_name {
+                const PROPS: &'static [(&'static str, crate::kdl::parser::utils::PrimitiveType)] =
+                    &[ #(#prop_schema),* ];
+                const CHILDREN: &'static [&'static str] = &[ #(#child_names),* ];
+
+                fn parse_group<S>(
+                    ctx: &crate::kdl::parser::ctx::ParseContext,
+                    state: &S,
+                    __children_map: &mut std::collections::HashMap<String, Vec<crate::kdl::parser::ctx::ParseContext>>,
+                    errors: &mut Vec<#error_mod::ParseError>,
+                ) -> Option<Self> {
+                    let mut __errors: Vec<#error_mod::ParseError> = Vec::new();
+
+                    #parse_props
+                    #parse_children
+
+                    if !__errors.is_empty() {
+                        errors.append(&mut __errors);
+                        return None;
+                    }
+
+                    Some(#constructor)
+                }
+            }
+        }
+    }
+}
@@ -11,12 +11,15 @@ mod constructor_gen;
 mod content_gen;
 mod enum_gen;
 mod field_gen;
+mod group_gen;
 mod struct_gen;
 mod types;
 mod validation;
 
 use enum_gen::EnumGenerator;
+use group_gen::GroupGenerator;
 use struct_gen::StructGenerator;
+use validation::ValidationGenerator;
 
 #[derive(Clone)]
 pub struct Namespaces {
@@ -46,6 +49,10 @@ impl<'a> ParserGenerator<'a> {
         let struct_name = &self.model.struct_name;
         let ns = Namespaces::new();
 
+        if self.model.is_field_group {
+            return GroupGenerator::new(self.model, ns).generate();
+        }
+
         let mut helper_impl = quote!();
 
         let body = match &self.model.kind {
@@ -60,11 +67,15 @@ impl<'a> ParserGenerator<'a> {
 
         let parsable_impl = self.wrap_trait_impl(struct_name, body, &ns);
         let schema_impl = self.gen_node_schema_impl(struct_name);
+        let snapshot_impl = self.gen_snapshot_impl(struct_name);
+        let group_checks = ValidationGenerator::new(&ns).gen_group_conflicts(self.model);
 
         quote! {
             #helper_impl
             #parsable_impl
             #schema_impl
+            #snapshot_impl
+            #group_checks
         }
     }
 
@@ -122,16 +133,37 @@ impl<'a> ParserGenerator<'a> {
 
         let match_score_body = ScoreGenerator::gen_match_score_impl(self.model);
 
+        let aliases_fn = self.model.deprecation.alias.as_ref().map(|alias| {
+            quote! {
+                fn node_name_aliases() -> &'static [&'static str] {
+                    &[#alias]
+                }
+            }
+        });
+
         quote! {
             impl crate::kdl::parser::node_schema::NodeSchema for #struct_name {
                 fn applicable_node_names() -> &'static [&'static str] {
                     #names_body
                 }
 
+                #aliases_fn
+
                 fn match_score(ctx: &crate::kdl::parser::ctx::ParseContext) -> (isize, Option<String>) {
                     #match_score_body
                 }
             }
         }
     }
+
+    fn gen_snapshot_impl(&self, struct_name: &syn::Ident) -> TokenStream {
+        let root = self.model.is_root;
+
+        quote! {
+            #[cfg(any(test, feature = "schema-snapshots"))]
+            impl crate::kdl::schema::snapshot::SchemaSnapshot for #struct_name {
+                const ROOT: bool = #root;
+            }
+        }
+    }
 }
@@ -3,7 +3,10 @@ use quote::quote;
 
 use super::content_gen::ContentGenerator;
 use crate::node_parser::{
-    codegen::parser::{Namespaces, types::ParseTarget},
+    codegen::{
+        parser::{Namespaces, types::ParseTarget},
+        utils::opt_str,
+    },
     model::NodeModel,
 };
 
@@ -30,6 +33,8 @@ impl<'a> StructGenerator<'a> {
             self.gen_node_name_check()
         };
 
+        let deprecation_check = self.gen_deprecation_check();
+
         let content_gen = ContentGenerator::new(&self.namespaces, self.model);
 
         let target = ParseTarget {
@@ -39,6 +44,7 @@ impl<'a> StructGenerator<'a> {
             all_props: &self.model.all_props_field,
             all_args: &self.model.all_args_field,
             node_name: &self.model.node_name_field,
+            groups: &self.model.groups,
             ctor_path: quote!(Self),
             is_tuple: false,
         };
@@ -51,6 +57,7 @@ impl<'a> StructGenerator<'a> {
             let mut __errors: Vec<#error_mod::ParseError> = Vec::new();
             #name_extraction
             #name_check
+            #deprecation_check
             #body
         }
     }
@@ -82,7 +89,7 @@ impl<'a> StructGenerator<'a> {
                             msg,
                             None,
                             ctx.current_span(),
-                            ctx.source().clone()
+                            ctx
                         );
                         return Err(#error_mod::ConfigError::from_list(__errors));
                     }
@@ -93,6 +100,26 @@ impl<'a> StructGenerator<'a> {
         }
     }
 
+    /// Warns when the node is written under its old name, or at all when it is
+    /// deprecated without one.
+    fn gen_deprecation_check(&self) -> TokenStream {
+        let helpers = &self.namespaces.helpers;
+        let deprecation = &self.model.deprecation;
+        let note = opt_str(&deprecation.note);
+
+        match (&deprecation.alias, &self.model.kdl_name) {
+            (Some(alias), Some(name)) => quote! {
+                if __actual_name == #alias {
+                    #helpers::warn_deprecated(ctx, #alias, Some(#name), #note, ctx.name_span());
+                }
+            },
+            (None, Some(_)) if deprecation.note.is_some() => quote! {
+                #helpers::warn_deprecated(ctx, __actual_name, None, #note, ctx.name_span());
+            },
+            _ => quote!(),
+        }
+    }
+
     fn gen_name_extraction(&self) -> TokenStream {
         let helpers = &self.namespaces.helpers;
         let error_mod = &self.namespaces.error_mod;
@@ -102,7 +129,7 @@ impl<'a> StructGenerator<'a> {
                 Ok(n) => n,
                 Err(e) => {
                     return Err(#error_mod::ConfigError::from_list(vec![
-                        #helpers::to_parse_error(e, ctx.current_span(), ctx.source().clone())
+                        #helpers::to_parse_error(e, ctx.current_span(), ctx)
                     ]));
                 }
             };
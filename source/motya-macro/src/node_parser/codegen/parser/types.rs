@@ -1,6 +1,6 @@
 use proc_macro2::TokenStream;
 
-use crate::node_parser::model::{ArgSpec, BaseField, BlockSpec, NameSpec, PropSpec};
+use crate::node_parser::model::{ArgSpec, BaseField, BlockSpec, GroupSpec, NameSpec, PropSpec};
 
 pub struct ParseTarget<'a> {
     pub props: &'a [PropSpec],
@@ -9,6 +9,7 @@ pub struct ParseTarget<'a> {
     pub all_props: &'a Option<BaseField>,
     pub all_args: &'a Option<BaseField>,
     pub node_name: &'a Option<NameSpec>,
+    pub groups: &'a [GroupSpec],
 
     pub ctor_path: TokenStream,
 
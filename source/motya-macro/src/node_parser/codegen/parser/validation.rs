@@ -2,7 +2,7 @@ use proc_macro2::TokenStream;
 use quote::quote;
 
 use super::Namespaces;
-use crate::node_parser::model::{ArgSpec, BlockSpec, ParseOptions, PropSpec};
+use crate::node_parser::model::{ArgSpec, BlockSpec, GroupSpec, NodeModel, ParseOptions, PropSpec};
 
 pub struct ValidationGenerator<'a> {
     namespaces: &'a Namespaces,
@@ -18,6 +18,7 @@ impl<'a> ValidationGenerator<'a> {
         props: &[PropSpec],
         args: &[ArgSpec],
         block: &BlockSpec,
+        groups: &[GroupSpec],
         has_all_props: bool,
         has_all_args: bool,
         allow_empty_block: bool,
@@ -25,8 +26,11 @@ impl<'a> ValidationGenerator<'a> {
     ) -> TokenStream {
         let helpers = &self.namespaces.helpers;
         let mut rules = Vec::new();
+        let mut prelude = quote!();
 
         match block {
+            // The children of field groups are checked as they are parsed.
+            BlockSpec::Empty if !groups.is_empty() => {}
             BlockSpec::Empty => rules.push(quote!(crate::kdl::parser::ensures::Rule::NoChildren)),
             _ if !allow_empty_block => {
                 rules.push(quote!(crate::kdl::parser::ensures::Rule::ReqChildren))
@@ -60,14 +64,26 @@ impl<'a> ValidationGenerator<'a> {
             }
 
             if !has_all_props {
-                let prop_schema = props.iter().map(|p| {
-                    let k = &p.key;
+                let prop_schema = props.iter().flat_map(|p| {
                     let pt = &p.primitive_kind;
-                    quote!((#k, crate::kdl::parser::utils::PrimitiveType::#pt))
+                    p.keys()
+                        .map(move |k| quote!((#k, crate::kdl::parser::utils::PrimitiveType::#pt)))
                 });
-                rules.push(
-                    quote!(crate::kdl::parser::ensures::Rule::OnlyKeysTyped(&[ #(#prop_schema),* ])),
-                );
+                if groups.is_empty() {
+                    rules.push(quote!(crate::kdl::parser::ensures::Rule::OnlyKeysTyped(&[ #(#prop_schema),* ])));
+                } else {
+                    let group_types = groups.iter().map(|g| &g.base.inner_type);
+                    prelude = quote! {
+                        let __prop_schema: Vec<(&str, crate::kdl::parser::utils::PrimitiveType)> =
+                            [ #(#prop_schema),* ]
+                                .into_iter()
+                                #(.chain(<#group_types as crate::kdl::parser::field_group::FieldGroup>::PROPS.iter().copied()))*
+                                .collect();
+                    };
+                    rules.push(quote!(crate::kdl::parser::ensures::Rule::OnlyKeysTyped(
+                        &__prop_schema
+                    )));
+                }
             }
         }
 
@@ -76,8 +92,9 @@ impl<'a> ValidationGenerator<'a> {
         }
 
         quote! {
+            #prelude
             if let Err(e) = ctx.validate(&[ #(#rules),* ]) {
-                #helpers::push_report(&mut __errors, e, ctx.current_span(), ctx.source().clone());
+                #helpers::push_report(&mut __errors, e, ctx.current_span(), ctx);
             }
         }
     }
@@ -125,7 +142,7 @@ impl<'a> ValidationGenerator<'a> {
             checks.push(quote! {
                 if #ident.len() < #min {
                     let msg = format!("{} must contain at least {} item(s), found {}", #context_name, #min, #ident.len());
-                    #helpers::push_custom(&mut __errors, msg, None, ctx.current_span(), ctx.source().clone());
+                    #helpers::push_custom(&mut __errors, msg, None, ctx.current_span(), ctx);
                 }
             });
         }
@@ -133,7 +150,7 @@ impl<'a> ValidationGenerator<'a> {
             checks.push(quote! {
                 if #ident.len() > #max {
                     let msg = format!("{} cannot contain more than {} item(s), found {}", #context_name, #max, #ident.len());
-                    #helpers::push_custom(&mut __errors, msg, None, ctx.current_span(), ctx.source().clone());
+                    #helpers::push_custom(&mut __errors, msg, None, ctx.current_span(), ctx);
                 }
             });
         }
@@ -141,6 +158,79 @@ impl<'a> ValidationGenerator<'a> {
         quote!( #(#checks)* )
     }
 
+    /// Compile-time checks that the field groups embedded with `#[node(flatten)]`
+    /// declare none of the props and children of the node, nor of each other.
+    pub fn gen_group_conflicts(&self, model: &NodeModel) -> TokenStream {
+        let groups = &model.groups;
+        if groups.is_empty() {
+            return quote!();
+        }
+
+        let fg = quote!(crate::kdl::parser::field_group);
+        let struct_name = model.struct_name.to_string();
+        let children = match &model.block {
+            BlockSpec::Strict(children) => children.as_slice(),
+            _ => &[],
+        };
+        let type_name = |g: &GroupSpec| {
+            let ty = &g.base.inner_type;
+            quote!(#ty).to_string().replace(' ', "")
+        };
+
+        let mut checks = Vec::new();
+        for group in groups {
+            let ty = &group.base.inner_type;
+            let group_name = type_name(group);
+
+            for prop in model.props.iter() {
+                let key = &prop.key;
+                let msg = format!(
+                    "`{struct_name}` and its flattened `{group_name}` both declare the property '{key}'"
+                );
+                checks.push(quote! {
+                    assert!(!#fg::has_prop(<#ty as #fg::FieldGroup>::PROPS, #key), #msg);
+                });
+            }
+
+            for child in children.iter().filter(|c| !c.base.opts.flatten) {
+                let name = child
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| child.base.ident.to_string().replace('_', "-"));
+                let msg = format!(
+                    "`{struct_name}` and its flattened `{group_name}` both declare the child '{name}'"
+                );
+                checks.push(quote! {
+                    assert!(!#fg::has_child(<#ty as #fg::FieldGroup>::CHILDREN, #name), #msg);
+                });
+            }
+        }
+
+        for (i, a) in groups.iter().enumerate() {
+            for b in &groups[i + 1..] {
+                let (a_ty, b_ty) = (&a.base.inner_type, &b.base.inner_type);
+                let msg = format!(
+                    "The flattened `{}` and `{}` of `{struct_name}` declare the same properties or children",
+                    type_name(a),
+                    type_name(b)
+                );
+                checks.push(quote! {
+                    assert!(
+                        !#fg::props_overlap(<#a_ty as #fg::FieldGroup>::PROPS, <#b_ty as #fg::FieldGroup>::PROPS)
+                            && !#fg::children_overlap(<#a_ty as #fg::FieldGroup>::CHILDREN, <#b_ty as #fg::FieldGroup>::CHILDREN),
+                        #msg
+                    );
+                });
+            }
+        }
+
+        quote! {
+            const _: () = {
+                #(#checks)*
+            };
+        }
+    }
+
     pub fn gen_duplicate_node_check(
         &self,
         node_name_expr: &TokenStream,
@@ -151,7 +241,7 @@ impl<'a> ValidationGenerator<'a> {
             if #nodes_var.len() > 1 {
                  let msg = format!("Node '{}' cannot be repeated", #node_name_expr);
 
-                 #helpers::push_custom(&mut __errors, msg, None, #nodes_var[1].current_span(), #nodes_var[1].source());
+                 #helpers::push_custom(&mut __errors, msg, None, #nodes_var[1].current_span(), &#nodes_var[1]);
             }
         }
     }
@@ -18,6 +18,10 @@ impl<'a> SchemaGenerator<'a> {
     pub fn generate(&self) -> TokenStream {
         let struct_name = &self.model.struct_name;
 
+        if self.model.is_field_group {
+            return self.gen_group_schema();
+        }
+
         let body = match &self.model.kind {
             NodeModelKind::Struct => self.gen_struct_schema(),
             NodeModelKind::Enum(variants) => self.gen_enum_schema(variants),
@@ -46,6 +50,7 @@ impl<'a> SchemaGenerator<'a> {
         let args = self.gen_args(&self.model.args);
         let props = self.gen_props(&self.model.props);
         let children = self.gen_children_block(&self.model.block);
+        let (props, children) = self.merge_groups(props, children);
         let docs = &self.model.docs;
 
         quote! {
@@ -79,6 +84,84 @@ impl<'a> SchemaGenerator<'a> {
         }
     }
 
+    /// The `GroupSchema` of a `#[node(field_group)]` struct.
+    fn gen_group_schema(&self) -> TokenStream {
+        let struct_name = &self.model.struct_name;
+        let props = self.gen_props(&self.model.props);
+        let rules: Vec<_> = match &self.model.block {
+            BlockSpec::Strict(children) => children
+                .iter()
+                .map(|child| self.gen_child_schema_call(child))
+                .collect(),
+            _ => vec![],
+        };
+
+        quote! {
+            impl crate::kdl::parser::field_group::GroupSchema for #struct_name {
+                fn props(ctx: &mut crate::kdl::schema::schema_context::SchemaContext) -> Vec<crate::kdl::schema::definitions::PropSchema> {
+                    #props
+                }
+
+                fn children(ctx: &mut crate::kdl::schema::schema_context::SchemaContext) -> Vec<crate::kdl::schema::definitions::NodeSchema> {
+                    let mut list = Vec::new();
+                    #(
+                        list.extend(#rules);
+                    )*
+                    list
+                }
+            }
+        }
+    }
+
+    /// Adds the props and children of the field groups embedded with `#[node(flatten)]`.
+    fn merge_groups(
+        &self,
+        props: TokenStream,
+        children: TokenStream,
+    ) -> (TokenStream, TokenStream) {
+        if self.model.groups.is_empty() {
+            return (props, children);
+        }
+
+        let group_types: Vec<_> = self
+            .model
+            .groups
+            .iter()
+            .map(|g| &g.base.inner_type)
+            .collect();
+
+        let props = quote! {
+            {
+                let mut props = #props;
+                #(
+                    props.extend(<#group_types as crate::kdl::parser::field_group::GroupSchema>::props(ctx));
+                )*
+                props
+            }
+        };
+
+        let children = quote! {
+            {
+                let mut group_children = Vec::new();
+                #(
+                    group_children.extend(<#group_types as crate::kdl::parser::field_group::GroupSchema>::children(ctx));
+                )*
+                match #children {
+                    crate::kdl::schema::definitions::ChildrenSchema::Fixed(mut list) => {
+                        list.extend(group_children);
+                        crate::kdl::schema::definitions::ChildrenSchema::Fixed(list)
+                    }
+                    crate::kdl::schema::definitions::ChildrenSchema::None if !group_children.is_empty() => {
+                        crate::kdl::schema::definitions::ChildrenSchema::Fixed(group_children)
+                    }
+                    other => other,
+                }
+            }
+        };
+
+        (props, children)
+    }
+
     fn gen_enum_schema(&self, variants: &[VariantSpec]) -> TokenStream {
         let schemas = variants.iter().map(|v| {
             let docs = &v.docs;
@@ -122,8 +205,14 @@ impl<'a> SchemaGenerator<'a> {
                     }
                 }
                 VariantFields::Struct { props, args, block, node_name, .. } => {
+                    // The variants of a named enum are forms of the same node.
+                    let enum_name = self
+                        .model
+                        .kdl_name
+                        .as_deref()
+                        .filter(|_| self.model.explicit_name);
                     let matcher = self.gen_matcher(
-                        v.kdl_name.as_deref().or(Some(&v.ident.to_string())),
+                        v.kdl_name.as_deref().or(enum_name).or(Some(&v.ident.to_string())),
                         node_name.as_ref().map(|n| &n.base)
                     );
                     let args_gen = self.gen_args(args);
@@ -41,3 +41,11 @@ pub fn gen_value_parser(ty: &Type, opts: &ParseOptions) -> TokenStream {
         _ => quote!(v.parse_as()?),
     }
 }
+
+/// `value` as an `Option<&str>` expression.
+pub fn opt_str(value: &Option<String>) -> TokenStream {
+    match value {
+        Some(value) => quote!(Some(#value)),
+        None => quote!(None),
+    }
+}
@@ -16,6 +16,11 @@ pub struct NodeModel {
     pub all_args_field: Option<BaseField>,
     pub is_root: bool,
     pub ignore_unknown: bool,
+    /// Field groups embedded with `#[node(flatten)]`.
+    pub groups: Vec<GroupSpec>,
+    /// Derives `FieldGroup` rather than a parser of its own, see `#[node(field_group)]`.
+    pub is_field_group: bool,
+    pub deprecation: Deprecation,
 }
 
 pub struct BaseField {
@@ -25,6 +30,11 @@ pub struct BaseField {
     pub opts: ParseOptions,
     pub docs: DocTokens,
 }
+
+/// A field group, whose props and children are parsed as if declared in place.
+pub struct GroupSpec {
+    pub base: BaseField,
+}
 pub struct NameSpec {
     pub base: BaseField,
 }
@@ -34,6 +44,14 @@ pub struct PropSpec {
     pub key: String,
     pub primitive_kind: TokenStream,
     pub required: bool,
+    pub deprecation: Deprecation,
+}
+
+impl PropSpec {
+    /// The key of the prop, then its old name if it has one.
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        std::iter::once(&self.key).chain(&self.deprecation.alias)
+    }
 }
 
 pub struct ArgSpec {
@@ -86,6 +104,20 @@ pub enum ChildMode {
     Field,
 }
 
+/// Where a map-typed `dynamic_child` takes the key of each child from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MapKey {
+    NodeName,
+    FirstArg,
+}
+
+/// A `dynamic_child` collected into a `HashMap<String, T>` or `BTreeMap<String, T>`.
+#[derive(Clone)]
+pub struct MapSpec {
+    pub ty: Type,
+    pub key: MapKey,
+}
+
 pub struct ChildSpec {
     pub base: BaseField,
     pub multiplicity: TokenStream,
@@ -93,6 +125,22 @@ pub struct ChildSpec {
     pub group: Option<String>,
     pub mode: ChildMode,
     pub name: Option<String>,
+    pub deprecation: Deprecation,
+}
+
+/// The old name a prop, child or node is still parsed under, from `alias`, and
+/// the note of `deprecated`. Using the old name is a warning; with a note and no
+/// alias, using the prop, child or node at all is.
+#[derive(Clone, Default)]
+pub struct Deprecation {
+    pub alias: Option<String>,
+    pub note: Option<String>,
+}
+
+impl Deprecation {
+    pub fn is_set(&self) -> bool {
+        self.alias.is_some() || self.note.is_some()
+    }
 }
 
 #[allow(clippy::large_enum_variant)]
@@ -104,6 +152,7 @@ pub enum BlockSpec {
         inner_type: Type,
         opts: ParseOptions,
         docs: DocTokens,
+        map: Option<MapSpec>,
     },
 }
 
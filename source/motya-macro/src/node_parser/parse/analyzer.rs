@@ -20,11 +20,25 @@ pub struct TypeInfo {
     pub is_vec: bool,
     pub is_option: bool,
     pub primitive_kind: TokenStream,
+    /// The key type of a `HashMap` or `BTreeMap` `dynamic_child`, whose value type is `inner`.
+    pub map_key: Option<Type>,
 }
 
 impl AnalyzedField {
     pub fn new(original: Field, attrs: NodeFieldAttrs) -> syn::Result<Self> {
-        let (inner, is_vec, is_option) = TypeAnalyzer::analyze(&original.ty);
+        // `all_props` collects into a map too, but of values rather than children.
+        let map = attrs
+            .dynamic_child
+            .then(|| TypeAnalyzer::analyze_map(&original.ty))
+            .flatten();
+
+        let (inner, is_vec, is_option, map_key) = match map {
+            Some((key, value)) => (value, false, false, Some(key)),
+            None => {
+                let (inner, is_vec, is_option) = TypeAnalyzer::analyze(&original.ty);
+                (inner, is_vec, is_option, None)
+            }
+        };
         let primitive_kind = TypeAnalyzer::to_primitive(&inner);
 
         let default_expr = attrs.default.clone();
@@ -58,6 +72,7 @@ impl AnalyzedField {
                 is_vec,
                 is_option,
                 primitive_kind,
+                map_key,
             },
             parse_opts,
             docs,
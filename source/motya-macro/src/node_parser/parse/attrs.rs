@@ -18,6 +18,15 @@ pub struct NodeStructAttrs {
 
     #[darling(default)]
     pub ignore_unknown: bool,
+
+    #[darling(default)]
+    pub field_group: bool,
+
+    #[darling(default)]
+    pub alias: Option<String>,
+
+    #[darling(default)]
+    pub deprecated: Option<String>,
 }
 
 #[derive(FromField)]
@@ -73,6 +82,15 @@ pub struct NodeFieldAttrs {
 
     #[darling(default)]
     pub max: Option<usize>,
+
+    #[darling(default)]
+    pub key: Option<String>,
+
+    #[darling(default)]
+    pub alias: Option<String>,
+
+    #[darling(default)]
+    pub deprecated: Option<String>,
 }
 
 #[derive(FromVariant)]
@@ -11,8 +11,8 @@ use registry::FieldRegistry;
 
 use crate::node_parser::{
     model::{
-        ArgSpec, BaseField, BlockSpec, NameSpec, NodeModel, NodeModelKind, PropSpec, VariantFields,
-        VariantSpec,
+        ArgSpec, BaseField, BlockSpec, Deprecation, GroupSpec, NameSpec, NodeModel, NodeModelKind,
+        PropSpec, VariantFields, VariantSpec,
     },
     parse::attrs::NodeVariantAttrs,
     utils::DocParser,
@@ -28,11 +28,41 @@ pub fn parse(input: DeriveInput) -> Result<NodeModel> {
     let allow_empty_block = struct_attrs.allow_empty;
     let docs = DocParser::parse(&input.attrs);
 
+    let deprecation = Deprecation {
+        alias: struct_attrs.alias,
+        note: struct_attrs.deprecated,
+    };
+    if deprecation.is_set()
+        && (kdl_name.is_none()
+            || struct_attrs.field_group
+            || !matches!(input.data, syn::Data::Struct(_)))
+    {
+        return Err(syn::Error::new(
+            struct_name.span(),
+            "`alias` and `deprecated` only apply to structs with a `name`",
+        ));
+    }
+
     match input.data {
         syn::Data::Struct(data) => {
-            let (props, args, block, node_name, all_props, all_args) =
+            let (props, args, block, node_name, all_props, all_args, groups) =
                 parse_fields_batch(data.fields, &struct_name)?;
 
+            let is_field_group = struct_attrs.field_group;
+            if is_field_group
+                && (!args.is_empty()
+                    || node_name.is_some()
+                    || all_props.is_some()
+                    || all_args.is_some()
+                    || !groups.is_empty()
+                    || matches!(block, BlockSpec::Dynamic { .. }))
+            {
+                return Err(syn::Error::new(
+                    struct_name.span(),
+                    "A #[node(field_group)] can only have props and children",
+                ));
+            }
+
             Ok(NodeModel {
                 struct_name,
                 kdl_name,
@@ -48,6 +78,9 @@ pub fn parse(input: DeriveInput) -> Result<NodeModel> {
                 kind: NodeModelKind::Struct,
                 ignore_unknown: struct_attrs.ignore_unknown,
                 is_root: struct_attrs.root.unwrap_or(false),
+                groups,
+                is_field_group,
+                deprecation,
             })
         }
         syn::Data::Enum(data) => {
@@ -80,6 +113,9 @@ pub fn parse(input: DeriveInput) -> Result<NodeModel> {
                 is_root: false,
                 ignore_unknown: struct_attrs.ignore_unknown,
                 kind: NodeModelKind::Enum(variants),
+                groups: vec![],
+                is_field_group: false,
+                deprecation,
             })
         }
         syn::Data::Union(_) => Err(syn::Error::new(
@@ -99,6 +135,7 @@ fn parse_fields_batch(
     Option<NameSpec>,
     Option<BaseField>,
     Option<BaseField>,
+    Vec<GroupSpec>,
 )> {
     let mut registry = FieldRegistry::default();
     let mut errors = Vec::new();
@@ -130,6 +167,16 @@ fn parse_fields_batch(
     Ok(registry.finalize(span_source)?)
 }
 
+fn reject_groups(groups: &[GroupSpec]) -> std::result::Result<(), darling::Error> {
+    match groups.first() {
+        Some(group) => Err(darling::Error::custom(
+            "#[node(flatten)] field groups are not supported in enum variants",
+        )
+        .with_span(&group.base.ident)),
+        None => Ok(()),
+    }
+}
+
 fn parse_variant(variant: syn::Variant) -> std::result::Result<VariantSpec, darling::Error> {
     let attrs = NodeVariantAttrs::from_variant(&variant)?;
 
@@ -158,8 +205,9 @@ fn parse_variant(variant: syn::Variant) -> std::result::Result<VariantSpec, darl
                     f
                 });
 
-                let (props, args, block, node_name, all_props, all_args) =
+                let (props, args, block, node_name, all_props, all_args, groups) =
                     parse_fields_batch(synthetic_fields, &ident).map_err(darling::Error::from)?;
+                reject_groups(&groups)?;
 
                 VariantFields::Struct {
                     props,
@@ -174,8 +222,9 @@ fn parse_variant(variant: syn::Variant) -> std::result::Result<VariantSpec, darl
         }
 
         syn::Fields::Named(fields) => {
-            let (props, args, block, node_name, all_props, all_args) =
+            let (props, args, block, node_name, all_props, all_args, groups) =
                 parse_fields_batch(fields.named, &ident).map_err(darling::Error::from)?;
+            reject_groups(&groups)?;
 
             VariantFields::Struct {
                 props,
@@ -8,11 +8,18 @@ pub struct FieldRegistry {
     props: Vec<PropSpec>,
     args: Vec<ArgSpec>,
     strict_children: Vec<ChildSpec>,
-    dynamic_child: Option<(syn::Ident, syn::Type, ParseOptions, DocTokens)>,
+    dynamic_child: Option<(
+        syn::Ident,
+        syn::Type,
+        ParseOptions,
+        DocTokens,
+        Option<MapSpec>,
+    )>,
 
     node_name: Option<NameSpec>,
     all_props: Option<BaseField>,
     all_args: Option<BaseField>,
+    groups: Vec<GroupSpec>,
 
     errors: Vec<DarlingError>,
 }
@@ -46,7 +53,20 @@ impl FieldRegistry {
             return;
         }
 
+        if Self::deprecation(&field).is_set()
+            && (!(field.attrs.prop || field.attrs.child) || field.attrs.flatten)
+        {
+            self.errors.push(
+                DarlingError::custom("`alias` and `deprecated` only apply to props and children")
+                    .with_span(field.ident()),
+            );
+            return;
+        }
+
         if active_roles.is_empty() {
+            if field.attrs.flatten {
+                self.add_group(field);
+            }
             return;
         }
 
@@ -77,6 +97,13 @@ impl FieldRegistry {
         }
     }
 
+    fn deprecation(f: &AnalyzedField) -> Deprecation {
+        Deprecation {
+            alias: f.attrs.alias.clone(),
+            note: f.attrs.deprecated.clone(),
+        }
+    }
+
     fn add_prop(&mut self, f: AnalyzedField) {
         let key = f
             .attrs
@@ -91,6 +118,7 @@ impl FieldRegistry {
             key,
             primitive_kind: f.type_info.primitive_kind,
             required,
+            deprecation: Self::deprecation(&f),
         });
     }
 
@@ -128,6 +156,7 @@ impl FieldRegistry {
             is_vec,
             group: f.attrs.group.clone(),
             mode,
+            deprecation: Self::deprecation(&f),
             name: f.attrs.name,
         });
     }
@@ -139,14 +168,67 @@ impl FieldRegistry {
             );
             return;
         }
+
+        let map = match (&f.type_info.map_key, f.attrs.key.as_deref()) {
+            (None, None) => None,
+            (None, Some(_)) => {
+                self.errors.push(
+                    DarlingError::custom("`key` only applies to HashMap and BTreeMap fields")
+                        .with_span(f.ident()),
+                );
+                return;
+            }
+            (Some(key_type), key) => {
+                if quote::quote!(#key_type).to_string() != "String" {
+                    self.errors.push(
+                        DarlingError::custom("Map children must be keyed by String")
+                            .with_span(key_type),
+                    );
+                    return;
+                }
+                let key = match key {
+                    None | Some("name") => MapKey::NodeName,
+                    Some("arg") => MapKey::FirstArg,
+                    Some(other) => {
+                        self.errors.push(
+                            DarlingError::custom(format!(
+                                "Unknown key '{other}', expected \"name\" or \"arg\""
+                            ))
+                            .with_span(f.ident()),
+                        );
+                        return;
+                    }
+                };
+                Some(MapSpec {
+                    ty: f.original.ty.clone(),
+                    key,
+                })
+            }
+        };
+
         self.dynamic_child = Some((
             f.ident().clone(),
             f.type_info.inner.clone(),
             f.parse_opts,
             f.docs,
+            map,
         ));
     }
 
+    fn add_group(&mut self, f: AnalyzedField) {
+        if f.type_info.is_option || f.type_info.is_vec {
+            self.errors.push(
+                DarlingError::custom("A flattened field group cannot be an Option or a Vec")
+                    .with_span(f.ident()),
+            );
+            return;
+        }
+
+        self.groups.push(GroupSpec {
+            base: Self::base_field(&f),
+        });
+    }
+
     fn add_node_name(&mut self, f: AnalyzedField) {
         if self.node_name.is_some() {
             self.errors
@@ -189,6 +271,7 @@ impl FieldRegistry {
             Option<NameSpec>,
             Option<BaseField>,
             Option<BaseField>,
+            Vec<GroupSpec>,
         ),
         DarlingError,
     > {
@@ -197,11 +280,18 @@ impl FieldRegistry {
         }
 
         let block = match (self.dynamic_child, self.strict_children.is_empty()) {
-            (Some((ident, ty, opts, docs)), true) => BlockSpec::Dynamic {
+            (Some(_), true) if !self.groups.is_empty() => {
+                return Err(DarlingError::custom(
+                    "Cannot mix #[node(flatten)] and #[node(dynamic_child)]",
+                )
+                .with_span(struct_span));
+            }
+            (Some((ident, ty, opts, docs, map)), true) => BlockSpec::Dynamic {
                 field_ident: ident,
                 inner_type: ty,
                 opts,
                 docs,
+                map,
             },
             (Some(_), false) => {
                 return Err(DarlingError::custom(
@@ -220,6 +310,7 @@ impl FieldRegistry {
             self.node_name,
             self.all_props,
             self.all_args,
+            self.groups,
         ))
     }
 }
@@ -19,6 +19,29 @@ impl TypeAnalyzer {
         (ty.clone(), false, false)
     }
 
+    /// Splits `HashMap<K, V>` and `BTreeMap<K, V>` into their key and value types.
+    pub fn analyze_map(ty: &Type) -> Option<(Type, Type)> {
+        let Type::Path(tp) = ty else {
+            return None;
+        };
+        let seg = tp.path.segments.last()?;
+        if seg.ident != "HashMap" && seg.ident != "BTreeMap" {
+            return None;
+        }
+        let syn::PathArguments::AngleBracketed(args) = &seg.arguments else {
+            return None;
+        };
+
+        let mut types = args.args.iter().filter_map(|arg| match arg {
+            syn::GenericArgument::Type(ty) => Some(ty.clone()),
+            _ => None,
+        });
+        match (types.next(), types.next(), types.next()) {
+            (Some(key), Some(value), None) => Some((key, value)),
+            _ => None,
+        }
+    }
+
     fn extract(seg: &syn::PathSegment) -> Option<Type> {
         if let syn::PathArguments::AngleBracketed(args) = &seg.arguments
             && let Some(syn::GenericArgument::Type(inner)) = args.args.first()
@@ -0,0 +1,114 @@
+//! Load time of a config spread over many include files.
+//!
+//! The entry file imports 500 files with an upstream group each and routes one
+//! section to every group. Parsed files are cached by content, so `unchanged`
+//! measures a reload of the same tree and `one_file_changed` a reload after an
+//! edit to a single include.
+
+use std::{fs, path::Path};
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use motya::fs_adapter::TokioFs;
+use motya_config::{
+    common_types::definitions_table::DefinitionsTable,
+    kdl::fs_loader::FileCollector,
+    loader::{ConfigLoader, FileConfigLoaderProvider},
+};
+use tempfile::TempDir;
+
+const FILES: usize = 500;
+
+fn group_file(idx: usize, port: usize) -> String {
+    format!(
+        r#"definitions {{
+    upstream-groups {{
+        group "g{idx}" {{
+            server "10.0.{}.{}:{port}" weight=2
+            server "10.1.{}.{}:{port}"
+        }}
+    }}
+}}
+"#,
+        idx / 256,
+        idx % 256,
+        idx / 256,
+        idx % 256,
+    )
+}
+
+fn write_config(dir: &Path) {
+    fs::create_dir(dir.join("groups")).unwrap();
+
+    let mut imports = String::new();
+    let mut sections = String::new();
+    for idx in 0..FILES {
+        fs::write(
+            dir.join(format!("groups/g{idx}.kdl")),
+            group_file(idx, 8080),
+        )
+        .unwrap();
+
+        imports.push_str(&format!("    \"groups/g{idx}.kdl\"\n"));
+        sections.push_str(&format!(
+            "            section \"/g{idx}\" as=\"prefix\" {{\n                proxy use-group=\"g{idx}\"\n            }}\n"
+        ));
+    }
+
+    let main = format!(
+        r#"imports {{
+{imports}}}
+
+services {{
+    Api {{
+        listeners {{
+            "127.0.0.1:8080"
+        }}
+        connectors {{
+{sections}        }}
+    }}
+}}
+"#
+    );
+    fs::write(dir.join("main.kdl"), main).unwrap();
+}
+
+async fn load(dir: &Path) {
+    let loader = ConfigLoader::new(FileCollector::<TokioFs>::default());
+    let mut table = DefinitionsTable::new_with_global();
+
+    let config = loader
+        .load_entry_point(Some(dir.join("main.kdl")), &mut table)
+        .await
+        .expect("the synthetic config is valid");
+    assert!(config.is_some());
+}
+
+fn bench_config_loader(c: &mut Criterion) {
+    let dir = TempDir::new().unwrap();
+    write_config(dir.path());
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let mut group = c.benchmark_group("config_loader");
+    group.sample_size(20);
+
+    group.bench_function("unchanged", |b| {
+        b.to_async(&runtime).iter(|| load(dir.path()));
+    });
+
+    let mut port = 8080;
+    group.bench_function("one_file_changed", |b| {
+        b.to_async(&runtime).iter_batched(
+            || {
+                port += 1;
+                fs::write(dir.path().join("groups/g0.kdl"), group_file(0, port)).unwrap();
+            },
+            |()| load(dir.path()),
+            BatchSize::PerIteration,
+        );
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_config_loader);
+criterion_main!(benches);
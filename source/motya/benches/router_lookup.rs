@@ -0,0 +1,143 @@
+//! Route lookups of the proxy.
+//!
+//! `router_lookup` compares the lookups of one request before and after the route
+//! is kept in the context. The router holds 1000 `prefix` sections and 20 `regex`
+//! sections, so a path that ends up on a prefix route is first tried against every
+//! pattern. `per_phase` matches the path in each of the three phases that need the
+//! route, as the proxy used to; `resolved_once` matches it once and looks the
+//! route up by its index afterwards.
+//!
+//! `prefix_routes` compares the longest-prefix lookup among 10k `prefix` sections
+//! in the segment tree of the router with the `matchit` catch-all routes it
+//! replaced, for a path under a deep route and for one that only the root takes.
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use http::Method;
+use matchit::Router;
+use motya::proxy::{
+    metrics,
+    upstream_router::{UpstreamContext, UpstreamRouter},
+};
+use motya_config::common_types::connectors::{
+    HttpPeerConfig, RouteConditions, RouteMatcher, RoutePattern, UpstreamConfig, ALPN,
+};
+
+const PREFIX_ROUTES: usize = 1000;
+const REGEX_ROUTES: usize = 20;
+const MANY_PREFIX_ROUTES: usize = 10_000;
+/// The phases that look the route up: request, upstream request and upstream
+/// response.
+const PHASES: usize = 3;
+
+fn route(path: &str, matcher: RouteMatcher) -> UpstreamContext {
+    let upstream = UpstreamConfig::Service(HttpPeerConfig {
+        peer_address: "127.0.0.1:8000".parse().unwrap(),
+        alpn: ALPN::H1,
+        tls: false,
+        sni: String::new(),
+        prefix_path: path.parse().unwrap(),
+        matcher,
+    });
+
+    UpstreamContext {
+        metrics: metrics::upstream(&upstream),
+        upstream,
+        chains: vec![],
+        balancer: None,
+        compression: None,
+        sse: None,
+        cache: None,
+        retry: None,
+        mirror: None,
+        timeout: None,
+        allow_upgrades: true,
+        grpc: false,
+        upstream_tls: None,
+        h2: None,
+        debug_trace: false,
+        conditions: RouteConditions::default(),
+        rewrite: None,
+    }
+}
+
+fn router() -> UpstreamRouter<UpstreamContext> {
+    let mut routes = Vec::new();
+    for idx in 0..REGEX_ROUTES {
+        let pattern = format!("/files/{idx}/[a-z]+\\.png");
+        let matcher = RouteMatcher::Regex(RoutePattern::new(&pattern).unwrap());
+        routes.push(route(&format!("/files/{idx}"), matcher));
+    }
+    for idx in 0..PREFIX_ROUTES {
+        routes.push(route(&format!("/api/v{idx}"), RouteMatcher::Prefix));
+    }
+
+    UpstreamRouter::build(routes).unwrap()
+}
+
+fn bench_router_lookup(c: &mut Criterion) {
+    let router = router();
+    let path = "/api/v500/users/42/orders";
+    let mut group = c.benchmark_group("router_lookup");
+
+    group.bench_function("per_phase", |b| {
+        b.iter(|| {
+            for _ in 0..PHASES {
+                black_box(router.get_upstream_by_path(black_box(path)));
+            }
+        });
+    });
+
+    group.bench_function("resolved_once", |b| {
+        b.iter(|| {
+            let route = router.route(&Method::GET, black_box(path), None);
+            for _ in 0..PHASES {
+                black_box(router.get_upstream(route));
+            }
+        });
+    });
+
+    group.finish();
+}
+
+fn bench_prefix_routes(c: &mut Criterion) {
+    // Two levels, so that lookups pass through a node with many children.
+    let prefixes: Vec<String> = (0..MANY_PREFIX_ROUTES)
+        .map(|idx| format!("/tenants/t{}/apps/a{}", idx / 100, idx % 100))
+        .chain(["/".to_string()])
+        .collect();
+
+    let router = UpstreamRouter::build(
+        prefixes
+            .iter()
+            .map(|prefix| route(prefix, RouteMatcher::Prefix))
+            .collect(),
+    )
+    .unwrap();
+
+    let mut catch_all = Router::new();
+    for (idx, prefix) in prefixes.iter().enumerate() {
+        let prefix = prefix.trim_end_matches('/');
+        catch_all
+            .insert(format!("{prefix}/{{*catch_all}}"), idx)
+            .unwrap();
+    }
+
+    let mut group = c.benchmark_group("prefix_routes");
+    for (name, path) in [
+        ("deep", "/tenants/t57/apps/a42/v1/users/42/orders"),
+        ("root", "/static/css/site.css"),
+    ] {
+        group.bench_function(format!("matchit_catch_all/{name}"), |b| {
+            b.iter(|| black_box(catch_all.at(black_box(path)).ok().map(|v| *v.value)));
+        });
+        group.bench_function(format!("prefix_trie/{name}"), |b| {
+            b.iter(|| black_box(router.route(&Method::GET, black_box(path), None)));
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_router_lookup, bench_prefix_routes);
+criterion_main!(benches);
@@ -0,0 +1,212 @@
+//! Access control for admin routes: a client network allowlist plus an optional
+//! bearer token read from the environment at startup.
+
+use std::{fmt, net::IpAddr};
+
+use cidr::IpCidr;
+use motya_config::common_types::admin::AdminConfig;
+
+use crate::proxy::credentials::constant_time_eq;
+
+/// Log target of the admin audit trail, so it can be routed separately from the
+/// proxy logs with `RUST_LOG=motya::admin::audit=info`.
+pub const AUDIT_TARGET: &str = "motya::admin::audit";
+
+#[derive(Debug, Clone)]
+pub struct AdminAccess {
+    allow: Vec<IpCidr>,
+    token: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Denied {
+    AddressNotAllowed,
+    MissingToken,
+    InvalidToken,
+}
+
+impl Denied {
+    /// Status code to answer a rejected admin request with.
+    pub fn status(self) -> u16 {
+        match self {
+            Denied::AddressNotAllowed => 403,
+            Denied::MissingToken | Denied::InvalidToken => 401,
+        }
+    }
+}
+
+impl fmt::Display for Denied {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Denied::AddressNotAllowed => "client address is not in the admin allowlist",
+            Denied::MissingToken => "missing bearer token",
+            Denied::InvalidToken => "invalid bearer token",
+        })
+    }
+}
+
+impl AdminAccess {
+    /// Resolves the token variable named by `auth-token-env`.
+    ///
+    /// Fails when the variable is configured but unset or empty: starting with an
+    /// admin API that silently requires no token would be worse than not starting.
+    pub fn from_config(config: &AdminConfig) -> miette::Result<Self> {
+        let token = match &config.auth_token_env {
+            Some(var) => match std::env::var(var) {
+                Ok(token) if !token.is_empty() => Some(token),
+                _ => {
+                    return Err(miette::miette!(
+                        "'admin.allow' requires a token from the environment variable '{var}', but it is not set"
+                    ))
+                }
+            },
+            None => None,
+        };
+
+        Ok(Self {
+            allow: config.allow.clone(),
+            token,
+        })
+    }
+
+    /// Checks an admin request before any routing happens.
+    ///
+    /// `client` is `None` for clients of a Unix admin socket, where file permissions
+    /// take the place of the address allowlist; the token is still required.
+    /// `authorization` is the raw `Authorization` header value.
+    pub fn check(&self, client: Option<IpAddr>, authorization: Option<&str>) -> Result<(), Denied> {
+        if let Some(addr) = client {
+            // Dual-stack listeners see IPv4 clients as `::ffff:a.b.c.d`.
+            let addr = match addr {
+                IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(addr, IpAddr::V4),
+                v4 => v4,
+            };
+            if !self.allow.iter().any(|net| net.contains(&addr)) {
+                return Err(Denied::AddressNotAllowed);
+            }
+        }
+
+        let Some(expected) = &self.token else {
+            return Ok(());
+        };
+
+        let presented = authorization
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim)
+            .ok_or(Denied::MissingToken)?;
+
+        if constant_time_eq(presented.as_bytes(), expected.as_bytes()) {
+            Ok(())
+        } else {
+            Err(Denied::InvalidToken)
+        }
+    }
+}
+
+/// Records an admin action and whether it was let through.
+pub fn audit(client: Option<IpAddr>, action: &str, outcome: Result<(), Denied>) {
+    let client = client
+        .map(|addr| addr.to_string())
+        .unwrap_or_else(|| "<unix socket>".into());
+
+    match outcome {
+        Ok(()) => tracing::info!(target: AUDIT_TARGET, %client, action, "admin action allowed"),
+        Err(reason) => {
+            tracing::warn!(target: AUDIT_TARGET, %client, action, %reason, "admin action denied")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn access(allow: &[&str], token: Option<&str>) -> AdminAccess {
+        AdminAccess {
+            allow: allow.iter().map(|net| net.parse().unwrap()).collect(),
+            token: token.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_allowlist() {
+        let access = access(&["127.0.0.1/32", "10.0.0.0/8"], None);
+
+        assert_eq!(
+            access.check(Some("127.0.0.1".parse().unwrap()), None),
+            Ok(())
+        );
+        assert_eq!(
+            access.check(Some("10.20.30.40".parse().unwrap()), None),
+            Ok(())
+        );
+        assert_eq!(
+            access.check(Some("192.168.1.1".parse().unwrap()), None),
+            Err(Denied::AddressNotAllowed)
+        );
+        assert_eq!(access.check(None, None), Ok(()));
+    }
+
+    #[test]
+    fn test_default_allows_only_loopback() {
+        let access = AdminAccess::from_config(&AdminConfig::default()).unwrap();
+
+        assert_eq!(
+            access.check(Some("127.0.0.1".parse().unwrap()), None),
+            Ok(())
+        );
+        assert_eq!(access.check(Some("::1".parse().unwrap()), None), Ok(()));
+        assert_eq!(
+            access.check(Some("10.0.0.1".parse().unwrap()), None),
+            Err(Denied::AddressNotAllowed)
+        );
+    }
+
+    #[test]
+    fn test_ipv4_mapped_clients() {
+        let access = AdminAccess::from_config(&AdminConfig::default()).unwrap();
+
+        // An IPv4 client of a `[::]` listener.
+        assert_eq!(
+            access.check(Some("::ffff:127.0.0.1".parse().unwrap()), None),
+            Ok(())
+        );
+        assert_eq!(
+            access.check(Some("::ffff:10.0.0.1".parse().unwrap()), None),
+            Err(Denied::AddressNotAllowed)
+        );
+    }
+
+    #[test]
+    fn test_bearer_token() {
+        let access = access(&["127.0.0.0/8"], Some("s3cret"));
+        let local = Some("127.0.0.1".parse().unwrap());
+
+        assert_eq!(access.check(local, Some("Bearer s3cret")), Ok(()));
+        assert_eq!(access.check(None, Some("Bearer s3cret")), Ok(()));
+        assert_eq!(access.check(local, None), Err(Denied::MissingToken));
+        assert_eq!(
+            access.check(local, Some("Basic s3cret")),
+            Err(Denied::MissingToken)
+        );
+        assert_eq!(
+            access.check(local, Some("Bearer s3cre")),
+            Err(Denied::InvalidToken)
+        );
+        assert_eq!(
+            access.check(Some("8.8.8.8".parse().unwrap()), Some("Bearer s3cret")),
+            Err(Denied::AddressNotAllowed)
+        );
+    }
+
+    #[test]
+    fn test_missing_token_env_is_an_error() {
+        let config = AdminConfig {
+            auth_token_env: Some("MOTYA_TEST_ADMIN_TOKEN_UNSET".into()),
+            ..AdminConfig::default()
+        };
+
+        let err = AdminAccess::from_config(&config).unwrap_err();
+        assert!(err.to_string().contains("MOTYA_TEST_ADMIN_TOKEN_UNSET"));
+    }
+}
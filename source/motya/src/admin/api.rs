@@ -0,0 +1,449 @@
+//! The admin HTTP API.
+//!
+//! A small listener for operators: it reports what the process is serving right
+//! now, reading the routers from the same [`SharedProxyState`] the proxy services
+//! use, so a reload is visible here as soon as it is swapped in. The maintenance
+//! switches are the only thing it changes.
+//!
+//! - `/health`: `ok` while the process is up
+//! - `/config`: the resolved configuration, including the filter chains of every route
+//! - `/config-version`: the version hash of the resolved configuration
+//! - `/upstreams`: the routes of every proxy service and where they lead
+//! - `/metrics`: gauges, the service limits and the upstream request metrics in the
+//!   Prometheus text format
+//! - `/traces`: the last requests of routes with `debug-trace=#true`, oldest first
+//! - `/maintenance`: which proxy services are in maintenance, and why;
+//!   `POST /maintenance/<service>/on` and `/off` switch one
+
+use std::net::SocketAddr;
+
+use async_trait::async_trait;
+use http::{header, Method, Response, StatusCode};
+use motya_config::common_types::connectors::RouteMatcher;
+use pingora::{
+    apps::http_app::{HttpServer, ServeHttp},
+    protocols::http::ServerSession,
+    services::{listening::Service as ListeningService, Service},
+};
+
+use crate::{
+    admin::access::{audit, AdminAccess, AUDIT_TARGET},
+    proxy::{
+        config_version, filters, limits,
+        metrics::{self, escape_label},
+        panic_guard::describe_upstream,
+        rate_limiter, trace,
+        upstream_router::UpstreamContextTrait,
+        SharedProxyState,
+    },
+};
+
+const TEXT: &str = "text/plain; charset=utf-8";
+const PROMETHEUS_TEXT: &str = "text/plain; version=0.0.4";
+
+pub struct AdminApi {
+    access: AdminAccess,
+    proxies: Vec<(String, SharedProxyState)>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Reply {
+    status: StatusCode,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Reply {
+    fn text(status: StatusCode, body: impl Into<String>) -> Self {
+        Self {
+            status,
+            content_type: TEXT,
+            body: body.into(),
+        }
+    }
+
+    fn unpublished() -> Self {
+        Self::text(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "no configuration published yet\n",
+        )
+    }
+}
+
+/// Creates the admin listener on `addr`.
+pub fn admin_service(
+    addr: SocketAddr,
+    access: AdminAccess,
+    proxies: Vec<(String, SharedProxyState)>,
+) -> Box<dyn Service> {
+    let mut service = ListeningService::new(
+        "motya-admin".to_string(),
+        HttpServer::new_app(AdminApi { access, proxies }),
+    );
+    service.add_tcp(&addr.to_string());
+
+    Box::new(service)
+}
+
+#[async_trait]
+impl ServeHttp for AdminApi {
+    async fn response(&self, session: &mut ServerSession) -> Response<Vec<u8>> {
+        let req = session.req_header();
+        let action = format!("{} {}", req.method, req.uri.path());
+
+        let reply = match session.client_addr() {
+            Some(peer) => {
+                let client = peer.as_inet().map(|addr| addr.ip());
+                let authorization = req
+                    .headers
+                    .get(header::AUTHORIZATION)
+                    .and_then(|value| value.to_str().ok());
+
+                let outcome = self.access.check(client, authorization);
+                audit(client, &action, outcome);
+
+                match outcome {
+                    Ok(()) => self.handle(&req.method, req.uri.path()),
+                    Err(denied) => Reply::text(
+                        StatusCode::from_u16(denied.status())
+                            .expect("admin denial codes are valid"),
+                        format!("{denied}\n"),
+                    ),
+                }
+            }
+            None => {
+                tracing::warn!(target: AUDIT_TARGET, %action, "admin client address unknown");
+                Reply::text(StatusCode::FORBIDDEN, "client address unknown\n")
+            }
+        };
+
+        let body = reply.body.into_bytes();
+        Response::builder()
+            .status(reply.status)
+            .header(header::CONTENT_TYPE, reply.content_type)
+            .header(header::CONTENT_LENGTH, body.len())
+            .header(header::CACHE_CONTROL, "no-store")
+            .body(body)
+            .expect("admin response parts are valid")
+    }
+}
+
+impl AdminApi {
+    fn handle(&self, method: &Method, path: &str) -> Reply {
+        if method == Method::POST {
+            if let Some(switch) = path.strip_prefix("/maintenance/") {
+                return self.switch_maintenance(switch);
+            }
+        }
+        if method != Method::GET {
+            return Reply::text(
+                StatusCode::METHOD_NOT_ALLOWED,
+                "the admin API is read-only, except POST /maintenance/<service>/on|off\n",
+            );
+        }
+
+        match path {
+            "/health" => Reply::text(StatusCode::OK, "ok\n"),
+            "/config" => match config_version::active_config() {
+                Some(config) => Reply::text(StatusCode::OK, format!("{config:#?}\n")),
+                None => Reply::unpublished(),
+            },
+            "/config-version" => match config_version::active() {
+                Some(version) => Reply::text(StatusCode::OK, format!("{version}\n")),
+                None => Reply::unpublished(),
+            },
+            "/upstreams" => Reply::text(StatusCode::OK, self.upstreams()),
+            "/metrics" => Reply {
+                status: StatusCode::OK,
+                content_type: PROMETHEUS_TEXT,
+                body: self.metrics(),
+            },
+            "/traces" => Reply::text(StatusCode::OK, trace::recent().join("\n")),
+            "/maintenance" => Reply::text(StatusCode::OK, self.maintenance()),
+            _ => Reply::text(StatusCode::NOT_FOUND, "unknown admin route\n"),
+        }
+    }
+
+    fn maintenance(&self) -> String {
+        let mut out = String::new();
+
+        for (name, state) in &self.proxies {
+            let maintenance = &state.maintenance;
+            let mut reasons = vec![];
+            if maintenance.switched_on() {
+                reasons.push("switched on");
+            }
+            if maintenance.flagged() {
+                reasons.push("flag-file");
+            }
+
+            if reasons.is_empty() {
+                out.push_str(&format!("{name}: off\n"));
+            } else {
+                out.push_str(&format!("{name}: on ({})\n", reasons.join(", ")));
+            }
+        }
+
+        out
+    }
+
+    /// Handles `POST /maintenance/<service>/on` and `/off`, given `<service>/<on|off>`.
+    fn switch_maintenance(&self, switch: &str) -> Reply {
+        let on = match switch.rsplit_once('/') {
+            Some((service, "on")) => Some((service, true)),
+            Some((service, "off")) => Some((service, false)),
+            _ => None,
+        };
+        let Some((service, on)) = on else {
+            return Reply::text(
+                StatusCode::NOT_FOUND,
+                "expected /maintenance/<service>/on or /off\n",
+            );
+        };
+        let Some((_, state)) = self.proxies.iter().find(|(name, _)| name == service) else {
+            return Reply::text(
+                StatusCode::NOT_FOUND,
+                format!("no proxy service named '{service}'\n"),
+            );
+        };
+
+        state.maintenance.switch(on);
+        let body = if !on && state.maintenance.flagged() {
+            format!("{service}: still on, its flag-file exists\n")
+        } else {
+            format!("{service}: {}\n", if on { "on" } else { "off" })
+        };
+        Reply::text(StatusCode::OK, body)
+    }
+
+    fn upstreams(&self) -> String {
+        let mut out = String::new();
+
+        for (name, state) in &self.proxies {
+            let router = state.router.load();
+            out.push_str(&format!("{name}\n"));
+
+            for upstream in router.upstreams() {
+                let matcher = match upstream.get_route_type() {
+                    RouteMatcher::Exact => "exact",
+                    RouteMatcher::Prefix => "prefix",
+                    RouteMatcher::Regex(_) => "regex",
+                };
+
+                let mut extras = vec![];
+                if !upstream.chains.is_empty() {
+                    extras.push(format!("chains={}", upstream.chains.len()));
+                }
+                if upstream.balancer.is_some() {
+                    extras.push("load-balanced".to_string());
+                }
+                if upstream.compression.is_some() {
+                    extras.push("compression".to_string());
+                }
+                if upstream.sse.is_some() {
+                    extras.push("sse".to_string());
+                }
+                if let Some(cache) = &upstream.cache {
+                    extras.push(format!("cache={}", humantime::format_duration(cache.ttl)));
+                }
+                if upstream.debug_trace {
+                    extras.push("debug-trace".to_string());
+                }
+                if upstream.rewrite.is_some() {
+                    extras.push("rewrite".to_string());
+                }
+                if upstream.upstream_tls.is_some() {
+                    extras.push("tls".to_string());
+                }
+                if upstream.h2.is_some() {
+                    extras.push("h2".to_string());
+                }
+                if !upstream.conditions.is_empty() {
+                    extras.push(upstream.conditions.to_string());
+                }
+
+                out.push_str(&format!(
+                    "  {} ({matcher}) -> {}",
+                    upstream.get_prefix_path().path(),
+                    describe_upstream(&upstream.upstream)
+                ));
+                if !extras.is_empty() {
+                    out.push_str(&format!(" [{}]", extras.join(", ")));
+                }
+                out.push('\n');
+            }
+        }
+
+        out
+    }
+
+    fn metrics(&self) -> String {
+        let mut out = String::new();
+
+        if let Some(version) = config_version::active() {
+            out.push_str("# HELP motya_config_info Version of the configuration being served.\n");
+            out.push_str("# TYPE motya_config_info gauge\n");
+            out.push_str(&format!("motya_config_info{{version=\"{version}\"}} 1\n"));
+        }
+
+        out.push_str("# HELP motya_routes Number of routes of a proxy service.\n");
+        out.push_str("# TYPE motya_routes gauge\n");
+        for (name, state) in &self.proxies {
+            out.push_str(&format!(
+                "motya_routes{{service=\"{}\"}} {}\n",
+                escape_label(name),
+                state.router.load().upstreams().len()
+            ));
+        }
+
+        let evictions = rate_limiter::metrics::evictions();
+        if !evictions.is_empty() {
+            out.push_str(
+                "# HELP motya_rate_limit_evictions_total Rate limit keys evicted because their key table was full.\n",
+            );
+            out.push_str("# TYPE motya_rate_limit_evictions_total counter\n");
+            for (table, count) in evictions {
+                out.push_str(&format!(
+                    "motya_rate_limit_evictions_total{{table=\"{}\"}} {count}\n",
+                    escape_label(&table)
+                ));
+            }
+        }
+
+        limits::render(&mut out);
+        metrics::render(&mut out);
+        filters::metrics::render(&mut out);
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use motya_config::common_types::{
+        admin::AdminConfig, connectors::UpstreamConfig, simple_response_type::SimpleResponseConfig,
+    };
+
+    use super::*;
+    use crate::proxy::{
+        maintenance::Maintenance,
+        upstream_router::{UpstreamContext, UpstreamRouter},
+        ProxyState,
+    };
+
+    fn api() -> AdminApi {
+        let upstream = UpstreamConfig::Static(SimpleResponseConfig {
+            http_code: StatusCode::OK,
+            response_body: "pong".into(),
+            prefix_path: "/ping".parse().unwrap(),
+        });
+        let upstream = UpstreamContext {
+            metrics: metrics::upstream(&upstream),
+            upstream,
+            chains: vec![],
+            balancer: None,
+            compression: None,
+            sse: None,
+            cache: None,
+            retry: None,
+            mirror: None,
+            timeout: None,
+            allow_upgrades: true,
+            grpc: false,
+            upstream_tls: None,
+            h2: None,
+            debug_trace: false,
+            conditions: Default::default(),
+            rewrite: None,
+        };
+        let router = UpstreamRouter::build(vec![upstream]).unwrap();
+
+        AdminApi {
+            access: AdminAccess::from_config(&AdminConfig::default()).unwrap(),
+            proxies: vec![(
+                "Public".into(),
+                Arc::new(ProxyState::new(
+                    router,
+                    Maintenance::start("Public", Default::default()),
+                )),
+            )],
+        }
+    }
+
+    #[test]
+    fn test_health() {
+        assert_eq!(
+            api().handle(&Method::GET, "/health"),
+            Reply::text(StatusCode::OK, "ok\n")
+        );
+    }
+
+    #[test]
+    fn test_upstreams_lists_live_routes() {
+        let api = api();
+        let reply = api.handle(&Method::GET, "/upstreams");
+
+        assert_eq!(reply.status, StatusCode::OK);
+        assert_eq!(reply.body, "Public\n  /ping (exact) -> static(200)\n");
+
+        let (_, state) = &api.proxies[0];
+        state
+            .router
+            .store(Arc::new(UpstreamRouter::build(vec![]).unwrap()));
+
+        let reply = api.handle(&Method::GET, "/upstreams");
+        assert_eq!(reply.body, "Public\n");
+    }
+
+    #[test]
+    fn test_metrics_counts_routes() {
+        let reply = api().handle(&Method::GET, "/metrics");
+
+        assert_eq!(reply.content_type, PROMETHEUS_TEXT);
+        assert!(reply.body.contains("motya_routes{service=\"Public\"} 1\n"));
+    }
+
+    #[test]
+    fn test_maintenance_switch() {
+        let api = api();
+        assert_eq!(
+            api.handle(&Method::GET, "/maintenance").body,
+            "Public: off\n"
+        );
+
+        let reply = api.handle(&Method::POST, "/maintenance/Public/on");
+        assert_eq!(reply, Reply::text(StatusCode::OK, "Public: on\n"));
+        assert!(api.proxies[0].1.maintenance.holds("/ping"));
+        assert_eq!(
+            api.handle(&Method::GET, "/maintenance").body,
+            "Public: on (switched on)\n"
+        );
+
+        api.handle(&Method::POST, "/maintenance/Public/off");
+        assert!(!api.proxies[0].1.maintenance.is_on());
+
+        for path in ["/maintenance/Private/on", "/maintenance/Public/maybe"] {
+            assert_eq!(
+                api.handle(&Method::POST, path).status,
+                StatusCode::NOT_FOUND,
+                "{path}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_unknown_route_and_method() {
+        let api = api();
+
+        assert_eq!(
+            api.handle(&Method::GET, "/nope").status,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            api.handle(&Method::POST, "/health").status,
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+    }
+}
@@ -0,0 +1,7 @@
+//! Administrative endpoints of the running proxy.
+//!
+//! Every admin request goes through [`access::AdminAccess::check`] before it is
+//! routed, and the outcome is written to the audit log with [`access::audit`].
+
+pub mod access;
+pub mod api;
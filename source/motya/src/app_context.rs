@@ -1,11 +1,11 @@
-use std::{path::PathBuf, sync::Arc};
+use std::{path::PathBuf, sync::Arc, time::Duration};
 
 use motya_config::{
     cli::{
         builder::CliConfigBuilder,
         cli_struct::{Cli, Commands},
     },
-    common_types::definitions_table::DefinitionsTable,
+    common_types::{acme::AcmeConfig, admin::AdminConfig, definitions_table::DefinitionsTable},
     internal::Config,
     kdl::fs_loader::FileCollector,
     loader::{ConfigLoader, FileConfigLoaderProvider},
@@ -20,16 +20,21 @@ use pingora::{
 use tokio::sync::Mutex;
 
 use crate::{
+    admin::{access::AdminAccess, api::admin_service},
     files::motya_file_server,
     fs_adapter::TokioFs,
     proxy::{
-        filters::{chain_resolver::ChainResolver, generate_registry},
+        acme, config_version,
+        filters::{chain_resolver::ChainResolver, generate_registry, registry::FilterRegistry},
+        metrics::metrics_service,
         motya_proxy_service,
         plugins::store::WasmPluginStore,
         rate_limiter::registry::StorageRegistry,
         upstream_factory::UpstreamFactory,
         watcher::file_watcher::ConfigWatcher,
+        SharedProxyState,
     },
+    upgrade,
 };
 
 pub struct AppContext {
@@ -51,6 +56,13 @@ fn resolve_config_path(cli: &Cli) -> PathBuf {
     "/etc/motya/entry.kdl".into()
 }
 
+/// The profile given with `--profile`, or else in `MOTYA_PROFILE`.
+pub fn resolve_profile(cli: &Cli) -> Option<String> {
+    cli.profile
+        .clone()
+        .or_else(|| std::env::var("MOTYA_PROFILE").ok())
+}
+
 impl AppContext {
     pub async fn bootstrap(cli_args: Cli) -> miette::Result<AppContext> {
         let config_path = resolve_config_path(&cli_args);
@@ -59,23 +71,17 @@ impl AppContext {
 
         // 2. Load Registry & Global Definitions
         let mut global_definitions = DefinitionsTable::default();
-        let mut registry_map = generate_registry::load_registry(&mut global_definitions);
+        let registry_map = generate_registry::load_registry(&mut global_definitions);
 
         // 3. Load Config File
         let config = Self::load_config(&cli_args, &config_path, &mut global_definitions).await?;
 
+        if config.upgrade {
+            upgrade::take_over(&config)?;
+        }
+
         // 4. Compile WASM & Setup Resolver
-        let store = WasmPluginStore::compile(&global_definitions).await?;
-        store.register_into(&mut registry_map);
-
-        let registry = Arc::new(Mutex::new(registry_map));
-        let storage_registry = Arc::new(StorageRegistry::default());
-        let resolver = ChainResolver::new(
-            global_definitions.clone(),
-            registry.clone(),
-            storage_registry,
-        )
-        .await?;
+        let resolver = create_resolver(&global_definitions, registry_map).await?;
 
         // 5. Setup Watcher
         let watcher = ConfigWatcher::new(
@@ -83,7 +89,7 @@ impl AppContext {
             global_definitions,
             config_path,
             UpstreamFactory::new(resolver.clone()),
-            ConfigLoader::new(FileCollector::default()),
+            ConfigLoader::new(FileCollector::default()).with_profile(resolve_profile(&cli_args)),
         );
 
         // 6. Prepare Server instance (Pingora)
@@ -99,32 +105,28 @@ impl AppContext {
     }
 
     pub async fn build_services(&mut self) -> miette::Result<Vec<Box<dyn Service>>> {
-        let mut services: Vec<Box<dyn Service>> = vec![];
-
-        tracing::info!("Configuring Basic Proxies...");
-
-        for proxy_conf in &self.config.basic_proxies {
-            tracing::info!("Configuring Basic Proxy: {}", proxy_conf.name);
+        // TLS listeners serving the ACME certificate need its files before they load.
+        if let Some(acme) = &self.config.acme {
+            acme::prepare(acme)?;
+        }
 
-            let (motya_service, shared_state) =
-                motya_proxy_service(proxy_conf.clone(), self.resolver.clone(), &self.server)
-                    .await
-                    .map_err(|e| {
-                        miette::miette!("Failed create service {}: {}", proxy_conf.name, e)
-                    })?;
+        let watcher = &mut self.watcher;
 
-            self.watcher
-                .insert_proxy_state(motya_service.name().to_string(), shared_state);
-            services.push(motya_service);
-        }
+        build_services(
+            &self.config,
+            &self.resolver,
+            &self.server,
+            |name, shared_state| watcher.insert_proxy_state(name.to_string(), shared_state),
+        )
+        .await
+    }
 
-        for fs_conf in &self.config.file_servers {
-            tracing::info!("Configuring File Server: {}", fs_conf.name);
-            let service = motya_file_server(fs_conf.clone(), &self.server);
-            services.push(service);
-        }
+    pub fn acme_config(&self) -> Option<AcmeConfig> {
+        self.config.acme.clone()
+    }
 
-        Ok(services)
+    pub fn shutdown_grace(&self) -> Option<Duration> {
+        self.config.shutdown_grace
     }
 
     pub fn ready(self) -> (Server, ConfigWatcher) {
@@ -159,8 +161,24 @@ impl AppContext {
 
                 CliConfigBuilder::build_routes(*port, routes)?
             }
+            Some(Commands::Validate { .. }) => {
+                return Err(miette::miette!(
+                    "'validate' only checks the configuration, it does not start a server"
+                ));
+            }
+            Some(Commands::Schema { .. }) => {
+                return Err(miette::miette!(
+                    "'schema' only prints the configuration schema, it does not start a server"
+                ));
+            }
+            Some(Commands::Config { .. }) => {
+                return Err(miette::miette!(
+                    "'config' only works with configuration files, it does not start a server"
+                ));
+            }
             None => {
-                let loader = ConfigLoader::new(FileCollector::<TokioFs>::default());
+                let loader = ConfigLoader::new(FileCollector::<TokioFs>::default())
+                    .with_profile(resolve_profile(cli_args));
                 loader
                     .load_entry_point(Some(config_path.into()), global_definitions)
                     .await?
@@ -181,6 +199,78 @@ impl AppContext {
     }
 }
 
+/// Compiles the WASM plugins referenced by `definitions` and sets up the chain resolver
+/// on top of the filter registry.
+pub async fn create_resolver(
+    definitions: &DefinitionsTable,
+    mut registry_map: FilterRegistry,
+) -> miette::Result<ChainResolver> {
+    let store = WasmPluginStore::compile(definitions).await?;
+    store.register_into(&mut registry_map);
+
+    let registry = Arc::new(Mutex::new(registry_map));
+    let storage_registry = Arc::new(StorageRegistry::new(definitions).await?);
+
+    ChainResolver::new(definitions.clone(), registry, storage_registry).await
+}
+
+/// Creates the pingora services for every proxy and file server in `config`, plus
+/// the admin listener when `system.admin` gives it an address and the metrics
+/// listener when `system.metrics-listener` is set.
+///
+/// `on_proxy_state` receives the router state of each proxy service, keyed by the
+/// proxy name, so that it can be swapped on config reload.
+pub async fn build_services(
+    config: &Config,
+    resolver: &ChainResolver,
+    server: &Server,
+    mut on_proxy_state: impl FnMut(&str, SharedProxyState),
+) -> miette::Result<Vec<Box<dyn Service>>> {
+    let mut services: Vec<Box<dyn Service>> = vec![];
+    let mut proxy_states = vec![];
+
+    config_version::publish(config);
+
+    tracing::info!("Configuring Basic Proxies...");
+
+    for proxy_conf in &config.basic_proxies {
+        tracing::info!("Configuring Basic Proxy: {}", proxy_conf.name);
+
+        let (motya_service, shared_state) =
+            motya_proxy_service(proxy_conf.clone(), resolver.clone(), server)
+                .await
+                .map_err(|e| miette::miette!("Failed create service {}: {}", proxy_conf.name, e))?;
+
+        on_proxy_state(&proxy_conf.name, shared_state.clone());
+        proxy_states.push((proxy_conf.name.clone(), shared_state));
+        services.push(motya_service);
+    }
+
+    for fs_conf in &config.file_servers {
+        tracing::info!("Configuring File Server: {}", fs_conf.name);
+        let service = motya_file_server(fs_conf.clone(), server);
+        services.push(service);
+    }
+
+    // The metrics listener takes its clients from `system.admin` as well, and
+    // only accepts loopback clients without it.
+    let default_admin = AdminConfig::default();
+    let admin = config.admin.as_ref().unwrap_or(&default_admin);
+    let access = AdminAccess::from_config(admin)?;
+
+    if let Some(addr) = admin.listen {
+        tracing::info!("Configuring Admin API on {addr}");
+        services.push(admin_service(addr, access.clone(), proxy_states));
+    }
+
+    if let Some(addr) = config.metrics_listener {
+        tracing::info!("Configuring Prometheus metrics on {addr}");
+        services.push(metrics_service(addr, access));
+    }
+
+    Ok(services)
+}
+
 fn apply_cli(conf: &mut Config, cli: &Cli) {
     let Cli {
         validate_configs,
@@ -190,6 +280,7 @@ fn apply_cli(conf: &mut Config, cli: &Cli) {
         upgrade,
         pidfile,
         upgrade_socket,
+        profile: _,
         command: _,
     } = cli;
 
@@ -262,6 +353,10 @@ pub fn pingora_server_conf(config: &Config) -> PingoraServerConf {
         group: None,
         threads: config.threads_per_service,
         work_stealing: true,
+        // Whole seconds only; the drain ends the process as soon as nothing is left.
+        grace_period_seconds: config
+            .shutdown_grace
+            .map(|grace| grace.as_millis().div_ceil(1000) as u64),
         ca_file: None,
         ..PingoraServerConf::default()
     }
@@ -0,0 +1,371 @@
+//! Embedding API.
+//!
+//! [`MotyaBuilder`] assembles the same [`Config`] the KDL loader produces and runs it
+//! through the same service setup as the `motya` binary, so another Rust application
+//! can host the proxy without any configuration files:
+//!
+//! ```no_run
+//! use motya::builder::MotyaBuilder;
+//!
+//! # async fn run() -> miette::Result<()> {
+//! let motya = MotyaBuilder::new()
+//!     .service("api", "0.0.0.0:8080")
+//!     .upstream("/users", "127.0.0.1:3000".parse().unwrap())
+//!     .upstream("/orders", "127.0.0.1:3001".parse().unwrap())
+//!     .build()
+//!     .await?;
+//!
+//! std::thread::spawn(move || motya.run_forever());
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! `service` opens a new proxy service and the calls that follow it (`listen`,
+//! `upstream`, `route`, `use_chain`) configure that service.
+
+use std::{net::SocketAddr, str::FromStr};
+
+use http::uri::PathAndQuery;
+use motya_config::{
+    common_types::{
+        connectors::{
+            Connectors, HttpPeerConfig, RouteMatcher, UpstreamConfig, UpstreamContextConfig, ALPN,
+        },
+        definitions::{FilterChain, Modificator, NamedFilterChain},
+        definitions_table::DefinitionsTable,
+        listeners::{ListenerConfig, ListenerKind, Listeners, SocketOptions},
+    },
+    internal::{Config, ProxyConfig},
+};
+use pingora::{server::Server, services::Service};
+
+use crate::{
+    app_context::{build_services, create_resolver, pingora_opt, pingora_server_conf},
+    proxy::filters::generate_registry,
+};
+
+#[derive(Default)]
+pub struct MotyaBuilder {
+    config: Config,
+    chains: Vec<(String, FilterChain)>,
+    errors: Vec<String>,
+}
+
+/// A server built by [`MotyaBuilder`], ready to run.
+pub struct Motya {
+    server: Server,
+    services: Vec<Box<dyn Service>>,
+}
+
+impl MotyaBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn threads_per_service(mut self, threads: usize) -> Self {
+        self.config.threads_per_service = threads;
+        self
+    }
+
+    /// Registers a named filter chain, which routes can then reference with [`Self::use_chain`].
+    pub fn chain(mut self, name: impl Into<String>, chain: FilterChain) -> Self {
+        self.chains.push((name.into(), chain));
+        self
+    }
+
+    /// Starts a new proxy service listening on `addr`.
+    pub fn service(mut self, name: impl Into<String>, addr: impl Into<String>) -> Self {
+        self.config.basic_proxies.push(ProxyConfig {
+            name: name.into(),
+            listeners: Listeners { list_cfgs: vec![] },
+            access_log: None,
+            limits: None,
+            error_pages: None,
+            maintenance: None,
+            connectors: Connectors { upstreams: vec![] },
+        });
+        self.listen(addr)
+    }
+
+    /// Adds another plain TCP listener to the current service.
+    pub fn listen(mut self, addr: impl Into<String>) -> Self {
+        let listener = ListenerConfig {
+            source: ListenerKind::Tcp {
+                addr: addr.into(),
+                tls: None,
+                offer_h2: false,
+                proxy_protocol: false,
+                max_conn_rate: None,
+                socket: SocketOptions::default(),
+            },
+        };
+
+        if let Some(service) = self.current_service("listen") {
+            service.listeners.list_cfgs.push(listener);
+        }
+        self
+    }
+
+    /// Proxies every request under `prefix` to `peer` over plain HTTP/1.
+    pub fn upstream(mut self, prefix: &str, peer: SocketAddr) -> Self {
+        let prefix_path = match PathAndQuery::from_str(prefix) {
+            Ok(path) => path,
+            Err(e) => {
+                self.errors
+                    .push(format!("Invalid route path '{prefix}': {e}"));
+                return self;
+            }
+        };
+
+        self.route(UpstreamContextConfig {
+            upstream: UpstreamConfig::Service(HttpPeerConfig {
+                peer_address: peer,
+                alpn: ALPN::H1,
+                sni: String::new(),
+                tls: false,
+                prefix_path,
+                matcher: RouteMatcher::Prefix,
+            }),
+            chains: vec![],
+            lb_options: None,
+            compression: None,
+            sse: None,
+            cache: None,
+            retry: None,
+            mirror: None,
+            timeout: None,
+            allow_upgrades: true,
+            grpc: false,
+            upstream_tls: None,
+            h2: None,
+            debug_trace: false,
+            conditions: Default::default(),
+            rewrite: None,
+        })
+    }
+
+    /// Adds a fully specified route to the current service.
+    pub fn route(mut self, route: UpstreamContextConfig) -> Self {
+        if let Some(service) = self.current_service("route") {
+            service.connectors.upstreams.push(route);
+        }
+        self
+    }
+
+    /// Applies a chain registered with [`Self::chain`] to the last route of the current service.
+    pub fn use_chain(mut self, name: &str) -> Self {
+        let Some(chain) = self
+            .chains
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, c)| c.clone())
+        else {
+            self.errors
+                .push(format!("Chain '{name}' is used before it was registered"));
+            return self;
+        };
+
+        let route = self
+            .current_service("use_chain")
+            .and_then(|s| s.connectors.upstreams.last_mut());
+
+        match route {
+            Some(route) => route.chains.push(Modificator::Chain(NamedFilterChain {
+                chain,
+                name: name.to_string(),
+            })),
+            None => self
+                .errors
+                .push(format!("'use_chain({name})' must follow a route")),
+        }
+        self
+    }
+
+    /// Resolves the filter chains and creates the pingora services.
+    pub async fn build(self) -> miette::Result<Motya> {
+        let mut errors = self.errors;
+
+        for proxy in &self.config.basic_proxies {
+            if proxy.connectors.upstreams.is_empty() {
+                errors.push(format!("Service '{}' has no routes", proxy.name));
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(miette::miette!(
+                "Invalid embedded configuration:\n{}",
+                errors.join("\n")
+            ));
+        }
+
+        let mut definitions = DefinitionsTable::default();
+        let registry = generate_registry::load_registry(&mut definitions);
+
+        for (name, chain) in self.chains {
+            definitions.insert_chain(name, chain);
+        }
+
+        let resolver = create_resolver(&definitions, registry).await?;
+
+        let server = Server::new_with_opt_and_conf(
+            pingora_opt(&self.config),
+            pingora_server_conf(&self.config),
+        );
+
+        let services = build_services(&self.config, &resolver, &server, |_, _| {}).await?;
+
+        Ok(Motya { server, services })
+    }
+
+    fn current_service(&mut self, method: &str) -> Option<&mut ProxyConfig> {
+        if self.config.basic_proxies.is_empty() {
+            self.errors
+                .push(format!("'{method}' must be called after 'service'"));
+        }
+
+        self.config.basic_proxies.last_mut()
+    }
+}
+
+impl Motya {
+    /// Runs the server on the current thread. Never returns.
+    pub fn run_forever(self) -> ! {
+        let (mut server, services) = self.into_parts();
+
+        server.bootstrap();
+        server.add_services(services);
+        server.run_forever()
+    }
+
+    /// Gives back the pingora server and its services, for callers that want to add
+    /// services of their own before starting it.
+    pub fn into_parts(self) -> (Server, Vec<Box<dyn Service>>) {
+        (self.server, self.services)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn build_error(builder: MotyaBuilder) -> String {
+        match builder.build().await {
+            Ok(_) => panic!("Expected the build to fail"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    fn peer(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[tokio::test]
+    async fn test_build_routes() {
+        let builder = MotyaBuilder::new()
+            .chain("security", FilterChain { items: vec![] })
+            .service("api", "127.0.0.1:8080")
+            .listen("127.0.0.1:8081")
+            .upstream("/users", peer(3000))
+            .use_chain("security")
+            .upstream("/orders", peer(3001));
+
+        let proxy = &builder.config.basic_proxies[0];
+        assert_eq!(proxy.name, "api");
+        assert_eq!(proxy.listeners.list_cfgs.len(), 2);
+
+        let routes: Vec<_> = proxy
+            .connectors
+            .upstreams
+            .iter()
+            .map(|route| match &route.upstream {
+                UpstreamConfig::Service(http_peer) => (
+                    http_peer.prefix_path.as_str(),
+                    http_peer.peer_address,
+                    route.chains.len(),
+                ),
+                other => panic!("Unexpected upstream {other:?}"),
+            })
+            .collect();
+        assert_eq!(
+            routes,
+            vec![("/users", peer(3000), 1), ("/orders", peer(3001), 0)]
+        );
+
+        let (_, services) = builder.build().await.unwrap().into_parts();
+        assert_eq!(services.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_calls_before_service() {
+        let err = build_error(
+            MotyaBuilder::new()
+                .listen("127.0.0.1:8080")
+                .upstream("/", peer(3000)),
+        )
+        .await;
+
+        assert!(
+            err.contains("'listen' must be called after 'service'"),
+            "{err}"
+        );
+        assert!(
+            err.contains("'route' must be called after 'service'"),
+            "{err}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_use_chain_errors() {
+        let err = build_error(
+            MotyaBuilder::new()
+                .service("api", "127.0.0.1:8080")
+                .upstream("/", peer(3000))
+                .use_chain("security"),
+        )
+        .await;
+        assert!(
+            err.contains("Chain 'security' is used before it was registered"),
+            "{err}"
+        );
+
+        let err = build_error(
+            MotyaBuilder::new()
+                .chain("security", FilterChain { items: vec![] })
+                .service("api", "127.0.0.1:8080")
+                .use_chain("security")
+                .upstream("/", peer(3000)),
+        )
+        .await;
+        assert!(
+            err.contains("'use_chain(security)' must follow a route"),
+            "{err}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_invalid_prefix() {
+        let err = build_error(
+            MotyaBuilder::new()
+                .service("api", "127.0.0.1:8080")
+                .upstream("/a b", peer(3000))
+                .upstream("/", peer(3001)),
+        )
+        .await;
+
+        assert!(err.contains("Invalid route path '/a b'"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_service_without_routes() {
+        let err = build_error(
+            MotyaBuilder::new()
+                .service("api", "127.0.0.1:8080")
+                .upstream("/", peer(3000))
+                .service("empty", "127.0.0.1:8081"),
+        )
+        .await;
+
+        assert!(err.contains("Service 'empty' has no routes"), "{err}");
+        assert!(!err.contains("Service 'api'"), "{err}");
+    }
+}
@@ -0,0 +1,532 @@
+//! `motya config diff <old.kdl> <new.kdl>`: loads both configurations the way the
+//! server does and lists what the new one changes, so it can be reviewed before a
+//! reload.
+//!
+//! Both sides are compared as the internal [`Config`] model, after includes are
+//! resolved and chains are linked into their routes, so formatting, file layout
+//! and the order of nodes do not show up. Services, listeners, routes and named
+//! definitions are matched by name; for an entry in both, the lines of its model
+//! that differ are listed.
+
+use std::{collections::HashMap, fmt, path::Path};
+
+use miette::miette;
+use motya_config::{
+    common_types::{
+        connectors::UpstreamContextConfig,
+        definitions_table::DefinitionsTable,
+        file_server::FileServerConfig,
+        listeners::{ListenerConfig, ListenerKind, Listeners},
+    },
+    internal::{Config, ProxyConfig},
+    kdl::fs_loader::FileCollector,
+    loader::ConfigLoader,
+};
+
+use crate::{
+    config_render::{route_label, route_path},
+    fs_adapter::TokioFs,
+    proxy::filters::generate_registry,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    Added(String),
+    Removed(String),
+    /// An entry on both sides: the lines of its model that differ, prefixed
+    /// with `-` or `+`, and the changes of the entries it holds.
+    Modified {
+        name: String,
+        lines: Vec<String>,
+        changes: Vec<Change>,
+    },
+    /// Changes listed under a heading, such as the listeners of a service.
+    Group {
+        name: String,
+        changes: Vec<Change>,
+    },
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ConfigDiff {
+    pub changes: Vec<Change>,
+}
+
+impl ConfigDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+impl fmt::Display for ConfigDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for change in &self.changes {
+            write_change(f, change, 0)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_change(f: &mut fmt::Formatter<'_>, change: &Change, depth: usize) -> fmt::Result {
+    let indent = "  ".repeat(depth);
+    match change {
+        Change::Added(name) => writeln!(f, "{indent}+ {name}"),
+        Change::Removed(name) => writeln!(f, "{indent}- {name}"),
+        Change::Modified {
+            name,
+            lines,
+            changes,
+        } => {
+            writeln!(f, "{indent}~ {name}")?;
+            for line in lines {
+                writeln!(f, "{indent}    {line}")?;
+            }
+            for change in changes {
+                write_change(f, change, depth + 1)?;
+            }
+            Ok(())
+        }
+        Change::Group { name, changes } => {
+            writeln!(f, "{indent}{name}")?;
+            for change in changes {
+                write_change(f, change, depth + 1)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Loads an entry point with its includes and the given profile applied,
+/// failing with the configuration errors if there are any.
+pub async fn load(
+    entry: &Path,
+    profile: Option<&str>,
+) -> miette::Result<(Config, DefinitionsTable)> {
+    let mut definitions = DefinitionsTable::default();
+    generate_registry::load_registry(&mut definitions);
+
+    let loader = ConfigLoader::new(FileCollector::<TokioFs>::default())
+        .with_profile(profile.map(String::from));
+    let (config, errors) = loader
+        .load_lossy(Some(entry.to_path_buf()), &mut definitions)
+        .await;
+
+    if !errors.is_empty() {
+        return Err(
+            miette::Report::new(errors).wrap_err(format!("Failed to load {}", entry.display()))
+        );
+    }
+
+    let config = config.ok_or_else(|| miette!("No configuration in {}", entry.display()))?;
+    Ok((config, definitions))
+}
+
+pub async fn diff_files(
+    old: &Path,
+    new: &Path,
+    profile: Option<&str>,
+) -> miette::Result<ConfigDiff> {
+    let (old_config, old_definitions) = load(old, profile).await?;
+    let (new_config, new_definitions) = load(new, profile).await?;
+
+    Ok(diff(
+        (&old_config, &old_definitions),
+        (&new_config, &new_definitions),
+    ))
+}
+
+pub fn diff(old: (&Config, &DefinitionsTable), new: (&Config, &DefinitionsTable)) -> ConfigDiff {
+    let mut changes = Vec::new();
+
+    if let Some(change) = modified("system", &system(old.0), &system(new.0)) {
+        changes.push(change);
+    }
+
+    let services = diff_by_name(services(old.0), services(new.0), diff_service);
+    push_group(&mut changes, "services", services);
+
+    let definitions = diff_definitions(old.1, new.1);
+    push_group(&mut changes, "definitions", definitions);
+
+    ConfigDiff { changes }
+}
+
+/// The settings of `system`: the config without its services.
+fn system(config: &Config) -> Config {
+    Config {
+        basic_proxies: Vec::new(),
+        file_servers: Vec::new(),
+        ..config.clone()
+    }
+}
+
+enum Service<'a> {
+    Proxy(&'a ProxyConfig),
+    FileServer(&'a FileServerConfig),
+}
+
+impl Service<'_> {
+    fn listeners(&self) -> &Listeners {
+        match self {
+            Service::Proxy(proxy) => &proxy.listeners,
+            Service::FileServer(file_server) => &file_server.listeners,
+        }
+    }
+
+    fn without_listeners(&self) -> String {
+        match self {
+            Service::Proxy(proxy) => {
+                let mut proxy = (*proxy).clone();
+                proxy.listeners.list_cfgs.clear();
+                format!("{proxy:#?}")
+            }
+            Service::FileServer(file_server) => file_server_settings(file_server),
+        }
+    }
+}
+
+fn services(config: &Config) -> Vec<(String, Service<'_>)> {
+    let proxies = config
+        .basic_proxies
+        .iter()
+        .map(|proxy| (proxy.name.clone(), Service::Proxy(proxy)));
+    let file_servers = config
+        .file_servers
+        .iter()
+        .map(|file_server| (file_server.name.clone(), Service::FileServer(file_server)));
+
+    proxies.chain(file_servers).collect()
+}
+
+fn diff_service(name: &str, old: &Service<'_>, new: &Service<'_>) -> Option<Change> {
+    let mut changes = Vec::new();
+
+    let listeners = diff_by_name(
+        listeners(old.listeners()),
+        listeners(new.listeners()),
+        modified,
+    );
+    push_group(&mut changes, "listeners", listeners);
+
+    let lines = match (old, new) {
+        (Service::Proxy(old), Service::Proxy(new)) => {
+            let routes = diff_by_name(
+                routes(&old.connectors.upstreams),
+                routes(&new.connectors.upstreams),
+                modified,
+            );
+            push_group(&mut changes, "routes", routes);
+
+            line_diff(&proxy_settings(old), &proxy_settings(new))
+        }
+        (Service::FileServer(old), Service::FileServer(new)) => {
+            line_diff(&file_server_settings(old), &file_server_settings(new))
+        }
+        // A service that changed kind is listed in full, bar its listeners.
+        (old, new) => line_diff(&old.without_listeners(), &new.without_listeners()),
+    };
+
+    (!lines.is_empty() || !changes.is_empty()).then(|| Change::Modified {
+        name: name.to_string(),
+        lines,
+        changes,
+    })
+}
+
+/// A proxy without its listeners and routes, which are compared one by one.
+fn proxy_settings(proxy: &ProxyConfig) -> String {
+    let mut proxy = proxy.clone();
+    proxy.listeners.list_cfgs.clear();
+    proxy.connectors.upstreams.clear();
+    format!("{proxy:#?}")
+}
+
+fn file_server_settings(file_server: &FileServerConfig) -> String {
+    let mut file_server = file_server.clone();
+    file_server.listeners.list_cfgs.clear();
+    format!("{file_server:#?}")
+}
+
+fn listeners(listeners: &Listeners) -> Vec<(String, &ListenerConfig)> {
+    listeners
+        .list_cfgs
+        .iter()
+        .map(|listener| {
+            let name = match &listener.source {
+                ListenerKind::Tcp { addr, .. } => addr.clone(),
+                ListenerKind::Uds(uds) => format!("unix:{}", uds.path.display()),
+            };
+            (name, listener)
+        })
+        .collect()
+}
+
+fn routes(upstreams: &[UpstreamContextConfig]) -> Vec<(String, &UpstreamContextConfig)> {
+    upstreams
+        .iter()
+        .map(|upstream| {
+            let (path, matcher) = route_path(&upstream.upstream);
+            (route_label(&path, matcher), upstream)
+        })
+        .collect()
+}
+
+fn diff_definitions(old: &DefinitionsTable, new: &DefinitionsTable) -> Vec<Change> {
+    let mut changes = Vec::new();
+
+    let chains = diff_named(old.get_chains(), new.get_chains());
+    push_group(&mut changes, "chains", chains);
+
+    let key_profiles = diff_named(old.get_key_templates(), new.get_key_templates());
+    push_group(&mut changes, "key-profiles", key_profiles);
+
+    let storages = diff_named(old.get_storages(), new.get_storages());
+    push_group(&mut changes, "storages", storages);
+
+    let rate_limits = diff_named(old.get_rate_limits(), new.get_rate_limits());
+    push_group(&mut changes, "rate-limits", rate_limits);
+
+    let upstream_groups = diff_named(old.get_upstream_groups(), new.get_upstream_groups());
+    push_group(&mut changes, "upstream-groups", upstream_groups);
+
+    let credentials = diff_named(old.get_all_credentials(), new.get_all_credentials());
+    push_group(&mut changes, "credentials", credentials);
+
+    let secrets = diff_named(old.get_secrets(), new.get_secrets());
+    push_group(&mut changes, "secrets", secrets);
+
+    let plugins = |table: &DefinitionsTable| {
+        let plugins = table.get_plugins().iter();
+        sorted(plugins.map(|(name, plugin)| (name.to_string(), plugin.clone())))
+    };
+    let plugins = diff_by_name(plugins(old), plugins(new), modified);
+    push_group(&mut changes, "plugins", plugins);
+
+    changes
+}
+
+/// Compares named definitions. The anonymous ones, generated for inline blocks,
+/// are left out: they are part of the routes that hold them.
+fn diff_named<T: fmt::Debug + PartialEq>(
+    old: &HashMap<String, T>,
+    new: &HashMap<String, T>,
+) -> Vec<Change> {
+    let named = |table: &HashMap<String, T>| {
+        sorted(
+            table
+                .iter()
+                .filter(|(name, _)| !name.starts_with("__anon"))
+                .map(|(name, value)| (name.clone(), value)),
+        )
+    };
+
+    diff_by_name(named(old), named(new), modified)
+}
+
+fn sorted<T>(entries: impl Iterator<Item = (String, T)>) -> Vec<(String, T)> {
+    let mut entries: Vec<_> = entries.collect();
+    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+    entries
+}
+
+/// Removed and modified entries in the order of `old`, then the added ones in
+/// the order of `new`.
+fn diff_by_name<T>(
+    old: Vec<(String, T)>,
+    new: Vec<(String, T)>,
+    mut compare: impl FnMut(&str, &T, &T) -> Option<Change>,
+) -> Vec<Change> {
+    let mut changes = Vec::new();
+
+    for (name, old_value) in &old {
+        match new.iter().find(|(new_name, _)| new_name == name) {
+            Some((_, new_value)) => changes.extend(compare(name, old_value, new_value)),
+            None => changes.push(Change::Removed(name.clone())),
+        }
+    }
+
+    for (name, _) in &new {
+        if !old.iter().any(|(old_name, _)| old_name == name) {
+            changes.push(Change::Added(name.clone()));
+        }
+    }
+
+    changes
+}
+
+fn modified<T: fmt::Debug + PartialEq>(name: &str, old: &T, new: &T) -> Option<Change> {
+    (old != new).then(|| Change::Modified {
+        name: name.to_string(),
+        lines: line_diff(&format!("{old:#?}"), &format!("{new:#?}")),
+        changes: Vec::new(),
+    })
+}
+
+fn push_group(changes: &mut Vec<Change>, name: &str, group: Vec<Change>) {
+    if !group.is_empty() {
+        changes.push(Change::Group {
+            name: name.to_string(),
+            changes: group,
+        });
+    }
+}
+
+/// The lines only in `old`, prefixed with `-`, and only in `new`, prefixed with
+/// `+`, in the order of a longest common subsequence of the two.
+fn line_diff(old: &str, new: &str) -> Vec<String> {
+    let old: Vec<&str> = old.lines().map(str::trim).collect();
+    let new: Vec<&str> = new.lines().map(str::trim).collect();
+
+    // common[i][j]: the length of the longest common subsequence of old[i..] and new[j..].
+    let mut common = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut lines = Vec::new();
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            i += 1;
+            j += 1;
+        } else if j == new.len() || (i < old.len() && common[i + 1][j] >= common[i][j + 1]) {
+            lines.push(format!("- {}", old[i]));
+            i += 1;
+        } else {
+            lines.push(format!("+ {}", new[j]));
+            j += 1;
+        }
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::*;
+
+    fn write_config(dir: &Path, name: &str, contents: &str) -> PathBuf {
+        let entry = dir.join(name);
+        fs::write(&entry, contents).unwrap();
+        entry
+    }
+
+    #[test]
+    fn test_line_diff() {
+        let old = "Config {\n    port: 80,\n    tls: false,\n}";
+        let new = "Config {\n    port: 80,\n    tls: true,\n    h2: true,\n}";
+
+        assert_eq!(
+            line_diff(old, new),
+            vec!["- tls: false,", "+ tls: true,", "+ h2: true,"]
+        );
+        assert!(line_diff(old, old).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_diff_services_and_definitions() {
+        let dir = tempfile::tempdir().unwrap();
+        let old = write_config(
+            dir.path(),
+            "old.kdl",
+            r#"
+            definitions {
+                modifiers {
+                    chain-filters "security" {
+                        filter "motya.request.upsert-header" key="X-Old" value="1"
+                    }
+                }
+            }
+            services {
+                Api {
+                    listeners {
+                        "127.0.0.1:8080"
+                        "127.0.0.1:8081"
+                    }
+                    connectors {
+                        section "/" {
+                            return 200 "OK"
+                        }
+                        section "/old" {
+                            return 200 "Old"
+                        }
+                    }
+                }
+                Legacy {
+                    listeners { "127.0.0.1:9000" }
+                    connectors {
+                        section "/" {
+                            return 200 "Legacy"
+                        }
+                    }
+                }
+            }
+            "#,
+        );
+        let new = write_config(
+            dir.path(),
+            "new.kdl",
+            r#"
+            definitions {
+                modifiers {
+                    chain-filters "security" {
+                        filter "motya.request.upsert-header" key="X-New" value="1"
+                    }
+                }
+            }
+            services {
+                Api {
+                    listeners {
+                        "127.0.0.1:8080"
+                    }
+                    connectors {
+                        section "/" {
+                            return 200 "Hello"
+                        }
+                    }
+                }
+                Files {
+                    listeners { "127.0.0.1:9001" }
+                    file-server root="."
+                }
+            }
+            "#,
+        );
+
+        let diff = diff_files(&old, &new, None).await.unwrap();
+        let text = diff.to_string();
+
+        assert!(
+            text.contains("services\n  ~ Api\n    listeners\n      - 127.0.0.1:8081\n"),
+            "{text}"
+        );
+        assert!(text.contains("    routes\n      ~ /\n"), "{text}");
+        assert!(text.contains("+ response_body: \"Hello\","), "{text}");
+        assert!(text.contains("      - /old\n"), "{text}");
+        assert!(text.contains("  - Legacy\n  + Files\n"), "{text}");
+        assert!(
+            text.contains("definitions\n  chains\n    ~ security\n"),
+            "{text}"
+        );
+
+        let same = diff_files(&old, &old, None).await.unwrap();
+        assert!(same.is_empty(), "{same}");
+    }
+
+    #[tokio::test]
+    async fn test_diff_fails_on_config_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let old = write_config(dir.path(), "old.kdl", "services { }");
+        let new = write_config(dir.path(), "new.kdl", "servces { }");
+
+        assert!(diff_files(&old, &new, None).await.is_err());
+    }
+}
@@ -0,0 +1,537 @@
+//! `motya config render <entry.kdl>`: loads a configuration and prints the routes
+//! it ends up with, one row per section, after includes are resolved, nested
+//! sections are flattened and chains are linked in.
+//!
+//! The KDL form describes the routes; it is not a configuration that can be
+//! loaded back.
+
+use motya_config::{
+    cli::cli_struct::RenderFormat,
+    common_types::{
+        balancer::{DiscoveryKind, HealthCheckKind, SelectionKind, UpstreamOptions},
+        connectors::{RouteMatcher, UpstreamConfig, UpstreamContextConfig},
+        definitions::{ChainItem, Modificator},
+        listeners::{ListenerKind, Listeners},
+    },
+    internal::Config,
+};
+use serde_json::{json, Value};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServiceView {
+    pub name: String,
+    pub listeners: Vec<String>,
+    pub routes: Vec<RouteView>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteView {
+    /// The section path, or its pattern for `as="regex"`.
+    pub path: String,
+    /// `exact`, `prefix` or `regex`, as in `as=`.
+    pub matcher: &'static str,
+    /// The `method` and `query` the route is limited to, e.g. `method=POST`, or
+    /// empty.
+    pub conditions: String,
+    pub upstream: String,
+    pub chains: Vec<ChainView>,
+    pub load_balancing: Option<String>,
+    /// The route settings that differ from the defaults, such as `retry` or
+    /// `grpc`.
+    pub options: Vec<&'static str>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainView {
+    /// `None` for a chain written inline in the section.
+    pub name: Option<String>,
+    /// The kind of each item, `filter`, `rate-limit` or `basic-auth`, and what it
+    /// refers to.
+    pub items: Vec<(&'static str, String)>,
+}
+
+pub fn services(config: &Config) -> Vec<ServiceView> {
+    let proxies = config.basic_proxies.iter().map(|proxy| ServiceView {
+        name: proxy.name.clone(),
+        listeners: listeners(&proxy.listeners),
+        routes: proxy.connectors.upstreams.iter().map(route).collect(),
+    });
+
+    let file_servers = config.file_servers.iter().map(|file_server| {
+        let root = file_server
+            .base_path
+            .as_ref()
+            .map(|path| path.display().to_string())
+            .unwrap_or_default();
+
+        ServiceView {
+            name: file_server.name.clone(),
+            listeners: listeners(&file_server.listeners),
+            routes: vec![RouteView {
+                path: "/".to_string(),
+                matcher: "prefix",
+                conditions: String::new(),
+                upstream: format!("files {root}"),
+                chains: Vec::new(),
+                load_balancing: None,
+                options: Vec::new(),
+            }],
+        }
+    });
+
+    proxies.chain(file_servers).collect()
+}
+
+fn listeners(listeners: &Listeners) -> Vec<String> {
+    listeners
+        .list_cfgs
+        .iter()
+        .map(|listener| match &listener.source {
+            ListenerKind::Tcp {
+                addr, tls: None, ..
+            } => addr.clone(),
+            ListenerKind::Tcp { addr, .. } => format!("{addr} (tls)"),
+            ListenerKind::Uds(uds) => format!("unix:{}", uds.path.display()),
+        })
+        .collect()
+}
+
+/// The path a route matches and how, e.g. `("/api", "prefix")`.
+pub(crate) fn route_path(upstream: &UpstreamConfig) -> (String, &'static str) {
+    let (path, matcher) = match upstream {
+        UpstreamConfig::Service(peer) => (&peer.prefix_path, &peer.matcher),
+        UpstreamConfig::MultiServer(multi) => (&multi.prefix_path, &multi.matcher),
+        UpstreamConfig::Static(response) => (&response.prefix_path, &RouteMatcher::Exact),
+    };
+
+    match matcher {
+        RouteMatcher::Exact => (path.to_string(), "exact"),
+        RouteMatcher::Prefix => (path.to_string(), "prefix"),
+        RouteMatcher::Regex(pattern) => (pattern.as_str().to_string(), "regex"),
+    }
+}
+
+/// How a route is listed, e.g. `/api` or `/api (prefix)`.
+pub(crate) fn route_label(path: &str, matcher: &str) -> String {
+    match matcher {
+        "exact" => path.to_string(),
+        matcher => format!("{path} ({matcher})"),
+    }
+}
+
+fn route(context: &UpstreamContextConfig) -> RouteView {
+    let (path, matcher) = route_path(&context.upstream);
+
+    let upstream = match &context.upstream {
+        UpstreamConfig::Service(peer) => {
+            let scheme = if peer.tls { "https" } else { "http" };
+            format!("{scheme}://{}", peer.peer_address)
+        }
+        UpstreamConfig::MultiServer(multi) => {
+            let servers: Vec<String> = multi
+                .servers
+                .iter()
+                .map(|server| match server.weight {
+                    1 => server.address.to_string(),
+                    weight => format!("{} weight={weight}", server.address),
+                })
+                .collect();
+            servers.join(", ")
+        }
+        UpstreamConfig::Static(response) => format!(
+            "return {} {:?}",
+            response.http_code.as_u16(),
+            response.response_body
+        ),
+    };
+
+    let chains = context
+        .chains
+        .iter()
+        .map(|Modificator::Chain(named)| ChainView {
+            name: (!named.name.starts_with("__anon")).then(|| named.name.clone()),
+            items: named.chain.items.iter().map(chain_item).collect(),
+        })
+        .collect();
+
+    let switches = [
+        ("compression", context.compression.is_some()),
+        ("sse", context.sse.is_some()),
+        ("cache", context.cache.is_some()),
+        ("retry", context.retry.is_some()),
+        ("mirror", context.mirror.is_some()),
+        ("timeout", context.timeout.is_some()),
+        ("rewrite", context.rewrite.is_some()),
+        ("no-upgrades", !context.allow_upgrades),
+        ("grpc", context.grpc),
+        ("tls", context.upstream_tls.is_some()),
+        ("h2", context.h2.is_some()),
+    ];
+
+    RouteView {
+        path,
+        matcher,
+        conditions: context.conditions.to_string(),
+        upstream,
+        chains,
+        load_balancing: context.lb_options.as_ref().map(load_balancing),
+        options: switches
+            .into_iter()
+            .filter_map(|(name, on)| on.then_some(name))
+            .collect(),
+    }
+}
+
+fn chain_item(item: &ChainItem) -> (&'static str, String) {
+    match item {
+        ChainItem::Filter(filter) => ("filter", filter.name.to_string()),
+        ChainItem::RateLimiter(policy) => ("rate-limit", policy.name.clone()),
+        ChainItem::BasicAuth(auth) => ("basic-auth", auth.realm.clone()),
+        ChainItem::When { condition, item } => {
+            let (kind, name) = chain_item(item);
+            (kind, format!("{name} when {}", condition.source))
+        }
+    }
+}
+
+/// e.g. `Ketama by key, tcp health check every 5s, dns every 30s`.
+fn load_balancing(options: &UpstreamOptions) -> String {
+    let mut parts = vec![match options.selection {
+        SelectionKind::RoundRobin => "RoundRobin".to_string(),
+        SelectionKind::Random => "Random".to_string(),
+        SelectionKind::FvnHash => "FNV".to_string(),
+        SelectionKind::KetamaHashing => "Ketama".to_string(),
+        SelectionKind::LeastConnections => "LeastConnections".to_string(),
+        SelectionKind::PeakEwma => "PeakEWMA".to_string(),
+    }];
+    if options.template.is_some() {
+        parts[0].push_str(" by key");
+    }
+
+    if let HealthCheckKind::Tcp(check) = &options.health_checks {
+        parts.push(format!("tcp health check every {:?}", check.interval));
+    }
+
+    match &options.discovery {
+        DiscoveryKind::Static => {}
+        DiscoveryKind::Dns { refresh } => parts.push(format!("dns every {refresh:?}")),
+        DiscoveryKind::Kubernetes(kubernetes) => {
+            parts.push(format!("kubernetes service {}", kubernetes.service))
+        }
+    }
+
+    if let Some(window) = options.slow_start {
+        parts.push(format!("slow start {window:?}"));
+    }
+
+    if let Some(outliers) = &options.outlier_detection {
+        parts.push(format!(
+            "eject after {} errors in {:?}",
+            outliers.errors, outliers.window
+        ));
+    }
+
+    parts.join(", ")
+}
+
+pub fn render(config: &Config, format: RenderFormat) -> String {
+    let services = services(config);
+    match format {
+        RenderFormat::Table => render_table(&services),
+        RenderFormat::Kdl => render_kdl(&services),
+        RenderFormat::Json => format!("{:#}\n", render_json(&services)),
+    }
+}
+
+fn render_table(services: &[ServiceView]) -> String {
+    const HEADER: [&str; 5] = ["PATH", "UPSTREAM", "CHAINS", "LOAD BALANCING", "OPTIONS"];
+    let mut out = String::new();
+
+    for service in services {
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        out.push_str(&format!(
+            "{} on {}\n",
+            service.name,
+            service.listeners.join(", ")
+        ));
+
+        let rows: Vec<[String; 5]> = service
+            .routes
+            .iter()
+            .map(|route| {
+                let chains: Vec<String> = route
+                    .chains
+                    .iter()
+                    .map(|chain| {
+                        let items: Vec<String> = chain
+                            .items
+                            .iter()
+                            .map(|(kind, name)| match *kind {
+                                "filter" => name.clone(),
+                                kind => format!("{kind} {name}"),
+                            })
+                            .collect();
+                        let name = chain.name.as_deref().unwrap_or("inline");
+                        format!("{name}: {}", items.join(", "))
+                    })
+                    .collect();
+
+                let label = route_label(&route.path, route.matcher);
+                [
+                    match route.conditions.as_str() {
+                        "" => label,
+                        conditions => format!("{label} {conditions}"),
+                    },
+                    route.upstream.clone(),
+                    or_dash(chains.join("; ")),
+                    or_dash(route.load_balancing.clone().unwrap_or_default()),
+                    or_dash(route.options.join(", ")),
+                ]
+            })
+            .collect();
+
+        let mut widths = HEADER.map(str::len);
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+
+        let header = HEADER.map(str::to_string);
+        for row in std::iter::once(&header).chain(&rows) {
+            let cells: Vec<String> = row
+                .iter()
+                .zip(widths)
+                .map(|(cell, width)| format!("{cell:width$}"))
+                .collect();
+            out.push_str(&format!("  {}\n", cells.join("  ").trim_end()));
+        }
+    }
+
+    out
+}
+
+fn or_dash(cell: String) -> String {
+    if cell.is_empty() {
+        "-".to_string()
+    } else {
+        cell
+    }
+}
+
+fn render_kdl(services: &[ServiceView]) -> String {
+    let mut out = String::from("services {\n");
+
+    for service in services {
+        out.push_str(&format!("    {} {{\n", kdl_string(&service.name)));
+
+        let listeners: Vec<String> = service.listeners.iter().map(|l| kdl_string(l)).collect();
+        out.push_str(&format!("        listeners {}\n", listeners.join(" ")));
+
+        for route in &service.routes {
+            out.push_str(&format!(
+                "        route {} as=\"{}\" {{\n",
+                kdl_string(&route.path),
+                route.matcher
+            ));
+            out.push_str(&format!(
+                "            upstream {}\n",
+                kdl_string(&route.upstream)
+            ));
+
+            if !route.conditions.is_empty() {
+                out.push_str(&format!(
+                    "            conditions {}\n",
+                    kdl_string(&route.conditions)
+                ));
+            }
+
+            for chain in &route.chains {
+                let name = match &chain.name {
+                    Some(name) => format!("chain {} ", kdl_string(name)),
+                    None => "chain ".to_string(),
+                };
+                let items: Vec<String> = chain
+                    .items
+                    .iter()
+                    .map(|(kind, name)| format!("{kind} {}", kdl_string(name)))
+                    .collect();
+                if items.is_empty() {
+                    out.push_str(&format!("            {}\n", name.trim_end()));
+                } else {
+                    out.push_str(&format!("            {name}{{ {}; }}\n", items.join("; ")));
+                }
+            }
+
+            if let Some(load_balancing) = &route.load_balancing {
+                out.push_str(&format!(
+                    "            load-balance {}\n",
+                    kdl_string(load_balancing)
+                ));
+            }
+
+            if !route.options.is_empty() {
+                let options: Vec<String> = route.options.iter().map(|o| kdl_string(o)).collect();
+                out.push_str(&format!("            options {}\n", options.join(" ")));
+            }
+
+            out.push_str("        }\n");
+        }
+
+        out.push_str("    }\n");
+    }
+
+    out.push_str("}\n");
+    out
+}
+
+fn kdl_string(value: &str) -> String {
+    format!("{value:?}")
+}
+
+fn render_json(services: &[ServiceView]) -> Value {
+    let services: Vec<Value> = services
+        .iter()
+        .map(|service| {
+            let routes: Vec<Value> = service
+                .routes
+                .iter()
+                .map(|route| {
+                    let chains: Vec<Value> = route
+                        .chains
+                        .iter()
+                        .map(|chain| {
+                            let items: Vec<Value> = chain
+                                .items
+                                .iter()
+                                .map(|(kind, name)| json!({ "kind": kind, "name": name }))
+                                .collect();
+                            json!({ "name": chain.name, "items": items })
+                        })
+                        .collect();
+
+                    json!({
+                        "path": route.path,
+                        "match": route.matcher,
+                        "conditions": (!route.conditions.is_empty()).then_some(&route.conditions),
+                        "upstream": route.upstream,
+                        "chains": chains,
+                        "load_balancing": route.load_balancing,
+                        "options": route.options,
+                    })
+                })
+                .collect();
+
+            json!({
+                "name": service.name,
+                "listeners": service.listeners,
+                "routes": routes,
+            })
+        })
+        .collect();
+
+    Value::Array(services)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use super::*;
+    use crate::config_diff::load;
+
+    async fn load_services(contents: &str) -> Vec<ServiceView> {
+        let dir = tempfile::tempdir().unwrap();
+        let entry = dir.path().join("entry.kdl");
+        fs::write(&entry, contents).unwrap();
+
+        let (config, _) = load(Path::new(&entry), None).await.unwrap();
+        services(&config)
+    }
+
+    const CONFIG: &str = r#"
+        definitions {
+            modifiers {
+                chain-filters "security" {
+                    filter "motya.request.upsert-header" key="X-Secure" value="1"
+                }
+            }
+        }
+        services {
+            Api {
+                listeners { "127.0.0.1:8080" }
+                connectors {
+                    section "/api" as="prefix" {
+                        use-chain "security"
+                        proxy "http://127.0.0.1:3001"
+                        section "/v1" as="prefix" {
+                            proxy "http://127.0.0.1:3000"
+                        }
+                    }
+                    section "/health" {
+                        return 200 "OK"
+                    }
+                }
+            }
+        }
+    "#;
+
+    #[tokio::test]
+    async fn test_nested_sections_are_flattened() {
+        let services = load_services(CONFIG).await;
+        assert_eq!(services.len(), 1);
+
+        let api = &services[0];
+        assert_eq!(api.listeners, vec!["127.0.0.1:8080"]);
+
+        let v1 = api
+            .routes
+            .iter()
+            .find(|route| route.path == "/api/v1")
+            .expect("nested section");
+        assert_eq!(v1.matcher, "prefix");
+        assert_eq!(v1.upstream, "http://127.0.0.1:3000");
+        assert_eq!(
+            v1.chains,
+            vec![ChainView {
+                name: Some("security".to_string()),
+                items: vec![("filter", "motya.request.upsert-header".to_string())],
+            }]
+        );
+
+        let health = api
+            .routes
+            .iter()
+            .find(|route| route.path == "/health")
+            .expect("static section");
+        assert_eq!(health.upstream, "return 200 \"OK\"");
+        assert!(health.chains.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_render_formats() {
+        let services = load_services(CONFIG).await;
+
+        let table = render_table(&services);
+        assert!(
+            table.starts_with("Api on 127.0.0.1:8080\n  PATH"),
+            "{table}"
+        );
+        assert!(
+            table.contains("security: motya.request.upsert-header"),
+            "{table}"
+        );
+
+        let kdl = render_kdl(&services);
+        assert!(
+            kdl.contains("chain \"security\" { filter \"motya.request.upsert-header\"; }"),
+            "{kdl}"
+        );
+
+        let json = render_json(&services);
+        assert_eq!(json[0]["name"], "Api");
+        assert_eq!(json[0]["routes"][0]["chains"][0]["name"], "security");
+    }
+}
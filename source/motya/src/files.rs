@@ -1,42 +1,241 @@
 //! File Serving
 
-use std::ops::{Deref, DerefMut};
+use std::{
+    collections::BTreeMap,
+    ops::{Deref, DerefMut},
+    path::{Path, PathBuf},
+};
 
 use async_trait::async_trait;
-use motya_config::common_types::file_server::FileServerConfig;
+use bytes::Bytes;
+use http::{header, HeaderValue, Method, StatusCode};
+use motya_config::common_types::{
+    compression::CompressionAlgorithm, file_server::FileServerConfig,
+};
 use pandora_module_utils::{pingora::SessionWrapper, RequestFilter, RequestFilterResult};
 use pingora::{server::Server, upstreams::peer::HttpPeer, Result};
+use pingora_http::ResponseHeader;
 use pingora_proxy::{ProxyHttp, Session};
 use static_files_module::{StaticFilesConf, StaticFilesHandler};
 
-use crate::proxy::populate_listeners::populate_listners;
+use crate::proxy::{populate_listeners::populate_listners, proxy_protocol::ProxyProtocol};
 
 pub fn motya_file_server(
     conf: FileServerConfig,
     server: &Server,
 ) -> Box<dyn pingora::services::Service> {
     let fsconf = StaticFilesConf {
-        root: conf.base_path,
+        root: conf.base_path.clone(),
         canonicalize_uri: true,
-        index_file: Vec::new().into(),
+        index_file: conf.index_files.clone().into(),
         page_404: None,
-        precompressed: Vec::new().into(),
+        precompressed: conf
+            .precompressed
+            .iter()
+            .map(|algorithm| precompressed_algorithm(*algorithm))
+            .collect::<Vec<_>>()
+            .into(),
         ..Default::default()
     };
     let file_server = FileServer {
         server: StaticFilesHandler::try_from(fsconf)
             .expect("Creation of a Static File Service should not fail"),
+        root: conf.base_path,
+        index_files: conf.index_files,
+        autoindex: conf.autoindex,
+        mime_types: conf
+            .mime_types
+            .into_iter()
+            .map(|(ext, mime)| {
+                let mime = HeaderValue::from_str(&mime).expect("MIME types are validated on load");
+                (ext, mime)
+            })
+            .collect(),
+        cache_control: conf.cache_control.map(|value| {
+            HeaderValue::from_str(&value).expect("Cache-Control is validated on load")
+        }),
     };
-    let mut my_proxy =
-        pingora_proxy::http_proxy_service_with_name(&server.configuration, file_server, &conf.name);
+    let proxy = pingora_proxy::http_proxy(&server.configuration, file_server);
+    let mut my_proxy = pingora::services::listening::Service::new(
+        conf.name.clone(),
+        ProxyProtocol::new(proxy, &conf.listeners),
+    );
 
     populate_listners(&conf.listeners, &mut my_proxy);
 
     Box::new(my_proxy)
 }
 
+/// The name the file handler knows `algorithm` by.
+fn precompressed_algorithm(
+    algorithm: CompressionAlgorithm,
+) -> static_files_module::CompressionAlgorithm {
+    match algorithm {
+        CompressionAlgorithm::Brotli => static_files_module::CompressionAlgorithm::Brotli,
+        CompressionAlgorithm::Gzip => static_files_module::CompressionAlgorithm::Gzip,
+        CompressionAlgorithm::Zstd => static_files_module::CompressionAlgorithm::Zstandard,
+    }
+}
+
 pub struct FileServer {
     pub server: StaticFilesHandler,
+    pub root: Option<PathBuf>,
+    pub index_files: Vec<String>,
+    pub autoindex: bool,
+    /// Content types by lowercase extension, over the ones the handler guesses.
+    pub mime_types: BTreeMap<String, HeaderValue>,
+    pub cache_control: Option<HeaderValue>,
+}
+
+impl FileServer {
+    /// The directory a request for `path` lists, if it is one without index file.
+    ///
+    /// Paths of directories that don't end with a slash are left to the handler,
+    /// which redirects them first, so relative links of the listing resolve.
+    fn listed_directory(&self, path: &str) -> Option<PathBuf> {
+        if !self.autoindex || !path.ends_with('/') {
+            return None;
+        }
+        let dir = self.resolve(path)?;
+        let has_index = self.index_files.iter().any(|name| dir.join(name).is_file());
+        (dir.is_dir() && !has_index).then_some(dir)
+    }
+
+    /// The file or directory under the root that `path` names. Paths that try to
+    /// leave the root are not resolved, the handler rejects them.
+    fn resolve(&self, path: &str) -> Option<PathBuf> {
+        let mut resolved = self.root.clone()?;
+        for segment in percent_decode(path)?.split('/') {
+            match segment {
+                "" | "." => {}
+                ".." => return None,
+                segment => resolved.push(segment),
+            }
+        }
+        Some(resolved)
+    }
+
+    /// The content type configured for the file served for `path`.
+    fn content_type(&self, path: &str) -> Option<&HeaderValue> {
+        if self.mime_types.is_empty() {
+            return None;
+        }
+        let file = if path.ends_with('/') {
+            let dir = self.resolve(path)?;
+            let index = self
+                .index_files
+                .iter()
+                .find(|name| dir.join(name).is_file())?;
+            dir.join(index)
+        } else {
+            PathBuf::from(percent_decode(path)?)
+        };
+        let extension = file.extension()?.to_str()?.to_lowercase();
+        self.mime_types.get(&extension)
+    }
+
+    async fn write_listing(&self, session: &mut Session, path: &str, dir: &Path) -> Result<()> {
+        let body = Bytes::from(render_listing(path, dir).map_err(|e| {
+            tracing::warn!("Failed to list '{}': {e}", dir.display());
+            pingora::Error::new(pingora::ErrorType::HTTPStatus(403))
+        })?);
+
+        let mut response = ResponseHeader::build(StatusCode::OK, Some(2))?;
+        response.insert_header(header::CONTENT_TYPE, "text/html; charset=utf-8")?;
+        response.insert_header(header::CONTENT_LENGTH, body.len())?;
+
+        session
+            .downstream_session
+            .write_response_header(Box::new(response))
+            .await?;
+        session
+            .downstream_session
+            .write_response_body(body, true)
+            .await?;
+        Ok(())
+    }
+}
+
+/// Renders an HTML listing of `dir`, requested as `path`. Directories come first,
+/// and hidden entries are left out.
+fn render_listing(path: &str, dir: &Path) -> std::io::Result<String> {
+    let mut entries = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        if name.starts_with('.') {
+            continue;
+        }
+        let is_dir = entry.path().is_dir();
+        entries.push((!is_dir, name));
+    }
+    entries.sort();
+
+    let title = html_escape(&percent_decode(path).unwrap_or_else(|| path.to_string()));
+    let mut html = String::from("<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\">");
+    html.push_str(&format!("<title>Index of {title}</title></head>\n"));
+    html.push_str(&format!("<body>\n<h1>Index of {title}</h1>\n<ul>\n"));
+    if path != "/" {
+        html.push_str("<li><a href=\"../\">../</a></li>\n");
+    }
+    for (is_file, name) in entries {
+        let slash = if is_file { "" } else { "/" };
+        html.push_str(&format!(
+            "<li><a href=\"{}{slash}\">{}{slash}</a></li>\n",
+            percent_encode(&name),
+            html_escape(&name),
+        ));
+    }
+    html.push_str("</ul>\n</body>\n</html>\n");
+    Ok(html)
+}
+
+fn html_escape(raw: &str) -> String {
+    let mut escaped = String::with_capacity(raw.len());
+    for c in raw.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Encodes a file name for a relative link.
+fn percent_encode(name: &str) -> String {
+    let mut encoded = String::with_capacity(name.len());
+    for byte in name.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    encoded
+}
+
+/// Decodes a request path. Returns `None` for invalid escapes or UTF-8.
+fn percent_decode(path: &str) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
 }
 
 /// Implementation detail for integrating pingora-web-server's file server
@@ -45,6 +244,8 @@ pub struct FileServer {
 pub struct SesWrap<'a> {
     extensions: &'a mut http::Extensions,
     session: &'a mut Session,
+    content_type: Option<&'a HeaderValue>,
+    cache_control: Option<&'a HeaderValue>,
 }
 
 #[async_trait]
@@ -56,6 +257,32 @@ impl<'a> SessionWrapper for SesWrap<'a> {
     fn extensions_mut(&mut self) -> &mut http::Extensions {
         self.extensions
     }
+
+    /// Applies the configured content type and `Cache-Control` to files served.
+    async fn write_response_header(
+        &mut self,
+        mut resp: Box<ResponseHeader>,
+        end_of_stream: bool,
+    ) -> Result<()> {
+        let served = resp.status.is_success() || resp.status == StatusCode::NOT_MODIFIED;
+        if served {
+            // Answers to several ranges have a type of their own, each part keeps the
+            // one of the file.
+            let file_type = resp
+                .headers
+                .get(header::CONTENT_TYPE)
+                .is_some_and(|v| !v.as_bytes().starts_with(b"multipart/byteranges"));
+            if let Some(content_type) = self.content_type.filter(|_| file_type) {
+                resp.insert_header(header::CONTENT_TYPE, content_type.clone())?;
+            }
+            if let Some(cache_control) = self.cache_control {
+                resp.insert_header(header::CACHE_CONTROL, cache_control.clone())?;
+            }
+        }
+        self.session
+            .write_response_header(resp, end_of_stream)
+            .await
+    }
 }
 
 impl<'a> Deref for SesWrap<'a> {
@@ -93,9 +320,20 @@ impl ProxyHttp for FileServer {
     }
 
     async fn request_filter(&self, session: &mut Session, ctx: &mut Self::CTX) -> Result<bool> {
+        let path = session.req_header().uri.path().to_string();
+        if let Some(dir) = self.listed_directory(&path) {
+            let method = &session.req_header().method;
+            if method == Method::GET || method == Method::HEAD {
+                self.write_listing(session, &path, &dir).await?;
+                return Ok(true);
+            }
+        }
+
         let mut wrap = SesWrap {
             extensions: ctx,
             session,
+            content_type: self.content_type(&path),
+            cache_control: self.cache_control.as_ref(),
         };
         match self.server.request_filter(&mut wrap, &mut ()).await? {
             RequestFilterResult::ResponseSent => Ok(true),
@@ -103,3 +341,40 @@ impl ProxyHttp for FileServer {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percent_coding() {
+        assert_eq!(percent_decode("/a%20b/%C3%A9").as_deref(), Some("/a b/é"));
+        assert_eq!(percent_decode("/bad%2"), None);
+        assert_eq!(percent_decode("/bad%zz"), None);
+        assert_eq!(percent_decode("/%FF"), None);
+
+        assert_eq!(percent_encode("a b&c.txt"), "a%20b%26c.txt");
+    }
+
+    #[test]
+    fn test_render_listing() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("docs")).unwrap();
+        std::fs::write(dir.path().join("<b>.txt"), "").unwrap();
+        std::fs::write(dir.path().join("a.txt"), "").unwrap();
+        std::fs::write(dir.path().join(".env"), "").unwrap();
+
+        let html = render_listing("/files/", dir.path()).unwrap();
+        let links: Vec<&str> = html.lines().filter(|l| l.starts_with("<li>")).collect();
+        assert_eq!(
+            links,
+            [
+                "<li><a href=\"../\">../</a></li>",
+                "<li><a href=\"docs/\">docs/</a></li>",
+                "<li><a href=\"%3Cb%3E.txt\">&lt;b&gt;.txt</a></li>",
+                "<li><a href=\"a.txt\">a.txt</a></li>",
+            ]
+        );
+        assert!(html.contains("<title>Index of /files/</title>"));
+    }
+}
@@ -15,4 +15,15 @@ impl AsyncFs for TokioFs {
     async fn read_to_string(path: &Path) -> Result<String> {
         fs::read_to_string(path).await.into_diagnostic()
     }
+
+    async fn read_dir(path: &Path) -> Result<Vec<PathBuf>> {
+        let mut entries = fs::read_dir(path).await.into_diagnostic()?;
+        let mut files = Vec::new();
+        while let Some(entry) = entries.next_entry().await.into_diagnostic()? {
+            if entry.file_type().await.into_diagnostic()?.is_file() {
+                files.push(entry.path());
+            }
+        }
+        Ok(files)
+    }
 }
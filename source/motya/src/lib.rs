@@ -1,5 +1,11 @@
+pub mod admin;
 pub mod app_context;
+pub mod builder;
 pub mod config_aggregator;
+pub mod config_diff;
+pub mod config_render;
 pub mod files;
 pub mod fs_adapter;
 pub mod proxy;
+pub mod upgrade;
+pub mod validate;
@@ -1,18 +1,29 @@
-mod app_context;
-mod files;
-pub mod fs_adapter;
-mod proxy;
-
 use std::process;
 
 use clap::{CommandFactory, FromArgMatches};
-use motya_config::cli::cli_struct::{Cli, BANNER};
+use miette::IntoDiagnostic;
+use motya::{
+    app_context::{resolve_profile, AppContext},
+    config_diff, config_render,
+    proxy::{acme, drain::DrainSignal, panic_guard, watcher::cert_watcher},
+    validate,
+};
+use motya_config::{
+    cli::cli_struct::{Cli, Commands, ConfigCommand, SchemaCommand, BANNER},
+    kdl::{
+        models::root::RootDef,
+        schema::{
+            definitions::GetSchema, docs::render_docs, export::export_schema,
+            schema_context::SchemaContext,
+        },
+    },
+};
+use pingora::server::RunArgs;
 use tokio::runtime::Runtime;
 
-use crate::app_context::AppContext;
-
 fn main() -> miette::Result<()> {
     tracing_subscriber::fmt().with_thread_ids(true).init();
+    panic_guard::install_hook();
 
     let rt = Runtime::new().expect("Failed to build Tokio runtime");
 
@@ -21,20 +32,76 @@ fn main() -> miette::Result<()> {
         .get_matches();
     let cli_args = Cli::from_arg_matches(&command).expect("Failed to parse args");
 
+    let profile = resolve_profile(&cli_args);
+
+    if let Some(Commands::Validate { entry }) = &cli_args.command {
+        let report = rt.block_on(validate::validate(entry, profile.as_deref()));
+        report.print(entry);
+        process::exit(if report.is_ok() { 0 } else { 1 });
+    }
+
+    if let Some(Commands::Schema { command }) = &cli_args.command {
+        let root = RootDef::schemas(&mut SchemaContext::default()).remove(0);
+        match command {
+            SchemaCommand::Docs { format, lang } => print!("{}", render_docs(&root, *format, lang)),
+            SchemaCommand::Export { format } => {
+                let schema = export_schema(&root, *format).into_diagnostic()?;
+                println!("{schema:#}");
+            }
+        }
+        return Ok(());
+    }
+
+    if let Some(Commands::Config { command }) = &cli_args.command {
+        match command {
+            ConfigCommand::Diff { old, new } => {
+                let diff = rt.block_on(config_diff::diff_files(old, new, profile.as_deref()));
+                let diff = match diff {
+                    Ok(diff) => diff,
+                    Err(err) => {
+                        eprintln!("{err:?}");
+                        process::exit(2);
+                    }
+                };
+                if diff.is_empty() {
+                    println!("No changes");
+                    process::exit(0);
+                }
+                print!("{diff}");
+                process::exit(1);
+            }
+            ConfigCommand::Render { entry, format } => {
+                let (config, _) = rt.block_on(config_diff::load(entry, profile.as_deref()))?;
+                print!("{}", config_render::render(&config, *format));
+                return Ok(());
+            }
+        }
+    }
+
     let mut ctx = rt.block_on(AppContext::bootstrap(cli_args))?;
 
     let services = rt.block_on(ctx.build_services())?;
 
     tracing::info!("Server running (PID: {})", process::id());
 
+    let acme_config = ctx.acme_config();
+    let shutdown_grace = ctx.shutdown_grace();
     let (mut server, mut watcher) = ctx.ready();
 
     server.bootstrap();
     server.add_services(services);
 
     rt.spawn(async move { watcher.watch().await });
+    rt.spawn(cert_watcher::watch());
+    if let Some(acme_config) = acme_config {
+        rt.spawn(acme::run(acme_config));
+    }
 
     tracing::info!("Starting Pingora Server...");
 
-    server.run_forever();
+    server.run(RunArgs {
+        shutdown_signal: Box::new(DrainSignal::new(shutdown_grace)),
+    });
+
+    Ok(())
 }
@@ -0,0 +1,221 @@
+//! Connection rate caps of listeners (`max-conn-rate` on a listener).
+//!
+//! Every listener with a cap has a token bucket that holds up to one unit of
+//! the rate, e.g. 1000 connections for `"1000/s"`, and refills continuously. A
+//! connection that finds the bucket empty is closed as soon as it reaches the
+//! service, before any of it is read, so a flood of connections never gets to
+//! the PROXY header, the connection limits or the HTTP parser. On TLS listeners
+//! pingora has already finished the handshake by then.
+//!
+//! The closed connections are counted per listener, and [`render`] serves the
+//! counts next to the other metrics.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+use async_trait::async_trait;
+use motya_config::common_types::listeners::{ConnectionRate, ListenerKind, Listeners};
+use pingora::{
+    apps::ServerApp,
+    protocols::{GetSocketDigest, Stream},
+    server::ShutdownWatch,
+};
+
+use crate::proxy::metrics::escape_label;
+
+static LISTENERS: Mutex<BTreeMap<String, Arc<ListenerRate>>> = Mutex::new(BTreeMap::new());
+
+#[derive(Debug)]
+pub struct ListenerRate {
+    capacity: f64,
+    per_second: f64,
+    bucket: Mutex<Bucket>,
+    refused: AtomicU64,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl ListenerRate {
+    fn new(rate: &ConnectionRate) -> Self {
+        let capacity = f64::from(rate.count);
+        Self {
+            capacity,
+            per_second: rate.per_second(),
+            bucket: Mutex::new(Bucket {
+                tokens: capacity,
+                refilled: Instant::now(),
+            }),
+            refused: AtomicU64::new(0),
+        }
+    }
+
+    /// Takes a token for a new connection, or counts it as refused.
+    pub fn try_accept(&self) -> bool {
+        self.try_accept_at(Instant::now())
+    }
+
+    fn try_accept_at(&self, now: Instant) -> bool {
+        let mut bucket = self.bucket.lock().expect("listener rate lock poisoned");
+
+        let elapsed = now.saturating_duration_since(bucket.refilled).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.per_second).min(self.capacity);
+        bucket.refilled = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            drop(bucket);
+            self.refused.fetch_add(1, Ordering::Relaxed);
+            false
+        }
+    }
+}
+
+/// Wraps the HTTP app of a service, closing the connections past the
+/// `max-conn-rate` of the listener they came in on.
+pub struct AcceptRate<A> {
+    inner: Arc<A>,
+    ports: HashMap<u16, Arc<ListenerRate>>,
+}
+
+impl<A> AcceptRate<A> {
+    pub fn new(inner: A, listeners: &Listeners) -> Self {
+        let mut table = LISTENERS.lock().expect("listener rate lock poisoned");
+        let ports = listeners
+            .list_cfgs
+            .iter()
+            .filter_map(|list_cfg| match &list_cfg.source {
+                ListenerKind::Tcp {
+                    addr,
+                    max_conn_rate: Some(rate),
+                    ..
+                } => {
+                    let port = addr.parse::<std::net::SocketAddr>().ok()?.port();
+                    let limiter = Arc::new(ListenerRate::new(rate));
+                    table.insert(addr.clone(), limiter.clone());
+                    Some((port, limiter))
+                }
+                _ => None,
+            })
+            .collect();
+
+        Self {
+            inner: Arc::new(inner),
+            ports,
+        }
+    }
+
+    fn limiter(&self, stream: &Stream) -> Option<&ListenerRate> {
+        let port = stream.get_socket_digest()?.local_addr()?.as_inet()?.port();
+        self.ports.get(&port).map(Arc::as_ref)
+    }
+}
+
+#[async_trait]
+impl<A: ServerApp + Send + Sync + 'static> ServerApp for AcceptRate<A> {
+    async fn process_new(
+        self: &Arc<Self>,
+        stream: Stream,
+        shutdown: &ShutdownWatch,
+    ) -> Option<Stream> {
+        if self.limiter(&stream).is_some_and(|rate| !rate.try_accept()) {
+            tracing::debug!("Closing a connection past max-conn-rate");
+            return None;
+        }
+
+        self.inner.process_new(stream, shutdown).await
+    }
+
+    async fn cleanup(&self) {
+        self.inner.cleanup().await
+    }
+}
+
+/// Writes the refused connections of every listener with `max-conn-rate` in the
+/// Prometheus text format.
+pub fn render(out: &mut String) {
+    let listeners = LISTENERS
+        .lock()
+        .expect("listener rate lock poisoned")
+        .clone();
+
+    if listeners.is_empty() {
+        return;
+    }
+
+    out.push_str(
+        "# HELP motya_listener_refused_total Connections closed past the max-conn-rate of a listener.\n",
+    );
+    out.push_str("# TYPE motya_listener_refused_total counter\n");
+    for (addr, rate) in &listeners {
+        out.push_str(&format!(
+            "motya_listener_refused_total{{listener=\"{}\"}} {}\n",
+            escape_label(addr),
+            rate.refused.load(Ordering::Relaxed)
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use motya_config::common_types::listeners::ListenerConfig;
+
+    use super::*;
+
+    #[test]
+    fn test_bucket() {
+        let rate = ListenerRate::new(&"2/s".parse().unwrap());
+        let start = Instant::now();
+
+        assert!(rate.try_accept_at(start));
+        assert!(rate.try_accept_at(start));
+        assert!(!rate.try_accept_at(start));
+
+        // Half a second refills one token, never more than the rate.
+        assert!(rate.try_accept_at(start + Duration::from_millis(500)));
+        assert!(!rate.try_accept_at(start + Duration::from_millis(500)));
+        let later = start + Duration::from_secs(60);
+        assert!(rate.try_accept_at(later));
+        assert!(rate.try_accept_at(later));
+        assert!(!rate.try_accept_at(later));
+
+        assert_eq!(rate.refused.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn test_render() {
+        let listeners = Listeners {
+            list_cfgs: vec![ListenerConfig {
+                source: ListenerKind::Tcp {
+                    addr: "127.0.0.1:18443".to_string(),
+                    tls: None,
+                    offer_h2: false,
+                    proxy_protocol: false,
+                    max_conn_rate: Some("1/m".parse().unwrap()),
+                    socket: Default::default(),
+                },
+            }],
+        };
+        let accept_rate = AcceptRate::new((), &listeners);
+        let rate = &accept_rate.ports[&18443];
+        assert!(rate.try_accept());
+        assert!(!rate.try_accept());
+
+        let mut out = String::new();
+        render(&mut out);
+        assert!(out.contains("motya_listener_refused_total{listener=\"127.0.0.1:18443\"} 1\n"));
+    }
+}
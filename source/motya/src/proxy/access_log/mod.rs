@@ -0,0 +1,222 @@
+//! Access logging for proxy services with an `access-log` node.
+//!
+//! Every finished request is captured as an [`Entry`] in the `logging` phase,
+//! rendered as a template line or a JSON object, and queued on the service's
+//! [`Sink`]. A background task does the writing, so a slow disk never holds up a
+//! request; if it falls too far behind, lines are dropped and counted instead.
+
+pub mod sink;
+
+use std::time::Duration;
+
+use http::header;
+use miette::{Context, IntoDiagnostic};
+use motya_config::common_types::access_log::{
+    AccessLogConfig, AccessLogField, AccessLogFormat, AccessLogPart,
+};
+use pingora_proxy::Session;
+use serde_json::{Map, Value};
+use uuid::Uuid;
+
+use crate::proxy::{access_log::sink::Sink, MotyaContext};
+
+/// What is known about a request once it is done.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub remote_addr: Option<String>,
+    pub method: String,
+    pub path: String,
+    pub query: Option<String>,
+    pub host: Option<String>,
+    /// `None` when the connection ended before a response header was sent.
+    pub status: Option<u16>,
+    pub upstream_addr: Option<String>,
+    pub duration: Duration,
+    pub bytes_sent: usize,
+    pub request_id: Uuid,
+    pub user_agent: Option<String>,
+    pub referer: Option<String>,
+    /// Only known for routes with `grpc=#true`.
+    pub grpc_status: Option<u32>,
+}
+
+impl Entry {
+    pub fn capture(session: &Session, ctx: &MotyaContext) -> Self {
+        let req = session.req_header();
+        let header = |name: header::HeaderName| {
+            req.headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(String::from)
+        };
+
+        Self {
+            remote_addr: session
+                .client_addr()
+                .and_then(|addr| addr.as_inet())
+                .map(|addr| addr.ip().to_string()),
+            method: req.method.to_string(),
+            path: req.uri.path().to_string(),
+            query: req.uri.query().map(String::from),
+            host: req
+                .uri
+                .host()
+                .map(String::from)
+                .or_else(|| header(header::HOST)),
+            status: session.response_written().map(|res| res.status.as_u16()),
+            upstream_addr: ctx.upstream_addr.as_ref().map(ToString::to_string),
+            duration: ctx.started.elapsed(),
+            bytes_sent: session.body_bytes_sent(),
+            request_id: ctx.request_id,
+            user_agent: header(header::USER_AGENT),
+            referer: header(header::REFERER),
+            grpc_status: ctx.grpc_status,
+        }
+    }
+
+    /// The field as written in text lines; `None` is written as `-`.
+    fn text(&self, field: AccessLogField) -> Option<String> {
+        match field {
+            AccessLogField::RemoteAddr => self.remote_addr.clone(),
+            AccessLogField::Method => Some(self.method.clone()),
+            AccessLogField::Path => Some(self.path.clone()),
+            AccessLogField::Query => self.query.clone(),
+            AccessLogField::Host => self.host.clone(),
+            AccessLogField::Status => self.status.map(|status| status.to_string()),
+            AccessLogField::UpstreamAddr => self.upstream_addr.clone(),
+            AccessLogField::DurationMs => Some(format!("{:.3}", self.duration_ms())),
+            AccessLogField::BytesSent => Some(self.bytes_sent.to_string()),
+            AccessLogField::RequestId => Some(self.request_id.to_string()),
+            AccessLogField::UserAgent => self.user_agent.clone(),
+            AccessLogField::Referer => self.referer.clone(),
+            AccessLogField::GrpcStatus => self.grpc_status.map(|code| code.to_string()),
+        }
+    }
+
+    /// The field as written in JSON lines, numbers as numbers.
+    fn json(&self, field: AccessLogField) -> Value {
+        match field {
+            AccessLogField::Status => self.status.into(),
+            AccessLogField::DurationMs => self.duration_ms().into(),
+            AccessLogField::BytesSent => self.bytes_sent.into(),
+            AccessLogField::GrpcStatus => self.grpc_status.into(),
+            other => self.text(other).into(),
+        }
+    }
+
+    fn duration_ms(&self) -> f64 {
+        self.duration.as_secs_f64() * 1000.0
+    }
+}
+
+/// One log line for `entry`, without the trailing newline.
+pub fn render(format: &AccessLogFormat, entry: &Entry) -> String {
+    match format {
+        AccessLogFormat::Text(template) => {
+            let mut line = String::new();
+            for part in &template.parts {
+                match part {
+                    AccessLogPart::Literal(text) => line.push_str(text),
+                    AccessLogPart::Field(field) => {
+                        line.push_str(entry.text(*field).as_deref().unwrap_or("-"))
+                    }
+                }
+            }
+            line
+        }
+        AccessLogFormat::Json => {
+            let object: Map<String, Value> = AccessLogField::ALL
+                .iter()
+                .map(|field| (field.name().to_string(), entry.json(*field)))
+                .collect();
+            Value::Object(object).to_string()
+        }
+    }
+}
+
+/// The access log of one proxy service.
+pub struct AccessLog {
+    format: AccessLogFormat,
+    sink: Sink,
+}
+
+impl AccessLog {
+    /// Opens the configured sink. Must be called from within the runtime that
+    /// should run the writer task.
+    pub async fn start(config: &AccessLogConfig) -> miette::Result<Self> {
+        let (sink, _) = Sink::open(&config.sink)
+            .await
+            .into_diagnostic()
+            .wrap_err_with(|| format!("cannot open access log {}", config.sink))?;
+
+        Ok(Self {
+            format: config.format.clone(),
+            sink,
+        })
+    }
+
+    pub fn record(&self, entry: &Entry) {
+        self.sink.send(render(&self.format, entry));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use motya_config::common_types::access_log::AccessLogTemplate;
+
+    use super::*;
+
+    fn entry() -> Entry {
+        Entry {
+            remote_addr: Some("203.0.113.7".into()),
+            method: "GET".into(),
+            path: "/api/items".into(),
+            query: Some("page=2".into()),
+            host: Some("example.com".into()),
+            status: Some(200),
+            upstream_addr: Some("10.0.0.5:8080".into()),
+            duration: Duration::from_micros(12_345),
+            bytes_sent: 512,
+            request_id: Uuid::nil(),
+            user_agent: Some("curl/8.5.0".into()),
+            referer: None,
+            grpc_status: None,
+        }
+    }
+
+    #[test]
+    fn test_default_template_line() {
+        let format = AccessLogFormat::Text(AccessLogTemplate::default());
+
+        assert_eq!(
+            render(&format, &entry()),
+            "203.0.113.7 GET /api/items 200 10.0.0.5:8080 12.345"
+        );
+    }
+
+    #[test]
+    fn test_missing_values_are_dashes() {
+        let format = AccessLogFormat::Text("$status $upstream_addr $referer".parse().unwrap());
+        let entry = Entry {
+            status: None,
+            upstream_addr: None,
+            ..entry()
+        };
+
+        assert_eq!(render(&format, &entry), "- - -");
+    }
+
+    #[test]
+    fn test_json_line_has_every_field() {
+        let line = render(&AccessLogFormat::Json, &entry());
+        let object: Map<String, Value> = serde_json::from_str(&line).unwrap();
+
+        assert_eq!(object.len(), AccessLogField::ALL.len());
+        assert_eq!(object["status"], 200);
+        assert_eq!(object["bytes_sent"], 512);
+        assert_eq!(object["query"], "page=2");
+        assert_eq!(object["referer"], Value::Null);
+        assert_eq!(object["grpc_status"], Value::Null);
+        assert!((object["duration_ms"].as_f64().unwrap() - 12.345).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,137 @@
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use motya_config::common_types::access_log::AccessLogSink;
+use tokio::{
+    fs::OpenOptions,
+    io::{AsyncWrite, AsyncWriteExt, BufWriter},
+    sync::mpsc,
+    task::JoinHandle,
+};
+
+/// Lines waiting to be written before new ones are dropped.
+const CHANNEL_CAPACITY: usize = 8192;
+
+/// How often buffered lines are flushed when the log is quiet.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The writing end of an access log. Cheap to clone; the file or stdout is owned
+/// by a background task that exits once every clone is dropped.
+#[derive(Clone)]
+pub struct Sink {
+    tx: mpsc::Sender<String>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Sink {
+    /// Opens `target`, appending to it if it is a file, and starts the task
+    /// writing to it.
+    pub async fn open(target: &AccessLogSink) -> io::Result<(Self, JoinHandle<()>)> {
+        let out: Box<dyn AsyncWrite + Send + Unpin> = match target {
+            AccessLogSink::Stdout => Box::new(tokio::io::stdout()),
+            AccessLogSink::File(path) => Box::new(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await?,
+            ),
+        };
+
+        let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
+        let dropped = Arc::new(AtomicU64::new(0));
+
+        let task = tokio::spawn(write_lines(
+            BufWriter::new(out),
+            rx,
+            dropped.clone(),
+            target.to_string(),
+        ));
+
+        Ok((Self { tx, dropped }, task))
+    }
+
+    /// Queues a line without waiting. If the writer is behind, the line is
+    /// dropped and counted.
+    pub fn send(&self, line: String) {
+        if self.tx.try_send(line).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+async fn write_lines<W: AsyncWrite + Unpin>(
+    mut out: BufWriter<W>,
+    mut rx: mpsc::Receiver<String>,
+    dropped: Arc<AtomicU64>,
+    name: String,
+) {
+    let mut flush = tokio::time::interval(FLUSH_INTERVAL);
+
+    loop {
+        tokio::select! {
+            line = rx.recv() => {
+                let Some(line) = line else { break };
+
+                let written = match out.write_all(line.as_bytes()).await {
+                    Ok(()) => out.write_all(b"\n").await,
+                    Err(err) => Err(err),
+                };
+                if let Err(err) = written {
+                    tracing::warn!("Cannot write to access log {name}: {err}");
+                }
+            }
+            _ = flush.tick() => {
+                if let Err(err) = out.flush().await {
+                    tracing::warn!("Cannot flush access log {name}: {err}");
+                }
+
+                let lost = dropped.swap(0, Ordering::Relaxed);
+                if lost > 0 {
+                    tracing::warn!("Access log {name} fell behind and dropped {lost} lines");
+                }
+            }
+        }
+    }
+
+    if let Err(err) = out.flush().await {
+        tracing::warn!("Cannot flush access log {name}: {err}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_lines_are_appended_to_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("access.log");
+        std::fs::write(&path, "existing\n").unwrap();
+
+        let (sink, task) = Sink::open(&AccessLogSink::File(path.clone()))
+            .await
+            .unwrap();
+        sink.send("first".into());
+        sink.clone().send("second".into());
+        drop(sink);
+        task.await.unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(contents, "existing\nfirst\nsecond\n");
+    }
+
+    #[tokio::test]
+    async fn test_missing_directory_fails_to_open() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = AccessLogSink::File(dir.path().join("missing").join("access.log"));
+
+        assert!(Sink::open(&target).await.is_err());
+    }
+}
@@ -0,0 +1,452 @@
+//! Certificates from an ACME server (Let's Encrypt by default), for `system.acme`.
+//!
+//! The certificate lives in the `storage` directory, where TLS listeners point
+//! their `cert-path`/`key-path`. On first start there is nothing to serve yet, so
+//! [`prepare`] writes a self-signed placeholder that lets the listeners come up.
+//! [`run`] then orders the real certificate over HTTP-01, with every proxy service
+//! answering the challenge under [`CHALLENGE_PREFIX`], and renews it before it
+//! expires. New files are picked up by the certificate watcher.
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::Path,
+    sync::{LazyLock, RwLock},
+    time::Duration,
+};
+
+use instant_acme::{
+    Account, AccountCredentials, AuthorizationStatus, ChallengeType, Identifier, NewAccount,
+    NewOrder, Order, OrderStatus,
+};
+use miette::{miette, Context, IntoDiagnostic, Result};
+use motya_config::common_types::acme::AcmeConfig;
+use pingora::tls::{
+    asn1::Asn1Time,
+    ec::{EcGroup, EcKey},
+    hash::MessageDigest,
+    nid::Nid,
+    pkey::{PKey, Private},
+    stack::Stack,
+    x509::{extension::SubjectAlternativeName, X509NameBuilder, X509ReqBuilder, X509},
+};
+
+use crate::proxy::watcher::cert_watcher;
+
+/// Path prefix of HTTP-01 challenge requests; the token follows it.
+pub const CHALLENGE_PREFIX: &str = "/.well-known/acme-challenge/";
+
+/// How often the certificate's expiry is checked.
+const CHECK_INTERVAL: Duration = Duration::from_secs(12 * 60 * 60);
+
+/// How long to wait before trying again after a failed order.
+const RETRY_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Key authorizations of the challenges in flight, by token.
+static CHALLENGES: LazyLock<RwLock<HashMap<String, String>>> = LazyLock::new(Default::default);
+
+/// The body to answer a challenge request for `path` with, if `path` names a
+/// challenge this process is waiting on.
+pub fn challenge_response(path: &str) -> Option<String> {
+    let token = path.strip_prefix(CHALLENGE_PREFIX)?;
+    CHALLENGES.read().unwrap().get(token).cloned()
+}
+
+/// Makes sure the storage directory holds a certificate and key the listeners can
+/// load, writing a self-signed placeholder if it does not.
+pub fn prepare(config: &AcmeConfig) -> Result<()> {
+    fs::create_dir_all(&config.storage)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("cannot create ACME storage {:?}", config.storage))?;
+
+    if config.cert_path().is_file() && config.key_path().is_file() {
+        return Ok(());
+    }
+
+    tracing::info!(
+        "No certificate in {:?} yet, serving a self-signed one until ACME issues it",
+        config.storage
+    );
+
+    let (cert, key) = self_signed(&config.domains, 1)?;
+    store(config, &cert, &key)
+}
+
+/// Orders a certificate whenever the stored one is a placeholder, does not cover
+/// the configured domains, or expires within `renew-before`. Never returns.
+pub async fn run(config: AcmeConfig) {
+    loop {
+        let wait = match renewal_due(&config) {
+            Ok(false) => CHECK_INTERVAL,
+            Ok(true) => match issue(&config).await {
+                Ok(()) => {
+                    tracing::info!("ACME issued a certificate for {:?}", config.domains);
+                    cert_watcher::reload_all();
+                    CHECK_INTERVAL
+                }
+                Err(err) => {
+                    tracing::error!(
+                        "ACME order for {:?} failed, retrying in {RETRY_INTERVAL:?}: {err:?}",
+                        config.domains
+                    );
+                    RETRY_INTERVAL
+                }
+            },
+            Err(err) => {
+                tracing::error!("Cannot check the ACME certificate: {err:?}");
+                RETRY_INTERVAL
+            }
+        };
+
+        tokio::time::sleep(wait).await;
+    }
+}
+
+fn renewal_due(config: &AcmeConfig) -> Result<bool> {
+    let pem = fs::read(config.cert_path()).into_diagnostic()?;
+    let cert = X509::from_pem(&pem).into_diagnostic()?;
+
+    // Only the placeholder is signed by its own key.
+    if cert
+        .verify(&cert.public_key().into_diagnostic()?)
+        .into_diagnostic()?
+    {
+        return Ok(true);
+    }
+
+    let names: Vec<String> = cert
+        .subject_alt_names()
+        .map(|names| {
+            names
+                .iter()
+                .filter_map(|n| n.dnsname().map(String::from))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    if config.domains.iter().any(|domain| !names.contains(domain)) {
+        return Ok(true);
+    }
+
+    let left = Asn1Time::days_from_now(0)
+        .into_diagnostic()?
+        .diff(cert.not_after())
+        .into_diagnostic()?;
+    let left = i64::from(left.days) * 24 * 60 * 60 + i64::from(left.secs);
+
+    Ok(left < config.renew_before.as_secs() as i64)
+}
+
+async fn issue(config: &AcmeConfig) -> Result<()> {
+    let account = account(config).await?;
+
+    let identifiers: Vec<Identifier> = config
+        .domains
+        .iter()
+        .map(|domain| Identifier::Dns(domain.clone()))
+        .collect();
+
+    let mut order = account
+        .new_order(&NewOrder {
+            identifiers: &identifiers,
+        })
+        .await
+        .into_diagnostic()?;
+
+    let tokens = publish_challenges(&mut order).await?;
+    let ready = wait_for(&mut order, OrderStatus::Ready).await;
+
+    let mut challenges = CHALLENGES.write().unwrap();
+    for token in tokens {
+        challenges.remove(&token);
+    }
+    drop(challenges);
+    ready?;
+
+    let key = PKey::from_ec_key(p256_key()?).into_diagnostic()?;
+    order
+        .finalize(&csr(&config.domains, &key)?)
+        .await
+        .into_diagnostic()?;
+
+    let chain = loop {
+        if let Some(chain) = order.certificate().await.into_diagnostic()? {
+            break chain;
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    };
+
+    store(
+        config,
+        chain.as_bytes(),
+        &key.private_key_to_pem_pkcs8().into_diagnostic()?,
+    )
+}
+
+/// Loads the account from storage, registering a new one on first use.
+async fn account(config: &AcmeConfig) -> Result<Account> {
+    let path = config.account_path();
+
+    match fs::read(&path) {
+        Ok(json) => {
+            let credentials: AccountCredentials =
+                serde_json::from_slice(&json)
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("invalid ACME account {path:?}"))?;
+            Account::from_credentials(credentials)
+                .await
+                .into_diagnostic()
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            let contact: Vec<String> = config
+                .email
+                .iter()
+                .map(|email| format!("mailto:{email}"))
+                .collect();
+            let contact: Vec<&str> = contact.iter().map(String::as_str).collect();
+
+            let (account, credentials) = Account::create(
+                &NewAccount {
+                    contact: &contact,
+                    terms_of_service_agreed: true,
+                    only_return_existing: false,
+                },
+                &config.directory,
+                None,
+            )
+            .await
+            .into_diagnostic()?;
+
+            let json = serde_json::to_vec_pretty(&credentials).into_diagnostic()?;
+            write_private(&path, &json)?;
+
+            tracing::info!("Registered ACME account at {}", config.directory);
+            Ok(account)
+        }
+        Err(err) => Err(err).into_diagnostic(),
+    }
+}
+
+/// Publishes the HTTP-01 answer of every pending authorization and tells the
+/// server to check it. Returns the published tokens.
+async fn publish_challenges(order: &mut Order) -> Result<Vec<String>> {
+    let mut tokens = Vec::new();
+
+    for authz in order.authorizations().await.into_diagnostic()? {
+        match authz.status {
+            AuthorizationStatus::Pending => {}
+            AuthorizationStatus::Valid => continue,
+            status => {
+                return Err(miette!(
+                    "authorization for {:?} is {status:?}",
+                    authz.identifier
+                ))
+            }
+        }
+
+        let challenge = authz
+            .challenges
+            .iter()
+            .find(|c| c.r#type == ChallengeType::Http01)
+            .ok_or_else(|| miette!("no HTTP-01 challenge offered for {:?}", authz.identifier))?;
+
+        let key_authorization = order.key_authorization(challenge);
+        CHALLENGES.write().unwrap().insert(
+            challenge.token.clone(),
+            key_authorization.as_str().to_string(),
+        );
+        tokens.push(challenge.token.clone());
+
+        order
+            .set_challenge_ready(&challenge.url)
+            .await
+            .into_diagnostic()?;
+    }
+
+    Ok(tokens)
+}
+
+/// Polls the order, backing off, until it reaches `status`.
+async fn wait_for(order: &mut Order, status: OrderStatus) -> Result<()> {
+    let mut delay = Duration::from_millis(250);
+
+    for _ in 0..10 {
+        tokio::time::sleep(delay).await;
+
+        let state = order.refresh().await.into_diagnostic()?;
+        if state.status == status {
+            return Ok(());
+        }
+        if state.status == OrderStatus::Invalid {
+            return Err(miette!(
+                "the ACME server rejected the order; are the domains served by this proxy on port 80?"
+            ));
+        }
+
+        delay *= 2;
+    }
+
+    Err(miette!("the order did not become {status:?} in time"))
+}
+
+/// Writes the key, then the certificate, each replaced in one rename so the
+/// certificate watcher never reads half a file.
+fn store(config: &AcmeConfig, cert: &[u8], key: &[u8]) -> Result<()> {
+    write_private(&config.key_path(), key)?;
+
+    let tmp = config.cert_path().with_extension("pem.tmp");
+    fs::write(&tmp, cert).into_diagnostic()?;
+    fs::rename(&tmp, config.cert_path()).into_diagnostic()
+}
+
+fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+    use std::{fs::OpenOptions, io::Write, os::unix::fs::OpenOptionsExt};
+
+    let tmp = path.with_extension("tmp");
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&tmp)
+        .and_then(|mut file| file.write_all(contents))
+        .into_diagnostic()
+        .wrap_err_with(|| format!("cannot write {tmp:?}"))?;
+
+    fs::rename(&tmp, path).into_diagnostic()
+}
+
+fn p256_key() -> Result<EcKey<Private>> {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).into_diagnostic()?;
+    EcKey::generate(&group).into_diagnostic()
+}
+
+/// A DER certificate signing request for `domains`.
+fn csr(domains: &[String], key: &PKey<Private>) -> Result<Vec<u8>> {
+    let mut req = X509ReqBuilder::new().into_diagnostic()?;
+    req.set_pubkey(key).into_diagnostic()?;
+
+    let mut names = SubjectAlternativeName::new();
+    for domain in domains {
+        names.dns(domain);
+    }
+    let names = names.build(&req.x509v3_context(None)).into_diagnostic()?;
+
+    let mut extensions = Stack::new().into_diagnostic()?;
+    extensions.push(names).into_diagnostic()?;
+    req.add_extensions(&extensions).into_diagnostic()?;
+
+    req.sign(key, MessageDigest::sha256()).into_diagnostic()?;
+    req.build().to_der().into_diagnostic()
+}
+
+/// A self-signed certificate for `domains`, valid for `days`, and its key, both
+/// PEM encoded. The first domain is the subject.
+pub(crate) fn self_signed(domains: &[String], days: u32) -> Result<(Vec<u8>, Vec<u8>)> {
+    let key = PKey::from_ec_key(p256_key()?).into_diagnostic()?;
+
+    let mut subject = X509NameBuilder::new().into_diagnostic()?;
+    if let Some(domain) = domains.first() {
+        subject
+            .append_entry_by_text("CN", domain)
+            .into_diagnostic()?;
+    }
+    let subject = subject.build();
+
+    let mut cert = X509::builder().into_diagnostic()?;
+    cert.set_version(2).into_diagnostic()?;
+    cert.set_subject_name(&subject).into_diagnostic()?;
+    cert.set_issuer_name(&subject).into_diagnostic()?;
+    cert.set_pubkey(&key).into_diagnostic()?;
+    cert.set_not_before(&Asn1Time::days_from_now(0).into_diagnostic()?)
+        .into_diagnostic()?;
+    cert.set_not_after(&Asn1Time::days_from_now(days).into_diagnostic()?)
+        .into_diagnostic()?;
+
+    if !domains.is_empty() {
+        let mut names = SubjectAlternativeName::new();
+        for domain in domains {
+            names.dns(domain);
+        }
+        let names = names
+            .build(&cert.x509v3_context(None, None))
+            .into_diagnostic()?;
+        cert.append_extension(names).into_diagnostic()?;
+    }
+
+    cert.sign(&key, MessageDigest::sha256()).into_diagnostic()?;
+
+    Ok((
+        cert.build().to_pem().into_diagnostic()?,
+        key.private_key_to_pem_pkcs8().into_diagnostic()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn config(storage: PathBuf) -> AcmeConfig {
+        AcmeConfig {
+            domains: vec!["example.com".into(), "www.example.com".into()],
+            email: None,
+            storage,
+            directory: "https://acme.invalid/directory".into(),
+            renew_before: Duration::from_secs(30 * 24 * 60 * 60),
+        }
+    }
+
+    #[test]
+    fn test_challenge_response() {
+        CHALLENGES
+            .write()
+            .unwrap()
+            .insert("token-1".into(), "token-1.thumbprint".into());
+
+        assert_eq!(
+            challenge_response("/.well-known/acme-challenge/token-1").as_deref(),
+            Some("token-1.thumbprint")
+        );
+        assert_eq!(
+            challenge_response("/.well-known/acme-challenge/other"),
+            None
+        );
+        assert_eq!(challenge_response("/token-1"), None);
+    }
+
+    #[test]
+    fn test_placeholder_is_written_once_and_due_for_renewal() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = config(dir.path().join("acme"));
+
+        prepare(&config).unwrap();
+        let placeholder = fs::read(config.cert_path()).unwrap();
+        assert!(config.key_path().is_file());
+        assert!(renewal_due(&config).unwrap());
+
+        prepare(&config).unwrap();
+        assert_eq!(fs::read(config.cert_path()).unwrap(), placeholder);
+    }
+
+    #[test]
+    fn test_renewal_due_when_domains_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = config(dir.path().to_path_buf());
+
+        prepare(&config).unwrap();
+        config.domains.push("api.example.com".into());
+
+        assert!(renewal_due(&config).unwrap());
+    }
+
+    #[test]
+    fn test_csr_lists_every_domain() {
+        let key = PKey::from_ec_key(p256_key().unwrap()).unwrap();
+        let der = csr(&["example.com".into(), "www.example.com".into()], &key).unwrap();
+
+        let req = pingora::tls::x509::X509Req::from_der(&der).unwrap();
+        assert!(req.verify(&key).unwrap());
+    }
+}
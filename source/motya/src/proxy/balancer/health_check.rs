@@ -0,0 +1,194 @@
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Weak},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use motya_config::common_types::balancer::TcpHealthCheckConfig;
+use pingora::{Error, ErrorType, Result};
+use pingora_load_balancing::{
+    health_check::HealthCheck,
+    selection::{BackendIter, BackendSelection},
+    Backend, LoadBalancer,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use crate::proxy::balancer::slow_start::SlowStart;
+
+/// Probes a TCP endpoint: connect, optionally write `send`, and optionally
+/// check that the reply starts with `expect`. The whole probe is bounded by
+/// the configured timeout.
+///
+/// Does not know anything about HTTP, so it can be used for any TCP upstream.
+pub async fn probe_tcp(addr: SocketAddr, config: &TcpHealthCheckConfig) -> std::io::Result<()> {
+    tokio::time::timeout(config.timeout, async {
+        let mut stream = TcpStream::connect(addr).await?;
+
+        if let Some(payload) = &config.send {
+            stream.write_all(payload).await?;
+        }
+
+        if let Some(expected) = &config.expect {
+            let mut buf = vec![0u8; expected.len()];
+            stream.read_exact(&mut buf).await?;
+
+            if &buf != expected {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "unexpected response to health-check probe",
+                ));
+            }
+        }
+
+        Ok(())
+    })
+    .await
+    .map_err(|_| {
+        std::io::Error::new(std::io::ErrorKind::TimedOut, "health-check probe timed out")
+    })?
+}
+
+pub struct TcpProbe {
+    config: TcpHealthCheckConfig,
+}
+
+impl TcpProbe {
+    pub fn new(config: TcpHealthCheckConfig) -> Self {
+        Self { config }
+    }
+}
+
+#[async_trait]
+impl HealthCheck for TcpProbe {
+    async fn check(&self, target: &Backend) -> Result<()> {
+        let Some(addr) = target.addr.as_inet() else {
+            return Error::e_explain(
+                ErrorType::ConnectError,
+                "TCP health-check requires an inet backend address",
+            );
+        };
+
+        probe_tcp(*addr, &self.config).await.map_err(|e| {
+            tracing::debug!("Health-check of {addr} failed: {e}");
+            Error::because(ErrorType::ConnectError, "TCP health-check failed", e)
+        })
+    }
+
+    fn health_threshold(&self, _success: bool) -> usize {
+        1
+    }
+}
+
+/// Runs the health checks of `lb` every `interval` until the balancer is dropped,
+/// e.g. when a config reload replaces the router. `slow_start` sees the results
+/// of every round.
+pub fn spawn_health_checks<S>(
+    lb: &Arc<LoadBalancer<S>>,
+    interval: Duration,
+    slow_start: Option<Arc<SlowStart>>,
+) where
+    S: BackendSelection + Send + Sync + 'static,
+    S::Iter: BackendIter,
+{
+    let weak: Weak<LoadBalancer<S>> = Arc::downgrade(lb);
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+
+        loop {
+            ticker.tick().await;
+
+            let Some(lb) = weak.upgrade() else {
+                break;
+            };
+            lb.backends().run_health_check(false).await;
+            if let Some(slow_start) = &slow_start {
+                slow_start.observe(lb.backends());
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    fn config() -> TcpHealthCheckConfig {
+        TcpHealthCheckConfig {
+            timeout: Duration::from_millis(500),
+            ..TcpHealthCheckConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_probe_succeeds_on_connect() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        assert!(probe_tcp(addr, &config()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_probe_fails_when_nothing_listens() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        assert!(probe_tcp(addr, &config()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_probe_send_expect() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut buf = [0u8; 6];
+                socket.read_exact(&mut buf).await.unwrap();
+                let reply: &[u8] = if &buf == b"PING\r\n" {
+                    b"+PONG\r\n"
+                } else {
+                    b"-ERR\r\n"
+                };
+                socket.write_all(reply).await.unwrap();
+            }
+        });
+
+        let ok = TcpHealthCheckConfig {
+            send: Some(b"PING\r\n".to_vec()),
+            expect: Some(b"+PONG".to_vec()),
+            ..config()
+        };
+        assert!(probe_tcp(addr, &ok).await.is_ok());
+
+        let mismatch = TcpHealthCheckConfig {
+            expect: Some(b"+OK".to_vec()),
+            ..ok
+        };
+        assert!(probe_tcp(addr, &mismatch).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_probe_times_out_on_silent_peer() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let cfg = TcpHealthCheckConfig {
+            timeout: Duration::from_millis(50),
+            expect: Some(b"hello".to_vec()),
+            ..config()
+        };
+
+        let err = probe_tcp(addr, &cfg).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+        drop(listener);
+    }
+}
@@ -0,0 +1,312 @@
+//! Balancing on the live load of the backends (`selection "LeastConnections"`
+//! and `selection "PeakEWMA"`).
+//!
+//! Every backend picked for a request holds a [`LoadGuard`] in the context of
+//! the request, which counts the request as in flight on that backend until the
+//! request is done. For `PeakEWMA` the guard also reports how long the backend
+//! took to send its response headers. The average of those latencies follows a
+//! slower response at once and decays towards faster ones over about
+//! [`DECAY`], so a backend that starts to stall is avoided within a request.
+//!
+//! A pick takes the healthy backend with the lowest cost, divided by its
+//! `weight`: the requests in flight for `LeastConnections`, and the average
+//! latency times one more than the requests in flight for `PeakEWMA`. Backends
+//! with the same cost are taken in turn.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+use pingora::protocols::l4::socket::SocketAddr;
+use pingora_load_balancing::Backend;
+
+/// How long a latency takes to weigh a third of what it did, without new ones.
+pub const DECAY: Duration = Duration::from_secs(10);
+
+/// The latency a backend is assumed to have before it answered anything, so
+/// that a new backend isn't sent every request until it does.
+const MIN_LATENCY: Duration = Duration::from_millis(1);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoadCost {
+    LeastConnections,
+    PeakEwma,
+}
+
+/// The load of the backends of one upstream.
+pub struct BackendLoads {
+    cost: LoadCost,
+    loads: RwLock<HashMap<SocketAddr, Arc<BackendLoad>>>,
+    next: AtomicUsize,
+}
+
+#[derive(Debug)]
+struct BackendLoad {
+    inflight: AtomicU64,
+    latency: Mutex<Ewma>,
+}
+
+#[derive(Debug)]
+struct Ewma {
+    seconds: f64,
+    updated: Instant,
+}
+
+/// Counts a request as in flight on its backend until it is dropped.
+pub struct LoadGuard {
+    load: Arc<BackendLoad>,
+    started: Instant,
+}
+
+impl BackendLoads {
+    pub fn new(cost: LoadCost) -> Self {
+        Self {
+            cost,
+            loads: RwLock::new(HashMap::new()),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Picks the backend with the lowest cost among those `accept` lets through.
+    pub fn select(
+        &self,
+        backends: &BTreeSet<Backend>,
+        accept: impl Fn(&Backend) -> bool,
+    ) -> Option<Backend> {
+        self.select_at(backends, accept, Instant::now())
+    }
+
+    fn select_at(
+        &self,
+        backends: &BTreeSet<Backend>,
+        accept: impl Fn(&Backend) -> bool,
+        now: Instant,
+    ) -> Option<Backend> {
+        if backends.is_empty() {
+            return None;
+        }
+
+        self.forget_removed(backends);
+        let loads = self.loads.read().expect("backend loads lock poisoned");
+        let start = self.next.fetch_add(1, Ordering::Relaxed) % backends.len();
+
+        let mut best: Option<(&Backend, f64)> = None;
+        for backend in backends.iter().cycle().skip(start).take(backends.len()) {
+            if !accept(backend) {
+                continue;
+            }
+
+            let cost = self.cost(loads.get(&backend.addr).map(Arc::as_ref), now)
+                / backend.weight.max(1) as f64;
+            if best.is_none_or(|(_, lowest)| cost < lowest) {
+                best = Some((backend, cost));
+            }
+        }
+
+        best.map(|(backend, _)| backend.clone())
+    }
+
+    fn cost(&self, load: Option<&BackendLoad>, now: Instant) -> f64 {
+        let inflight = load.map_or(0, |load| load.inflight.load(Ordering::Relaxed)) as f64;
+
+        match self.cost {
+            LoadCost::LeastConnections => inflight,
+            LoadCost::PeakEwma => {
+                let latency = load.map_or(0.0, |load| load.latency(now));
+                latency.max(MIN_LATENCY.as_secs_f64()) * (inflight + 1.0)
+            }
+        }
+    }
+
+    /// Drops the load of backends that discovery took away, once their
+    /// requests are done.
+    fn forget_removed(&self, backends: &BTreeSet<Backend>) {
+        let stale = {
+            let loads = self.loads.read().expect("backend loads lock poisoned");
+            loads.len() > backends.len()
+        };
+        if stale {
+            let mut loads = self.loads.write().expect("backend loads lock poisoned");
+            loads.retain(|addr, load| {
+                backends.iter().any(|backend| &backend.addr == addr)
+                    || load.inflight.load(Ordering::Relaxed) > 0
+            });
+        }
+    }
+
+    /// Counts a request as in flight on `addr` until the guard is dropped.
+    pub fn start(&self, addr: &SocketAddr) -> LoadGuard {
+        let known = self
+            .loads
+            .read()
+            .expect("backend loads lock poisoned")
+            .get(addr)
+            .cloned();
+
+        let load = known.unwrap_or_else(|| {
+            self.loads
+                .write()
+                .expect("backend loads lock poisoned")
+                .entry(addr.clone())
+                .or_insert_with(|| Arc::new(BackendLoad::new()))
+                .clone()
+        });
+
+        load.inflight.fetch_add(1, Ordering::Relaxed);
+        LoadGuard {
+            load,
+            started: Instant::now(),
+        }
+    }
+}
+
+impl BackendLoad {
+    fn new() -> Self {
+        Self {
+            inflight: AtomicU64::new(0),
+            latency: Mutex::new(Ewma {
+                seconds: 0.0,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    /// The average latency, decayed for the time since the last response so
+    /// that a backend that was avoided for a peak gets another chance.
+    fn latency(&self, now: Instant) -> f64 {
+        let ewma = self.latency.lock().expect("backend latency lock poisoned");
+        ewma.seconds * decay(now.saturating_duration_since(ewma.updated))
+    }
+
+    fn observe(&self, latency: Duration, now: Instant) {
+        let mut ewma = self.latency.lock().expect("backend latency lock poisoned");
+        let sample = latency.as_secs_f64();
+
+        if sample > ewma.seconds {
+            ewma.seconds = sample;
+        } else {
+            let weight = decay(now.saturating_duration_since(ewma.updated));
+            ewma.seconds = ewma.seconds * weight + sample * (1.0 - weight);
+        }
+        ewma.updated = now;
+    }
+}
+
+fn decay(elapsed: Duration) -> f64 {
+    (-elapsed.as_secs_f64() / DECAY.as_secs_f64()).exp()
+}
+
+impl LoadGuard {
+    /// Records the time from the pick to the response headers of the backend.
+    pub fn record_response(&self) {
+        self.load.observe(self.started.elapsed(), Instant::now());
+    }
+}
+
+impl Drop for LoadGuard {
+    fn drop(&mut self) {
+        self.load.inflight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backends(weights: &[usize]) -> BTreeSet<Backend> {
+        weights
+            .iter()
+            .enumerate()
+            .map(|(idx, weight)| {
+                Backend::new_with_weight(&format!("127.0.0.1:{}", 7001 + idx), *weight).unwrap()
+            })
+            .collect()
+    }
+
+    fn port(backend: Option<Backend>) -> u16 {
+        backend.unwrap().addr.as_inet().unwrap().port() - 7000
+    }
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::Inet(format!("127.0.0.1:{}", 7000 + port).parse().unwrap())
+    }
+
+    #[test]
+    fn test_least_connections() {
+        let backends = backends(&[1, 1, 2]);
+        let loads = BackendLoads::new(LoadCost::LeastConnections);
+
+        // Idle backends are taken in turn.
+        let picks: Vec<u16> = (0..3)
+            .map(|_| port(loads.select(&backends, |_| true)))
+            .collect();
+        assert_eq!(picks, vec![1, 2, 3]);
+
+        let _first = loads.start(&addr(1));
+        let second = loads.start(&addr(2));
+        // With twice the weight, three requests on the third backend still cost
+        // it more than one costs the others.
+        let _third: Vec<LoadGuard> = (0..3).map(|_| loads.start(&addr(3))).collect();
+        for _ in 0..3 {
+            assert_ne!(port(loads.select(&backends, |_| true)), 3);
+        }
+
+        drop(second);
+        for _ in 0..3 {
+            assert_eq!(port(loads.select(&backends, |_| true)), 2);
+        }
+        assert_eq!(port(loads.select(&backends, |b| b.addr != addr(2))), 1);
+        assert!(loads.select(&backends, |_| false).is_none());
+    }
+
+    #[test]
+    fn test_peak_ewma() {
+        let backends = backends(&[1, 1]);
+        let loads = BackendLoads::new(LoadCost::PeakEwma);
+        let now = Instant::now();
+
+        let slow = loads.start(&addr(1)).load.clone();
+        let fast = loads.start(&addr(2)).load.clone();
+
+        // A peak is taken at once.
+        slow.observe(Duration::from_millis(10), now);
+        fast.observe(Duration::from_millis(10), now);
+        slow.observe(Duration::from_millis(500), now);
+        assert_eq!(slow.latency(now), 0.5);
+        for _ in 0..4 {
+            assert_eq!(port(loads.select_at(&backends, |_| true, now)), 2);
+        }
+
+        // A faster response only pulls the average down in part.
+        slow.observe(Duration::from_millis(10), now + DECAY);
+        let latency = slow.latency(now + DECAY);
+        assert!(latency > 0.01 && latency < 0.5, "{latency}");
+
+        // Requests in flight make the fast backend cost more than the slow one.
+        let _busy: Vec<LoadGuard> = (0..100).map(|_| loads.start(&addr(2))).collect();
+        assert_eq!(port(loads.select_at(&backends, |_| true, now + DECAY)), 1);
+    }
+
+    #[test]
+    fn test_removed_backends() {
+        let loads = BackendLoads::new(LoadCost::LeastConnections);
+
+        let guard = loads.start(&addr(1));
+        loads.start(&addr(2));
+        assert_eq!(loads.loads.read().unwrap().len(), 2);
+
+        // The first backend is gone from discovery, but still has a request.
+        let remaining = backends(&[1, 1]).into_iter().skip(1).collect();
+        assert_eq!(port(loads.select(&remaining, |_| true)), 2);
+        assert_eq!(loads.loads.read().unwrap().len(), 2);
+
+        drop(guard);
+        loads.select(&remaining, |_| true);
+        assert_eq!(loads.loads.read().unwrap().len(), 1);
+    }
+}
@@ -1,51 +1,140 @@
+use std::sync::Arc;
+
 use motya_config::common_types::key_template::HashOp;
+use pingora::protocols::l4::socket::SocketAddr;
 use pingora_load_balancing::{
-    prelude::RoundRobin,
     selection::{consistent::KetamaHashing, FNVHash, Random},
     Backend, LoadBalancer,
 };
 use smallvec::SmallVec;
 
-use crate::proxy::key_selector::{hash, KeySelector, KeySourceContext};
+use crate::proxy::{
+    balancer::{
+        least_loaded::{BackendLoads, LoadGuard},
+        outlier::OutlierDetector,
+        slow_start::SlowStart,
+        weighted::WeightedRoundRobin,
+    },
+    key_selector::{hash, KeySelector, KeySourceContext},
+};
 
+pub mod health_check;
 pub mod key_selector_builder;
+pub mod least_loaded;
+pub mod outlier;
+pub mod slow_start;
+pub mod weighted;
 
 pub struct Balancer {
     pub selector: Option<KeySelector>,
     pub balancer_type: BalancerType,
     pub hasher: HashOp,
+    pub slow_start: Option<Arc<SlowStart>>,
+    pub outliers: Option<OutlierDetector>,
 }
 
 impl Balancer {
-    pub fn select_backend<C: KeySourceContext>(&self, ctx: &C) -> Option<Backend> {
-        if let Some(selector) = &self.selector {
+    /// Picks a healthy backend for the request, passing over the ones in `avoid`
+    /// (those a retried request already failed on) unless nothing else is left.
+    pub fn select_backend<C: KeySourceContext>(
+        &self,
+        ctx: &C,
+        avoid: &[SocketAddr],
+    ) -> Option<Backend> {
+        let key = if let Some(selector) = &self.selector {
             let mut buffer: SmallVec<[u8; 256]> = SmallVec::new();
 
-            let key = if selector.select(ctx, &mut buffer) {
+            if selector.select(ctx, &mut buffer) {
                 hash(&self.hasher, &buffer)
             } else {
                 hash(&HashOp::XxHash64(0), &[])
-            };
-
-            self.select(&key.to_le_bytes())
+            }
         } else {
-            self.select(&0u64.to_le_bytes())
+            0
+        };
+        let key = key.to_le_bytes();
+
+        if avoid.is_empty() {
+            return self.select(&key, &[]);
+        }
+        self.select(&key, avoid).or_else(|| self.select(&key, &[]))
+    }
+
+    /// The bytes the key selector read from the request, before they are hashed,
+    /// for `debug-trace`. `None` without a selector or when the key is missing.
+    pub fn key_bytes<C: KeySourceContext>(&self, ctx: &C) -> Option<Vec<u8>> {
+        let selector = self.selector.as_ref()?;
+        let mut buffer: SmallVec<[u8; 256]> = SmallVec::new();
+
+        selector.select(ctx, &mut buffer).then(|| buffer.to_vec())
+    }
+
+    /// Counts a request as in flight on the backend at `addr` until the guard is
+    /// dropped, for the selections that pick on load.
+    pub fn start_request(&self, addr: &SocketAddr) -> Option<LoadGuard> {
+        match &self.balancer_type {
+            BalancerType::LeastConnections(_, loads) | BalancerType::PeakEwma(_, loads) => {
+                Some(loads.start(addr))
+            }
+            _ => None,
+        }
+    }
+
+    /// Counts a failed request against the backend at `addr`, for
+    /// `outlier-detection`.
+    pub fn record_failure(&self, addr: &SocketAddr) {
+        if let Some(outliers) = &self.outliers {
+            outliers.record_failure(addr);
         }
     }
 
-    fn select(&self, key: &[u8]) -> Option<Backend> {
+    fn select(&self, key: &[u8], avoid: &[SocketAddr]) -> Option<Backend> {
+        // Backends that are ejected or still warming up give way to the others,
+        // but take the request when nothing else would.
+        if self.outliers.is_some() || self.slow_start.is_some() {
+            let picked = self.pick(key, |backend| {
+                !avoid.contains(&backend.addr)
+                    && self
+                        .outliers
+                        .as_ref()
+                        .is_none_or(|outliers| !outliers.is_ejected(&backend.addr))
+                    && self
+                        .slow_start
+                        .as_ref()
+                        .is_none_or(|slow_start| slow_start.admits(backend))
+            });
+            if picked.is_some() {
+                return picked;
+            }
+        }
+        self.pick(key, |backend| !avoid.contains(&backend.addr))
+    }
+
+    fn pick(&self, key: &[u8], allowed: impl Fn(&Backend) -> bool) -> Option<Backend> {
+        let accept = |backend: &Backend, healthy: bool| healthy && allowed(backend);
+
         match &self.balancer_type {
-            BalancerType::FNVHash(b) => b.select(key, 256),
-            BalancerType::Random(b) => b.select(key, 256),
-            BalancerType::KetamaHashing(b) => b.select(key, 256),
-            BalancerType::RoundRobin(b) => b.select(key, 256),
+            BalancerType::FNVHash(b) => b.select_with(key, 256, accept),
+            BalancerType::Random(b) => b.select_with(key, 256, accept),
+            BalancerType::KetamaHashing(b) => b.select_with(key, 256, accept),
+            BalancerType::RoundRobin(b) => b.select_with(key, 256, accept),
+            BalancerType::LeastConnections(b, loads) | BalancerType::PeakEwma(b, loads) => {
+                let backends = b.backends();
+                loads.select(&backends.get_backend(), |backend| {
+                    accept(backend, backends.ready(backend))
+                })
+            }
         }
     }
 }
 
 pub enum BalancerType {
-    RoundRobin(LoadBalancer<RoundRobin>),
-    Random(LoadBalancer<Random>),
-    FNVHash(LoadBalancer<FNVHash>),
-    KetamaHashing(LoadBalancer<KetamaHashing>),
+    RoundRobin(Arc<LoadBalancer<WeightedRoundRobin>>),
+    Random(Arc<LoadBalancer<Random>>),
+    FNVHash(Arc<LoadBalancer<FNVHash>>),
+    KetamaHashing(Arc<LoadBalancer<KetamaHashing>>),
+    /// The load balancer keeps the backends and their health, and the loads pick
+    /// among them.
+    LeastConnections(Arc<LoadBalancer<WeightedRoundRobin>>, BackendLoads),
+    PeakEwma(Arc<LoadBalancer<WeightedRoundRobin>>, BackendLoads),
 }
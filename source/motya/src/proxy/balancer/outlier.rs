@@ -0,0 +1,144 @@
+//! Passive health checks of the backends of a balancer (`outlier-detection` in
+//! a `load-balance`).
+//!
+//! The proxy reports every connection that fails and every `5xx` response to
+//! the balancer that picked the backend. A backend that fails `errors` times
+//! within `window` is ejected for `ejection`: picks pass it over for the other
+//! backends, unless none of them is left. It takes requests again once the
+//! ejection ends, with its count of failures started over.
+//!
+//! This complements `health-check`, which only sees whether a backend accepts
+//! connections, with what the requests actually got.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::Instant,
+};
+
+use motya_config::common_types::balancer::OutlierDetectionConfig;
+use pingora::protocols::l4::socket::SocketAddr;
+
+pub struct OutlierDetector {
+    config: OutlierDetectionConfig,
+    backends: Mutex<HashMap<SocketAddr, Failures>>,
+}
+
+#[derive(Default)]
+struct Failures {
+    /// When the failures within the window happened, oldest first.
+    recent: VecDeque<Instant>,
+    ejected_until: Option<Instant>,
+}
+
+impl OutlierDetector {
+    pub fn new(config: OutlierDetectionConfig) -> Self {
+        Self {
+            config,
+            backends: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Counts a failed request to `addr`, ejecting it once it failed too often.
+    pub fn record_failure(&self, addr: &SocketAddr) {
+        self.record_failure_at(addr, Instant::now());
+    }
+
+    fn record_failure_at(&self, addr: &SocketAddr, now: Instant) {
+        let mut backends = self
+            .backends
+            .lock()
+            .expect("outlier detection lock poisoned");
+        let failures = backends.entry(addr.clone()).or_default();
+
+        if failures.ejected_until.is_some_and(|until| now < until) {
+            return;
+        }
+
+        failures.recent.push_back(now);
+        while failures
+            .recent
+            .front()
+            .is_some_and(|at| now.saturating_duration_since(*at) > self.config.window)
+        {
+            failures.recent.pop_front();
+        }
+
+        if failures.recent.len() >= self.config.errors as usize {
+            tracing::warn!(
+                "Ejecting backend {addr} for {:?} after {} failures within {:?}",
+                self.config.ejection,
+                failures.recent.len(),
+                self.config.window
+            );
+            failures.recent.clear();
+            failures.ejected_until = Some(now + self.config.ejection);
+        }
+    }
+
+    pub fn is_ejected(&self, addr: &SocketAddr) -> bool {
+        self.is_ejected_at(addr, Instant::now())
+    }
+
+    fn is_ejected_at(&self, addr: &SocketAddr, now: Instant) -> bool {
+        let mut backends = self
+            .backends
+            .lock()
+            .expect("outlier detection lock poisoned");
+        let Some(ejected_until) = backends.get(addr).and_then(|f| f.ejected_until) else {
+            return false;
+        };
+
+        if now < ejected_until {
+            return true;
+        }
+        // The failures were cleared when it was ejected, so it starts over.
+        backends.remove(addr);
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::Inet(format!("127.0.0.1:{port}").parse().unwrap())
+    }
+
+    #[test]
+    fn test_ejection() {
+        let detector = OutlierDetector::new(OutlierDetectionConfig {
+            errors: 3,
+            window: Duration::from_secs(10),
+            ejection: Duration::from_secs(30),
+        });
+        let (a, b) = (addr(7001), addr(7002));
+        let start = Instant::now();
+
+        // Failures further apart than the window don't add up.
+        detector.record_failure_at(&a, start);
+        detector.record_failure_at(&a, start + Duration::from_secs(5));
+        detector.record_failure_at(&a, start + Duration::from_secs(20));
+        detector.record_failure_at(&a, start + Duration::from_secs(22));
+        detector.record_failure_at(&b, start + Duration::from_secs(22));
+        assert!(!detector.is_ejected_at(&a, start + Duration::from_secs(22)));
+
+        let ejected = start + Duration::from_secs(25);
+        detector.record_failure_at(&a, ejected);
+        assert!(detector.is_ejected_at(&a, ejected));
+        assert!(!detector.is_ejected_at(&b, ejected));
+
+        // Failures while ejected aren't counted against it afterwards.
+        detector.record_failure_at(&a, ejected + Duration::from_secs(1));
+        let back = ejected + Duration::from_secs(30);
+        assert!(!detector.is_ejected_at(&a, back));
+        detector.record_failure_at(&a, back);
+        detector.record_failure_at(&a, back);
+        assert!(!detector.is_ejected_at(&a, back));
+        detector.record_failure_at(&a, back);
+        assert!(detector.is_ejected_at(&a, back));
+    }
+}
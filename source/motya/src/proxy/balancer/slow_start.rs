@@ -0,0 +1,147 @@
+//! Warming up backends that join a balancer (`slow-start` in a `load-balance`).
+//!
+//! The backend set is observed once it is first filled, after every update of
+//! the discovery and after every round of health checks. Backends that fill an
+//! empty set count as warm, as there is nothing for them to give way to. One that
+//! shows up next to others, or comes back healthy, warms up over the window: a pick lets it through with a chance that grows from 0 to 1,
+//! and otherwise moves on to the next backend the selection offers, so its share
+//! of requests ramps up from nothing to what its `weight` gives it.
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use pingora::protocols::l4::socket::SocketAddr;
+use pingora_load_balancing::{Backend, Backends};
+
+pub struct SlowStart {
+    window: Duration,
+    backends: Mutex<HashMap<SocketAddr, Warmup>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Warmup {
+    Down,
+    Since(Instant),
+    Warm,
+}
+
+impl SlowStart {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            backends: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Notes which backends of the set are ready to take requests.
+    pub fn observe(&self, backends: &Backends) {
+        let set = backends.get_backend();
+        self.observe_at(
+            set.iter().map(|backend| (backend, backends.ready(backend))),
+            Instant::now(),
+        );
+    }
+
+    fn observe_at<'a>(&self, backends: impl Iterator<Item = (&'a Backend, bool)>, now: Instant) {
+        let mut states = self.backends.lock().expect("slow start lock poisoned");
+        let first = states.is_empty();
+
+        let mut seen = HashMap::new();
+        for (backend, ready) in backends {
+            let previous = states.get(&backend.addr).copied();
+            let warmup = match (previous, ready) {
+                (_, false) => Warmup::Down,
+                (None, true) if first => Warmup::Warm,
+                (None | Some(Warmup::Down), true) => Warmup::Since(now),
+                (Some(warmup), true) => warmup,
+            };
+            seen.insert(backend.addr.clone(), warmup);
+        }
+        *states = seen;
+    }
+
+    /// Whether a pick may take `backend`, which gets likelier as it warms up.
+    pub fn admits(&self, backend: &Backend) -> bool {
+        let share = self.share_at(&backend.addr, Instant::now());
+        share >= 1.0 || fastrand::f64() < share
+    }
+
+    /// The part of its full share of requests `addr` gets, from 0 to 1.
+    fn share_at(&self, addr: &SocketAddr, now: Instant) -> f64 {
+        let mut states = self.backends.lock().expect("slow start lock poisoned");
+        let Some(warmup) = states.get_mut(addr) else {
+            return 1.0;
+        };
+
+        match *warmup {
+            Warmup::Since(since) => {
+                let share =
+                    now.saturating_duration_since(since).as_secs_f64() / self.window.as_secs_f64();
+                if share >= 1.0 {
+                    *warmup = Warmup::Warm;
+                }
+                share.min(1.0)
+            }
+            Warmup::Down | Warmup::Warm => 1.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backend(port: u16) -> Backend {
+        Backend::new(&format!("127.0.0.1:{port}")).unwrap()
+    }
+
+    #[test]
+    fn test_warmup() {
+        let slow_start = SlowStart::new(Duration::from_secs(60));
+        let (a, b) = (backend(7001), backend(7002));
+        let start = Instant::now();
+
+        // The first backends are warm.
+        slow_start.observe_at(std::iter::empty(), start);
+        slow_start.observe_at([(&a, true)].into_iter(), start);
+        assert_eq!(slow_start.share_at(&a.addr, start), 1.0);
+
+        // A new one ramps up over the window.
+        slow_start.observe_at([(&a, true), (&b, true)].into_iter(), start);
+        assert_eq!(slow_start.share_at(&b.addr, start), 0.0);
+        let quarter = start + Duration::from_secs(15);
+        assert_eq!(slow_start.share_at(&b.addr, quarter), 0.25);
+        let later = start + Duration::from_secs(90);
+        assert_eq!(slow_start.share_at(&b.addr, later), 1.0);
+        assert!(slow_start.admits(&b));
+
+        // Further observations keep the warm state.
+        slow_start.observe_at([(&a, true), (&b, true)].into_iter(), later);
+        assert_eq!(slow_start.share_at(&b.addr, later), 1.0);
+    }
+
+    #[test]
+    fn test_unhealthy_and_removed_backends_warm_up_again() {
+        let slow_start = SlowStart::new(Duration::from_secs(10));
+        let (a, b) = (backend(7001), backend(7002));
+        let start = Instant::now();
+        slow_start.observe_at([(&a, true), (&b, true)].into_iter(), start);
+
+        let back = start + Duration::from_secs(30);
+        slow_start.observe_at([(&a, false), (&b, true)].into_iter(), start);
+        slow_start.observe_at([(&a, true), (&b, true)].into_iter(), back);
+        assert_eq!(slow_start.share_at(&a.addr, back), 0.0);
+        assert_eq!(slow_start.share_at(&b.addr, back), 1.0);
+
+        // Discovery took b away and gave it back.
+        slow_start.observe_at([(&a, true)].into_iter(), back);
+        slow_start.observe_at([(&a, true), (&b, true)].into_iter(), back);
+        assert_eq!(
+            slow_start.share_at(&b.addr, back + Duration::from_secs(5)),
+            0.5
+        );
+    }
+}
@@ -0,0 +1,163 @@
+use std::{
+    collections::BTreeSet,
+    sync::{Arc, Mutex},
+};
+
+use pingora_load_balancing::{
+    selection::{BackendIter, BackendSelection},
+    Backend,
+};
+
+/// Smooth weighted round-robin, the algorithm nginx uses for `upstream` weights.
+///
+/// Every pick adds each backend's weight to its running score, takes the backend
+/// with the highest score and lowers that score by the total weight. A backend
+/// with `weight=3` next to one with `weight=1` gets three of every four requests,
+/// interleaved (`a a b a`, not `a a a b`), and equal weights fall back to plain
+/// round-robin.
+pub struct WeightedRoundRobin {
+    backends: Box<[Backend]>,
+    weights: Box<[i64]>,
+    total: i64,
+    scores: Mutex<Vec<i64>>,
+}
+
+impl WeightedRoundRobin {
+    fn pick(&self) -> Option<usize> {
+        if self.total == 0 {
+            return None;
+        }
+
+        let mut scores = self
+            .scores
+            .lock()
+            .expect("round-robin scores lock poisoned");
+
+        let mut best = 0;
+        for (idx, weight) in self.weights.iter().enumerate() {
+            scores[idx] += weight;
+            if scores[idx] > scores[best] {
+                best = idx;
+            }
+        }
+        scores[best] -= self.total;
+
+        Some(best)
+    }
+}
+
+impl BackendSelection for WeightedRoundRobin {
+    type Iter = WeightedRoundRobinIter;
+    type Config = ();
+
+    fn build(backends: &BTreeSet<Backend>) -> Self {
+        let backends: Box<[Backend]> = backends.iter().cloned().collect();
+        let weights: Box<[i64]> = backends.iter().map(|b| b.weight as i64).collect();
+
+        Self {
+            total: weights.iter().sum(),
+            scores: Mutex::new(vec![0; backends.len()]),
+            backends,
+            weights,
+        }
+    }
+
+    fn iter(self: &Arc<Self>, _key: &[u8]) -> Self::Iter {
+        WeightedRoundRobinIter {
+            start: self.pick(),
+            selection: Arc::clone(self),
+            step: 0,
+        }
+    }
+}
+
+/// Yields the weighted pick first, then the backends after it in order, so the
+/// load balancer can skip over unhealthy ones.
+pub struct WeightedRoundRobinIter {
+    selection: Arc<WeightedRoundRobin>,
+    start: Option<usize>,
+    step: usize,
+}
+
+impl BackendIter for WeightedRoundRobinIter {
+    fn next(&mut self) -> Option<&Backend> {
+        let backends = &self.selection.backends;
+        let start = self.start?;
+
+        if self.step >= backends.len() {
+            return None;
+        }
+
+        let idx = (start + self.step) % backends.len();
+        self.step += 1;
+        backends.get(idx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn selection(weights: &[usize]) -> Arc<WeightedRoundRobin> {
+        let backends = weights
+            .iter()
+            .enumerate()
+            .map(|(idx, weight)| {
+                Backend::new_with_weight(&format!("127.0.0.1:{}", 7001 + idx), *weight).unwrap()
+            })
+            .collect();
+
+        Arc::new(WeightedRoundRobin::build(&backends))
+    }
+
+    fn picks(selection: &Arc<WeightedRoundRobin>, count: usize) -> Vec<u16> {
+        (0..count)
+            .map(|_| {
+                let mut iter = selection.iter(b"");
+                let backend = iter.next().expect("a backend is picked");
+                backend.addr.as_inet().unwrap().port() - 7000
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_distribution_is_proportional_to_weights() {
+        let selection = selection(&[3, 1, 2]);
+        let picks = picks(&selection, 600);
+
+        let count = |port| picks.iter().filter(|p| **p == port).count();
+        assert_eq!(count(1), 300);
+        assert_eq!(count(2), 100);
+        assert_eq!(count(3), 200);
+    }
+
+    #[test]
+    fn test_picks_are_interleaved() {
+        let selection = selection(&[5, 1, 1]);
+        assert_eq!(picks(&selection, 7), vec![1, 1, 2, 1, 3, 1, 1]);
+    }
+
+    #[test]
+    fn test_equal_weights_are_plain_round_robin() {
+        let selection = selection(&[1, 1, 1]);
+        assert_eq!(picks(&selection, 6), vec![1, 2, 3, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_fallbacks_cover_every_backend_once() {
+        let selection = selection(&[1, 4]);
+
+        let mut iter = selection.iter(b"");
+        let mut seen = Vec::new();
+        while let Some(backend) = iter.next() {
+            seen.push(backend.addr.as_inet().unwrap().port() - 7000);
+        }
+        assert_eq!(seen, vec![2, 1]);
+    }
+
+    #[test]
+    fn test_no_backends() {
+        let selection = Arc::new(WeightedRoundRobin::build(&BTreeSet::new()));
+        assert!(selection.iter(b"").next().is_none());
+    }
+}
@@ -0,0 +1,437 @@
+//! Response caching for routes with a `cache` directive.
+//!
+//! Responses are stored in one in-memory store shared by every route, keyed by
+//! the listener, method, host and path (with query), plus the request values of
+//! the headers the upstream lists in `Vary`. The store is bounded by an LRU of
+//! [`CACHE_CAPACITY`] bytes; the route's `max-body` bounds a single response.
+//!
+//! Clients in the route's `bypass-from` networks can skip the cache with
+//! `Cache-Control: no-cache`, and routes with `debug-headers` report the outcome
+//! of the lookup in `X-Cache`.
+
+use std::{net::IpAddr, sync::LazyLock, time::SystemTime};
+
+use http::{header, HeaderMap, Method, StatusCode};
+use motya_config::common_types::connectors::CacheConfig;
+use pingora::protocols::l4::socket::SocketAddr;
+use pingora_cache::{
+    cache_control::CacheControl, eviction::simple_lru, key::HashBinary, CacheKey, CacheMeta,
+    CachePhase, MemCache, NoCacheReason, RespCacheable, VarianceBuilder,
+};
+use pingora_http::{RequestHeader, ResponseHeader};
+use pingora_proxy::Session;
+
+/// Headers added by routes with `debug-headers`.
+pub const X_CACHE: &str = "x-cache";
+pub const X_CACHE_AGE: &str = "x-cache-age";
+pub const X_CACHE_TTL: &str = "x-cache-ttl";
+
+/// Total size of all cached responses, across every route.
+pub const CACHE_CAPACITY: usize = 256 * 1024 * 1024;
+
+/// Statuses that may be stored without explicit freshness from the upstream.
+/// `206` is left out: partial content is only useful to the client that asked.
+const CACHEABLE_STATUSES: &[StatusCode] = &[
+    StatusCode::OK,
+    StatusCode::NON_AUTHORITATIVE_INFORMATION,
+    StatusCode::NO_CONTENT,
+    StatusCode::MULTIPLE_CHOICES,
+    StatusCode::MOVED_PERMANENTLY,
+    StatusCode::PERMANENT_REDIRECT,
+    StatusCode::NOT_FOUND,
+    StatusCode::GONE,
+];
+
+static STORAGE: LazyLock<MemCache> = LazyLock::new(MemCache::new);
+static EVICTION: LazyLock<simple_lru::Manager> =
+    LazyLock::new(|| simple_lru::Manager::new(CACHE_CAPACITY));
+
+/// Turns on the cache phases for the current request.
+///
+/// Called from `request_cache_filter`, once the route is known. Only `GET` and
+/// `HEAD` are looked up; anything else, and requests that are [`bypassed`], go
+/// straight to the upstream.
+pub fn enable(session: &mut Session, config: &CacheConfig) {
+    let method = &session.req_header().method;
+    if method != Method::GET && method != Method::HEAD {
+        return;
+    }
+
+    let client = session
+        .client_addr()
+        .and_then(|addr| addr.as_inet())
+        .map(|addr| addr.ip());
+    if bypassed(config, client, session.req_header()) {
+        return;
+    }
+
+    session
+        .cache
+        .enable(&*STORAGE, Some(&*EVICTION), None, None, None);
+    session.cache.set_max_file_size_bytes(config.max_body);
+}
+
+/// Whether a request skips the cache: it asks to with `Cache-Control: no-cache`,
+/// and comes from one of the route's `bypass-from` networks. The header is
+/// ignored from anyone else, so that it can't be used to drive every request to
+/// the upstream.
+pub fn bypassed(config: &CacheConfig, client: Option<IpAddr>, request: &RequestHeader) -> bool {
+    let Some(client) = client else {
+        return false;
+    };
+    let client = match client {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(client, IpAddr::V4),
+        v4 => v4,
+    };
+
+    if !config.bypass_from.iter().any(|net| net.contains(&client)) {
+        return false;
+    }
+
+    request
+        .headers
+        .get_all(header::CACHE_CONTROL)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|directive| directive.trim().eq_ignore_ascii_case("no-cache"))
+}
+
+/// The `X-Cache` value for a cache phase: `HIT` when the response was served
+/// from the store, `MISS` when it was looked up but fetched from the upstream,
+/// and `BYPASS` when the cache was not used at all.
+pub fn status(phase: CachePhase) -> &'static str {
+    match phase {
+        CachePhase::Hit
+        | CachePhase::Stale
+        | CachePhase::StaleUpdating
+        | CachePhase::Revalidated => "HIT",
+        CachePhase::Miss | CachePhase::Expired => "MISS",
+        _ => "BYPASS",
+    }
+}
+
+/// Adds `X-Cache`, and for a response that has an entry in the store,
+/// `X-Cache-Age` and `X-Cache-TTL` in whole seconds.
+pub fn add_debug_headers(
+    response: &mut ResponseHeader,
+    phase: CachePhase,
+    meta: Option<&CacheMeta>,
+    now: SystemTime,
+) -> pingora::Result<()> {
+    response.insert_header(X_CACHE, status(phase))?;
+
+    if let Some(meta) = meta {
+        let age = now.duration_since(meta.created()).unwrap_or_default();
+        let ttl = meta.fresh_until().duration_since(now).unwrap_or_default();
+        response.insert_header(X_CACHE_AGE, age.as_secs().to_string())?;
+        response.insert_header(X_CACHE_TTL, ttl.as_secs().to_string())?;
+    }
+
+    Ok(())
+}
+
+/// The primary key of a request. `Vary` is handled separately, by [`variance`].
+pub fn key(listener: Option<&SocketAddr>, request: &RequestHeader) -> CacheKey {
+    let namespace = listener.map(|addr| addr.to_string()).unwrap_or_default();
+
+    let host = request
+        .uri
+        .host()
+        .or_else(|| {
+            request
+                .headers
+                .get(header::HOST)
+                .and_then(|v| v.to_str().ok())
+        })
+        .unwrap_or_default();
+
+    let path = request
+        .uri
+        .path_and_query()
+        .map(|pq| pq.as_str())
+        .unwrap_or("/");
+
+    CacheKey::new(namespace, format!("{} {host}{path}", request.method), "")
+}
+
+/// Decides whether an upstream response may be stored, and for how long.
+///
+/// The route's `ttl` is used as is; the upstream can still opt a response out
+/// with `Cache-Control: no-store`, `no-cache` or `private`. Responses that set a
+/// cookie, or answer a request carrying `Authorization`, are never shared.
+pub fn cacheability(
+    config: &CacheConfig,
+    request: &RequestHeader,
+    response: &ResponseHeader,
+) -> RespCacheable {
+    use RespCacheable::Uncacheable;
+
+    if !CACHEABLE_STATUSES.contains(&response.status) {
+        return Uncacheable(NoCacheReason::OriginNotCache);
+    }
+
+    if request.headers.contains_key(header::AUTHORIZATION)
+        || response.headers.contains_key(header::SET_COOKIE)
+    {
+        return Uncacheable(NoCacheReason::OriginNotCache);
+    }
+
+    let opted_out = CacheControl::from_resp_headers(response)
+        .is_some_and(|cc| cc.no_store() || cc.no_cache() || cc.private());
+
+    if opted_out || vary_names(&response.headers).any(|name| name == "*") {
+        return Uncacheable(NoCacheReason::OriginNotCache);
+    }
+
+    let too_large = response
+        .headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok())
+        .is_some_and(|len| len > config.max_body);
+
+    if too_large {
+        return Uncacheable(NoCacheReason::ResponseTooLarge);
+    }
+
+    let now = SystemTime::now();
+    RespCacheable::Cacheable(CacheMeta::new(
+        now + config.ttl,
+        now,
+        0,
+        0,
+        response.clone(),
+    ))
+}
+
+/// The variance of a cached response for `request`: a hash of the request's
+/// values for every header named in the response's `Vary`, or `None` when the
+/// response does not vary.
+pub fn variance(meta: &CacheMeta, request: &RequestHeader) -> Option<HashBinary> {
+    let names: Vec<String> = vary_names(meta.headers())
+        .filter(|name| !name.is_empty())
+        .map(str::to_ascii_lowercase)
+        .collect();
+
+    let mut variance = VarianceBuilder::new();
+    for name in &names {
+        let value = request
+            .headers
+            .get(name.as_str())
+            .map(|v| v.as_bytes())
+            .unwrap_or_default();
+        variance.add_value(name, value);
+    }
+
+    variance.finalize()
+}
+
+fn vary_names(headers: &HeaderMap) -> impl Iterator<Item = &str> {
+    headers
+        .get_all(header::VARY)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use pingora_cache::key::CacheHashKey;
+
+    use super::*;
+
+    fn config() -> CacheConfig {
+        CacheConfig {
+            ttl: Duration::from_secs(30),
+            max_body: 1024,
+            bypass_from: vec!["10.0.0.0/8".parse().unwrap()],
+            debug_headers: true,
+        }
+    }
+
+    fn no_cache(request: &mut RequestHeader, value: &str) {
+        request.insert_header(header::CACHE_CONTROL, value).unwrap();
+    }
+
+    fn request(method: &str, path: &str) -> RequestHeader {
+        let mut req = RequestHeader::build(method, path.as_bytes(), None).unwrap();
+        req.insert_header(header::HOST, "example.com").unwrap();
+        req
+    }
+
+    fn response(status: u16) -> ResponseHeader {
+        ResponseHeader::build(status, None).unwrap()
+    }
+
+    fn stored(request: &RequestHeader, response: &ResponseHeader) -> bool {
+        matches!(
+            cacheability(&config(), request, response),
+            RespCacheable::Cacheable(_)
+        )
+    }
+
+    #[test]
+    fn test_key_covers_method_and_query() {
+        let combined = |req: &RequestHeader| key(None, req).combined();
+
+        let get = combined(&request("GET", "/items?page=1"));
+        assert_eq!(get, combined(&request("GET", "/items?page=1")));
+        assert_ne!(get, combined(&request("GET", "/items?page=2")));
+        assert_ne!(get, combined(&request("HEAD", "/items?page=1")));
+    }
+
+    #[test]
+    fn test_ok_response_is_stored_for_the_ttl() {
+        let result = cacheability(&config(), &request("GET", "/"), &response(200));
+
+        let RespCacheable::Cacheable(meta) = result else {
+            panic!("a plain 200 should be cacheable");
+        };
+        let ttl = meta.fresh_until().duration_since(meta.created()).unwrap();
+        assert_eq!(ttl, Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_errors_and_partial_content_are_not_stored() {
+        let req = request("GET", "/");
+
+        assert!(!stored(&req, &response(500)));
+        assert!(!stored(&req, &response(206)));
+        assert!(stored(&req, &response(404)));
+    }
+
+    #[test]
+    fn test_upstream_can_opt_out() {
+        let req = request("GET", "/");
+
+        for cache_control in ["no-store", "private, max-age=60", "no-cache"] {
+            let mut res = response(200);
+            res.insert_header(header::CACHE_CONTROL, cache_control)
+                .unwrap();
+            assert!(!stored(&req, &res), "{cache_control}");
+        }
+
+        let mut res = response(200);
+        res.insert_header(header::VARY, "*").unwrap();
+        assert!(!stored(&req, &res));
+    }
+
+    #[test]
+    fn test_personal_responses_are_not_stored() {
+        let mut res = response(200);
+        res.insert_header(header::SET_COOKIE, "session=1").unwrap();
+        assert!(!stored(&request("GET", "/"), &res));
+
+        let mut req = request("GET", "/");
+        req.insert_header(header::AUTHORIZATION, "Bearer x")
+            .unwrap();
+        assert!(!stored(&req, &response(200)));
+    }
+
+    #[test]
+    fn test_large_responses_are_not_stored() {
+        let mut res = response(200);
+        res.insert_header(header::CONTENT_LENGTH, "2048").unwrap();
+
+        let result = cacheability(&config(), &request("GET", "/"), &res);
+        assert!(matches!(
+            result,
+            RespCacheable::Uncacheable(NoCacheReason::ResponseTooLarge)
+        ));
+    }
+
+    #[test]
+    fn test_variance_follows_vary_headers() {
+        let mut res = response(200);
+        res.insert_header(header::VARY, "Accept-Encoding").unwrap();
+        let RespCacheable::Cacheable(meta) = cacheability(&config(), &request("GET", "/"), &res)
+        else {
+            panic!("should be cacheable");
+        };
+
+        let with_encoding = |encoding: &str| {
+            let mut req = request("GET", "/");
+            req.insert_header(header::ACCEPT_ENCODING, encoding)
+                .unwrap();
+            variance(&meta, &req)
+        };
+
+        assert!(with_encoding("gzip").is_some());
+        assert_eq!(with_encoding("gzip"), with_encoding("gzip"));
+        assert_ne!(with_encoding("gzip"), with_encoding("br"));
+    }
+
+    #[test]
+    fn test_no_vary_means_no_variance() {
+        let RespCacheable::Cacheable(meta) =
+            cacheability(&config(), &request("GET", "/"), &response(200))
+        else {
+            panic!("should be cacheable");
+        };
+
+        assert!(variance(&meta, &request("GET", "/")).is_none());
+    }
+
+    #[test]
+    fn test_bypass_only_from_trusted_networks() {
+        let trusted: IpAddr = "10.1.2.3".parse().unwrap();
+        let mapped: IpAddr = "::ffff:10.1.2.3".parse().unwrap();
+        let outsider: IpAddr = "192.168.1.1".parse().unwrap();
+
+        let mut req = request("GET", "/");
+        assert!(!bypassed(&config(), Some(trusted), &req));
+
+        no_cache(&mut req, "max-age=0, No-Cache");
+        assert!(bypassed(&config(), Some(trusted), &req));
+        assert!(bypassed(&config(), Some(mapped), &req));
+        assert!(!bypassed(&config(), Some(outsider), &req));
+        assert!(!bypassed(&config(), None, &req));
+
+        let mut req = request("GET", "/");
+        no_cache(&mut req, "no-store");
+        assert!(!bypassed(&config(), Some(trusted), &req));
+    }
+
+    #[test]
+    fn test_status_of_cache_phases() {
+        assert_eq!(status(CachePhase::Hit), "HIT");
+        assert_eq!(status(CachePhase::Stale), "HIT");
+        assert_eq!(status(CachePhase::Miss), "MISS");
+        assert_eq!(status(CachePhase::Expired), "MISS");
+        assert_eq!(
+            status(CachePhase::Disabled(NoCacheReason::NeverEnabled)),
+            "BYPASS"
+        );
+    }
+
+    #[test]
+    fn test_debug_headers_of_a_stored_response() {
+        let RespCacheable::Cacheable(meta) =
+            cacheability(&config(), &request("GET", "/"), &response(200))
+        else {
+            panic!("should be cacheable");
+        };
+        let header = |res: &ResponseHeader, name: &str| {
+            res.headers
+                .get(name)
+                .map(|v| v.to_str().unwrap().to_string())
+        };
+
+        let mut res = response(200);
+        let later = meta.created() + Duration::from_secs(12);
+        add_debug_headers(&mut res, CachePhase::Hit, Some(&meta), later).unwrap();
+        assert_eq!(header(&res, X_CACHE).as_deref(), Some("HIT"));
+        assert_eq!(header(&res, X_CACHE_AGE).as_deref(), Some("12"));
+        assert_eq!(header(&res, X_CACHE_TTL).as_deref(), Some("18"));
+
+        let mut res = response(200);
+        let disabled = CachePhase::Disabled(NoCacheReason::NeverEnabled);
+        add_debug_headers(&mut res, disabled, None, SystemTime::now()).unwrap();
+        assert_eq!(header(&res, X_CACHE).as_deref(), Some("BYPASS"));
+        assert_eq!(header(&res, X_CACHE_AGE), None);
+    }
+}
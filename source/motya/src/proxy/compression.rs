@@ -0,0 +1,339 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use http::header;
+use motya_config::common_types::compression::{CompressionAlgorithm, CompressionConfig};
+use pingora::protocols::http::compression::Algorithm;
+use pingora_http::{RequestHeader, ResponseHeader};
+use pingora_proxy::Session;
+
+/// Process-wide counters of compression decisions, shared by every route.
+pub static COMPRESSION_STATS: CompressionStats = CompressionStats::new();
+
+pub struct CompressionStats {
+    compressed: AtomicU64,
+    skipped_content_type: AtomicU64,
+    skipped_already_encoded: AtomicU64,
+    skipped_not_accepted: AtomicU64,
+    skipped_too_small: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CompressionStatsSnapshot {
+    pub compressed: u64,
+    pub skipped_content_type: u64,
+    pub skipped_already_encoded: u64,
+    pub skipped_not_accepted: u64,
+    pub skipped_too_small: u64,
+}
+
+impl CompressionStatsSnapshot {
+    pub fn skipped(&self) -> u64 {
+        self.skipped_content_type
+            + self.skipped_already_encoded
+            + self.skipped_not_accepted
+            + self.skipped_too_small
+    }
+}
+
+impl CompressionStats {
+    const fn new() -> Self {
+        Self {
+            compressed: AtomicU64::new(0),
+            skipped_content_type: AtomicU64::new(0),
+            skipped_already_encoded: AtomicU64::new(0),
+            skipped_not_accepted: AtomicU64::new(0),
+            skipped_too_small: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record(&self, decision: CompressionDecision) {
+        let counter = match decision {
+            CompressionDecision::Compress => &self.compressed,
+            CompressionDecision::SkipContentType => &self.skipped_content_type,
+            CompressionDecision::SkipAlreadyEncoded => &self.skipped_already_encoded,
+            CompressionDecision::SkipNotAccepted => &self.skipped_not_accepted,
+            CompressionDecision::SkipTooSmall => &self.skipped_too_small,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> CompressionStatsSnapshot {
+        CompressionStatsSnapshot {
+            compressed: self.compressed.load(Ordering::Relaxed),
+            skipped_content_type: self.skipped_content_type.load(Ordering::Relaxed),
+            skipped_already_encoded: self.skipped_already_encoded.load(Ordering::Relaxed),
+            skipped_not_accepted: self.skipped_not_accepted.load(Ordering::Relaxed),
+            skipped_too_small: self.skipped_too_small.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionDecision {
+    Compress,
+    SkipContentType,
+    SkipAlreadyEncoded,
+    SkipNotAccepted,
+    SkipTooSmall,
+}
+
+/// Decides whether a response should go through the compressor.
+///
+/// The content type is the one the upstream settled on after negotiation, so it is
+/// checked against the route's `content-types` and exclusion list. Responses that
+/// already carry a `Content-Encoding` or announce a body under `min-size`, and
+/// clients that accept none of the route's algorithms, are left alone.
+pub fn decide(
+    config: &CompressionConfig,
+    request: &RequestHeader,
+    response: &ResponseHeader,
+) -> CompressionDecision {
+    let accepts_encoding = request
+        .headers
+        .get(header::ACCEPT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| {
+            v.split(',')
+                .map(|e| e.split(';').next().unwrap_or_default().trim())
+                .any(|e| {
+                    e == "*"
+                        || config
+                            .algorithms
+                            .iter()
+                            .any(|algorithm| algorithm.token().eq_ignore_ascii_case(e))
+                })
+        });
+
+    if !accepts_encoding {
+        return CompressionDecision::SkipNotAccepted;
+    }
+
+    let already_encoded = response
+        .headers
+        .get(header::CONTENT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| !v.trim().eq_ignore_ascii_case("identity"));
+
+    if already_encoded {
+        return CompressionDecision::SkipAlreadyEncoded;
+    }
+
+    let too_small = response
+        .headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok()?.parse::<usize>().ok())
+        .is_some_and(|length| length < config.min_size);
+
+    if too_small {
+        return CompressionDecision::SkipTooSmall;
+    }
+
+    let content_type = response
+        .headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok());
+    let excluded =
+        !config.is_included(content_type) || content_type.is_some_and(|ct| config.is_excluded(ct));
+
+    if excluded {
+        CompressionDecision::SkipContentType
+    } else {
+        CompressionDecision::Compress
+    }
+}
+
+/// Enables the pingora response compressor for the current request, for the
+/// route's algorithms only.
+///
+/// Called from `request_filter`, once the route is known.
+pub fn enable(session: &mut Session, config: &CompressionConfig) {
+    for algorithm in CompressionAlgorithm::ALL {
+        let level = if config.algorithms.contains(&algorithm) {
+            config.level
+        } else {
+            0
+        };
+        session
+            .upstream_compression
+            .adjust_algorithm_level(pingora_algorithm(algorithm), level);
+    }
+}
+
+fn pingora_algorithm(algorithm: CompressionAlgorithm) -> Algorithm {
+    match algorithm {
+        CompressionAlgorithm::Brotli => Algorithm::Brotli,
+        CompressionAlgorithm::Gzip => Algorithm::Gzip,
+        CompressionAlgorithm::Zstd => Algorithm::Zstd,
+    }
+}
+
+/// Applies the exclusion list once the upstream response headers are available,
+/// turning the compressor back off when the payload is not worth compressing.
+pub fn apply_exclusions(
+    session: &mut Session,
+    response: &ResponseHeader,
+    config: &CompressionConfig,
+) {
+    if !session.upstream_compression.is_enabled() {
+        return;
+    }
+
+    let decision = decide(config, session.req_header(), response);
+    COMPRESSION_STATS.record(decision);
+
+    if decision != CompressionDecision::Compress {
+        tracing::trace!("Skipping response compression: {decision:?}");
+        session.upstream_compression.adjust_level(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(accept_encoding: Option<&str>) -> RequestHeader {
+        let mut req = RequestHeader::build("GET", b"/", None).unwrap();
+        if let Some(value) = accept_encoding {
+            req.insert_header("Accept-Encoding", value).unwrap();
+        }
+        req
+    }
+
+    fn response(content_type: &str, content_encoding: Option<&str>) -> ResponseHeader {
+        let mut res = ResponseHeader::build(200, None).unwrap();
+        res.insert_header("Content-Type", content_type).unwrap();
+        if let Some(value) = content_encoding {
+            res.insert_header("Content-Encoding", value).unwrap();
+        }
+        res
+    }
+
+    #[test]
+    fn test_text_is_compressed() {
+        let decision = decide(
+            &CompressionConfig::default(),
+            &request(Some("gzip, br")),
+            &response("text/html; charset=utf-8", None),
+        );
+
+        assert_eq!(decision, CompressionDecision::Compress);
+    }
+
+    #[test]
+    fn test_images_are_skipped() {
+        let decision = decide(
+            &CompressionConfig::default(),
+            &request(Some("gzip")),
+            &response("image/jpeg", None),
+        );
+
+        assert_eq!(decision, CompressionDecision::SkipContentType);
+    }
+
+    #[test]
+    fn test_route_override_allows_images() {
+        let config = CompressionConfig {
+            excluded_types: vec![],
+            ..CompressionConfig::default()
+        };
+
+        let decision = decide(
+            &config,
+            &request(Some("gzip")),
+            &response("image/jpeg", None),
+        );
+
+        assert_eq!(decision, CompressionDecision::Compress);
+    }
+
+    #[test]
+    fn test_already_encoded_is_skipped() {
+        let decision = decide(
+            &CompressionConfig::default(),
+            &request(Some("gzip")),
+            &response("application/json", Some("br")),
+        );
+
+        assert_eq!(decision, CompressionDecision::SkipAlreadyEncoded);
+    }
+
+    #[test]
+    fn test_identity_only_client_is_skipped() {
+        let config = CompressionConfig::default();
+
+        assert_eq!(
+            decide(&config, &request(None), &response("text/plain", None)),
+            CompressionDecision::SkipNotAccepted
+        );
+        assert_eq!(
+            decide(
+                &config,
+                &request(Some("identity")),
+                &response("text/plain", None)
+            ),
+            CompressionDecision::SkipNotAccepted
+        );
+    }
+
+    #[test]
+    fn test_only_configured_algorithms_are_accepted() {
+        let config = CompressionConfig {
+            algorithms: vec![CompressionAlgorithm::Brotli],
+            ..CompressionConfig::default()
+        };
+        let html = response("text/html", None);
+
+        assert_eq!(
+            decide(&config, &request(Some("gzip, deflate")), &html),
+            CompressionDecision::SkipNotAccepted
+        );
+        assert_eq!(
+            decide(&config, &request(Some("gzip, br;q=0.8")), &html),
+            CompressionDecision::Compress
+        );
+        assert_eq!(
+            decide(&config, &request(Some("*")), &html),
+            CompressionDecision::Compress
+        );
+    }
+
+    #[test]
+    fn test_min_size_and_content_types() {
+        let config = CompressionConfig {
+            min_size: 1024,
+            content_types: vec!["text/*".parse().unwrap()],
+            ..CompressionConfig::default()
+        };
+        let gzip = request(Some("gzip"));
+
+        let mut small = response("text/css", None);
+        small.insert_header("Content-Length", "1023").unwrap();
+        assert_eq!(
+            decide(&config, &gzip, &small),
+            CompressionDecision::SkipTooSmall
+        );
+
+        // Without a length, the size is not known up front.
+        assert_eq!(
+            decide(&config, &gzip, &response("text/css", None)),
+            CompressionDecision::Compress
+        );
+        assert_eq!(
+            decide(&config, &gzip, &response("application/json", None)),
+            CompressionDecision::SkipContentType
+        );
+    }
+
+    #[test]
+    fn test_stats_snapshot_counts_skips() {
+        let stats = CompressionStats::new();
+
+        stats.record(CompressionDecision::Compress);
+        stats.record(CompressionDecision::SkipContentType);
+        stats.record(CompressionDecision::SkipNotAccepted);
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.compressed, 1);
+        assert_eq!(snapshot.skipped(), 2);
+    }
+}
@@ -0,0 +1,79 @@
+//! The configuration version the process is currently serving.
+//!
+//! Published once when the services are built and again after every reload, so
+//! request logs and the `X-Motya-Config` response header always name the config
+//! that handled the request. The admin API reads the published configuration back
+//! from here as well.
+
+use std::sync::Arc;
+
+use arc_swap::ArcSwapOption;
+use motya_config::{common_types::config_version::ConfigVersion, internal::Config};
+
+pub const CONFIG_VERSION_HEADER: &str = "X-Motya-Config";
+
+struct ActiveConfig {
+    version: ConfigVersion,
+    expose_header: bool,
+    config: Arc<Config>,
+}
+
+static ACTIVE: ArcSwapOption<ActiveConfig> = ArcSwapOption::const_empty();
+
+pub fn publish(config: &Config) {
+    let version = config.version();
+
+    ACTIVE.store(Some(Arc::new(ActiveConfig {
+        version,
+        expose_header: config.config_version_header,
+        config: Arc::new(config.clone()),
+    })));
+
+    tracing::info!(config_version = %version, "Serving configuration {version}");
+}
+
+pub fn active() -> Option<ConfigVersion> {
+    ACTIVE.load().as_ref().map(|active| active.version)
+}
+
+/// The configuration published last, `None` before the services are built.
+pub fn active_config() -> Option<Arc<Config>> {
+    ACTIVE.load().as_ref().map(|active| active.config.clone())
+}
+
+/// The active version for log fields, `<unpublished>` before the services are built.
+pub fn describe_active() -> String {
+    active()
+        .map(|version| version.to_string())
+        .unwrap_or_else(|| "<unpublished>".into())
+}
+
+/// The version to send in [`CONFIG_VERSION_HEADER`], if `system.config-version-header` is on.
+pub fn header_value() -> Option<ConfigVersion> {
+    ACTIVE
+        .load()
+        .as_ref()
+        .filter(|active| active.expose_header)
+        .map(|active| active.version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_publish_controls_header() {
+        let hidden = Config::default();
+        publish(&hidden);
+        assert_eq!(active(), Some(hidden.version()));
+        assert_eq!(active_config().as_deref(), Some(&hidden));
+        assert_eq!(header_value(), None);
+
+        let exposed = Config {
+            config_version_header: true,
+            ..Config::default()
+        };
+        publish(&exposed);
+        assert_eq!(header_value(), Some(exposed.version()));
+    }
+}
@@ -1,7 +1,7 @@
 use std::net::IpAddr;
 
 use cookie::Cookie;
-use http::uri::PathAndQuery;
+use http::{header, uri::PathAndQuery};
 use pingora::protocols::l4::socket::SocketAddr;
 use pingora_http::RequestHeader;
 
@@ -13,7 +13,10 @@ pub struct SessionInfo<'a> {
     pub path: &'a PathAndQuery,
 }
 
-pub struct ContextInfo {}
+pub struct ContextInfo<'a> {
+    /// Backends this request already failed on, for a retry to pass over.
+    pub tried_peers: &'a [SocketAddr],
+}
 
 impl<'a> KeySourceContext for SessionInfo<'a> {
     fn get_path(&self) -> &PathAndQuery {
@@ -41,6 +44,32 @@ impl<'a> KeySourceContext for SessionInfo<'a> {
         self.headers.headers.get(name).and_then(|v| v.to_str().ok())
     }
 
+    fn get_header_values(&self, name: &str) -> impl Iterator<Item = &[u8]> {
+        self.headers
+            .headers
+            .get_all(name)
+            .iter()
+            .map(|v| v.as_bytes())
+    }
+
+    fn get_method(&self) -> &str {
+        self.headers.method.as_str()
+    }
+
+    fn get_host(&self) -> Option<&str> {
+        if let Some(host) = self.headers.uri.host() {
+            return Some(host);
+        }
+
+        let host = self.headers.headers.get(header::HOST)?.to_str().ok()?;
+        // A colon after the closing bracket of an IPv6 address, or in a name,
+        // starts the port.
+        match host.rfind(':') {
+            Some(pos) if !host[pos..].contains(']') => Some(&host[..pos]),
+            _ => Some(host),
+        }
+    }
+
     fn get_ip(&self) -> Option<IpAddr> {
         self.client_addr
             .and_then(|addr| addr.as_inet())
@@ -0,0 +1,362 @@
+//! Users and password hashes for `basic-auth`, from `definitions.credentials`.
+//!
+//! Each set of credentials is loaded once into a [`CredentialStore`] shared by
+//! every chain naming it. Stores backed by an htpasswd file pick up edits to the
+//! file on their own, so users can be added or removed without a config reload.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use arc_swap::ArcSwap;
+use miette::{miette, Context, IntoDiagnostic, Result};
+use motya_config::common_types::basic_auth::{
+    parse_htpasswd, CredentialsConfig, CredentialsSource, HashScheme, InlineUser, UserEntry,
+};
+use pingora::tls::{
+    base64,
+    error::ErrorStack,
+    hash::{hash, Hasher, MessageDigest},
+    memcmp, sha,
+};
+
+use crate::proxy::secrets;
+
+/// How often an htpasswd file is re-read, at most.
+const RECHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The alphabet of `$apr1$` hashes.
+const CRYPT_ALPHABET: &[u8; 64] =
+    b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+struct Users {
+    hashes: HashMap<String, String>,
+    /// The file contents the users were parsed from, to skip unchanged files.
+    raw: Option<String>,
+}
+
+impl Users {
+    fn from_entries(entries: Vec<UserEntry>, raw: Option<String>) -> Self {
+        Self {
+            hashes: entries.into_iter().map(|e| (e.user, e.hash)).collect(),
+            raw,
+        }
+    }
+}
+
+/// One set of credentials, replaceable while requests are checked against it.
+pub struct CredentialStore {
+    name: String,
+    source: CredentialsSource,
+    users: ArcSwap<Users>,
+    last_check: Mutex<Instant>,
+}
+
+impl CredentialStore {
+    pub fn load(config: &CredentialsConfig) -> Result<Self> {
+        let users = match &config.source {
+            CredentialsSource::Htpasswd(path) => read_htpasswd(path)
+                .wrap_err_with(|| format!("cannot load credentials '{}'", config.name))?,
+            CredentialsSource::Inline(users) => read_inline(users)
+                .wrap_err_with(|| format!("cannot load credentials '{}'", config.name))?,
+        };
+
+        Ok(Self {
+            name: config.name.clone(),
+            source: config.source.clone(),
+            users: ArcSwap::from_pointee(users),
+            last_check: Mutex::new(Instant::now()),
+        })
+    }
+
+    /// Re-reads the htpasswd file and swaps the users if it changed.
+    ///
+    /// Returns whether new users are now in place. A file that cannot be read or
+    /// parsed is an error, and the previous users stay in place.
+    pub fn reload(&self) -> Result<bool> {
+        let CredentialsSource::Htpasswd(path) = &self.source else {
+            return Ok(false);
+        };
+
+        let users = read_htpasswd(path)?;
+        if users.raw == self.users.load().raw {
+            return Ok(false);
+        }
+
+        self.users.store(Arc::new(users));
+        Ok(true)
+    }
+
+    /// Reloads the file if the last check is older than [`RECHECK_INTERVAL`].
+    fn refresh(&self) {
+        // Whoever holds the lock is checking already.
+        let Ok(mut last_check) = self.last_check.try_lock() else {
+            return;
+        };
+        if last_check.elapsed() < RECHECK_INTERVAL {
+            return;
+        }
+        *last_check = Instant::now();
+        drop(last_check);
+
+        match self.reload() {
+            Ok(true) => tracing::info!("Reloaded credentials '{}'", self.name),
+            Ok(false) => {}
+            Err(err) => tracing::warn!(
+                "Cannot reload credentials '{}': {err:?}. Keeping the current users",
+                self.name
+            ),
+        }
+    }
+
+    /// Whether `password` is the password of `user`.
+    ///
+    /// Blocks while hashing, which takes milliseconds for bcrypt.
+    pub fn verify(&self, user: &str, password: &str) -> bool {
+        self.refresh();
+
+        let users = self.users.load();
+        users
+            .hashes
+            .get(user)
+            .is_some_and(|hash| verify_password(hash, password))
+    }
+}
+
+/// Resolves the hashes of a `users` list. Hashes from secrets are only seen
+/// now, so their format is checked here rather than when parsing.
+fn read_inline(users: &[InlineUser]) -> Result<Users> {
+    let entries = users
+        .iter()
+        .map(|user| {
+            let hash = secrets::resolve(&user.hash)?;
+            if HashScheme::detect(&hash).is_none() {
+                return Err(miette!(
+                    "unsupported password hash for user '{}', use bcrypt, $apr1$ or {{SHA}}",
+                    user.user
+                ));
+            }
+            Ok(UserEntry {
+                user: user.user.clone(),
+                hash,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(Users::from_entries(entries, None))
+}
+
+fn read_htpasswd(path: &Path) -> Result<Users> {
+    let contents = fs::read_to_string(path)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("cannot read htpasswd file {}", path.display()))?;
+
+    let entries = parse_htpasswd(&contents).map_err(|err| miette!("{}: {err}", path.display()))?;
+
+    Ok(Users::from_entries(entries, Some(contents)))
+}
+
+/// Checks `password` against an htpasswd-style `hash`. Hashes are compared in
+/// constant time, so the response time does not tell how much of a guess was right.
+pub fn verify_password(hash: &str, password: &str) -> bool {
+    match HashScheme::detect(hash) {
+        Some(HashScheme::Bcrypt) => bcrypt::verify(password, hash).unwrap_or(false),
+        Some(HashScheme::Apr1) => {
+            let salt = hash["$apr1$".len()..].split('$').next().unwrap_or_default();
+            apr1(password.as_bytes(), salt.as_bytes())
+                .is_ok_and(|computed| constant_time_eq(computed.as_bytes(), hash.as_bytes()))
+        }
+        Some(HashScheme::Sha1) => {
+            let digest = sha::sha1(password.as_bytes());
+            let computed = format!("{{SHA}}{}", base64::encode_block(&digest));
+            constant_time_eq(computed.as_bytes(), hash.as_bytes())
+        }
+        None => false,
+    }
+}
+
+/// Compares without an early exit on the first differing byte, so the response time
+/// does not tell how much of a secret was guessed. The lengths are not secret: for
+/// hashes they follow from the scheme.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && memcmp::eq(a, b)
+}
+
+/// Apache's MD5-based crypt, as written by `htpasswd -m`: `$apr1$<salt>$<hash>`.
+fn apr1(password: &[u8], salt: &[u8]) -> Result<String, ErrorStack> {
+    const MAGIC: &[u8] = b"$apr1$";
+    let salt = &salt[..salt.len().min(8)];
+
+    let alternate = hash(MessageDigest::md5(), &[password, salt, password].concat())?;
+
+    let mut ctx = Hasher::new(MessageDigest::md5())?;
+    ctx.update(password)?;
+    ctx.update(MAGIC)?;
+    ctx.update(salt)?;
+    for chunk in password.chunks(16) {
+        ctx.update(&alternate[..chunk.len()])?;
+    }
+    let mut bits = password.len();
+    while bits != 0 {
+        if bits & 1 != 0 {
+            ctx.update(&[0])?;
+        } else {
+            ctx.update(&password[..1])?;
+        }
+        bits >>= 1;
+    }
+    let mut digest = ctx.finish()?;
+
+    // Deliberately slow: a thousand more rounds.
+    for round in 0..1000 {
+        let mut ctx = Hasher::new(MessageDigest::md5())?;
+        ctx.update(if round % 2 == 1 {
+            password
+        } else {
+            &digest[..]
+        })?;
+        if round % 3 != 0 {
+            ctx.update(salt)?;
+        }
+        if round % 7 != 0 {
+            ctx.update(password)?;
+        }
+        ctx.update(if round % 2 == 1 {
+            &digest[..]
+        } else {
+            password
+        })?;
+        digest = ctx.finish()?;
+    }
+
+    let mut out = String::with_capacity(MAGIC.len() + salt.len() + 23);
+    out.push_str("$apr1$");
+    out.push_str(&String::from_utf8_lossy(salt));
+    out.push('$');
+
+    let mut push = |mut value: u32, chars: usize| {
+        for _ in 0..chars {
+            out.push(CRYPT_ALPHABET[(value & 0x3f) as usize] as char);
+            value >>= 6;
+        }
+    };
+    for (a, b, c) in [(0, 6, 12), (1, 7, 13), (2, 8, 14), (3, 9, 15), (4, 10, 5)] {
+        push(
+            (digest[a] as u32) << 16 | (digest[b] as u32) << 8 | digest[c] as u32,
+            4,
+        );
+    }
+    push(digest[11] as u32, 2);
+
+    Ok(out)
+}
+
+/// Every credential store in use, by name.
+#[derive(Default)]
+pub struct CredentialRegistry {
+    stores: Mutex<HashMap<String, Arc<CredentialStore>>>,
+}
+
+impl CredentialRegistry {
+    /// The store for `config`, loaded on first use and then shared by every
+    /// `basic-auth` naming the same credentials.
+    pub fn get_or_load(&self, config: &CredentialsConfig) -> Result<Arc<CredentialStore>> {
+        let mut stores = self.stores.lock().unwrap();
+
+        if let Some(store) = stores.get(&config.name) {
+            return Ok(store.clone());
+        }
+
+        let store = Arc::new(CredentialStore::load(config)?);
+        stores.insert(config.name.clone(), store.clone());
+        Ok(store)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use motya_config::common_types::secrets::SecretValue;
+
+    use super::*;
+
+    // Examples from the Apache documentation, all for the password "myPassword".
+    const BCRYPT: &str = "$2y$05$c4WoMPo3SXsafkva.HHa6uXQZWr7oboPiC2bT/r7q1BB8I2s0BRqC";
+    const APR1: &str = "$apr1$r31.....$HqJZimcKQFAMYayBlzkrA/";
+    const SHA1: &str = "{SHA}VBPuJHI7uixaa6LQGWx4s+5GKNE=";
+
+    #[test]
+    fn test_verify_password() {
+        for hash in [BCRYPT, APR1, SHA1] {
+            assert!(verify_password(hash, "myPassword"), "{hash}");
+            assert!(!verify_password(hash, "mypassword"), "{hash}");
+            assert!(!verify_password(hash, ""), "{hash}");
+        }
+
+        assert!(!verify_password("myPassword", "myPassword"));
+    }
+
+    #[test]
+    fn test_apr1() {
+        assert_eq!(apr1(b"myPassword", b"r31.....").unwrap(), APR1);
+    }
+
+    #[test]
+    fn test_inline_users() {
+        let store = CredentialStore::load(&CredentialsConfig {
+            name: "ops".into(),
+            source: CredentialsSource::Inline(vec![InlineUser {
+                user: "alice".into(),
+                hash: SecretValue::Inline(SHA1.into()),
+            }]),
+        })
+        .unwrap();
+
+        assert!(store.verify("alice", "myPassword"));
+        assert!(!store.verify("bob", "myPassword"));
+        assert!(!store.reload().unwrap());
+    }
+
+    #[test]
+    fn test_htpasswd_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("admins.htpasswd");
+        fs::write(&path, format!("alice:{SHA1}\n")).unwrap();
+
+        let store = CredentialStore::load(&CredentialsConfig {
+            name: "admins".into(),
+            source: CredentialsSource::Htpasswd(path.clone()),
+        })
+        .unwrap();
+        assert!(store.verify("alice", "myPassword"));
+        assert!(!store.reload().unwrap(), "unchanged files are not reloaded");
+
+        fs::write(&path, format!("bob:{APR1}\n")).unwrap();
+        assert!(store.reload().unwrap());
+        assert!(!store.verify("alice", "myPassword"));
+        assert!(store.verify("bob", "myPassword"));
+
+        fs::write(&path, "bob\n").unwrap();
+        assert!(store.reload().is_err());
+        assert!(
+            store.verify("bob", "myPassword"),
+            "a broken file keeps the old users"
+        );
+    }
+
+    #[test]
+    fn test_registry_shares_stores() {
+        let registry = CredentialRegistry::default();
+        let config = CredentialsConfig {
+            name: "ops".into(),
+            source: CredentialsSource::Inline(vec![]),
+        };
+
+        let a = registry.get_or_load(&config).unwrap();
+        let b = registry.get_or_load(&config).unwrap();
+        assert!(Arc::ptr_eq(&a, &b));
+    }
+}
@@ -0,0 +1,136 @@
+//! `discovery "Dns"`: the servers of an upstream are resolved again on every
+//! refresh, so the backends follow DNS round-robin records or headless services
+//! without a restart.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    net::SocketAddr,
+    sync::Mutex,
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use motya_config::common_types::connectors::{ServerAddress, UpstreamServer};
+use pingora::{Error, ErrorType, Result};
+use pingora_load_balancing::Backend;
+
+use crate::proxy::discovery::{http_backend, Discovery};
+
+pub struct DnsDiscovery {
+    servers: Vec<UpstreamServer>,
+    tls_sni: Option<String>,
+    refresh: Duration,
+    /// The addresses each hostname last resolved to. A lookup that fails keeps
+    /// them, so a DNS hiccup does not take backends away.
+    resolved: Mutex<HashMap<String, Vec<SocketAddr>>>,
+}
+
+impl DnsDiscovery {
+    pub fn new(servers: Vec<UpstreamServer>, tls_sni: Option<String>, refresh: Duration) -> Self {
+        Self {
+            servers,
+            tls_sni,
+            refresh,
+            resolved: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn resolve(&self, host: &str, port: u16) -> Vec<SocketAddr> {
+        let key = format!("{host}:{port}");
+
+        match tokio::net::lookup_host((host, port)).await {
+            Ok(addrs) => {
+                let addrs = addrs.collect::<Vec<_>>();
+                self.resolved
+                    .lock()
+                    .expect("dns discovery lock poisoned")
+                    .insert(key, addrs.clone());
+                addrs
+            }
+            Err(e) => {
+                tracing::warn!("Failed to resolve upstream server '{key}': {e}");
+                self.resolved
+                    .lock()
+                    .expect("dns discovery lock poisoned")
+                    .get(&key)
+                    .cloned()
+                    .unwrap_or_default()
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Discovery for DnsDiscovery {
+    async fn discover(&self) -> Result<BTreeSet<Backend>> {
+        let mut backends = BTreeSet::new();
+
+        for server in &self.servers {
+            let addrs = match &server.address {
+                ServerAddress::Socket(addr) => vec![*addr],
+                ServerAddress::Host { host, port } => self.resolve(host, *port).await,
+            };
+
+            for addr in addrs {
+                backends.insert(http_backend(addr, server.weight, self.tls_sni.as_deref()));
+            }
+        }
+
+        if backends.is_empty() {
+            return Error::e_explain(
+                ErrorType::ConnectNoRoute,
+                "no server of the upstream resolved to an address",
+            );
+        }
+
+        Ok(backends)
+    }
+
+    async fn changed(&self) -> bool {
+        tokio::time::sleep(self.refresh).await;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pingora::prelude::HttpPeer;
+
+    use super::*;
+
+    fn server(address: &str) -> UpstreamServer {
+        UpstreamServer {
+            address: address.parse().unwrap(),
+            weight: 1,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_discover_resolves_hostnames() {
+        let disco = DnsDiscovery::new(
+            vec![server("localhost:8080"), server("10.0.0.1:9000")],
+            None,
+            Duration::from_secs(30),
+        );
+
+        let (backends, _) = disco.discover().await.unwrap();
+        let addrs = backends
+            .iter()
+            .map(|b| b.addr.to_string())
+            .collect::<Vec<_>>();
+
+        assert!(addrs.contains(&"10.0.0.1:9000".to_string()));
+        assert!(addrs.iter().any(|a| a.ends_with(":8080")), "{addrs:?}");
+        assert!(backends.iter().all(|b| b.ext.get::<HttpPeer>().is_some()));
+    }
+
+    #[tokio::test]
+    async fn test_discover_fails_without_addresses() {
+        let disco = DnsDiscovery::new(
+            vec![server("motya-test.invalid:80")],
+            None,
+            Duration::from_secs(30),
+        );
+        assert!(disco.discover().await.is_err());
+    }
+}
@@ -0,0 +1,377 @@
+//! `discovery "Kubernetes"`: the backends are the ready endpoints of a service.
+//!
+//! The EndpointSlices of the service are listed, then watched through the API
+//! of the cluster motya runs in, using the credentials of its service account.
+//! Any failure of the API leads to a new listing after a short delay; until
+//! then the balancer keeps the backends it has.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use miette::{miette, IntoDiagnostic, WrapErr};
+use motya_config::common_types::balancer::KubernetesDiscoveryConfig;
+use pingora::{Error, ErrorType, Result};
+use pingora_load_balancing::Backend;
+use serde_json::Value;
+use tokio::{sync::Notify, task::JoinHandle};
+
+use crate::proxy::discovery::{http_backend, Discovery};
+
+const SERVICE_ACCOUNT: &str = "/var/run/secrets/kubernetes.io/serviceaccount";
+
+/// How long to wait before listing again after the API failed.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Watches are ended by the API server after this many seconds and started
+/// again with a fresh listing, so a watch that silently broke does not linger.
+const WATCH_TIMEOUT_SECS: u64 = 300;
+
+pub struct KubernetesDiscovery {
+    shared: Arc<Shared>,
+    tls_sni: Option<String>,
+    watch: JoinHandle<()>,
+}
+
+struct Shared {
+    /// `None` until the endpoints were listed once.
+    addrs: Mutex<Option<BTreeSet<SocketAddr>>>,
+    changed: Notify,
+}
+
+impl Shared {
+    fn publish(&self, addrs: BTreeSet<SocketAddr>) {
+        let mut current = self
+            .addrs
+            .lock()
+            .expect("kubernetes discovery lock poisoned");
+        if current.as_ref() != Some(&addrs) {
+            *current = Some(addrs);
+            self.changed.notify_one();
+        }
+    }
+}
+
+impl KubernetesDiscovery {
+    /// Starts watching the endpoints of the service. Fails when motya does not
+    /// run in a Kubernetes pod.
+    pub fn start(
+        config: &KubernetesDiscoveryConfig,
+        tls_sni: Option<String>,
+    ) -> miette::Result<Self> {
+        let client = ApiClient::in_cluster()?;
+        let namespace = match &config.namespace {
+            Some(namespace) => namespace.clone(),
+            None => std::fs::read_to_string(format!("{SERVICE_ACCOUNT}/namespace"))
+                .map(|ns| ns.trim().to_string())
+                .unwrap_or_else(|_| "default".to_string()),
+        };
+
+        let shared = Arc::new(Shared {
+            addrs: Mutex::new(None),
+            changed: Notify::new(),
+        });
+        let target = Target {
+            service: config.service.clone(),
+            namespace,
+            port: config.port.clone(),
+        };
+        let watch = tokio::spawn(watch(client, target, shared.clone()));
+
+        Ok(Self {
+            shared,
+            tls_sni,
+            watch,
+        })
+    }
+}
+
+impl Drop for KubernetesDiscovery {
+    fn drop(&mut self) {
+        self.watch.abort();
+    }
+}
+
+#[async_trait]
+impl Discovery for KubernetesDiscovery {
+    async fn discover(&self) -> Result<BTreeSet<Backend>> {
+        let addrs = self
+            .shared
+            .addrs
+            .lock()
+            .expect("kubernetes discovery lock poisoned")
+            .clone();
+
+        let Some(addrs) = addrs else {
+            return Error::e_explain(
+                ErrorType::ConnectNoRoute,
+                "the endpoints of the service were not listed yet",
+            );
+        };
+
+        Ok(addrs
+            .into_iter()
+            .map(|addr| http_backend(addr, 1, self.tls_sni.as_deref()))
+            .collect())
+    }
+
+    async fn changed(&self) -> bool {
+        self.shared.changed.notified().await;
+        true
+    }
+}
+
+/// The API server of the cluster, reached with the credentials of motya's
+/// service account.
+struct ApiClient {
+    base: String,
+    http: reqwest::Client,
+}
+
+impl ApiClient {
+    fn in_cluster() -> miette::Result<Self> {
+        let host = std::env::var("KUBERNETES_SERVICE_HOST").map_err(|_| {
+            miette!("'discovery \"Kubernetes\"' needs motya to run in a Kubernetes pod")
+        })?;
+        let port = std::env::var("KUBERNETES_SERVICE_PORT").unwrap_or_else(|_| "443".into());
+        let host = if host.contains(':') {
+            format!("[{host}]")
+        } else {
+            host
+        };
+
+        let ca = std::fs::read(format!("{SERVICE_ACCOUNT}/ca.crt"))
+            .into_diagnostic()
+            .wrap_err("cannot read the CA of the cluster")?;
+        let http = reqwest::Client::builder()
+            .add_root_certificate(reqwest::Certificate::from_pem(&ca).into_diagnostic()?)
+            .build()
+            .into_diagnostic()?;
+
+        Ok(Self {
+            base: format!("https://{host}:{port}"),
+            http,
+        })
+    }
+
+    async fn get(&self, path: &str) -> miette::Result<reqwest::Response> {
+        // Service account tokens are rotated, so the file is read for every request.
+        let token = tokio::fs::read_to_string(format!("{SERVICE_ACCOUNT}/token"))
+            .await
+            .into_diagnostic()?;
+
+        self.http
+            .get(format!("{}{path}", self.base))
+            .bearer_auth(token.trim())
+            .send()
+            .await
+            .into_diagnostic()?
+            .error_for_status()
+            .into_diagnostic()
+    }
+}
+
+struct Target {
+    service: String,
+    namespace: String,
+    port: Option<String>,
+}
+
+async fn watch(client: ApiClient, target: Target, shared: Arc<Shared>) {
+    loop {
+        if let Err(e) = list_and_watch(&client, &target, &shared).await {
+            tracing::warn!(
+                "Failed to watch the endpoints of service '{}' in '{}': {e}",
+                target.service,
+                target.namespace
+            );
+            tokio::time::sleep(RETRY_DELAY).await;
+        }
+    }
+}
+
+async fn list_and_watch(
+    client: &ApiClient,
+    target: &Target,
+    shared: &Shared,
+) -> miette::Result<()> {
+    let path = format!(
+        "/apis/discovery.k8s.io/v1/namespaces/{}/endpointslices?labelSelector=kubernetes.io%2Fservice-name%3D{}",
+        target.namespace, target.service
+    );
+    let port = target.port.as_deref();
+
+    let body = client.get(&path).await?.bytes().await.into_diagnostic()?;
+    let list: Value = serde_json::from_slice(&body).into_diagnostic()?;
+
+    let mut endpoints = Endpoints::default();
+    for slice in list["items"].as_array().into_iter().flatten() {
+        endpoints.apply("ADDED", slice, port);
+    }
+    shared.publish(endpoints.addrs());
+
+    let version = list["metadata"]["resourceVersion"]
+        .as_str()
+        .unwrap_or_default();
+    let mut response = client
+        .get(&format!(
+            "{path}&watch=1&resourceVersion={version}&timeoutSeconds={WATCH_TIMEOUT_SECS}"
+        ))
+        .await?;
+
+    // One JSON event per line; chunks don't follow the lines.
+    let mut buffer = Vec::new();
+    while let Some(chunk) = response.chunk().await.into_diagnostic()? {
+        buffer.extend_from_slice(&chunk);
+
+        while let Some(end) = buffer.iter().position(|b| *b == b'\n') {
+            let line = buffer.drain(..=end).collect::<Vec<_>>();
+            let event: Value = serde_json::from_slice(&line).into_diagnostic()?;
+
+            match event["type"].as_str() {
+                // Most often the listing got too old to watch from.
+                Some("ERROR") => {
+                    return Err(miette!("watch ended: {}", event["object"]["message"]));
+                }
+                Some(kind) => {
+                    if endpoints.apply(kind, &event["object"], port) {
+                        shared.publish(endpoints.addrs());
+                    }
+                }
+                None => {}
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The ready addresses of each EndpointSlice of the service.
+#[derive(Default)]
+struct Endpoints {
+    slices: BTreeMap<String, Vec<SocketAddr>>,
+}
+
+impl Endpoints {
+    /// Applies a watch event of type `kind` to `slice`. Returns whether the
+    /// addresses changed.
+    fn apply(&mut self, kind: &str, slice: &Value, port: Option<&str>) -> bool {
+        let Some(name) = slice["metadata"]["name"].as_str() else {
+            return false;
+        };
+
+        match kind {
+            "ADDED" | "MODIFIED" => {
+                let addrs = ready_addrs(slice, port);
+                self.slices.insert(name.to_string(), addrs.clone()) != Some(addrs)
+            }
+            "DELETED" => self.slices.remove(name).is_some(),
+            _ => false,
+        }
+    }
+
+    fn addrs(&self) -> BTreeSet<SocketAddr> {
+        self.slices.values().flatten().copied().collect()
+    }
+}
+
+/// The addresses of the ready endpoints of `slice`, on the port that is named
+/// or numbered `port`, or on its first port.
+fn ready_addrs(slice: &Value, port: Option<&str>) -> Vec<SocketAddr> {
+    let number = slice["ports"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find(|p| match port {
+            Some(port) => p["name"].as_str() == Some(port) || p["port"].to_string() == port,
+            None => true,
+        })
+        .and_then(|p| p["port"].as_u64())
+        .and_then(|p| u16::try_from(p).ok());
+    let Some(number) = number else {
+        return Vec::new();
+    };
+
+    slice["endpoints"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        // An endpoint without the condition counts as ready.
+        .filter(|endpoint| endpoint["conditions"]["ready"].as_bool().unwrap_or(true))
+        .flat_map(|endpoint| endpoint["addresses"].as_array().into_iter().flatten())
+        .filter_map(|addr| addr.as_str()?.parse::<IpAddr>().ok())
+        .map(|ip| SocketAddr::new(ip, number))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn slice(name: &str, ready: &[&str], not_ready: &[&str]) -> Value {
+        let endpoint = |addr: &&str, ready: bool| json!({ "addresses": [addr], "conditions": { "ready": ready } });
+        let endpoints = ready
+            .iter()
+            .map(|a| endpoint(a, true))
+            .chain(not_ready.iter().map(|a| endpoint(a, false)))
+            .collect::<Vec<_>>();
+
+        json!({
+            "metadata": { "name": name },
+            "addressType": "IPv4",
+            "endpoints": endpoints,
+            "ports": [
+                { "name": "metrics", "port": 9090 },
+                { "name": "http", "port": 8080 }
+            ]
+        })
+    }
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_ready_addrs_by_port() {
+        let slice = slice("web-abc", &["10.1.0.1", "10.1.0.2"], &["10.1.0.3"]);
+
+        assert_eq!(
+            ready_addrs(&slice, Some("http")),
+            [addr("10.1.0.1:8080"), addr("10.1.0.2:8080")]
+        );
+        assert_eq!(
+            ready_addrs(&slice, Some("8080")),
+            ready_addrs(&slice, Some("http"))
+        );
+        assert_eq!(ready_addrs(&slice, None)[0], addr("10.1.0.1:9090"));
+        assert!(ready_addrs(&slice, Some("grpc")).is_empty());
+    }
+
+    #[test]
+    fn test_watch_events() {
+        let mut endpoints = Endpoints::default();
+
+        assert!(endpoints.apply("ADDED", &slice("a", &["10.1.0.1"], &[]), Some("http")));
+        assert!(endpoints.apply("ADDED", &slice("b", &["10.1.0.2"], &[]), Some("http")));
+        // Nothing changed for the balancer.
+        assert!(!endpoints.apply(
+            "MODIFIED",
+            &slice("b", &["10.1.0.2"], &["10.1.0.9"]),
+            Some("http")
+        ));
+        assert_eq!(
+            endpoints.addrs(),
+            BTreeSet::from([addr("10.1.0.1:8080"), addr("10.1.0.2:8080")])
+        );
+
+        assert!(endpoints.apply("DELETED", &slice("a", &[], &[]), Some("http")));
+        assert!(!endpoints.apply("BOOKMARK", &slice("b", &[], &[]), Some("http")));
+        assert_eq!(endpoints.addrs(), BTreeSet::from([addr("10.1.0.2:8080")]));
+    }
+}
@@ -0,0 +1,143 @@
+//! Where the backends of a load-balanced upstream come from.
+//!
+//! A [`Discovery`] lists the backends and tells when they may have changed. The
+//! balancer takes the list once when it is built, then again after every change
+//! for as long as it is in use.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    net::SocketAddr,
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use pingora::{prelude::HttpPeer, Result};
+use pingora_load_balancing::{
+    discovery::ServiceDiscovery,
+    selection::{BackendIter, BackendSelection},
+    Backend, LoadBalancer,
+};
+use tokio::sync::oneshot;
+
+use crate::proxy::balancer::slow_start::SlowStart;
+
+pub mod dns;
+pub mod kubernetes;
+
+#[async_trait]
+pub trait Discovery: Send + Sync + 'static {
+    /// The backends as of now.
+    async fn discover(&self) -> Result<BTreeSet<Backend>>;
+
+    /// Waits until the backends may have changed. Returns `false` once they
+    /// never will again.
+    async fn changed(&self) -> bool;
+}
+
+/// `discovery "Static"`: the servers of the config, as written.
+pub struct StaticDiscovery {
+    backends: BTreeSet<Backend>,
+}
+
+impl StaticDiscovery {
+    pub fn new(backends: impl IntoIterator<Item = Backend>) -> Self {
+        Self {
+            backends: backends.into_iter().collect(),
+        }
+    }
+}
+
+#[async_trait]
+impl Discovery for StaticDiscovery {
+    async fn discover(&self) -> Result<BTreeSet<Backend>> {
+        Ok(self.backends.clone())
+    }
+
+    async fn changed(&self) -> bool {
+        false
+    }
+}
+
+/// A backend at `addr`, carrying the peer that requests to it connect with.
+pub fn http_backend(addr: SocketAddr, weight: usize, tls_sni: Option<&str>) -> Backend {
+    let mut backend = Backend::new_with_weight(&addr.to_string(), weight)
+        .expect("never fail because addr is already IpAddr");
+    //sni is https only
+    //https://github.com/cloudflare/pingora/blob/main/docs/user_guide/peer.md
+    backend.ext.insert(HttpPeer::new(
+        addr,
+        tls_sni.is_some(),
+        tls_sni.unwrap_or_default().to_string(),
+    ));
+    backend
+}
+
+/// Splits `discovery` into the part a [`LoadBalancer`] is built from and the
+/// [`Updates`] that keep that balancer current.
+pub fn attach(discovery: Arc<dyn Discovery>) -> (Box<dyn ServiceDiscovery + Send + Sync>, Updates) {
+    let (alive, closed) = oneshot::channel();
+    let attached = Attached {
+        discovery: discovery.clone(),
+        _alive: alive,
+    };
+
+    (Box::new(attached), Updates { discovery, closed })
+}
+
+/// The discovery as the balancer sees it. It goes away with the balancer,
+/// e.g. when a config reload replaces the router, and that ends the updates.
+struct Attached {
+    discovery: Arc<dyn Discovery>,
+    _alive: oneshot::Sender<()>,
+}
+
+#[async_trait]
+impl ServiceDiscovery for Attached {
+    async fn discover(&self) -> Result<(BTreeSet<Backend>, HashMap<u64, bool>)> {
+        Ok((self.discovery.discover().await?, HashMap::new()))
+    }
+}
+
+pub struct Updates {
+    discovery: Arc<dyn Discovery>,
+    closed: oneshot::Receiver<()>,
+}
+
+impl Updates {
+    /// Updates the backends of `lb` on every change of the discovery, until
+    /// the balancer is dropped. `slow_start` sees every new set of backends.
+    pub fn spawn<S>(self, lb: &Arc<LoadBalancer<S>>, slow_start: Option<Arc<SlowStart>>)
+    where
+        S: BackendSelection + Send + Sync + 'static,
+        S::Iter: BackendIter,
+    {
+        let weak = Arc::downgrade(lb);
+        let Updates {
+            discovery,
+            mut closed,
+        } = self;
+
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    changed = discovery.changed() => {
+                        if !changed {
+                            break;
+                        }
+                    }
+                    _ = &mut closed => break,
+                }
+
+                let Some(lb) = weak.upgrade() else {
+                    break;
+                };
+                if let Err(e) = lb.update().await {
+                    tracing::warn!("Failed to update upstream backends: {e}");
+                }
+                if let Some(slow_start) = &slow_start {
+                    slow_start.observe(lb.backends());
+                }
+            }
+        });
+    }
+}
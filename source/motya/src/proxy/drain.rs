@@ -0,0 +1,146 @@
+//! Draining the proxied requests on shutdown.
+//!
+//! [`DrainSignal`] replaces pingora's own signal handling, with the same meaning
+//! for each signal. Once the server stops accepting, the requests still in flight
+//! are given `system.shutdown-grace` to finish, and how many of them did is logged.
+//! On `SIGTERM` the process exits as soon as none is left, instead of always
+//! waiting out the grace period.
+
+use std::{
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use pingora::server::{ShutdownSignal, ShutdownSignalWatch};
+use tokio::signal::unix::{signal, SignalKind};
+
+/// What pingora waits for when no grace period is configured.
+pub const DEFAULT_GRACE: Duration = Duration::from_secs(300);
+
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+static DRAINED: AtomicUsize = AtomicUsize::new(0);
+static DRAINING: AtomicBool = AtomicBool::new(false);
+
+/// Counts a proxied request as in flight for as long as it is held.
+#[derive(Debug)]
+pub struct InFlight(());
+
+impl InFlight {
+    pub fn start() -> Self {
+        IN_FLIGHT.fetch_add(1, Ordering::Relaxed);
+        Self(())
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        IN_FLIGHT.fetch_sub(1, Ordering::Relaxed);
+        if DRAINING.load(Ordering::Relaxed) {
+            DRAINED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+pub fn in_flight() -> usize {
+    IN_FLIGHT.load(Ordering::Relaxed)
+}
+
+/// `SIGTERM` drains and exits, `SIGQUIT` hands the listeners over and drains, and
+/// `SIGINT` stops at once.
+pub struct DrainSignal {
+    grace: Duration,
+}
+
+impl DrainSignal {
+    pub fn new(grace: Option<Duration>) -> Self {
+        Self {
+            grace: grace.unwrap_or(DEFAULT_GRACE),
+        }
+    }
+}
+
+#[async_trait]
+impl ShutdownSignalWatch for DrainSignal {
+    async fn recv(&self) -> ShutdownSignal {
+        let mut terminate = signal(SignalKind::terminate()).expect("cannot listen for SIGTERM");
+        let mut quit = signal(SignalKind::quit()).expect("cannot listen for SIGQUIT");
+        let mut interrupt = signal(SignalKind::interrupt()).expect("cannot listen for SIGINT");
+
+        tokio::select! {
+            _ = terminate.recv() => {
+                tracing::info!("SIGTERM received, draining");
+                start_draining(self.grace, true);
+                ShutdownSignal::GracefulTerminate
+            }
+            _ = quit.recv() => {
+                // The listeners are sent to the new instance after this returns, so
+                // the process is left to pingora to end.
+                tracing::info!("SIGQUIT received, handing over and draining");
+                start_draining(self.grace, false);
+                ShutdownSignal::GracefulUpgrade
+            }
+            _ = interrupt.recv() => {
+                tracing::info!("SIGINT received, cutting off {} requests in flight", in_flight());
+                ShutdownSignal::FastShutdown
+            }
+        }
+    }
+}
+
+fn start_draining(grace: Duration, exit_when_drained: bool) {
+    DRAINING.store(true, Ordering::Relaxed);
+    tracing::info!(
+        "Waiting up to {} for {} requests in flight",
+        humantime::format_duration(grace),
+        in_flight()
+    );
+
+    thread::spawn(move || {
+        let drained = wait_for_drain(grace);
+        report();
+        if drained && exit_when_drained {
+            std::process::exit(0);
+        }
+    });
+}
+
+/// Whether the requests in flight all finished within `grace`.
+fn wait_for_drain(grace: Duration) -> bool {
+    let started = Instant::now();
+    while in_flight() > 0 {
+        if started.elapsed() >= grace {
+            return false;
+        }
+        thread::sleep(DRAIN_POLL_INTERVAL);
+    }
+    true
+}
+
+fn report() {
+    let drained = DRAINED.load(Ordering::Relaxed);
+    match in_flight() {
+        0 => tracing::info!("Drained {drained} requests"),
+        cut => tracing::warn!("Drained {drained} requests, {cut} still in flight are cut off"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drain() {
+        let request = InFlight::start();
+        assert!(in_flight() >= 1);
+        assert!(!wait_for_drain(Duration::ZERO));
+
+        DRAINING.store(true, Ordering::Relaxed);
+        let drained = DRAINED.load(Ordering::Relaxed);
+        drop(request);
+        assert!(DRAINED.load(Ordering::Relaxed) > drained);
+    }
+}
@@ -0,0 +1,180 @@
+//! Custom error pages of a proxy service (`error-pages` in the service).
+//!
+//! The errors the proxy answers itself, such as a `502` for an unreachable
+//! upstream, a `503` past the service limits or a `429` of a rate limit, go out
+//! through [`respond_error`] or [`respond`]. When the service has a page for the
+//! status, it is sent as the body, with `${status}` and `${request_id}` filled in;
+//! otherwise the response stays pingora's empty one. Responses of the upstreams
+//! are passed on untouched, whatever their status.
+
+use std::{collections::BTreeMap, fs};
+
+use bytes::Bytes;
+use miette::{Context, IntoDiagnostic};
+use motya_config::common_types::error_pages::{
+    ErrorPageField, ErrorPagePart, ErrorPageSource, ErrorPageTemplate, ErrorPagesConfig,
+};
+use pingora::Result;
+use pingora_http::ResponseHeader;
+use pingora_proxy::Session;
+use uuid::Uuid;
+
+use crate::proxy::MotyaContext;
+
+#[derive(Debug)]
+pub struct ErrorPages {
+    pages: BTreeMap<u16, Page>,
+}
+
+#[derive(Debug)]
+struct Page {
+    template: ErrorPageTemplate,
+    content_type: String,
+}
+
+impl ErrorPages {
+    /// Reads the templates kept in files, which must exist when the service starts.
+    pub fn load(config: &ErrorPagesConfig) -> miette::Result<Self> {
+        let mut pages = BTreeMap::new();
+
+        for page in &config.pages {
+            let template = match &page.source {
+                ErrorPageSource::Inline(template) => template.clone(),
+                ErrorPageSource::File(path) => fs::read_to_string(path)
+                    .into_diagnostic()
+                    .and_then(|text| text.parse::<ErrorPageTemplate>())
+                    .wrap_err_with(|| {
+                        format!("Cannot load the error page {} from {path:?}", page.status)
+                    })?,
+            };
+
+            pages.insert(
+                page.status,
+                Page {
+                    template,
+                    content_type: page.content_type.clone(),
+                },
+            );
+        }
+
+        Ok(Self { pages })
+    }
+
+    /// The content type and body of the page for `status`, if there is one.
+    pub fn render(&self, status: u16, request_id: &Uuid) -> Option<(&str, Bytes)> {
+        let page = self.pages.get(&status)?;
+
+        let mut body = String::new();
+        for part in &page.template.parts {
+            match part {
+                ErrorPagePart::Literal(text) => body.push_str(text),
+                ErrorPagePart::Field(ErrorPageField::Status) => body.push_str(&status.to_string()),
+                ErrorPagePart::Field(ErrorPageField::RequestId) => {
+                    body.push_str(&request_id.to_string())
+                }
+            }
+        }
+
+        Some((&page.content_type, Bytes::from(body)))
+    }
+}
+
+/// Answers the request with the error `code`.
+pub async fn respond_error(session: &mut Session, ctx: &MotyaContext, code: u16) -> Result<()> {
+    let has_page = ctx
+        .error_pages
+        .as_ref()
+        .is_some_and(|pages| pages.pages.contains_key(&code));
+    if !has_page {
+        return session.downstream_session.respond_error(code).await;
+    }
+
+    let mut header = ResponseHeader::build(code, Some(3))?;
+    header.insert_header("Cache-Control", "private, no-store")?;
+    respond(session, ctx, header).await
+}
+
+/// Answers the request with `header`, an error response built by the caller, and
+/// the page for its status or an empty body.
+pub async fn respond(
+    session: &mut Session,
+    ctx: &MotyaContext,
+    mut header: ResponseHeader,
+) -> Result<()> {
+    match page(ctx, header.status.as_u16()) {
+        Some((content_type, body)) => {
+            header.insert_header("Content-Type", content_type)?;
+            header.insert_header("Content-Length", body.len().to_string())?;
+
+            session
+                .write_response_header(Box::new(header), false)
+                .await?;
+            session.write_response_body(Some(body), true).await
+        }
+        None => {
+            header.insert_header("Content-Length", "0")?;
+            session.write_response_header(Box::new(header), true).await
+        }
+    }
+}
+
+fn page(ctx: &MotyaContext, code: u16) -> Option<(&str, Bytes)> {
+    ctx.error_pages
+        .as_deref()
+        .and_then(|pages| pages.render(code, &ctx.request_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use motya_config::common_types::error_pages::ErrorPage;
+
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("502.html");
+        fs::write(&path, "<h1>${status}</h1><p>${request_id}</p>").unwrap();
+
+        let config = ErrorPagesConfig {
+            pages: vec![
+                ErrorPage {
+                    status: 502,
+                    source: ErrorPageSource::File(path),
+                    content_type: "text/html; charset=utf-8".into(),
+                },
+                ErrorPage {
+                    status: 429,
+                    source: ErrorPageSource::Inline(r#"{"status": ${status}}"#.parse().unwrap()),
+                    content_type: "application/json".into(),
+                },
+            ],
+        };
+        let pages = ErrorPages::load(&config).unwrap();
+        let request_id = Uuid::new_v4();
+
+        let (content_type, body) = pages.render(502, &request_id).unwrap();
+        assert_eq!(content_type, "text/html; charset=utf-8");
+        assert_eq!(body, format!("<h1>502</h1><p>{request_id}</p>"));
+
+        let (content_type, body) = pages.render(429, &request_id).unwrap();
+        assert_eq!(content_type, "application/json");
+        assert_eq!(body, r#"{"status": 429}"#);
+
+        assert!(pages.render(504, &request_id).is_none());
+    }
+
+    #[test]
+    fn test_missing_file() {
+        let config = ErrorPagesConfig {
+            pages: vec![ErrorPage {
+                status: 503,
+                source: ErrorPageSource::File("/nonexistent/503.html".into()),
+                content_type: "text/html; charset=utf-8".into(),
+            }],
+        };
+
+        let err = ErrorPages::load(&config).unwrap_err();
+        assert!(err.to_string().contains("error page 503"), "{err}");
+    }
+}
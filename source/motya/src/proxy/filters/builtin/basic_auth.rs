@@ -0,0 +1,113 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use http::{header, HeaderValue, StatusCode};
+use pingora::Result;
+use pingora_http::ResponseHeader;
+use pingora_proxy::Session;
+
+use crate::proxy::{credentials::CredentialStore, filters::types::RequestFilterMod, MotyaContext};
+
+/// Rejects requests without a valid `Authorization: Basic` header with a 401.
+pub struct BasicAuthFilter {
+    realm: String,
+    store: Arc<CredentialStore>,
+}
+
+impl BasicAuthFilter {
+    pub fn new(realm: String, store: Arc<CredentialStore>) -> Self {
+        Self { realm, store }
+    }
+}
+
+/// The user and password of a `Basic` authorization header.
+fn parse_basic(value: &HeaderValue) -> Option<(String, String)> {
+    let value = value.to_str().ok()?;
+    let (scheme, encoded) = value.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+
+    let decoded = pingora::tls::base64::decode_block(encoded.trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (user, password) = decoded.split_once(':')?;
+
+    Some((user.to_string(), password.to_string()))
+}
+
+#[async_trait]
+impl RequestFilterMod for BasicAuthFilter {
+    async fn request_filter(&self, session: &mut Session, _ctx: &mut MotyaContext) -> Result<bool> {
+        let credentials = session
+            .req_header()
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(parse_basic);
+
+        if let Some((user, password)) = credentials {
+            let store = self.store.clone();
+            let valid = tokio::task::spawn_blocking(move || store.verify(&user, &password))
+                .await
+                .unwrap_or(false);
+
+            if valid {
+                return Ok(false);
+            }
+        }
+
+        let body = Bytes::from_static(b"Unauthorized");
+        let mut response = ResponseHeader::build(StatusCode::UNAUTHORIZED, Some(3))?;
+        response.insert_header(
+            header::WWW_AUTHENTICATE,
+            format!("Basic realm=\"{}\", charset=\"UTF-8\"", self.realm),
+        )?;
+        response.insert_header(header::CONTENT_TYPE, "text/plain; charset=utf-8")?;
+        response.insert_header(header::CONTENT_LENGTH, body.len())?;
+
+        session
+            .downstream_session
+            .write_response_header(Box::new(response))
+            .await?;
+        session
+            .downstream_session
+            .write_response_body(body, true)
+            .await?;
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(value: &str) -> HeaderValue {
+        HeaderValue::from_str(value).unwrap()
+    }
+
+    #[test]
+    fn test_parse_basic() {
+        // "alice:open:sesame"; only the first colon separates the user.
+        assert_eq!(
+            parse_basic(&header("Basic YWxpY2U6b3BlbjpzZXNhbWU=")),
+            Some(("alice".into(), "open:sesame".into()))
+        );
+        assert_eq!(
+            parse_basic(&header("basic  YWxpY2U6b3BlbjpzZXNhbWU=")),
+            Some(("alice".into(), "open:sesame".into()))
+        );
+    }
+
+    #[test]
+    fn test_parse_basic_rejects_other_headers() {
+        assert_eq!(
+            parse_basic(&header("Bearer YWxpY2U6b3BlbjpzZXNhbWU=")),
+            None
+        );
+        assert_eq!(parse_basic(&header("Basic")), None);
+        assert_eq!(parse_basic(&header("Basic not-base64!")), None);
+        // "alice", no colon
+        assert_eq!(parse_basic(&header("Basic YWxpY2U=")), None);
+    }
+}
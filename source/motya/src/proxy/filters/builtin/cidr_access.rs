@@ -0,0 +1,291 @@
+use std::{collections::BTreeMap, net::IpAddr};
+
+use async_trait::async_trait;
+use cidr::IpCidr;
+use motya_config::common_types::value::Value;
+use pingora::{protocols::l4::socket::SocketAddr, Error, Result};
+use pingora_proxy::Session;
+
+use crate::proxy::{
+    error_pages,
+    filters::{
+        builtin::helpers::{ConfigMapExt, RequiredValueExt},
+        types::RequestFilterMod,
+    },
+    MotyaContext,
+};
+
+const NO_CHILD: u32 = u32::MAX;
+
+#[derive(Clone, Copy)]
+struct Node {
+    children: [u32; 2],
+    /// A configured range ends here; every address below is covered.
+    terminal: bool,
+}
+
+impl Node {
+    const EMPTY: Node = Node {
+        children: [NO_CHILD; 2],
+        terminal: false,
+    };
+}
+
+/// A binary prefix trie over address bits, one per address family.
+///
+/// A lookup walks at most 32 (IPv4) or 128 (IPv6) nodes, however many ranges are
+/// configured.
+pub struct CidrTrie {
+    nodes: Vec<Node>,
+    v4_root: u32,
+    v6_root: u32,
+}
+
+impl Default for CidrTrie {
+    fn default() -> Self {
+        Self {
+            nodes: vec![Node::EMPTY, Node::EMPTY],
+            v4_root: 0,
+            v6_root: 1,
+        }
+    }
+}
+
+impl CidrTrie {
+    pub fn insert(&mut self, cidr: &IpCidr) {
+        let (root, bits, width) = match cidr.first_address() {
+            IpAddr::V4(addr) => (self.v4_root, u32::from(addr) as u128, 32u32),
+            IpAddr::V6(addr) => (self.v6_root, u128::from(addr), 128),
+        };
+
+        let mut node = root;
+        for depth in 0..cidr.network_length() as u32 {
+            if self.nodes[node as usize].terminal {
+                // Already covered by a shorter prefix.
+                return;
+            }
+
+            let bit = ((bits >> (width - 1 - depth)) & 1) as usize;
+            let mut next = self.nodes[node as usize].children[bit];
+            if next == NO_CHILD {
+                next = self.nodes.len() as u32;
+                self.nodes.push(Node::EMPTY);
+                self.nodes[node as usize].children[bit] = next;
+            }
+            node = next;
+        }
+
+        // Longer prefixes below this one are redundant now.
+        self.nodes[node as usize] = Node {
+            children: [NO_CHILD; 2],
+            terminal: true,
+        };
+    }
+
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        // Dual-stack listeners see IPv4 clients as `::ffff:a.b.c.d`.
+        let addr = match addr {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(addr, IpAddr::V4),
+            v4 => v4,
+        };
+        let (mut node, bits, width) = match addr {
+            IpAddr::V4(addr) => (self.v4_root, u32::from(addr) as u128, 32u32),
+            IpAddr::V6(addr) => (self.v6_root, u128::from(addr), 128),
+        };
+
+        for depth in 0..=width {
+            let current = &self.nodes[node as usize];
+            if current.terminal {
+                return true;
+            }
+            if depth == width {
+                break;
+            }
+
+            let bit = ((bits >> (width - 1 - depth)) & 1) as usize;
+            node = current.children[bit];
+            if node == NO_CHILD {
+                return false;
+            }
+        }
+
+        false
+    }
+
+    /// Builds a trie from the comma separated `addrs` setting. Single addresses
+    /// are accepted as `/32` or `/128` ranges.
+    pub fn from_settings(settings: &mut BTreeMap<String, Value>) -> Result<Self> {
+        let addrs_raw = settings.take_val::<String>("addrs")?.required("addrs")?;
+
+        let mut trie = Self::default();
+        let mut count = 0;
+
+        for s in addrs_raw
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+        {
+            let cidr = s.parse::<IpCidr>().map_err(|e| {
+                tracing::error!("Failed to parse '{s}' as a valid CIDR: {e:?}");
+                Error::new_str("Invalid configuration: Invalid CIDR notation")
+            })?;
+            trie.insert(&cidr);
+            count += 1;
+        }
+
+        if count == 0 {
+            tracing::error!("'addrs' lists no address ranges");
+            return Err(Error::new_str("Invalid configuration: Empty 'addrs'"));
+        }
+
+        Ok(trie)
+    }
+}
+
+/// `motya.filters.cidr-allow`: only clients inside `addrs` get through.
+pub struct CidrAllowFilter {
+    allowed: CidrTrie,
+}
+
+impl CidrAllowFilter {
+    pub fn from_settings(mut settings: BTreeMap<String, Value>) -> Result<Self> {
+        Ok(Self {
+            allowed: CidrTrie::from_settings(&mut settings)?,
+        })
+    }
+}
+
+#[async_trait]
+impl RequestFilterMod for CidrAllowFilter {
+    async fn request_filter(&self, session: &mut Session, ctx: &mut MotyaContext) -> Result<bool> {
+        let allowed = match session.downstream_session.client_addr() {
+            Some(SocketAddr::Inet(addr)) => self.allowed.contains(addr.ip()),
+            // CIDR filters don't apply to UDS
+            Some(_) => true,
+            // Unable to determine source address, assuming it should be blocked
+            None => false,
+        };
+
+        if allowed {
+            Ok(false)
+        } else {
+            error_pages::respond_error(session, ctx, 403).await?;
+            Ok(true)
+        }
+    }
+}
+
+/// `motya.filters.cidr-deny`: clients inside `addrs` are turned away.
+pub struct CidrDenyFilter {
+    denied: CidrTrie,
+}
+
+impl CidrDenyFilter {
+    pub fn from_settings(mut settings: BTreeMap<String, Value>) -> Result<Self> {
+        Ok(Self {
+            denied: CidrTrie::from_settings(&mut settings)?,
+        })
+    }
+}
+
+#[async_trait]
+impl RequestFilterMod for CidrDenyFilter {
+    async fn request_filter(&self, session: &mut Session, ctx: &mut MotyaContext) -> Result<bool> {
+        let denied = match session.downstream_session.client_addr() {
+            Some(SocketAddr::Inet(addr)) => self.denied.contains(addr.ip()),
+            // CIDR filters don't apply to UDS
+            Some(_) => false,
+            // Unable to determine source address, assuming it should be blocked
+            None => true,
+        };
+
+        if denied {
+            error_pages::respond_error(session, ctx, 403).await?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trie(addrs: &str) -> CidrTrie {
+        let mut settings = BTreeMap::new();
+        settings.insert("addrs".to_string(), Value::String(addrs.to_string()));
+        CidrTrie::from_settings(&mut settings).expect("Should build the trie")
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_ipv4_ranges() {
+        let trie = trie("10.0.0.0/8, 192.168.1.0/24, 203.0.113.7");
+
+        assert!(trie.contains(ip("10.255.0.1")));
+        assert!(trie.contains(ip("192.168.1.200")));
+        assert!(trie.contains(ip("203.0.113.7")));
+        assert!(!trie.contains(ip("192.168.2.1")));
+        assert!(!trie.contains(ip("203.0.113.8")));
+        assert!(!trie.contains(ip("11.0.0.1")));
+    }
+
+    #[test]
+    fn test_ipv6_ranges_and_mapped_ipv4() {
+        let trie = trie("2001:db8::/32, ::1, 10.0.0.0/8");
+
+        assert!(trie.contains(ip("2001:db8:1234::1")));
+        assert!(trie.contains(ip("::1")));
+        assert!(!trie.contains(ip("2001:db9::1")));
+        assert!(!trie.contains(ip("::2")));
+
+        assert!(trie.contains(ip("::ffff:10.1.2.3")));
+        assert!(!trie.contains(ip("::ffff:11.1.2.3")));
+    }
+
+    #[test]
+    fn test_families_are_separate() {
+        // 0.0.0.0/0 covers every IPv4 address, but no IPv6 one.
+        let trie = trie("0.0.0.0/0");
+
+        assert!(trie.contains(ip("8.8.8.8")));
+        assert!(!trie.contains(ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn test_nested_ranges() {
+        let wide_first = trie("10.0.0.0/8, 10.1.0.0/16");
+        let narrow_first = trie("10.1.0.0/16, 10.0.0.0/8");
+
+        for trie in [wide_first, narrow_first] {
+            assert!(trie.contains(ip("10.1.2.3")));
+            assert!(trie.contains(ip("10.2.3.4")));
+            assert!(!trie.contains(ip("11.0.0.0")));
+        }
+    }
+
+    #[test]
+    fn test_from_settings_errors() {
+        let mut settings = BTreeMap::new();
+        settings.insert(
+            "addrs".to_string(),
+            Value::String("10.0.0.0/8, not_a_cidr".to_string()),
+        );
+        let err = CidrAllowFilter::from_settings(settings).err().unwrap();
+        assert!(format!("{err:?}").contains("Invalid CIDR notation"));
+
+        let mut settings = BTreeMap::new();
+        settings.insert("addrs".to_string(), Value::String(" , ".to_string()));
+        let err = CidrDenyFilter::from_settings(settings).err().unwrap();
+        assert!(format!("{err:?}").contains("Empty 'addrs'"));
+
+        let err = CidrDenyFilter::from_settings(BTreeMap::new())
+            .err()
+            .unwrap();
+        assert!(format!("{err:?}").contains("Missing configuration"));
+    }
+}
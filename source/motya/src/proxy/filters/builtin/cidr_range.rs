@@ -7,6 +7,7 @@ use pingora::{protocols::l4::socket::SocketAddr, Error, Result};
 use pingora_proxy::Session;
 
 use crate::proxy::{
+    error_pages,
     filters::{
         builtin::helpers::{ConfigMapExt, RequiredValueExt},
         types::RequestFilterMod,
@@ -40,10 +41,10 @@ impl CidrRangeFilter {
 
 #[async_trait]
 impl RequestFilterMod for CidrRangeFilter {
-    async fn request_filter(&self, session: &mut Session, _ctx: &mut MotyaContext) -> Result<bool> {
+    async fn request_filter(&self, session: &mut Session, ctx: &mut MotyaContext) -> Result<bool> {
         let Some(addr) = session.downstream_session.client_addr() else {
             // Unable to determine source address, assuming it should be blocked
-            session.downstream_session.respond_error(401).await?;
+            error_pages::respond_error(session, ctx, 401).await?;
             return Ok(true);
         };
         let SocketAddr::Inet(addr) = addr else {
@@ -53,7 +54,7 @@ impl RequestFilterMod for CidrRangeFilter {
         let ip_addr = addr.ip();
 
         if self.blocks.iter().any(|b| b.contains(&ip_addr)) {
-            session.downstream_session.respond_error(401).await?;
+            error_pages::respond_error(session, ctx, 401).await?;
             Ok(true)
         } else {
             Ok(false)
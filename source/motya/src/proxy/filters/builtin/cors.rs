@@ -0,0 +1,316 @@
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use http::{header, HeaderValue, Method, StatusCode};
+use motya_config::common_types::{cors::Cors, value::Value};
+use pingora::{Error, Result};
+use pingora_http::{RequestHeader, ResponseHeader};
+use pingora_proxy::Session;
+
+use crate::proxy::{
+    filters::types::{RequestFilterMod, ResponseModifyMod},
+    MotyaContext,
+};
+
+/// `motya.filters.cors`: answers CORS preflights and adds the
+/// `Access-Control-*` headers to responses for allowed origins.
+///
+/// Requests without an `Origin` are none of its business and pass untouched.
+/// A preflight is answered here and never reaches the upstream: `204` if the
+/// origin, method and headers are allowed, `403` otherwise.
+pub struct CorsFilter {
+    cors: Cors,
+}
+
+impl CorsFilter {
+    pub fn from_settings(settings: BTreeMap<String, Value>) -> Result<Self> {
+        let cors = Cors::parse(&settings).map_err(|e| {
+            tracing::error!("Invalid cors configuration: {}", e.message);
+            Error::new_str("Invalid configuration: Bad cors argument")
+        })?;
+        Ok(Self { cors })
+    }
+
+    /// The allowed `Origin` of `request`, if it has one.
+    fn origin<'a>(&self, request: &'a RequestHeader) -> Option<&'a str> {
+        request
+            .headers
+            .get(header::ORIGIN)
+            .and_then(|v| v.to_str().ok())
+            .filter(|origin| self.cors.allows_origin(origin))
+    }
+
+    /// The answer to a preflight `request`, `None` if it isn't one.
+    fn preflight(&self, request: &RequestHeader) -> Result<Option<ResponseHeader>> {
+        if request.method != Method::OPTIONS || !request.headers.contains_key(header::ORIGIN) {
+            return Ok(None);
+        }
+        let Some(method) = request.headers.get(header::ACCESS_CONTROL_REQUEST_METHOD) else {
+            return Ok(None);
+        };
+        let requested_headers = request
+            .headers
+            .get(header::ACCESS_CONTROL_REQUEST_HEADERS)
+            .map_or(Some(""), |v| v.to_str().ok());
+
+        let allowed = self.origin(request).filter(|_| {
+            method
+                .to_str()
+                .is_ok_and(|method| self.cors.allows_method(method))
+                && requested_headers.is_some_and(|headers| self.cors.allows_headers(headers))
+        });
+        let Some(origin) = allowed else {
+            let mut response = ResponseHeader::build(StatusCode::FORBIDDEN, Some(1))?;
+            response.insert_header(header::CONTENT_LENGTH, 0)?;
+            return Ok(Some(response));
+        };
+
+        let mut response = ResponseHeader::build(StatusCode::NO_CONTENT, Some(7))?;
+        self.allow(&mut response, origin)?;
+        let methods = self
+            .cors
+            .methods
+            .iter()
+            .map(Method::as_str)
+            .collect::<Vec<_>>()
+            .join(", ");
+        response.insert_header(header::ACCESS_CONTROL_ALLOW_METHODS, methods)?;
+        match &self.cors.headers {
+            // Browsers take `*` literally along with credentials, so the
+            // requested headers are echoed instead.
+            None => {
+                if let Some(headers) = requested_headers.filter(|h| !h.is_empty()) {
+                    response.insert_header(header::ACCESS_CONTROL_ALLOW_HEADERS, headers)?;
+                }
+            }
+            Some(headers) if !headers.is_empty() => {
+                let headers = headers
+                    .iter()
+                    .map(|h| h.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                response.insert_header(header::ACCESS_CONTROL_ALLOW_HEADERS, headers)?;
+            }
+            Some(_) => {}
+        }
+        if let Some(max_age) = self.cors.max_age {
+            response.insert_header(header::ACCESS_CONTROL_MAX_AGE, max_age.as_secs())?;
+        }
+        response.insert_header(header::CONTENT_LENGTH, 0)?;
+        Ok(Some(response))
+    }
+
+    /// Adds the headers both preflights and responses carry for `origin`.
+    fn allow(&self, response: &mut ResponseHeader, origin: &str) -> Result<()> {
+        response.insert_header(
+            header::ACCESS_CONTROL_ALLOW_ORIGIN,
+            self.cors.allow_origin_value(origin),
+        )?;
+        if self.cors.credentials {
+            response.insert_header(header::ACCESS_CONTROL_ALLOW_CREDENTIALS, "true")?;
+        }
+        // The answer depends on the origin unless it is `*`, caches must know.
+        if !self.cors.any_origin {
+            response.append_header(header::VARY, HeaderValue::from_static("Origin"))?;
+        }
+        Ok(())
+    }
+
+    fn decorate(&self, request: &RequestHeader, response: &mut ResponseHeader) -> Result<()> {
+        let Some(origin) = self.origin(request) else {
+            return Ok(());
+        };
+        self.allow(response, origin)?;
+        if !self.cors.expose_headers.is_empty() {
+            let headers = self
+                .cors
+                .expose_headers
+                .iter()
+                .map(|h| h.as_str())
+                .collect::<Vec<_>>()
+                .join(", ");
+            response.insert_header(header::ACCESS_CONTROL_EXPOSE_HEADERS, headers)?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl RequestFilterMod for CorsFilter {
+    async fn request_filter(&self, session: &mut Session, _ctx: &mut MotyaContext) -> Result<bool> {
+        let Some(response) = self.preflight(session.req_header())? else {
+            return Ok(false);
+        };
+        session
+            .write_response_header(Box::new(response), true)
+            .await?;
+        Ok(true)
+    }
+}
+
+impl ResponseModifyMod for CorsFilter {
+    fn upstream_response_filter(
+        &self,
+        session: &mut Session,
+        header: &mut ResponseHeader,
+        _ctx: &mut MotyaContext,
+    ) {
+        if let Err(e) = self.decorate(session.req_header(), header) {
+            tracing::warn!("Failed to add the CORS headers: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(pairs: &[(&str, Value)]) -> CorsFilter {
+        let settings = pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.clone()))
+            .collect();
+        CorsFilter::from_settings(settings).unwrap()
+    }
+
+    fn string(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+
+    fn request(method: &str, headers: &[(&str, &str)]) -> RequestHeader {
+        let mut request = RequestHeader::build(method, b"/api/orders", None).unwrap();
+        for (name, value) in headers {
+            request.insert_header(name.to_string(), *value).unwrap();
+        }
+        request
+    }
+
+    fn header<'a>(response: &'a ResponseHeader, name: &str) -> Option<&'a str> {
+        response.headers.get(name).map(|v| v.to_str().unwrap())
+    }
+
+    #[test]
+    fn test_preflight() {
+        let cors = filter(&[
+            ("origins", string("https://shop.example.com")),
+            ("methods", string("GET, PUT")),
+            ("headers", string("content-type, x-token")),
+            ("credentials", Value::Bool(true)),
+            ("max-age", string("1h")),
+        ]);
+
+        let preflight =
+            |headers: &[(&str, &str)]| cors.preflight(&request("OPTIONS", headers)).unwrap();
+
+        let response = preflight(&[
+            ("origin", "https://shop.example.com"),
+            ("access-control-request-method", "PUT"),
+            ("access-control-request-headers", "X-Token"),
+        ])
+        .unwrap();
+        assert_eq!(response.status, StatusCode::NO_CONTENT);
+        assert_eq!(
+            header(&response, "access-control-allow-origin"),
+            Some("https://shop.example.com")
+        );
+        assert_eq!(
+            header(&response, "access-control-allow-methods"),
+            Some("GET, PUT")
+        );
+        assert_eq!(
+            header(&response, "access-control-allow-headers"),
+            Some("content-type, x-token")
+        );
+        assert_eq!(
+            header(&response, "access-control-allow-credentials"),
+            Some("true")
+        );
+        assert_eq!(header(&response, "access-control-max-age"), Some("3600"));
+        assert_eq!(header(&response, "vary"), Some("Origin"));
+
+        for headers in [
+            vec![
+                ("origin", "https://evil.com"),
+                ("access-control-request-method", "PUT"),
+            ],
+            vec![
+                ("origin", "https://shop.example.com"),
+                ("access-control-request-method", "DELETE"),
+            ],
+            vec![
+                ("origin", "https://shop.example.com"),
+                ("access-control-request-method", "PUT"),
+                ("access-control-request-headers", "authorization"),
+            ],
+        ] {
+            let response = preflight(&headers).unwrap();
+            assert_eq!(response.status, StatusCode::FORBIDDEN, "{headers:?}");
+            assert_eq!(header(&response, "access-control-allow-origin"), None);
+        }
+
+        // Plain OPTIONS requests go to the upstream.
+        assert!(preflight(&[("origin", "https://shop.example.com")]).is_none());
+        assert!(preflight(&[("access-control-request-method", "PUT")]).is_none());
+    }
+
+    #[test]
+    fn test_any_header_is_echoed() {
+        let cors = filter(&[("origins", string("*")), ("headers", string("*"))]);
+        let response = cors
+            .preflight(&request(
+                "OPTIONS",
+                &[
+                    ("origin", "https://anywhere"),
+                    ("access-control-request-method", "GET"),
+                    ("access-control-request-headers", "x-one, x-two"),
+                ],
+            ))
+            .unwrap()
+            .unwrap();
+        assert_eq!(header(&response, "access-control-allow-origin"), Some("*"));
+        assert_eq!(
+            header(&response, "access-control-allow-headers"),
+            Some("x-one, x-two")
+        );
+        assert_eq!(header(&response, "vary"), None);
+    }
+
+    #[test]
+    fn test_response_headers() {
+        let cors = filter(&[
+            ("origin-pattern", string(r"^https://[a-z]+\.example\.com$")),
+            ("expose-headers", string("x-request-id")),
+        ]);
+
+        let mut response = ResponseHeader::build(200, None).unwrap();
+        response.insert_header("vary", "Accept-Encoding").unwrap();
+        cors.decorate(
+            &request("GET", &[("origin", "https://app.example.com")]),
+            &mut response,
+        )
+        .unwrap();
+        assert_eq!(
+            header(&response, "access-control-allow-origin"),
+            Some("https://app.example.com")
+        );
+        assert_eq!(
+            header(&response, "access-control-expose-headers"),
+            Some("x-request-id")
+        );
+        assert_eq!(header(&response, "access-control-allow-credentials"), None);
+        let vary = response
+            .headers
+            .get_all("vary")
+            .iter()
+            .map(|v| v.to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(vary, ["Accept-Encoding", "Origin"]);
+
+        for headers in [vec![("origin", "https://evil.com")], vec![]] {
+            let mut response = ResponseHeader::build(200, None).unwrap();
+            cors.decorate(&request("GET", &headers), &mut response)
+                .unwrap();
+            assert!(response.headers.is_empty(), "{headers:?}");
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use async_trait::async_trait;
+use motya_config::common_types::{duration, value::Value};
+use pingora::{Error, Result};
+use pingora_proxy::Session;
+
+use crate::proxy::{
+    filters::{
+        builtin::helpers::{ConfigMapExt, RequiredValueExt},
+        types::RequestFilterMod,
+    },
+    MotyaContext,
+};
+
+/// Holds every request for `duration` plus up to `jitter` before letting it through,
+/// to simulate a slow backend in staging.
+///
+/// The wait is a tokio timer, so the worker thread keeps serving other requests.
+/// The config linker rejects this filter when `system { production #true; }` is set.
+pub struct DelayFilter {
+    duration: Duration,
+    jitter: Duration,
+}
+
+impl DelayFilter {
+    pub fn from_settings(mut settings: BTreeMap<String, Value>) -> Result<Self> {
+        let duration = parse_duration(
+            "duration",
+            &settings
+                .take_val::<String>("duration")?
+                .required("duration")?,
+        )?;
+
+        let jitter = settings
+            .take_val::<String>("jitter")?
+            .map(|raw| parse_duration("jitter", &raw))
+            .transpose()?
+            .unwrap_or_default();
+
+        Ok(Self { duration, jitter })
+    }
+
+    fn next_delay(&self) -> Duration {
+        if self.jitter.is_zero() {
+            return self.duration;
+        }
+
+        let jitter_ns = fastrand::u64(0..=self.jitter.as_nanos() as u64);
+        self.duration + Duration::from_nanos(jitter_ns)
+    }
+}
+
+fn parse_duration(key: &str, raw: &str) -> Result<Duration> {
+    raw.parse::<duration::Duration>()
+        .map(duration::Duration::get)
+        .map_err(|e| {
+            tracing::error!("Field '{key}' is not a valid duration: {e}");
+            Error::new_str("Invalid configuration: Invalid duration")
+        })
+}
+
+#[async_trait]
+impl RequestFilterMod for DelayFilter {
+    async fn request_filter(
+        &self,
+        _session: &mut Session,
+        _ctx: &mut MotyaContext,
+    ) -> Result<bool> {
+        tokio::time::sleep(self.next_delay()).await;
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(pairs: &[(&str, &str)]) -> BTreeMap<String, Value> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), Value::String(v.to_string())))
+            .collect()
+    }
+
+    #[test]
+    fn test_from_settings_with_jitter() {
+        let filter =
+            DelayFilter::from_settings(settings(&[("duration", "150ms"), ("jitter", "50ms")]))
+                .expect("Should successfully create filter");
+
+        assert_eq!(filter.duration, Duration::from_millis(150));
+        assert_eq!(filter.jitter, Duration::from_millis(50));
+
+        for _ in 0..100 {
+            let delay = filter.next_delay();
+            assert!(delay >= Duration::from_millis(150));
+            assert!(delay <= Duration::from_millis(200));
+        }
+    }
+
+    #[test]
+    fn test_from_settings_without_jitter() {
+        let filter = DelayFilter::from_settings(settings(&[("duration", "1s")]))
+            .expect("Should successfully create filter");
+
+        assert_eq!(filter.next_delay(), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_from_settings_invalid_duration() {
+        let result = DelayFilter::from_settings(settings(&[("duration", "soon")]));
+
+        let err = result.err().unwrap();
+        assert!(format!("{:?}", err).contains("Invalid duration"));
+    }
+
+    #[test]
+    fn test_from_settings_missing_duration() {
+        let result = DelayFilter::from_settings(settings(&[("jitter", "10ms")]));
+
+        let err = result.err().unwrap();
+        assert!(format!("{:?}", err).contains("Missing configuration"));
+    }
+}
@@ -0,0 +1,270 @@
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use http::{header, HeaderMap, HeaderName, StatusCode};
+use motya_config::common_types::{
+    ext_auth::{ExtAuth, FailureMode},
+    value::Value,
+};
+use pingora::{Error, Result};
+use pingora_http::{RequestHeader, ResponseHeader};
+use pingora_proxy::Session;
+
+use crate::proxy::{
+    error_pages, filters::types::RequestFilterMod, mirror::HOP_BY_HOP, MotyaContext,
+};
+
+/// `motya.filters.ext-auth`: asks an authorization service about every request.
+///
+/// The service gets the method, path and headers of the request, without its
+/// body. A `2xx` reply lets the request through with the `upstream-headers` of
+/// the reply, any other reply below `500` is passed to the client instead. A
+/// `5xx`, a timeout or a failed connection leave it to the `failure-mode`.
+pub struct ExtAuthFilter {
+    auth: ExtAuth,
+    client: reqwest::Client,
+    /// `url` without a trailing `/`.
+    base: String,
+}
+
+/// What the authorization service made of a request.
+enum Verdict {
+    /// It goes on, with the `upstream-headers` of the reply.
+    Allow(HeaderMap),
+    /// The client gets this instead.
+    Deny {
+        status: StatusCode,
+        headers: HeaderMap,
+        body: Bytes,
+    },
+    /// The service gave no answer.
+    Failed(String),
+}
+
+impl ExtAuthFilter {
+    pub fn from_settings(settings: BTreeMap<String, Value>) -> Result<Self> {
+        let auth = ExtAuth::parse(&settings).map_err(|e| {
+            tracing::error!("Invalid ext-auth configuration: {}", e.message);
+            Error::new_str("Invalid configuration: Bad ext-auth argument")
+        })?;
+
+        let client = reqwest::Client::builder()
+            .timeout(auth.timeout)
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .map_err(|e| {
+                tracing::error!("Failed to create the ext-auth client: {e}");
+                Error::new_str("Failed to create the ext-auth client")
+            })?;
+        let base = auth.url.to_string().trim_end_matches('/').to_string();
+
+        Ok(Self { auth, client, base })
+    }
+
+    async fn check(&self, request: &RequestHeader) -> Verdict {
+        let headers = match &self.auth.request_headers {
+            Some(names) => pick(&request.headers, names),
+            None => {
+                let mut headers = request.headers.clone();
+                for name in HOP_BY_HOP {
+                    headers.remove(name);
+                }
+                headers
+            }
+        };
+        let path = request.uri.path_and_query().map_or("/", |p| p.as_str());
+
+        let reply = self
+            .client
+            .request(request.method.clone(), format!("{}{path}", self.base))
+            .headers(headers)
+            .send()
+            .await;
+        let reply = match reply {
+            Ok(reply) => reply,
+            Err(e) => return Verdict::Failed(e.to_string()),
+        };
+
+        let status = reply.status();
+        if status.is_server_error() {
+            return Verdict::Failed(format!("it answered {status}"));
+        }
+        if status.is_success() {
+            return Verdict::Allow(pick(reply.headers(), &self.auth.upstream_headers));
+        }
+
+        let headers = pick(reply.headers(), &self.auth.client_headers);
+        match reply.bytes().await {
+            Ok(body) => Verdict::Deny {
+                status,
+                headers,
+                body,
+            },
+            Err(e) => Verdict::Failed(e.to_string()),
+        }
+    }
+}
+
+/// The values `headers` has for `names`.
+fn pick(headers: &HeaderMap, names: &[HeaderName]) -> HeaderMap {
+    let mut picked = HeaderMap::new();
+    for name in names {
+        for value in headers.get_all(name) {
+            picked.append(name.clone(), value.clone());
+        }
+    }
+    picked
+}
+
+#[async_trait]
+impl RequestFilterMod for ExtAuthFilter {
+    async fn request_filter(&self, session: &mut Session, ctx: &mut MotyaContext) -> Result<bool> {
+        match self.check(session.req_header()).await {
+            Verdict::Allow(headers) => {
+                // Listed headers the client sent itself never reach the upstream.
+                let request = session.req_header_mut();
+                for name in &self.auth.upstream_headers {
+                    request.remove_header(name);
+                    for value in headers.get_all(name) {
+                        request.append_header(name.clone(), value.clone())?;
+                    }
+                }
+                Ok(false)
+            }
+            Verdict::Deny {
+                status,
+                headers,
+                body,
+            } => {
+                let mut response = ResponseHeader::build(status, Some(headers.len() + 1))?;
+                for (name, value) in &headers {
+                    response.append_header(name.clone(), value.clone())?;
+                }
+                response.insert_header(header::CONTENT_LENGTH, body.len())?;
+
+                session
+                    .downstream_session
+                    .write_response_header(Box::new(response))
+                    .await?;
+                session
+                    .downstream_session
+                    .write_response_body(body, true)
+                    .await?;
+                Ok(true)
+            }
+            Verdict::Failed(reason) => {
+                tracing::warn!("Authorization service {} failed: {reason}", self.base);
+                match self.auth.failure_mode {
+                    FailureMode::Open => Ok(false),
+                    FailureMode::Closed => {
+                        error_pages::respond_error(session, ctx, 503).await?;
+                        Ok(true)
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::{
+        matchers::{header as has_header, method, path, query_param},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::*;
+
+    fn filter(url: &str, extra: &[(&str, &str)]) -> ExtAuthFilter {
+        let mut settings = BTreeMap::new();
+        settings.insert("url".to_string(), Value::String(url.to_string()));
+        for (key, value) in extra {
+            settings.insert(key.to_string(), Value::String(value.to_string()));
+        }
+        ExtAuthFilter::from_settings(settings).unwrap()
+    }
+
+    fn request(token: &str) -> RequestHeader {
+        let mut request = RequestHeader::build("POST", b"/orders?id=7", None).unwrap();
+        request.insert_header("authorization", token).unwrap();
+        request.insert_header("cookie", "theme=dark").unwrap();
+        request
+    }
+
+    #[tokio::test]
+    async fn test_verdicts() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/check/orders"))
+            .and(query_param("id", "7"))
+            .and(has_header("authorization", "Bearer good"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("x-user-id", "42")
+                    .insert_header("x-internal", "yes"),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(has_header("authorization", "Bearer bad"))
+            .respond_with(
+                ResponseTemplate::new(401)
+                    .insert_header("www-authenticate", "Bearer")
+                    .insert_header("x-internal", "yes")
+                    .set_body_string("expired"),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(502))
+            .mount(&server)
+            .await;
+
+        let auth = filter(
+            &format!("{}/check/", server.uri()),
+            &[
+                ("request-headers", "authorization"),
+                ("upstream-headers", "x-user-id"),
+                ("client-headers", "www-authenticate"),
+            ],
+        );
+
+        let Verdict::Allow(headers) = auth.check(&request("Bearer good")).await else {
+            panic!("expected the request to be allowed");
+        };
+        assert_eq!(headers["x-user-id"], "42");
+        assert_eq!(headers.len(), 1);
+
+        let Verdict::Deny {
+            status,
+            headers,
+            body,
+        } = auth.check(&request("Bearer bad")).await
+        else {
+            panic!("expected the request to be denied");
+        };
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(headers["www-authenticate"], "Bearer");
+        assert_eq!(headers.len(), 1);
+        assert_eq!(body, "expired");
+
+        assert!(matches!(
+            auth.check(&request("Bearer other")).await,
+            Verdict::Failed(_)
+        ));
+
+        // Only the listed headers are sent along.
+        let requests = server.received_requests().await.unwrap();
+        assert!(requests.iter().all(|r| !r.headers.contains_key("cookie")));
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_service() {
+        let auth = filter("http://127.0.0.1:1", &[("timeout", "200ms")]);
+        assert!(matches!(
+            auth.check(&request("Bearer good")).await,
+            Verdict::Failed(_)
+        ));
+    }
+}
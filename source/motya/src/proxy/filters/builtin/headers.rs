@@ -0,0 +1,246 @@
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use http::{HeaderName, HeaderValue};
+use motya_config::common_types::{
+    header_ops::{HeaderOp, HeaderOpKind},
+    value::Value,
+};
+use pingora::{Error, Result};
+use pingora_http::{RequestHeader, ResponseHeader};
+use pingora_proxy::Session;
+
+use crate::proxy::{
+    filters::types::{RequestModifyMod, ResponseModifyMod},
+    MotyaContext,
+};
+
+/// The header methods shared by requests and responses. They keep pingora's
+/// record of the original header name case in step with the header map.
+trait HeaderTarget {
+    fn names(&self) -> Vec<HeaderName>;
+    fn take_all(&mut self, name: &HeaderName) -> Vec<HeaderValue>;
+    fn insert(&mut self, name: HeaderName, value: HeaderValue) -> Result<()>;
+    fn append(&mut self, name: HeaderName, value: HeaderValue) -> Result<()>;
+}
+
+macro_rules! impl_header_target {
+    ($($t:ty),*) => {
+        $(
+            impl HeaderTarget for $t {
+                fn names(&self) -> Vec<HeaderName> {
+                    self.headers.keys().cloned().collect()
+                }
+
+                fn take_all(&mut self, name: &HeaderName) -> Vec<HeaderValue> {
+                    let values = self.headers.get_all(name).iter().cloned().collect();
+                    self.remove_header(name);
+                    values
+                }
+
+                fn insert(&mut self, name: HeaderName, value: HeaderValue) -> Result<()> {
+                    self.insert_header(name, value)
+                }
+
+                fn append(&mut self, name: HeaderName, value: HeaderValue) -> Result<()> {
+                    self.append_header(name, value).map(|_| ())
+                }
+            }
+        )*
+    };
+}
+
+impl_header_target!(RequestHeader, ResponseHeader);
+
+fn apply(op: &HeaderOp, target: &mut impl HeaderTarget) -> Result<()> {
+    match op {
+        HeaderOp::Set { name, value } => {
+            target.insert(name.clone(), value.clone())?;
+            tracing::debug!("Set header: {name}: {value:?}");
+        }
+        HeaderOp::Remove { name } => {
+            if !target.take_all(name).is_empty() {
+                tracing::debug!("Removed header: {name}");
+            }
+        }
+        HeaderOp::RemoveRegex { pattern } => {
+            for name in target.names() {
+                if pattern.is_match(name.as_str()) {
+                    target.take_all(&name);
+                    tracing::debug!("Removed header: {name}");
+                }
+            }
+        }
+        HeaderOp::Rename { from, to } => {
+            let values = target.take_all(from);
+            if values.is_empty() {
+                return Ok(());
+            }
+
+            target.take_all(to);
+            for value in values {
+                target.append(to.clone(), value)?;
+            }
+            tracing::debug!("Renamed header: {from} -> {to}");
+        }
+    }
+
+    Ok(())
+}
+
+fn parse(kind: HeaderOpKind, settings: &BTreeMap<String, Value>) -> Result<HeaderOp> {
+    HeaderOp::parse(kind, settings).map_err(|e| {
+        tracing::error!("Invalid header filter configuration: {}", e.message);
+        Error::new_str("Invalid configuration: Bad header filter argument")
+    })
+}
+
+macro_rules! header_filters {
+    ($($(#[$meta:meta])* $name:ident => $kind:ident),* $(,)?) => {
+        $(
+            $(#[$meta])*
+            pub struct $name {
+                op: HeaderOp,
+            }
+
+            impl $name {
+                pub fn from_settings(settings: BTreeMap<String, Value>) -> Result<Self> {
+                    Ok(Self {
+                        op: parse(HeaderOpKind::$kind, &settings)?,
+                    })
+                }
+            }
+
+            #[async_trait]
+            impl RequestModifyMod for $name {
+                async fn upstream_request_filter(
+                    &self,
+                    _session: &mut Session,
+                    header: &mut RequestHeader,
+                    _ctx: &mut MotyaContext,
+                ) -> Result<()> {
+                    apply(&self.op, header)
+                }
+            }
+
+            impl ResponseModifyMod for $name {
+                fn upstream_response_filter(
+                    &self,
+                    _session: &mut Session,
+                    header: &mut ResponseHeader,
+                    _ctx: &mut MotyaContext,
+                ) {
+                    if let Err(e) = apply(&self.op, header) {
+                        tracing::warn!("Failed to modify response headers: {e:?}");
+                    }
+                }
+            }
+        )*
+    };
+}
+
+header_filters! {
+    /// `set-header`: replaces every value of `name` with `value`.
+    SetHeader => Set,
+    /// `remove-header`: drops every value of `name`.
+    RemoveHeader => Remove,
+    /// `remove-header-regex`: drops every header whose name matches `pattern`.
+    RemoveHeaderRegex => RemoveRegex,
+    /// `rename-header`: moves the values of `from` to `to`.
+    RenameHeader => Rename,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(pairs: &[(&str, &str)]) -> BTreeMap<String, Value> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), Value::String(v.to_string())))
+            .collect()
+    }
+
+    fn request() -> RequestHeader {
+        let mut req = RequestHeader::build("GET", b"/", None).unwrap();
+        req.append_header("X-Old", "1").unwrap();
+        req.append_header("X-Old", "2").unwrap();
+        req.append_header("X-New", "stale").unwrap();
+        req.append_header("X-Trace-Id", "abc").unwrap();
+        req
+    }
+
+    fn values(req: &RequestHeader, name: &str) -> Vec<String> {
+        req.headers
+            .get_all(name)
+            .iter()
+            .map(|v| v.to_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_set_replaces_all_values() {
+        let filter =
+            SetHeader::from_settings(settings(&[("name", "X-Old"), ("value", "3")])).unwrap();
+        let mut req = request();
+        apply(&filter.op, &mut req).unwrap();
+
+        assert_eq!(values(&req, "x-old"), ["3"]);
+    }
+
+    #[test]
+    fn test_remove_and_remove_regex() {
+        let filter = RemoveHeader::from_settings(settings(&[("name", "x-old")])).unwrap();
+        let mut req = request();
+        apply(&filter.op, &mut req).unwrap();
+        assert!(values(&req, "x-old").is_empty());
+        assert_eq!(values(&req, "x-new"), ["stale"]);
+
+        let filter =
+            RemoveHeaderRegex::from_settings(settings(&[("pattern", "^x-(old|new)$")])).unwrap();
+        let mut req = request();
+        apply(&filter.op, &mut req).unwrap();
+        assert!(values(&req, "x-old").is_empty());
+        assert!(values(&req, "x-new").is_empty());
+        assert_eq!(values(&req, "x-trace-id"), ["abc"]);
+    }
+
+    #[test]
+    fn test_rename_moves_every_value() {
+        let filter =
+            RenameHeader::from_settings(settings(&[("from", "X-Old"), ("to", "X-New")])).unwrap();
+        let mut req = request();
+        apply(&filter.op, &mut req).unwrap();
+
+        assert!(values(&req, "x-old").is_empty());
+        assert_eq!(values(&req, "x-new"), ["1", "2"]);
+
+        // Nothing to rename leaves the target alone.
+        let filter =
+            RenameHeader::from_settings(settings(&[("from", "X-Missing"), ("to", "X-Trace-Id")]))
+                .unwrap();
+        apply(&filter.op, &mut req).unwrap();
+        assert_eq!(values(&req, "x-trace-id"), ["abc"]);
+    }
+
+    #[test]
+    fn test_response_headers() {
+        let mut resp = ResponseHeader::build(200, None).unwrap();
+        resp.append_header("Server", "upstream").unwrap();
+
+        let filter =
+            RenameHeader::from_settings(settings(&[("from", "Server"), ("to", "X-Server")]))
+                .unwrap();
+        apply(&filter.op, &mut resp).unwrap();
+
+        assert!(resp.headers.get("server").is_none());
+        assert_eq!(resp.headers.get("x-server").unwrap(), "upstream");
+    }
+
+    #[test]
+    fn test_bad_settings() {
+        assert!(SetHeader::from_settings(settings(&[("name", "X-Only")])).is_err());
+        assert!(RemoveHeaderRegex::from_settings(settings(&[("pattern", "(")])).is_err());
+        assert!(RenameHeader::from_settings(settings(&[("from", "a"), ("to", "A")])).is_err());
+    }
+}
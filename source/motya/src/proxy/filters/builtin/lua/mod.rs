@@ -0,0 +1,176 @@
+//! Filters written in Lua (`motya.request.lua` and `motya.response.lua`),
+//! in builds with the `lua` feature.
+//!
+//! The script given as `script` runs once, when the chain is built, and defines
+//! a global function the filter calls for every request: `on_request(request)`
+//! for the request filter, `on_response(response)` for the response filter.
+//! The argument has methods named as the host functions of plugins, e.g.
+//! `request:get_header(name)`, and only lives for the call.
+//!
+//! Every filter has a Lua state of its own, with the `string`, `table`, `math`
+//! and `utf8` libraries but no `io` or `os`. The state runs the script for one
+//! request at a time.
+
+mod request;
+mod response;
+
+use std::collections::BTreeMap;
+
+use fqdn::fqdn;
+use mlua::{Function, Lua, LuaOptions, StdLib, UserData, UserDataMethods};
+use motya_config::common_types::{definitions_table::DefinitionsTable, value::Value};
+use pingora::{Error, Result};
+
+pub use self::{request::LuaRequestFilter, response::LuaResponseFilter};
+use crate::proxy::filters::{
+    builtin::helpers::{ConfigMapExt, RequiredValueExt},
+    registry::{FilterInstance, FilterRegistry, RegistryFilterContainer},
+};
+
+/// Adds the Lua filters to the built-in ones.
+pub fn register(registry: &mut FilterRegistry, definitions: &mut DefinitionsTable) {
+    let key = fqdn!("motya.request.lua");
+    definitions.insert_filter(key.clone());
+    registry.register_factory(
+        key,
+        Box::new(|settings| {
+            let item = LuaRequestFilter::from_settings(settings)?;
+            Ok(RegistryFilterContainer::Builtin(FilterInstance::Action(
+                Box::new(item),
+            )))
+        }),
+    );
+
+    let key = fqdn!("motya.response.lua");
+    definitions.insert_filter(key.clone());
+    registry.register_factory(
+        key,
+        Box::new(|settings| {
+            let item = LuaResponseFilter::from_settings(settings)?;
+            Ok(RegistryFilterContainer::Builtin(FilterInstance::Response(
+                Box::new(item),
+            )))
+        }),
+    );
+}
+
+struct Script {
+    path: String,
+    lua: Lua,
+    function: Function,
+}
+
+impl Script {
+    /// Runs the script given as `script` and takes its global `function`.
+    fn from_settings(mut settings: BTreeMap<String, Value>, function: &str) -> Result<Self> {
+        let path = settings.take_val::<String>("script")?.required("script")?;
+
+        let source = std::fs::read_to_string(&path).map_err(|e| {
+            tracing::error!("Failed to read Lua script '{path}': {e}");
+            Error::new_str("Failed to read Lua script")
+        })?;
+
+        let libs = StdLib::STRING | StdLib::TABLE | StdLib::MATH | StdLib::UTF8;
+        let lua = Lua::new_with(libs, LuaOptions::default()).map_err(|e| {
+            tracing::error!("Failed to create a Lua state: {e}");
+            Error::new_str("Failed to create a Lua state")
+        })?;
+
+        lua.load(source.as_str())
+            .set_name(format!("@{path}"))
+            .exec()
+            .map_err(|e| {
+                tracing::error!("Lua script '{path}' failed to run: {e}");
+                Error::new_str("Lua script failed to run")
+            })?;
+
+        let function = lua
+            .globals()
+            .get::<Option<Function>>(function)
+            .ok()
+            .flatten()
+            .ok_or_else(|| {
+                tracing::error!("Lua script '{path}' defines no '{function}' function");
+                Error::new_str("Lua script lacks its filter function")
+            })?;
+
+        Ok(Self {
+            path,
+            lua,
+            function,
+        })
+    }
+
+    /// Calls the function of the script with `arg`, which it can't keep.
+    fn call<T: UserData>(&self, arg: T) -> Result<()> {
+        self.lua
+            .scope(|scope| self.function.call::<()>(scope.create_userdata(arg)?))
+            .map_err(|e| {
+                tracing::error!("Lua script '{}' failed: {e}", self.path);
+                Error::new_str("Lua script failed")
+            })
+    }
+}
+
+/// The headers of a request or a response, for the methods both have.
+trait Headers {
+    fn get(&self, name: &str) -> Option<String>;
+
+    fn set(&mut self, name: String, value: String) -> Result<()>;
+
+    fn remove(&mut self, name: &str);
+}
+
+fn add_header_methods<T, M>(methods: &mut M)
+where
+    T: Headers + UserData,
+    M: UserDataMethods<T>,
+{
+    methods.add_method("get_header", |_, this, name: String| Ok(this.get(&name)));
+
+    methods.add_method_mut("set_header", |_, this, (name, value): (String, String)| {
+        this.set(name, value)
+            .map_err(|e| mlua::Error::runtime(e.to_string()))
+    });
+
+    methods.add_method_mut("remove_header", |_, this, name: String| {
+        this.remove(&name);
+        Ok(())
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    pub(super) fn script_settings(
+        script: &str,
+    ) -> (tempfile::NamedTempFile, BTreeMap<String, Value>) {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(script.as_bytes()).unwrap();
+
+        let mut settings = BTreeMap::new();
+        settings.insert(
+            "script".to_string(),
+            Value::String(file.path().to_str().unwrap().to_string()),
+        );
+        (file, settings)
+    }
+
+    #[test]
+    fn test_script_errors() {
+        let (_file, settings) = script_settings("function on_response(response) end");
+        assert!(Script::from_settings(settings, "on_request").is_err());
+
+        let (_file, settings) = script_settings("function on_request(request");
+        assert!(Script::from_settings(settings, "on_request").is_err());
+
+        // The sandbox has no `io` or `os`.
+        let (_file, settings) = script_settings("os.exit(1)");
+        assert!(Script::from_settings(settings, "on_request").is_err());
+
+        assert!(Script::from_settings(BTreeMap::new(), "on_request").is_err());
+    }
+}
@@ -0,0 +1,189 @@
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use http::{
+    uri::{PathAndQuery, Uri},
+    StatusCode,
+};
+use mlua::{UserData, UserDataMethods};
+use motya_config::common_types::value::Value;
+use pingora::Result;
+use pingora_http::{RequestHeader, ResponseHeader};
+use pingora_proxy::Session;
+
+use crate::proxy::{
+    error_pages,
+    filters::{
+        builtin::lua::{add_header_methods, Headers, Script},
+        types::RequestFilterMod,
+    },
+    MotyaContext,
+};
+
+/// `motya.request.lua`: calls `on_request(request)` of the script before the
+/// request goes upstream.
+pub struct LuaRequestFilter {
+    script: Script,
+}
+
+/// What the script answered the request with, from `request:respond`.
+struct Reply {
+    status: StatusCode,
+    body: Option<String>,
+}
+
+/// The `request` of `on_request`.
+struct ScriptRequest<'a> {
+    header: &'a mut RequestHeader,
+    reply: &'a mut Option<Reply>,
+}
+
+impl LuaRequestFilter {
+    pub fn from_settings(settings: BTreeMap<String, Value>) -> Result<Self> {
+        Ok(Self {
+            script: Script::from_settings(settings, "on_request")?,
+        })
+    }
+
+    /// Runs the script on `header`, returning its reply if it gave one.
+    fn run(&self, header: &mut RequestHeader) -> Result<Option<Reply>> {
+        let mut reply = None;
+        self.script.call(ScriptRequest {
+            header,
+            reply: &mut reply,
+        })?;
+        Ok(reply)
+    }
+}
+
+#[async_trait]
+impl RequestFilterMod for LuaRequestFilter {
+    async fn request_filter(&self, session: &mut Session, ctx: &mut MotyaContext) -> Result<bool> {
+        let Some(reply) = self.run(session.req_header_mut())? else {
+            return Ok(false);
+        };
+
+        let Some(body) = reply.body else {
+            error_pages::respond_error(session, ctx, reply.status.as_u16()).await?;
+            return Ok(true);
+        };
+
+        let mut response = ResponseHeader::build(reply.status, Some(2))?;
+        response.insert_header("Content-Type", "text/plain; charset=utf-8")?;
+        response.insert_header("Content-Length", body.len())?;
+        session
+            .downstream_session
+            .write_response_header(Box::new(response))
+            .await?;
+        session
+            .downstream_session
+            .write_response_body(Bytes::from(body), true)
+            .await?;
+        Ok(true)
+    }
+}
+
+impl Headers for ScriptRequest<'_> {
+    fn get(&self, name: &str) -> Option<String> {
+        self.header
+            .headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+    }
+
+    fn set(&mut self, name: String, value: String) -> Result<()> {
+        self.header.insert_header(name, value)
+    }
+
+    fn remove(&mut self, name: &str) {
+        self.header.remove_header(name);
+    }
+}
+
+impl UserData for ScriptRequest<'_> {
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        add_header_methods(methods);
+
+        methods.add_method("get_method", |_, this, ()| {
+            Ok(this.header.method.to_string())
+        });
+
+        methods.add_method("get_path", |_, this, ()| {
+            Ok(this.header.uri.path().to_string())
+        });
+
+        methods.add_method_mut("set_path", |_, this, path: String| {
+            let path_and_query = match this.header.uri.query() {
+                Some(query) => format!("{path}?{query}"),
+                None => path.clone(),
+            };
+            let invalid = || mlua::Error::runtime(format!("'{path}' is not a valid path"));
+
+            let path_and_query: PathAndQuery = path_and_query.parse().map_err(|_| invalid())?;
+            let mut parts = this.header.uri.clone().into_parts();
+            parts.path_and_query = Some(path_and_query);
+            let uri = Uri::from_parts(parts).map_err(|_| invalid())?;
+
+            this.header.set_uri(uri);
+            Ok(())
+        });
+
+        methods.add_method_mut(
+            "respond",
+            |_, this, (status, body): (u16, Option<String>)| {
+                let status = StatusCode::from_u16(status).map_err(|_| {
+                    mlua::Error::runtime(format!("{status} is not a valid status code"))
+                })?;
+                *this.reply = Some(Reply { status, body });
+                Ok(())
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::filters::builtin::lua::tests::script_settings;
+
+    const SCRIPT: &str = r#"
+        function on_request(request)
+            if request:get_header("x-token") ~= "secret" then
+                request:respond(401, "who are you?")
+                return
+            end
+            request:remove_header("x-token")
+            request:set_header("x-method", request:get_method())
+            request:set_path("/v2" .. request:get_path())
+        end
+    "#;
+
+    #[test]
+    fn test_request_script() {
+        let (_file, settings) = script_settings(SCRIPT);
+        let filter = LuaRequestFilter::from_settings(settings).unwrap();
+
+        let mut request = RequestHeader::build("GET", b"/api?page=2", None).unwrap();
+        let reply = filter.run(&mut request).unwrap().unwrap();
+        assert_eq!(reply.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(reply.body.as_deref(), Some("who are you?"));
+
+        request.insert_header("x-token", "secret").unwrap();
+        assert!(filter.run(&mut request).unwrap().is_none());
+        assert_eq!(request.uri.to_string(), "/v2/api?page=2");
+        assert_eq!(request.headers.get("x-method").unwrap(), "GET");
+        assert!(request.headers.get("x-token").is_none());
+    }
+
+    #[test]
+    fn test_script_failures() {
+        let (_file, settings) =
+            script_settings("function on_request(request) request:respond(1000) end");
+        let filter = LuaRequestFilter::from_settings(settings).unwrap();
+
+        let mut request = RequestHeader::build("GET", b"/", None).unwrap();
+        assert!(filter.run(&mut request).is_err());
+    }
+}
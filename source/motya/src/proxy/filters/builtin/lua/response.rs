@@ -0,0 +1,115 @@
+use std::collections::BTreeMap;
+
+use http::StatusCode;
+use mlua::{UserData, UserDataMethods};
+use motya_config::common_types::value::Value;
+use pingora::Result;
+use pingora_http::ResponseHeader;
+use pingora_proxy::Session;
+
+use crate::proxy::{
+    filters::{
+        builtin::lua::{add_header_methods, Headers, Script},
+        types::ResponseModifyMod,
+    },
+    MotyaContext,
+};
+
+/// `motya.response.lua`: calls `on_response(response)` of the script with the
+/// response header of the upstream.
+pub struct LuaResponseFilter {
+    script: Script,
+}
+
+/// The `response` of `on_response`.
+struct ScriptResponse<'a> {
+    header: &'a mut ResponseHeader,
+}
+
+impl LuaResponseFilter {
+    pub fn from_settings(settings: BTreeMap<String, Value>) -> Result<Self> {
+        Ok(Self {
+            script: Script::from_settings(settings, "on_response")?,
+        })
+    }
+}
+
+impl ResponseModifyMod for LuaResponseFilter {
+    fn upstream_response_filter(
+        &self,
+        _session: &mut Session,
+        header: &mut ResponseHeader,
+        _ctx: &mut MotyaContext,
+    ) {
+        // Past this point the response can't fail, it goes out with whatever
+        // the script changed before failing. `call` logs the error.
+        let _ = self.script.call(ScriptResponse { header });
+    }
+}
+
+impl Headers for ScriptResponse<'_> {
+    fn get(&self, name: &str) -> Option<String> {
+        self.header
+            .headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+    }
+
+    fn set(&mut self, name: String, value: String) -> Result<()> {
+        self.header.insert_header(name, value)
+    }
+
+    fn remove(&mut self, name: &str) {
+        self.header.remove_header(name);
+    }
+}
+
+impl UserData for ScriptResponse<'_> {
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        add_header_methods(methods);
+
+        methods.add_method("get_status", |_, this, ()| Ok(this.header.status.as_u16()));
+
+        methods.add_method_mut("set_status", |_, this, status: u16| {
+            let invalid = || mlua::Error::runtime(format!("{status} is not a valid status code"));
+            let status = StatusCode::from_u16(status).map_err(|_| invalid())?;
+            this.header.set_status(status).map_err(|_| invalid())
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::filters::builtin::lua::tests::script_settings;
+
+    #[test]
+    fn test_response_script() {
+        let (_file, settings) = script_settings(
+            r#"
+            function on_response(response)
+                response:remove_header("server")
+                if response:get_status() == 404 then
+                    response:set_status(410)
+                    response:set_header("x-gone", "yes")
+                end
+            end
+            "#,
+        );
+        let filter = LuaResponseFilter::from_settings(settings).unwrap();
+
+        let mut response = ResponseHeader::build(404, None).unwrap();
+        response.insert_header("server", "legacy").unwrap();
+        filter
+            .script
+            .call(ScriptResponse {
+                header: &mut response,
+            })
+            .unwrap();
+
+        assert_eq!(response.status, StatusCode::GONE);
+        assert_eq!(response.headers.get("x-gone").unwrap(), "yes");
+        assert!(response.headers.get("server").is_none());
+    }
+}
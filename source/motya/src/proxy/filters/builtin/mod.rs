@@ -1,6 +1,15 @@
+pub mod basic_auth;
+pub mod cidr_access;
 pub mod cidr_range;
+pub mod cors;
+pub mod delay;
+pub mod ext_auth;
+pub mod headers;
 pub mod helpers;
+#[cfg(feature = "lua")]
+pub mod lua;
 pub mod rate_limiter;
 pub mod request;
-pub mod response;
+pub mod rewrite_body;
 pub mod simple_response;
+pub mod throttle;
@@ -2,10 +2,11 @@ use std::sync::Arc;
 
 use async_trait::async_trait;
 use pingora::Result;
+use pingora_http::ResponseHeader;
 use pingora_proxy::Session;
 
 use crate::proxy::{
-    context::SessionInfo, filters::types::RequestFilterMod,
+    context::SessionInfo, error_pages, filters::types::RequestFilterMod,
     rate_limiter::instance::RateLimiterInstance, MotyaContext,
 };
 
@@ -21,7 +22,7 @@ impl RateLimitFilter {
 
 #[async_trait]
 impl RequestFilterMod for RateLimitFilter {
-    async fn request_filter(&self, session: &mut Session, _ctx: &mut MotyaContext) -> Result<bool> {
+    async fn request_filter(&self, session: &mut Session, ctx: &mut MotyaContext) -> Result<bool> {
         let result = self
             .limiter
             .check(&SessionInfo {
@@ -41,16 +42,23 @@ impl RequestFilterMod for RateLimitFilter {
 
         headers.insert_header("X-RateLimit-Remaining", result.remaining.to_string())?;
 
+        let exposed = self.limiter.headers(&result);
+
         if result.allowed {
+            if let Some(exposed) = exposed {
+                ctx.expose_rate_limit(exposed);
+            }
             Ok(false)
         } else {
             let retry_secs = result.reset_after.as_secs().max(1).to_string();
-            session
-                .downstream_session
-                .req_header_mut()
-                .insert_header("Retry-After", retry_secs)?;
+            let mut response = ResponseHeader::build(429, Some(6))?;
+            response.insert_header("Cache-Control", "private, no-store")?;
+            response.insert_header("Retry-After", retry_secs)?;
+            if let Some(exposed) = exposed {
+                exposed.apply(&mut response)?;
+            }
 
-            session.downstream_session.respond_error(429).await?;
+            error_pages::respond(session, ctx, response).await?;
             Ok(true)
         }
     }
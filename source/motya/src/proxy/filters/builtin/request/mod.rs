@@ -1,4 +1,2 @@
-pub mod remove_headers;
 pub mod rewrite_path;
 pub mod strip_prefix;
-pub mod upsert_headers;
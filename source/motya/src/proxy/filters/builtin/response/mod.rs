@@ -1,2 +0,0 @@
-pub mod remove_header;
-pub mod upsert_header;
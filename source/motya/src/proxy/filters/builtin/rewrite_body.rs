@@ -0,0 +1,117 @@
+use std::{borrow::Cow, collections::BTreeMap};
+
+use bytes::Bytes;
+use http::header;
+use motya_config::common_types::{body_rewrite::BodyRewrite, value::Value};
+use pingora::{Error, Result};
+use pingora_proxy::Session;
+
+use crate::proxy::{
+    filters::types::{RequestBodyMod, ResponseBodyMod},
+    MotyaContext,
+};
+
+/// `rewrite-body`: replaces a string or regex in bodies of the listed content types.
+pub struct RewriteBody {
+    rewrite: BodyRewrite,
+}
+
+impl RewriteBody {
+    pub fn from_settings(settings: BTreeMap<String, Value>) -> Result<Self> {
+        let rewrite = BodyRewrite::parse(&settings).map_err(|e| {
+            tracing::error!("Invalid rewrite-body configuration: {}", e.message);
+            Error::new_str("Invalid configuration: Bad rewrite-body argument")
+        })?;
+
+        Ok(Self { rewrite })
+    }
+
+    fn rewrite(&self, body: &mut Bytes) {
+        if let Cow::Owned(rewritten) = self.rewrite.apply(body) {
+            *body = rewritten.into();
+        }
+    }
+}
+
+impl RequestBodyMod for RewriteBody {
+    fn max_body_size(&self) -> usize {
+        self.rewrite.max_body
+    }
+
+    fn request_body_filter(
+        &self,
+        session: &mut Session,
+        body: &mut Bytes,
+        _ctx: &mut MotyaContext,
+    ) -> Result<()> {
+        let content_type = session
+            .req_header()
+            .headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok());
+
+        if self.rewrite.applies_to(content_type) {
+            self.rewrite(body);
+        }
+        Ok(())
+    }
+}
+
+impl ResponseBodyMod for RewriteBody {
+    fn max_body_size(&self) -> usize {
+        self.rewrite.max_body
+    }
+
+    fn applies_to(&self, content_type: Option<&str>) -> bool {
+        self.rewrite.applies_to(content_type)
+    }
+
+    fn decodes_upstream(&self) -> bool {
+        self.rewrite.decode_upstream
+    }
+
+    fn response_body_filter(
+        &self,
+        _session: &mut Session,
+        body: &mut Bytes,
+        _ctx: &mut MotyaContext,
+    ) -> Result<()> {
+        self.rewrite(body);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(pairs: &[(&str, &str)]) -> BTreeMap<String, Value> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), Value::String(v.to_string())))
+            .collect()
+    }
+
+    #[test]
+    fn test_rewrite_keeps_untouched_bodies() {
+        let filter = RewriteBody::from_settings(settings(&[
+            ("find", "http://legacy.local"),
+            ("replace", "https://shop.example.com"),
+        ]))
+        .unwrap();
+
+        let original = Bytes::from_static(b"<p>nothing to see</p>");
+        let mut body = original.clone();
+        filter.rewrite(&mut body);
+        assert_eq!(body.as_ptr(), original.as_ptr());
+
+        let mut body = Bytes::from_static(b"<a href=\"http://legacy.local/\">");
+        filter.rewrite(&mut body);
+        assert_eq!(body, "<a href=\"https://shop.example.com/\">");
+    }
+
+    #[test]
+    fn test_invalid_settings() {
+        assert!(RewriteBody::from_settings(settings(&[("find", "x")])).is_err());
+    }
+}
@@ -0,0 +1,199 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, Instant},
+};
+
+use motya_config::common_types::{throttle::Throttle, value::Value};
+use pingora::{Error, Result};
+use pingora_http::ResponseHeader;
+use pingora_proxy::Session;
+use smallvec::SmallVec;
+
+use crate::proxy::{
+    context::SessionInfo, filters::types::ResponseModifyMod, key_selector::KeySelector,
+    MotyaContext,
+};
+
+/// `motya.response.throttle`: paces response bodies to `rate` bytes per second.
+///
+/// The filter picks the bucket of the response when its head arrives, and the
+/// proxy delays every body chunk by what the bucket owes. Responses with the
+/// same `key` share a bucket, so a client opening more connections doesn't get
+/// more bandwidth. Without a `key`, every response has its own.
+pub struct ThrottleFilter {
+    throttle: Throttle,
+    selector: Option<KeySelector>,
+    /// Buckets of the keys with responses in flight.
+    buckets: Mutex<Buckets>,
+}
+
+#[derive(Default)]
+struct Buckets {
+    by_key: HashMap<String, Weak<Pacer>>,
+    /// Dead buckets are dropped once the map grows to this size.
+    prune_at: usize,
+}
+
+impl ThrottleFilter {
+    pub fn from_settings(settings: BTreeMap<String, Value>) -> Result<Self> {
+        let throttle = Throttle::parse(&settings).map_err(|e| {
+            tracing::error!("Invalid throttle configuration: {}", e.message);
+            Error::new_str("Invalid configuration: Bad throttle argument")
+        })?;
+        let selector = throttle.key.clone().map(|key| KeySelector {
+            extraction_strategies: vec![key],
+            transforms: vec![],
+        });
+        Ok(Self {
+            throttle,
+            selector,
+            buckets: Mutex::default(),
+        })
+    }
+
+    fn new_pacer(&self) -> Arc<Pacer> {
+        Arc::new(Pacer::new(self.throttle.rate, self.throttle.burst))
+    }
+
+    /// The bucket shared by the responses with `key`.
+    fn pacer_for(&self, key: &str) -> Arc<Pacer> {
+        let mut buckets = self.buckets.lock().unwrap();
+        if let Some(pacer) = buckets.by_key.get(key).and_then(Weak::upgrade) {
+            return pacer;
+        }
+
+        let pacer = self.new_pacer();
+        buckets
+            .by_key
+            .insert(key.to_string(), Arc::downgrade(&pacer));
+        if buckets.by_key.len() >= buckets.prune_at {
+            buckets.by_key.retain(|_, pacer| pacer.strong_count() > 0);
+            buckets.prune_at = (buckets.by_key.len() * 2).max(64);
+        }
+        pacer
+    }
+}
+
+impl ResponseModifyMod for ThrottleFilter {
+    fn upstream_response_filter(
+        &self,
+        session: &mut Session,
+        _header: &mut ResponseHeader,
+        ctx: &mut MotyaContext,
+    ) {
+        let mut key_buf: SmallVec<[u8; 256]> = SmallVec::new();
+        let keyed = self.selector.as_ref().is_some_and(|selector| {
+            let request = session.req_header();
+            let Some(path) = request.uri.path_and_query() else {
+                return false;
+            };
+            selector.select(
+                &SessionInfo {
+                    headers: request,
+                    client_addr: session.client_addr(),
+                    path,
+                },
+                &mut key_buf,
+            )
+        });
+
+        // Without its key the response is paced on its own rather than not at all.
+        let pacer = match std::str::from_utf8(&key_buf) {
+            Ok(key) if keyed => self.pacer_for(key),
+            _ => self.new_pacer(),
+        };
+        ctx.pace_response(pacer);
+    }
+}
+
+/// A token bucket of bytes, `burst` deep and refilled at `rate` per second.
+///
+/// Taking more than there is leaves the bucket in debt, and the debt is how
+/// long the taker waits.
+pub struct Pacer {
+    rate: f64,
+    burst: f64,
+    state: Mutex<PacerState>,
+}
+
+struct PacerState {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Pacer {
+    pub fn new(rate: usize, burst: usize) -> Self {
+        Self {
+            rate: rate as f64,
+            burst: burst as f64,
+            state: Mutex::new(PacerState {
+                tokens: burst as f64,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    /// Takes `bytes` out of the bucket, and returns how long to hold them back.
+    pub fn take(&self, bytes: usize) -> Option<Duration> {
+        self.take_at(bytes, Instant::now())
+    }
+
+    fn take_at(&self, bytes: usize, now: Instant) -> Option<Duration> {
+        let mut state = self.state.lock().unwrap();
+        let elapsed = now.saturating_duration_since(state.updated).as_secs_f64();
+        state.tokens = (state.tokens + elapsed * self.rate).min(self.burst) - bytes as f64;
+        state.updated = now;
+
+        (state.tokens < 0.0).then(|| Duration::from_secs_f64(-state.tokens / self.rate))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pacing() {
+        let start = Instant::now();
+        let pacer = Pacer::new(1000, 1000);
+
+        // The burst goes out at once.
+        assert_eq!(pacer.take_at(600, start), None);
+        assert_eq!(pacer.take_at(400, start), None);
+
+        // Then a second per 1000 bytes.
+        assert_eq!(pacer.take_at(500, start), Some(Duration::from_millis(500)));
+        assert_eq!(pacer.take_at(500, start), Some(Duration::from_secs(1)));
+
+        // Waiting pays the debt back, and refills up to the burst only.
+        let later = start + Duration::from_secs(1);
+        assert_eq!(pacer.take_at(0, later), None);
+        let much_later = later + Duration::from_secs(60);
+        assert_eq!(pacer.take_at(1000, much_later), None);
+        assert_eq!(
+            pacer.take_at(250, much_later),
+            Some(Duration::from_millis(250))
+        );
+    }
+
+    #[test]
+    fn test_shared_buckets() {
+        let filter = ThrottleFilter::from_settings(
+            [("rate", "1kb/s"), ("key", "${client-ip}")]
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), Value::String(v.to_string())))
+                .collect(),
+        )
+        .unwrap();
+
+        let a = filter.pacer_for("10.0.0.1");
+        assert!(Arc::ptr_eq(&a, &filter.pacer_for("10.0.0.1")));
+        assert!(!Arc::ptr_eq(&a, &filter.pacer_for("10.0.0.2")));
+
+        // Once its responses are done, a key starts over with a full bucket.
+        a.take(4096);
+        drop(a);
+        assert_eq!(filter.pacer_for("10.0.0.1").take(1024), None);
+    }
+}
@@ -2,6 +2,7 @@ use std::{collections::BTreeMap, sync::Arc};
 
 use miette::{miette, Context, IntoDiagnostic, Result};
 use motya_config::common_types::{
+    builtin_filters_name::is_lua_filter,
     definitions::{ChainItem, FilterChain},
     definitions_table::DefinitionsTable,
     value::Value,
@@ -9,10 +10,15 @@ use motya_config::common_types::{
 use tokio::sync::Mutex;
 
 use crate::proxy::{
+    credentials::CredentialRegistry,
     filters::{
-        builtin::rate_limiter::RateLimitFilter,
+        builtin::{basic_auth::BasicAuthFilter, rate_limiter::RateLimitFilter},
+        condition::{RequestCondition, When},
+        metrics::{self, Measured, Timings},
         registry::{FilterInstance, FilterRegistry, RegistryFilterContainer},
-        types::{RequestFilterMod, RequestModifyMod, ResponseModifyMod},
+        types::{
+            RequestBodyMod, RequestFilterMod, RequestModifyMod, ResponseBodyMod, ResponseModifyMod,
+        },
     },
     plugins::module::{FilterType, WasmInvoker},
     rate_limiter::{instance::RateLimiterInstance, registry::StorageRegistry},
@@ -20,9 +26,87 @@ use crate::proxy::{
 
 #[derive(Default)]
 pub struct RuntimeChain {
+    /// The name of the chain in `definitions`, for `debug-trace`.
+    pub name: String,
     pub actions: Vec<Box<dyn RequestFilterMod>>,
+    /// The name of each of `actions`, in the same order.
+    pub action_names: Vec<String>,
     pub req_mods: Vec<Box<dyn RequestModifyMod>>,
     pub res_mods: Vec<Box<dyn ResponseModifyMod>>,
+    pub body_mods: Vec<Box<dyn RequestBodyMod>>,
+    pub res_body_mods: Vec<Box<dyn ResponseBodyMod>>,
+    /// Of the request filters as a whole, recorded by the proxy.
+    pub timings: Arc<Timings>,
+}
+
+impl RuntimeChain {
+    fn push_action(&mut self, name: String, filter: Box<dyn RequestFilterMod>) {
+        self.actions.push(filter);
+        self.action_names.push(name);
+    }
+
+    fn extend(&mut self, other: RuntimeChain) {
+        for (name, filter) in other.action_names.into_iter().zip(other.actions) {
+            self.push_action(name, filter);
+        }
+        self.req_mods.extend(other.req_mods);
+        self.res_mods.extend(other.res_mods);
+        self.body_mods.extend(other.body_mods);
+        self.res_body_mods.extend(other.res_body_mods);
+    }
+
+    /// Wraps every filter to record into `timings`.
+    fn measured(self, timings: Arc<Timings>) -> RuntimeChain {
+        let mut measured = RuntimeChain::default();
+        for (name, filter) in self.action_names.into_iter().zip(self.actions) {
+            measured.push_action(name, Box::new(Measured::new(timings.clone(), filter)));
+        }
+        for filter in self.req_mods {
+            measured
+                .req_mods
+                .push(Box::new(Measured::new(timings.clone(), filter)));
+        }
+        for filter in self.res_mods {
+            measured
+                .res_mods
+                .push(Box::new(Measured::new(timings.clone(), filter)));
+        }
+        for filter in self.body_mods {
+            measured
+                .body_mods
+                .push(Box::new(Measured::new(timings.clone(), filter)));
+        }
+        for filter in self.res_body_mods {
+            measured
+                .res_body_mods
+                .push(Box::new(Measured::new(timings.clone(), filter)));
+        }
+        measured
+    }
+
+    /// Appends the filters of `guarded`, to run only for requests that match
+    /// `condition`.
+    fn extend_when(&mut self, guarded: RuntimeChain, condition: Arc<RequestCondition>) {
+        for (name, filter) in guarded.action_names.into_iter().zip(guarded.actions) {
+            self.push_action(name, Box::new(When::new(condition.clone(), filter)));
+        }
+        for filter in guarded.req_mods {
+            self.req_mods
+                .push(Box::new(When::new(condition.clone(), filter)));
+        }
+        for filter in guarded.res_mods {
+            self.res_mods
+                .push(Box::new(When::new(condition.clone(), filter)));
+        }
+        for filter in guarded.body_mods {
+            self.body_mods
+                .push(Box::new(When::new(condition.clone(), filter)));
+        }
+        for filter in guarded.res_body_mods {
+            self.res_body_mods
+                .push(Box::new(When::new(condition.clone(), filter)));
+        }
+    }
 }
 
 #[derive(Clone, Default)]
@@ -30,6 +114,7 @@ pub struct ChainResolver {
     table: DefinitionsTable,
     filter_registry: Arc<Mutex<FilterRegistry>>,
     storage_registry: Arc<StorageRegistry>,
+    credentials: Arc<CredentialRegistry>,
 }
 
 impl ChainResolver {
@@ -42,8 +127,25 @@ impl ChainResolver {
 
         for (chain_name, chain) in table.get_chains() {
             for item in &chain.items {
+                let item = match item {
+                    ChainItem::When { item, .. } => item.as_ref(),
+                    item => item,
+                };
                 match item {
+                    ChainItem::When { .. } => {
+                        return Err(miette!(
+                            "Chain '{}' has a 'when' within a 'when'",
+                            chain_name
+                        ));
+                    }
                     ChainItem::Filter(filter) => {
+                        if !registry_.contains(&filter.name) && is_lua_filter(&filter.name) {
+                            return Err(miette!(
+                                "Chain '{}' uses '{}', but Motya was built without the 'lua' feature",
+                                chain_name,
+                                filter.name
+                            ));
+                        }
                         if !registry_.contains(&filter.name) {
                             return Err(miette!(
                                 "Chain '{}' references unknown filter '{}'. Did you forget to load a plugin?",
@@ -52,7 +154,7 @@ impl ChainResolver {
                             ));
                         }
                     }
-                    ChainItem::RateLimiter(_) => {}
+                    ChainItem::RateLimiter(_) | ChainItem::BasicAuth(_) => {}
                 }
             }
         }
@@ -72,97 +174,175 @@ impl ChainResolver {
             table,
             filter_registry: registry,
             storage_registry,
+            credentials: Arc::default(),
         })
     }
 
-    pub async fn resolve(&self, chain_name: &str) -> Result<RuntimeChain> {
+    /// Builds the filters of `chain_name` for a route of `service`.
+    pub async fn resolve(&self, chain_name: &str, service: &str) -> Result<RuntimeChain> {
         let chain_cfg = self
             .table
             .get_chains()
             .get(chain_name)
             .ok_or_else(|| miette!("Chain '{}' not found in definitions table", chain_name))?;
 
-        self.build_chain(chain_cfg, chain_name).await
+        self.build_chain(chain_cfg, chain_name, service).await
     }
 
-    async fn build_chain(&self, chain: &FilterChain, context_name: &str) -> Result<RuntimeChain> {
-        let mut runtime_chain = RuntimeChain::default();
+    async fn build_chain(
+        &self,
+        chain: &FilterChain,
+        context_name: &str,
+        service: &str,
+    ) -> Result<RuntimeChain> {
+        let mut runtime_chain = RuntimeChain {
+            name: context_name.to_string(),
+            timings: metrics::chain(context_name),
+            ..RuntimeChain::default()
+        };
 
         for item in &chain.items {
-            match item {
-                ChainItem::Filter(filter_cfg) => {
-                    let settings: BTreeMap<String, Value> = filter_cfg
-                        .args
-                        .iter()
-                        .map(|(k, v)| (k.clone(), v.clone()))
-                        .collect();
-
-                    let registry = self.filter_registry.lock().await;
-                    let container = registry
-                        .build(&filter_cfg.name, settings.clone())
-                        .into_diagnostic()
-                        .wrap_err_with(|| {
-                            format!(
-                                "Failed to build filter '{}' in chain '{}'",
-                                filter_cfg.name, context_name
-                            )
-                        })?;
-
-                    match container {
-                        RegistryFilterContainer::Builtin(builtin) => match builtin {
-                            FilterInstance::Action(f) => runtime_chain.actions.push(f),
-                            FilterInstance::Request(f) => runtime_chain.req_mods.push(f),
-                            FilterInstance::Response(f) => runtime_chain.res_mods.push(f),
-                        },
-                        RegistryFilterContainer::Plugin(plugin) => {
-                            let (_plugin_name, filter_name) = filter_cfg
-                                .name
-                                .as_c_str()
-                                .to_str()
-                                .expect("invariant violated: not a valid UTF-8")
-                                .split_once('.')
-                                .ok_or_else(|| {
-                                    miette!(
-                                        "Invalid filter format: '{}'. Expected '<plugin-name>.<filter-name>'",
-                                        filter_cfg.name
-                                    )
-                                })?;
-
-                            let invoker =
-                                WasmInvoker::new(plugin, filter_name.to_string(), settings);
-
-                            match invoker.get_filter_type()? {
-                                FilterType::Filter => runtime_chain.actions.push(Box::new(invoker)),
-                                FilterType::OnRequest => {
-                                    runtime_chain.req_mods.push(Box::new(invoker))
-                                }
-                                FilterType::OnResponse => {
-                                    runtime_chain.res_mods.push(Box::new(invoker))
-                                }
-                            };
-                        }
-                    }
+            let (condition, item) = match item {
+                ChainItem::When { condition, item } => (Some(condition), item.as_ref()),
+                item => (None, item),
+            };
+
+            let mut built = RuntimeChain::default();
+            self.build_item(item, &mut built, context_name, service)
+                .await?;
+            // Inside the `when`, so that skipped runs don't count.
+            let built = built.measured(metrics::filter(context_name, &item_name(item)));
+
+            match condition {
+                Some(condition) => {
+                    runtime_chain.extend_when(built, Arc::new(RequestCondition::new(condition)))
                 }
-                ChainItem::RateLimiter(policy) => {
-                    let storage_arc =
-                        self.storage_registry
-                            .get(&policy.storage_key)
+                None => runtime_chain.extend(built),
+            }
+        }
+
+        Ok(runtime_chain)
+    }
+
+    async fn build_item(
+        &self,
+        item: &ChainItem,
+        runtime_chain: &mut RuntimeChain,
+        context_name: &str,
+        service: &str,
+    ) -> Result<()> {
+        match item {
+            ChainItem::Filter(filter_cfg) => {
+                let settings: BTreeMap<String, Value> = filter_cfg
+                    .args
+                    .iter()
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect();
+
+                let registry = self.filter_registry.lock().await;
+                let container = registry
+                    .build(&filter_cfg.name, settings.clone())
+                    .into_diagnostic()
+                    .wrap_err_with(|| {
+                        format!(
+                            "Failed to build filter '{}' in chain '{}'",
+                            filter_cfg.name, context_name
+                        )
+                    })?;
+
+                match container {
+                    RegistryFilterContainer::Builtin(builtin) => match builtin {
+                        FilterInstance::Action(f) => runtime_chain.push_action(item_name(item), f),
+                        FilterInstance::Request(f) => runtime_chain.req_mods.push(f),
+                        FilterInstance::Response(f) => runtime_chain.res_mods.push(f),
+                        FilterInstance::RequestBody(f) => runtime_chain.body_mods.push(f),
+                        FilterInstance::ResponseBody(f) => runtime_chain.res_body_mods.push(f),
+                        FilterInstance::ActionAndResponse(action, response) => {
+                            runtime_chain.push_action(item_name(item), action);
+                            runtime_chain.res_mods.push(response);
+                        }
+                    },
+                    RegistryFilterContainer::Plugin(plugin) => {
+                        let (_plugin_name, filter_name) = filter_cfg
+                            .name
+                            .as_c_str()
+                            .to_str()
+                            .expect("invariant violated: not a valid UTF-8")
+                            .split_once('.')
                             .ok_or_else(|| {
                                 miette!(
-                            "Storage '{}' not found for rate limit policy '{}' in chain '{}'", 
-                            policy.storage_key, policy.name, context_name
-                        )
+                                    "Invalid filter format: '{}'. Expected '<plugin-name>.<filter-name>'",
+                                    filter_cfg.name
+                                )
                             })?;
 
-                    let instance = RateLimiterInstance::new(policy.clone(), storage_arc);
+                        let invoker = WasmInvoker::new(plugin, filter_name.to_string(), settings);
+                        let filter_type = invoker.get_filter_type()?;
 
-                    let filter = Box::new(RateLimitFilter::new(instance));
+                        if invoker.request_body_limit().is_some() {
+                            // Such plugins run `on-request` once the body is in.
+                            if filter_type != FilterType::OnRequest {
+                                return Err(miette!(
+                                    "Filter '{}' in chain '{}' is not a request filter, but its plugin sets 'request-body'",
+                                    filter_cfg.name,
+                                    context_name
+                                ));
+                            }
+                            runtime_chain.body_mods.push(Box::new(invoker));
+                            return Ok(());
+                        }
 
-                    runtime_chain.actions.push(filter);
+                        match filter_type {
+                            FilterType::Filter => {
+                                runtime_chain.push_action(item_name(item), Box::new(invoker))
+                            }
+                            FilterType::OnRequest => runtime_chain.req_mods.push(Box::new(invoker)),
+                            FilterType::OnResponse => {
+                                runtime_chain.res_mods.push(Box::new(invoker))
+                            }
+                        };
+                    }
                 }
             }
+            ChainItem::RateLimiter(policy) => {
+                let storage_arc = self
+                    .storage_registry
+                    .for_policy(policy)
+                    .wrap_err_with(|| format!("in chain '{context_name}'"))?;
+
+                let instance = RateLimiterInstance::new(policy.clone(), storage_arc, service);
+
+                let filter = Box::new(RateLimitFilter::new(instance));
+
+                runtime_chain.push_action(item_name(item), filter);
+            }
+            ChainItem::BasicAuth(auth) => {
+                let store = self
+                    .credentials
+                    .get_or_load(&auth.credentials)
+                    .wrap_err_with(|| format!("in chain '{context_name}'"))?;
+
+                let filter = BasicAuthFilter::new(auth.realm.clone(), store);
+
+                runtime_chain.push_action(item_name(item), Box::new(filter));
+            }
+            ChainItem::When { .. } => {
+                return Err(miette!(
+                    "Chain '{context_name}' has a 'when' within a 'when'"
+                ));
+            }
         }
 
-        Ok(runtime_chain)
+        Ok(())
+    }
+}
+
+/// What `item` is called in `debug-trace` and the metrics.
+fn item_name(item: &ChainItem) -> String {
+    match item {
+        ChainItem::Filter(filter) => filter.name.to_string(),
+        ChainItem::RateLimiter(policy) => format!("rate-limit {}", policy.name),
+        ChainItem::BasicAuth(_) => "basic-auth".to_string(),
+        ChainItem::When { item, .. } => item_name(item),
     }
 }
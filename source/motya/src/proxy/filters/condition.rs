@@ -0,0 +1,213 @@
+//! Chain items that only run for some requests (`when=` on an item).
+//!
+//! The condition renders its template from the request as a key selector does,
+//! so a missing header, cookie or query parameter renders as nothing, and
+//! compares the result. Each filter built for the item is wrapped in [`When`],
+//! which checks the condition in every phase the filter runs in before handing
+//! it the request. Every phase checks the request of the client, not the one
+//! sent upstream.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use http::uri::PathAndQuery;
+use motya_config::common_types::condition::{Condition, ConditionTest};
+use pingora::Result;
+use pingora_http::{RequestHeader, ResponseHeader};
+use pingora_proxy::Session;
+use smallvec::SmallVec;
+
+use crate::proxy::{
+    context::SessionInfo,
+    filters::types::{
+        RequestBodyMod, RequestFilterMod, RequestModifyMod, ResponseBodyMod, ResponseModifyMod,
+    },
+    key_selector::{KeySelector, KeySourceContext},
+    MotyaContext,
+};
+
+pub struct RequestCondition {
+    selector: KeySelector,
+    test: ConditionTest,
+}
+
+impl RequestCondition {
+    pub fn new(condition: &Condition) -> Self {
+        Self {
+            selector: KeySelector {
+                extraction_strategies: vec![condition.template.clone()],
+                transforms: vec![],
+            },
+            test: condition.test.clone(),
+        }
+    }
+
+    pub fn matches<C: KeySourceContext>(&self, ctx: &C) -> bool {
+        let mut buffer: SmallVec<[u8; 256]> = SmallVec::new();
+        let present = self.selector.select(ctx, &mut buffer);
+
+        match &self.test {
+            ConditionTest::Present => present,
+            ConditionTest::Absent => !present,
+            ConditionTest::Equals(value) => buffer.as_slice() == value.as_bytes(),
+            ConditionTest::NotEquals(value) => buffer.as_slice() != value.as_bytes(),
+        }
+    }
+
+    fn matches_session(&self, session: &Session) -> bool {
+        static DEFAULT: PathAndQuery = PathAndQuery::from_static("/");
+
+        self.matches(&SessionInfo {
+            headers: session.req_header(),
+            client_addr: session.client_addr(),
+            path: session
+                .req_header()
+                .uri
+                .path_and_query()
+                .unwrap_or(&DEFAULT),
+        })
+    }
+}
+
+/// A filter of a chain item with a `when`.
+pub struct When<F: ?Sized> {
+    condition: Arc<RequestCondition>,
+    inner: Box<F>,
+}
+
+impl<F: ?Sized> When<F> {
+    pub fn new(condition: Arc<RequestCondition>, inner: Box<F>) -> Self {
+        Self { condition, inner }
+    }
+}
+
+#[async_trait]
+impl RequestFilterMod for When<dyn RequestFilterMod> {
+    async fn request_filter(&self, session: &mut Session, ctx: &mut MotyaContext) -> Result<bool> {
+        if !self.condition.matches_session(session) {
+            return Ok(false);
+        }
+        self.inner.request_filter(session, ctx).await
+    }
+}
+
+#[async_trait]
+impl RequestModifyMod for When<dyn RequestModifyMod> {
+    async fn upstream_request_filter(
+        &self,
+        session: &mut Session,
+        header: &mut RequestHeader,
+        ctx: &mut MotyaContext,
+    ) -> Result<()> {
+        if !self.condition.matches_session(session) {
+            return Ok(());
+        }
+        self.inner
+            .upstream_request_filter(session, header, ctx)
+            .await
+    }
+}
+
+impl ResponseModifyMod for When<dyn ResponseModifyMod> {
+    fn upstream_response_filter(
+        &self,
+        session: &mut Session,
+        header: &mut ResponseHeader,
+        ctx: &mut MotyaContext,
+    ) {
+        if self.condition.matches_session(session) {
+            self.inner.upstream_response_filter(session, header, ctx);
+        }
+    }
+}
+
+impl RequestBodyMod for When<dyn RequestBodyMod> {
+    fn max_body_size(&self) -> usize {
+        self.inner.max_body_size()
+    }
+
+    fn request_body_filter(
+        &self,
+        session: &mut Session,
+        body: &mut Bytes,
+        ctx: &mut MotyaContext,
+    ) -> Result<()> {
+        if !self.condition.matches_session(session) {
+            return Ok(());
+        }
+        self.inner.request_body_filter(session, body, ctx)
+    }
+}
+
+impl ResponseBodyMod for When<dyn ResponseBodyMod> {
+    fn max_body_size(&self) -> usize {
+        self.inner.max_body_size()
+    }
+
+    fn applies_to(&self, content_type: Option<&str>) -> bool {
+        self.inner.applies_to(content_type)
+    }
+
+    fn decodes_upstream(&self) -> bool {
+        self.inner.decodes_upstream()
+    }
+
+    fn response_body_filter(
+        &self,
+        session: &mut Session,
+        body: &mut Bytes,
+        ctx: &mut MotyaContext,
+    ) -> Result<()> {
+        if !self.condition.matches_session(session) {
+            return Ok(());
+        }
+        self.inner.response_body_filter(session, body, ctx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn condition(source: &str) -> RequestCondition {
+        RequestCondition::new(&source.parse().unwrap())
+    }
+
+    fn request(path: &str, headers: &[(&'static str, &str)]) -> RequestHeader {
+        let mut request = RequestHeader::build("GET", path.as_bytes(), None).unwrap();
+        for (name, value) in headers {
+            request.insert_header(*name, *value).unwrap();
+        }
+        request
+    }
+
+    fn info(request: &RequestHeader) -> SessionInfo<'_> {
+        SessionInfo {
+            headers: request,
+            client_addr: None,
+            path: request.uri.path_and_query().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_conditions() {
+        let debug = request("/api?v=2", &[("x-debug", "1")]);
+        let plain = request("/api", &[]);
+
+        let is_debug = condition("${header-x-debug} == '1'");
+        assert!(is_debug.matches(&info(&debug)));
+        assert!(!is_debug.matches(&info(&plain)));
+
+        let not_debug = condition("${header-x-debug} != '1'");
+        assert!(!not_debug.matches(&info(&debug)));
+        assert!(not_debug.matches(&info(&plain)));
+
+        assert!(condition("${header-x-debug}").matches(&info(&debug)));
+        assert!(!condition("${header-x-debug}").matches(&info(&plain)));
+        assert!(condition("!${query?v}").matches(&info(&plain)));
+        assert!(!condition("!${query?v}").matches(&info(&debug)));
+
+        assert!(condition("${method} ${uri-path} == 'GET /api'").matches(&info(&plain)));
+    }
+}
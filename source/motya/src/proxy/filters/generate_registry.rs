@@ -1,17 +1,18 @@
+use std::sync::Arc;
+
 use motya_config::{common_types::definitions_table::DefinitionsTable, define_builtin_filters};
 
 use crate::proxy::filters::{
     builtin::{
+        cidr_access::{CidrAllowFilter, CidrDenyFilter},
         cidr_range::CidrRangeFilter,
-        request::{
-            remove_headers::RemoveHeaderKeyRegex as RequestRemoveHeaderKeyRegex,
-            rewrite_path::RewritePathRegex, strip_prefix::StripPrefix,
-            upsert_headers::UpsertHeader as RequestUpsertHeader,
-        },
-        response::{
-            remove_header::RemoveHeaderKeyRegex as ResponseRemoveHeaderKeyRegex,
-            upsert_header::UpsertHeader as ResponseUpsertHeader,
-        },
+        cors::CorsFilter,
+        delay::DelayFilter,
+        ext_auth::ExtAuthFilter,
+        headers::{RemoveHeader, RemoveHeaderRegex, RenameHeader, SetHeader},
+        request::{rewrite_path::RewritePathRegex, strip_prefix::StripPrefix},
+        rewrite_body::RewriteBody,
+        throttle::ThrottleFilter,
     },
     registry::{FilterInstance, FilterRegistry, RegistryFilterContainer},
 };
@@ -23,6 +24,12 @@ macro_rules! impl_registry_loader {
         requests: { $($req_key:literal => $req_type:ty),* $(,)? }
 
         responses: { $($res_key:literal => $res_type:ty),* $(,)? }
+
+        request_bodies: { $($req_body_key:literal => $req_body_type:ty),* $(,)? }
+
+        response_bodies: { $($res_body_key:literal => $res_body_type:ty),* $(,)? }
+
+        actions_with_responses: { $($both_key:literal => $both_type:ty),* $(,)? }
     ) => {
         pub fn load_registry(definitions: &mut DefinitionsTable) -> FilterRegistry {
             let mut registry = FilterRegistry::new();
@@ -58,6 +65,42 @@ macro_rules! impl_registry_loader {
                 }));
             )*
 
+            $(
+                let key = fqdn::fqdn!($req_body_key);
+                definitions.insert_filter(key.clone());
+
+                registry.register_factory(key, Box::new(|settings| {
+                    let item = <$req_body_type>::from_settings(settings)?;
+                    Ok(RegistryFilterContainer::Builtin(FilterInstance::RequestBody(Box::new(item))))
+                }));
+            )*
+
+            $(
+                let key = fqdn::fqdn!($res_body_key);
+                definitions.insert_filter(key.clone());
+
+                registry.register_factory(key, Box::new(|settings| {
+                    let item = <$res_body_type>::from_settings(settings)?;
+                    Ok(RegistryFilterContainer::Builtin(FilterInstance::ResponseBody(Box::new(item))))
+                }));
+            )*
+
+            $(
+                let key = fqdn::fqdn!($both_key);
+                definitions.insert_filter(key.clone());
+
+                registry.register_factory(key, Box::new(|settings| {
+                    let item = Arc::new(<$both_type>::from_settings(settings)?);
+                    Ok(RegistryFilterContainer::Builtin(FilterInstance::ActionAndResponse(
+                        Box::new(item.clone()),
+                        Box::new(item),
+                    )))
+                }));
+            )*
+
+            #[cfg(feature = "lua")]
+            crate::proxy::filters::builtin::lua::register(&mut registry, definitions);
+
             registry
         }
     };
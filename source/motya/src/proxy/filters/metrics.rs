@@ -0,0 +1,298 @@
+//! Timings of chains and of the filters in them.
+//!
+//! Every filter built for a chain is wrapped in [`Measured`], which counts its
+//! runs by outcome and adds up the time they took, in every phase the filter
+//! runs in. A filter that answers the request itself denies it, and one that
+//! fails errors, any other run passes. The request filters of a chain are
+//! counted as a whole as well, by the proxy, with the outcome of the chain.
+//!
+//! As with the upstream metrics, the counters are shared through process-wide
+//! tables keyed by the names of the chain and the filter, so they survive config
+//! reloads. Filters with the same name in one chain share theirs. [`render`]
+//! writes them next to the other metrics.
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use pingora::Result;
+use pingora_http::{RequestHeader, ResponseHeader};
+use pingora_proxy::Session;
+
+use crate::proxy::{
+    filters::types::{
+        RequestBodyMod, RequestFilterMod, RequestModifyMod, ResponseBodyMod, ResponseModifyMod,
+    },
+    metrics::escape_label,
+    MotyaContext,
+};
+
+const OUTCOMES: [&str; 3] = ["pass", "deny", "error"];
+
+static CHAINS: Mutex<BTreeMap<String, Arc<Timings>>> = Mutex::new(BTreeMap::new());
+static FILTERS: Mutex<BTreeMap<(String, String), Arc<Timings>>> = Mutex::new(BTreeMap::new());
+
+#[derive(Debug, Clone, Copy)]
+pub enum Outcome {
+    Pass,
+    Deny,
+    Error,
+}
+
+impl Outcome {
+    /// The outcome of a request filter, which returns whether it responded.
+    pub fn of_request_filter(result: &Result<bool>) -> Self {
+        match result {
+            Ok(false) => Self::Pass,
+            Ok(true) => Self::Deny,
+            Err(_) => Self::Error,
+        }
+    }
+
+    fn of<T>(result: &Result<T>) -> Self {
+        match result {
+            Ok(_) => Self::Pass,
+            Err(_) => Self::Error,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Timings {
+    runs: [AtomicU64; OUTCOMES.len()],
+    micros: AtomicU64,
+}
+
+impl Timings {
+    pub fn record(&self, outcome: Outcome, elapsed: Duration) {
+        self.runs[outcome as usize].fetch_add(1, Ordering::Relaxed);
+        self.micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+}
+
+/// The timings of the request filters of `chain`, created on first use.
+pub fn chain(chain: &str) -> Arc<Timings> {
+    CHAINS
+        .lock()
+        .expect("chain metrics lock poisoned")
+        .entry(chain.to_string())
+        .or_default()
+        .clone()
+}
+
+/// The timings of `filter` in `chain`, created on first use.
+pub fn filter(chain: &str, filter: &str) -> Arc<Timings> {
+    FILTERS
+        .lock()
+        .expect("chain metrics lock poisoned")
+        .entry((chain.to_string(), filter.to_string()))
+        .or_default()
+        .clone()
+}
+
+/// A filter of a chain, timed.
+pub struct Measured<F: ?Sized> {
+    timings: Arc<Timings>,
+    inner: Box<F>,
+}
+
+impl<F: ?Sized> Measured<F> {
+    pub fn new(timings: Arc<Timings>, inner: Box<F>) -> Self {
+        Self { timings, inner }
+    }
+}
+
+#[async_trait]
+impl RequestFilterMod for Measured<dyn RequestFilterMod> {
+    async fn request_filter(&self, session: &mut Session, ctx: &mut MotyaContext) -> Result<bool> {
+        let start = Instant::now();
+        let result = self.inner.request_filter(session, ctx).await;
+        self.timings
+            .record(Outcome::of_request_filter(&result), start.elapsed());
+        result
+    }
+}
+
+#[async_trait]
+impl RequestModifyMod for Measured<dyn RequestModifyMod> {
+    async fn upstream_request_filter(
+        &self,
+        session: &mut Session,
+        header: &mut RequestHeader,
+        ctx: &mut MotyaContext,
+    ) -> Result<()> {
+        let start = Instant::now();
+        let result = self
+            .inner
+            .upstream_request_filter(session, header, ctx)
+            .await;
+        self.timings.record(Outcome::of(&result), start.elapsed());
+        result
+    }
+}
+
+impl ResponseModifyMod for Measured<dyn ResponseModifyMod> {
+    fn upstream_response_filter(
+        &self,
+        session: &mut Session,
+        header: &mut ResponseHeader,
+        ctx: &mut MotyaContext,
+    ) {
+        let start = Instant::now();
+        self.inner.upstream_response_filter(session, header, ctx);
+        self.timings.record(Outcome::Pass, start.elapsed());
+    }
+}
+
+impl RequestBodyMod for Measured<dyn RequestBodyMod> {
+    fn max_body_size(&self) -> usize {
+        self.inner.max_body_size()
+    }
+
+    fn request_body_filter(
+        &self,
+        session: &mut Session,
+        body: &mut Bytes,
+        ctx: &mut MotyaContext,
+    ) -> Result<()> {
+        let start = Instant::now();
+        let result = self.inner.request_body_filter(session, body, ctx);
+        self.timings.record(Outcome::of(&result), start.elapsed());
+        result
+    }
+}
+
+impl ResponseBodyMod for Measured<dyn ResponseBodyMod> {
+    fn max_body_size(&self) -> usize {
+        self.inner.max_body_size()
+    }
+
+    fn applies_to(&self, content_type: Option<&str>) -> bool {
+        self.inner.applies_to(content_type)
+    }
+
+    fn decodes_upstream(&self) -> bool {
+        self.inner.decodes_upstream()
+    }
+
+    fn response_body_filter(
+        &self,
+        session: &mut Session,
+        body: &mut Bytes,
+        ctx: &mut MotyaContext,
+    ) -> Result<()> {
+        let start = Instant::now();
+        let result = self.inner.response_body_filter(session, body, ctx);
+        self.timings.record(Outcome::of(&result), start.elapsed());
+        result
+    }
+}
+
+/// Writes the timings of every chain and filter in the Prometheus text format.
+pub fn render(out: &mut String) {
+    let chains = CHAINS.lock().expect("chain metrics lock poisoned").clone();
+    let filters = FILTERS.lock().expect("chain metrics lock poisoned").clone();
+
+    if !chains.is_empty() {
+        out.push_str(
+            "# HELP motya_chain_runs_total Runs of the request filters of a chain by outcome.\n",
+        );
+        out.push_str("# TYPE motya_chain_runs_total counter\n");
+        for (chain, timings) in &chains {
+            for (outcome, count) in OUTCOMES.iter().zip(&timings.runs) {
+                out.push_str(&format!(
+                    "motya_chain_runs_total{{chain=\"{}\",outcome=\"{outcome}\"}} {}\n",
+                    escape_label(chain),
+                    count.load(Ordering::Relaxed)
+                ));
+            }
+        }
+
+        out.push_str(
+            "# HELP motya_chain_seconds_total Time spent in the request filters of a chain.\n",
+        );
+        out.push_str("# TYPE motya_chain_seconds_total counter\n");
+        for (chain, timings) in &chains {
+            out.push_str(&format!(
+                "motya_chain_seconds_total{{chain=\"{}\"}} {}\n",
+                escape_label(chain),
+                seconds(&timings.micros)
+            ));
+        }
+    }
+
+    if !filters.is_empty() {
+        out.push_str("# HELP motya_filter_runs_total Runs of a filter of a chain by outcome.\n");
+        out.push_str("# TYPE motya_filter_runs_total counter\n");
+        for ((chain, filter), timings) in &filters {
+            for (outcome, count) in OUTCOMES.iter().zip(&timings.runs) {
+                out.push_str(&format!(
+                    "motya_filter_runs_total{{chain=\"{}\",filter=\"{}\",outcome=\"{outcome}\"}} {}\n",
+                    escape_label(chain),
+                    escape_label(filter),
+                    count.load(Ordering::Relaxed)
+                ));
+            }
+        }
+
+        out.push_str(
+            "# HELP motya_filter_seconds_total Time spent in a filter of a chain, in every phase.\n",
+        );
+        out.push_str("# TYPE motya_filter_seconds_total counter\n");
+        for ((chain, filter), timings) in &filters {
+            out.push_str(&format!(
+                "motya_filter_seconds_total{{chain=\"{}\",filter=\"{}\"}} {}\n",
+                escape_label(chain),
+                escape_label(filter),
+                seconds(&timings.micros)
+            ));
+        }
+    }
+}
+
+fn seconds(micros: &AtomicU64) -> f64 {
+    micros.load(Ordering::Relaxed) as f64 / 1_000_000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timings() {
+        let timings = filter("test-chain", "motya.request.upsert-header");
+        assert!(Arc::ptr_eq(
+            &timings,
+            &filter("test-chain", "motya.request.upsert-header")
+        ));
+
+        timings.record(Outcome::Pass, Duration::from_millis(2));
+        timings.record(Outcome::Deny, Duration::from_micros(500));
+        chain("test-chain").record(Outcome::Error, Duration::from_millis(1));
+
+        let mut out = String::new();
+        render(&mut out);
+
+        let line = |outcome: &str, count: u64| {
+            format!(
+                "motya_filter_runs_total{{chain=\"test-chain\",filter=\"motya.request.upsert-header\",outcome=\"{outcome}\"}} {count}\n"
+            )
+        };
+        assert!(out.contains(&line("pass", 1)));
+        assert!(out.contains(&line("deny", 1)));
+        assert!(out.contains(&line("error", 0)));
+        assert!(out.contains(
+            "motya_filter_seconds_total{chain=\"test-chain\",filter=\"motya.request.upsert-header\"} 0.0025\n"
+        ));
+        assert!(out.contains("motya_chain_runs_total{chain=\"test-chain\",outcome=\"error\"} 1\n"));
+        assert!(out.contains("motya_chain_seconds_total{chain=\"test-chain\"} 0.001\n"));
+    }
+}
@@ -1,5 +1,7 @@
 pub mod builtin;
 pub mod chain_resolver;
+pub mod condition;
 pub mod generate_registry;
+pub mod metrics;
 pub mod registry;
 pub mod types;
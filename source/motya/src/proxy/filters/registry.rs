@@ -5,13 +5,19 @@ use motya_config::common_types::value::Value;
 use pingora::{Error, ErrorType, Result};
 
 use crate::proxy::{
-    plugins::module::WasmModule, RequestFilterMod, RequestModifyMod, ResponseModifyMod,
+    filters::types::{RequestBodyMod, ResponseBodyMod},
+    plugins::module::WasmModule,
+    RequestFilterMod, RequestModifyMod, ResponseModifyMod,
 };
 
 pub enum FilterInstance {
     Action(Box<dyn RequestFilterMod>),
     Request(Box<dyn RequestModifyMod>),
     Response(Box<dyn ResponseModifyMod>),
+    RequestBody(Box<dyn RequestBodyMod>),
+    ResponseBody(Box<dyn ResponseBodyMod>),
+    /// One filter seeing both the request and the response, such as `cors`.
+    ActionAndResponse(Box<dyn RequestFilterMod>, Box<dyn ResponseModifyMod>),
 }
 
 pub enum RegistryFilterContainer {
@@ -219,7 +225,7 @@ mod tests {
         .unwrap();
 
         let chain = resolver
-            .resolve("main_pipeline")
+            .resolve("main_pipeline", "test")
             .await
             .expect("Chain not found");
 
@@ -261,6 +267,39 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_compile_fail_nested_when() {
+        let mut table = DefinitionsTable::default();
+        table.insert_filter(FQDN::from_str("motya.sec.block").unwrap());
+
+        let block = ChainItem::Filter(ConfiguredFilter {
+            name: FQDN::from_str("motya.sec.block").unwrap(),
+            args: BTreeMap::new(),
+        });
+        table.insert_chain(
+            "guarded",
+            FilterChain {
+                items: vec![block
+                    .when("${cookie-session}".parse().ok())
+                    .when("${header-x-debug} == '1'".parse().ok())],
+            },
+        );
+
+        let res = ChainResolver::new(
+            table,
+            Arc::new(setup_registry().into()),
+            Arc::new(StorageRegistry::default()),
+        )
+        .await;
+
+        let err_msg = res.err().unwrap().to_string();
+        assert!(
+            err_msg.contains("Chain 'guarded' has a 'when' within a 'when'"),
+            "Unexpected error message: {}",
+            err_msg
+        );
+    }
+
     #[tokio::test]
     async fn test_instantiation_failure() {
         let mut reg = FilterRegistry::new();
@@ -292,7 +331,7 @@ mod tests {
         )
         .await
         .unwrap();
-        let err = resolver.resolve("test").await.err().unwrap();
+        let err = resolver.resolve("test", "test").await.err().unwrap();
 
         assert!(err
             .to_string()
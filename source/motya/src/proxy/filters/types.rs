@@ -1,4 +1,7 @@
+use std::sync::Arc;
+
 use async_trait::async_trait;
+use bytes::Bytes;
 use pingora::Result;
 use pingora_http::{RequestHeader, ResponseHeader};
 use pingora_proxy::Session;
@@ -37,3 +40,62 @@ pub trait RequestFilterMod: Send + Sync {
     /// See [ProxyHttp::request_filter] for more details
     async fn request_filter(&self, session: &mut Session, ctx: &mut MotyaContext) -> Result<bool>;
 }
+
+/// Lets one filter serve several phases of a chain.
+#[async_trait]
+impl<T: RequestFilterMod + ?Sized> RequestFilterMod for Arc<T> {
+    async fn request_filter(&self, session: &mut Session, ctx: &mut MotyaContext) -> Result<bool> {
+        (**self).request_filter(session, ctx).await
+    }
+}
+
+impl<T: ResponseModifyMod + ?Sized> ResponseModifyMod for Arc<T> {
+    fn upstream_response_filter(
+        &self,
+        session: &mut Session,
+        header: &mut ResponseHeader,
+        ctx: &mut MotyaContext,
+    ) {
+        (**self).upstream_response_filter(session, header, ctx)
+    }
+}
+
+/// Modifiers that see the whole request body at once, from
+/// [ProxyHttp::request_body_filter]. The body is buffered for them.
+pub trait RequestBodyMod: Send + Sync {
+    /// Requests with larger bodies are rejected with a 413.
+    fn max_body_size(&self) -> usize;
+
+    /// Called once, with the complete body, which may be replaced.
+    fn request_body_filter(
+        &self,
+        session: &mut Session,
+        body: &mut Bytes,
+        ctx: &mut MotyaContext,
+    ) -> Result<()>;
+}
+
+/// Modifiers that see the whole response body at once, from
+/// [ProxyHttp::response_body_filter]. The body is buffered for them, unless it
+/// is larger than they take.
+pub trait ResponseBodyMod: Send + Sync {
+    /// Responses with larger bodies pass through unchanged.
+    fn max_body_size(&self) -> usize;
+
+    /// Whether the filter wants bodies of `content_type`.
+    fn applies_to(&self, content_type: Option<&str>) -> bool;
+
+    /// Whether the filter reads gzip and deflate bodies from the upstream,
+    /// decoded. Otherwise the upstream is asked for unencoded bodies.
+    fn decodes_upstream(&self) -> bool {
+        false
+    }
+
+    /// Called once, with the complete body, which may be replaced.
+    fn response_body_filter(
+        &self,
+        session: &mut Session,
+        body: &mut Bytes,
+        ctx: &mut MotyaContext,
+    ) -> Result<()>;
+}
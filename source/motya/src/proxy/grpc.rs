@@ -0,0 +1,89 @@
+//! gRPC routes (`grpc=#true` on a `proxy`).
+//!
+//! gRPC runs over HTTP/2 and ends every response with trailers that carry the
+//! call's status. pingora hands trailers from an HTTP/2 upstream to an HTTP/2
+//! client as they are, so a gRPC route mostly has to reach its upstream over
+//! HTTP/2 and keep anything that rewrites or holds back bodies out of the way.
+//! The status is read on the way through, for the access log and the metrics.
+
+use http::HeaderMap;
+use pingora::{prelude::HttpPeer, protocols::ALPN};
+
+pub const GRPC_STATUS: &str = "grpc-status";
+
+const UNKNOWN: u32 = 2;
+
+/// The names of the status codes, indexed by code.
+pub const STATUS_NAMES: [&str; 17] = [
+    "OK",
+    "CANCELLED",
+    "UNKNOWN",
+    "INVALID_ARGUMENT",
+    "DEADLINE_EXCEEDED",
+    "NOT_FOUND",
+    "ALREADY_EXISTS",
+    "PERMISSION_DENIED",
+    "RESOURCE_EXHAUSTED",
+    "FAILED_PRECONDITION",
+    "ABORTED",
+    "OUT_OF_RANGE",
+    "UNIMPLEMENTED",
+    "INTERNAL",
+    "UNAVAILABLE",
+    "DATA_LOSS",
+    "UNAUTHENTICATED",
+];
+
+/// Connects with HTTP/2 only. Without TLS that is cleartext HTTP/2 (h2c), since
+/// there is no ALPN to agree on it.
+pub fn configure_peer(peer: &mut HttpPeer) {
+    peer.options.alpn = ALPN::H2;
+}
+
+/// The `grpc-status` of a response, from its trailers or, for a response that
+/// failed before its first message, from its headers.
+pub fn status(headers: &HeaderMap) -> Option<u32> {
+    headers.get(GRPC_STATUS)?.to_str().ok()?.trim().parse().ok()
+}
+
+/// `code`, or `UNKNOWN` for codes the protocol does not define.
+pub fn known(code: u32) -> u32 {
+    if (code as usize) < STATUS_NAMES.len() {
+        code
+    } else {
+        UNKNOWN
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_configure_peer_speaks_h2_only() {
+        let mut peer = HttpPeer::new("127.0.0.1:50051", false, String::new());
+
+        configure_peer(&mut peer);
+
+        assert_eq!(peer.options.alpn, ALPN::H2);
+    }
+
+    #[test]
+    fn test_status() {
+        let mut trailers = HeaderMap::new();
+        assert_eq!(status(&trailers), None);
+
+        trailers.insert(GRPC_STATUS, "14".parse().unwrap());
+        assert_eq!(status(&trailers), Some(14));
+        assert_eq!(STATUS_NAMES[14], "UNAVAILABLE");
+
+        trailers.insert(GRPC_STATUS, "fine".parse().unwrap());
+        assert_eq!(status(&trailers), None);
+    }
+
+    #[test]
+    fn test_undefined_codes_are_unknown() {
+        assert_eq!(known(16), 16);
+        assert_eq!(STATUS_NAMES[known(99) as usize], "UNKNOWN");
+    }
+}
@@ -12,9 +12,14 @@ use smallvec::{Array, SmallVec};
 
 pub trait KeySourceContext {
     fn get_header(&self, name: &str) -> Option<&str>;
+    /// Every value of a header, in the order the client sent them.
+    fn get_header_values(&self, name: &str) -> impl Iterator<Item = &[u8]>;
     fn get_cookie(&self, name: &str) -> Option<Cookie<'_>>;
     fn get_ip(&self) -> Option<IpAddr>;
     fn get_path(&self) -> &PathAndQuery;
+    fn get_method(&self) -> &str;
+    /// The host the request is for, without the port.
+    fn get_host(&self) -> Option<&str>;
 }
 
 #[derive(Debug, Clone)]
@@ -40,9 +45,29 @@ impl KeySelector {
                     KeyPart::Literal(s) => {
                         buffer.extend_from_slice(s.as_bytes());
                     }
-                    KeyPart::Header(name) => {
-                        if let Some(val) = ctx.get_header(name) {
-                            buffer.extend_from_slice(val.as_bytes());
+                    KeyPart::Header {
+                        name,
+                        all: false,
+                        raw,
+                    } => {
+                        let first = ctx.get_header_values(name).next();
+                        if let Some(val) = first.filter(|val| *raw || is_visible_ascii(val)) {
+                            buffer.extend_from_slice(val);
+                        }
+                    }
+                    KeyPart::Header {
+                        name,
+                        all: true,
+                        raw,
+                    } => {
+                        let values = ctx
+                            .get_header_values(name)
+                            .filter(|val| *raw || is_visible_ascii(val));
+                        for (idx, val) in values.enumerate() {
+                            if idx > 0 {
+                                buffer.extend_from_slice(b", ");
+                            }
+                            buffer.extend_from_slice(val);
                         }
                     }
                     KeyPart::Cookie(name) => {
@@ -84,6 +109,14 @@ impl KeySelector {
                             buffer.extend_from_slice(val.as_bytes());
                         }
                     }
+                    KeyPart::Method => {
+                        buffer.extend_from_slice(ctx.get_method().as_bytes());
+                    }
+                    KeyPart::Host => {
+                        if let Some(val) = ctx.get_host() {
+                            buffer.extend_from_slice(val.as_bytes());
+                        }
+                    }
                 }
             }
 
@@ -103,6 +136,13 @@ impl KeySelector {
     }
 }
 
+/// Whether a header value reads as text, as `HeaderValue::to_str` requires.
+fn is_visible_ascii(bytes: &[u8]) -> bool {
+    bytes
+        .iter()
+        .all(|&b| b == b'\t' || (b' '..=b'~').contains(&b))
+}
+
 fn apply_transform<A: Array<Item = u8>>(op: &TransformOp, buf: &mut SmallVec<A>) {
     match op {
         TransformOp::Lowercase => {
@@ -158,10 +198,12 @@ mod execution_tests {
     // --- Mock Setup ---
 
     struct MockContext {
-        headers: HashMap<String, String>,
+        headers: HashMap<String, Vec<Vec<u8>>>,
         cookies: HashMap<String, Cookie<'static>>,
         ip: Option<IpAddr>,
         uri: PathAndQuery,
+        method: String,
+        host: Option<String>,
     }
 
     impl MockContext {
@@ -171,11 +213,26 @@ mod execution_tests {
                 cookies: HashMap::new(),
                 ip: Some(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))),
                 uri: PathAndQuery::from_static("/"),
+                method: "GET".to_string(),
+                host: None,
             }
         }
 
-        fn with_header(mut self, k: &str, v: &str) -> Self {
-            self.headers.insert(k.to_lowercase(), v.to_string());
+        fn with_header(self, k: &str, v: &str) -> Self {
+            self.with_raw_header(k, v.as_bytes())
+        }
+
+        fn with_raw_header(mut self, k: &str, v: &[u8]) -> Self {
+            self.headers
+                .entry(k.to_lowercase())
+                .or_default()
+                .push(v.to_vec());
+            self
+        }
+
+        fn with_request(mut self, method: &str, host: &str) -> Self {
+            self.method = method.to_string();
+            self.host = Some(host.to_string());
             self
         }
 
@@ -192,7 +249,15 @@ mod execution_tests {
 
     impl KeySourceContext for MockContext {
         fn get_header(&self, name: &str) -> Option<&str> {
-            self.headers.get(name).map(|s| s.as_str())
+            let first = self.headers.get(name)?.first()?;
+            std::str::from_utf8(first).ok()
+        }
+        fn get_header_values(&self, name: &str) -> impl Iterator<Item = &[u8]> {
+            self.headers
+                .get(name)
+                .into_iter()
+                .flatten()
+                .map(Vec::as_slice)
         }
         fn get_cookie(&self, name: &str) -> Option<Cookie<'_>> {
             self.cookies.get(name).cloned()
@@ -203,6 +268,12 @@ mod execution_tests {
         fn get_path(&self) -> &PathAndQuery {
             &self.uri
         }
+        fn get_method(&self) -> &str {
+            &self.method
+        }
+        fn get_host(&self) -> Option<&str> {
+            self.host.as_deref()
+        }
     }
 
     // --- Helpers ---
@@ -262,7 +333,14 @@ mod execution_tests {
 
     #[test]
     fn test_case_sensitivity_without_transform() {
-        let selector = build_manual_selector(vec![KeyPart::Header("x-id".to_string())], vec![]);
+        let selector = build_manual_selector(
+            vec![KeyPart::Header {
+                name: "x-id".to_string(),
+                all: false,
+                raw: false,
+            }],
+            vec![],
+        );
 
         let hasher = HashOp::XxHash64(0);
 
@@ -441,4 +519,61 @@ mod execution_tests {
         assert!(!found, "Should return false if key extraction failed");
         assert!(buf.is_empty());
     }
+
+    #[test]
+    fn test_repeated_headers() {
+        let ctx = MockContext::new()
+            .with_header("x-forwarded-for", "10.0.0.1")
+            .with_header("x-forwarded-for", "10.0.0.2");
+
+        let mut buf: SmallVec<[u8; 256]> = SmallVec::new();
+        let first = selector_from_config(make_config("${header-x-forwarded-for}", vec![]));
+        assert!(first.select(&ctx, &mut buf));
+        assert_eq!(String::from_utf8(buf.to_vec()).unwrap(), "10.0.0.1");
+
+        let all = selector_from_config(make_config("${header-x-forwarded-for:all}", vec![]));
+        assert!(all.select(&ctx, &mut buf));
+        assert_eq!(
+            String::from_utf8(buf.to_vec()).unwrap(),
+            "10.0.0.1, 10.0.0.2"
+        );
+    }
+
+    #[test]
+    fn test_raw_header_bytes() {
+        let token = [0x00, 0xff, b'k', 0x80];
+        let ctx = MockContext::new()
+            .with_raw_header("x-token", &token)
+            .with_header("x-token", "text");
+
+        // Without `:raw`, values that aren't text are left out.
+        let mut buf: SmallVec<[u8; 256]> = SmallVec::new();
+        let text = selector_from_config(make_config("${header-x-token}", vec![]));
+        assert!(!text.select(&ctx, &mut buf));
+        let text_all = selector_from_config(make_config("${header-x-token:all}", vec![]));
+        assert!(text_all.select(&ctx, &mut buf));
+        assert_eq!(buf.as_slice(), b"text");
+
+        let raw = selector_from_config(make_config("${header-x-token:raw}", vec![]));
+        assert!(raw.select(&ctx, &mut buf));
+        assert_eq!(buf.as_slice(), token);
+    }
+
+    #[test]
+    fn test_method_and_host() {
+        let selector = selector_from_config(make_config("${method} ${host}${uri-path}", vec![]));
+        let ctx = MockContext::new()
+            .with_request("POST", "shop.example.com")
+            .with_path(PathAndQuery::from_static("/cart?item=1"));
+
+        let mut buf: SmallVec<[u8; 256]> = SmallVec::new();
+        assert!(selector.select(&ctx, &mut buf));
+        assert_eq!(
+            String::from_utf8(buf.to_vec()).unwrap(),
+            "POST shop.example.com/cart"
+        );
+
+        let host = selector_from_config(make_config("${host}", vec![]));
+        assert!(!host.select(&MockContext::new(), &mut buf));
+    }
 }
@@ -0,0 +1,323 @@
+//! Concurrency caps of a proxy service (`limits` in the service).
+//!
+//! Every service with `limits` holds a [`ServiceLimits`] with two counters: the
+//! client connections it has open, taken by [`ConnectionLimit`] around its HTTP app,
+//! and the requests it is handling, taken in `request_filter`. Each is held by a
+//! [`Permit`] for as long as the connection or request lasts. Past a cap the
+//! connection or request is shed at once with `503` and `Retry-After`, so that the
+//! service stays responsive for the work it did accept.
+//!
+//! The counters live in a process-wide table keyed by the service name, which
+//! [`render`] serves next to the other metrics.
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use async_trait::async_trait;
+use motya_config::common_types::limits::LimitsConfig;
+use pingora::{
+    apps::ServerApp,
+    protocols::{Ssl, Stream, ALPN},
+    server::ShutdownWatch,
+};
+use pingora_http::ResponseHeader;
+use pingora_proxy::Session;
+use tokio::io::AsyncWriteExt;
+
+use crate::proxy::{error_pages, metrics::escape_label, MotyaContext};
+
+/// Seconds a shed client is asked to wait before trying again.
+pub const RETRY_AFTER_SECS: u64 = 1;
+
+/// Written to a connection over `max-connections`, which is closed right after.
+/// Asks for the same wait as [`RETRY_AFTER_SECS`].
+const SHED_CONNECTION: &[u8] = b"HTTP/1.1 503 Service Unavailable\r\n\
+    Retry-After: 1\r\n\
+    Content-Length: 0\r\n\
+    Connection: close\r\n\r\n";
+
+static SERVICES: Mutex<BTreeMap<String, Arc<ServiceLimits>>> = Mutex::new(BTreeMap::new());
+
+/// Stands for a missing cap.
+const UNCAPPED: usize = usize::MAX;
+
+#[derive(Debug)]
+pub struct ServiceLimits {
+    max_connections: AtomicUsize,
+    max_inflight: AtomicUsize,
+    connections: AtomicUsize,
+    inflight: AtomicUsize,
+    shed_connections: AtomicU64,
+    shed_requests: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Counter {
+    Connections,
+    Inflight,
+}
+
+/// Keeps a connection or a request counted until it is dropped.
+#[derive(Debug)]
+pub struct Permit {
+    limits: Arc<ServiceLimits>,
+    counter: Counter,
+}
+
+/// The limits of the service `name`, created on first use, with the caps of `config`.
+pub fn service(name: &str, config: &LimitsConfig) -> Arc<ServiceLimits> {
+    let limits = SERVICES
+        .lock()
+        .expect("service limits lock poisoned")
+        .entry(name.to_string())
+        .or_insert_with(|| Arc::new(ServiceLimits::new(config)))
+        .clone();
+
+    limits.set_caps(config);
+    limits
+}
+
+impl ServiceLimits {
+    fn new(config: &LimitsConfig) -> Self {
+        let limits = Self {
+            max_connections: AtomicUsize::new(UNCAPPED),
+            max_inflight: AtomicUsize::new(UNCAPPED),
+            connections: AtomicUsize::new(0),
+            inflight: AtomicUsize::new(0),
+            shed_connections: AtomicU64::new(0),
+            shed_requests: AtomicU64::new(0),
+        };
+        limits.set_caps(config);
+        limits
+    }
+
+    fn set_caps(&self, config: &LimitsConfig) {
+        let caps = [
+            (&self.max_connections, config.max_connections),
+            (&self.max_inflight, config.max_inflight),
+        ];
+        for (cap, max) in caps {
+            cap.store(max.unwrap_or(UNCAPPED), Ordering::Relaxed);
+        }
+    }
+
+    /// A permit for a new client connection, or `None` past `max-connections`.
+    pub fn try_connection(self: &Arc<Self>) -> Option<Permit> {
+        self.try_acquire(Counter::Connections)
+    }
+
+    /// A permit for a new request, or `None` past `max-inflight`.
+    pub fn try_request(self: &Arc<Self>) -> Option<Permit> {
+        self.try_acquire(Counter::Inflight)
+    }
+
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::Relaxed)
+    }
+
+    pub fn inflight(&self) -> usize {
+        self.inflight.load(Ordering::Relaxed)
+    }
+
+    fn try_acquire(self: &Arc<Self>, counter: Counter) -> Option<Permit> {
+        let (count, max, shed) = self.parts(counter);
+
+        let max = max.load(Ordering::Relaxed);
+        let taken = count.fetch_update(Ordering::AcqRel, Ordering::Relaxed, |current| {
+            (current < max).then_some(current + 1)
+        });
+
+        match taken {
+            Ok(_) => Some(Permit {
+                limits: self.clone(),
+                counter,
+            }),
+            Err(_) => {
+                shed.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    fn parts(&self, counter: Counter) -> (&AtomicUsize, &AtomicUsize, &AtomicU64) {
+        match counter {
+            Counter::Connections => (
+                &self.connections,
+                &self.max_connections,
+                &self.shed_connections,
+            ),
+            Counter::Inflight => (&self.inflight, &self.max_inflight, &self.shed_requests),
+        }
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let (count, _, _) = self.limits.parts(self.counter);
+        count.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Answers a request over `max-inflight`.
+pub async fn shed_request(session: &mut Session, ctx: &MotyaContext) -> pingora::Result<()> {
+    let mut header = ResponseHeader::build(503, Some(3))?;
+    header.insert_header("Retry-After", RETRY_AFTER_SECS.to_string())?;
+
+    error_pages::respond(session, ctx, header).await
+}
+
+/// Wraps the HTTP app of a service, counting its client connections against
+/// `max-connections`.
+pub struct ConnectionLimit<A> {
+    inner: Arc<A>,
+    limits: Option<Arc<ServiceLimits>>,
+}
+
+impl<A> ConnectionLimit<A> {
+    pub fn new(inner: A, limits: Option<Arc<ServiceLimits>>) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            limits,
+        }
+    }
+}
+
+#[async_trait]
+impl<A: ServerApp + Send + Sync + 'static> ServerApp for ConnectionLimit<A> {
+    async fn process_new(
+        self: &Arc<Self>,
+        mut stream: Stream,
+        shutdown: &ShutdownWatch,
+    ) -> Option<Stream> {
+        let Some(limits) = &self.limits else {
+            return self.inner.process_new(stream, shutdown).await;
+        };
+
+        let Some(_permit) = limits.try_connection() else {
+            // An HTTP/2 client expects a preface first, it is only closed on.
+            if !matches!(stream.selected_alpn_proto(), Some(ALPN::H2)) {
+                let _ = stream.write_all(SHED_CONNECTION).await;
+                let _ = stream.flush().await;
+            }
+            tracing::debug!("Shedding a connection past max-connections");
+            return None;
+        };
+
+        // Kept-alive connections are served here, so that the permit is held
+        // between their requests.
+        let mut reused = self.inner.process_new(stream, shutdown).await;
+        while let Some(stream) = reused {
+            reused = self.inner.process_new(stream, shutdown).await;
+        }
+        None
+    }
+
+    async fn cleanup(&self) {
+        self.inner.cleanup().await
+    }
+}
+
+/// Writes the counters of every service with `limits` in the Prometheus text format.
+pub fn render(out: &mut String) {
+    let services = SERVICES
+        .lock()
+        .expect("service limits lock poisoned")
+        .clone();
+
+    if services.is_empty() {
+        return;
+    }
+
+    out.push_str("# HELP motya_service_connections Client connections open on a service.\n");
+    out.push_str("# TYPE motya_service_connections gauge\n");
+    for (name, limits) in &services {
+        out.push_str(&format!(
+            "motya_service_connections{{service=\"{}\"}} {}\n",
+            escape_label(name),
+            limits.connections()
+        ));
+    }
+
+    out.push_str("# HELP motya_service_inflight Requests being handled by a service.\n");
+    out.push_str("# TYPE motya_service_inflight gauge\n");
+    for (name, limits) in &services {
+        out.push_str(&format!(
+            "motya_service_inflight{{service=\"{}\"}} {}\n",
+            escape_label(name),
+            limits.inflight()
+        ));
+    }
+
+    out.push_str(
+        "# HELP motya_service_shed_total Connections and requests refused past the limits of a service.\n",
+    );
+    out.push_str("# TYPE motya_service_shed_total counter\n");
+    for (name, limits) in &services {
+        for (kind, count) in [
+            ("connection", &limits.shed_connections),
+            ("request", &limits.shed_requests),
+        ] {
+            out.push_str(&format!(
+                "motya_service_shed_total{{service=\"{}\",kind=\"{kind}\"}} {}\n",
+                escape_label(name),
+                count.load(Ordering::Relaxed)
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(max_connections: Option<usize>, max_inflight: Option<usize>) -> LimitsConfig {
+        LimitsConfig {
+            max_connections,
+            max_inflight,
+        }
+    }
+
+    #[test]
+    fn test_permits_are_capped() {
+        let limits = Arc::new(ServiceLimits::new(&limits(Some(2), None)));
+
+        let first = limits.try_connection().unwrap();
+        let _second = limits.try_connection().unwrap();
+        assert!(limits.try_connection().is_none());
+        assert_eq!(limits.connections(), 2);
+
+        drop(first);
+        assert_eq!(limits.connections(), 1);
+        assert!(limits.try_connection().is_some());
+
+        // Without a cap the requests are still counted.
+        let requests: Vec<_> = (0..10).map(|_| limits.try_request().unwrap()).collect();
+        assert_eq!(limits.inflight(), 10);
+        drop(requests);
+        assert_eq!(limits.inflight(), 0);
+
+        assert_eq!(limits.shed_connections.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_service_table() {
+        let first = service("test-table", &limits(None, Some(5)));
+        let _request = first.try_request().unwrap();
+
+        let again = service("test-table", &limits(None, Some(1)));
+        assert_eq!(again.inflight(), 1);
+        assert!(again.try_request().is_none());
+
+        let mut out = String::new();
+        render(&mut out);
+        assert!(out.contains("motya_service_inflight{service=\"test-table\"} 1\n"));
+        assert!(
+            out.contains("motya_service_shed_total{service=\"test-table\",kind=\"request\"} 1\n")
+        );
+    }
+}
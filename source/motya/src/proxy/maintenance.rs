@@ -0,0 +1,186 @@
+//! The maintenance switch of proxy services (`maintenance` in the service).
+//!
+//! While a service is in maintenance, `request_filter` answers its requests with
+//! the configured status before any route or filter runs, except requests under
+//! the `allow` paths, such as health endpoints. The switch is flipped through the
+//! admin API, and `flag-file` is polled: the service is in maintenance while
+//! either says so. Neither survives a restart, which starts from `enabled`.
+
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Weak,
+    },
+    time::Duration,
+};
+
+use motya_config::common_types::maintenance::MaintenanceConfig;
+use pingora::Result;
+use pingora_http::ResponseHeader;
+use pingora_proxy::Session;
+
+use crate::proxy::{error_pages, MotyaContext};
+
+/// How often `flag-file` is looked for.
+pub const FLAG_FILE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct Maintenance {
+    service: String,
+    config: MaintenanceConfig,
+    /// Set through the admin API, or from `enabled` at start.
+    switched_on: AtomicBool,
+    /// Whether `flag-file` existed when last looked for.
+    flagged: AtomicBool,
+}
+
+impl Maintenance {
+    /// Starts polling `flag-file`, if there is one, until the switch is dropped.
+    pub fn start(service: &str, config: MaintenanceConfig) -> Arc<Self> {
+        let flag_file = config.flag_file.clone();
+        let maintenance = Arc::new(Self {
+            service: service.to_string(),
+            switched_on: AtomicBool::new(config.enabled),
+            flagged: AtomicBool::new(flag_file.as_ref().is_some_and(|path| path.exists())),
+            config,
+        });
+
+        if let Some(path) = flag_file {
+            spawn_flag_file_poll(Arc::downgrade(&maintenance), path);
+        }
+
+        maintenance
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.switched_on() || self.flagged()
+    }
+
+    pub fn switched_on(&self) -> bool {
+        self.switched_on.load(Ordering::Relaxed)
+    }
+
+    pub fn flagged(&self) -> bool {
+        self.flagged.load(Ordering::Relaxed)
+    }
+
+    /// Flips the admin switch. The service stays in maintenance while its
+    /// `flag-file` exists, whatever the switch says.
+    pub fn switch(&self, on: bool) {
+        if self.switched_on.swap(on, Ordering::Relaxed) != on {
+            tracing::info!(
+                "Service '{}' switched {} maintenance",
+                self.service,
+                if on { "to" } else { "out of" }
+            );
+        }
+    }
+
+    /// Whether a request for `path` is answered by maintenance.
+    pub fn holds(&self, path: &str) -> bool {
+        self.is_on() && !self.config.allow.iter().any(|allowed| under(path, allowed))
+    }
+
+    /// Answers the request with the maintenance status and its error page.
+    pub async fn respond(&self, session: &mut Session, ctx: &MotyaContext) -> Result<()> {
+        let mut header = ResponseHeader::build(self.config.status, Some(4))?;
+        header.insert_header("Cache-Control", "no-store")?;
+        if let Some(retry_after) = self.config.retry_after {
+            header.insert_header("Retry-After", retry_after.as_secs().to_string())?;
+        }
+
+        error_pages::respond(session, ctx, header).await
+    }
+}
+
+/// Whether `path` is `prefix` or below it, `/healthz/live` is under `/healthz`
+/// but `/healthzz` isn't.
+fn under(path: &str, prefix: &str) -> bool {
+    match path.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || prefix.ends_with('/') || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+fn spawn_flag_file_poll(maintenance: Weak<Maintenance>, path: PathBuf) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(FLAG_FILE_POLL_INTERVAL);
+
+        loop {
+            ticker.tick().await;
+
+            let Some(maintenance) = maintenance.upgrade() else {
+                break;
+            };
+            let exists = tokio::fs::try_exists(&path).await.unwrap_or(false);
+            if maintenance.flagged.swap(exists, Ordering::Relaxed) != exists {
+                tracing::info!(
+                    "Service '{}' {} maintenance: {path:?} {}",
+                    maintenance.service,
+                    if exists { "entered" } else { "left" },
+                    if exists { "exists" } else { "is gone" }
+                );
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(allow: &[&str]) -> MaintenanceConfig {
+        MaintenanceConfig {
+            allow: allow.iter().map(|p| p.to_string()).collect(),
+            ..MaintenanceConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_switch_and_allow() {
+        let maintenance = Maintenance::start("api", config(&["/healthz", "/status/"]));
+        assert!(!maintenance.holds("/orders"));
+
+        maintenance.switch(true);
+        assert!(maintenance.holds("/orders"));
+        assert!(maintenance.holds("/healthzz"));
+        assert!(!maintenance.holds("/healthz"));
+        assert!(!maintenance.holds("/healthz/live"));
+        assert!(!maintenance.holds("/status/db"));
+
+        maintenance.switch(false);
+        assert!(!maintenance.holds("/orders"));
+    }
+
+    /// Waits for the poll to catch up with the flag file.
+    async fn settle() {
+        tokio::time::sleep(FLAG_FILE_POLL_INTERVAL + Duration::from_millis(500)).await;
+    }
+
+    #[tokio::test]
+    async fn test_flag_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let flag = dir.path().join("api.maintenance");
+        let maintenance = Maintenance::start(
+            "api",
+            MaintenanceConfig {
+                flag_file: Some(flag.clone()),
+                ..config(&[])
+            },
+        );
+        assert!(!maintenance.is_on());
+
+        std::fs::write(&flag, "").unwrap();
+        settle().await;
+        assert!(maintenance.is_on());
+        assert!(maintenance.flagged());
+
+        // The admin switch can't turn it off while the file is there.
+        maintenance.switch(false);
+        assert!(maintenance.is_on());
+
+        std::fs::remove_file(&flag).unwrap();
+        settle().await;
+        assert!(!maintenance.is_on());
+    }
+}
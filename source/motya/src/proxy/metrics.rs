@@ -0,0 +1,521 @@
+//! Request metrics of the proxy services.
+//!
+//! Every route holds the [`UpstreamMetrics`] of its upstream, shared through a
+//! process-wide table keyed by the upstream description, so the counters survive
+//! config reloads as long as the upstream stays the same. The proxy phases record
+//! into them without locking:
+//!
+//! - `request_filter`: a request was routed to the upstream
+//! - `upstream_peer`: the balancer picked a peer, and the upstream timer starts
+//! - `upstream_response_filter`: the status class and the upstream latency
+//! - `logging`: the status of gRPC calls
+//!
+//! [`render`] writes them in the Prometheus text format, along with the compression
+//! decisions of every route; [`metrics_service`] serves it on the listener given by
+//! `system.metrics-listener`, to the clients the admin API accepts.
+
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use http::{header, Method, Response, StatusCode};
+use motya_config::common_types::connectors::UpstreamConfig;
+use pingora::{
+    apps::http_app::{HttpServer, ServeHttp},
+    protocols::http::ServerSession,
+    services::{listening::Service as ListeningService, Service},
+};
+
+use crate::{
+    admin::access::{audit, AdminAccess},
+    proxy::{
+        accept_rate,
+        compression::{CompressionStatsSnapshot, COMPRESSION_STATS},
+        filters, grpc, limits,
+        panic_guard::describe_upstream,
+    },
+};
+
+const PROMETHEUS_TEXT: &str = "text/plain; version=0.0.4";
+const TEXT: &str = "text/plain; charset=utf-8";
+
+/// Upper bounds of the latency histogram buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+const STATUS_CLASSES: [&str; 5] = ["1xx", "2xx", "3xx", "4xx", "5xx"];
+
+static UPSTREAMS: Mutex<BTreeMap<String, Arc<UpstreamMetrics>>> = Mutex::new(BTreeMap::new());
+
+#[derive(Debug)]
+pub struct UpstreamMetrics {
+    requests: AtomicU64,
+    responses: [AtomicU64; STATUS_CLASSES.len()],
+    latency: Histogram,
+    /// gRPC calls by status code, for routes with `grpc=#true`.
+    grpc_statuses: [AtomicU64; grpc::STATUS_NAMES.len()],
+    /// One counter per server of a load-balanced upstream, empty otherwise.
+    selections: BTreeMap<SocketAddr, AtomicU64>,
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+/// The metrics of `upstream`, created on first use.
+pub fn upstream(upstream: &UpstreamConfig) -> Arc<UpstreamMetrics> {
+    UPSTREAMS
+        .lock()
+        .expect("upstream metrics lock poisoned")
+        .entry(describe_upstream(upstream))
+        .or_insert_with(|| Arc::new(UpstreamMetrics::new(upstream)))
+        .clone()
+}
+
+impl UpstreamMetrics {
+    fn new(upstream: &UpstreamConfig) -> Self {
+        let selections = match upstream {
+            UpstreamConfig::MultiServer(multi) => multi
+                .servers
+                .iter()
+                // A hostname has no fixed address to count the picks of.
+                .filter_map(|server| server.address.as_socket())
+                .map(|address| (address, AtomicU64::new(0)))
+                .collect(),
+            UpstreamConfig::Service(_) | UpstreamConfig::Static(_) => BTreeMap::new(),
+        };
+
+        Self {
+            requests: AtomicU64::new(0),
+            responses: Default::default(),
+            latency: Histogram::default(),
+            grpc_statuses: Default::default(),
+            selections,
+        }
+    }
+
+    pub fn record_request(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a pick of the balancer. Peers that aren't servers of the upstream are ignored.
+    pub fn record_selection(&self, peer: &SocketAddr) {
+        if let Some(count) = self.selections.get(peer) {
+            count.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn record_response(&self, status: StatusCode, latency: Option<Duration>) {
+        let class = (status.as_u16() / 100).clamp(1, 5) as usize - 1;
+        self.responses[class].fetch_add(1, Ordering::Relaxed);
+
+        if let Some(latency) = latency {
+            self.latency.observe(latency);
+        }
+    }
+
+    pub fn record_grpc_status(&self, code: u32) {
+        self.grpc_statuses[grpc::known(code) as usize].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl Histogram {
+    fn observe(&self, value: Duration) {
+        let seconds = value.as_secs_f64();
+
+        if let Some(idx) = LATENCY_BUCKETS.iter().position(|le| seconds <= *le) {
+            self.buckets[idx].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(value.as_micros() as u64, Ordering::Relaxed);
+    }
+}
+
+/// Writes the compression counters and every upstream metric in the Prometheus text format.
+pub fn render(out: &mut String) {
+    render_compression(out, COMPRESSION_STATS.snapshot());
+
+    let upstreams = UPSTREAMS
+        .lock()
+        .expect("upstream metrics lock poisoned")
+        .clone();
+
+    if upstreams.is_empty() {
+        return;
+    }
+
+    out.push_str("# HELP motya_upstream_requests_total Requests routed to an upstream.\n");
+    out.push_str("# TYPE motya_upstream_requests_total counter\n");
+    for (name, metrics) in &upstreams {
+        out.push_str(&format!(
+            "motya_upstream_requests_total{{upstream=\"{}\"}} {}\n",
+            escape_label(name),
+            metrics.requests.load(Ordering::Relaxed)
+        ));
+    }
+
+    out.push_str("# HELP motya_upstream_responses_total Upstream responses by status class.\n");
+    out.push_str("# TYPE motya_upstream_responses_total counter\n");
+    for (name, metrics) in &upstreams {
+        for (class, count) in STATUS_CLASSES.iter().zip(&metrics.responses) {
+            out.push_str(&format!(
+                "motya_upstream_responses_total{{upstream=\"{}\",class=\"{class}\"}} {}\n",
+                escape_label(name),
+                count.load(Ordering::Relaxed)
+            ));
+        }
+    }
+
+    out.push_str(
+        "# HELP motya_upstream_latency_seconds Time from picking a peer to its response header.\n",
+    );
+    out.push_str("# TYPE motya_upstream_latency_seconds histogram\n");
+    for (name, metrics) in &upstreams {
+        let name = escape_label(name);
+        let latency = &metrics.latency;
+
+        let mut cumulative = 0;
+        for (le, count) in LATENCY_BUCKETS.iter().zip(&latency.buckets) {
+            cumulative += count.load(Ordering::Relaxed);
+            out.push_str(&format!(
+                "motya_upstream_latency_seconds_bucket{{upstream=\"{name}\",le=\"{le}\"}} {cumulative}\n"
+            ));
+        }
+
+        let count = latency.count.load(Ordering::Relaxed);
+        let sum = latency.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        out.push_str(&format!(
+            "motya_upstream_latency_seconds_bucket{{upstream=\"{name}\",le=\"+Inf\"}} {count}\n"
+        ));
+        out.push_str(&format!(
+            "motya_upstream_latency_seconds_sum{{upstream=\"{name}\"}} {sum}\n"
+        ));
+        out.push_str(&format!(
+            "motya_upstream_latency_seconds_count{{upstream=\"{name}\"}} {count}\n"
+        ));
+    }
+
+    let saw_grpc = |m: &UpstreamMetrics| {
+        m.grpc_statuses
+            .iter()
+            .any(|count| count.load(Ordering::Relaxed) > 0)
+    };
+    if upstreams.values().any(|m| saw_grpc(m)) {
+        out.push_str("# HELP motya_upstream_grpc_responses_total gRPC calls by status code.\n");
+        out.push_str("# TYPE motya_upstream_grpc_responses_total counter\n");
+        for (name, metrics) in upstreams.iter().filter(|(_, m)| saw_grpc(m)) {
+            for (code, count) in grpc::STATUS_NAMES.iter().zip(&metrics.grpc_statuses) {
+                out.push_str(&format!(
+                    "motya_upstream_grpc_responses_total{{upstream=\"{}\",code=\"{code}\"}} {}\n",
+                    escape_label(name),
+                    count.load(Ordering::Relaxed)
+                ));
+            }
+        }
+    }
+
+    if upstreams.values().any(|m| !m.selections.is_empty()) {
+        out.push_str(
+            "# HELP motya_balancer_selections_total Peers picked by the load balancer of an upstream.\n",
+        );
+        out.push_str("# TYPE motya_balancer_selections_total counter\n");
+        for (name, metrics) in &upstreams {
+            for (peer, count) in &metrics.selections {
+                out.push_str(&format!(
+                    "motya_balancer_selections_total{{upstream=\"{}\",peer=\"{peer}\"}} {}\n",
+                    escape_label(name),
+                    count.load(Ordering::Relaxed)
+                ));
+            }
+        }
+    }
+}
+
+fn render_compression(out: &mut String, stats: CompressionStatsSnapshot) {
+    out.push_str("# HELP motya_compression_responses_total Responses by compression outcome.\n");
+    out.push_str("# TYPE motya_compression_responses_total counter\n");
+    for (outcome, count) in [
+        ("compressed", stats.compressed),
+        ("skipped", stats.skipped()),
+    ] {
+        out.push_str(&format!(
+            "motya_compression_responses_total{{outcome=\"{outcome}\"}} {count}\n"
+        ));
+    }
+
+    out.push_str(
+        "# HELP motya_compression_skipped_total Responses left uncompressed, by reason.\n",
+    );
+    out.push_str("# TYPE motya_compression_skipped_total counter\n");
+    for (reason, count) in [
+        ("content-type", stats.skipped_content_type),
+        ("already-encoded", stats.skipped_already_encoded),
+        ("not-accepted", stats.skipped_not_accepted),
+        ("too-small", stats.skipped_too_small),
+    ] {
+        out.push_str(&format!(
+            "motya_compression_skipped_total{{reason=\"{reason}\"}} {count}\n"
+        ));
+    }
+}
+
+pub fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+struct MetricsEndpoint {
+    access: AdminAccess,
+}
+
+/// Creates the Prometheus scrape listener on `addr`, serving `GET /metrics` to the
+/// clients `access` lets through.
+pub fn metrics_service(addr: SocketAddr, access: AdminAccess) -> Box<dyn Service> {
+    let mut service = ListeningService::new(
+        "motya-metrics".to_string(),
+        HttpServer::new_app(MetricsEndpoint { access }),
+    );
+    service.add_tcp(&addr.to_string());
+
+    Box::new(service)
+}
+
+#[async_trait]
+impl ServeHttp for MetricsEndpoint {
+    async fn response(&self, session: &mut ServerSession) -> Response<Vec<u8>> {
+        let req = session.req_header();
+        let authorization = req
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok());
+
+        let (status, content_type, body) =
+            match session.client_addr().and_then(|peer| peer.as_inet()) {
+                Some(peer) => self.reply(peer.ip(), authorization, &req.method, req.uri.path()),
+                None => (
+                    StatusCode::FORBIDDEN,
+                    TEXT,
+                    "client address unknown\n".to_string(),
+                ),
+            };
+
+        let body = body.into_bytes();
+        Response::builder()
+            .status(status)
+            .header(header::CONTENT_TYPE, content_type)
+            .header(header::CONTENT_LENGTH, body.len())
+            .body(body)
+            .expect("metrics response parts are valid")
+    }
+}
+
+impl MetricsEndpoint {
+    /// Checks the client like an admin request, then serves the metrics.
+    fn reply(
+        &self,
+        client: IpAddr,
+        authorization: Option<&str>,
+        method: &Method,
+        path: &str,
+    ) -> (StatusCode, &'static str, String) {
+        let outcome = self.access.check(Some(client), authorization);
+        audit(Some(client), &format!("{method} {path}"), outcome);
+
+        if let Err(denied) = outcome {
+            return (
+                StatusCode::from_u16(denied.status()).expect("admin denial codes are valid"),
+                TEXT,
+                format!("{denied}\n"),
+            );
+        }
+
+        match (method, path) {
+            (&Method::GET, "/metrics") => {
+                let mut body = String::new();
+                limits::render(&mut body);
+                accept_rate::render(&mut body);
+                render(&mut body);
+                filters::metrics::render(&mut body);
+                (StatusCode::OK, PROMETHEUS_TEXT, body)
+            }
+            _ => (
+                StatusCode::NOT_FOUND,
+                TEXT,
+                "metrics are served at GET /metrics\n".to_string(),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use motya_config::common_types::{
+        admin::AdminConfig,
+        connectors::{MultiServerUpstreamConfig, RouteMatcher, UpstreamServer, ALPN},
+        simple_response_type::SimpleResponseConfig,
+    };
+
+    use super::*;
+
+    fn static_upstream(code: u16) -> UpstreamConfig {
+        UpstreamConfig::Static(SimpleResponseConfig {
+            http_code: StatusCode::from_u16(code).unwrap(),
+            response_body: "".into(),
+            prefix_path: "/".parse().unwrap(),
+        })
+    }
+
+    #[test]
+    fn test_counters_are_shared_by_upstream() {
+        let first = upstream(&static_upstream(201));
+        let second = upstream(&static_upstream(201));
+        assert!(Arc::ptr_eq(&first, &second));
+
+        first.record_request();
+        second.record_request();
+        second.record_response(StatusCode::BAD_GATEWAY, Some(Duration::from_millis(30)));
+
+        let mut out = String::new();
+        render(&mut out);
+
+        assert!(out.contains("motya_upstream_requests_total{upstream=\"static(201)\"} 2\n"));
+        assert!(out.contains(
+            "motya_upstream_responses_total{upstream=\"static(201)\",class=\"5xx\"} 1\n"
+        ));
+        assert!(out.contains(
+            "motya_upstream_latency_seconds_bucket{upstream=\"static(201)\",le=\"0.025\"} 0\n"
+        ));
+        assert!(out.contains(
+            "motya_upstream_latency_seconds_bucket{upstream=\"static(201)\",le=\"0.05\"} 1\n"
+        ));
+        assert!(out.contains("motya_upstream_latency_seconds_count{upstream=\"static(201)\"} 1\n"));
+    }
+
+    #[test]
+    fn test_grpc_statuses() {
+        let metrics = upstream(&static_upstream(202));
+        metrics.record_grpc_status(0);
+        metrics.record_grpc_status(14);
+        metrics.record_grpc_status(42);
+
+        let mut out = String::new();
+        render(&mut out);
+
+        let line = |code: &str, count: u64| {
+            format!(
+                "motya_upstream_grpc_responses_total{{upstream=\"static(202)\",code=\"{code}\"}} {count}\n"
+            )
+        };
+        assert!(out.contains(&line("OK", 1)));
+        assert!(out.contains(&line("UNAVAILABLE", 1)));
+        assert!(out.contains(&line("UNKNOWN", 1)));
+        assert!(out.contains(&line("NOT_FOUND", 0)));
+        // Upstreams without gRPC calls get no series.
+        assert!(!out.contains("motya_upstream_grpc_responses_total{upstream=\"static(201)\""));
+    }
+
+    #[test]
+    fn test_balancer_selections() {
+        let servers: Vec<SocketAddr> = vec![
+            "127.0.0.1:7001".parse().unwrap(),
+            "127.0.0.1:7002".parse().unwrap(),
+        ];
+        let metrics =
+            UpstreamMetrics::new(&UpstreamConfig::MultiServer(MultiServerUpstreamConfig {
+                servers: servers
+                    .iter()
+                    .map(|address| UpstreamServer {
+                        address: (*address).into(),
+                        weight: 1,
+                    })
+                    .collect(),
+                tls_sni: None,
+                alpn: ALPN::H1,
+                prefix_path: "/".parse().unwrap(),
+                matcher: RouteMatcher::Prefix,
+            }));
+
+        metrics.record_selection(&servers[1]);
+        metrics.record_selection(&servers[1]);
+        metrics.record_selection(&"127.0.0.1:7003".parse().unwrap());
+
+        assert_eq!(metrics.selections[&servers[0]].load(Ordering::Relaxed), 0);
+        assert_eq!(metrics.selections[&servers[1]].load(Ordering::Relaxed), 2);
+        assert_eq!(metrics.selections.len(), 2);
+    }
+
+    #[test]
+    fn test_compression_outcomes() {
+        let mut out = String::new();
+        render_compression(
+            &mut out,
+            CompressionStatsSnapshot {
+                compressed: 3,
+                skipped_content_type: 1,
+                skipped_already_encoded: 0,
+                skipped_not_accepted: 2,
+                skipped_too_small: 4,
+            },
+        );
+
+        assert!(out.contains("# TYPE motya_compression_responses_total counter\n"));
+        assert!(out.contains("motya_compression_responses_total{outcome=\"compressed\"} 3\n"));
+        assert!(out.contains("motya_compression_responses_total{outcome=\"skipped\"} 7\n"));
+        assert!(out.contains("motya_compression_skipped_total{reason=\"content-type\"} 1\n"));
+        assert!(out.contains("motya_compression_skipped_total{reason=\"already-encoded\"} 0\n"));
+        assert!(out.contains("motya_compression_skipped_total{reason=\"not-accepted\"} 2\n"));
+        assert!(out.contains("motya_compression_skipped_total{reason=\"too-small\"} 4\n"));
+    }
+
+    #[test]
+    fn test_compression_counters_are_rendered() {
+        let mut out = String::new();
+        render(&mut out);
+
+        assert!(out.contains("motya_compression_responses_total{outcome=\"compressed\"} "));
+    }
+
+    #[test]
+    fn test_endpoint_checks_the_admin_allowlist() {
+        let endpoint = MetricsEndpoint {
+            access: AdminAccess::from_config(&AdminConfig::default()).unwrap(),
+        };
+        let scrape = |client: &str| {
+            let (status, _, _) =
+                endpoint.reply(client.parse().unwrap(), None, &Method::GET, "/metrics");
+            status
+        };
+
+        assert_eq!(scrape("127.0.0.1"), StatusCode::OK);
+        assert_eq!(scrape("::ffff:127.0.0.1"), StatusCode::OK);
+        assert_eq!(scrape("203.0.113.7"), StatusCode::FORBIDDEN);
+
+        let (status, _, body) = endpoint.reply(
+            "203.0.113.7".parse().unwrap(),
+            None,
+            &Method::GET,
+            "/missing",
+        );
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert!(!body.contains("GET /metrics"));
+    }
+
+    #[test]
+    fn test_escape_label() {
+        assert_eq!(escape_label("a\"b\\c\n"), "a\\\"b\\\\c\\n");
+    }
+}
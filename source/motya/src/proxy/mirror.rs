@@ -0,0 +1,193 @@
+//! Request mirroring, for routes with a `mirror` directive.
+//!
+//! A sampled request is copied to a shadow upstream next to the real one. The
+//! head is copied when the request arrives and the body as its chunks pass
+//! through; once the body is complete, the copy goes out on a task of its own.
+//! Whatever the shadow answers, or however it fails, the client never sees it.
+
+use std::{sync::Arc, time::Duration};
+
+use bytes::BytesMut;
+use http::{HeaderMap, Method};
+use miette::{IntoDiagnostic, Result};
+use motya_config::common_types::connectors::MirrorConfig;
+use pingora_http::RequestHeader;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Copies in flight per route. Past that, requests go unmirrored, so a slow
+/// shadow can't pile up work in the proxy.
+const MAX_IN_FLIGHT: usize = 64;
+/// Requests with larger bodies go unmirrored.
+const MAX_BODY: usize = 1024 * 1024;
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Headers about the client's connection rather than the request.
+pub const HOP_BY_HOP: [&str; 7] = [
+    "connection",
+    "keep-alive",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+    "content-length",
+];
+
+/// The `mirror` directive of a route.
+pub struct Mirror {
+    client: reqwest::Client,
+    /// Scheme and address of the shadow upstream, without a trailing `/`.
+    base: String,
+    sample: f64,
+    in_flight: Arc<Semaphore>,
+}
+
+impl Mirror {
+    pub fn new(config: MirrorConfig) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(TIMEOUT)
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .into_diagnostic()?;
+
+        Ok(Self {
+            client,
+            base: config.url.to_string().trim_end_matches('/').to_string(),
+            sample: config.sample,
+            in_flight: Arc::new(Semaphore::new(MAX_IN_FLIGHT)),
+        })
+    }
+
+    /// Starts a copy of `request`, if it is in the sample and there is room.
+    pub fn start(&self, request: &RequestHeader) -> Option<MirrorRequest> {
+        if self.sample < 1.0 && fastrand::f64() >= self.sample {
+            return None;
+        }
+        let permit = self.in_flight.clone().try_acquire_owned().ok()?;
+
+        let mut headers = request.headers.clone();
+        for name in HOP_BY_HOP {
+            headers.remove(name);
+        }
+        let path = request.uri.path_and_query().map_or("/", |p| p.as_str());
+
+        Some(MirrorRequest {
+            client: self.client.clone(),
+            method: request.method.clone(),
+            url: format!("{}{path}", self.base),
+            headers,
+            body: BytesMut::new(),
+            _permit: permit,
+        })
+    }
+}
+
+/// The copy of one request, collecting its body.
+pub struct MirrorRequest {
+    client: reqwest::Client,
+    method: Method,
+    url: String,
+    headers: HeaderMap,
+    body: BytesMut,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl MirrorRequest {
+    /// Adds a chunk of the body. Returns `false` once the body is too large to
+    /// be mirrored, and the copy should be dropped.
+    pub fn push(&mut self, chunk: &[u8]) -> bool {
+        if self.body.len() + chunk.len() > MAX_BODY {
+            return false;
+        }
+        self.body.extend_from_slice(chunk);
+        true
+    }
+
+    /// Sends the copy and forgets about it.
+    pub fn send(self) {
+        let Self {
+            client,
+            method,
+            url,
+            headers,
+            body,
+            _permit: permit,
+        } = self;
+
+        tokio::spawn(async move {
+            let result = client
+                .request(method, &url)
+                .headers(headers)
+                .body(body.freeze())
+                .send()
+                .await;
+            if let Err(err) = result {
+                tracing::debug!("Mirrored request to {url} failed: {err}");
+            }
+            drop(permit);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mirror(sample: f64) -> Mirror {
+        Mirror::new(MirrorConfig {
+            url: "http://staging:8080".parse().unwrap(),
+            sample,
+        })
+        .unwrap()
+    }
+
+    fn request() -> RequestHeader {
+        let mut request = RequestHeader::build("POST", b"/orders?id=7", None).unwrap();
+        request.insert_header("host", "shop.example.com").unwrap();
+        request.insert_header("connection", "keep-alive").unwrap();
+        request.insert_header("content-length", "2").unwrap();
+        request
+    }
+
+    #[test]
+    fn test_copy_of_request() {
+        let copy = mirror(1.0).start(&request()).unwrap();
+
+        assert_eq!(copy.method, Method::POST);
+        assert_eq!(copy.url, "http://staging:8080/orders?id=7");
+        assert_eq!(copy.headers["host"], "shop.example.com");
+        assert!(copy.headers.get("connection").is_none());
+        assert!(copy.headers.get("content-length").is_none());
+    }
+
+    #[test]
+    fn test_sample() {
+        assert!(mirror(0.0).start(&request()).is_none());
+
+        let half = mirror(0.5);
+        let copied = (0..1000)
+            .filter(|_| half.start(&request()).is_some())
+            .count();
+        assert!((350..650).contains(&copied), "{copied}");
+    }
+
+    #[test]
+    fn test_in_flight_limit() {
+        let mirror = mirror(1.0);
+        let copies: Vec<_> = (0..MAX_IN_FLIGHT)
+            .map(|_| mirror.start(&request()).unwrap())
+            .collect();
+        assert!(mirror.start(&request()).is_none());
+
+        drop(copies);
+        assert!(mirror.start(&request()).is_some());
+    }
+
+    #[test]
+    fn test_body_limit() {
+        let mut copy = mirror(1.0).start(&request()).unwrap();
+
+        assert!(copy.push(&[0; MAX_BODY - 1]));
+        assert!(copy.push(b"!"));
+        assert!(!copy.push(b"!"));
+    }
+}
@@ -1,42 +1,98 @@
-use std::sync::Arc;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
 
 use arc_swap::ArcSwap;
 use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
 use futures_util::future::try_join_all;
-use http::uri::PathAndQuery;
+use http::{uri::PathAndQuery, HeaderMap};
 use motya_config::{
     common_types::{
+        access_log::AccessLogConfig,
         connectors::{UpstreamConfig, UpstreamContextConfig},
+        error_pages::ErrorPagesConfig,
         listeners::Listeners,
     },
     internal::ProxyConfig,
 };
-use pingora::{prelude::HttpPeer, server::Server, Result};
+use pingora::{
+    prelude::HttpPeer,
+    protocols::{l4::socket::SocketAddr, Digest},
+    server::Server,
+    BError, Error, ErrorSource, ErrorType, Result,
+};
+use pingora_cache::{key::HashBinary, CacheKey, CacheMeta, NoCacheReason, RespCacheable};
 use pingora_http::{RequestHeader, ResponseHeader};
-use pingora_proxy::{ProxyHttp, Session};
+use pingora_proxy::{FailToProxy, ProxyHttp, Session};
 use uuid::Uuid;
 
 use crate::proxy::{
+    accept_rate::AcceptRate,
+    access_log::{AccessLog, Entry},
+    balancer::least_loaded::LoadGuard,
     context::{ContextInfo, SessionInfo},
+    drain::InFlight,
+    error_pages::ErrorPages,
     filters::{
-        builtin::simple_response::SimpleResponse,
+        builtin::{simple_response::SimpleResponse, throttle::Pacer},
         chain_resolver::ChainResolver,
+        metrics::Outcome,
         types::{RequestFilterMod, RequestModifyMod, ResponseModifyMod},
     },
+    limits::{ConnectionLimit, Permit, ServiceLimits},
+    maintenance::Maintenance,
+    mirror::MirrorRequest,
+    panic_guard::{CaughtPanic, RequestReport},
     populate_listeners::populate_listners,
+    proxy_protocol::ProxyProtocol,
+    rate_limiter::headers::RateLimitHeaders,
+    request_body::BodyBuffer,
+    response_body::ResponseBodyBuffer,
+    retry::{Failure, RetryPolicy, RetryState},
+    trace::Trace,
     upstream_factory::UpstreamFactory,
-    upstream_router::{UpstreamContext, UpstreamRouter},
+    upstream_router::{UpstreamContext, UpstreamContextTrait, UpstreamRouter},
 };
 
+pub mod accept_rate;
+pub mod access_log;
+pub mod acme;
 pub mod balancer;
+pub mod cache;
+pub mod compression;
+pub mod config_version;
 pub mod context;
+pub mod credentials;
+pub mod discovery;
+pub mod drain;
+pub mod error_pages;
 pub mod filters;
+pub mod grpc;
 pub mod key_selector;
+pub mod limits;
+pub mod maintenance;
+pub mod metrics;
+pub mod mirror;
+pub mod panic_guard;
 pub mod plugins;
 pub mod populate_listeners;
+pub mod proxy_protocol;
 pub mod rate_limiter;
+pub mod request_body;
+pub mod response_body;
+pub mod retry;
+pub mod rewrite;
+pub mod secrets;
+pub mod sse;
+pub mod timeout;
+pub mod trace;
+pub mod upgrade;
 pub mod upstream_factory;
+pub mod upstream_h2;
 pub mod upstream_router;
+pub mod upstream_tls;
 pub mod watcher;
 
 // pub struct RateLimiters {
@@ -44,11 +100,30 @@ pub mod watcher;
 //     request_filter_stage_single: Vec<SingleInstance>,
 // }
 
-pub type SharedProxyState = Arc<ArcSwap<UpstreamRouter<UpstreamContext>>>;
+/// What a running proxy service shares with config reloads and the admin API.
+pub struct ProxyState {
+    /// Swapped when a reload changes the connectors of the service.
+    pub router: ArcSwap<UpstreamRouter<UpstreamContext>>,
+    pub maintenance: Arc<Maintenance>,
+}
+
+impl ProxyState {
+    pub fn new(router: UpstreamRouter<UpstreamContext>, maintenance: Arc<Maintenance>) -> Self {
+        Self {
+            router: ArcSwap::from_pointee(router),
+            maintenance,
+        }
+    }
+}
+
+pub type SharedProxyState = Arc<ProxyState>;
 
 pub struct MotyaProxyService {
     // pub rate_limiters: RateLimiters,
     pub state: SharedProxyState,
+    pub access_log: Option<AccessLog>,
+    pub limits: Option<Arc<ServiceLimits>>,
+    pub error_pages: Option<Arc<ErrorPages>>,
 }
 
 /// Create a proxy service, with the type parameters chosen based on the config file
@@ -58,23 +133,41 @@ pub async fn motya_proxy_service(
     server: &Server,
 ) -> miette::Result<(Box<dyn pingora::services::Service>, SharedProxyState)> {
     let factory = UpstreamFactory::new(chain_resolver);
+    let limits = conf
+        .limits
+        .map(|config| limits::service(&conf.name, &config));
 
-    MotyaProxyService::from_basic_conf(conf.connectors.upstreams, &conf.listeners, factory, server)
-        .await
+    MotyaProxyService::from_basic_conf(
+        &conf.name,
+        conf.connectors.upstreams,
+        &conf.listeners,
+        conf.access_log,
+        limits,
+        conf.error_pages,
+        Maintenance::start(&conf.name, conf.maintenance.unwrap_or_default()),
+        factory,
+        server,
+    )
+    .await
 }
 
 impl MotyaProxyService {
     /// Create a new [MotyaProxyService] from the given [ProxyConfig]
     pub async fn from_basic_conf(
+        name: &str,
         upstream_configs: Vec<UpstreamContextConfig>,
         listeners: &Listeners,
+        access_log: Option<AccessLogConfig>,
+        limits: Option<Arc<ServiceLimits>>,
+        error_pages: Option<ErrorPagesConfig>,
+        maintenance: Arc<Maintenance>,
         upstream_factory: UpstreamFactory,
         server: &Server,
     ) -> miette::Result<(Box<dyn pingora::services::Service>, SharedProxyState)> {
         let upstream_ctx = try_join_all(
             upstream_configs
                 .into_iter()
-                .map(|cfg| upstream_factory.create_context(cfg)),
+                .map(|cfg| upstream_factory.create_context(name, cfg)),
         )
         .await?;
 
@@ -97,13 +190,31 @@ impl MotyaProxyService {
         //     }
         // }
 
-        let shared_state = Arc::new(ArcSwap::from_pointee(router));
-        let mut my_proxy = pingora_proxy::http_proxy_service_with_name(
+        let access_log = match access_log {
+            Some(config) => Some(AccessLog::start(&config).await?),
+            None => None,
+        };
+
+        let error_pages = error_pages
+            .map(|config| ErrorPages::load(&config).map(Arc::new))
+            .transpose()?;
+
+        let shared_state = Arc::new(ProxyState::new(router, maintenance));
+        let proxy = pingora_proxy::http_proxy(
             &server.configuration,
             Self {
                 state: shared_state.clone(),
+                access_log,
+                limits: limits.clone(),
+                error_pages,
             },
-            "motya-proxy",
+        );
+        let mut my_proxy = pingora::services::listening::Service::new(
+            "motya-proxy".to_string(),
+            AcceptRate::new(
+                ConnectionLimit::new(ProxyProtocol::new(proxy, listeners), limits),
+                listeners,
+            ),
         );
 
         populate_listners(listeners, &mut my_proxy);
@@ -114,6 +225,74 @@ impl MotyaProxyService {
 
 pub struct MotyaContext {
     router: Arc<UpstreamRouter<UpstreamContext>>,
+    /// The route of the request in `router`, resolved once in `request_filter` so
+    /// that the later phases don't match the path again.
+    route: Option<usize>,
+    request_id: Uuid,
+    /// When the request arrived, for the access log.
+    started: Instant,
+    /// When the upstream peer was picked, for the upstream latency metric.
+    upstream_started: Option<Instant>,
+    /// Address of the picked upstream peer, for the access log and
+    /// `outlier-detection`.
+    upstream_addr: Option<SocketAddr>,
+    /// The request body so far, for routes with body filters.
+    request_body: Option<BodyBuffer>,
+    /// The response body so far, for routes with response body filters.
+    response_body: Option<ResponseBodyBuffer>,
+    /// Retries taken so far, for routes with a `retry` directive.
+    retry: RetryState,
+    /// The request switches protocols, and its bodies are a byte stream.
+    upgrade: bool,
+    /// The status of the call, for routes with `grpc=#true`.
+    grpc_status: Option<u32>,
+    /// The copy of the request for the shadow upstream, until its body is complete.
+    mirror: Option<MirrorRequest>,
+    /// Keeps the request counted until it is done, for draining on shutdown.
+    _in_flight: InFlight,
+    /// Keeps the request counted against `max-inflight` until it is done.
+    _inflight_permit: Option<Permit>,
+    /// The pages of the service, for the errors it answers itself.
+    error_pages: Option<Arc<ErrorPages>>,
+    /// The decisions taken for the request, for routes with `debug-trace=#true`.
+    trace: Option<Trace>,
+    /// Counts the request against the load of its backend, for balancers that
+    /// pick on load.
+    backend_load: Option<LoadGuard>,
+    /// The buckets `throttle` filters pace the response body with.
+    pacers: Vec<Arc<Pacer>>,
+    /// The `RateLimit-*` headers of the policy the request came closest to
+    /// running out of, among those with `expose-headers=#true`.
+    rate_limit: Option<RateLimitHeaders>,
+}
+
+impl MotyaContext {
+    /// The route of the request, once `request_filter` resolved it.
+    fn upstream(&self) -> Option<&UpstreamContext> {
+        self.router.get_upstream(self.route)
+    }
+
+    /// Paces the response body with `pacer`, along with the others.
+    pub fn pace_response(&mut self, pacer: Arc<Pacer>) {
+        self.pacers.push(pacer);
+    }
+
+    /// Sends `headers` with the response, unless another policy is tighter.
+    pub fn expose_rate_limit(&mut self, headers: RateLimitHeaders) {
+        match &self.rate_limit {
+            Some(current) if !headers.tighter_than(current) => {}
+            _ => self.rate_limit = Some(headers),
+        }
+    }
+
+    /// How long to hold back `bytes` of the response body, the longest any
+    /// pacer asks for.
+    fn pace(&self, bytes: usize) -> Option<Duration> {
+        self.pacers
+            .iter()
+            .filter_map(|pacer| pacer.take(bytes))
+            .max()
+    }
 }
 
 #[async_trait]
@@ -121,9 +300,27 @@ impl ProxyHttp for MotyaProxyService {
     type CTX = MotyaContext;
 
     fn new_ctx(&self) -> Self::CTX {
-        let router = self.state.load();
+        let router = self.state.router.load();
         MotyaContext {
             router: router.clone(),
+            route: None,
+            request_id: Uuid::new_v4(),
+            started: Instant::now(),
+            upstream_started: None,
+            upstream_addr: None,
+            request_body: None,
+            response_body: None,
+            retry: RetryState::default(),
+            upgrade: false,
+            grpc_status: None,
+            mirror: None,
+            _in_flight: InFlight::start(),
+            _inflight_permit: None,
+            error_pages: self.error_pages.clone(),
+            trace: None,
+            backend_load: None,
+            pacers: vec![],
+            rate_limit: None,
         }
     }
 
@@ -132,10 +329,454 @@ impl ProxyHttp for MotyaProxyService {
     where
         Self::CTX: Send + Sync,
     {
+        panic_guard::catch_async(self.handle_request_filter(session, ctx))
+            .await
+            .unwrap_or_else(|p| Err(panic_report("request_filter", p, session, ctx)))
+    }
+
+    /// Handle the "upstream peer" phase, where we pick which upstream to proxy to.
+    async fn upstream_peer(
+        &self,
+        session: &mut Session,
+        ctx: &mut Self::CTX,
+    ) -> Result<Box<HttpPeer>> {
+        if let Some(delay) =
+            retry_policy(&ctx.router, ctx.route).and_then(|policy| ctx.retry.backoff(policy))
+        {
+            tokio::time::sleep(delay).await;
+        }
+
+        panic_guard::catch_sync(|| self.handle_upstream_peer(session, ctx))
+            .unwrap_or_else(|p| Err(panic_report("upstream_peer", p, session, ctx)))
+    }
+
+    /// Checks the certificate of a new upstream connection against the
+    /// `cert-sha256` pins of the route.
+    async fn connected_to_upstream(
+        &self,
+        _session: &mut Session,
+        _reused: bool,
+        _peer: &HttpPeer,
+        #[cfg(unix)] _fd: std::os::unix::io::RawFd,
+        #[cfg(windows)] _sock: std::os::windows::io::RawSocket,
+        digest: Option<&Digest>,
+        ctx: &mut Self::CTX,
+    ) -> Result<()> {
+        let tls = ctx
+            .upstream()
+            .and_then(|upstream_ctx| upstream_ctx.upstream_tls.as_ref());
+        tls.map_or(Ok(()), |tls| tls.check_pins(digest))
+    }
+
+    /// Marks a failed connection as retryable on routes that retry them, and
+    /// counts it against the backend for `outlier-detection`.
+    fn fail_to_connect(
+        &self,
+        session: &mut Session,
+        peer: &HttpPeer,
+        ctx: &mut Self::CTX,
+        mut e: Box<Error>,
+    ) -> Box<Error> {
+        if let Some(balancer) = ctx.upstream().and_then(|u| u.balancer.as_ref()) {
+            balancer.record_failure(&peer._address);
+        }
+        if let Some(policy) = retry_policy(&ctx.router, ctx.route) {
+            let method = &session.req_header().method;
+            e.set_retry(ctx.retry.try_retry(policy, Failure::Connect, method));
+        }
+
+        e
+    }
+
+    /// Marks an upstream failure as retryable on routes that retry them, or
+    /// leaves the decision to pingora.
+    fn error_while_proxy(
+        &self,
+        peer: &HttpPeer,
+        session: &mut Session,
+        e: Box<Error>,
+        ctx: &mut Self::CTX,
+        client_reused: bool,
+    ) -> Box<Error> {
+        let mut e = e.more_context(format!("Peer: {peer}"));
+
+        let method = &session.req_header().method;
+        match retry_policy(&ctx.router, ctx.route) {
+            // Decided with the response, the error already says so.
+            Some(_) if ctx.retry.take_pending() => {}
+            Some(policy) if ctx.retry.try_retry(policy, Failure::Error, method) => {
+                e.set_retry(true);
+            }
+            // pingora's default: retry if a reused connection turned out stale.
+            _ => e.retry.decide_reuse(client_reused),
+        }
+
+        e
+    }
+
+    /// Answers the timeouts of routes with a `timeout` with `504` or `408`, and
+    /// every other failure as pingora does, with the error pages of the service.
+    async fn fail_to_proxy(
+        &self,
+        session: &mut Session,
+        e: &Error,
+        ctx: &mut Self::CTX,
+    ) -> FailToProxy
+    where
+        Self::CTX: Send + Sync,
+    {
+        let timed_out = ctx
+            .upstream()
+            .filter(|upstream_ctx| upstream_ctx.timeout.is_some())
+            .and_then(|_| timeout::status(e));
+
+        let code = match (timed_out, e.etype()) {
+            (Some(code), _) => code,
+            (None, ErrorType::HTTPStatus(code)) => *code,
+            (None, etype) => match e.esource() {
+                ErrorSource::Upstream => 502,
+                ErrorSource::Downstream => match etype {
+                    // The client is gone, nothing can be sent.
+                    ErrorType::WriteError | ErrorType::ReadError | ErrorType::ConnectionClosed => 0,
+                    _ => 400,
+                },
+                ErrorSource::Internal | ErrorSource::Unset => 500,
+            },
+        };
+
+        if code > 0 {
+            if let Err(err) = error_pages::respond_error(session, ctx, code).await {
+                tracing::error!("failed to send error response to downstream: {err}");
+            }
+        }
+
+        FailToProxy {
+            error_code: code,
+            can_reuse_downstream: false,
+        }
+    }
+
+    /// Handle the "upstream request filter" phase, where we can choose to make
+    /// modifications to the request, prior to it being passed along to the
+    /// upstream.
+    ///
+    /// We can also *reject* requests here, though in the future we might do that
+    /// via the `request_filter` stage, as that rejection can be done prior to
+    /// paying any potential cost `upstream_peer` may incur.
+    async fn upstream_request_filter(
+        &self,
+        session: &mut Session,
+        header: &mut RequestHeader,
+        ctx: &mut Self::CTX,
+    ) -> Result<()> {
+        panic_guard::catch_async(self.handle_upstream_request_filter(session, header, ctx))
+            .await
+            .unwrap_or_else(|p| Err(panic_report("upstream_request_filter", p, session, ctx)))
+    }
+
+    /// Holds the request body back for routes with body filters, and hands the
+    /// filtered body upstream once it is complete.
+    async fn request_body_filter(
+        &self,
+        session: &mut Session,
+        body: &mut Option<Bytes>,
+        end_of_stream: bool,
+        ctx: &mut Self::CTX,
+    ) -> Result<()>
+    where
+        Self::CTX: Send + Sync,
+    {
+        panic_guard::catch_sync(|| {
+            self.handle_request_body_filter(session, body, end_of_stream, ctx)
+        })
+        .unwrap_or_else(|p| Err(panic_report("request_body_filter", p, session, ctx)))
+    }
+
+    /// Handle the "upstream response filter" phase, where we can choose to make
+    /// modifications to the response, prior to it being passed along downstream
+    ///
+    /// We may want to also support `upstream_response` stage, as that may interact
+    /// with cache differently.
+    fn upstream_response_filter(
+        &self,
+        session: &mut Session,
+        upstream_response: &mut ResponseHeader,
+        ctx: &mut Self::CTX,
+    ) -> Result<()> {
+        panic_guard::catch_sync(|| {
+            self.handle_upstream_response_filter(session, upstream_response, ctx)
+        })
+        .unwrap_or_else(|p| Err(panic_report("upstream_response_filter", p, session, ctx)))
+    }
+
+    /// Holds the response body back for routes with response body filters, and adds
+    /// the exposed `RateLimit-*` headers, the `X-Cache` headers for routes with
+    /// cache `debug-headers`, and the trace header for routes with `debug-trace`.
+    /// Unlike `upstream_response_filter`, this also runs for responses from the cache.
+    async fn response_filter(
+        &self,
+        session: &mut Session,
+        upstream_response: &mut ResponseHeader,
+        ctx: &mut Self::CTX,
+    ) -> Result<()>
+    where
+        Self::CTX: Send + Sync,
+    {
+        if let Some(upstream_ctx) = ctx
+            .router
+            .get_upstream(ctx.route)
+            .filter(|upstream_ctx| filters_response_bodies(upstream_ctx, ctx))
+        {
+            ctx.response_body = response_body::prepare_response(
+                &upstream_ctx.chains,
+                session.req_header(),
+                upstream_response,
+            );
+        }
+
+        if let Some(headers) = &ctx.rate_limit {
+            headers.apply(upstream_response)?;
+        }
+
+        if ctx
+            .upstream()
+            .and_then(|upstream_ctx| upstream_ctx.cache.as_ref())
+            .is_some_and(|config| config.debug_headers)
+        {
+            cache::add_debug_headers(
+                upstream_response,
+                session.cache.phase(),
+                session.cache.maybe_cache_meta(),
+                SystemTime::now(),
+            )?;
+        }
+
+        if let Some(trace) = &mut ctx.trace {
+            trace.record("response", upstream_response.status.as_str().to_string());
+            upstream_response.insert_header(trace::TRACE_HEADER, trace.summary(&ctx.request_id))?;
+        }
+
+        Ok(())
+    }
+
+    /// Sends the filtered response body once it is complete, paced by the
+    /// `throttle` filters of the route.
+    fn response_body_filter(
+        &self,
+        session: &mut Session,
+        body: &mut Option<Bytes>,
+        end_of_stream: bool,
+        ctx: &mut Self::CTX,
+    ) -> Result<Option<Duration>>
+    where
+        Self::CTX: Send + Sync,
+    {
+        panic_guard::catch_sync(|| {
+            self.handle_response_body_filter(session, body, end_of_stream, ctx)
+        })
+        .unwrap_or_else(|p| Err(panic_report("response_body_filter", p, session, ctx)))
+        .map(|()| ctx.pace(body.as_ref().map_or(0, Bytes::len)))
+    }
+
+    /// Turns on the cache lookup for routes with a `cache` directive.
+    fn request_cache_filter(&self, session: &mut Session, ctx: &mut Self::CTX) -> Result<()> {
+        if let Some(config) = ctx
+            .upstream()
+            .filter(|upstream_ctx| !raw_bodies(upstream_ctx, ctx))
+            .and_then(|upstream_ctx| upstream_ctx.cache.as_ref())
+        {
+            cache::enable(session, config);
+        }
+
+        Ok(())
+    }
+
+    fn cache_key_callback(&self, session: &Session, _ctx: &mut Self::CTX) -> Result<CacheKey> {
+        Ok(cache::key(session.server_addr(), session.req_header()))
+    }
+
+    /// Decides whether the upstream response is stored, using the route's `ttl`.
+    fn response_cache_filter(
+        &self,
+        session: &Session,
+        resp: &ResponseHeader,
+        ctx: &mut Self::CTX,
+    ) -> Result<RespCacheable> {
+        match ctx
+            .upstream()
+            .and_then(|upstream_ctx| upstream_ctx.cache.as_ref())
+        {
+            Some(config) => Ok(cache::cacheability(config, session.req_header(), resp)),
+            None => Ok(RespCacheable::Uncacheable(NoCacheReason::NeverEnabled)),
+        }
+    }
+
+    fn cache_vary_filter(
+        &self,
+        meta: &CacheMeta,
+        _ctx: &mut Self::CTX,
+        req: &RequestHeader,
+    ) -> Option<HashBinary> {
+        cache::variance(meta, req)
+    }
+
+    /// Reads the status of gRPC calls, which comes with the trailers.
+    async fn response_trailer_filter(
+        &self,
+        _session: &mut Session,
+        upstream_trailers: &mut HeaderMap,
+        ctx: &mut Self::CTX,
+    ) -> Result<Option<Bytes>>
+    where
+        Self::CTX: Send + Sync,
+    {
+        if ctx.upstream().is_some_and(|upstream_ctx| upstream_ctx.grpc) {
+            ctx.grpc_status = grpc::status(upstream_trailers).or(ctx.grpc_status);
+        }
+
+        // The trailers go on as they are.
+        Ok(None)
+    }
+
+    /// Writes the access log line, once the response is done.
+    async fn logging(&self, session: &mut Session, _e: Option<&pingora::Error>, ctx: &mut Self::CTX)
+    where
+        Self::CTX: Send + Sync,
+    {
+        if let Some(code) = ctx.grpc_status {
+            if let Some(upstream_ctx) = ctx.upstream() {
+                upstream_ctx.metrics.record_grpc_status(code);
+            }
+        }
+
+        if let Some(access_log) = &self.access_log {
+            access_log.record(&Entry::capture(session, ctx));
+        }
+
+        if let Some(trace) = ctx.trace.take() {
+            let request = session.req_header();
+            let status = session.response_written().map(|h| h.status.as_u16());
+            trace.finish(
+                &ctx.request_id,
+                &format!("{} {}", request.method, request.uri),
+                status,
+            );
+        }
+    }
+}
+
+/// Whether the bodies of the request and its response must pass through as they
+/// are: an upgraded connection carries a byte stream, and gRPC messages are
+/// framed by their length.
+fn raw_bodies(upstream_ctx: &UpstreamContext, ctx: &MotyaContext) -> bool {
+    ctx.upgrade || upstream_ctx.grpc
+}
+
+/// Whether response bodies of the route go through its body filters. Event
+/// streams and raw bodies are never held back.
+fn filters_response_bodies(upstream_ctx: &UpstreamContext, ctx: &MotyaContext) -> bool {
+    upstream_ctx.sse.is_none()
+        && !raw_bodies(upstream_ctx, ctx)
+        && response_body::has_filters(&upstream_ctx.chains)
+}
+
+/// The `retry` directive of the request's route, if it has one.
+fn retry_policy(
+    router: &UpstreamRouter<UpstreamContext>,
+    route: Option<usize>,
+) -> Option<&RetryPolicy> {
+    router
+        .get_upstream(route)
+        .and_then(|upstream_ctx| upstream_ctx.retry.as_ref())
+}
+
+/// Builds the error report for a panic caught in one of the proxy phases.
+///
+/// The phase future has been dropped by now, so the session can be borrowed again
+/// to find out which route and upstream the request was headed to.
+fn panic_report(
+    phase: &'static str,
+    caught: CaughtPanic,
+    session: &Session,
+    ctx: &MotyaContext,
+) -> BError {
+    let path = session.req_header().uri.path();
+    let upstream_ctx = ctx.upstream();
+
+    caught.into_error(RequestReport {
+        phase,
+        request_id: &ctx.request_id,
+        path,
+        route: upstream_ctx.map(|u| u.get_prefix_path().path()),
+        upstream: upstream_ctx.map(|u| &u.upstream),
+    })
+}
+
+impl MotyaProxyService {
+    async fn handle_request_filter(
+        &self,
+        session: &mut Session,
+        ctx: &mut MotyaContext,
+    ) -> Result<bool> {
         let router = ctx.router.clone();
-        let path = session.req_header().uri.path();
+        let req = session.req_header();
+        let path = req.uri.path();
+        ctx.route = router.route(&req.method, path, req.uri.query());
+
+        // ACME validates over plain HTTP on port 80, whatever the routes there say.
+        if let Some(key_authorization) = acme::challenge_response(path) {
+            let response = SimpleResponse {
+                http_code: http::StatusCode::OK,
+                response_body: key_authorization,
+                prefix_path: PathAndQuery::from_static(acme::CHALLENGE_PREFIX),
+            };
+            return response.request_filter(session, ctx).await;
+        }
+
+        let maintenance = &self.state.maintenance;
+        if maintenance.holds(path) {
+            maintenance.respond(session, ctx).await?;
+            return Ok(true);
+        }
+
+        if let Some(limits) = &self.limits {
+            match limits.try_request() {
+                Some(permit) => ctx._inflight_permit = Some(permit),
+                None => {
+                    limits::shed_request(session, ctx).await?;
+                    return Ok(true);
+                }
+            }
+        }
+
+        if let Some(upstream_ctx) = router.get_upstream(ctx.route) {
+            upstream_ctx.metrics.record_request();
+
+            if upstream_ctx.debug_trace {
+                let mut trace = Trace::new(ctx.started);
+                trace.record(
+                    "route",
+                    format!(
+                        "{} ({:?})",
+                        upstream_ctx.get_prefix_path(),
+                        upstream_ctx.get_route_type()
+                    ),
+                );
+                ctx.trace = Some(trace);
+            }
+
+            if upgrade::is_upgrade(session.req_header()) {
+                if upstream_ctx.allow_upgrades {
+                    ctx.upgrade = true;
+                } else {
+                    upgrade::strip(session.req_header_mut());
+                }
+            }
+
+            if let Some(config) = &upstream_ctx.timeout {
+                timeout::configure_downstream(session, config);
+            }
 
-        if let Some(upstream_ctx) = router.get_upstream_by_path(path) {
             // let multis = self
             //     .rate_limiters
             //     .request_filter_stage_multi
@@ -170,16 +811,54 @@ impl ProxyHttp for MotyaProxyService {
             // }
 
             for chain in &upstream_ctx.chains {
-                for filter in &chain.actions {
-                    match filter.request_filter(session, ctx).await {
-                        // If Ok true: we're done handling this request
-                        o @ Ok(true) => return o,
-                        // If Err: we return that
-                        e @ Err(_) => return e,
+                let start = Instant::now();
+                for (filter, name) in chain.actions.iter().zip(&chain.action_names) {
+                    let outcome = filter.request_filter(session, ctx).await;
+
+                    if let Some(trace) = &mut ctx.trace {
+                        let decision = match &outcome {
+                            Ok(true) => "respond",
+                            Ok(false) => "continue",
+                            Err(_) => "error",
+                        };
+                        trace.record("filter", format!("{}/{name} {decision}", chain.name));
+                    }
+
+                    match outcome {
                         // If Ok(false), we move on to the next filter
                         Ok(false) => {}
+                        // If Ok true: we're done handling this request
+                        // If Err: we return that
+                        done => {
+                            let outcome = Outcome::of_request_filter(&done);
+                            chain.timings.record(outcome, start.elapsed());
+                            return done;
+                        }
                     }
                 }
+                if !chain.actions.is_empty() {
+                    chain.timings.record(Outcome::Pass, start.elapsed());
+                }
+            }
+
+            if let Some(compression) = upstream_ctx
+                .compression
+                .as_ref()
+                .filter(|_| !raw_bodies(upstream_ctx, ctx))
+            {
+                compression::enable(session, compression);
+            }
+
+            if let Some(mirror) = upstream_ctx
+                .mirror
+                .as_ref()
+                .filter(|_| !raw_bodies(upstream_ctx, ctx))
+            {
+                match mirror.start(session.req_header()) {
+                    // No body phase will follow to complete the copy.
+                    Some(copy) if !request_body::has_body(session.req_header()) => copy.send(),
+                    copy => ctx.mirror = copy,
+                }
             }
 
             if let UpstreamConfig::Static(response) = upstream_ctx.upstream.clone() {
@@ -193,86 +872,327 @@ impl ProxyHttp for MotyaProxyService {
         Ok(false)
     }
 
-    /// Handle the "upstream peer" phase, where we pick which upstream to proxy to.
-    async fn upstream_peer(
+    fn handle_upstream_peer(
         &self,
         session: &mut Session,
-        ctx: &mut Self::CTX,
+        ctx: &mut MotyaContext,
     ) -> Result<Box<HttpPeer>> {
         static DEFAULT: PathAndQuery = PathAndQuery::from_static("/");
         dbg!(&session.req_header().uri);
 
+        let mut session_info = SessionInfo {
+            headers: session.req_header(),
+            client_addr: session.client_addr(),
+            path: session
+                .req_header()
+                .uri
+                .path_and_query()
+                .unwrap_or(&DEFAULT),
+        };
+
+        if let Some(trace) = &mut ctx.trace {
+            if let Some(balancer) = ctx
+                .router
+                .get_upstream(ctx.route)
+                .and_then(|upstream_ctx| upstream_ctx.balancer.as_ref())
+            {
+                trace.record_key(balancer.key_bytes(&session_info).as_deref());
+            }
+        }
+
         match ctx.router.pick_peer(
-            &mut ContextInfo {},
-            &mut SessionInfo {
-                headers: session.req_header(),
-                client_addr: session.client_addr(),
-                path: session
-                    .req_header()
-                    .uri
-                    .path_and_query()
-                    .unwrap_or(&DEFAULT),
+            ctx.route,
+            &mut ContextInfo {
+                tried_peers: ctx.retry.tried(),
             },
+            &mut session_info,
         ) {
-            Ok(Some(peer)) => Ok(Box::new(peer)),
+            Ok(Some(mut peer)) => {
+                if let Some(upstream_ctx) = ctx.router.get_upstream(ctx.route) {
+                    if let Some(addr) = peer._address.as_inet() {
+                        upstream_ctx.metrics.record_selection(addr);
+                    }
+                    if let Some(sse) = &upstream_ctx.sse {
+                        sse::configure_peer(&mut peer, sse);
+                    }
+                    if upstream_ctx.grpc {
+                        grpc::configure_peer(&mut peer);
+                    }
+                    if let Some(tls) = &upstream_ctx.upstream_tls {
+                        tls.configure_peer(&mut peer);
+                    }
+                    if let Some(h2) = &upstream_ctx.h2 {
+                        upstream_h2::configure_peer(&mut peer, h2);
+                    }
+                    if let Some(remaining) = upstream_ctx
+                        .timeout
+                        .as_ref()
+                        .and_then(|config| timeout::remaining(config, ctx.started))
+                    {
+                        if remaining.is_zero() {
+                            return Err(Error::new(ErrorType::HTTPStatus(504)));
+                        }
+                        timeout::configure_peer(&mut peer, remaining);
+                    }
+                    if upstream_ctx.retry.is_some() {
+                        ctx.retry.record_peer(peer._address.clone());
+                    }
+                    // A retry drops the guard of the backend it moves away from.
+                    ctx.backend_load = upstream_ctx
+                        .balancer
+                        .as_ref()
+                        .and_then(|balancer| balancer.start_request(&peer._address));
+                }
+
+                if let Some(trace) = &mut ctx.trace {
+                    trace.record("peer", peer._address.to_string());
+                }
+
+                ctx.upstream_started = Some(Instant::now());
+                ctx.upstream_addr = Some(peer._address.clone());
+                Ok(Box::new(peer))
+            }
             Ok(None) => Err(pingora::Error::new(pingora::ErrorType::HTTPStatus(404))),
             Err(err) => {
-                let id = Uuid::new_v4();
-                tracing::error!("[{id}] error on pick_peer. err: {err}");
+                let id = ctx.request_id;
+                tracing::error!(
+                    config_version = %config_version::describe_active(),
+                    "[{id}] error on pick_peer. err: {err}"
+                );
 
                 Err(pingora::Error::new(pingora::ErrorType::HTTPStatus(500)))
             }
         }
     }
 
-    /// Handle the "upstream request filter" phase, where we can choose to make
-    /// modifications to the request, prior to it being passed along to the
-    /// upstream.
-    ///
-    /// We can also *reject* requests here, though in the future we might do that
-    /// via the `request_filter` stage, as that rejection can be done prior to
-    /// paying any potential cost `upstream_peer` may incur.
-    async fn upstream_request_filter(
+    async fn handle_upstream_request_filter(
         &self,
         session: &mut Session,
         header: &mut RequestHeader,
-        ctx: &mut Self::CTX,
+        ctx: &mut MotyaContext,
     ) -> Result<()> {
         let router = ctx.router.clone();
-        let path = session.req_header().uri.path();
 
-        if let Some(upstream_ctx) = router.get_upstream_by_path(path) {
+        if let Some(upstream_ctx) = router.get_upstream(ctx.route) {
+            if let Some(rewrite) = &upstream_ctx.rewrite {
+                let rewritten = rewrite::apply(rewrite, header)?;
+                if let (Some(trace), Some(path)) = (&mut ctx.trace, rewritten) {
+                    trace.record("rewrite", path);
+                }
+            }
+
             for chain in &upstream_ctx.chains {
                 for filter in &chain.req_mods {
                     filter.upstream_request_filter(session, header, ctx).await?;
                 }
             }
+
+            if upstream_ctx.sse.is_some() {
+                sse::prepare_request(header);
+            }
+
+            if filters_response_bodies(upstream_ctx, ctx) {
+                response_body::prepare_request(&upstream_ctx.chains, header);
+            }
+
+            // Body filters would have to hold back a body that is a stream.
+            let limit = request_body::limit(&upstream_ctx.chains)
+                .filter(|_| !raw_bodies(upstream_ctx, ctx));
+            if let Some(limit) = limit {
+                if request_body::prepare_request(header)? {
+                    ctx.request_body = Some(BodyBuffer::new(limit));
+                } else {
+                    // No body phase will follow, the filters see an empty body now.
+                    let mut empty = Bytes::new();
+                    for chain in &upstream_ctx.chains {
+                        for filter in &chain.body_mods {
+                            filter.request_body_filter(session, &mut empty, ctx)?;
+                        }
+                    }
+                }
+            }
         }
 
         Ok(())
     }
 
-    /// Handle the "upstream response filter" phase, where we can choose to make
-    /// modifications to the response, prior to it being passed along downstream
-    ///
-    /// We may want to also support `upstream_response` stage, as that may interact
-    /// with cache differently.
-    fn upstream_response_filter(
+    fn handle_request_body_filter(
+        &self,
+        session: &mut Session,
+        body: &mut Option<Bytes>,
+        end_of_stream: bool,
+        ctx: &mut MotyaContext,
+    ) -> Result<()> {
+        // The copy gets the body as the client sent it, before any body filter.
+        if let Some(mut copy) = ctx.mirror.take() {
+            if body.as_ref().is_none_or(|chunk| copy.push(chunk)) {
+                if end_of_stream {
+                    copy.send();
+                } else {
+                    ctx.mirror = Some(copy);
+                }
+            }
+        }
+
+        let router = ctx.router.clone();
+        let Some(upstream_ctx) = router.get_upstream(ctx.route) else {
+            return Ok(());
+        };
+        // Set up with the upstream request, for routes with body filters.
+        let Some(buffer) = ctx.request_body.as_mut() else {
+            return Ok(());
+        };
+
+        if let Some(chunk) = body.take() {
+            buffer.push(&chunk)?;
+        }
+        if !end_of_stream {
+            return Ok(());
+        }
+
+        let mut full_body = ctx
+            .request_body
+            .take()
+            .map(BodyBuffer::into_bytes)
+            .unwrap_or_default();
+        for chain in &upstream_ctx.chains {
+            for filter in &chain.body_mods {
+                filter.request_body_filter(session, &mut full_body, ctx)?;
+            }
+        }
+
+        *body = Some(full_body);
+        Ok(())
+    }
+
+    fn handle_response_body_filter(
+        &self,
+        session: &mut Session,
+        body: &mut Option<Bytes>,
+        end_of_stream: bool,
+        ctx: &mut MotyaContext,
+    ) -> Result<()> {
+        // Set up with the response head, for responses the body filters want.
+        let Some(buffer) = ctx.response_body.as_mut() else {
+            return Ok(());
+        };
+
+        if let Some(chunk) = body.take() {
+            if !buffer.push(&chunk) {
+                // Too large for the filters: what was held back goes out as it
+                // is, and so does the rest.
+                let held = ctx
+                    .response_body
+                    .take()
+                    .map(ResponseBodyBuffer::into_bytes)
+                    .unwrap_or_default();
+                let mut unchanged = BytesMut::with_capacity(held.len() + chunk.len());
+                unchanged.extend_from_slice(&held);
+                unchanged.extend_from_slice(&chunk);
+                *body = Some(unchanged.freeze());
+                return Ok(());
+            }
+        }
+        if !end_of_stream {
+            return Ok(());
+        }
+
+        let Some(buffer) = ctx.response_body.take() else {
+            return Ok(());
+        };
+        let content_type = buffer.content_type().map(str::to_string);
+        let encoding = buffer.encoding();
+        let mut full_body = match buffer.into_decoded() {
+            Ok(decoded) => decoded,
+            Err(unchanged) => {
+                *body = Some(unchanged);
+                return Ok(());
+            }
+        };
+
+        let router = ctx.router.clone();
+        if let Some(upstream_ctx) = router.get_upstream(ctx.route) {
+            for chain in &upstream_ctx.chains {
+                for filter in &chain.res_body_mods {
+                    if filter.applies_to(content_type.as_deref()) {
+                        filter.response_body_filter(session, &mut full_body, ctx)?;
+                    }
+                }
+            }
+        }
+
+        *body = Some(match encoding {
+            Some(encoding) => encoding.encode(&full_body),
+            None => full_body,
+        });
+        Ok(())
+    }
+
+    fn handle_upstream_response_filter(
         &self,
         session: &mut Session,
         upstream_response: &mut ResponseHeader,
-        ctx: &mut Self::CTX,
+        ctx: &mut MotyaContext,
     ) -> Result<()> {
         let router = ctx.router.clone();
-        let path = session.req_header().uri.path();
 
-        if let Some(upstream_ctx) = router.get_upstream_by_path(path) {
+        if let Some(upstream_ctx) = router.get_upstream(ctx.route) {
+            upstream_ctx.metrics.record_response(
+                upstream_response.status,
+                ctx.upstream_started.map(|started| started.elapsed()),
+            );
+            if let Some(load) = &ctx.backend_load {
+                load.record_response();
+            }
+            if upstream_response.status.is_server_error() {
+                if let (Some(balancer), Some(addr)) = (&upstream_ctx.balancer, &ctx.upstream_addr) {
+                    balancer.record_failure(addr);
+                }
+            }
+
+            if let Some(policy) = &upstream_ctx.retry {
+                let status = upstream_response.status.as_u16();
+                let method = &session.req_header().method;
+                // Nothing was sent downstream yet, so the response can be dropped
+                // for another attempt.
+                if ctx
+                    .retry
+                    .try_retry_pending(policy, Failure::Status(status), method)
+                {
+                    let mut e = Error::explain(
+                        ErrorType::HTTPStatus(status),
+                        "upstream server error, retrying",
+                    );
+                    e.set_retry(true);
+                    return Err(e);
+                }
+            }
+
             for chain in &upstream_ctx.chains {
                 for filter in &chain.res_mods {
                     filter.upstream_response_filter(session, upstream_response, ctx);
                 }
             }
+
+            if let Some(compression) = &upstream_ctx.compression {
+                compression::apply_exclusions(session, upstream_response, compression);
+            }
+
+            if upstream_ctx.sse.is_some() && !raw_bodies(upstream_ctx, ctx) {
+                sse::prepare_response(upstream_response)?;
+            }
+
+            // A call that fails before its first message has no trailers.
+            if upstream_ctx.grpc {
+                ctx.grpc_status = grpc::status(&upstream_response.headers);
+            }
+        }
+
+        if let Some(version) = config_version::header_value() {
+            upstream_response
+                .insert_header(config_version::CONFIG_VERSION_HEADER, version.to_string())?;
         }
+
         Ok(())
     }
 }
@@ -0,0 +1,196 @@
+//! Isolation of per-request panics.
+//!
+//! A panic inside a filter, a WASM invoker or the router must not take the worker
+//! down with it. Every `ProxyHttp` phase of [`MotyaProxyService`](super::MotyaProxyService)
+//! runs through [`catch_async`] or [`catch_sync`], and a caught panic is turned into
+//! a `500` for the current request by [`CaughtPanic::into_error`].
+//!
+//! Session data is borrowed, so the phases can't be moved into a separate tokio
+//! task; the future is polled in place under `catch_unwind` instead, which gives the
+//! same isolation for the request. Once the future is dropped the session can be
+//! borrowed again to describe what was being processed.
+
+use std::{
+    any::Any,
+    backtrace::Backtrace,
+    cell::RefCell,
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    sync::Once,
+};
+
+use futures_util::FutureExt;
+use motya_config::common_types::connectors::UpstreamConfig;
+use pingora::{BError, Error, ErrorType};
+use uuid::Uuid;
+
+use super::config_version;
+
+thread_local! {
+    static LAST_BACKTRACE: RefCell<Option<Backtrace>> = const { RefCell::new(None) };
+}
+
+static INSTALL_HOOK: Once = Once::new();
+
+/// Installs a panic hook that keeps the backtrace of the last panic on the
+/// current thread, so that it can be attached to the error report.
+///
+/// The previously installed hook is still called.
+pub fn install_hook() {
+    INSTALL_HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            LAST_BACKTRACE.with(|bt| *bt.borrow_mut() = Some(Backtrace::force_capture()));
+            previous(info);
+        }));
+    });
+}
+
+#[derive(Debug)]
+pub struct CaughtPanic {
+    message: String,
+    backtrace: Option<Backtrace>,
+}
+
+/// What was being processed when the panic happened.
+#[derive(Debug)]
+pub struct RequestReport<'a> {
+    pub phase: &'static str,
+    pub request_id: &'a Uuid,
+    pub path: &'a str,
+    pub route: Option<&'a str>,
+    pub upstream: Option<&'a UpstreamConfig>,
+}
+
+/// Polls an async phase, catching a panic raised by any of its polls.
+pub async fn catch_async<F: Future>(fut: F) -> Result<F::Output, CaughtPanic> {
+    AssertUnwindSafe(fut)
+        .catch_unwind()
+        .await
+        .map_err(CaughtPanic::from_payload)
+}
+
+/// Runs a sync phase, catching a panic.
+pub fn catch_sync<F: FnOnce() -> R, R>(func: F) -> Result<R, CaughtPanic> {
+    panic::catch_unwind(AssertUnwindSafe(func)).map_err(CaughtPanic::from_payload)
+}
+
+impl CaughtPanic {
+    fn from_payload(payload: Box<dyn Any + Send>) -> Self {
+        Self {
+            message: panic_message(payload.as_ref()).to_string(),
+            backtrace: LAST_BACKTRACE.with(|bt| bt.borrow_mut().take()),
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Logs the panic along with the request context and converts it into a `500`.
+    pub fn into_error(self, report: RequestReport<'_>) -> BError {
+        let backtrace = self
+            .backtrace
+            .map(|bt| bt.to_string())
+            .unwrap_or_else(|| "<backtrace unavailable, panic hook is not installed>".into());
+
+        tracing::error!(
+            request_id = %report.request_id,
+            config_version = %config_version::describe_active(),
+            phase = report.phase,
+            path = report.path,
+            route = report.route.unwrap_or("<unmatched>"),
+            upstream = report.upstream.map(describe_upstream).as_deref().unwrap_or("<none>"),
+            "[{}] panic while processing request: {}\n{backtrace}",
+            report.request_id,
+            self.message
+        );
+
+        Error::explain(
+            ErrorType::HTTPStatus(500),
+            format!("request {} failed: internal panic", report.request_id),
+        )
+    }
+}
+
+pub fn describe_upstream(upstream: &UpstreamConfig) -> String {
+    match upstream {
+        UpstreamConfig::Service(peer) => peer.peer_address.to_string(),
+        UpstreamConfig::Static(response) => format!("static({})", response.http_code.as_u16()),
+        UpstreamConfig::MultiServer(multi) => multi
+            .servers
+            .iter()
+            .map(|s| s.address.to_string())
+            .collect::<Vec<_>>()
+            .join(","),
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(s) = payload.downcast_ref::<&'static str>() {
+        s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.as_str()
+    } else {
+        "<non-string panic payload>"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(id: &Uuid) -> RequestReport<'_> {
+        RequestReport {
+            phase: "test",
+            request_id: id,
+            path: "/api/users",
+            route: Some("/api"),
+            upstream: None,
+        }
+    }
+
+    #[test]
+    fn test_sync_panic_becomes_500() {
+        install_hook();
+        let id = Uuid::new_v4();
+
+        let caught = catch_sync(|| panic!("boom")).unwrap_err();
+        assert_eq!(caught.message(), "boom");
+        assert!(caught.backtrace.is_some());
+
+        let err = caught.into_error(report(&id));
+        assert_eq!(err.etype(), &ErrorType::HTTPStatus(500));
+        assert!(err.to_string().contains(&id.to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_async_panic_is_caught_across_polls() {
+        install_hook();
+
+        let caught = catch_async(async {
+            tokio::task::yield_now().await;
+            panic!("boom {}", 42)
+        })
+        .await
+        .unwrap_err();
+
+        assert_eq!(caught.message(), "boom 42");
+    }
+
+    #[tokio::test]
+    async fn test_results_pass_through() {
+        let res = catch_async(async { 7 }).await;
+        assert_eq!(res.unwrap(), 7);
+
+        let res = catch_sync(|| "ok");
+        assert_eq!(res.unwrap(), "ok");
+    }
+
+    #[test]
+    fn test_panic_message_extraction() {
+        assert_eq!(panic_message(&"static"), "static");
+        assert_eq!(panic_message(&String::from("owned")), "owned");
+        assert_eq!(panic_message(&42u32), "<non-string panic payload>");
+    }
+}
@@ -1,3 +1,6 @@
+use bytes::Bytes;
+use http::StatusCode;
+use pingora_http::ResponseHeader;
 use wasmtime::component::{Linker, LinkerInstance};
 use wasmtime_wasi::WasiView;
 use wasmtime_wasi_io::IoView;
@@ -6,6 +9,18 @@ use crate::proxy::plugins::{module::TraitModuleState, store::ModuleState};
 
 pub trait HostFunctions {
     fn get_path(&self) -> String;
+
+    /// The response being filtered; `None` outside of `on-response`.
+    fn response_header(&mut self) -> Option<&mut ResponseHeader>;
+
+    /// The buffered request body; `None` unless the plugin sets `request-body`.
+    fn request_body(&mut self) -> Option<&mut Bytes>;
+}
+
+fn response_of<T: HostFunctions>(state: &mut T) -> wasmtime::Result<&mut ResponseHeader> {
+    state
+        .response_header()
+        .ok_or_else(|| wasmtime::Error::msg("response functions are only available in on-response"))
 }
 
 pub struct PluginHost;
@@ -18,6 +33,8 @@ impl PluginHost {
 
         Self::register_logger(linker.root().instance("motya:proxy/logger")?)?;
         Self::register_context(linker.root().instance("motya:proxy/context")?)?;
+        Self::register_response(linker.root().instance("motya:proxy/response")?)?;
+        Self::register_request_body(linker.root().instance("motya:proxy/request-body")?)?;
 
         Ok(())
     }
@@ -32,6 +49,63 @@ impl PluginHost {
         Ok(())
     }
 
+    fn register_response<T: TraitModuleState>(
+        mut response: LinkerInstance<'_, T>,
+    ) -> wasmtime::Result<()> {
+        response.func_wrap("get-status", |mut ctx, (): ()| {
+            Ok((response_of(ctx.data_mut())?.status.as_u16(),))
+        })?;
+
+        response.func_wrap("set-status", |mut ctx, (status,): (u16,)| {
+            let header = response_of(ctx.data_mut())?;
+            let result = StatusCode::from_u16(status)
+                .map_err(|_| format!("{status} is not a valid status code"))
+                .and_then(|status| header.set_status(status).map_err(|e| e.to_string()));
+            Ok((result,))
+        })?;
+
+        response.func_wrap("get-header", |mut ctx, (name,): (String,)| {
+            let header = response_of(ctx.data_mut())?;
+            let value = header
+                .headers
+                .get(name.as_str())
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string);
+            Ok((value,))
+        })?;
+
+        response.func_wrap("set-header", |mut ctx, (name, value): (String, String)| {
+            let header = response_of(ctx.data_mut())?;
+            let result = header.insert_header(name, value).map_err(|e| e.to_string());
+            Ok((result,))
+        })?;
+
+        response.func_wrap("remove-header", |mut ctx, (name,): (String,)| {
+            response_of(ctx.data_mut())?.remove_header(name.as_str());
+            Ok(())
+        })?;
+
+        Ok(())
+    }
+
+    fn register_request_body<T: TraitModuleState>(
+        mut body: LinkerInstance<'_, T>,
+    ) -> wasmtime::Result<()> {
+        body.func_wrap("get-body", |mut ctx, (): ()| {
+            Ok((ctx.data_mut().request_body().map(|body| body.to_vec()),))
+        })?;
+
+        body.func_wrap("set-body", |mut ctx, (new_body,): (Vec<u8>,)| {
+            let body = ctx.data_mut().request_body().ok_or_else(|| {
+                wasmtime::Error::msg("set-body needs a plugin with 'request-body' set")
+            })?;
+            *body = Bytes::from(new_body);
+            Ok(())
+        })?;
+
+        Ok(())
+    }
+
     fn register_logger<T: WasiView + IoView>(
         mut logger: LinkerInstance<'_, T>,
     ) -> wasmtime::Result<()> {
@@ -63,4 +137,15 @@ impl HostFunctions for ModuleState {
             panic!("invariant violated: session was null on filter phase");
         }
     }
+
+    fn response_header(&mut self) -> Option<&mut ResponseHeader> {
+        let mut header = self.session.as_ref()?.res_header?;
+        // The header outlives the filter call that set it.
+        Some(unsafe { header.as_mut() })
+    }
+
+    fn request_body(&mut self) -> Option<&mut Bytes> {
+        let mut body = self.session.as_ref()?.req_body?;
+        Some(unsafe { body.as_mut() })
+    }
 }
@@ -1,12 +1,28 @@
-use std::time::Duration;
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
+use fqdn::FQDN;
 use http::StatusCode;
 use miette::{miette, Context, IntoDiagnostic, Result};
-use motya_config::common_types::definitions::PluginSource;
+use motya_config::common_types::definitions::{PluginChecksum, PluginSource};
+use pingora::tls::sha;
 use reqwest::Client;
 
 pub struct PluginLoader;
 
+/// Lower-case hex SHA-256 of `bytes`.
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    sha::sha256(bytes)
+        .iter()
+        .fold(String::with_capacity(64), |mut hex, b| {
+            let _ = write!(hex, "{b:02x}");
+            hex
+        })
+}
+
 impl PluginLoader {
     fn client() -> Result<Client> {
         Client::builder()
@@ -85,4 +101,63 @@ impl PluginLoader {
             }
         }
     }
+
+    /// Where modules fetched from a URL are kept, by checksum, so that restarts
+    /// do not download them again.
+    pub fn cache_dir() -> PathBuf {
+        std::env::temp_dir().join("motya-plugins")
+    }
+
+    /// Fetches the module and checks it against `checksum`. Modules from a URL are
+    /// served from `cache_dir` when a copy with the same digest is there.
+    pub async fn fetch_verified(
+        name: &FQDN,
+        source: &PluginSource,
+        checksum: &PluginChecksum,
+        cache_dir: &Path,
+    ) -> Result<Vec<u8>> {
+        let cached = cache_dir.join(format!("{}.wasm", checksum.sha256));
+        let from_url = matches!(source, PluginSource::Url(_));
+
+        if from_url {
+            if let Ok(bytes) = tokio::fs::read(&cached).await {
+                if sha256_hex(&bytes) == checksum.sha256 {
+                    tracing::debug!("Plugin '{}' found in cache {:?}", name, cached);
+                    return Ok(bytes);
+                }
+            }
+        }
+
+        Self::check_availability(source)
+            .await
+            .wrap_err_with(|| format!("Availability check failed for plugin '{}'", name))?;
+
+        let bytes = Self::fetch_bytes(source)
+            .await
+            .wrap_err_with(|| format!("Download failed for plugin '{}'", name))?;
+
+        let actual = sha256_hex(&bytes);
+        if actual != checksum.sha256 {
+            return Err(checksum.mismatch(name, &actual).into());
+        }
+
+        if from_url {
+            if let Err(err) = Self::store_cached(&cached, &bytes).await {
+                tracing::warn!("Cannot cache plugin '{}' at {:?}: {err}", name, cached);
+            }
+        }
+
+        Ok(bytes)
+    }
+
+    async fn store_cached(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+
+        // Another instance may be reading the cache, so the file appears whole or not at all.
+        let partial = path.with_extension(format!("{}.partial", std::process::id()));
+        tokio::fs::write(&partial, bytes).await?;
+        tokio::fs::rename(&partial, path).await
+    }
 }
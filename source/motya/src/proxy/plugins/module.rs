@@ -1,6 +1,7 @@
-use std::collections::BTreeMap;
+use std::{collections::BTreeMap, ptr::NonNull};
 
 use async_trait::async_trait;
+use bytes::Bytes;
 use miette::miette;
 use motya_config::common_types::value::Value;
 use pingora_http::{RequestHeader, ResponseHeader};
@@ -13,7 +14,7 @@ use wasmtime_wasi::WasiView;
 use wasmtime_wasi_io::IoView;
 
 use crate::proxy::{
-    filters::types::{RequestFilterMod, RequestModifyMod, ResponseModifyMod},
+    filters::types::{RequestBodyMod, RequestFilterMod, RequestModifyMod, ResponseModifyMod},
     plugins::{
         g::{self, exports::motya::proxy::filter_factory::GuestFilterInstance},
         host::HostFunctions,
@@ -45,6 +46,10 @@ impl<T: TraitModuleState> WasmModule<T> {
         Self { artifact, linker }
     }
 
+    pub fn request_body_limit(&self) -> Option<usize> {
+        self.artifact.request_body_limit
+    }
+
     pub fn pick(
         &self,
         name: &str,
@@ -124,6 +129,10 @@ impl<T: TraitModuleState> WasmInvoker<T> {
         }
     }
 
+    pub fn request_body_limit(&self) -> Option<usize> {
+        self.module.request_body_limit()
+    }
+
     pub fn get_filter_type(&self) -> miette::Result<FilterType> {
         let state = T::default();
 
@@ -168,7 +177,6 @@ impl<T: TraitModuleState> WasmInvoker<T> {
         self.execute(state, |f, s, r| f.call_filter(s, r))
     }
 
-    #[allow(unused)]
     fn on_response(&self, state: T) -> pingora::Result<()> {
         self.execute(state, |f, s, r| f.call_on_response(s, r))
     }
@@ -187,7 +195,8 @@ impl RequestFilterMod for WasmInvoker {
     ) -> pingora::Result<bool> {
         let session_state = SessionCtx {
             req_header: None,
-            _res_headers: None,
+            res_header: None,
+            req_body: None,
             _session: session.into(),
         };
 
@@ -210,14 +219,20 @@ impl ResponseModifyMod for WasmInvoker {
     ) {
         let session_state = SessionCtx {
             req_header: None,
-            _res_headers: Some(header.into()),
+            res_header: Some(header.into()),
+            req_body: None,
             _session: session.into(),
         };
 
-        let _state = ModuleState {
+        let state = ModuleState {
             session: Some(session_state),
             ..Default::default()
         };
+
+        // The response goes on unchanged by this filter if it fails.
+        if let Err(e) = self.on_response(state) {
+            tracing::error!("WASM filter '{}' failed on response: {e}", self.filter_name);
+        }
     }
 }
 
@@ -231,7 +246,37 @@ impl RequestModifyMod for WasmInvoker {
     ) -> pingora::Result<()> {
         let session_state = SessionCtx {
             req_header: Some(header.into()),
-            _res_headers: None,
+            res_header: None,
+            req_body: None,
+            _session: session.into(),
+        };
+
+        let state = ModuleState {
+            session: Some(session_state),
+            ..Default::default()
+        };
+
+        self.on_request(state)
+    }
+}
+
+impl RequestBodyMod for WasmInvoker {
+    fn max_body_size(&self) -> usize {
+        self.request_body_limit().unwrap_or_default()
+    }
+
+    fn request_body_filter(
+        &self,
+        session: &mut Session,
+        body: &mut Bytes,
+        _: &mut MotyaContext,
+    ) -> pingora::Result<()> {
+        // The upstream request has been sent by now, `get-path` reads the client's.
+        let req_header = NonNull::from(session.req_header());
+        let session_state = SessionCtx {
+            req_header: Some(req_header),
+            res_header: None,
+            req_body: Some(body.into()),
             _session: session.into(),
         };
 
@@ -291,6 +336,14 @@ mod tests {
         fn get_path(&self) -> String {
             "/hubabuba".to_string()
         }
+
+        fn response_header(&mut self) -> Option<&mut ResponseHeader> {
+            None
+        }
+
+        fn request_body(&mut self) -> Option<&mut Bytes> {
+            None
+        }
     }
 
     use super::*;
@@ -300,6 +353,7 @@ mod tests {
             FQDN::from_str("example").unwrap(),
             //request_filter.wasm from examples/wasm-module
             &PluginSource::File("./assets/request_filter.wasm".into()),
+            None,
             &Engine::default(),
         )
         .await
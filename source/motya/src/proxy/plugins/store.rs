@@ -1,9 +1,13 @@
 use std::{collections::HashMap, ptr::NonNull, sync::Arc};
 
+use bytes::Bytes;
 use fqdn::FQDN;
 use futures_util::future::join_all;
 use miette::{miette, Context, Result};
-use motya_config::common_types::{definitions::PluginSource, definitions_table::DefinitionsTable};
+use motya_config::common_types::{
+    definitions::{PluginChecksum, PluginSource},
+    definitions_table::DefinitionsTable,
+};
 use pingora_http::{RequestHeader, ResponseHeader};
 use pingora_proxy::Session;
 use wasmtime::{
@@ -27,6 +31,8 @@ pub struct WasmArtifact {
     pub _name: FQDN,
     pub component: Component,
     pub engine: Engine,
+    /// From the plugin's `request-body max-size`.
+    pub request_body_limit: Option<usize>,
 }
 
 pub struct WasmPluginStore {
@@ -45,10 +51,18 @@ impl WasmPluginStore {
             let engine = engine.clone();
             let name = name.clone();
             let source = def.source.clone();
+            let checksum = def.checksum.clone();
+            let request_body_limit = def.request_body_limit;
 
             async move {
-                let artifact =
-                    WasmPluginStore::create_artifact(name.clone(), &source, &engine).await?;
+                let mut artifact = WasmPluginStore::create_artifact(
+                    name.clone(),
+                    &source,
+                    checksum.as_ref(),
+                    &engine,
+                )
+                .await?;
+                artifact.request_body_limit = request_body_limit;
                 Ok::<_, miette::Report>((name, Arc::new(artifact)))
             }
         });
@@ -93,17 +107,26 @@ impl WasmPluginStore {
     pub async fn create_artifact(
         name: FQDN,
         source: &PluginSource,
+        checksum: Option<&PluginChecksum>,
         engine: &Engine,
     ) -> Result<WasmArtifact> {
         tracing::debug!("Preparing plugin '{}'...", name);
 
-        PluginLoader::check_availability(source)
-            .await
-            .wrap_err_with(|| format!("Availability check failed for plugin '{}'", name))?;
-
-        let bytes = PluginLoader::fetch_bytes(source)
-            .await
-            .wrap_err_with(|| format!("Download failed for plugin '{}'", name))?;
+        let bytes = match checksum {
+            Some(checksum) => {
+                PluginLoader::fetch_verified(&name, source, checksum, &PluginLoader::cache_dir())
+                    .await?
+            }
+            None => {
+                PluginLoader::check_availability(source)
+                    .await
+                    .wrap_err_with(|| format!("Availability check failed for plugin '{}'", name))?;
+
+                PluginLoader::fetch_bytes(source)
+                    .await
+                    .wrap_err_with(|| format!("Download failed for plugin '{}'", name))?
+            }
+        };
 
         tracing::debug!("Compiling plugin '{}' ({} bytes)...", name, bytes.len());
 
@@ -115,6 +138,7 @@ impl WasmPluginStore {
             _name: name,
             component,
             engine: engine.clone(),
+            request_body_limit: None,
         })
     }
 
@@ -142,7 +166,8 @@ unsafe impl Sync for ModuleState {}
 pub struct SessionCtx {
     pub _session: NonNull<Session>,
     pub req_header: Option<NonNull<RequestHeader>>,
-    pub _res_headers: Option<NonNull<ResponseHeader>>,
+    pub res_header: Option<NonNull<ResponseHeader>>,
+    pub req_body: Option<NonNull<Bytes>>,
 }
 
 impl WasiView for ModuleState {
@@ -167,8 +192,9 @@ mod tests {
         str::FromStr,
     };
 
+    use miette::NamedSource;
     use motya_config::common_types::{
-        definitions::PluginDefinition, definitions_table::DefinitionsTable,
+        definitions::PluginDefinition, definitions_table::DefinitionsTable, error::ConfigError,
     };
     use wiremock::{
         matchers::{method, path},
@@ -176,6 +202,7 @@ mod tests {
     };
 
     use super::*;
+    use crate::proxy::plugins::loader::sha256_hex;
 
     const WASM_BYTES: &[u8] = include_bytes!("../../../assets/request_filter.wasm");
 
@@ -186,6 +213,8 @@ mod tests {
             PluginDefinition {
                 name: FQDN::from_str(plugin_name).unwrap(),
                 source,
+                request_body_limit: None,
+                checksum: None,
             },
         );
 
@@ -309,6 +338,8 @@ mod tests {
             PluginDefinition {
                 name: FQDN::from_str("remote").unwrap(),
                 source: PluginSource::Url(url),
+                request_body_limit: None,
+                checksum: None,
             },
         );
 
@@ -317,6 +348,8 @@ mod tests {
             PluginDefinition {
                 name: FQDN::from_str("local").unwrap(),
                 source: PluginSource::File(file_path),
+                request_body_limit: None,
+                checksum: None,
             },
         );
 
@@ -340,4 +373,76 @@ mod tests {
             .artifacts
             .contains_key(&FQDN::from_str("local").unwrap()));
     }
+
+    fn checksum(sha256: String) -> PluginChecksum {
+        PluginChecksum {
+            sha256,
+            src: NamedSource::new("main.kdl", String::new()),
+            span: (0, 0).into(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_checksum_verified_and_cached() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("HEAD"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(WASM_BYTES))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let name = FQDN::from_str("pinned").unwrap();
+        let source = PluginSource::Url(format!("{}/pinned.wasm", mock_server.uri()));
+        let checksum = checksum(sha256_hex(WASM_BYTES));
+        let cache_dir = tempfile::tempdir().unwrap();
+
+        for _ in 0..2 {
+            let bytes = PluginLoader::fetch_verified(&name, &source, &checksum, cache_dir.path())
+                .await
+                .expect("Module matches its checksum");
+            assert_eq!(bytes, WASM_BYTES);
+        }
+
+        assert!(cache_dir
+            .path()
+            .join(format!("{}.wasm", checksum.sha256))
+            .exists());
+    }
+
+    #[tokio::test]
+    async fn test_checksum_mismatch() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("HEAD"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(WASM_BYTES))
+            .mount(&mock_server)
+            .await;
+
+        let name = FQDN::from_str("tampered").unwrap();
+        let source = PluginSource::Url(format!("{}/tampered.wasm", mock_server.uri()));
+        let checksum = checksum("0".repeat(64));
+        let cache_dir = tempfile::tempdir().unwrap();
+
+        let err = PluginLoader::fetch_verified(&name, &source, &checksum, cache_dir.path())
+            .await
+            .unwrap_err();
+
+        let config_err = err
+            .downcast_ref::<ConfigError>()
+            .expect("A mismatch is a configuration error");
+        assert!(config_err.errors[0].message.contains("Checksum mismatch"));
+        assert!(config_err.errors[0].label.is_some());
+
+        // Nothing unverified is kept.
+        assert_eq!(std::fs::read_dir(cache_dir.path()).unwrap().count(), 0);
+    }
 }
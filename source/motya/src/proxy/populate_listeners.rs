@@ -1,5 +1,27 @@
-use motya_config::common_types::listeners::{ListenerKind, Listeners};
-use pingora::listeners::tls::TlsSettings;
+use std::{
+    fs::{self, Permissions},
+    io,
+    os::unix::{
+        fs::{FileTypeExt, PermissionsExt},
+        net::UnixStream,
+    },
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use motya_config::common_types::listeners::{ListenerKind, Listeners, SocketOptions, UdsConfig};
+use nix::unistd::{Group, User};
+use pingora::listeners::{tls::TlsSettings, TcpSocketOptions};
+
+use crate::proxy::watcher::cert_watcher::ReloadableCert;
+
+/// How many connections may wait for their TCP Fast Open cookie to be checked.
+const TCP_FAST_OPEN_QUEUE: usize = 256;
+
+/// How often, and for how long, the owner of a unix socket waits for it to be bound.
+const BIND_POLL_INTERVAL: Duration = Duration::from_millis(50);
+const BIND_WAIT: Duration = Duration::from_secs(30);
 
 pub fn populate_listners<T>(
     listeners: &Listeners,
@@ -16,36 +38,203 @@ pub fn populate_listners<T>(
                 addr,
                 tls: Some(tls_cfg),
                 offer_h2,
+                socket,
+                ..
             } => {
-                let cert_path = tls_cfg
-                    .cert_path
-                    .to_str()
-                    .expect("cert path should be utf8");
-                let key_path = tls_cfg.key_path.to_str().expect("key path should be utf8");
-
-                // TODO: Make conditional!
-                let mut settings = TlsSettings::intermediate(cert_path, key_path)
+                // The certificate is handed out per handshake, so that
+                // `cert_watcher` can swap it when the files are renewed.
+                let cert = ReloadableCert::load(tls_cfg).unwrap_or_else(|err| {
+                    panic!("Cannot load the certificate of TLS listener {addr:?}: {err:?}")
+                });
+
+                let mut settings = TlsSettings::with_callbacks(Box::new(cert))
                     .expect("adding TLS listener shouldn't fail");
                 if *offer_h2 {
                     settings.enable_h2();
                 }
 
-                service.add_tls_with_settings(addr, None, settings);
+                service.add_tls_with_settings(addr, tcp_socket_options(socket), settings);
             }
             ListenerKind::Tcp {
                 addr,
                 tls: None,
                 offer_h2,
+                socket,
+                ..
             } => {
                 if *offer_h2 {
                     panic!("Unsupported configuration: {addr:?} configured without TLS, but H2 enabled which requires TLS");
                 }
-                service.add_tcp(addr);
+                match tcp_socket_options(socket) {
+                    Some(options) => service.add_tcp_with_settings(addr, options),
+                    None => service.add_tcp(addr),
+                }
             }
-            ListenerKind::Uds(path) => {
-                let path = path.to_str().unwrap();
-                service.add_uds(path, None); // todo
+            ListenerKind::Uds(uds) => {
+                let path = &uds.path;
+                remove_stale_socket(path)
+                    .unwrap_or_else(|err| panic!("Cannot listen on unix socket {path:?}: {err}"));
+                let owner = socket_owner(uds)
+                    .unwrap_or_else(|err| panic!("Cannot hand over unix socket {path:?}: {err}"));
+
+                let mode = uds.mode.map(Permissions::from_mode);
+                service.add_uds(path.to_str().unwrap(), mode);
+
+                if let Some((uid, gid)) = owner {
+                    chown_once_bound(path.clone(), uid, gid);
+                }
             }
         }
     }
 }
+
+/// The options pingora sets on the socket itself, if any was asked for.
+fn tcp_socket_options(socket: &SocketOptions) -> Option<TcpSocketOptions> {
+    if !socket.reuse_port && !socket.tcp_fast_open && socket.ipv6_only.is_none() {
+        return None;
+    }
+
+    Some(TcpSocketOptions {
+        ipv6_only: socket.ipv6_only,
+        tcp_fastopen: socket.tcp_fast_open.then_some(TCP_FAST_OPEN_QUEUE),
+        // `reuse-port` is rejected on other platforms when the config is loaded.
+        #[cfg(target_os = "linux")]
+        so_reuseport: socket.reuse_port.then_some(true),
+        ..Default::default()
+    })
+}
+
+/// Removes a socket left behind by a server that is gone, so that it can be bound
+/// again. A socket something still listens on is kept, and a path that is not a
+/// socket is never removed.
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    };
+
+    if !metadata.file_type().is_socket() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "the path exists and is not a socket",
+        ));
+    }
+
+    match UnixStream::connect(path) {
+        Ok(_) => Ok(()),
+        Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => {
+            tracing::info!("Removing stale unix socket {path:?}");
+            fs::remove_file(path)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// The user and group ids to hand the socket to, if `owner` or `group` is set.
+fn socket_owner(uds: &UdsConfig) -> Result<Option<(Option<u32>, Option<u32>)>, String> {
+    if uds.owner.is_none() && uds.group.is_none() {
+        return Ok(None);
+    }
+
+    let uid = uds.owner.as_deref().map(user_id).transpose()?;
+    let gid = uds.group.as_deref().map(group_id).transpose()?;
+    Ok(Some((uid, gid)))
+}
+
+fn user_id(name: &str) -> Result<u32, String> {
+    if let Ok(id) = name.parse() {
+        return Ok(id);
+    }
+    match User::from_name(name) {
+        Ok(Some(user)) => Ok(user.uid.as_raw()),
+        Ok(None) => Err(format!("there is no user '{name}'")),
+        Err(err) => Err(format!("cannot look up user '{name}': {err}")),
+    }
+}
+
+fn group_id(name: &str) -> Result<u32, String> {
+    if let Ok(id) = name.parse() {
+        return Ok(id);
+    }
+    match Group::from_name(name) {
+        Ok(Some(group)) => Ok(group.gid.as_raw()),
+        Ok(None) => Err(format!("there is no group '{name}'")),
+        Err(err) => Err(format!("cannot look up group '{name}': {err}")),
+    }
+}
+
+/// pingora binds its listeners when the server starts, after the services are
+/// built, so the owner is changed from a thread that waits for the socket.
+fn chown_once_bound(path: PathBuf, uid: Option<u32>, gid: Option<u32>) {
+    thread::spawn(move || {
+        let mut waited = Duration::ZERO;
+
+        while waited < BIND_WAIT {
+            let bound =
+                fs::symlink_metadata(&path).is_ok_and(|metadata| metadata.file_type().is_socket());
+
+            if bound {
+                if let Err(err) = std::os::unix::fs::chown(&path, uid, gid) {
+                    tracing::error!("Cannot change the owner of unix socket {path:?}: {err}");
+                }
+                return;
+            }
+
+            thread::sleep(BIND_POLL_INTERVAL);
+            waited += BIND_POLL_INTERVAL;
+        }
+
+        tracing::error!("Unix socket {path:?} was not bound in time to change its owner");
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixListener;
+
+    use super::*;
+
+    #[test]
+    fn test_remove_stale_socket() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let stale = dir.path().join("stale.sock");
+        drop(UnixListener::bind(&stale).unwrap());
+        remove_stale_socket(&stale).unwrap();
+        assert!(!stale.exists());
+
+        let live = dir.path().join("live.sock");
+        let _listener = UnixListener::bind(&live).unwrap();
+        remove_stale_socket(&live).unwrap();
+        assert!(live.exists());
+
+        let file = dir.path().join("file.sock");
+        fs::write(&file, "not a socket").unwrap();
+        assert!(remove_stale_socket(&file).is_err());
+        assert!(file.exists());
+
+        remove_stale_socket(&dir.path().join("missing.sock")).unwrap();
+    }
+
+    #[test]
+    fn test_socket_owner() {
+        let uds = |owner: Option<&str>, group: Option<&str>| UdsConfig {
+            path: PathBuf::from("/run/motya.sock"),
+            mode: None,
+            owner: owner.map(String::from),
+            group: group.map(String::from),
+        };
+
+        assert_eq!(socket_owner(&uds(None, None)), Ok(None));
+        assert_eq!(
+            socket_owner(&uds(Some("1000"), None)),
+            Ok(Some((Some(1000), None)))
+        );
+        assert_eq!(
+            socket_owner(&uds(None, Some("0"))),
+            Ok(Some((None, Some(0))))
+        );
+        assert!(socket_owner(&uds(Some("no-such-motya-user"), None)).is_err());
+    }
+}
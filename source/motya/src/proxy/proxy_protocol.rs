@@ -0,0 +1,352 @@
+//! The PROXY protocol (`proxy-protocol=#true` on a listener).
+//!
+//! Behind a load balancer every connection comes from the balancer's address.
+//! HAProxy, ELB and friends can start each connection with a PROXY header that
+//! names the client they forward. The header is read off the connection before
+//! pingora parses any HTTP, and the client it names replaces the peer address
+//! of the connection, so rate limits, `${client-ip}` keys, CIDR filters and the
+//! access log all see the client instead of the balancer.
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::Arc,
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use motya_config::common_types::listeners::{ListenerKind, Listeners};
+use pingora::{
+    apps::ServerApp,
+    protocols::{l4::socket::SocketAddr, GetSocketDigest, SocketDigest, Stream, UniqueID},
+    server::ShutdownWatch,
+};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// How long a balancer may take to send the header.
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+/// Longest v1 header, `\r\n` included.
+const V1_MAX_LEN: usize = 107;
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Wraps the HTTP app of a service, reading the PROXY header on the listeners
+/// that expect one.
+pub struct ProxyProtocol<A> {
+    inner: Arc<A>,
+    /// Every TCP listener of the service, and whether it expects the header.
+    listeners: HashMap<std::net::SocketAddr, bool>,
+}
+
+impl<A> ProxyProtocol<A> {
+    pub fn new(inner: A, listeners: &Listeners) -> Self {
+        let listeners = listeners
+            .list_cfgs
+            .iter()
+            .filter_map(|list_cfg| match &list_cfg.source {
+                ListenerKind::Tcp {
+                    addr,
+                    proxy_protocol,
+                    ..
+                } => Some((addr.parse().ok()?, *proxy_protocol)),
+                ListenerKind::Uds(_) => None,
+            })
+            .collect();
+
+        Self {
+            inner: Arc::new(inner),
+            listeners,
+        }
+    }
+
+    fn expects_header(&self, stream: &Stream) -> bool {
+        stream
+            .get_socket_digest()
+            .and_then(|digest| digest.local_addr()?.as_inet().copied())
+            .is_some_and(|local| self.expects_header_on(local))
+    }
+
+    /// Whether the listener that accepted a connection on `local` expects the header.
+    /// A listener bound to that exact address wins over one on the unspecified
+    /// address of the same port, so the option of one listener never applies to
+    /// another that shares its port.
+    fn expects_header_on(&self, local: std::net::SocketAddr) -> bool {
+        if let Some(expects) = self.listeners.get(&local) {
+            return *expects;
+        }
+
+        self.listeners.iter().any(|(addr, expects)| {
+            *expects && addr.ip().is_unspecified() && addr.port() == local.port()
+        })
+    }
+}
+
+#[async_trait]
+impl<A: ServerApp + Send + Sync + 'static> ServerApp for ProxyProtocol<A> {
+    async fn process_new(
+        self: &Arc<Self>,
+        mut stream: Stream,
+        shutdown: &ShutdownWatch,
+    ) -> Option<Stream> {
+        if self.expects_header(&stream) {
+            match tokio::time::timeout(HEADER_TIMEOUT, read_header(&mut stream)).await {
+                Ok(Ok(Some(client))) => set_client(&mut stream, client),
+                Ok(Ok(None)) => {}
+                Ok(Err(reason)) => {
+                    tracing::debug!("Dropping connection without a valid PROXY header: {reason}");
+                    return None;
+                }
+                Err(_) => {
+                    tracing::debug!("Dropping connection that sent no PROXY header in time");
+                    return None;
+                }
+            }
+        }
+
+        // Kept-alive connections are served here, so that their next request
+        // isn't mistaken for another PROXY header.
+        let mut reused = self.inner.process_new(stream, shutdown).await;
+        while let Some(stream) = reused {
+            reused = self.inner.process_new(stream, shutdown).await;
+        }
+        None
+    }
+
+    async fn cleanup(&self) {
+        self.inner.cleanup().await
+    }
+}
+
+fn set_client(stream: &mut Stream, client: std::net::SocketAddr) {
+    let digest = SocketDigest::from_raw_fd(stream.id());
+    let _ = digest.peer_addr.set(Some(SocketAddr::Inet(client)));
+    stream.set_socket_digest(digest);
+}
+
+/// Reads a v1 or v2 header, and not a byte past it. Returns the client it names,
+/// or `None` for headers that name none: health checks of the balancer itself
+/// and connections it can't describe.
+pub async fn read_header<S: AsyncRead + Unpin>(
+    stream: &mut S,
+) -> Result<Option<std::net::SocketAddr>, &'static str> {
+    // Both versions are at least this long.
+    let mut start = [0u8; V2_SIGNATURE.len()];
+    stream
+        .read_exact(&mut start)
+        .await
+        .map_err(|_| "connection closed")?;
+
+    if start == V2_SIGNATURE {
+        read_v2(stream).await
+    } else if start.starts_with(V1_PREFIX) {
+        read_v1(stream, &start).await
+    } else {
+        Err("missing header")
+    }
+}
+
+async fn read_v1<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    start: &[u8],
+) -> Result<Option<std::net::SocketAddr>, &'static str> {
+    let mut line = start.to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() == V1_MAX_LEN {
+            return Err("v1 header too long");
+        }
+        line.push(stream.read_u8().await.map_err(|_| "connection closed")?);
+    }
+
+    let line = std::str::from_utf8(&line[..line.len() - 2]).map_err(|_| "invalid v1 header")?;
+    parse_v1(line).ok_or("invalid v1 header")
+}
+
+/// `None` for a malformed line, `Some(None)` for `PROXY UNKNOWN`.
+fn parse_v1(line: &str) -> Option<Option<std::net::SocketAddr>> {
+    let mut parts = line.split(' ').skip(1);
+    let family = parts.next()?;
+    if family == "UNKNOWN" {
+        return Some(None);
+    }
+
+    let source: IpAddr = parts.next()?.parse().ok()?;
+    let _destination: IpAddr = parts.next()?.parse().ok()?;
+    let source_port: u16 = parts.next()?.parse().ok()?;
+    let _destination_port: u16 = parts.next()?.parse().ok()?;
+
+    match (family, source) {
+        ("TCP4", IpAddr::V4(_)) | ("TCP6", IpAddr::V6(_)) if parts.next().is_none() => {
+            Some(Some((source, source_port).into()))
+        }
+        _ => None,
+    }
+}
+
+async fn read_v2<S: AsyncRead + Unpin>(
+    stream: &mut S,
+) -> Result<Option<std::net::SocketAddr>, &'static str> {
+    let mut fixed = [0u8; 4];
+    stream
+        .read_exact(&mut fixed)
+        .await
+        .map_err(|_| "connection closed")?;
+    let [version_command, family, len @ ..] = fixed;
+
+    let mut addresses = vec![0u8; u16::from_be_bytes(len) as usize];
+    stream
+        .read_exact(&mut addresses)
+        .await
+        .map_err(|_| "connection closed")?;
+
+    if version_command >> 4 != 2 {
+        return Err("unsupported version");
+    }
+    match version_command & 0x0f {
+        // LOCAL: the balancer talking for itself.
+        0 => return Ok(None),
+        1 => {}
+        _ => return Err("unsupported command"),
+    }
+
+    // TCP or UDP over IPv4 or IPv6; for anything else there is no client to name.
+    let (ip, port): (IpAddr, &[u8]) = match family >> 4 {
+        1 if addresses.len() >= 12 => {
+            let ip: [u8; 4] = addresses[..4].try_into().unwrap();
+            (Ipv4Addr::from(ip).into(), &addresses[8..10])
+        }
+        2 if addresses.len() >= 36 => {
+            let ip: [u8; 16] = addresses[..16].try_into().unwrap();
+            (Ipv6Addr::from(ip).into(), &addresses[32..34])
+        }
+        1 | 2 => return Err("truncated v2 addresses"),
+        _ => return Ok(None),
+    };
+
+    Ok(Some((ip, u16::from_be_bytes([port[0], port[1]])).into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use motya_config::common_types::listeners::ListenerConfig;
+
+    use super::*;
+
+    fn tcp_listener(addr: &str, proxy_protocol: bool) -> ListenerConfig {
+        ListenerConfig {
+            source: ListenerKind::Tcp {
+                addr: addr.to_string(),
+                tls: None,
+                offer_h2: false,
+                proxy_protocol,
+                max_conn_rate: None,
+                socket: Default::default(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_listeners_are_matched_by_address() {
+        let listeners = Listeners {
+            list_cfgs: vec![
+                tcp_listener("10.0.0.1:8080", true),
+                tcp_listener("127.0.0.1:8080", false),
+                tcp_listener("0.0.0.0:9090", true),
+                tcp_listener("[::]:9443", false),
+            ],
+        };
+        let proxy_protocol = ProxyProtocol::new((), &listeners);
+        let expects = |local: &str| proxy_protocol.expects_header_on(local.parse().unwrap());
+
+        assert!(expects("10.0.0.1:8080"));
+        // Same port, but a listener without the option.
+        assert!(!expects("127.0.0.1:8080"));
+        // Any address of a listener on the unspecified address.
+        assert!(expects("192.0.2.10:9090"));
+        assert!(!expects("192.0.2.10:9443"));
+        assert!(!expects("10.0.0.2:8080"));
+    }
+
+    async fn read(mut input: &[u8]) -> (Result<Option<std::net::SocketAddr>, &'static str>, &[u8]) {
+        let header = read_header(&mut input).await;
+        (header, input)
+    }
+
+    #[tokio::test]
+    async fn test_v1() {
+        let (client, rest) =
+            read(b"PROXY TCP4 203.0.113.7 10.0.0.1 51234 80\r\nGET / HTTP/1.1\r\n").await;
+        assert_eq!(client, Ok(Some("203.0.113.7:51234".parse().unwrap())));
+        assert_eq!(rest, b"GET / HTTP/1.1\r\n");
+
+        let (client, _) = read(b"PROXY TCP6 2001:db8::7 2001:db8::1 51234 443\r\n").await;
+        assert_eq!(client, Ok(Some("[2001:db8::7]:51234".parse().unwrap())));
+
+        let (client, rest) = read(b"PROXY UNKNOWN\r\nGET").await;
+        assert_eq!(client, Ok(None));
+        assert_eq!(rest, b"GET");
+    }
+
+    #[tokio::test]
+    async fn test_v1_rejects_malformed() {
+        for header in [
+            &b"PROXY TCP4 2001:db8::7 10.0.0.1 51234 80\r\n"[..],
+            b"PROXY TCP4 203.0.113.7 10.0.0.1 51234\r\n",
+            b"PROXY TCP4 203.0.113.7 10.0.0.1 51234 80 extra\r\n",
+            b"PROXY TCP4 203.0.113.7 10.0.0.1 99999 80\r\n",
+        ] {
+            assert!(read(header).await.0.is_err(), "{header:?}");
+        }
+
+        let endless = [b"PROXY TCP4 ".as_slice(), &[b'1'; 200]].concat();
+        assert_eq!(read(&endless).await.0, Err("v1 header too long"));
+    }
+
+    fn v2(command: u8, family: u8, addresses: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend([0x20 | command, family]);
+        header.extend((addresses.len() as u16).to_be_bytes());
+        header.extend(addresses);
+        header
+    }
+
+    #[tokio::test]
+    async fn test_v2() {
+        let mut ipv4 = vec![203, 0, 113, 7, 10, 0, 0, 1];
+        ipv4.extend(51234u16.to_be_bytes());
+        ipv4.extend(80u16.to_be_bytes());
+        // A TLV after the addresses is skipped along with them.
+        ipv4.extend([0x04, 0x00, 0x01, 0xff]);
+
+        let mut input = v2(1, 0x11, &ipv4);
+        input.extend(b"GET");
+        let (client, rest) = read(&input).await;
+        assert_eq!(client, Ok(Some("203.0.113.7:51234".parse().unwrap())));
+        assert_eq!(rest, b"GET");
+
+        let mut ipv6 = Ipv6Addr::LOCALHOST.octets().to_vec();
+        ipv6.extend(Ipv6Addr::UNSPECIFIED.octets());
+        ipv6.extend(51234u16.to_be_bytes());
+        ipv6.extend(443u16.to_be_bytes());
+        let (client, _) = read(&v2(1, 0x21, &ipv6)).await;
+        assert_eq!(client, Ok(Some("[::1]:51234".parse().unwrap())));
+    }
+
+    #[tokio::test]
+    async fn test_v2_without_client() {
+        let (client, rest) = read(&[v2(0, 0x00, &[]), b"GET".to_vec()].concat()).await;
+        assert_eq!(client, Ok(None));
+        assert_eq!(rest, b"GET");
+
+        let (client, _) = read(&v2(1, 0x31, &[0u8; 216])).await;
+        assert_eq!(client, Ok(None));
+
+        assert!(read(&v2(1, 0x11, &[203, 0, 113, 7])).await.0.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_missing_header() {
+        let (client, _) = read(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n").await;
+        assert_eq!(client, Err("missing header"));
+    }
+}
@@ -0,0 +1,438 @@
+//! How a key's state decides whether a request goes through.
+//!
+//! Each [`RateLimitAlgorithm`] of a policy has an [`Algorithm`] here, which
+//! [`MemoryStorage`](super::storage::MemoryStorage) runs on the state it keeps
+//! per key. Algorithms are stateless, so one instance serves every key.
+
+use std::{fmt::Debug, time::Duration};
+
+use motya_config::common_types::rate_limiter::RateLimitAlgorithm;
+use tokio::time::Instant;
+
+use crate::proxy::rate_limiter::storage::RateLimitResult;
+
+/// What the storage keeps for a key, in the shape of the algorithm that left it.
+#[derive(Debug, Clone)]
+pub enum KeyState {
+    Bucket {
+        tokens: f64,
+        updated: Instant,
+    },
+    Window {
+        /// When the current window started.
+        start: Instant,
+        current: f64,
+        previous: f64,
+    },
+    Gcra {
+        /// The theoretical arrival time: when the key would be back to zero
+        /// requests in flight.
+        tat: Instant,
+    },
+}
+
+pub trait Algorithm: Send + Sync + Debug {
+    /// Decides on a request costing `cost` at `now`, given the `state` the key
+    /// was left in, `None` for its first request. Returns the new state with
+    /// the outcome.
+    ///
+    /// A state of another algorithm, left by a policy reloaded with a new
+    /// `algorithm`, counts as none.
+    fn check(
+        &self,
+        state: Option<&KeyState>,
+        now: Instant,
+        rate_per_sec: f64,
+        burst: usize,
+        cost: u32,
+    ) -> (KeyState, RateLimitResult);
+}
+
+/// The algorithm named by a policy's `algorithm`.
+pub fn algorithm(kind: RateLimitAlgorithm) -> &'static dyn Algorithm {
+    match kind {
+        RateLimitAlgorithm::TokenBucket => &TokenBucket,
+        RateLimitAlgorithm::SlidingWindow => &SlidingWindow,
+        RateLimitAlgorithm::Gcra => &Gcra,
+    }
+}
+
+/// `burst` tokens, refilled at `rate_per_sec`. A request takes `cost` of them.
+#[derive(Debug)]
+pub struct TokenBucket;
+
+impl Algorithm for TokenBucket {
+    fn check(
+        &self,
+        state: Option<&KeyState>,
+        now: Instant,
+        rate_per_sec: f64,
+        burst: usize,
+        cost: u32,
+    ) -> (KeyState, RateLimitResult) {
+        let burst = burst as f64;
+        let cost = cost as f64;
+
+        let mut tokens = match state {
+            Some(KeyState::Bucket { tokens, updated }) => {
+                let elapsed = now.saturating_duration_since(*updated).as_secs_f64();
+                (tokens + elapsed * rate_per_sec).min(burst)
+            }
+            _ => burst,
+        };
+
+        let allowed = tokens >= cost;
+        let reset_after = if allowed {
+            tokens -= cost;
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((cost - tokens) / rate_per_sec)
+        };
+
+        (
+            KeyState::Bucket {
+                tokens,
+                updated: now,
+            },
+            RateLimitResult {
+                allowed,
+                remaining: tokens as usize,
+                reset_after,
+            },
+        )
+    }
+}
+
+/// Windows of `burst / rate_per_sec` seconds letting `burst` requests through.
+///
+/// The count over the last window length is estimated as the count of the
+/// current window plus the share of the previous one the last window length
+/// still covers, assuming its requests came evenly. Unlike fixed windows, this
+/// doesn't let `2 * burst` requests through around the start of a window.
+#[derive(Debug)]
+pub struct SlidingWindow;
+
+impl Algorithm for SlidingWindow {
+    fn check(
+        &self,
+        state: Option<&KeyState>,
+        now: Instant,
+        rate_per_sec: f64,
+        burst: usize,
+        cost: u32,
+    ) -> (KeyState, RateLimitResult) {
+        let length = Duration::from_secs_f64(burst as f64 / rate_per_sec);
+        let burst = burst as f64;
+        let cost = cost as f64;
+
+        let (mut start, mut current, mut previous) = match state {
+            Some(KeyState::Window {
+                start,
+                current,
+                previous,
+            }) => (*start, *current, *previous),
+            _ => (now, 0.0, 0.0),
+        };
+
+        // Move on to the window `now` falls in.
+        let passed = now.saturating_duration_since(start).as_secs_f64() / length.as_secs_f64();
+        if passed >= 2.0 {
+            start += length.mul_f64(passed.floor());
+            (current, previous) = (0.0, 0.0);
+        } else if passed >= 1.0 {
+            start += length;
+            (current, previous) = (0.0, current);
+        }
+
+        let into_window = now.saturating_duration_since(start).as_secs_f64() / length.as_secs_f64();
+        let estimate = previous * (1.0 - into_window) + current;
+
+        let allowed = estimate + cost <= burst;
+        let reset_after = if allowed {
+            current += cost;
+            Duration::ZERO
+        } else {
+            window_wait(length, into_window, current, previous, burst, cost)
+        };
+        let remaining = if allowed {
+            burst - estimate - cost
+        } else {
+            0.0
+        };
+
+        (
+            KeyState::Window {
+                start,
+                current,
+                previous,
+            },
+            RateLimitResult {
+                allowed,
+                remaining: remaining.max(0.0) as usize,
+                reset_after,
+            },
+        )
+    }
+}
+
+/// How long until a request costing `cost` fits, `into_window` of the way
+/// through a window, as the share of `previous` in the estimate runs out.
+fn window_wait(
+    length: Duration,
+    into_window: f64,
+    current: f64,
+    previous: f64,
+    burst: f64,
+    cost: f64,
+) -> Duration {
+    if cost > burst {
+        return length;
+    }
+
+    // It fits later in this window once `previous` weighs little enough.
+    if previous > 0.0 && current + cost <= burst {
+        let at = 1.0 - (burst - current - cost) / previous;
+        return length.mul_f64((at - into_window).max(0.0));
+    }
+
+    // Otherwise in the next one, where `current` becomes the previous count.
+    let at = if current > 0.0 {
+        1.0 - (burst - cost) / current
+    } else {
+        0.0
+    };
+    length.mul_f64(1.0 - into_window + at.max(0.0))
+}
+
+/// The generic cell rate algorithm.
+///
+/// Requests are spaced `1 / rate_per_sec` seconds apart, and may come up to
+/// `burst` spacings early. It lets the same requests through as the token
+/// bucket, keeping a single instant per key instead of a count and a time.
+#[derive(Debug)]
+pub struct Gcra;
+
+impl Algorithm for Gcra {
+    fn check(
+        &self,
+        state: Option<&KeyState>,
+        now: Instant,
+        rate_per_sec: f64,
+        burst: usize,
+        cost: u32,
+    ) -> (KeyState, RateLimitResult) {
+        // Kept in whole nanoseconds, so that `burst` requests at once add up
+        // to exactly the tolerance.
+        let spacing = Duration::from_secs_f64(1.0 / rate_per_sec).max(Duration::from_nanos(1));
+        let tolerance = spacing.saturating_mul(burst as u32);
+
+        let tat = match state {
+            Some(KeyState::Gcra { tat }) => (*tat).max(now),
+            _ => now,
+        };
+        let new_tat = tat + spacing.saturating_mul(cost);
+        let ahead = new_tat.duration_since(now);
+
+        let allowed = ahead <= tolerance;
+        let (tat, reset_after) = if allowed {
+            (new_tat, Duration::ZERO)
+        } else {
+            (tat, ahead - tolerance)
+        };
+        let in_flight = tat.duration_since(now);
+
+        (
+            KeyState::Gcra { tat },
+            RateLimitResult {
+                allowed,
+                remaining: (tolerance.saturating_sub(in_flight).as_nanos() / spacing.as_nanos())
+                    as usize,
+                reset_after,
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    const ALGORITHMS: [RateLimitAlgorithm; 3] = [
+        RateLimitAlgorithm::TokenBucket,
+        RateLimitAlgorithm::SlidingWindow,
+        RateLimitAlgorithm::Gcra,
+    ];
+
+    /// Runs requests of cost 1 at `offsets` from `start`, returning which went through.
+    fn run(
+        kind: RateLimitAlgorithm,
+        start: Instant,
+        offsets: &[Duration],
+        rate: f64,
+        burst: usize,
+    ) -> Vec<bool> {
+        let algorithm = algorithm(kind);
+        let mut state = None;
+        offsets
+            .iter()
+            .map(|offset| {
+                let (next, result) =
+                    algorithm.check(state.as_ref(), start + *offset, rate, burst, 1);
+                state = Some(next);
+                result.allowed
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_burst_then_rate() {
+        for kind in ALGORITHMS {
+            let algorithm = algorithm(kind);
+            let start = Instant::now();
+
+            let mut state = None;
+            for i in 0..3 {
+                let (next, result) = algorithm.check(state.as_ref(), start, 1.0, 3, 1);
+                assert!(result.allowed, "{kind}: request {i}");
+                assert_eq!(result.remaining, 2 - i, "{kind}: request {i}");
+                state = Some(next);
+            }
+
+            let (next, result) = algorithm.check(state.as_ref(), start, 1.0, 3, 1);
+            assert!(!result.allowed, "{kind}");
+            assert_eq!(result.remaining, 0, "{kind}");
+            assert!(result.reset_after > Duration::ZERO, "{kind}");
+
+            // The request fits once the wait it was given is over.
+            let later = start + result.reset_after + Duration::from_millis(1);
+            let (_, result) = algorithm.check(Some(&next), later, 1.0, 3, 1);
+            assert!(result.allowed, "{kind}");
+        }
+    }
+
+    #[test]
+    fn test_sliding_window_weights_the_previous_window() {
+        let start = Instant::now();
+        let ms = Duration::from_millis;
+
+        // 4 requests per 4s window, the first window starting with the first
+        // request. Fixed windows would let 4 more through at 4s, right after
+        // the 3 at 3.5s, but the previous window still weighs in full.
+        // Halfway through the window, half of it still counts.
+        let offsets = [
+            ms(0),
+            ms(3_500),
+            ms(3_500),
+            ms(3_500),
+            ms(4_000),
+            ms(6_000),
+            ms(6_000),
+            ms(6_000),
+        ];
+        assert_eq!(
+            run(RateLimitAlgorithm::SlidingWindow, start, &offsets, 1.0, 4),
+            [true, true, true, true, false, true, true, false]
+        );
+    }
+
+    #[test]
+    fn test_unknown_state_starts_over() {
+        let start = Instant::now();
+        let (bucket, _) = TokenBucket.check(None, start, 1.0, 1, 1);
+
+        for kind in ALGORITHMS {
+            let (_, result) = algorithm(kind).check(Some(&bucket), start, 1.0, 2, 1);
+            if kind == RateLimitAlgorithm::TokenBucket {
+                assert_eq!(result.remaining, 0);
+            } else {
+                assert_eq!(result.remaining, 1, "{kind}");
+            }
+        }
+    }
+
+    fn offsets() -> impl Strategy<Value = Vec<Duration>> {
+        prop::collection::vec(0u64..10_000, 1..200).prop_map(|mut millis| {
+            millis.sort_unstable();
+            millis.into_iter().map(Duration::from_millis).collect()
+        })
+    }
+
+    proptest! {
+        /// A key with no history takes exactly `burst` requests at once.
+        #[test]
+        fn prop_fresh_key_takes_the_burst(rate in 0.1f64..1000.0, burst in 1usize..100) {
+            let offsets = vec![Duration::ZERO; burst + 1];
+            for kind in ALGORITHMS {
+                let allowed = run(kind, Instant::now(), &offsets, rate, burst);
+                prop_assert!(allowed[..burst].iter().all(|a| *a), "{}", kind);
+                prop_assert!(!allowed[burst], "{}", kind);
+            }
+        }
+
+        /// Over any span, no more than the burst plus the rate for the span
+        /// gets through; the sliding window may let a window's worth more
+        /// through as it only estimates the count.
+        #[test]
+        fn prop_never_more_than_burst_and_rate(
+            rate in 0.5f64..50.0,
+            burst in 1usize..20,
+            offsets in offsets(),
+        ) {
+            let span = offsets.last().unwrap().as_secs_f64();
+            for kind in ALGORITHMS {
+                let allowed = run(kind, Instant::now(), &offsets, rate, burst)
+                    .into_iter()
+                    .filter(|a| *a)
+                    .count() as f64;
+                let slack = match kind {
+                    RateLimitAlgorithm::SlidingWindow => burst as f64,
+                    _ => 0.0,
+                };
+                prop_assert!(
+                    allowed <= burst as f64 + rate * span + slack + 1.0,
+                    "{}: {} allowed over {}s",
+                    kind,
+                    allowed,
+                    span
+                );
+            }
+        }
+
+        /// Requests coming a little slower than the rate are never limited by
+        /// the token bucket and GCRA, whatever the burst.
+        #[test]
+        fn prop_traffic_under_the_rate_goes_through(
+            rate in 0.5f64..50.0,
+            burst in 1usize..20,
+            count in 1usize..200,
+        ) {
+            let gap = Duration::from_secs_f64(1.01 / rate);
+            let offsets = (0..count as u32).map(|i| gap * i).collect::<Vec<_>>();
+            for kind in [RateLimitAlgorithm::TokenBucket, RateLimitAlgorithm::Gcra] {
+                let allowed = run(kind, Instant::now(), &offsets, rate, burst);
+                prop_assert!(allowed.iter().all(|a| *a), "{}", kind);
+            }
+        }
+
+        /// After a quiet spell of twice what the burst takes to refill, a key
+        /// takes a full burst again, whatever it went through before.
+        #[test]
+        fn prop_idle_key_recovers_the_burst(
+            rate in 0.5f64..50.0,
+            burst in 1usize..20,
+            offsets in offsets(),
+        ) {
+            let refill = Duration::from_secs_f64(burst as f64 / rate);
+            let mut offsets = offsets;
+            let quiet = *offsets.last().unwrap() + refill * 2 + Duration::from_millis(1);
+            offsets.extend(vec![quiet; burst]);
+
+            for kind in ALGORITHMS {
+                let allowed = run(kind, Instant::now(), &offsets, rate, burst);
+                prop_assert!(allowed[allowed.len() - burst..].iter().all(|a| *a), "{}", kind);
+            }
+        }
+    }
+}
@@ -0,0 +1,68 @@
+//! The `RateLimit-*` headers of policies with `expose-headers=#true`, after the
+//! IETF draft on rate-limit headers, so that clients can slow down on their own.
+
+use pingora::Result;
+use pingora_http::ResponseHeader;
+
+pub const RATELIMIT_LIMIT: &str = "RateLimit-Limit";
+pub const RATELIMIT_REMAINING: &str = "RateLimit-Remaining";
+pub const RATELIMIT_RESET: &str = "RateLimit-Reset";
+
+/// Where a client stands with a policy after a request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimitHeaders {
+    /// Requests the client may send at once, the `burst` of the policy.
+    pub limit: usize,
+    pub remaining: usize,
+    /// Seconds until the quota is back in full, or until a rejected request
+    /// would go through.
+    pub reset: u64,
+}
+
+impl RateLimitHeaders {
+    /// Whether these are closer to the limit than `other`. A request checked
+    /// by several policies reports the one it is closest to running out of.
+    pub fn tighter_than(&self, other: &Self) -> bool {
+        (self.remaining, std::cmp::Reverse(self.reset))
+            < (other.remaining, std::cmp::Reverse(other.reset))
+    }
+
+    pub fn apply(&self, header: &mut ResponseHeader) -> Result<()> {
+        header.insert_header(RATELIMIT_LIMIT, self.limit.to_string())?;
+        header.insert_header(RATELIMIT_REMAINING, self.remaining.to_string())?;
+        header.insert_header(RATELIMIT_RESET, self.reset.to_string())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(remaining: usize, reset: u64) -> RateLimitHeaders {
+        RateLimitHeaders {
+            limit: 10,
+            remaining,
+            reset,
+        }
+    }
+
+    #[test]
+    fn test_tightest_policy_wins() {
+        assert!(headers(2, 5).tighter_than(&headers(3, 1)));
+        assert!(headers(2, 5).tighter_than(&headers(2, 1)));
+        assert!(!headers(2, 5).tighter_than(&headers(2, 5)));
+        assert!(!headers(4, 5).tighter_than(&headers(3, 60)));
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut response = ResponseHeader::build(200, None).unwrap();
+        headers(7, 3).apply(&mut response).unwrap();
+
+        let value = |name| response.headers.get(name).unwrap().to_str().unwrap();
+        assert_eq!(value("ratelimit-limit"), "10");
+        assert_eq!(value("ratelimit-remaining"), "7");
+        assert_eq!(value("ratelimit-reset"), "3");
+    }
+}
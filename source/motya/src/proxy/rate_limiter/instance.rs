@@ -3,37 +3,74 @@ use std::sync::Arc;
 use miette::{miette, Result};
 use motya_config::common_types::{
     key_template::{KeyTemplate, TransformOp},
-    rate_limiter::RateLimitPolicy,
+    rate_limiter::{RateLimitPolicy, RateLimitScope},
 };
 use smallvec::{Array, SmallVec};
 
 use crate::proxy::{
     context::SessionInfo,
     key_selector::{KeySelector, KeySourceContext},
-    rate_limiter::storage::{RateLimitResult, RateLimitStorage},
+    rate_limiter::{
+        algorithm::{self, Algorithm},
+        headers::RateLimitHeaders,
+        storage::{RateLimitResult, RateLimitStorage},
+    },
 };
 
 #[derive(Debug, Clone)]
 pub struct RateLimiterInstance {
     storage: Arc<dyn RateLimitStorage>,
+    algorithm: &'static dyn Algorithm,
 
     selector: KeySelector,
+    /// Put before every key, so that policies sharing a storage, and services
+    /// sharing a policy of the `service` scope, don't share buckets.
+    namespace: String,
 
     rate: f64,
     burst: usize,
+    expose_headers: bool,
 }
 
 impl RateLimiterInstance {
-    pub fn new(policy: RateLimitPolicy, storage: Arc<dyn RateLimitStorage>) -> Self {
+    /// The instance of `policy` for the routes of `service`.
+    pub fn new(policy: RateLimitPolicy, storage: Arc<dyn RateLimitStorage>, service: &str) -> Self {
+        let namespace = match policy.scope {
+            RateLimitScope::Service => format!("{}@{service}:", policy.name),
+            RateLimitScope::Global => format!("{}:", policy.name),
+        };
+
         Self {
             storage,
+            algorithm: algorithm::algorithm(policy.algorithm),
             selector: KeySelector {
                 extraction_strategies: vec![policy.key_template],
                 transforms: policy.transforms,
             },
+            namespace,
             rate: policy.rate_req_per_sec,
             burst: policy.burst,
+            expose_headers: policy.expose_headers,
+        }
+    }
+
+    /// The `RateLimit-*` headers for `result`, if the policy exposes them.
+    pub fn headers(&self, result: &RateLimitResult) -> Option<RateLimitHeaders> {
+        if !self.expose_headers {
+            return None;
         }
+
+        let reset = if result.allowed {
+            (self.burst.saturating_sub(result.remaining) as f64 / self.rate).ceil() as u64
+        } else {
+            result.reset_after.as_secs_f64().ceil().max(1.0) as u64
+        };
+
+        Some(RateLimitHeaders {
+            limit: self.burst,
+            remaining: result.remaining,
+            reset,
+        })
     }
 
     pub async fn check(&self, session: &SessionInfo<'_>) -> Result<RateLimitResult> {
@@ -47,11 +84,119 @@ impl RateLimiterInstance {
             });
         }
 
+        key_buf.insert_from_slice(0, self.namespace.as_bytes());
         let key_str = std::str::from_utf8(&key_buf)
             .map_err(|err| miette!("key is not a valid utf-8, reason: {err}"))?;
 
         self.storage
-            .check_and_update(key_str, self.rate, self.burst, 1)
+            .check_and_update(key_str, self.algorithm, self.rate, self.burst, 1)
             .await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use motya_config::common_types::rate_limiter::RateLimitAlgorithm;
+
+    use pingora_http::RequestHeader;
+
+    use super::*;
+    use crate::proxy::rate_limiter::storage::MemoryStorage;
+
+    fn policy(name: &str, scope: RateLimitScope, expose_headers: bool) -> RateLimitPolicy {
+        RateLimitPolicy {
+            name: name.into(),
+            algorithm: RateLimitAlgorithm::TokenBucket,
+            scope,
+            storage_key: "mem".into(),
+            transforms: vec![],
+            key_template: KeyTemplate::new("${header-x-api-key}").unwrap(),
+            rate_req_per_sec: 2.0,
+            burst: 10,
+            max_keys: None,
+            expose_headers,
+        }
+    }
+
+    fn storage() -> Arc<dyn RateLimitStorage> {
+        Arc::new(MemoryStorage::new(100, Duration::from_secs(60)))
+    }
+
+    fn instance(expose_headers: bool) -> RateLimiterInstance {
+        let policy = policy("per-key", RateLimitScope::Service, expose_headers);
+        RateLimiterInstance::new(policy, storage(), "api")
+    }
+
+    /// Sends requests with the same key until `limiter` rejects one, and returns
+    /// how many went through.
+    async fn exhaust(limiter: &RateLimiterInstance) -> usize {
+        let mut request = RequestHeader::build("GET", b"/", None).unwrap();
+        request.insert_header("x-api-key", "abc").unwrap();
+        let session = SessionInfo {
+            headers: &request,
+            client_addr: None,
+            path: request.uri.path_and_query().unwrap(),
+        };
+
+        let mut allowed = 0;
+        while limiter.check(&session).await.unwrap().allowed {
+            allowed += 1;
+        }
+        allowed
+    }
+
+    #[tokio::test]
+    async fn test_scopes() {
+        let storage = storage();
+        let new = |name, scope, service| {
+            RateLimiterInstance::new(policy(name, scope, false), storage.clone(), service)
+        };
+        let (per_service, global) = (RateLimitScope::Service, RateLimitScope::Global);
+
+        // Services have buckets of their own.
+        assert_eq!(exhaust(&new("per-key", per_service, "api")).await, 10);
+        assert_eq!(exhaust(&new("per-key", per_service, "api")).await, 0);
+        assert_eq!(exhaust(&new("per-key", per_service, "web")).await, 10);
+
+        // Unless the policy is global.
+        assert_eq!(exhaust(&new("shared", global, "api")).await, 10);
+        assert_eq!(exhaust(&new("shared", global, "web")).await, 0);
+
+        // Policies on the same storage never share buckets.
+        assert_eq!(exhaust(&new("other", global, "api")).await, 10);
+    }
+
+    #[test]
+    fn test_headers() {
+        let allowed = RateLimitResult {
+            allowed: true,
+            remaining: 7,
+            reset_after: Duration::ZERO,
+        };
+        assert_eq!(instance(false).headers(&allowed), None);
+        assert_eq!(
+            instance(true).headers(&allowed),
+            Some(RateLimitHeaders {
+                limit: 10,
+                remaining: 7,
+                reset: 2,
+            })
+        );
+
+        let rejected = RateLimitResult {
+            allowed: false,
+            remaining: 0,
+            reset_after: Duration::from_millis(300),
+        };
+        assert_eq!(
+            instance(true).headers(&rejected),
+            Some(RateLimitHeaders {
+                limit: 10,
+                remaining: 0,
+                reset: 1,
+            })
+        );
+    }
+}
@@ -0,0 +1,34 @@
+//! Eviction counters of the in-memory rate limit key tables.
+//!
+//! Counters are kept per table name and survive config reloads; the admin API
+//! reports them as `motya_rate_limit_evictions_total`.
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+static EVICTIONS: Mutex<BTreeMap<String, Arc<AtomicU64>>> = Mutex::new(BTreeMap::new());
+
+/// The eviction counter of the key table `table`, created on first use.
+pub fn eviction_counter(table: &str) -> Arc<AtomicU64> {
+    EVICTIONS
+        .lock()
+        .expect("eviction counters lock poisoned")
+        .entry(table.to_string())
+        .or_default()
+        .clone()
+}
+
+/// A snapshot of every eviction counter, ordered by table name.
+pub fn evictions() -> Vec<(String, u64)> {
+    EVICTIONS
+        .lock()
+        .expect("eviction counters lock poisoned")
+        .iter()
+        .map(|(table, count)| (table.clone(), count.load(Ordering::Relaxed)))
+        .collect()
+}
@@ -1,3 +1,6 @@
+pub mod algorithm;
+pub mod headers;
 pub mod instance;
+pub mod metrics;
 pub mod registry;
 pub mod storage;
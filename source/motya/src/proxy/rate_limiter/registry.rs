@@ -1,19 +1,33 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
+use miette::miette;
 use motya_config::common_types::{
-    definitions_table::DefinitionsTable, rate_limiter::StorageConfig,
+    definitions_table::DefinitionsTable,
+    rate_limiter::{RateLimitPolicy, StorageConfig},
 };
 
-use crate::proxy::rate_limiter::storage::{MemoryStorage, RateLimitStorage};
+use crate::proxy::{
+    rate_limiter::storage::{MemoryStorage, RateLimitStorage, RedisStorage},
+    secrets,
+};
 
-#[derive(Clone, Default)]
+#[derive(Default)]
 pub struct StorageRegistry {
     storages: HashMap<String, Arc<dyn RateLimitStorage>>,
+    memory_cleanup_intervals: HashMap<String, Duration>,
+    /// Key tables of policies with their own `max-keys`, kept here so that every
+    /// route using a policy shares its buckets.
+    policy_tables: Mutex<HashMap<String, Arc<dyn RateLimitStorage>>>,
 }
 
 impl StorageRegistry {
     pub async fn new(table: &DefinitionsTable) -> miette::Result<Self> {
         let mut storages = HashMap::new();
+        let mut memory_cleanup_intervals = HashMap::new();
 
         for (name, config) in table.get_storages() {
             let storage: Arc<dyn RateLimitStorage> = match config {
@@ -21,30 +35,81 @@ impl StorageRegistry {
                     max_keys,
                     cleanup_interval,
                 } => {
-                    let mem = MemoryStorage::new(*max_keys as u64, *cleanup_interval);
+                    memory_cleanup_intervals.insert(name.clone(), *cleanup_interval);
+                    let mem = MemoryStorage::tracked(name, *max_keys as u64, *cleanup_interval);
                     Arc::new(mem)
                 }
 
                 StorageConfig::Redis {
-                    addresses: _,
-                    password: _,
-                    timeout: _,
+                    addresses,
+                    password,
+                    timeout,
                 } => {
-                    tracing::warn!(
-                        "Redis storage '{}' configured but implementation is pending",
-                        name
-                    );
-                    continue;
+                    let password = password.as_ref().map(secrets::resolve).transpose()?;
+                    let redis =
+                        RedisStorage::connect(name, addresses, password.as_deref(), *timeout)
+                            .await?;
+                    Arc::new(redis)
                 }
             };
 
             storages.insert(name.clone(), storage);
         }
 
-        Ok(Self { storages })
+        Ok(Self {
+            storages,
+            memory_cleanup_intervals,
+            policy_tables: Mutex::default(),
+        })
     }
 
     pub fn get(&self, name: &str) -> Option<Arc<dyn RateLimitStorage>> {
         self.storages.get(name).cloned()
     }
+
+    /// The storage `policy` keeps its buckets in.
+    ///
+    /// A policy with `max-keys` gets a key table of its own, with the cleanup interval
+    /// of the memory storage it names, so that one busy policy can't evict the keys
+    /// of the others.
+    pub fn for_policy(
+        &self,
+        policy: &RateLimitPolicy,
+    ) -> miette::Result<Arc<dyn RateLimitStorage>> {
+        let Some(max_keys) = policy.max_keys else {
+            return self.get(&policy.storage_key).ok_or_else(|| {
+                miette!(
+                    "Storage '{}' not found for rate limit policy '{}'",
+                    policy.storage_key,
+                    policy.name
+                )
+            });
+        };
+
+        let cleanup_interval = self
+            .memory_cleanup_intervals
+            .get(&policy.storage_key)
+            .ok_or_else(|| {
+                miette!(
+                    "Rate limit policy '{}' sets 'max-keys', but '{}' is not a memory storage",
+                    policy.name,
+                    policy.storage_key
+                )
+            })?;
+
+        let mut tables = self
+            .policy_tables
+            .lock()
+            .expect("policy tables lock poisoned");
+
+        let storage = tables.entry(policy.name.clone()).or_insert_with(|| {
+            Arc::new(MemoryStorage::tracked(
+                &policy.name,
+                max_keys as u64,
+                *cleanup_interval,
+            ))
+        });
+
+        Ok(storage.clone())
+    }
 }
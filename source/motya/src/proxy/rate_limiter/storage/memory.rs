@@ -0,0 +1,349 @@
+use std::{
+    future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use miette::{miette, Result};
+use moka::{future::Cache, notification::RemovalCause, policy::EvictionPolicy};
+use tokio::time::Instant;
+
+use crate::proxy::rate_limiter::{
+    algorithm::{Algorithm, KeyState},
+    metrics,
+    storage::{RateLimitResult, RateLimitStorage},
+};
+
+/// Rate-limit state kept in process memory, run by the algorithm of each policy.
+///
+/// Holds at most `max_keys` keys; once full, the least recently used key is
+/// evicted to make room, which resets the limit of that client.
+#[derive(Debug)]
+pub struct MemoryStorage {
+    cache: Cache<String, Entry>,
+    evictions: Arc<AtomicU64>,
+}
+
+impl MemoryStorage {
+    pub fn new(max_keys: u64, cleanup_interval: Duration) -> Self {
+        Self::with_counter(max_keys, cleanup_interval, Arc::default())
+    }
+
+    /// Like [`Self::new`], counting evictions under `table` in [`metrics`].
+    pub fn tracked(table: &str, max_keys: u64, cleanup_interval: Duration) -> Self {
+        Self::with_counter(max_keys, cleanup_interval, metrics::eviction_counter(table))
+    }
+
+    fn with_counter(max_keys: u64, cleanup_interval: Duration, evictions: Arc<AtomicU64>) -> Self {
+        let counter = evictions.clone();
+
+        let cache = Cache::builder()
+            .max_capacity(max_keys)
+            .time_to_idle(cleanup_interval)
+            .eviction_policy(EvictionPolicy::lru())
+            .eviction_listener(move |_key, _value, cause| {
+                if cause == RemovalCause::Size {
+                    counter.fetch_add(1, Ordering::Relaxed);
+                }
+            })
+            .build();
+
+        Self { cache, evictions }
+    }
+
+    /// Keys evicted so far because the storage was full.
+    pub fn evictions(&self) -> u64 {
+        self.evictions.load(Ordering::Relaxed)
+    }
+}
+
+/// The state of a key, with the outcome of the request that left it so.
+#[derive(Debug, Clone)]
+struct Entry {
+    state: KeyState,
+    outcome: RateLimitResult,
+}
+
+#[async_trait]
+impl RateLimitStorage for MemoryStorage {
+    async fn check_and_update(
+        &self,
+        key: &str,
+        algorithm: &dyn Algorithm,
+        rate_per_sec: f64,
+        burst: usize,
+        cost: u32,
+    ) -> Result<RateLimitResult> {
+        if rate_per_sec <= 0.0 {
+            return Err(miette!("rate_per_sec value cannot be less than zero"));
+        }
+
+        let now = Instant::now();
+
+        let entry = self
+            .cache
+            .entry_by_ref(key)
+            .and_upsert_with(|entry| {
+                let previous = entry.map(|v| v.into_value());
+                let (state, outcome) = algorithm.check(
+                    previous.as_ref().map(|entry| &entry.state),
+                    now,
+                    rate_per_sec,
+                    burst,
+                    cost,
+                );
+                future::ready(Entry { state, outcome })
+            })
+            .await
+            .into_value();
+
+        Ok(entry.outcome)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::time::{sleep, Duration};
+
+    use super::*;
+    use crate::proxy::rate_limiter::algorithm::{Gcra, SlidingWindow, TokenBucket};
+
+    fn create_storage() -> MemoryStorage {
+        MemoryStorage::new(1000, Duration::from_secs(60))
+    }
+
+    #[tokio::test]
+    async fn test_basic_allow() {
+        let storage = create_storage();
+        let key = "test_client";
+
+        let result = storage
+            .check_and_update(key, &TokenBucket, 10.0, 5, 1)
+            .await
+            .unwrap();
+
+        assert!(result.allowed, "First request should be allowed");
+        assert_eq!(result.remaining, 4, "Should consume 1 token");
+        assert_eq!(result.reset_after, Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn test_burst_depletion() {
+        let storage = create_storage();
+        let key = "greedy_client";
+
+        let rate = 1.0;
+        let burst = 3;
+
+        for i in 0..burst {
+            let res = storage
+                .check_and_update(key, &TokenBucket, rate, burst, 1)
+                .await
+                .unwrap();
+            assert!(res.allowed, "Request {} should be allowed", i);
+            assert_eq!(res.remaining, burst - 1 - i);
+        }
+
+        let res = storage
+            .check_and_update(key, &TokenBucket, rate, burst, 1)
+            .await
+            .unwrap();
+        assert!(!res.allowed, "Request exceeding burst should be denied");
+        assert_eq!(res.remaining, 0);
+
+        assert!(res.reset_after.as_millis() > 900);
+    }
+
+    #[tokio::test]
+    async fn test_refill_over_time() {
+        let storage = create_storage();
+        let key = "patient_client";
+
+        let rate = 10.0;
+        let burst = 1;
+
+        let res = storage
+            .check_and_update(key, &TokenBucket, rate, burst, 1)
+            .await
+            .unwrap();
+        assert!(res.allowed);
+        assert_eq!(res.remaining, 0);
+
+        let res = storage
+            .check_and_update(key, &TokenBucket, rate, burst, 1)
+            .await
+            .unwrap();
+        assert!(!res.allowed);
+
+        sleep(Duration::from_millis(150)).await;
+
+        let res = storage
+            .check_and_update(key, &TokenBucket, rate, burst, 1)
+            .await
+            .unwrap();
+        assert!(res.allowed, "Token should be refilled after wait");
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_no_race_condition() {
+        let storage = Arc::new(create_storage());
+        let key = "concurrent_key";
+
+        let rate = 0.01;
+        let burst = 100;
+        let cost = 1;
+
+        let mut handles = vec![];
+
+        for _ in 0..burst {
+            let s = storage.clone();
+            let k = key.to_string();
+            handles.push(tokio::spawn(async move {
+                s.check_and_update(&k, &TokenBucket, rate, burst, cost)
+                    .await
+                    .unwrap()
+            }));
+        }
+
+        let mut success_count = 0;
+        for h in handles {
+            let res = h.await.unwrap();
+            if res.allowed {
+                success_count += 1;
+            }
+        }
+
+        assert_eq!(
+            success_count, 100,
+            "Exactly 100 requests should pass in parallel"
+        );
+
+        let final_res = storage
+            .check_and_update(key, &TokenBucket, rate, burst, cost)
+            .await
+            .unwrap();
+        assert!(!final_res.allowed, "Bucket should be exactly empty");
+        assert_eq!(final_res.remaining, 0);
+    }
+
+    #[tokio::test]
+    async fn test_bucket_overflow_protection() {
+        let storage = create_storage();
+        let key = "overflow_check";
+
+        let rate = 100.0;
+        let burst = 5;
+
+        storage
+            .check_and_update(key, &TokenBucket, rate, burst, 1)
+            .await
+            .unwrap();
+
+        sleep(Duration::from_millis(200)).await;
+
+        let res = storage
+            .check_and_update(key, &TokenBucket, rate, burst, 0)
+            .await
+            .unwrap();
+
+        assert_eq!(res.remaining, 5, "Tokens should be capped at burst size");
+    }
+
+    #[tokio::test]
+    async fn test_policies_with_other_algorithms_share_the_storage() {
+        let storage = create_storage();
+        let algorithms: [(&str, &dyn Algorithm); 3] = [
+            ("bucket", &TokenBucket),
+            ("window", &SlidingWindow),
+            ("gcra", &Gcra),
+        ];
+
+        for (key, algorithm) in algorithms {
+            for i in 0..2 {
+                let res = storage
+                    .check_and_update(key, algorithm, 0.01, 2, 1)
+                    .await
+                    .unwrap();
+                assert!(res.allowed, "{key}: request {i}");
+                assert_eq!(res.remaining, 1 - i, "{key}: request {i}");
+            }
+
+            let res = storage
+                .check_and_update(key, algorithm, 0.01, 2, 1)
+                .await
+                .unwrap();
+            assert!(!res.allowed, "{key}");
+            assert!(res.reset_after > Duration::from_secs(1), "{key}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lru_eviction_is_counted() {
+        let storage = MemoryStorage::new(2, Duration::from_secs(60));
+
+        for key in ["a", "b"] {
+            storage
+                .check_and_update(key, &TokenBucket, 1.0, 1, 1)
+                .await
+                .unwrap();
+        }
+        storage.cache.run_pending_tasks().await;
+
+        // Touch "a" so that "b" is the least recently used key.
+        storage
+            .check_and_update("a", &TokenBucket, 1.0, 1, 1)
+            .await
+            .unwrap();
+        storage
+            .check_and_update("c", &TokenBucket, 1.0, 1, 1)
+            .await
+            .unwrap();
+        storage.cache.run_pending_tasks().await;
+
+        assert_eq!(storage.evictions(), 1);
+        assert!(storage.cache.contains_key("a"));
+        assert!(!storage.cache.contains_key("b"));
+
+        let res = storage
+            .check_and_update("a", &TokenBucket, 1.0, 1, 1)
+            .await
+            .unwrap();
+        assert!(!res.allowed, "The bucket of a kept key must not be reset");
+    }
+
+    #[tokio::test]
+    async fn test_cost_higher_than_one() {
+        let storage = create_storage();
+        let key = "heavy_request";
+
+        let rate = 0.01;
+        let burst = 10;
+        let cost = 5;
+
+        let res1 = storage
+            .check_and_update(key, &TokenBucket, rate, burst, cost)
+            .await
+            .unwrap();
+        assert!(res1.allowed);
+        assert_eq!(res1.remaining, 5);
+
+        let res2 = storage
+            .check_and_update(key, &TokenBucket, rate, burst, cost)
+            .await
+            .unwrap();
+        assert!(res2.allowed);
+        assert_eq!(res2.remaining, 0);
+
+        let res3 = storage
+            .check_and_update(key, &TokenBucket, rate, burst, cost)
+            .await
+            .unwrap();
+        assert!(!res3.allowed);
+    }
+}
@@ -0,0 +1,39 @@
+//! Where rate-limit counters live.
+//!
+//! [`MemoryStorage`] keeps the state of the policy's [`Algorithm`] in the
+//! process, so every motya instance limits on its own. [`RedisStorage`] keeps
+//! counters in Redis, so instances that point at the same server share their limits.
+
+use std::{fmt::Debug, time::Duration};
+
+use async_trait::async_trait;
+use miette::Result;
+
+use crate::proxy::rate_limiter::algorithm::Algorithm;
+
+mod memory;
+mod redis;
+
+pub use self::{memory::MemoryStorage, redis::RedisStorage};
+
+#[derive(Debug, Clone)]
+pub struct RateLimitResult {
+    pub allowed: bool,
+    pub remaining: usize,
+    pub reset_after: Duration,
+}
+
+#[async_trait]
+pub trait RateLimitStorage: Send + Sync + Debug {
+    /// Decides on a request of `key` with `algorithm`. Redis counts in fixed
+    /// windows instead, and the configuration lets only token-bucket policies
+    /// use it.
+    async fn check_and_update(
+        &self,
+        key: &str,
+        algorithm: &dyn Algorithm,
+        rate_per_sec: f64,
+        burst: usize,
+        cost: u32,
+    ) -> Result<RateLimitResult>;
+}
@@ -0,0 +1,236 @@
+use std::{
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use ::redis::{aio::ConnectionManager, Client, IntoConnectionInfo};
+use async_trait::async_trait;
+use miette::{miette, IntoDiagnostic, Result};
+
+use crate::proxy::rate_limiter::{
+    algorithm::Algorithm,
+    storage::{RateLimitResult, RateLimitStorage},
+};
+
+/// How long a check may wait for Redis when the storage sets no `timeout`.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Fixed-window counters kept in Redis, shared by every motya instance that
+/// points at the same server.
+///
+/// A window lasts as long as an empty bucket takes to refill (`burst / rate`),
+/// and lets `burst` requests through. Each check is one `MULTI` pipeline of
+/// `INCRBY` and `PEXPIRE` on the counter of the current window, so a counter
+/// disappears on its own once its window is over. Windows are derived from the
+/// wall clock, so the instances sharing a server should keep their clocks in sync.
+/// The configuration lets only policies with the default `token-bucket` algorithm
+/// use a Redis storage, and these windows stand in for its bucket.
+///
+/// When Redis fails or does not answer within the timeout, the request is let
+/// through: an unreachable Redis must not take the routes it guards down with it.
+#[derive(Clone)]
+pub struct RedisStorage {
+    name: String,
+    conn: ConnectionManager,
+    timeout: Duration,
+}
+
+impl fmt::Debug for RedisStorage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RedisStorage")
+            .field("name", &self.name)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+impl RedisStorage {
+    /// Connects to the first of `addresses` that accepts a connection. The
+    /// connection is re-established in the background if it drops later.
+    pub async fn connect(
+        name: &str,
+        addresses: &[String],
+        password: Option<&str>,
+        timeout: Option<Duration>,
+    ) -> Result<Self> {
+        let mut last_error = None;
+
+        for address in addresses {
+            match Self::connect_to(address, password).await {
+                Ok(conn) => {
+                    return Ok(Self {
+                        name: name.to_string(),
+                        conn,
+                        timeout: timeout.unwrap_or(DEFAULT_TIMEOUT),
+                    });
+                }
+                Err(err) => {
+                    tracing::warn!("Redis storage '{name}' could not connect to {address}: {err}");
+                    last_error = Some(err);
+                }
+            }
+        }
+
+        Err(match last_error {
+            Some(err) => err.wrap_err(format!("Redis storage '{name}' has no reachable address")),
+            None => miette!("Redis storage '{name}' has no addresses"),
+        })
+    }
+
+    async fn connect_to(address: &str, password: Option<&str>) -> Result<ConnectionManager> {
+        let url = if address.contains("://") {
+            address.to_string()
+        } else {
+            format!("redis://{address}")
+        };
+
+        let mut info = url.into_connection_info().into_diagnostic()?;
+        if let Some(password) = password {
+            info.redis.password = Some(password.to_string());
+        }
+
+        let client = Client::open(info).into_diagnostic()?;
+        ConnectionManager::new(client).await.into_diagnostic()
+    }
+
+    async fn increment(&self, window: &Window, cost: u32) -> Result<u64> {
+        let mut conn = self.conn.clone();
+
+        let pipeline = ::redis::pipe()
+            .atomic()
+            .incr(&window.key, cost)
+            .pexpire(&window.key, window.length.as_millis() as i64)
+            .ignore()
+            .query_async::<(u64,)>(&mut conn);
+
+        let (count,) = tokio::time::timeout(self.timeout, pipeline)
+            .await
+            .map_err(|_| miette!("timed out after {:?}", self.timeout))?
+            .into_diagnostic()?;
+
+        Ok(count)
+    }
+}
+
+#[async_trait]
+impl RateLimitStorage for RedisStorage {
+    async fn check_and_update(
+        &self,
+        key: &str,
+        _algorithm: &dyn Algorithm,
+        rate_per_sec: f64,
+        burst: usize,
+        cost: u32,
+    ) -> Result<RateLimitResult> {
+        if rate_per_sec <= 0.0 {
+            return Err(miette!("rate_per_sec value cannot be less than zero"));
+        }
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let window = Window::at(&self.name, key, rate_per_sec, burst, now);
+
+        match self.increment(&window, cost).await {
+            Ok(count) => Ok(window.outcome(count, burst)),
+            Err(err) => {
+                tracing::warn!(
+                    "Redis storage '{}' failed, letting the request through: {err}",
+                    self.name
+                );
+                Ok(RateLimitResult {
+                    allowed: true,
+                    remaining: burst,
+                    reset_after: Duration::ZERO,
+                })
+            }
+        }
+    }
+}
+
+/// The counting window a request falls into.
+#[derive(Debug, PartialEq)]
+struct Window {
+    key: String,
+    length: Duration,
+    /// Time left until the next window starts.
+    remaining: Duration,
+}
+
+impl Window {
+    fn at(storage: &str, key: &str, rate_per_sec: f64, burst: usize, now: Duration) -> Self {
+        let length_ms = ((burst as f64 / rate_per_sec) * 1000.0).ceil().max(1.0) as u128;
+
+        let now_ms = now.as_millis();
+        let index = now_ms / length_ms;
+        let remaining_ms = length_ms - now_ms % length_ms;
+
+        Self {
+            key: format!("motya:{storage}:{key}:{index}"),
+            length: Duration::from_millis(length_ms as u64),
+            remaining: Duration::from_millis(remaining_ms as u64),
+        }
+    }
+
+    /// The result of a request that brought the window's counter to `count`.
+    fn outcome(&self, count: u64, burst: usize) -> RateLimitResult {
+        let allowed = count <= burst as u64;
+
+        RateLimitResult {
+            allowed,
+            remaining: (burst as u64).saturating_sub(count) as usize,
+            reset_after: if allowed {
+                Duration::ZERO
+            } else {
+                self.remaining
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_window_length_is_the_refill_time() {
+        let window = Window::at("shared", "client", 10.0, 50, Duration::from_millis(12_345));
+
+        assert_eq!(window.length, Duration::from_secs(5));
+        assert_eq!(window.remaining, Duration::from_millis(2_655));
+        assert_eq!(window.key, "motya:shared:client:2");
+    }
+
+    #[test]
+    fn test_requests_in_the_same_window_share_a_counter() {
+        let at = |ms| Window::at("shared", "client", 1.0, 2, Duration::from_millis(ms)).key;
+
+        assert_eq!(at(4_000), at(5_999));
+        assert_ne!(at(5_999), at(6_000));
+    }
+
+    #[test]
+    fn test_fast_rates_get_a_window_of_at_least_a_millisecond() {
+        let window = Window::at("shared", "client", 1_000_000.0, 1, Duration::ZERO);
+        assert_eq!(window.length, Duration::from_millis(1));
+    }
+
+    #[test]
+    fn test_outcome() {
+        let window = Window::at("shared", "client", 1.0, 3, Duration::from_millis(1_000));
+
+        let first = window.outcome(1, 3);
+        assert!(first.allowed);
+        assert_eq!(first.remaining, 2);
+        assert_eq!(first.reset_after, Duration::ZERO);
+
+        let last = window.outcome(3, 3);
+        assert!(last.allowed);
+        assert_eq!(last.remaining, 0);
+
+        let over = window.outcome(4, 3);
+        assert!(!over.allowed);
+        assert_eq!(over.remaining, 0);
+        assert_eq!(over.reset_after, Duration::from_secs(2));
+    }
+}
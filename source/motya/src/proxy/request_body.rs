@@ -0,0 +1,116 @@
+//! Request bodies for filters that need all of it at once, such as plugins
+//! with `request-body` set.
+//!
+//! Chunks are held back until the body is complete, then every body filter of
+//! the route runs over the whole body and the result is sent upstream. The
+//! filters may change its length, so the upstream request carries none.
+
+use bytes::{Bytes, BytesMut};
+use http::{header, Version};
+use pingora::{Error, ErrorType, Result};
+use pingora_http::RequestHeader;
+
+use crate::proxy::filters::chain_resolver::RuntimeChain;
+
+/// The smallest body limit among the body filters of `chains`, or `None` if the
+/// route has no body filters.
+pub fn limit(chains: &[RuntimeChain]) -> Option<usize> {
+    chains
+        .iter()
+        .flat_map(|chain| &chain.body_mods)
+        .map(|filter| filter.max_body_size())
+        .min()
+}
+
+/// Whether a body follows the head of `request`.
+pub fn has_body(request: &RequestHeader) -> bool {
+    request.headers.contains_key(header::TRANSFER_ENCODING)
+        || request
+            .headers
+            .get(header::CONTENT_LENGTH)
+            .is_some_and(|len| len.as_bytes() != b"0")
+}
+
+/// Drops the length of a request with a body, since it is only known once the
+/// body filters have run. HTTP/1.1 upstreams get the body chunked instead.
+///
+/// Returns whether the request has a body at all.
+pub fn prepare_request(request: &mut RequestHeader) -> Result<bool> {
+    if !has_body(request) {
+        return Ok(false);
+    }
+
+    request.remove_header(&header::CONTENT_LENGTH);
+    // HTTP/2 frames the body itself.
+    if request.version != Version::HTTP_2 {
+        request.insert_header(header::TRANSFER_ENCODING, "chunked")?;
+    }
+
+    Ok(true)
+}
+
+/// Collects the chunks of one request body, up to `limit` bytes.
+pub struct BodyBuffer {
+    limit: usize,
+    data: BytesMut,
+}
+
+impl BodyBuffer {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            data: BytesMut::new(),
+        }
+    }
+
+    /// Adds a chunk, failing with a 413 once the body outgrows the limit.
+    pub fn push(&mut self, chunk: &[u8]) -> Result<()> {
+        if self.data.len() + chunk.len() > self.limit {
+            return Err(Error::explain(
+                ErrorType::HTTPStatus(413),
+                format!("request body is larger than {} bytes", self.limit),
+            ));
+        }
+
+        self.data.extend_from_slice(chunk);
+        Ok(())
+    }
+
+    pub fn into_bytes(self) -> Bytes {
+        self.data.freeze()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buffer_limit() {
+        let mut buffer = BodyBuffer::new(8);
+        buffer.push(b"hello").unwrap();
+        buffer.push(b"!!!").unwrap();
+
+        let err = buffer.push(b"?").unwrap_err();
+        assert_eq!(err.etype(), &ErrorType::HTTPStatus(413));
+
+        assert_eq!(buffer.into_bytes(), Bytes::from_static(b"hello!!!"));
+    }
+
+    #[test]
+    fn test_prepare_request() {
+        let mut request = RequestHeader::build("POST", b"/sign", None).unwrap();
+        request.insert_header(header::CONTENT_LENGTH, "11").unwrap();
+        assert!(prepare_request(&mut request).unwrap());
+
+        assert!(request.headers.get(header::CONTENT_LENGTH).is_none());
+        assert_eq!(
+            request.headers.get(header::TRANSFER_ENCODING).unwrap(),
+            "chunked"
+        );
+
+        let mut request = RequestHeader::build("GET", b"/", None).unwrap();
+        assert!(!prepare_request(&mut request).unwrap());
+        assert!(request.headers.get(header::TRANSFER_ENCODING).is_none());
+    }
+}
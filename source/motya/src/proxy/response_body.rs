@@ -0,0 +1,395 @@
+//! Response bodies for filters that need all of it at once, such as `rewrite-body`.
+//!
+//! Whether a response is held back is decided with its head: some body filter of
+//! the route must want its content type, the body must not be encoded, and a
+//! length it announces must be within the limit of those filters. The filters
+//! may change the length, so the response carries none and pingora frames it.
+//! A body that turns out larger than the limit goes out unchanged.
+//!
+//! Routes whose filters decode upstream bodies (`upstream-encoding="decode"`) also
+//! hold back gzip and deflate bodies. The filters see them decoded, and the result
+//! is encoded again the same way, so the client gets the encoding it accepted.
+
+use std::io::{Read, Write};
+
+use bytes::{Bytes, BytesMut};
+use flate2::{
+    read::{GzDecoder, ZlibDecoder},
+    write::{GzEncoder, ZlibEncoder},
+    Compression,
+};
+use http::{header, Method, StatusCode};
+use pingora_http::{RequestHeader, ResponseHeader};
+
+use crate::proxy::filters::chain_resolver::RuntimeChain;
+
+/// Whether the route has response body filters.
+pub fn has_filters(chains: &[RuntimeChain]) -> bool {
+    chains.iter().any(|chain| !chain.res_body_mods.is_empty())
+}
+
+/// Whether some response body filter of the route decodes upstream bodies.
+pub fn decodes_upstream(chains: &[RuntimeChain]) -> bool {
+    chains
+        .iter()
+        .flat_map(|chain| &chain.res_body_mods)
+        .any(|filter| filter.decodes_upstream())
+}
+
+/// Asks the upstream for a body the filters can read: unencoded, or when they
+/// decode it, in the encodings of [BodyEncoding] the client accepts as well.
+pub fn prepare_request(chains: &[RuntimeChain], request: &mut RequestHeader) {
+    let accepted = request
+        .headers
+        .get(header::ACCEPT_ENCODING)
+        .filter(|_| decodes_upstream(chains))
+        .and_then(|v| v.to_str().ok())
+        .map(|v| {
+            let mut accepted: Vec<&str> = v
+                .split(',')
+                .filter(|coding| !refused(coding))
+                .filter_map(|coding| BodyEncoding::of(coding.split(';').next()?.trim()))
+                .map(BodyEncoding::token)
+                .collect();
+            accepted.dedup();
+            accepted.join(", ")
+        })
+        .filter(|accepted| !accepted.is_empty());
+
+    match accepted {
+        Some(accepted) => {
+            let _ = request.insert_header(header::ACCEPT_ENCODING, accepted);
+        }
+        None => {
+            request.remove_header(&header::ACCEPT_ENCODING);
+        }
+    }
+}
+
+/// Whether an `Accept-Encoding` entry turns its coding down, with `q=0`.
+fn refused(coding: &str) -> bool {
+    coding.split(';').skip(1).any(|param| {
+        param
+            .trim()
+            .strip_prefix("q=")
+            .and_then(|q| q.parse::<f32>().ok())
+            == Some(0.0)
+    })
+}
+
+/// A `Content-Encoding` that filters with `upstream-encoding="decode"` read through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyEncoding {
+    Gzip,
+    /// The zlib format, which is what HTTP calls `deflate`.
+    Deflate,
+}
+
+impl BodyEncoding {
+    fn of(token: &str) -> Option<Self> {
+        if token.eq_ignore_ascii_case("gzip") || token.eq_ignore_ascii_case("x-gzip") {
+            Some(Self::Gzip)
+        } else if token.eq_ignore_ascii_case("deflate") {
+            Some(Self::Deflate)
+        } else {
+            None
+        }
+    }
+
+    fn token(self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Deflate => "deflate",
+        }
+    }
+
+    /// Decodes `data`, unless it is corrupt or decodes to more than `limit` bytes.
+    pub fn decode(self, data: &[u8], limit: usize) -> Option<Bytes> {
+        let mut decoded = Vec::new();
+        let cap = limit as u64 + 1;
+        let read = match self {
+            Self::Gzip => GzDecoder::new(data).take(cap).read_to_end(&mut decoded),
+            Self::Deflate => ZlibDecoder::new(data).take(cap).read_to_end(&mut decoded),
+        };
+
+        (read.is_ok() && decoded.len() <= limit).then(|| decoded.into())
+    }
+
+    pub fn encode(self, data: &[u8]) -> Bytes {
+        let encoded = match self {
+            Self::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data).and_then(|()| encoder.finish())
+            }
+            Self::Deflate => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data).and_then(|()| encoder.finish())
+            }
+        };
+
+        encoded.expect("encoding into memory does not fail").into()
+    }
+}
+
+/// Starts holding back the body of `response`, if the filters of `chains` want it.
+pub fn prepare_response(
+    chains: &[RuntimeChain],
+    request: &RequestHeader,
+    response: &mut ResponseHeader,
+) -> Option<ResponseBodyBuffer> {
+    let no_body = request.method == Method::HEAD
+        || response.status.is_informational()
+        || response.status == StatusCode::NO_CONTENT
+        || response.status == StatusCode::NOT_MODIFIED;
+    if no_body {
+        return None;
+    }
+
+    // An upstream may encode the body even when not asked to.
+    let encoding = match response.headers.get(header::CONTENT_ENCODING) {
+        None => None,
+        Some(encoding) if encoding.as_bytes() == b"identity" => None,
+        Some(encoding) => Some(
+            encoding
+                .to_str()
+                .ok()
+                .and_then(|token| BodyEncoding::of(token.trim()))
+                .filter(|_| decodes_upstream(chains))?,
+        ),
+    };
+
+    let content_type = response
+        .headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    let limit = chains
+        .iter()
+        .flat_map(|chain| &chain.res_body_mods)
+        .filter(|filter| filter.applies_to(content_type.as_deref()))
+        .map(|filter| filter.max_body_size())
+        .min()?;
+
+    let length = response
+        .headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|len| len.to_str().ok()?.parse::<usize>().ok());
+    if length.is_some_and(|length| length > limit) {
+        return None;
+    }
+
+    response.remove_header(&header::CONTENT_LENGTH);
+    Some(ResponseBodyBuffer {
+        limit,
+        content_type,
+        encoding,
+        data: BytesMut::new(),
+    })
+}
+
+/// Collects the chunks of one response body, up to `limit` bytes.
+pub struct ResponseBodyBuffer {
+    limit: usize,
+    content_type: Option<String>,
+    encoding: Option<BodyEncoding>,
+    data: BytesMut,
+}
+
+impl ResponseBodyBuffer {
+    /// The content type of the response, to pick the filters that run.
+    pub fn content_type(&self) -> Option<&str> {
+        self.content_type.as_deref()
+    }
+
+    /// The encoding the body arrives in, and has to leave in.
+    pub fn encoding(&self) -> Option<BodyEncoding> {
+        self.encoding
+    }
+
+    /// Adds a chunk. Returns `false` once the body is too large to be held back,
+    /// and the chunk was not added.
+    pub fn push(&mut self, chunk: &[u8]) -> bool {
+        if self.data.len() + chunk.len() > self.limit {
+            return false;
+        }
+        self.data.extend_from_slice(chunk);
+        true
+    }
+
+    pub fn into_bytes(self) -> Bytes {
+        self.data.freeze()
+    }
+
+    /// The body as the filters read it, decoded if it is encoded. A body that
+    /// does not decode, or decodes to more than the limit, is returned in `Err`
+    /// to go out as it is.
+    pub fn into_decoded(self) -> Result<Bytes, Bytes> {
+        let data = self.data.freeze();
+        match self.encoding {
+            None => Ok(data),
+            Some(encoding) => encoding.decode(&data, self.limit).ok_or(data),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use motya_config::common_types::value::Value;
+
+    use super::*;
+    use crate::proxy::filters::builtin::rewrite_body::RewriteBody;
+
+    fn response(content_type: &str, length: Option<usize>) -> ResponseHeader {
+        let mut response = ResponseHeader::build(200, None).unwrap();
+        response
+            .insert_header(header::CONTENT_TYPE, content_type)
+            .unwrap();
+        if let Some(length) = length {
+            response
+                .insert_header(header::CONTENT_LENGTH, length.to_string())
+                .unwrap();
+        }
+        response
+    }
+
+    #[test]
+    fn test_buffer_limit() {
+        let mut buffer = ResponseBodyBuffer {
+            limit: 8,
+            content_type: None,
+            encoding: None,
+            data: BytesMut::new(),
+        };
+        assert!(buffer.push(b"hello"));
+        assert!(buffer.push(b"!!!"));
+        assert!(!buffer.push(b"?"));
+
+        assert_eq!(buffer.into_bytes(), Bytes::from_static(b"hello!!!"));
+    }
+
+    fn chains(settings: &[(&str, &str)]) -> [RuntimeChain; 1] {
+        let filter = RewriteBody::from_settings(
+            settings
+                .iter()
+                .map(|(k, v)| (k.to_string(), Value::String(v.to_string())))
+                .collect(),
+        )
+        .unwrap();
+        let mut chain = RuntimeChain::default();
+        chain.res_body_mods.push(Box::new(filter));
+        [chain]
+    }
+
+    #[test]
+    fn test_prepare_response() {
+        let chains = chains(&[("find", "x"), ("replace", "y"), ("max-body", "16")]);
+        let get = RequestHeader::build("GET", b"/", None).unwrap();
+
+        let mut html = response("text/html", Some(12));
+        let buffer = prepare_response(&chains, &get, &mut html).unwrap();
+        assert_eq!(buffer.content_type(), Some("text/html"));
+        assert!(html.headers.get(header::CONTENT_LENGTH).is_none());
+
+        let mut large = response("text/html", Some(17));
+        assert!(prepare_response(&chains, &get, &mut large).is_none());
+        assert!(large.headers.get(header::CONTENT_LENGTH).is_some());
+
+        let mut image = response("image/png", None);
+        assert!(prepare_response(&chains, &get, &mut image).is_none());
+
+        let mut gzipped = response("text/html", None);
+        gzipped
+            .insert_header(header::CONTENT_ENCODING, "gzip")
+            .unwrap();
+        assert!(prepare_response(&chains, &get, &mut gzipped).is_none());
+
+        let head = RequestHeader::build("HEAD", b"/", None).unwrap();
+        let mut html = response("text/html", Some(12));
+        assert!(prepare_response(&chains, &head, &mut html).is_none());
+
+        assert!(prepare_response(&[], &get, &mut html).is_none());
+    }
+
+    #[test]
+    fn test_prepare_request() {
+        let accept_encoding = |chains: &[RuntimeChain], accepted: &str| {
+            let mut request = RequestHeader::build("GET", b"/", None).unwrap();
+            request
+                .insert_header(header::ACCEPT_ENCODING, accepted)
+                .unwrap();
+            prepare_request(chains, &mut request);
+            request
+                .headers
+                .get(header::ACCEPT_ENCODING)
+                .map(|v| v.to_str().unwrap().to_string())
+        };
+        let identity = chains(&[("find", "x"), ("replace", "y")]);
+        let decode = chains(&[
+            ("find", "x"),
+            ("replace", "y"),
+            ("upstream-encoding", "decode"),
+        ]);
+
+        assert_eq!(accept_encoding(&identity, "gzip, br"), None);
+        assert_eq!(
+            accept_encoding(&decode, "br, gzip;q=0.8, deflate").as_deref(),
+            Some("gzip, deflate")
+        );
+        assert_eq!(accept_encoding(&decode, "gzip;q=0, br"), None);
+    }
+
+    #[test]
+    fn test_encoded_bodies_are_held_back_for_decoding_filters() {
+        let get = RequestHeader::build("GET", b"/", None).unwrap();
+        let gzipped = || {
+            let mut response = response("text/html", None);
+            response
+                .insert_header(header::CONTENT_ENCODING, "gzip")
+                .unwrap();
+            response
+        };
+
+        let identity = chains(&[("find", "x"), ("replace", "y")]);
+        assert!(prepare_response(&identity, &get, &mut gzipped()).is_none());
+
+        let decode = chains(&[
+            ("find", "x"),
+            ("replace", "y"),
+            ("upstream-encoding", "decode"),
+        ]);
+        let buffer = prepare_response(&decode, &get, &mut gzipped()).unwrap();
+        assert_eq!(buffer.encoding(), Some(BodyEncoding::Gzip));
+
+        let mut brotli = response("text/html", None);
+        brotli
+            .insert_header(header::CONTENT_ENCODING, "br")
+            .unwrap();
+        assert!(prepare_response(&decode, &get, &mut brotli).is_none());
+    }
+
+    #[test]
+    fn test_decoding() {
+        for encoding in [BodyEncoding::Gzip, BodyEncoding::Deflate] {
+            let mut buffer = ResponseBodyBuffer {
+                limit: 64,
+                content_type: None,
+                encoding: Some(encoding),
+                data: BytesMut::new(),
+            };
+            assert!(buffer.push(&encoding.encode(b"hello")));
+            assert_eq!(buffer.into_decoded(), Ok(Bytes::from_static(b"hello")));
+
+            let large = encoding.encode(&[b'a'; 17]);
+            assert_eq!(encoding.decode(&large, 16), None);
+            assert_eq!(encoding.decode(&large, 17).unwrap().len(), 17);
+        }
+
+        let corrupt = ResponseBodyBuffer {
+            limit: 16,
+            content_type: None,
+            encoding: Some(BodyEncoding::Gzip),
+            data: BytesMut::from(&b"not gzip"[..]),
+        };
+        assert_eq!(corrupt.into_decoded(), Err(Bytes::from_static(b"not gzip")));
+    }
+}
@@ -0,0 +1,205 @@
+//! Retries of failed upstream requests, for routes with a `retry` directive.
+//!
+//! pingora asks for a new peer whenever a failed attempt comes back marked as
+//! retryable, so a retry is decided by marking the error. The backends a request
+//! failed on are remembered, and the balancer passes over them on the next pick.
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use http::Method;
+use motya_config::common_types::connectors::RetryConfig;
+use pingora::protocols::l4::socket::SocketAddr;
+
+/// Why an attempt failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// No connection to the backend could be made.
+    Connect,
+    /// The connection failed before the response started.
+    Error,
+    /// The backend answered with this status.
+    Status(u16),
+}
+
+/// The `retry` directive of a route, with the retries it has in flight.
+pub struct RetryPolicy {
+    config: RetryConfig,
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl RetryPolicy {
+    pub fn new(config: RetryConfig) -> Self {
+        Self {
+            config,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    fn covers(&self, failure: Failure, method: &Method) -> bool {
+        let on = &self.config.on;
+        match failure {
+            // Nothing reached the backend, so any request can go again.
+            Failure::Connect => on.connect_failure,
+            Failure::Error => on.error && method.is_idempotent(),
+            Failure::Status(status) => status >= 500 && on.server_error && method.is_idempotent(),
+        }
+    }
+
+    fn acquire(&self) -> Option<RetryPermit> {
+        self.in_flight
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < self.config.max_concurrent).then_some(n + 1)
+            })
+            .ok()
+            .map(|_| RetryPermit(self.in_flight.clone()))
+    }
+}
+
+/// Counts a request against its route's `max-concurrent` until it is done.
+struct RetryPermit(Arc<AtomicUsize>);
+
+impl Drop for RetryPermit {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// The retries of one request.
+#[derive(Default)]
+pub struct RetryState {
+    /// Retries taken so far.
+    attempts: usize,
+    /// Backends the request was sent to, in order.
+    tried: Vec<SocketAddr>,
+    /// Taken with the first retry, so a request counts once however often it
+    /// is retried.
+    permit: Option<RetryPermit>,
+    /// A retry was decided outside of pingora's error phases, and the error
+    /// they see next is already accounted for.
+    pending: bool,
+}
+
+impl RetryState {
+    pub fn tried(&self) -> &[SocketAddr] {
+        &self.tried
+    }
+
+    pub fn record_peer(&mut self, addr: SocketAddr) {
+        self.tried.push(addr);
+    }
+
+    /// How long to wait before the current attempt: nothing for the first one,
+    /// then `backoff`, doubled for each retry after.
+    pub fn backoff(&self, policy: &RetryPolicy) -> Option<Duration> {
+        let backoff = policy.config.backoff;
+        if self.attempts == 0 || backoff.is_zero() {
+            return None;
+        }
+
+        let factor = 1u32 << (self.attempts - 1).min(16);
+        Some(backoff.saturating_mul(factor))
+    }
+
+    /// Takes a retry for `failure` if the policy covers it and has retries left,
+    /// both for this request and for the route.
+    pub fn try_retry(&mut self, policy: &RetryPolicy, failure: Failure, method: &Method) -> bool {
+        if !policy.covers(failure, method) || self.attempts >= policy.config.attempts {
+            return false;
+        }
+
+        if self.permit.is_none() {
+            match policy.acquire() {
+                Some(permit) => self.permit = Some(permit),
+                None => {
+                    tracing::debug!("Not retrying {failure:?}: 'max-concurrent' retries in flight");
+                    return false;
+                }
+            }
+        }
+
+        self.attempts += 1;
+        true
+    }
+
+    /// Like [`RetryState::try_retry`], for a failure pingora does not report as
+    /// an error by itself. [`RetryState::take_pending`] tells the error phase.
+    pub fn try_retry_pending(
+        &mut self,
+        policy: &RetryPolicy,
+        failure: Failure,
+        method: &Method,
+    ) -> bool {
+        self.pending = self.try_retry(policy, failure, method);
+        self.pending
+    }
+
+    pub fn take_pending(&mut self) -> bool {
+        std::mem::take(&mut self.pending)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use motya_config::common_types::connectors::RetryOn;
+
+    use super::*;
+
+    fn policy(on: &str, attempts: usize, max_concurrent: usize) -> RetryPolicy {
+        RetryPolicy::new(RetryConfig {
+            attempts,
+            on: on.parse::<RetryOn>().unwrap(),
+            backoff: Duration::from_millis(50),
+            max_concurrent,
+        })
+    }
+
+    #[test]
+    fn test_attempts_and_backoff() {
+        let policy = policy("connect-failure", 2, 10);
+        let mut state = RetryState::default();
+        assert_eq!(state.backoff(&policy), None);
+
+        assert!(state.try_retry(&policy, Failure::Connect, &Method::POST));
+        assert_eq!(state.backoff(&policy), Some(Duration::from_millis(50)));
+        assert!(state.try_retry(&policy, Failure::Connect, &Method::POST));
+        assert_eq!(state.backoff(&policy), Some(Duration::from_millis(100)));
+
+        assert!(!state.try_retry(&policy, Failure::Connect, &Method::POST));
+    }
+
+    #[test]
+    fn test_conditions() {
+        let policy = policy("5xx,error", 3, 10);
+        let mut state = RetryState::default();
+
+        assert!(!state.try_retry(&policy, Failure::Connect, &Method::GET));
+        assert!(!state.try_retry(&policy, Failure::Status(404), &Method::GET));
+        // The backend may have acted on a POST already.
+        assert!(!state.try_retry(&policy, Failure::Status(502), &Method::POST));
+        assert!(!state.try_retry(&policy, Failure::Error, &Method::POST));
+
+        assert!(state.try_retry(&policy, Failure::Status(502), &Method::GET));
+        assert!(state.try_retry(&policy, Failure::Error, &Method::PUT));
+    }
+
+    #[test]
+    fn test_max_concurrent() {
+        let policy = policy("connect-failure", 3, 1);
+
+        let mut first = RetryState::default();
+        let mut second = RetryState::default();
+        assert!(first.try_retry(&policy, Failure::Connect, &Method::GET));
+        // The first request keeps its permit for its next retry.
+        assert!(first.try_retry(&policy, Failure::Connect, &Method::GET));
+        assert!(!second.try_retry(&policy, Failure::Connect, &Method::GET));
+
+        drop(first);
+        assert!(second.try_retry(&policy, Failure::Connect, &Method::GET));
+    }
+}
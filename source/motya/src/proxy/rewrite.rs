@@ -0,0 +1,114 @@
+//! Path rewrites of a route (`rewrite` in a connectors section).
+//!
+//! The path the client asked for picks the route, and stays what the logs, the
+//! cache and the request filters see. Only the request sent upstream carries the
+//! rewritten path, which is set in `upstream_request_filter` before the upstream
+//! request filters of the chains run. The query string is passed on untouched.
+
+use std::borrow::Cow;
+
+use http::{uri::PathAndQuery, Uri};
+use motya_config::common_types::connectors::RewriteConfig;
+use pingora::{Error, ErrorType, Result};
+use pingora_http::RequestHeader;
+
+/// The path `path` is sent upstream with. A result without a leading `/` gets one.
+pub fn rewrite_path<'a>(rewrite: &RewriteConfig, path: &'a str) -> Cow<'a, str> {
+    let rewritten = match rewrite {
+        RewriteConfig::StripPrefix { prefix } => match path.strip_prefix(prefix.as_str()) {
+            Some(rest) => Cow::Borrowed(rest),
+            None => return Cow::Borrowed(path),
+        },
+        RewriteConfig::Pattern { pattern, to } => pattern.0.replace(path, to.as_str()),
+    };
+
+    if rewritten.starts_with('/') {
+        rewritten
+    } else {
+        Cow::Owned(format!("/{rewritten}"))
+    }
+}
+
+/// Rewrites the path of the upstream request, returning the new path if it changed.
+pub fn apply(rewrite: &RewriteConfig, header: &mut RequestHeader) -> Result<Option<String>> {
+    let path = rewrite_path(rewrite, header.uri.path());
+    if path == header.uri.path() {
+        return Ok(None);
+    }
+
+    let path_and_query = match header.uri.query() {
+        Some(query) => format!("{path}?{query}"),
+        None => path.to_string(),
+    };
+    let invalid = || {
+        Error::explain(
+            ErrorType::HTTPStatus(500),
+            format!("rewritten path '{path}' is not a valid path"),
+        )
+    };
+
+    let path_and_query: PathAndQuery = path_and_query.parse().map_err(|_| invalid())?;
+    let mut parts = header.uri.clone().into_parts();
+    parts.path_and_query = Some(path_and_query);
+    let uri = Uri::from_parts(parts).map_err(|_| invalid())?;
+
+    let path = path.into_owned();
+    header.set_uri(uri);
+    Ok(Some(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use http::Method;
+    use motya_config::common_types::connectors::RewritePattern;
+    use regex::Regex;
+
+    use super::*;
+
+    fn pattern(pattern: &str, to: &str) -> RewriteConfig {
+        RewriteConfig::Pattern {
+            pattern: RewritePattern(Regex::new(pattern).unwrap()),
+            to: to.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_strip_prefix() {
+        let strip = RewriteConfig::StripPrefix {
+            prefix: "/api".to_string(),
+        };
+
+        assert_eq!(rewrite_path(&strip, "/api/users/42"), "/users/42");
+        assert_eq!(rewrite_path(&strip, "/api"), "/");
+        assert_eq!(rewrite_path(&strip, "/other"), "/other");
+    }
+
+    #[test]
+    fn test_pattern() {
+        let rewrite = pattern("^/old/(?<rest>.*)$", "/new/${rest}");
+        assert_eq!(rewrite_path(&rewrite, "/old/a/b"), "/new/a/b");
+        assert_eq!(rewrite_path(&rewrite, "/other"), "/other");
+
+        // Only the first match is replaced, and the result is kept a path.
+        let rewrite = pattern("v1", "v2");
+        assert_eq!(rewrite_path(&rewrite, "/v1/items/v1"), "/v2/items/v1");
+        let rewrite = pattern("^/api/(.*)$", "$1");
+        assert_eq!(rewrite_path(&rewrite, "/api/users"), "/users");
+    }
+
+    #[test]
+    fn test_apply_keeps_the_query() {
+        let mut header = RequestHeader::build(Method::GET, b"/old/a?x=1&y=2", None).unwrap();
+
+        let rewritten = apply(&pattern("^/old/(.*)$", "/new/$1"), &mut header).unwrap();
+        assert_eq!(rewritten.as_deref(), Some("/new/a"));
+        assert_eq!(header.uri, "/new/a?x=1&y=2");
+
+        let unchanged = apply(&pattern("^/old/(.*)$", "/new/$1"), &mut header).unwrap();
+        assert_eq!(unchanged, None);
+
+        let invalid = apply(&pattern("^/new/a$", "/a b"), &mut header);
+        assert!(invalid.is_err());
+        assert_eq!(header.uri, "/new/a?x=1&y=2");
+    }
+}
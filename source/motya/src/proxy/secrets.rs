@@ -0,0 +1,164 @@
+//! Values of `definitions.secrets`, read when the server builds what uses them.
+//!
+//! Errors name the secret and where it is read from, never its value.
+
+use std::{fs, process::Command};
+
+use miette::{miette, Context, IntoDiagnostic, Result};
+use motya_config::common_types::secrets::{SecretConfig, SecretSource, SecretValue};
+
+/// The inline value, or the value of the secret read now.
+pub fn resolve(value: &SecretValue) -> Result<String> {
+    match value {
+        SecretValue::Inline(value) => Ok(value.clone()),
+        SecretValue::Secret(secret) => read(secret),
+    }
+}
+
+pub fn read(secret: &SecretConfig) -> Result<String> {
+    match &secret.source {
+        SecretSource::Env(var) => std::env::var(var).map_err(|_| {
+            miette!(
+                "Secret '{}': environment variable {var} is not set",
+                secret.name
+            )
+        }),
+
+        SecretSource::File { path, key } => {
+            let contents = fs::read_to_string(path)
+                .into_diagnostic()
+                .wrap_err_with(|| {
+                    format!("Secret '{}': cannot read {}", secret.name, path.display())
+                })?;
+
+            match key {
+                Some(key) => lookup(&contents, key).ok_or_else(|| {
+                    miette!("Secret '{}': no '{key}' in {}", secret.name, path.display())
+                }),
+                None => Ok(trim_line_break(&contents).to_string()),
+            }
+        }
+
+        SecretSource::Command(run) => {
+            let output = Command::new("sh")
+                .arg("-c")
+                .arg(run)
+                .output()
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Secret '{}': cannot run its command", secret.name))?;
+
+            if !output.status.success() {
+                return Err(miette!(
+                    "Secret '{}': its command failed with {}",
+                    secret.name,
+                    output.status
+                ));
+            }
+
+            let stdout = String::from_utf8(output.stdout).map_err(|_| {
+                miette!(
+                    "Secret '{}': its command printed invalid UTF-8",
+                    secret.name
+                )
+            })?;
+            Ok(trim_line_break(&stdout).to_string())
+        }
+    }
+}
+
+/// The value of `key` in `KEY=VALUE` lines, with `#` comments, an optional
+/// `export ` prefix and quotes around the value, as in env files.
+fn lookup(contents: &str, key: &str) -> Option<String> {
+    contents.lines().find_map(|line| {
+        let line = line.trim();
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (name, value) = line.split_once('=')?;
+        if line.starts_with('#') || name.trim() != key {
+            return None;
+        }
+
+        let value = value.trim();
+        let unquoted = ['"', '\'']
+            .iter()
+            .find_map(|q| value.strip_prefix(*q)?.strip_suffix(*q));
+        Some(unquoted.unwrap_or(value).to_string())
+    })
+}
+
+fn trim_line_break(value: &str) -> &str {
+    value
+        .strip_suffix('\n')
+        .map(|value| value.strip_suffix('\r').unwrap_or(value))
+        .unwrap_or(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn secret(source: SecretSource) -> SecretConfig {
+        SecretConfig {
+            name: "test".into(),
+            source,
+        }
+    }
+
+    #[test]
+    fn test_lookup() {
+        let contents = "# comment\nexport REDIS_PASSWORD=\"s3cret\"\nOTHER = plain\n";
+
+        assert_eq!(
+            lookup(contents, "REDIS_PASSWORD").as_deref(),
+            Some("s3cret")
+        );
+        assert_eq!(lookup(contents, "OTHER").as_deref(), Some("plain"));
+        assert_eq!(lookup(contents, "MISSING"), None);
+    }
+
+    #[test]
+    fn test_read_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("redis-pass");
+        fs::write(&path, "s3cret\n").unwrap();
+
+        let whole = secret(SecretSource::File {
+            path: path.clone(),
+            key: None,
+        });
+        assert_eq!(read(&whole).unwrap(), "s3cret");
+
+        let keyed = secret(SecretSource::File {
+            path,
+            key: Some("REDIS_PASSWORD".into()),
+        });
+        let err = format!("{:?}", read(&keyed).unwrap_err());
+        assert!(err.contains("no 'REDIS_PASSWORD'"), "{err}");
+        assert!(!err.contains("s3cret"), "{err}");
+
+        let missing = secret(SecretSource::File {
+            path: PathBuf::from("/nonexistent/motya-secret"),
+            key: None,
+        });
+        assert!(read(&missing).is_err());
+    }
+
+    #[test]
+    fn test_read_command() {
+        let echo = secret(SecretSource::Command("printf 's3cret\\n'".into()));
+        assert_eq!(read(&echo).unwrap(), "s3cret");
+
+        let failing = secret(SecretSource::Command("echo s3cret; exit 3".into()));
+        let err = format!("{:?}", read(&failing).unwrap_err());
+        assert!(err.contains("failed"), "{err}");
+        assert!(!err.contains("s3cret"), "{err}");
+    }
+
+    #[test]
+    fn test_inline_values_are_not_logged() {
+        let value = SecretValue::Inline("s3cret".into());
+        assert_eq!(resolve(&value).unwrap(), "s3cret");
+        assert!(!format!("{value:?}").contains("s3cret"));
+    }
+}
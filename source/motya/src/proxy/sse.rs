@@ -0,0 +1,93 @@
+//! Server-sent events routes (`sse #true` in a connectors section).
+//!
+//! Pingora already forwards body chunks as soon as they arrive; what stalls an
+//! event stream is everything around it: a compressor waiting for a full block,
+//! an upstream read timeout tuned for request/response traffic, or a buffering
+//! proxy in front of motya. Each of those is switched off per route here.
+
+use http::header;
+use motya_config::common_types::connectors::SseConfig;
+use pingora::{prelude::HttpPeer, Result};
+use pingora_http::{RequestHeader, ResponseHeader};
+
+/// Lets the upstream stay silent between two events for up to the idle timeout.
+pub fn configure_peer(peer: &mut HttpPeer, config: &SseConfig) {
+    peer.options.read_timeout = Some(config.idle_timeout);
+}
+
+/// Asks the upstream for an uncompressed stream, so that events are not held back
+/// until a compression block fills up.
+pub fn prepare_request(request: &mut RequestHeader) {
+    request.remove_header(&header::ACCEPT_ENCODING);
+}
+
+/// Marks the response as a live stream for caches and proxies between motya and the client.
+pub fn prepare_response(response: &mut ResponseHeader) -> Result<()> {
+    response.remove_header(&header::CONTENT_LENGTH);
+
+    if !response.headers.contains_key(header::CACHE_CONTROL) {
+        response.insert_header(header::CACHE_CONTROL, "no-cache")?;
+    }
+
+    // Understood by nginx and most CDNs that buffer responses by default.
+    response.insert_header("X-Accel-Buffering", "no")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_configure_peer_sets_read_timeout() {
+        let mut peer = HttpPeer::new("127.0.0.1:3000", false, String::new());
+        let config = SseConfig {
+            idle_timeout: Duration::from_secs(90),
+        };
+
+        configure_peer(&mut peer, &config);
+
+        assert_eq!(peer.options.read_timeout, Some(Duration::from_secs(90)));
+    }
+
+    #[test]
+    fn test_prepare_request_drops_accept_encoding() {
+        let mut request = RequestHeader::build("GET", b"/events", None).unwrap();
+        request
+            .insert_header(header::ACCEPT_ENCODING, "gzip, br")
+            .unwrap();
+
+        prepare_request(&mut request);
+
+        assert!(request.headers.get(header::ACCEPT_ENCODING).is_none());
+    }
+
+    #[test]
+    fn test_prepare_response_headers() {
+        let mut response = ResponseHeader::build(200, None).unwrap();
+        response
+            .insert_header(header::CONTENT_LENGTH, "42")
+            .unwrap();
+
+        prepare_response(&mut response).unwrap();
+
+        assert!(response.headers.get(header::CONTENT_LENGTH).is_none());
+        assert_eq!(response.headers[header::CACHE_CONTROL], "no-cache");
+        assert_eq!(response.headers["X-Accel-Buffering"], "no");
+    }
+
+    #[test]
+    fn test_prepare_response_keeps_upstream_cache_control() {
+        let mut response = ResponseHeader::build(200, None).unwrap();
+        response
+            .insert_header(header::CACHE_CONTROL, "no-store")
+            .unwrap();
+
+        prepare_response(&mut response).unwrap();
+
+        assert_eq!(response.headers[header::CACHE_CONTROL], "no-store");
+    }
+}
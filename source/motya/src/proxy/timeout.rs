@@ -0,0 +1,102 @@
+//! Time limits of a route (`timeout` in a connectors section).
+//!
+//! `request` is a deadline counted from the arrival of the request: every pick of
+//! an upstream peer, retries included, only gets the time that is left, and a
+//! request past it is answered with `504`. `read-header` bounds each read from
+//! the client, so that a slow client gets `408` instead of holding a worker.
+
+use std::time::{Duration, Instant};
+
+use motya_config::common_types::connectors::TimeoutConfig;
+use pingora::{prelude::HttpPeer, Error, ErrorSource, ErrorType};
+use pingora_proxy::Session;
+
+/// Applies `read-header` to the reads from the client that follow.
+pub fn configure_downstream(session: &mut Session, config: &TimeoutConfig) {
+    if let Some(read_header) = config.read_header {
+        session.set_read_timeout(Some(read_header));
+    }
+}
+
+/// The time left of the `request` deadline, if the route has one.
+pub fn remaining(config: &TimeoutConfig, started: Instant) -> Option<Duration> {
+    config
+        .request
+        .map(|request| request.saturating_sub(started.elapsed()))
+}
+
+/// Keeps every wait on the upstream within the time left.
+pub fn configure_peer(peer: &mut HttpPeer, remaining: Duration) {
+    let options = &mut peer.options;
+    for timeout in [
+        &mut options.connection_timeout,
+        &mut options.total_connection_timeout,
+        &mut options.read_timeout,
+        &mut options.write_timeout,
+    ] {
+        *timeout = Some(timeout.map_or(remaining, |current| current.min(remaining)));
+    }
+}
+
+/// The status for a request of a route with a `timeout` that failed with `e`,
+/// when it is a timeout.
+pub fn status(e: &Error) -> Option<u16> {
+    use ErrorType::*;
+
+    match (e.esource(), e.etype()) {
+        (ErrorSource::Upstream, ConnectTimedout | ReadTimedout | WriteTimedout) => Some(504),
+        (ErrorSource::Downstream, ReadTimedout) => Some(408),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remaining() {
+        let config = TimeoutConfig {
+            request: Some(Duration::from_secs(30)),
+            read_header: None,
+        };
+
+        let left = remaining(&config, Instant::now()).unwrap();
+        assert!(left > Duration::from_secs(29) && left <= Duration::from_secs(30));
+
+        let late = Instant::now() - Duration::from_secs(31);
+        assert_eq!(remaining(&config, late), Some(Duration::ZERO));
+
+        let no_deadline = TimeoutConfig {
+            request: None,
+            read_header: Some(Duration::from_secs(5)),
+        };
+        assert_eq!(remaining(&no_deadline, late), None);
+    }
+
+    #[test]
+    fn test_configure_peer_keeps_shorter_timeouts() {
+        let mut peer = HttpPeer::new("127.0.0.1:3000", false, String::new());
+        peer.options.read_timeout = Some(Duration::from_secs(5));
+
+        configure_peer(&mut peer, Duration::from_secs(20));
+
+        assert_eq!(peer.options.read_timeout, Some(Duration::from_secs(5)));
+        assert_eq!(peer.options.write_timeout, Some(Duration::from_secs(20)));
+        assert_eq!(
+            peer.options.total_connection_timeout,
+            Some(Duration::from_secs(20))
+        );
+    }
+
+    #[test]
+    fn test_status() {
+        use ErrorType::*;
+
+        assert_eq!(status(&Error::new_up(ReadTimedout)), Some(504));
+        assert_eq!(status(&Error::new_up(ConnectTimedout)), Some(504));
+        assert_eq!(status(&Error::new_down(ReadTimedout)), Some(408));
+        assert_eq!(status(&Error::new_up(ConnectRefused)), None);
+        assert_eq!(status(&Error::new_down(ReadError)), None);
+    }
+}
@@ -0,0 +1,163 @@
+//! Decision traces of requests (`debug-trace=#true` on a section).
+//!
+//! A request of a traced route carries a [`Trace`] in its context. The proxy
+//! phases note in it what they decided: the route, every request filter of every
+//! chain and what it did, the key the balancer hashed and the peer it picked, and
+//! the status of the response, each with the time since the request arrived.
+//!
+//! The trace is sent back to the client in the `X-Motya-Trace` header of proxied
+//! responses, and once the request is done it is kept among the last
+//! [`KEPT_TRACES`], which the admin API serves at `/traces`.
+
+use std::{
+    collections::VecDeque,
+    fmt::Write as _,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use uuid::Uuid;
+
+/// The response header the summary of the trace is sent in.
+pub const TRACE_HEADER: &str = "X-Motya-Trace";
+
+/// How many finished traces are kept for the admin API.
+pub const KEPT_TRACES: usize = 64;
+
+/// Longest summary sent in [`TRACE_HEADER`], past which it is cut.
+const MAX_HEADER_LEN: usize = 2048;
+
+static RECENT: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+#[derive(Debug)]
+pub struct Trace {
+    started: Instant,
+    events: Vec<Event>,
+}
+
+#[derive(Debug)]
+struct Event {
+    at: Duration,
+    stage: &'static str,
+    detail: String,
+}
+
+impl Trace {
+    pub fn new(started: Instant) -> Self {
+        Self {
+            started,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, stage: &'static str, detail: impl Into<String>) {
+        self.events.push(Event {
+            at: self.started.elapsed(),
+            stage,
+            detail: detail.into(),
+        });
+    }
+
+    /// Notes the key bytes a balancer read from the request, escaped where they
+    /// aren't printable.
+    pub fn record_key(&mut self, key: Option<&[u8]>) {
+        match key {
+            Some(key) => self.record("balancer-key", format!("\"{}\"", key.escape_ascii())),
+            None => self.record("balancer-key", "none"),
+        }
+    }
+
+    /// The events in one line, for [`TRACE_HEADER`].
+    pub fn summary(&self, request_id: &Uuid) -> String {
+        let mut out = format!("id={request_id}");
+        for event in &self.events {
+            let _ = write!(out, "; {} {}", event.stage, event.detail);
+        }
+
+        // Header values are visible ASCII.
+        let mut out = out.replace(|c: char| !(c.is_ascii_graphic() || c == ' '), "?");
+        if out.len() > MAX_HEADER_LEN {
+            out.truncate(MAX_HEADER_LEN - 3);
+            out.push_str("...");
+        }
+        out
+    }
+
+    /// Keeps the finished trace of the request for the admin API.
+    pub fn finish(self, request_id: &Uuid, request: &str, status: Option<u16>) {
+        let mut out = format!("{request_id} {request}");
+        match status {
+            Some(status) => {
+                let _ = write!(out, " -> {status}");
+            }
+            None => out.push_str(" -> no response"),
+        }
+        let _ = writeln!(out, " in {}", millis(self.started.elapsed()));
+
+        for Event { at, stage, detail } in &self.events {
+            let _ = writeln!(out, "  +{} {stage}: {detail}", millis(*at));
+        }
+
+        let mut recent = RECENT.lock().expect("trace lock poisoned");
+        if recent.len() == KEPT_TRACES {
+            recent.pop_front();
+        }
+        recent.push_back(out);
+    }
+}
+
+/// The kept traces, oldest first.
+pub fn recent() -> Vec<String> {
+    RECENT
+        .lock()
+        .expect("trace lock poisoned")
+        .iter()
+        .cloned()
+        .collect()
+}
+
+fn millis(duration: Duration) -> String {
+    format!("{:.3}ms", duration.as_secs_f64() * 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary() {
+        let mut trace = Trace::new(Instant::now());
+        trace.record("route", "/api (prefix)");
+        trace.record("filter", "auth/basic-auth continue");
+        trace.record_key(Some(b"10.0.0.1\n"));
+
+        let id = Uuid::nil();
+        assert_eq!(
+            trace.summary(&id),
+            format!(
+                "id={id}; route /api (prefix); filter auth/basic-auth continue; \
+                 balancer-key \"10.0.0.1\\n\""
+            )
+        );
+
+        trace.record("peer", "x".repeat(MAX_HEADER_LEN));
+        let summary = trace.summary(&id);
+        assert_eq!(summary.len(), MAX_HEADER_LEN);
+        assert!(summary.ends_with("..."));
+    }
+
+    #[test]
+    fn test_finish_keeps_the_last_traces() {
+        for n in 0..KEPT_TRACES + 1 {
+            let mut trace = Trace::new(Instant::now());
+            trace.record("route", format!("/test-{n}"));
+            trace.finish(&Uuid::nil(), "GET /", Some(200));
+        }
+
+        let recent = recent();
+        assert_eq!(recent.len(), KEPT_TRACES);
+        let last = recent.last().unwrap();
+        assert!(last.contains(&format!("route: /test-{KEPT_TRACES}")));
+        assert!(recent[0].starts_with(&format!("{} GET / -> 200 in ", Uuid::nil())));
+    }
+}
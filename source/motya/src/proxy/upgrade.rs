@@ -0,0 +1,98 @@
+//! Requests that switch protocols (`Connection: Upgrade`), mostly WebSockets.
+//!
+//! Once the upstream answers with `101 Switching Protocols`, pingora turns the
+//! connection into a two-way byte pipe. Anything that waits for the end of a
+//! body, such as a request body buffer, a cache or a compressor, would hold the
+//! pipe up forever, so those stay out of the way for upgrade requests. Routes
+//! with `allow-upgrades=#false` get the request as a plain one instead.
+
+use http::{header, Version};
+use pingora_http::RequestHeader;
+
+/// Whether `request` asks to switch protocols. Only HTTP/1.1 can do that.
+pub fn is_upgrade(request: &RequestHeader) -> bool {
+    request.version == Version::HTTP_11
+        && request.headers.contains_key(header::UPGRADE)
+        && has_upgrade_token(request)
+}
+
+/// Turns an upgrade request into a plain one.
+pub fn strip(request: &mut RequestHeader) {
+    request.remove_header(&header::UPGRADE);
+
+    let rest = request
+        .headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|token| !token.is_empty() && !token.eq_ignore_ascii_case("upgrade"))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    request.remove_header(&header::CONNECTION);
+    if !rest.is_empty() {
+        let _ = request.insert_header(header::CONNECTION, rest);
+    }
+}
+
+fn has_upgrade_token(request: &RequestHeader) -> bool {
+    request
+        .headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|token| token.trim().eq_ignore_ascii_case("upgrade"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn websocket_request() -> RequestHeader {
+        let mut request = RequestHeader::build("GET", b"/ws", None).unwrap();
+        request
+            .insert_header(header::CONNECTION, "keep-alive, Upgrade")
+            .unwrap();
+        request.insert_header(header::UPGRADE, "websocket").unwrap();
+        request
+    }
+
+    #[test]
+    fn test_is_upgrade() {
+        assert!(is_upgrade(&websocket_request()));
+
+        let mut request = websocket_request();
+        request.remove_header(&header::CONNECTION);
+        assert!(!is_upgrade(&request));
+
+        let mut request = websocket_request();
+        request.set_version(Version::HTTP_2);
+        assert!(!is_upgrade(&request));
+    }
+
+    #[test]
+    fn test_strip_keeps_other_connection_tokens() {
+        let mut request = websocket_request();
+
+        strip(&mut request);
+
+        assert!(!is_upgrade(&request));
+        assert!(request.headers.get(header::UPGRADE).is_none());
+        assert_eq!(request.headers[header::CONNECTION], "keep-alive");
+    }
+
+    #[test]
+    fn test_strip_drops_lone_upgrade_token() {
+        let mut request = websocket_request();
+        request
+            .insert_header(header::CONNECTION, "upgrade")
+            .unwrap();
+
+        strip(&mut request);
+
+        assert!(request.headers.get(header::CONNECTION).is_none());
+    }
+}
@@ -1,26 +1,40 @@
-use std::collections::BTreeSet;
+use std::sync::Arc;
 
-use futures_util::FutureExt;
 use miette::{miette, Result};
 use motya_config::{
     common_types::{
-        balancer::SelectionKind, connectors::{MultiServerUpstreamConfig, UpstreamConfig, UpstreamContextConfig}, definitions::Modificator, key_template::HashOp
+        balancer::{DiscoveryKind, HealthCheckKind, SelectionKind},
+        connectors::{MultiServerUpstreamConfig, UpstreamConfig, UpstreamContextConfig},
+        definitions::Modificator,
+        key_template::HashOp,
     },
     internal::UpstreamOptions,
 };
-use pingora::prelude::HttpPeer;
 use pingora_load_balancing::{
-    discovery,
-    prelude::RoundRobin,
-    selection::{consistent::KetamaHashing, FNVHash, Random},
-    Backend, Backends, LoadBalancer,
+    discovery::ServiceDiscovery,
+    selection::{BackendIter, BackendSelection},
+    Backends, LoadBalancer,
 };
 
 use crate::proxy::{
-    balancer::{Balancer, BalancerType},
+    balancer::{
+        health_check::{spawn_health_checks, TcpProbe},
+        least_loaded::{BackendLoads, LoadCost},
+        outlier::OutlierDetector,
+        slow_start::SlowStart,
+        Balancer, BalancerType,
+    },
+    discovery::{
+        self, dns::DnsDiscovery, http_backend, kubernetes::KubernetesDiscovery, Discovery,
+        StaticDiscovery, Updates,
+    },
     filters::chain_resolver::ChainResolver,
     key_selector::KeySelector,
+    metrics,
+    mirror::Mirror,
+    retry::RetryPolicy,
     upstream_router::UpstreamContext,
+    upstream_tls::UpstreamTls,
 };
 
 #[derive(Clone)]
@@ -33,12 +47,17 @@ impl UpstreamFactory {
         Self { resolver }
     }
 
-    pub async fn create_context(&self, config: UpstreamContextConfig) -> Result<UpstreamContext> {
+    /// Builds a route of `service` from its configuration.
+    pub async fn create_context(
+        &self,
+        service: &str,
+        config: UpstreamContextConfig,
+    ) -> Result<UpstreamContext> {
         let balancer = match &config.upstream {
             UpstreamConfig::Static(_) | UpstreamConfig::Service(_) => None,
             UpstreamConfig::MultiServer(m) => {
                 if let Some(lb_options) = config.lb_options {
-                    setup_balancer(lb_options, m)?
+                    setup_balancer(lb_options, m).await?
                 } else {
                     None
                 }
@@ -50,7 +69,7 @@ impl UpstreamFactory {
         for modificator in config.chains {
             match modificator {
                 Modificator::Chain(named_chain) => {
-                    let chain = self.resolver.resolve(&named_chain.name).await?;
+                    let chain = self.resolver.resolve(&named_chain.name, service).await?;
                     chains.push(chain);
                 }
             }
@@ -58,61 +77,93 @@ impl UpstreamFactory {
 
         let ctx = UpstreamContext {
             balancer,
+            metrics: metrics::upstream(&config.upstream),
             upstream: config.upstream,
             chains,
+            compression: config.compression,
+            sse: config.sse,
+            cache: config.cache,
+            retry: config.retry.map(RetryPolicy::new),
+            mirror: config.mirror.map(Mirror::new).transpose()?,
+            timeout: config.timeout,
+            allow_upgrades: config.allow_upgrades,
+            grpc: config.grpc,
+            upstream_tls: config
+                .upstream_tls
+                .as_ref()
+                .map(UpstreamTls::load)
+                .transpose()?,
+            h2: config.h2,
+            debug_trace: config.debug_trace,
+            conditions: config.conditions,
+            rewrite: config.rewrite,
         };
 
         Ok(ctx)
     }
 }
 
-fn setup_balancer(
+async fn setup_balancer(
     lb_options: UpstreamOptions,
     m: &MultiServerUpstreamConfig,
 ) -> Result<Option<Balancer>, miette::Error> {
-    let addrs = m.servers.iter().map(|s| (&s.address, s.weight));
-    let mut backends = addrs
-        .clone()
-        .map(|(addr, weight)| {
-            Backend::new_with_weight(&addr.to_string(), weight)
-                .expect("never fail because addr is already IpAddr")
-        })
-        .collect::<Vec<_>>();
-    for (backend, (addr, _)) in backends.iter_mut().zip(addrs) {
-        assert!(backend
-            .ext
-            .insert(HttpPeer::new(
-                addr,
-                //sni is https only
-                //https://github.com/cloudflare/pingora/blob/main/docs/user_guide/peer.md
-                m.tls_sni.is_some(),
-                m.tls_sni.clone().unwrap_or("".to_string())
-            ))
-            .is_none());
-    }
-    let disco = discovery::Static::new(BTreeSet::from_iter(backends));
+    let discovery: Arc<dyn Discovery> = match &lb_options.discovery {
+        DiscoveryKind::Static => Arc::new(StaticDiscovery::new(m.servers.iter().map(|s| {
+            let addr = s
+                .address
+                .as_socket()
+                .expect("hostnames are rejected without dns discovery");
+            http_backend(addr, s.weight, m.tls_sni.as_deref())
+        }))),
+        DiscoveryKind::Dns { refresh } => Arc::new(DnsDiscovery::new(
+            m.servers.clone(),
+            m.tls_sni.clone(),
+            *refresh,
+        )),
+        DiscoveryKind::Kubernetes(config) => {
+            Arc::new(KubernetesDiscovery::start(config, m.tls_sni.clone())?)
+        }
+    };
+    let (disco, updates) = discovery::attach(discovery);
+    let health = &lb_options.health_checks;
+    let slow_start = lb_options
+        .slow_start
+        .map(|window| Arc::new(SlowStart::new(window)));
     let balancer_type = match lb_options.selection {
         SelectionKind::FvnHash => {
-            BalancerType::FNVHash(LoadBalancer::<FNVHash>::from_backends(Backends::new(disco)))
+            BalancerType::FNVHash(build_load_balancer(disco, health, &slow_start))
+        }
+        SelectionKind::RoundRobin => {
+            BalancerType::RoundRobin(build_load_balancer(disco, health, &slow_start))
         }
-        SelectionKind::RoundRobin => BalancerType::RoundRobin(
-            LoadBalancer::<RoundRobin>::from_backends(Backends::new(disco)),
-        ),
         SelectionKind::Random => {
-            BalancerType::Random(LoadBalancer::<Random>::from_backends(Backends::new(disco)))
+            BalancerType::Random(build_load_balancer(disco, health, &slow_start))
+        }
+        SelectionKind::KetamaHashing => {
+            BalancerType::KetamaHashing(build_load_balancer(disco, health, &slow_start))
         }
-        SelectionKind::KetamaHashing => BalancerType::KetamaHashing(
-            LoadBalancer::<KetamaHashing>::from_backends(Backends::new(disco)),
+        SelectionKind::LeastConnections => BalancerType::LeastConnections(
+            build_load_balancer(disco, health, &slow_start),
+            BackendLoads::new(LoadCost::LeastConnections),
+        ),
+        SelectionKind::PeakEwma => BalancerType::PeakEwma(
+            build_load_balancer(disco, health, &slow_start),
+            BackendLoads::new(LoadCost::PeakEwma),
         ),
     };
-    match &balancer_type {
-        BalancerType::FNVHash(b) => b.update().now_or_never(),
-        BalancerType::KetamaHashing(b) => b.update().now_or_never(),
-        BalancerType::Random(b) => b.update().now_or_never(),
-        BalancerType::RoundRobin(b) => b.update().now_or_never(),
+    let updated = match &balancer_type {
+        BalancerType::FNVHash(b) => update_backends(b, updates, &slow_start).await,
+        BalancerType::KetamaHashing(b) => update_backends(b, updates, &slow_start).await,
+        BalancerType::Random(b) => update_backends(b, updates, &slow_start).await,
+        BalancerType::RoundRobin(b) => update_backends(b, updates, &slow_start).await,
+        BalancerType::LeastConnections(b, _) | BalancerType::PeakEwma(b, _) => {
+            update_backends(b, updates, &slow_start).await
+        }
+    };
+    if let Err(e) = updated {
+        // Static servers are always there; other discoveries keep trying.
+        tracing::warn!("No backends discovered yet for upstream: {e}");
     }
-    .expect("static should not block")
-    .expect("static should not error");
 
     let alg = lb_options
         .template
@@ -128,5 +179,50 @@ fn setup_balancer(
             .map_err(|err| miette!("{err}"))?,
         balancer_type,
         hasher: alg,
+        slow_start,
+        outliers: lb_options.outlier_detection.map(OutlierDetector::new),
     }))
 }
+
+fn build_load_balancer<S>(
+    disco: Box<dyn ServiceDiscovery + Send + Sync>,
+    health: &HealthCheckKind,
+    slow_start: &Option<Arc<SlowStart>>,
+) -> Arc<LoadBalancer<S>>
+where
+    S: BackendSelection + Send + Sync + 'static,
+    S::Iter: BackendIter,
+{
+    let mut backends = Backends::new(disco);
+
+    if let HealthCheckKind::Tcp(cfg) = health {
+        backends.set_health_check(Box::new(TcpProbe::new(cfg.clone())));
+    }
+
+    let lb = Arc::new(LoadBalancer::<S>::from_backends(backends));
+
+    if let HealthCheckKind::Tcp(cfg) = health {
+        spawn_health_checks(&lb, cfg.interval, slow_start.clone());
+    }
+
+    lb
+}
+
+/// Fills `lb` with its first set of backends, then follows the changes of
+/// its discovery.
+async fn update_backends<S>(
+    lb: &Arc<LoadBalancer<S>>,
+    updates: Updates,
+    slow_start: &Option<Arc<SlowStart>>,
+) -> pingora::Result<()>
+where
+    S: BackendSelection + Send + Sync + 'static,
+    S::Iter: BackendIter,
+{
+    let updated = lb.update().await;
+    if let Some(slow_start) = slow_start {
+        slow_start.observe(lb.backends());
+    }
+    updates.spawn(lb, slow_start.clone());
+    updated
+}
@@ -0,0 +1,59 @@
+//! HTTP/2 multiplexing towards the upstream of a route (`h2` in a `proxy`).
+//!
+//! pingora sends one request per HTTP/2 connection unless told otherwise, which
+//! gives up most of what HTTP/2 is for with a gRPC backend. The settings of the
+//! route are handed to every peer picked for it in `upstream_peer`, and pingora
+//! applies them to the connections it opens and pools for that peer.
+
+use motya_config::common_types::connectors::H2Config;
+use pingora::prelude::HttpPeer;
+
+pub fn configure_peer(peer: &mut HttpPeer, config: &H2Config) {
+    let options = &mut peer.options;
+    if let Some(max_streams) = config.max_streams {
+        options.max_h2_streams = max_streams;
+    }
+    if let Some(ping_interval) = config.ping_interval {
+        options.h2_ping_interval = Some(ping_interval);
+    }
+    if let Some(idle_timeout) = config.idle_timeout {
+        options.idle_timeout = Some(idle_timeout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_configure_peer() {
+        let mut peer = HttpPeer::new("127.0.0.1:50051", false, String::new());
+        let defaults = (peer.options.max_h2_streams, peer.options.idle_timeout);
+
+        configure_peer(
+            &mut peer,
+            &H2Config {
+                ping_interval: Some(Duration::from_secs(30)),
+                ..Default::default()
+            },
+        );
+        assert_eq!(peer.options.h2_ping_interval, Some(Duration::from_secs(30)));
+        assert_eq!(
+            (peer.options.max_h2_streams, peer.options.idle_timeout),
+            defaults
+        );
+
+        configure_peer(
+            &mut peer,
+            &H2Config {
+                max_streams: Some(100),
+                ping_interval: None,
+                idle_timeout: Some(Duration::from_secs(90)),
+            },
+        );
+        assert_eq!(peer.options.max_h2_streams, 100);
+        assert_eq!(peer.options.idle_timeout, Some(Duration::from_secs(90)));
+    }
+}
@@ -1,25 +1,55 @@
-use std::fmt::Debug;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Debug,
+    sync::Arc,
+};
 
-use http::uri::PathAndQuery;
-use matchit::{InsertError, Router};
-use motya_config::common_types::connectors::{RouteMatcher, UpstreamConfig};
+use http::{uri::PathAndQuery, Method};
+use matchit::Router;
+use miette::{miette, IntoDiagnostic};
+use motya_config::common_types::{
+    compression::CompressionConfig,
+    connectors::{
+        CacheConfig, H2Config, RewriteConfig, RouteConditions, RouteMatcher, RoutePattern,
+        SseConfig, TimeoutConfig, UpstreamConfig,
+    },
+};
 use pingora::{prelude::HttpPeer, ErrorType};
 
 use crate::proxy::{
     balancer::Balancer,
     context::{ContextInfo, SessionInfo},
     filters::chain_resolver::RuntimeChain,
+    metrics::UpstreamMetrics,
+    mirror::Mirror,
+    retry::RetryPolicy,
+    upstream_tls::UpstreamTls,
 };
 
 pub struct UpstreamContext {
     pub upstream: UpstreamConfig,
     pub chains: Vec<RuntimeChain>,
     pub balancer: Option<Balancer>,
+    pub compression: Option<CompressionConfig>,
+    pub sse: Option<SseConfig>,
+    pub cache: Option<CacheConfig>,
+    pub retry: Option<RetryPolicy>,
+    pub mirror: Option<Mirror>,
+    pub timeout: Option<TimeoutConfig>,
+    pub allow_upgrades: bool,
+    pub grpc: bool,
+    pub upstream_tls: Option<UpstreamTls>,
+    pub h2: Option<H2Config>,
+    pub debug_trace: bool,
+    pub conditions: RouteConditions,
+    pub rewrite: Option<RewriteConfig>,
+    pub metrics: Arc<UpstreamMetrics>,
 }
 
 pub trait UpstreamContextTrait: Debug {
     fn get_prefix_path(&self) -> &PathAndQuery;
     fn get_route_type(&self) -> RouteMatcher;
+    fn get_conditions(&self) -> &RouteConditions;
     fn get_balancer(&self) -> Option<&Balancer>;
     fn get_peer(&self) -> Option<HttpPeer>;
 }
@@ -30,49 +60,147 @@ impl Debug for UpstreamContext {
     }
 }
 
+/// Routes by path, then by the `method` and `query` of the sections.
+///
+/// Each path leads to the indexes of its routes in configuration order; the
+/// first one whose conditions the request meets is taken.
 pub struct UpstreamRouter<TUpstream: UpstreamContextTrait> {
-    pub router: Router<TUpstream>,
+    /// `exact` routes, with their upstream indexes.
+    router: Router<Vec<usize>>,
+    /// `regex` routes in the order they were configured, with their upstream index.
+    patterns: Vec<(RoutePattern, usize)>,
+    /// `prefix` routes, with their upstream indexes.
+    prefixes: PrefixTrie,
+    upstreams: Vec<TUpstream>,
 }
 
-impl<TUpstream: UpstreamContextTrait> UpstreamRouter<TUpstream> {
-    pub fn build(paths: Vec<TUpstream>) -> Result<Self, InsertError> {
-        let mut router = Router::new();
+/// The `prefix` routes, as a tree of path segments.
+///
+/// A lookup walks the request path once, segment by segment, and keeps the
+/// deepest route it passed that takes the request, so it takes as long with ten
+/// thousand sections as with ten. A prefix matches the paths below it but not itself: `/api` takes
+/// `/api/users`, while `/api` and `/api/` fall through to a shorter prefix.
+#[derive(Debug, Default)]
+struct PrefixTrie {
+    root: PrefixNode,
+}
 
-        for item in paths {
-            let raw_path = item.get_prefix_path().path().to_string();
+#[derive(Debug, Default)]
+struct PrefixNode {
+    routes: Vec<usize>,
+    children: HashMap<Box<str>, PrefixNode>,
+}
 
-            match item.get_route_type() {
-                RouteMatcher::Exact => {
-                    router.insert(raw_path, item)?;
-                }
-                RouteMatcher::Prefix => {
-                    let clean_path = raw_path.trim_end_matches('/');
+impl PrefixTrie {
+    /// The routes under `prefix`, to add one to.
+    fn routes_mut(&mut self, prefix: &str) -> &mut Vec<usize> {
+        let mut node = &mut self.root;
+        for segment in prefix.trim_end_matches('/').split('/').skip(1) {
+            node = node.children.entry(segment.into()).or_default();
+        }
+        &mut node.routes
+    }
+
+    /// The first route of the longest prefix of `path` that `accept`s the request.
+    fn find(&self, path: &str, accept: impl Fn(usize) -> bool) -> Option<usize> {
+        let pick = |node: &PrefixNode| node.routes.iter().copied().find(|idx| accept(*idx));
+
+        let mut rest = path.strip_prefix('/')?;
+        let mut node = &self.root;
+        let mut found = pick(node).filter(|_| !rest.is_empty());
 
-                    let wildcard_path = if clean_path.is_empty() {
-                        "/{*catch_all}".to_string()
-                    } else {
-                        format!("{}/{{*catch_all}}", clean_path)
-                    };
+        while let Some((segment, tail)) = rest.split_once('/') {
+            let Some(child) = node.children.get(segment) else {
+                break;
+            };
+            node = child;
+            rest = tail;
 
-                    router.insert(wildcard_path, item)?;
+            if !rest.is_empty() {
+                found = pick(node).or(found);
+            }
+        }
+
+        found
+    }
+}
+
+/// Adds the route `idx` to the `routes` of its path. `false` if an earlier one
+/// already takes all of its requests: it has no conditions, or the same ones.
+fn add_route<T: UpstreamContextTrait>(
+    upstreams: &[T],
+    routes: &mut Vec<usize>,
+    idx: usize,
+) -> bool {
+    let conditions = upstreams[idx].get_conditions();
+    let shadowed = routes.iter().any(|earlier| {
+        let earlier = upstreams[*earlier].get_conditions();
+        earlier.is_empty() || earlier == conditions
+    });
+
+    if !shadowed {
+        routes.push(idx);
+    }
+    !shadowed
+}
+
+impl<TUpstream: UpstreamContextTrait> UpstreamRouter<TUpstream> {
+    pub fn build(paths: Vec<TUpstream>) -> miette::Result<Self> {
+        let mut exact: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+        let mut patterns = Vec::new();
+        let mut prefixes = PrefixTrie::default();
+
+        for (idx, item) in paths.iter().enumerate() {
+            let raw_path = item.get_prefix_path().path();
+
+            let added = match item.get_route_type() {
+                RouteMatcher::Exact => add_route(&paths, exact.entry(raw_path).or_default(), idx),
+                RouteMatcher::Prefix => add_route(&paths, prefixes.routes_mut(raw_path), idx),
+                RouteMatcher::Regex(pattern) => {
+                    patterns.push((pattern, idx));
+                    true
                 }
+            };
+
+            if !added {
+                return Err(miette!(
+                    "The section '{raw_path}' is never reached: an earlier section with the same \
+                     path takes all of its requests"
+                ));
             }
         }
 
-        Ok(Self { router })
+        let mut router = Router::new();
+        for (path, routes) in exact {
+            router.insert(path, routes).into_diagnostic()?;
+        }
+
+        Ok(Self {
+            router,
+            patterns,
+            prefixes,
+            upstreams: paths,
+        })
     }
 
+    /// All routes, in the order they were configured.
+    pub fn upstreams(&self) -> &[TUpstream] {
+        &self.upstreams
+    }
+
+    /// Picks the peer of `route` for the request, as found by [`Self::route`].
     pub fn pick_peer(
         &self,
-        _: &mut ContextInfo,
+        route: Option<usize>,
+        info: &mut ContextInfo,
         session: &mut SessionInfo,
     ) -> Result<Option<HttpPeer>, pingora::BError> {
-        let Some(upstream) = self.get_upstream_by_path(session.path.path()) else {
+        let Some(upstream) = self.get_upstream(route) else {
             return Ok(None);
         };
 
         if let Some(balancer) = upstream.get_balancer() {
-            let backend = balancer.select_backend(session);
+            let backend = balancer.select_backend(session, info.tried_peers);
 
             let backend = backend.ok_or_else(|| {
                 pingora::Error::explain(ErrorType::HTTPStatus(500), "Unable to determine backend")
@@ -93,8 +221,40 @@ impl<TUpstream: UpstreamContextTrait> UpstreamRouter<TUpstream> {
         }
     }
 
+    /// Finds the route of a `GET` of `path` without a query, see [`Self::route`].
     pub fn get_upstream_by_path(&self, path: &str) -> Option<&TUpstream> {
-        self.router.at(path).ok().map(|v| v.value)
+        self.get_upstream(self.route(&Method::GET, path, None))
+    }
+
+    /// The upstream of a route found by [`Self::route`].
+    pub fn get_upstream(&self, route: Option<usize>) -> Option<&TUpstream> {
+        route.map(|idx| &self.upstreams[idx])
+    }
+
+    /// Finds the route of a request, as its index in [`Self::upstreams`]: an
+    /// `exact` route first, then the first matching `regex` route in configuration
+    /// order, then the longest `prefix` route. Routes whose `method` or `query`
+    /// the request doesn't meet are passed over.
+    ///
+    /// The proxy resolves it once per request and keeps it, instead of matching
+    /// the path again in every phase.
+    pub fn route(&self, method: &Method, path: &str, query: Option<&str>) -> Option<usize> {
+        let accept = |idx: usize| self.upstreams[idx].get_conditions().matches(method, query);
+
+        let exact = self
+            .router
+            .at(path)
+            .ok()
+            .and_then(|matched| matched.value.iter().copied().find(|idx| accept(*idx)));
+        if exact.is_some() {
+            return exact;
+        }
+
+        self.patterns
+            .iter()
+            .find(|(pattern, idx)| pattern.is_match(path) && accept(*idx))
+            .map(|(_, idx)| *idx)
+            .or_else(|| self.prefixes.find(path, accept))
     }
 }
 
@@ -113,20 +273,22 @@ impl UpstreamContextTrait for UpstreamContext {
 
     fn get_route_type(&self) -> RouteMatcher {
         match &self.upstream {
-            UpstreamConfig::Service(peer_options) => peer_options.matcher,
+            UpstreamConfig::Service(peer_options) => peer_options.matcher.clone(),
             UpstreamConfig::Static(_) => RouteMatcher::Exact,
-            UpstreamConfig::MultiServer(m) => m.matcher,
+            UpstreamConfig::MultiServer(m) => m.matcher.clone(),
         }
     }
 
+    fn get_conditions(&self) -> &RouteConditions {
+        &self.conditions
+    }
+
     // Only Service can return an HttpPeer. In the other two cases:
     // Static - handles the request during the request_filter stage.
     // MultiServer - processing is delegated to the load balancer.
     fn get_peer(&self) -> Option<HttpPeer> {
         match &self.upstream {
-            UpstreamConfig::Service(s) => {
-                Some(HttpPeer::new(s.peer_address, false, "".to_string()))
-            }
+            UpstreamConfig::Service(s) => Some(HttpPeer::new(s.peer_address, s.tls, s.sni.clone())),
             _ => None,
         }
     }
@@ -134,12 +296,15 @@ impl UpstreamContextTrait for UpstreamContext {
 
 #[cfg(test)]
 pub mod tests {
+    use motya_config::common_types::connectors::{RouteMethods, RouteQuery};
+
     use super::*;
 
     #[derive(Debug)]
     pub struct MockUpstreamContext {
         pub prefix: PathAndQuery,
         pub matcher: RouteMatcher,
+        pub conditions: RouteConditions,
         pub peer: HttpPeer,
     }
 
@@ -149,7 +314,11 @@ pub mod tests {
         }
 
         fn get_route_type(&self) -> RouteMatcher {
-            self.matcher
+            self.matcher.clone()
+        }
+
+        fn get_conditions(&self) -> &RouteConditions {
+            &self.conditions
         }
 
         fn get_balancer(&self) -> Option<&Balancer> {
@@ -164,6 +333,7 @@ pub mod tests {
         MockUpstreamContext {
             prefix: path.parse().unwrap(),
             matcher,
+            conditions: RouteConditions::default(),
             peer: HttpPeer::new("0.0.0.0:0", false, "".to_string()),
         }
     }
@@ -178,6 +348,13 @@ pub mod tests {
 
         let router = UpstreamRouter::build(paths).expect("Router build failed");
 
+        let prefixes: Vec<_> = router
+            .upstreams()
+            .iter()
+            .map(|u| u.get_prefix_path().path())
+            .collect();
+        assert_eq!(prefixes, vec!["/health", "/api", "/"]);
+
         // --- Test Strict ---
         let elem = router.get_upstream_by_path("/health").unwrap();
         assert_eq!(elem.get_prefix_path(), "/health");
@@ -198,6 +375,125 @@ pub mod tests {
         assert_eq!(elem.get_prefix_path(), "/");
     }
 
+    #[test]
+    fn test_regex_routes_priority() {
+        let regex = |path: &str| RouteMatcher::Regex(RoutePattern::new(path).unwrap());
+        let paths = vec![
+            mock_context("/", RouteMatcher::Prefix),
+            mock_context("/api/v1/health", RouteMatcher::Exact),
+            mock_context("/api/v[0-9]+/.*", regex("/api/v[0-9]+/.*")),
+            mock_context("/api/v1/users", regex("/api/v1/users")),
+            mock_context("/api", RouteMatcher::Prefix),
+        ];
+        let router = UpstreamRouter::build(paths).expect("Router build failed");
+        let route = |path| router.get_upstream_by_path(path).unwrap().get_prefix_path();
+
+        // Exact routes win over patterns.
+        assert_eq!(route("/api/v1/health"), "/api/v1/health");
+
+        // Patterns win over prefixes, the first configured one first.
+        assert_eq!(route("/api/v2/orders"), "/api/v[0-9]+/.*");
+        assert_eq!(route("/api/v1/users"), "/api/v[0-9]+/.*");
+
+        // Patterns match the whole path.
+        assert_eq!(route("/api/vX/orders"), "/api");
+        assert_eq!(route("/other/api/v2/orders"), "/");
+    }
+
+    #[test]
+    fn test_route_index() {
+        let paths = vec![
+            mock_context("/", RouteMatcher::Prefix),
+            mock_context("/api", RouteMatcher::Prefix),
+        ];
+        let router = UpstreamRouter::build(paths).expect("Router build failed");
+
+        let route = router.route(&Method::GET, "/api/users", None);
+        assert_eq!(route, Some(1));
+        let elem = router.get_upstream(route).unwrap();
+        assert_eq!(elem.get_prefix_path(), "/api");
+
+        assert!(router.get_upstream(None).is_none());
+    }
+
+    #[test]
+    fn test_longest_prefix() {
+        let paths = vec![
+            mock_context("/", RouteMatcher::Prefix),
+            mock_context("/api", RouteMatcher::Prefix),
+            mock_context("/api/v1/", RouteMatcher::Prefix),
+            mock_context("/api/v1/{id}", RouteMatcher::Prefix),
+        ];
+        let router = UpstreamRouter::build(paths).expect("Router build failed");
+        let route = |path| router.get_upstream_by_path(path).unwrap().get_prefix_path();
+
+        assert_eq!(route("/api/v1/users"), "/api/v1/");
+        assert_eq!(route("/api/v2/users"), "/api");
+        // A prefix takes the paths below it, not itself.
+        assert_eq!(route("/api/v1"), "/api");
+        assert_eq!(route("/api/"), "/");
+        // Braces are plain characters in prefixes.
+        assert_eq!(route("/api/v1/{id}/orders"), "/api/v1/{id}");
+
+        let paths = vec![
+            mock_context("/api", RouteMatcher::Prefix),
+            mock_context("/api/", RouteMatcher::Prefix),
+        ];
+        assert!(UpstreamRouter::build(paths).is_err());
+    }
+
+    #[test]
+    fn test_route_conditions() {
+        let conditional = |path: &str, matcher, methods: &str, query: &str| {
+            let mut context = mock_context(path, matcher);
+            if !methods.is_empty() {
+                context.conditions.methods = methods.parse::<RouteMethods>().unwrap().0;
+            }
+            if !query.is_empty() {
+                context.conditions.query = query.parse::<RouteQuery>().unwrap().0;
+            }
+            context
+        };
+        let paths = vec![
+            conditional("/api", RouteMatcher::Prefix, "POST,PUT", ""),
+            conditional("/api", RouteMatcher::Prefix, "", "version=2"),
+            conditional("/api", RouteMatcher::Prefix, "", ""),
+            conditional("/api/v1/", RouteMatcher::Prefix, "DELETE", ""),
+            conditional("/login", RouteMatcher::Exact, "POST", ""),
+            conditional("/", RouteMatcher::Prefix, "", ""),
+        ];
+        let router = UpstreamRouter::build(paths).expect("Router build failed");
+        let route = |method: Method, path, query| router.route(&method, path, query);
+
+        // The first section of a path whose conditions the request meets.
+        assert_eq!(
+            route(Method::POST, "/api/users", Some("version=2")),
+            Some(0)
+        );
+        assert_eq!(
+            route(Method::GET, "/api/users", Some("a=1&version=2")),
+            Some(1)
+        );
+        assert_eq!(route(Method::GET, "/api/users", Some("version=3")), Some(2));
+
+        // A longer prefix is passed over when its conditions are not met.
+        assert_eq!(route(Method::DELETE, "/api/v1/users", None), Some(3));
+        assert_eq!(route(Method::GET, "/api/v1/users", None), Some(2));
+
+        // And so is an exact route, down to the prefixes.
+        assert_eq!(route(Method::POST, "/login", None), Some(4));
+        assert_eq!(route(Method::GET, "/login", None), Some(5));
+
+        // A section after one that takes all of its requests is never reached.
+        for shadowing in [("", ""), ("GET", "")] {
+            let paths = vec![
+                conditional("/api", RouteMatcher::Prefix, shadowing.0, shadowing.1),
+                conditional("/api", RouteMatcher::Prefix, "GET", ""),
+            ];
+            assert!(UpstreamRouter::build(paths).is_err());
+        }
+    }
+
     #[test]
     fn test_manual_wildcard_override() {
         let paths = vec![mock_context("/custom/{*foo}", RouteMatcher::Exact)];
@@ -0,0 +1,228 @@
+//! TLS towards the upstream of a route (`tls` in a `proxy`).
+//!
+//! The files named in the config are read once, when the route is built, and
+//! every peer picked for the route is handed the CA bundle, the client
+//! certificate and the verification switches in `upstream_peer`.
+//!
+//! pingora reports the SHA-256 of the certificate the upstream presented once
+//! the connection is up, which is what `cert-sha256` pins are compared with in
+//! `connected_to_upstream`. A connection to a certificate that isn't pinned is
+//! failed before the request is sent on it.
+
+use std::{fs, sync::Arc};
+
+use miette::{miette, Context, IntoDiagnostic, Result};
+use motya_config::common_types::connectors::UpstreamTlsConfig;
+use pingora::{
+    prelude::HttpPeer,
+    protocols::Digest,
+    tls::{pkey::PKey, x509::X509},
+    utils::tls::CertKey,
+    Error, ErrorType,
+};
+
+#[derive(Clone)]
+pub struct UpstreamTls {
+    verify_cert: bool,
+    ca: Option<Arc<Box<[X509]>>>,
+    client_cert_key: Option<Arc<CertKey>>,
+    pins: Vec<[u8; 32]>,
+}
+
+impl UpstreamTls {
+    pub fn load(config: &UpstreamTlsConfig) -> Result<Self> {
+        let ca = match &config.ca_path {
+            Some(path) => {
+                let pem = fs::read(path)
+                    .into_diagnostic()
+                    .with_context(|| format!("Reading the CA bundle {}", path.display()))?;
+                let certs = X509::stack_from_pem(&pem)
+                    .into_diagnostic()
+                    .with_context(|| format!("Parsing the CA bundle {}", path.display()))?;
+                if certs.is_empty() {
+                    return Err(miette!(
+                        "The CA bundle {} has no certificate",
+                        path.display()
+                    ));
+                }
+                Some(Arc::new(certs.into_boxed_slice()))
+            }
+            None => None,
+        };
+
+        let client_cert_key = match &config.client_cert {
+            Some(client) => {
+                let certs = fs::read(&client.cert_path)
+                    .into_diagnostic()
+                    .and_then(|pem| X509::stack_from_pem(&pem).into_diagnostic())
+                    .with_context(|| {
+                        format!(
+                            "Loading the client certificate {}",
+                            client.cert_path.display()
+                        )
+                    })?;
+                let key = fs::read(&client.key_path)
+                    .into_diagnostic()
+                    .and_then(|pem| PKey::private_key_from_pem(&pem).into_diagnostic())
+                    .with_context(|| {
+                        format!("Loading the client key {}", client.key_path.display())
+                    })?;
+                if certs.is_empty() {
+                    return Err(miette!(
+                        "The client certificate {} has no certificate",
+                        client.cert_path.display()
+                    ));
+                }
+                Some(Arc::new(CertKey::new(certs, key)))
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            verify_cert: config.verify_cert,
+            ca,
+            client_cert_key,
+            pins: config.cert_sha256.clone(),
+        })
+    }
+
+    pub fn configure_peer(&self, peer: &mut HttpPeer) {
+        peer.options.verify_cert = self.verify_cert;
+        peer.options.verify_hostname = self.verify_cert;
+        if let Some(ca) = &self.ca {
+            peer.options.ca = Some(ca.clone());
+        }
+        if let Some(client_cert_key) = &self.client_cert_key {
+            peer.client_cert_key = Some(client_cert_key.clone());
+        }
+    }
+
+    /// Fails a connection whose certificate is none of the pinned ones. Without
+    /// pins every certificate passes.
+    pub fn check_pins(&self, digest: Option<&Digest>) -> pingora::Result<()> {
+        if self.pins.is_empty() {
+            return Ok(());
+        }
+
+        let cert_digest = digest
+            .and_then(|digest| digest.ssl_digest.as_ref())
+            .map(|ssl| ssl.cert_digest.as_slice());
+
+        match cert_digest {
+            Some(cert_digest) if self.pins.iter().any(|pin| pin == cert_digest) => Ok(()),
+            Some(cert_digest) => Error::e_explain(
+                ErrorType::InvalidCert,
+                format!(
+                    "the upstream certificate sha256 {} is not pinned",
+                    hex(cert_digest)
+                ),
+            ),
+            None => Error::e_explain(
+                ErrorType::InvalidCert,
+                "the upstream connection has no certificate to check the pins against",
+            ),
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use motya_config::common_types::connectors::ClientCertConfig;
+    use pingora::{protocols::tls::SslDigest, tls::hash::MessageDigest};
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::proxy::acme::self_signed;
+
+    fn digest(cert_digest: Vec<u8>) -> Digest {
+        Digest {
+            ssl_digest: Some(Arc::new(SslDigest {
+                cipher: "TLS_AES_128_GCM_SHA256",
+                version: "TLSv1.3",
+                organization: None,
+                serial_number: None,
+                cert_digest,
+            })),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_load_and_configure_peer() {
+        let dir = TempDir::new().unwrap();
+        let (cert, key) = self_signed(&["upstream.test".to_string()], 1).unwrap();
+        let cert_path = dir.path().join("cert.pem");
+        let key_path = dir.path().join("key.pem");
+        fs::write(&cert_path, &cert).unwrap();
+        fs::write(&key_path, &key).unwrap();
+
+        let tls = UpstreamTls::load(&UpstreamTlsConfig {
+            verify_cert: true,
+            ca_path: Some(cert_path.clone()),
+            client_cert: Some(ClientCertConfig {
+                cert_path: cert_path.clone(),
+                key_path: key_path.clone(),
+            }),
+            cert_sha256: vec![],
+        })
+        .unwrap();
+
+        let mut peer = HttpPeer::new("127.0.0.1:443", true, "upstream.test".to_string());
+        tls.configure_peer(&mut peer);
+        assert!(peer.options.verify_cert);
+        assert_eq!(peer.options.ca.as_ref().map(|ca| ca.len()), Some(1));
+        assert!(peer.client_cert_key.is_some());
+
+        // A key is not a certificate.
+        let err = UpstreamTls::load(&UpstreamTlsConfig {
+            ca_path: Some(key_path),
+            ..Default::default()
+        });
+        assert!(err.is_err());
+    }
+
+    #[test]
+    fn test_skip_verification() {
+        let tls = UpstreamTls::load(&UpstreamTlsConfig {
+            verify_cert: false,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let mut peer = HttpPeer::new("127.0.0.1:443", true, "upstream.test".to_string());
+        tls.configure_peer(&mut peer);
+        assert!(!peer.options.verify_cert);
+        assert!(!peer.options.verify_hostname);
+        assert!(peer.options.ca.is_none());
+    }
+
+    #[test]
+    fn test_check_pins() {
+        let (cert, _) = self_signed(&["upstream.test".to_string()], 1).unwrap();
+        let cert = X509::from_pem(&cert).unwrap();
+        let pin: [u8; 32] = cert
+            .digest(MessageDigest::sha256())
+            .unwrap()
+            .as_ref()
+            .try_into()
+            .unwrap();
+
+        let unpinned = UpstreamTls::load(&UpstreamTlsConfig::default()).unwrap();
+        assert!(unpinned.check_pins(None).is_ok());
+
+        let pinned = UpstreamTls::load(&UpstreamTlsConfig {
+            cert_sha256: vec![[0; 32], pin],
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(pinned.check_pins(Some(&digest(pin.to_vec()))).is_ok());
+
+        let err = pinned.check_pins(Some(&digest(vec![1; 32]))).unwrap_err();
+        assert_eq!(err.etype, ErrorType::InvalidCert);
+        assert!(pinned.check_pins(None).is_err());
+    }
+}
@@ -0,0 +1,278 @@
+//! Reloading of TLS certificates when their files change on disk.
+//!
+//! Every TLS listener hands out its certificate through a [`ReloadableCert`]
+//! during the handshake. [`watch`] follows the directories holding those files
+//! and swaps a listener's key pair once its certificate or key is rewritten, so a
+//! renewal (certbot, an ACME client, a mounted Kubernetes secret) is served from
+//! the next handshake on. Connections already established are left alone.
+
+use std::{
+    convert::Infallible,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use arc_swap::ArcSwap;
+use async_trait::async_trait;
+use miette::{miette, IntoDiagnostic, Result, WrapErr};
+use motya_config::common_types::listeners::TlsConfig;
+use notify::{Event, RecursiveMode, Watcher};
+use pingora::{
+    listeners::TlsAccept,
+    protocols::tls::TlsRef,
+    tls::{
+        ext,
+        pkey::{PKey, Private},
+        x509::X509,
+    },
+};
+use tokio::sync::mpsc;
+
+/// Every certificate served by a TLS listener of this process.
+static REGISTERED: Mutex<Vec<Arc<ReloadableCert>>> = Mutex::new(Vec::new());
+
+/// A certificate and its key, as read from disk.
+struct KeyPair {
+    leaf: X509,
+    chain: Vec<X509>,
+    key: PKey<Private>,
+    /// The file contents the pair was parsed from, to tell a rewrite from a touch.
+    raw: (Vec<u8>, Vec<u8>),
+}
+
+impl KeyPair {
+    fn read(cert_path: &Path, key_path: &Path) -> Result<Self> {
+        let cert_pem = fs::read(cert_path)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("cannot read certificate {}", cert_path.display()))?;
+        let key_pem = fs::read(key_path)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("cannot read key {}", key_path.display()))?;
+
+        Self::parse(cert_pem, key_pem)
+    }
+
+    /// Parses a PEM certificate chain, leaf first, and the PEM private key of the leaf.
+    fn parse(cert_pem: Vec<u8>, key_pem: Vec<u8>) -> Result<Self> {
+        let mut certs = X509::stack_from_pem(&cert_pem)
+            .into_diagnostic()?
+            .into_iter();
+        let leaf = certs
+            .next()
+            .ok_or_else(|| miette!("the certificate file holds no certificate"))?;
+        let key = PKey::private_key_from_pem(&key_pem).into_diagnostic()?;
+
+        // Renewals rarely replace both files at once; a certificate read before its
+        // new key lands must not be served with the old one.
+        if !leaf.public_key().into_diagnostic()?.public_eq(&key) {
+            return Err(miette!("the key does not belong to the certificate"));
+        }
+
+        Ok(Self {
+            leaf,
+            chain: certs.collect(),
+            key,
+            raw: (cert_pem, key_pem),
+        })
+    }
+}
+
+/// The certificate of one TLS listener, replaceable while the listener runs.
+pub struct ReloadableCert {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    current: ArcSwap<KeyPair>,
+}
+
+impl ReloadableCert {
+    /// Reads the key pair of `tls` and registers it with the watcher.
+    pub fn load(tls: &TlsConfig) -> Result<Arc<Self>> {
+        let pair = KeyPair::read(&tls.cert_path, &tls.key_path)?;
+
+        let cert = Arc::new(Self {
+            cert_path: tls.cert_path.clone(),
+            key_path: tls.key_path.clone(),
+            current: ArcSwap::from_pointee(pair),
+        });
+
+        REGISTERED.lock().unwrap().push(cert.clone());
+
+        Ok(cert)
+    }
+
+    /// Re-reads the files and swaps the key pair if they changed.
+    ///
+    /// Returns whether a new pair is now served. A pair that cannot be read or
+    /// does not match is an error, and the previous one stays in place.
+    pub fn reload(&self) -> Result<bool> {
+        let pair = KeyPair::read(&self.cert_path, &self.key_path)?;
+
+        if pair.raw == self.current.load().raw {
+            return Ok(false);
+        }
+
+        self.current.store(Arc::new(pair));
+        Ok(true)
+    }
+
+    fn directories(&self) -> impl Iterator<Item = &Path> {
+        [&self.cert_path, &self.key_path]
+            .into_iter()
+            .map(|path| match path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            })
+    }
+}
+
+#[async_trait]
+impl TlsAccept for ReloadableCert {
+    async fn certificate_callback(&self, ssl: &mut TlsRef) {
+        let pair = self.current.load();
+
+        let result = ext::ssl_use_certificate(ssl, &pair.leaf)
+            .and_then(|_| ext::ssl_use_private_key(ssl, &pair.key))
+            .and_then(|_| {
+                pair.chain
+                    .iter()
+                    .try_for_each(|cert| ext::ssl_add_chain_cert(ssl, cert))
+            });
+
+        if let Err(err) = result {
+            tracing::error!("Cannot use certificate {}: {err}", self.cert_path.display());
+        }
+    }
+}
+
+/// Reloads every registered certificate whose files changed, logging the outcome.
+pub fn reload_all() {
+    let certs = REGISTERED.lock().unwrap().clone();
+
+    for cert in &certs {
+        match cert.reload() {
+            Ok(true) => tracing::info!("Reloaded certificate {:?}", cert.cert_path),
+            Ok(false) => {}
+            Err(err) => tracing::warn!(
+                "Cannot reload certificate {:?}: {err:?}. Keeping the current one",
+                cert.cert_path
+            ),
+        }
+    }
+}
+
+/// Reloads the registered certificates whenever a file in one of their
+/// directories changes. Idles if no listener uses TLS.
+///
+/// Directories are watched rather than the files themselves: renewals usually
+/// replace a file or re-point a symlink, which a watch on the old file misses.
+pub async fn watch() -> Result<Infallible, Box<dyn std::error::Error + Send + Sync>> {
+    let certs = REGISTERED.lock().unwrap().clone();
+
+    let mut directories: Vec<&Path> = certs.iter().flat_map(|c| c.directories()).collect();
+    directories.sort();
+    directories.dedup();
+
+    if directories.is_empty() {
+        return std::future::pending().await;
+    }
+
+    let (tx, mut rx) = mpsc::channel(100);
+
+    let mut watcher = notify::recommended_watcher(move |res: Result<Event, notify::Error>| {
+        if let Ok(event) = res {
+            if event.kind.is_modify() || event.kind.is_create() || event.kind.is_remove() {
+                let _ = tx.blocking_send(event);
+            }
+        }
+    })?;
+
+    for dir in directories {
+        tracing::info!("Watching certificates in {:?}", dir);
+
+        if let Err(err) = watcher.watch(dir, RecursiveMode::NonRecursive) {
+            tracing::warn!("Cannot watch {dir:?}: {err}. Certificates there are not reloaded");
+        }
+    }
+
+    while rx.recv().await.is_some() {
+        // Let the writer finish replacing both files before reading them.
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        while rx.try_recv().is_ok() {}
+
+        reload_all();
+    }
+
+    Err("certificate watcher stopped".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::acme;
+
+    fn self_signed(name: &str) -> (Vec<u8>, Vec<u8>) {
+        acme::self_signed(&[name.to_string()], 1).unwrap()
+    }
+
+    fn write_pair(tls: &TlsConfig, (cert, key): &(Vec<u8>, Vec<u8>)) {
+        fs::write(&tls.cert_path, cert).unwrap();
+        fs::write(&tls.key_path, key).unwrap();
+    }
+
+    fn served_name(cert: &ReloadableCert) -> String {
+        let pair = cert.current.load();
+        let entry = pair.leaf.subject_name().entries().next().unwrap();
+        entry.data().as_utf8().unwrap().to_string()
+    }
+
+    fn tls_in(dir: &Path) -> TlsConfig {
+        TlsConfig {
+            cert_path: dir.join("fullchain.pem"),
+            key_path: dir.join("privkey.pem"),
+        }
+    }
+
+    #[test]
+    fn test_reload_swaps_a_renewed_pair() {
+        let dir = tempfile::tempdir().unwrap();
+        let tls = tls_in(dir.path());
+
+        write_pair(&tls, &self_signed("old.example.com"));
+        let cert = ReloadableCert::load(&tls).unwrap();
+        assert_eq!(served_name(&cert), "old.example.com");
+
+        assert!(!cert.reload().unwrap(), "unchanged files are not reloaded");
+
+        write_pair(&tls, &self_signed("new.example.com"));
+        assert!(cert.reload().unwrap());
+        assert_eq!(served_name(&cert), "new.example.com");
+    }
+
+    #[test]
+    fn test_half_written_renewal_keeps_the_old_pair() {
+        let dir = tempfile::tempdir().unwrap();
+        let tls = tls_in(dir.path());
+
+        write_pair(&tls, &self_signed("old.example.com"));
+        let cert = ReloadableCert::load(&tls).unwrap();
+
+        let (new_cert, _) = self_signed("new.example.com");
+        fs::write(&tls.cert_path, new_cert).unwrap();
+
+        let err = cert.reload().unwrap_err();
+        assert!(err.to_string().contains("does not belong"), "{err}");
+        assert_eq!(served_name(&cert), "old.example.com");
+
+        fs::remove_file(&tls.key_path).unwrap();
+        assert!(cert.reload().is_err());
+        assert_eq!(served_name(&cert), "old.example.com");
+    }
+
+    #[test]
+    fn test_missing_files_fail_to_load() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(ReloadableCert::load(&tls_in(dir.path())).is_err());
+    }
+}
@@ -3,7 +3,6 @@ use std::{
 };
 
 use futures_util::future::try_join_all;
-use miette::IntoDiagnostic;
 use motya_config::{
     common_types::definitions_table::DefinitionsTable,
     config_source::ConfigSource,
@@ -12,11 +11,17 @@ use motya_config::{
     loader::{ConfigLoader, FileConfigLoaderProvider},
 };
 use notify::{Event, RecursiveMode, Watcher};
-use tokio::sync::mpsc;
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::mpsc,
+};
 
 use crate::{
     fs_adapter::TokioFs,
-    proxy::{upstream_factory::UpstreamFactory, upstream_router::UpstreamRouter, SharedProxyState},
+    proxy::{
+        config_version, upstream_factory::UpstreamFactory, upstream_router::UpstreamRouter,
+        SharedProxyState,
+    },
 };
 
 pub struct ConfigWatcher<
@@ -55,9 +60,16 @@ impl<Cs: ConfigSource, T: FileConfigLoaderProvider + Clone> ConfigWatcher<Cs, T>
         self.active_proxies.insert(name, state);
     }
 
+    /// Reloads the configuration when the watched files change, and rebuilds every
+    /// router on `SIGHUP`.
+    ///
+    /// A reload that fails to parse or link is logged and the running configuration
+    /// is kept.
     pub async fn watch(&mut self) -> Result<Infallible, Box<dyn std::error::Error + Send + Sync>> {
         tracing::info!("Starting watcher on: {:?}", &self.watch_entry_path);
 
+        let mut hangup = signal(SignalKind::hangup())?;
+
         let (tx, mut rx) = mpsc::channel(100);
 
         let mut watcher = notify::recommended_watcher(move |res: Result<Event, notify::Error>| {
@@ -68,23 +80,39 @@ impl<Cs: ConfigSource, T: FileConfigLoaderProvider + Clone> ConfigWatcher<Cs, T>
             }
         })?;
 
-        watcher.watch(&self.watch_entry_path, RecursiveMode::Recursive)?;
+        if let Err(err) = watcher.watch(&self.watch_entry_path, RecursiveMode::Recursive) {
+            tracing::warn!(
+                "Cannot watch {:?}: {err}. The configuration is only reloaded on SIGHUP",
+                &self.watch_entry_path
+            );
+        }
 
         loop {
-            if let Some(_event) = rx.recv().await {
-                tokio::time::sleep(Duration::from_millis(100)).await;
-
-                while rx.try_recv().is_ok() {}
+            let rebuild_all = tokio::select! {
+                Some(_event) = rx.recv() => {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
 
-                match self.reload().await {
-                    Ok(_) => {}
-                    Err(err) => tracing::error!("fail on reload: {err}"),
+                    while rx.try_recv().is_ok() {}
+                    false
                 }
+                Some(()) = hangup.recv() => {
+                    tracing::info!("SIGHUP received");
+                    true
+                }
+            };
+
+            match self.reload(rebuild_all).await {
+                Ok(_) => {}
+                Err(err) => tracing::error!("fail on reload: {err}"),
             }
         }
     }
 
-    async fn reload(&mut self) -> miette::Result<()> {
+    /// Re-parses the configuration and swaps the routers of the running services.
+    ///
+    /// Only services whose connectors changed get a new router, unless `rebuild_all`
+    /// is set. Requests already in flight keep the router they started with.
+    async fn reload(&mut self, rebuild_all: bool) -> miette::Result<()> {
         tracing::info!("Reloading configuration...");
 
         let mut new_definitions = DefinitionsTable::new_with_global();
@@ -110,29 +138,37 @@ impl<Cs: ConfigSource, T: FileConfigLoaderProvider + Clone> ConfigWatcher<Cs, T>
 
                 for (name, new) in new_proxies.iter() {
                     if let Some(old) = old_proxies.get(name) {
-                        if old.connectors != new.connectors {
+                        if rebuild_all || old.connectors != new.connectors {
                             if let Some(active_config) = self.active_proxies.get(*name) {
-                                println!("Connectors changed for proxy '{}'", new.name);
+                                tracing::info!("Rebuilding router of proxy '{}'", new.name);
                                 let upstreams = try_join_all(
                                     new.connectors
                                         .upstreams
                                         .clone()
                                         .into_iter()
-                                        .map(|cfg| self.upstream_factory.create_context(cfg))
+                                        .map(|cfg| {
+                                            self.upstream_factory.create_context(&new.name, cfg)
+                                        })
                                         .collect::<Vec<_>>(),
                                 )
                                 .await?;
 
-                                let router = UpstreamRouter::build(upstreams).into_diagnostic()?;
+                                let router = UpstreamRouter::build(upstreams)?;
 
-                                active_config.swap(router.into());
+                                active_config.router.swap(router.into());
                             }
                             // logic...
                         }
                     } else {
-                        // println!("New proxy detected: '{}'", new.name);
+                        tracing::warn!(
+                            "Proxy '{}' is new and will only start after a restart",
+                            new.name
+                        );
                     }
                 }
+
+                config_version::publish(&cfg);
+                self.config = cfg;
             }
             Ok(None) => {
                 tracing::warn!("Failed to load config: invariant violated: path not exist. Keeping old configuration.");
@@ -158,14 +194,19 @@ mod tests {
         listeners::Listeners,
         simple_response_type::SimpleResponseConfig,
     };
+    use pingora::server::Server;
     use tempfile::env::temp_dir;
     use tokio::sync::Mutex;
 
     use super::*;
-    use crate::proxy::{
-        filters::{chain_resolver::ChainResolver, registry::FilterRegistry},
-        rate_limiter::registry::StorageRegistry,
-        ArcSwap,
+    use crate::{
+        app_context::build_services,
+        proxy::{
+            filters::{chain_resolver::ChainResolver, registry::FilterRegistry},
+            maintenance::Maintenance,
+            rate_limiter::registry::StorageRegistry,
+            ProxyState,
+        },
     };
 
     #[derive(Clone)]
@@ -192,18 +233,35 @@ mod tests {
         }
     }
 
-    #[tokio::test]
-    async fn test_watcher_updates_proxies_using_mock() {
-        let new_proxy_config = Config {
+    /// A config with one proxy, `Test`, answering `body` on every path.
+    fn static_config(body: &str) -> Config {
+        Config {
             basic_proxies: vec![ProxyConfig {
                 listeners: Listeners { list_cfgs: vec![] },
+                access_log: None,
+                limits: None,
+                error_pages: None,
+                maintenance: None,
                 connectors: Connectors {
                     upstreams: vec![UpstreamContextConfig {
                         chains: vec![],
                         lb_options: Default::default(),
+                        compression: None,
+                        sse: None,
+                        cache: None,
+                        retry: None,
+                        mirror: None,
+                        timeout: None,
+                        allow_upgrades: true,
+                        grpc: false,
+                        upstream_tls: None,
+                        h2: None,
+                        debug_trace: false,
+                        conditions: Default::default(),
+                        rewrite: None,
                         upstream: UpstreamConfig::Static(SimpleResponseConfig {
                             http_code: StatusCode::OK,
-                            response_body: "ver 1".to_string(),
+                            response_body: body.to_string(),
                             prefix_path: PathAndQuery::from_static("/"),
                         }),
                     }],
@@ -211,7 +269,12 @@ mod tests {
                 name: "Test".to_string(),
             }],
             ..Config::default()
-        };
+        }
+    }
+
+    #[tokio::test]
+    async fn test_watcher_updates_proxies_using_mock() {
+        let new_proxy_config = static_config("ver 1");
 
         let mock_loader = MockConfigLoader::new(new_proxy_config.clone());
         let table = DefinitionsTable::default();
@@ -235,21 +298,26 @@ mod tests {
             );
 
         let upstream = factory
-            .create_context(new_proxy_config.basic_proxies[0].connectors.upstreams[0].clone())
+            .create_context(
+                "test",
+                new_proxy_config.basic_proxies[0].connectors.upstreams[0].clone(),
+            )
             .await
             .unwrap();
 
-        let tracked_router = Arc::new(ArcSwap::from_pointee(
+        let state = Arc::new(ProxyState::new(
             UpstreamRouter::build(vec![upstream]).unwrap(),
+            Maintenance::start("test", Default::default()),
         ));
+        let tracked_router = &state.router;
 
         watcher.insert_proxy_state(
             new_proxy_config.basic_proxies[0].name.clone(),
-            tracked_router.clone(),
+            state.clone(),
         );
 
         //nothing happen.
-        watcher.reload().await.expect("Reload failed");
+        watcher.reload(false).await.expect("Reload failed");
 
         let router = tracked_router.load();
         let first_version = router.get_upstream_by_path("/").unwrap();
@@ -271,7 +339,7 @@ mod tests {
         drop(rewrited_config);
 
         //switch response
-        watcher.reload().await.expect("Reload failed");
+        watcher.reload(false).await.expect("Reload failed");
 
         let router = tracked_router.load();
         let second_version = router.get_upstream_by_path("/").unwrap();
@@ -280,5 +348,58 @@ mod tests {
         };
 
         assert_eq!(response.response_body, "ver 2");
+
+        //unchanged config keeps the router, SIGHUP rebuilds it anyway
+        let current = tracked_router.load_full();
+
+        watcher.reload(false).await.expect("Reload failed");
+        assert!(Arc::ptr_eq(&current, &tracked_router.load_full()));
+
+        watcher.reload(true).await.expect("Reload failed");
+        assert!(!Arc::ptr_eq(&current, &tracked_router.load_full()));
+    }
+
+    #[tokio::test]
+    async fn test_reload_replaces_the_state_of_built_services() {
+        let config = static_config("ver 1");
+        let table = DefinitionsTable::default();
+        let resolver = ChainResolver::new(
+            table.clone(),
+            Arc::new(Mutex::new(FilterRegistry::default())),
+            Arc::new(StorageRegistry::default()),
+        )
+        .await
+        .unwrap();
+        let server = Server::new(None).unwrap();
+
+        let mut watcher: ConfigWatcher<FileCollector<TokioFs>, MockConfigLoader> =
+            ConfigWatcher::new(
+                config.clone(),
+                table,
+                temp_dir(),
+                UpstreamFactory::new(resolver.clone()),
+                MockConfigLoader::new(static_config("ver 2")),
+            );
+
+        // The states are registered the way the server does it at startup.
+        let mut states = vec![];
+        build_services(&config, &resolver, &server, |name, state| {
+            watcher.insert_proxy_state(name.to_string(), state.clone());
+            states.push(state);
+        })
+        .await
+        .unwrap();
+
+        let before = states[0].router.load_full();
+        watcher.reload(true).await.expect("Reload failed");
+
+        let router = states[0].router.load_full();
+        assert!(!Arc::ptr_eq(&before, &router));
+
+        let upstream = router.get_upstream_by_path("/").unwrap();
+        let UpstreamConfig::Static(response) = &upstream.upstream else {
+            unreachable!()
+        };
+        assert_eq!(response.response_body, "ver 2");
     }
 }
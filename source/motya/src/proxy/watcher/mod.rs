@@ -1,2 +1,3 @@
+pub mod cert_watcher;
 mod diffs;
 pub mod file_watcher;
@@ -0,0 +1,158 @@
+//! Taking over the listeners of a running instance (`--upgrade`).
+//!
+//! pingora moves the listening sockets itself: the new process binds the upgrade
+//! socket while it bootstraps and waits there, and the running process sends its
+//! listeners over it when it gets `SIGQUIT`. What is left to do here is asking the
+//! running instance for them, so that starting the new process is enough.
+
+use std::{
+    fs, io,
+    os::unix::fs::FileTypeExt,
+    path::Path,
+    process, thread,
+    time::{Duration, Instant},
+};
+
+use miette::{miette, Context, IntoDiagnostic};
+use motya_config::internal::Config;
+use nix::{
+    sys::signal::{kill, Signal},
+    unistd::Pid,
+};
+
+/// How often, and for how long, the hand-over request waits for the upgrade socket
+/// to be bound.
+const BIND_POLL_INTERVAL: Duration = Duration::from_millis(20);
+const BIND_WAIT: Duration = Duration::from_secs(30);
+
+/// Prepares the hand-over from the instance named by the pidfile of `config`.
+///
+/// Once the upgrade socket is bound, that instance is sent `SIGQUIT`. Without a
+/// pidfile of a running instance, the signal has to be sent by hand.
+pub fn take_over(config: &Config) -> miette::Result<()> {
+    if !cfg!(target_os = "linux") {
+        return Err(miette!("--upgrade is only supported on Linux"));
+    }
+
+    let socket = config
+        .upgrade_socket
+        .clone()
+        .ok_or_else(|| miette!("--upgrade needs an upgrade socket"))?;
+    if !socket.is_absolute() {
+        return Err(miette!(
+            "The upgrade socket {socket:?} must be an absolute path"
+        ));
+    }
+
+    // Left behind by an earlier hand-over; pingora binds the path again anyway, and
+    // its appearance is how the new socket is told apart.
+    remove_socket(&socket)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Cannot prepare the upgrade socket {socket:?}"))?;
+
+    let Some(pid) = config.pid_file.as_deref().and_then(running_instance) else {
+        tracing::warn!(
+            "No running instance in the pidfile, waiting on {socket:?} until one is sent SIGQUIT"
+        );
+        return Ok(());
+    };
+
+    tracing::info!("Taking over the listeners of PID {pid} over {socket:?}");
+    thread::spawn(move || request_handover(pid, &socket));
+
+    Ok(())
+}
+
+/// The process id in `pidfile`, if that process is still running and is not this one.
+fn running_instance(pidfile: &Path) -> Option<Pid> {
+    let pid = fs::read_to_string(pidfile).ok()?.trim().parse().ok()?;
+    let pid = Pid::from_raw(pid);
+
+    let alive = kill(pid, None).is_ok();
+    (alive && pid.as_raw() != process::id() as i32).then_some(pid)
+}
+
+fn request_handover(pid: Pid, socket: &Path) {
+    let started = Instant::now();
+    while !is_socket(socket) {
+        if started.elapsed() > BIND_WAIT {
+            tracing::error!(
+                "The upgrade socket {socket:?} was never bound, PID {pid} keeps its listeners"
+            );
+            return;
+        }
+        thread::sleep(BIND_POLL_INTERVAL);
+    }
+
+    match kill(pid, Signal::SIGQUIT) {
+        Ok(()) => tracing::info!("Asked PID {pid} to hand over its listeners"),
+        Err(err) => tracing::error!("Cannot send SIGQUIT to PID {pid}: {err}"),
+    }
+}
+
+fn is_socket(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket())
+}
+
+fn remove_socket(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path),
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "the path exists and is not a socket",
+        )),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixListener;
+
+    use super::*;
+
+    #[test]
+    fn test_running_instance() {
+        let dir = tempfile::tempdir().unwrap();
+        let pidfile = dir.path().join("motya.pidfile");
+
+        assert_eq!(running_instance(&pidfile), None);
+
+        fs::write(&pidfile, "not a pid\n").unwrap();
+        assert_eq!(running_instance(&pidfile), None);
+
+        // This process is never asked to hand over to itself.
+        fs::write(&pidfile, format!("{}\n", process::id())).unwrap();
+        assert_eq!(running_instance(&pidfile), None);
+
+        let mut child = process::Command::new("sleep").arg("10").spawn().unwrap();
+        fs::write(&pidfile, format!("{}\n", child.id())).unwrap();
+        assert_eq!(
+            running_instance(&pidfile),
+            Some(Pid::from_raw(child.id() as i32))
+        );
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+        assert_eq!(running_instance(&pidfile), None);
+    }
+
+    #[test]
+    fn test_remove_socket() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let socket = dir.path().join("upgrade.sock");
+        let _listener = UnixListener::bind(&socket).unwrap();
+        assert!(is_socket(&socket));
+        remove_socket(&socket).unwrap();
+        assert!(!socket.exists());
+
+        remove_socket(&socket).unwrap();
+
+        let file = dir.path().join("not-a-socket");
+        fs::write(&file, "").unwrap();
+        assert!(remove_socket(&file).is_err());
+        assert!(file.exists());
+    }
+}
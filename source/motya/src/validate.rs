@@ -0,0 +1,282 @@
+//! `motya validate <entry.kdl>`: loads a configuration the way the server does,
+//! without binding or spawning anything, and reports every problem at once so it
+//! can gate a deploy.
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use motya_config::{
+    common_types::{
+        basic_auth::CredentialsSource,
+        definitions::PluginSource,
+        definitions_table::DefinitionsTable,
+        error::ConfigError,
+        listeners::{ListenerKind, Listeners},
+    },
+    internal::Config,
+    kdl::fs_loader::FileCollector,
+    loader::ConfigLoader,
+};
+
+use crate::{fs_adapter::TokioFs, proxy::filters::generate_registry};
+
+/// A file or directory the configuration points at that is not there.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MissingPath {
+    pub what: String,
+    pub path: PathBuf,
+}
+
+impl fmt::Display for MissingPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} not found: {}", self.what, self.path.display())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ValidationReport {
+    pub errors: ConfigError,
+    pub missing: Vec<MissingPath>,
+}
+
+impl ValidationReport {
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty() && self.missing.is_empty()
+    }
+
+    /// Prints the problems to stderr, configuration errors with their source
+    /// snippets, or a one-line confirmation to stdout when there are none.
+    /// Warnings are printed either way.
+    pub fn print(&self, entry: &Path) {
+        for warning in &self.errors.warnings {
+            eprintln!("warning: {}: {warning}", warning.location());
+        }
+
+        if self.is_ok() {
+            println!("{}: configuration is valid", entry.display());
+            return;
+        }
+
+        if !self.errors.is_empty() {
+            eprintln!("{:?}", miette::Report::new(self.errors.clone()));
+        }
+
+        for missing in &self.missing {
+            eprintln!("error: {missing}");
+        }
+    }
+}
+
+pub async fn validate(entry: &Path, profile: Option<&str>) -> ValidationReport {
+    let mut definitions = DefinitionsTable::default();
+    generate_registry::load_registry(&mut definitions);
+
+    let loader = ConfigLoader::new(FileCollector::<TokioFs>::default())
+        .with_profile(profile.map(String::from));
+    let (config, errors) = loader
+        .load_lossy(Some(entry.to_path_buf()), &mut definitions)
+        .await;
+
+    let missing = config
+        .map(|config| missing_paths(&config, &definitions))
+        .unwrap_or_default();
+
+    ValidationReport { errors, missing }
+}
+
+/// TLS certificates and keys, file server roots, WASM plugin files and htpasswd
+/// files that do not exist. Relative paths are checked against the working directory, as the
+/// server resolves them.
+pub fn missing_paths(config: &Config, definitions: &DefinitionsTable) -> Vec<MissingPath> {
+    let mut missing = Vec::new();
+
+    // `system.acme` writes its certificate and key on startup if they are not there.
+    let provisioned: Vec<PathBuf> = config
+        .acme
+        .iter()
+        .flat_map(|acme| [acme.cert_path(), acme.key_path()])
+        .collect();
+
+    for proxy in &config.basic_proxies {
+        check_listeners(&mut missing, &provisioned, &proxy.name, &proxy.listeners);
+    }
+
+    for file_server in &config.file_servers {
+        check_listeners(
+            &mut missing,
+            &provisioned,
+            &file_server.name,
+            &file_server.listeners,
+        );
+
+        if let Some(base_path) = &file_server.base_path {
+            if !base_path.is_dir() {
+                missing.push(MissingPath {
+                    what: format!("Root of file server '{}'", file_server.name),
+                    path: base_path.clone(),
+                });
+            }
+        }
+    }
+
+    let mut plugins: Vec<_> = definitions.get_plugins().values().collect();
+    plugins.sort_by_key(|plugin| plugin.name.to_string());
+
+    for plugin in plugins {
+        if let PluginSource::File(path) = &plugin.source {
+            if !path.is_file() {
+                missing.push(MissingPath {
+                    what: format!("WASM module of plugin '{}'", plugin.name),
+                    path: path.clone(),
+                });
+            }
+        }
+    }
+
+    let mut credentials: Vec<_> = definitions.get_all_credentials().values().collect();
+    credentials.sort_by_key(|credentials| &credentials.name);
+
+    for credentials in credentials {
+        if let CredentialsSource::Htpasswd(path) = &credentials.source {
+            if !path.is_file() {
+                missing.push(MissingPath {
+                    what: format!("htpasswd file of credentials '{}'", credentials.name),
+                    path: path.clone(),
+                });
+            }
+        }
+    }
+
+    missing
+}
+
+fn check_listeners(
+    missing: &mut Vec<MissingPath>,
+    provisioned: &[PathBuf],
+    service: &str,
+    listeners: &Listeners,
+) {
+    for listener in &listeners.list_cfgs {
+        let ListenerKind::Tcp {
+            addr,
+            tls: Some(tls),
+            ..
+        } = &listener.source
+        else {
+            continue;
+        };
+
+        for (kind, path) in [("Certificate", &tls.cert_path), ("Key", &tls.key_path)] {
+            if !path.is_file() && !provisioned.contains(path) {
+                missing.push(MissingPath {
+                    what: format!("{kind} of listener '{addr}' in service '{service}'"),
+                    path: path.clone(),
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn write_config(dir: &Path, contents: &str) -> PathBuf {
+        let entry = dir.join("entry.kdl");
+        fs::write(&entry, contents).unwrap();
+        entry
+    }
+
+    #[tokio::test]
+    async fn test_valid_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let entry = write_config(
+            dir.path(),
+            r#"
+            services {
+                Api {
+                    listeners { "127.0.0.1:8080" }
+                    connectors {
+                        section "/" {
+                            return 200 "OK"
+                        }
+                    }
+                }
+            }
+            "#,
+        );
+
+        let report = validate(&entry, None).await;
+        assert!(report.is_ok(), "{report:?}");
+    }
+
+    #[tokio::test]
+    async fn test_config_errors_are_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let entry = write_config(
+            dir.path(),
+            r#"
+            services {
+                Api {
+                    listeners { "127.0.0.1:8080" }
+                    connectors {
+                        secton "/" {
+                            return 200 "OK"
+                        }
+                    }
+                }
+            }
+            "#,
+        );
+
+        let report = validate(&entry, None).await;
+        assert!(!report.is_ok());
+        assert!(!report.errors.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_missing_files_are_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let cert = dir.path().join("server.crt");
+        fs::write(&cert, "").unwrap();
+
+        let entry = write_config(
+            dir.path(),
+            &format!(
+                r#"
+                services {{
+                    Api {{
+                        listeners {{
+                            "127.0.0.1:8443" cert-path="{}" key-path="{}"
+                        }}
+                        connectors {{
+                            section "/" {{
+                                return 200 "OK"
+                            }}
+                        }}
+                    }}
+                    Files {{
+                        listeners {{ "127.0.0.1:8081" }}
+                        file-server root="{}"
+                    }}
+                }}
+                "#,
+                cert.display(),
+                dir.path().join("server.key").display(),
+                dir.path().join("public").display(),
+            ),
+        );
+
+        let report = validate(&entry, None).await;
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+
+        let missing: Vec<_> = report.missing.iter().map(|m| m.to_string()).collect();
+        assert_eq!(missing.len(), 2, "{missing:?}");
+        assert!(missing[0].starts_with("Key of listener '127.0.0.1:8443' in service 'Api'"));
+        assert!(missing[1].starts_with("Root of file server 'Files'"));
+    }
+}
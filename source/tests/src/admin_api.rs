@@ -0,0 +1,119 @@
+use std::{io::Write, thread, time::Duration};
+
+use motya::app_context::AppContext;
+use motya_config::cli::cli_struct::Cli;
+use reqwest::Client;
+use tempfile::NamedTempFile;
+use tokio::net::TcpStream;
+
+const ADMIN_CONFIG_TEMPLATE: &str = r#"
+system {
+    admin {
+        listen "127.0.0.1:__ADMIN_PORT__"
+        allow "127.0.0.1/32" auth-token-env="MOTYA_TEST_ADMIN_API_TOKEN"
+    }
+}
+services {
+    AdminTest {
+        listeners {
+            "127.0.0.1:__PROXY_PORT__"
+        }
+        connectors {
+            section "/ping" { return 200 "pong" }
+        }
+    }
+}
+"#;
+
+const TOKEN: &str = "admin-api-test-token";
+
+fn get_free_port() -> u16 {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("Failed to bind");
+    listener.local_addr().unwrap().port()
+}
+
+async fn wait_for_listener(addr: &str) {
+    let start = std::time::Instant::now();
+
+    while start.elapsed() < Duration::from_secs(5) {
+        if TcpStream::connect(addr).await.is_ok() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("Nothing listens at {addr} within timeout");
+}
+
+#[tokio::test]
+async fn test_admin_api_reports_live_routes() {
+    std::env::set_var("MOTYA_TEST_ADMIN_API_TOKEN", TOKEN);
+
+    let admin_port = get_free_port();
+    let proxy_port = get_free_port();
+
+    let config_content = ADMIN_CONFIG_TEMPLATE
+        .replace("__ADMIN_PORT__", &admin_port.to_string())
+        .replace("__PROXY_PORT__", &proxy_port.to_string());
+
+    let mut config_file = NamedTempFile::new().expect("Failed to create temp config file");
+    write!(config_file, "{}", config_content).expect("Failed to write config content");
+
+    let cli = Cli {
+        validate_configs: false,
+        threads_per_service: None,
+        config_entry: Some(config_file.path().to_path_buf()),
+        daemonize: false,
+        upgrade: false,
+        pidfile: None,
+        profile: None,
+        upgrade_socket: None,
+        command: None,
+    };
+
+    let mut app_ctx = AppContext::bootstrap(cli)
+        .await
+        .expect("Failed to bootstrap AppContext");
+    let services = app_ctx
+        .build_services()
+        .await
+        .expect("Failed to build services");
+
+    let (mut server, _watcher) = app_ctx.ready();
+    server.add_services(services);
+    server.bootstrap();
+
+    thread::spawn(move || {
+        server.run_forever();
+    });
+
+    let admin_addr = format!("127.0.0.1:{admin_port}");
+    wait_for_listener(&admin_addr).await;
+
+    let client = Client::new();
+
+    let denied = client
+        .get(format!("http://{admin_addr}/upstreams"))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(denied.status(), 401);
+
+    let response = client
+        .get(format!("http://{admin_addr}/upstreams"))
+        .bearer_auth(TOKEN)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), 200);
+
+    let body = response.text().await.expect("Failed to read body");
+    assert_eq!(body, "AdminTest\n  /ping (exact) -> static(200)\n");
+
+    let health = client
+        .get(format!("http://{admin_addr}/health"))
+        .bearer_auth(TOKEN)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(health.status(), 200);
+}
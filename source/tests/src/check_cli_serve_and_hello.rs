@@ -35,6 +35,7 @@ mod tests {
             daemonize: false,
             upgrade: false,
             pidfile: None,
+            profile: None,
             upgrade_socket: None,
             command: Some(Commands::Hello {
                 port,
@@ -83,6 +84,7 @@ mod tests {
             daemonize: false,
             upgrade: false,
             pidfile: None,
+            profile: None,
             upgrade_socket: None,
             command: Some(Commands::Serve {
                 port,
@@ -149,6 +151,7 @@ mod tests {
             daemonize: false,
             upgrade: false,
             pidfile: None,
+            profile: None,
             upgrade_socket: None,
             command: Some(Commands::Serve {
                 port: proxy_port,
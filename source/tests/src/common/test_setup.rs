@@ -19,7 +19,7 @@ use motya_config::{
         connectors::{Connectors, HttpPeerConfig, UpstreamConfig, UpstreamContextConfig, ALPN},
         definitions::{ChainItem, ConfiguredFilter, FilterChain, Modificator, NamedFilterChain},
         definitions_table::DefinitionsTable,
-        listeners::{ListenerConfig, ListenerKind, Listeners},
+        listeners::{ListenerConfig, ListenerKind, Listeners, SocketOptions},
         value::Value,
     },
     internal::{Config, ProxyConfig},
@@ -63,9 +63,15 @@ pub async fn setup_check_cidr() -> thread::JoinHandle<()> {
     let proxy_addr = "127.0.0.1:8081";
 
     let proxy = ProxyConfig {
+        access_log: None,
+        limits: None,
+        error_pages: None,
+        maintenance: None,
         connectors: Connectors {
             upstreams: vec![UpstreamContextConfig {
                 lb_options: Default::default(),
+                compression: None,
+                sse: None,
                 chains: vec![Modificator::Chain(NamedFilterChain {
                     name: "block-noob".to_string(),
                     chain: chain.clone(),
@@ -76,7 +82,6 @@ pub async fn setup_check_cidr() -> thread::JoinHandle<()> {
                     sni: String::new(),
                     tls: false,
                     prefix_path: PathAndQuery::from_static("/"),
-                    target_path: PathAndQuery::from_static("/"),
                     matcher: Default::default(),
                 }),
             }],
@@ -86,7 +91,9 @@ pub async fn setup_check_cidr() -> thread::JoinHandle<()> {
                 source: ListenerKind::Tcp {
                     addr: proxy_addr.to_string(),
                     offer_h2: false,
+                    proxy_protocol: false,
                     tls: None,
+                    socket: SocketOptions::default(),
                 },
             }],
         },
@@ -150,9 +157,15 @@ pub async fn setup_check_cidr_accept() -> thread::JoinHandle<()> {
     let proxy_addr = "127.0.0.1:8082";
 
     let proxy = ProxyConfig {
+        access_log: None,
+        limits: None,
+        error_pages: None,
+        maintenance: None,
         connectors: Connectors {
             upstreams: vec![UpstreamContextConfig {
                 lb_options: Default::default(),
+                compression: None,
+                sse: None,
                 chains: vec![Modificator::Chain(NamedFilterChain {
                     name: "block-noob".to_string(),
                     chain: chain.clone(),
@@ -163,7 +176,6 @@ pub async fn setup_check_cidr_accept() -> thread::JoinHandle<()> {
                     sni: String::new(),
                     tls: false,
                     prefix_path: PathAndQuery::from_static("/"),
-                    target_path: PathAndQuery::from_static("/"),
                     matcher: Default::default(),
                 }),
             }],
@@ -173,7 +185,9 @@ pub async fn setup_check_cidr_accept() -> thread::JoinHandle<()> {
                 source: ListenerKind::Tcp {
                     addr: proxy_addr.to_string(),
                     offer_h2: false,
+                    proxy_protocol: false,
                     tls: None,
+                    socket: SocketOptions::default(),
                 },
             }],
         },
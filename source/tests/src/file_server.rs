@@ -0,0 +1,207 @@
+use std::{io::Write, thread, time::Duration};
+
+use http::{header, StatusCode};
+use motya::app_context::AppContext;
+use motya_config::cli::cli_struct::Cli;
+use reqwest::Client;
+use tempfile::{NamedTempFile, TempDir};
+use tokio::net::TcpStream;
+
+const FILE_SERVER_CONFIG_TEMPLATE: &str = r#"
+system { }
+services {
+    Static {
+        listeners {
+            "127.0.0.1:__PORT__"
+        }
+        file-server root="__ROOT__" cache-control="public, max-age=60"
+    }
+}
+"#;
+
+fn get_free_port() -> u16 {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("Failed to bind");
+    listener.local_addr().unwrap().port()
+}
+
+async fn wait_for_server(addr: &str) {
+    let start = std::time::Instant::now();
+
+    while start.elapsed() < Duration::from_secs(5) {
+        if TcpStream::connect(addr).await.is_ok() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("File server did not start at {addr} within timeout");
+}
+
+/// Starts a file server for a directory holding `video.bin`, and returns its
+/// address with the contents of the file.
+async fn start_file_server() -> (String, Vec<u8>, TempDir) {
+    let root = tempfile::tempdir().expect("Failed to create temp dir");
+    let contents: Vec<u8> = (0..=255u8).cycle().take(4096).collect();
+    std::fs::write(root.path().join("video.bin"), &contents).unwrap();
+
+    let port = get_free_port();
+    let config_content = FILE_SERVER_CONFIG_TEMPLATE
+        .replace("__PORT__", &port.to_string())
+        .replace("__ROOT__", root.path().to_str().unwrap());
+
+    let mut config_file = NamedTempFile::new().expect("Failed to create temp config file");
+    write!(config_file, "{}", config_content).expect("Failed to write config content");
+
+    let cli = Cli {
+        validate_configs: false,
+        threads_per_service: None,
+        config_entry: Some(config_file.path().to_path_buf()),
+        daemonize: false,
+        upgrade: false,
+        pidfile: None,
+        profile: None,
+        upgrade_socket: None,
+        command: None,
+    };
+
+    let mut app_ctx = AppContext::bootstrap(cli)
+        .await
+        .expect("Failed to bootstrap AppContext");
+    let services = app_ctx
+        .build_services()
+        .await
+        .expect("Failed to build services");
+
+    let (mut server, _watcher) = app_ctx.ready();
+    server.add_services(services);
+    server.bootstrap();
+
+    thread::spawn(move || {
+        server.run_forever();
+    });
+
+    let addr = format!("127.0.0.1:{port}");
+    wait_for_server(&addr).await;
+
+    (format!("http://{addr}/video.bin"), contents, root)
+}
+
+#[tokio::test]
+async fn test_file_server_ranges() {
+    let (url, contents, _root) = start_file_server().await;
+    let client = Client::new();
+
+    let full = client.get(&url).send().await.unwrap();
+    assert_eq!(full.status(), StatusCode::OK);
+    assert_eq!(full.headers()[header::ACCEPT_RANGES], "bytes");
+    let etag = full.headers()[header::ETAG].clone();
+    assert_eq!(full.bytes().await.unwrap(), contents);
+
+    let partial = client
+        .get(&url)
+        .header(header::RANGE, "bytes=100-199")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(partial.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(
+        partial.headers()[header::CONTENT_RANGE],
+        "bytes 100-199/4096"
+    );
+    assert_eq!(
+        partial.headers()[header::CACHE_CONTROL],
+        "public, max-age=60"
+    );
+    assert_eq!(partial.bytes().await.unwrap(), contents[100..200]);
+
+    let suffix = client
+        .get(&url)
+        .header(header::RANGE, "bytes=-96")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(suffix.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(suffix.bytes().await.unwrap(), contents[4000..]);
+
+    // A resumed download of the same file continues where it stopped.
+    let resumed = client
+        .get(&url)
+        .header(header::RANGE, "bytes=2048-")
+        .header(header::IF_RANGE, etag)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resumed.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(resumed.bytes().await.unwrap(), contents[2048..]);
+
+    // The file changed since: the whole file is sent again.
+    let changed = client
+        .get(&url)
+        .header(header::RANGE, "bytes=2048-")
+        .header(header::IF_RANGE, "\"some-older-version\"")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(changed.status(), StatusCode::OK);
+    assert_eq!(changed.bytes().await.unwrap(), contents);
+
+    let unsatisfiable = client
+        .get(&url)
+        .header(header::RANGE, "bytes=5000-6000")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(unsatisfiable.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(
+        unsatisfiable.headers()[header::CONTENT_RANGE],
+        "bytes */4096"
+    );
+}
+
+#[tokio::test]
+async fn test_file_server_conditional_get() {
+    let (url, _contents, _root) = start_file_server().await;
+    let client = Client::new();
+
+    let full = client.get(&url).send().await.unwrap();
+    assert_eq!(full.status(), StatusCode::OK);
+    let etag = full.headers()[header::ETAG].clone();
+    let last_modified = full.headers()[header::LAST_MODIFIED].clone();
+
+    let by_etag = client
+        .get(&url)
+        .header(header::IF_NONE_MATCH, etag.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(by_etag.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(by_etag.headers()[header::ETAG], etag);
+    assert_eq!(
+        by_etag.headers()[header::CACHE_CONTROL],
+        "public, max-age=60"
+    );
+    assert!(by_etag.bytes().await.unwrap().is_empty());
+
+    let by_date = client
+        .get(&url)
+        .header(header::IF_MODIFIED_SINCE, last_modified)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(by_date.status(), StatusCode::NOT_MODIFIED);
+
+    let stale = client
+        .get(&url)
+        .header(header::IF_NONE_MATCH, "\"some-older-version\"")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(stale.status(), StatusCode::OK);
+
+    let old_date = client
+        .get(&url)
+        .header(header::IF_MODIFIED_SINCE, "Thu, 01 Jan 1970 00:00:00 GMT")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(old_date.status(), StatusCode::OK);
+}
@@ -0,0 +1,270 @@
+use std::{io::Write, net::SocketAddr, path::Path, thread, time::Duration};
+
+use bytes::Bytes;
+use http::{HeaderMap, Request, Response};
+use motya::app_context::AppContext;
+use motya_config::cli::cli_struct::Cli;
+use pingora::{
+    connectors::http::Connector,
+    http::RequestHeader,
+    prelude::HttpPeer,
+    protocols::{http::client::HttpSession, ALPN},
+};
+use tempfile::NamedTempFile;
+use tokio::net::{TcpListener, TcpStream};
+
+const GRPC_CONFIG_TEMPLATE: &str = r#"
+system { }
+services {
+    GrpcTest {
+        listeners {
+            "127.0.0.1:__PROXY_PORT__" cert-path="__ASSETS__/test.crt" key-path="__ASSETS__/test.key"
+        }
+        access-log "$path $status $grpc_status" path="__LOG__"
+        connectors {
+            section "/greeter.Greeter" as="prefix" {
+                compression
+                proxy "http://__BACKEND__" grpc=#true
+            }
+        }
+    }
+}
+"#;
+
+/// One gRPC message: uncompressed, with its length in front.
+fn message(payload: &[u8]) -> Bytes {
+    let mut framed = vec![0];
+    framed.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    framed.extend_from_slice(payload);
+    framed.into()
+}
+
+/// A minimal gRPC backend over cleartext HTTP/2: `SayHello` echoes the request
+/// messages and ends with an OK status in the trailers, every other method fails
+/// right away with a trailers-only `NOT_FOUND`.
+async fn spawn_grpc_backend() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        loop {
+            let (socket, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut connection = h2::server::handshake(socket).await.unwrap();
+                while let Some(Ok((request, respond))) = connection.accept().await {
+                    tokio::spawn(serve_call(request, respond));
+                }
+            });
+        }
+    });
+
+    addr
+}
+
+async fn serve_call(
+    request: Request<h2::RecvStream>,
+    mut respond: h2::server::SendResponse<Bytes>,
+) {
+    let response = Response::builder()
+        .status(200)
+        .header("content-type", "application/grpc");
+
+    if request.uri().path() != "/greeter.Greeter/SayHello" {
+        let response = response
+            .header("grpc-status", "5")
+            .header("grpc-message", "no such method")
+            .body(())
+            .unwrap();
+        respond.send_response(response, true).unwrap();
+        return;
+    }
+
+    let mut body = request.into_body();
+    let mut received = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.unwrap();
+        let _ = body.flow_control().release_capacity(chunk.len());
+        received.extend_from_slice(&chunk);
+    }
+
+    let mut stream = respond
+        .send_response(response.body(()).unwrap(), false)
+        .unwrap();
+    stream.send_data(received.into(), false).unwrap();
+
+    let mut trailers = HeaderMap::new();
+    trailers.insert("grpc-status", "0".parse().unwrap());
+    trailers.insert("grpc-message", "echoed".parse().unwrap());
+    stream.send_trailers(trailers).unwrap();
+}
+
+fn get_free_port() -> u16 {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("Failed to bind");
+    listener.local_addr().unwrap().port()
+}
+
+async fn wait_for_proxy(addr: &str) {
+    let start = std::time::Instant::now();
+
+    while start.elapsed() < Duration::from_secs(5) {
+        if TcpStream::connect(addr).await.is_ok() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("Proxy did not start at {addr} within timeout");
+}
+
+/// An HTTP/2 connection to the proxy, like a gRPC client would open it.
+async fn connect(proxy_addr: &str) -> HttpSession {
+    let mut peer = HttpPeer::new(proxy_addr, true, "localhost".to_string());
+    peer.options.verify_cert = false;
+    peer.options.verify_hostname = false;
+    peer.options.alpn = ALPN::H2;
+
+    let (session, _) = Connector::new(None)
+        .get_http_session(&peer)
+        .await
+        .expect("Failed to connect to the proxy");
+    session
+}
+
+fn call(method: &str) -> Box<RequestHeader> {
+    let mut request = RequestHeader::build(
+        "POST",
+        format!("https://localhost{method}").as_bytes(),
+        None,
+    )
+    .unwrap();
+    request
+        .insert_header("content-type", "application/grpc")
+        .unwrap();
+    request.insert_header("te", "trailers").unwrap();
+    request
+        .insert_header("grpc-accept-encoding", "identity")
+        .unwrap();
+    request.insert_header("accept-encoding", "gzip").unwrap();
+    Box::new(request)
+}
+
+async fn read_log(path: &Path, lines: usize) -> Vec<String> {
+    let start = std::time::Instant::now();
+
+    while start.elapsed() < Duration::from_secs(5) {
+        let content = std::fs::read_to_string(path).unwrap_or_default();
+        if content.lines().count() >= lines {
+            return content.lines().map(String::from).collect();
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("Access log did not get {lines} lines within timeout");
+}
+
+#[tokio::test]
+async fn test_grpc_trailers_pass_through() {
+    let backend = spawn_grpc_backend().await;
+    let proxy_port = get_free_port();
+    let log_dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let log_path = log_dir.path().join("access.log");
+    let assets = Path::new(env!("CARGO_MANIFEST_DIR")).join("../motya/assets");
+
+    let config_content = GRPC_CONFIG_TEMPLATE
+        .replace("__PROXY_PORT__", &proxy_port.to_string())
+        .replace("__BACKEND__", &backend.to_string())
+        .replace("__ASSETS__", assets.to_str().unwrap())
+        .replace("__LOG__", log_path.to_str().unwrap());
+
+    let mut config_file = NamedTempFile::new().expect("Failed to create temp config file");
+    write!(config_file, "{}", config_content).expect("Failed to write config content");
+
+    let cli = Cli {
+        validate_configs: false,
+        threads_per_service: None,
+        config_entry: Some(config_file.path().to_path_buf()),
+        daemonize: false,
+        upgrade: false,
+        pidfile: None,
+        profile: None,
+        upgrade_socket: None,
+        command: None,
+    };
+
+    let mut app_ctx = AppContext::bootstrap(cli)
+        .await
+        .expect("Failed to bootstrap AppContext");
+    let services = app_ctx
+        .build_services()
+        .await
+        .expect("Failed to build services");
+
+    let (mut server, _watcher) = app_ctx.ready();
+    server.add_services(services);
+    server.bootstrap();
+
+    thread::spawn(move || {
+        server.run_forever();
+    });
+
+    let proxy_addr = format!("127.0.0.1:{proxy_port}");
+    wait_for_proxy(&proxy_addr).await;
+
+    let HttpSession::H2(mut session) = connect(&proxy_addr).await else {
+        panic!("The proxy must offer HTTP/2");
+    };
+    session
+        .write_request_header(call("/greeter.Greeter/SayHello"), false)
+        .expect("Failed to send the call");
+    session
+        .write_request_body(message(b"hello"), true)
+        .await
+        .expect("Failed to send the message");
+
+    session.read_response_header().await.unwrap();
+    let response = session.response_header().unwrap();
+    assert_eq!(response.status, 200);
+    assert_eq!(response.headers["content-type"], "application/grpc");
+    assert!(
+        response.headers.get("content-encoding").is_none(),
+        "gRPC responses must not be compressed by the proxy"
+    );
+
+    let mut body = Vec::new();
+    while let Some(chunk) = session.read_response_body().await.unwrap() {
+        body.extend_from_slice(&chunk);
+    }
+    assert_eq!(body, message(b"hello"));
+
+    let trailers = session
+        .read_trailers()
+        .await
+        .unwrap()
+        .expect("The trailers must reach the client");
+    assert_eq!(trailers["grpc-status"], "0");
+    assert_eq!(trailers["grpc-message"], "echoed");
+
+    // A call that fails before its first message answers with headers only.
+    let HttpSession::H2(mut session) = connect(&proxy_addr).await else {
+        panic!("The proxy must offer HTTP/2");
+    };
+    session
+        .write_request_header(call("/greeter.Greeter/SayGoodbye"), false)
+        .expect("Failed to send the call");
+    session
+        .write_request_body(message(b"bye"), true)
+        .await
+        .expect("Failed to send the message");
+
+    session.read_response_header().await.unwrap();
+    let response = session.response_header().unwrap();
+    assert_eq!(response.headers["grpc-status"], "5");
+
+    let lines = read_log(&log_path, 2).await;
+    assert!(
+        lines.contains(&"/greeter.Greeter/SayHello 200 0".to_string()),
+        "{lines:?}"
+    );
+    assert!(
+        lines.contains(&"/greeter.Greeter/SayGoodbye 200 5".to_string()),
+        "{lines:?}"
+    );
+}
@@ -1,8 +1,14 @@
 #![cfg(test)]
+mod admin_api;
 mod check_cidr;
 mod check_cli_serve_and_hello;
 mod check_diff_filewatcher;
 mod common;
+mod file_server;
+mod grpc;
 mod integration_filters;
 mod load_balancer;
 mod load_balancer_ketama;
+mod proxy_protocol;
+mod sse;
+mod websocket;
@@ -97,6 +97,7 @@ async fn test_load_balancer_round_robin_distribution() {
         daemonize: false,
         upgrade: false,
         pidfile: None,
+        profile: None,
         upgrade_socket: None,
         command: None,
     };
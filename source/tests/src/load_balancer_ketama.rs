@@ -130,6 +130,7 @@ async fn test_ketama_hashing_with_transforms() {
         daemonize: false,
         upgrade: false,
         pidfile: None,
+        profile: None,
         upgrade_socket: None,
         command: None,
     };
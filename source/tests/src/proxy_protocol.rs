@@ -0,0 +1,166 @@
+use std::{io::Write, net::SocketAddr, path::Path, thread, time::Duration};
+
+use motya::app_context::AppContext;
+use motya_config::cli::cli_struct::Cli;
+use tempfile::NamedTempFile;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+const PROXY_PROTOCOL_CONFIG_TEMPLATE: &str = r#"
+system { }
+services {
+    BehindBalancer {
+        listeners {
+            "127.0.0.1:__PROXY_PORT__" proxy-protocol=#true
+        }
+        access-log "$remote_addr $path $status" path="__LOG__"
+        connectors {
+            proxy "http://__BACKEND__"
+        }
+    }
+}
+"#;
+
+async fn spawn_backend() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut buf = [0u8; 1024];
+                let _ = socket.read(&mut buf).await;
+                let _ = socket
+                    .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok")
+                    .await;
+            });
+        }
+    });
+
+    addr
+}
+
+fn get_free_port() -> u16 {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("Failed to bind");
+    listener.local_addr().unwrap().port()
+}
+
+async fn wait_for_proxy(addr: &str) {
+    let start = std::time::Instant::now();
+
+    while start.elapsed() < Duration::from_secs(5) {
+        if TcpStream::connect(addr).await.is_ok() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("Proxy did not start at {addr} within timeout");
+}
+
+async fn read_log(path: &Path, lines: usize) -> Vec<String> {
+    let start = std::time::Instant::now();
+
+    while start.elapsed() < Duration::from_secs(5) {
+        let content = std::fs::read_to_string(path).unwrap_or_default();
+        if content.lines().count() >= lines {
+            return content.lines().map(String::from).collect();
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("Access log did not get {lines} lines within timeout");
+}
+
+/// Sends `data` and returns everything the proxy answers until it closes.
+async fn exchange(proxy_addr: &str, data: &[u8]) -> String {
+    let mut client = TcpStream::connect(proxy_addr).await.unwrap();
+    client.write_all(data).await.unwrap();
+
+    let mut response = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), client.read_to_end(&mut response))
+        .await
+        .expect("The proxy must answer and close")
+        .unwrap_or_default();
+    String::from_utf8_lossy(&response).into_owned()
+}
+
+#[tokio::test]
+async fn test_proxy_protocol_client_address() {
+    let backend = spawn_backend().await;
+    let proxy_port = get_free_port();
+    let log_dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let log_path = log_dir.path().join("access.log");
+
+    let config_content = PROXY_PROTOCOL_CONFIG_TEMPLATE
+        .replace("__PROXY_PORT__", &proxy_port.to_string())
+        .replace("__BACKEND__", &backend.to_string())
+        .replace("__LOG__", log_path.to_str().unwrap());
+
+    let mut config_file = NamedTempFile::new().expect("Failed to create temp config file");
+    write!(config_file, "{}", config_content).expect("Failed to write config content");
+
+    let cli = Cli {
+        validate_configs: false,
+        threads_per_service: None,
+        config_entry: Some(config_file.path().to_path_buf()),
+        daemonize: false,
+        upgrade: false,
+        pidfile: None,
+        profile: None,
+        upgrade_socket: None,
+        command: None,
+    };
+
+    let mut app_ctx = AppContext::bootstrap(cli)
+        .await
+        .expect("Failed to bootstrap AppContext");
+    let services = app_ctx
+        .build_services()
+        .await
+        .expect("Failed to build services");
+
+    let (mut server, _watcher) = app_ctx.ready();
+    server.add_services(services);
+    server.bootstrap();
+
+    thread::spawn(move || {
+        server.run_forever();
+    });
+
+    let proxy_addr = format!("127.0.0.1:{proxy_port}");
+    wait_for_proxy(&proxy_addr).await;
+
+    let response = exchange(
+        &proxy_addr,
+        b"PROXY TCP4 203.0.113.7 127.0.0.1 51234 80\r\n\
+          GET /v1 HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+
+    let mut v2 = b"\r\n\r\n\0\r\nQUIT\n\x21\x11\x00\x0c".to_vec();
+    v2.extend([198, 51, 100, 9, 127, 0, 0, 1, 0xc8, 0x22, 0x00, 0x50]);
+    v2.extend(b"GET /v2 HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    let response = exchange(&proxy_addr, &v2).await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+
+    // Without the header, the connection is closed before any HTTP is parsed.
+    let response = exchange(
+        &proxy_addr,
+        b"GET /none HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+    assert_eq!(response, "");
+
+    let lines = read_log(&log_path, 2).await;
+    assert!(
+        lines.contains(&"203.0.113.7 /v1 200".to_string()),
+        "{lines:?}"
+    );
+    assert!(
+        lines.contains(&"198.51.100.9 /v2 200".to_string()),
+        "{lines:?}"
+    );
+}
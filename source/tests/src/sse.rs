@@ -0,0 +1,177 @@
+use std::{io::Write, net::SocketAddr, thread, time::Duration};
+
+use motya::app_context::AppContext;
+use motya_config::cli::cli_struct::Cli;
+use reqwest::Client;
+use tempfile::NamedTempFile;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+const SSE_CONFIG_TEMPLATE: &str = r#"
+system { }
+services {
+    SseTest {
+        listeners {
+            "127.0.0.1:__PROXY_PORT__"
+        }
+        connectors {
+            section "/" {
+                sse #true idle-timeout="10s"
+                proxy "http://__BACKEND__"
+            }
+        }
+    }
+}
+"#;
+
+const EVENT_GAP: Duration = Duration::from_secs(3);
+
+/// A minimal event-stream backend: every connection gets one event right away and a
+/// second one after [`EVENT_GAP`].
+async fn spawn_sse_backend() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        loop {
+            let (socket, _) = listener.accept().await.unwrap();
+            tokio::spawn(serve_events(socket));
+        }
+    });
+
+    addr
+}
+
+async fn serve_events(mut socket: TcpStream) {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.ends_with(b"\r\n\r\n") {
+        let n = socket.read(&mut buf).await.unwrap();
+        if n == 0 {
+            return;
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+
+    let encoding_requested = String::from_utf8_lossy(&request)
+        .to_ascii_lowercase()
+        .contains("accept-encoding");
+    let header = if encoding_requested {
+        "X-Saw-Accept-Encoding: yes\r\n"
+    } else {
+        ""
+    };
+
+    socket
+        .write_all(
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n{header}Transfer-Encoding: chunked\r\n\r\n"
+            )
+            .as_bytes(),
+        )
+        .await
+        .unwrap();
+
+    write_chunk(&mut socket, b"data: first\n\n").await;
+    tokio::time::sleep(EVENT_GAP).await;
+    write_chunk(&mut socket, b"data: second\n\n").await;
+    socket.write_all(b"0\r\n\r\n").await.unwrap();
+}
+
+async fn write_chunk(socket: &mut TcpStream, data: &[u8]) {
+    let frame = [format!("{:x}\r\n", data.len()).as_bytes(), data, b"\r\n"].concat();
+    socket.write_all(&frame).await.unwrap();
+    socket.flush().await.unwrap();
+}
+
+fn get_free_port() -> u16 {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("Failed to bind");
+    listener.local_addr().unwrap().port()
+}
+
+async fn wait_for_proxy(addr: &str) {
+    let start = std::time::Instant::now();
+
+    while start.elapsed() < Duration::from_secs(5) {
+        if TcpStream::connect(addr).await.is_ok() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("Proxy did not start at {addr} within timeout");
+}
+
+#[tokio::test]
+async fn test_sse_events_are_not_buffered() {
+    let backend = spawn_sse_backend().await;
+    let proxy_port = get_free_port();
+
+    let config_content = SSE_CONFIG_TEMPLATE
+        .replace("__PROXY_PORT__", &proxy_port.to_string())
+        .replace("__BACKEND__", &backend.to_string());
+
+    let mut config_file = NamedTempFile::new().expect("Failed to create temp config file");
+    write!(config_file, "{}", config_content).expect("Failed to write config content");
+
+    let cli = Cli {
+        validate_configs: false,
+        threads_per_service: None,
+        config_entry: Some(config_file.path().to_path_buf()),
+        daemonize: false,
+        upgrade: false,
+        pidfile: None,
+        profile: None,
+        upgrade_socket: None,
+        command: None,
+    };
+
+    let mut app_ctx = AppContext::bootstrap(cli)
+        .await
+        .expect("Failed to bootstrap AppContext");
+    let services = app_ctx
+        .build_services()
+        .await
+        .expect("Failed to build services");
+
+    let (mut server, _watcher) = app_ctx.ready();
+    server.add_services(services);
+    server.bootstrap();
+
+    thread::spawn(move || {
+        server.run_forever();
+    });
+
+    let proxy_addr = format!("127.0.0.1:{proxy_port}");
+    wait_for_proxy(&proxy_addr).await;
+
+    let mut response = Client::new()
+        .get(format!("http://{proxy_addr}/"))
+        .header("Accept-Encoding", "gzip")
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["content-type"], "text/event-stream");
+    assert_eq!(response.headers()["cache-control"], "no-cache");
+    assert_eq!(response.headers()["x-accel-buffering"], "no");
+    assert!(
+        response.headers().get("x-saw-accept-encoding").is_none(),
+        "Accept-Encoding must not reach the upstream of an SSE route"
+    );
+
+    let first = tokio::time::timeout(EVENT_GAP / 2, response.chunk())
+        .await
+        .expect("First event must arrive before the second one is sent")
+        .expect("Failed to read body")
+        .expect("Stream ended early");
+    assert_eq!(&first[..], b"data: first\n\n");
+
+    let rest = tokio::time::timeout(EVENT_GAP * 2, response.bytes())
+        .await
+        .expect("Stream must finish after the second event")
+        .expect("Failed to read body");
+    assert_eq!(&rest[..], b"data: second\n\n");
+}
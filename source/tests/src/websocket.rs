@@ -0,0 +1,218 @@
+use std::{io::Write, net::SocketAddr, thread, time::Duration};
+
+use motya::app_context::AppContext;
+use motya_config::cli::cli_struct::Cli;
+use tempfile::NamedTempFile;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+const WS_CONFIG_TEMPLATE: &str = r#"
+system { }
+services {
+    WsTest {
+        listeners {
+            "127.0.0.1:__PROXY_PORT__"
+        }
+        connectors {
+            section "/ws" {
+                proxy "http://__BACKEND__"
+            }
+            section "/plain" allow-upgrades=#false {
+                proxy "http://__BACKEND__"
+            }
+        }
+    }
+}
+"#;
+
+const HANDSHAKE: &str = "Upgrade: websocket\r\n\
+    Connection: Upgrade\r\n\
+    Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+    Sec-WebSocket-Version: 13\r\n";
+
+/// A minimal WebSocket backend: upgrade requests are switched and echoed back byte
+/// for byte, anything else gets a plain response telling it saw no upgrade.
+async fn spawn_ws_backend() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        loop {
+            let (socket, _) = listener.accept().await.unwrap();
+            tokio::spawn(serve(socket));
+        }
+    });
+
+    addr
+}
+
+async fn serve(mut socket: TcpStream) {
+    let Some(request) = read_head(&mut socket).await else {
+        return;
+    };
+
+    if !request.to_ascii_lowercase().contains("upgrade: websocket") {
+        let body = "no upgrade";
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        socket.write_all(response.as_bytes()).await.unwrap();
+        return;
+    }
+
+    socket
+        .write_all(
+            b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+              Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\r\n",
+        )
+        .await
+        .unwrap();
+
+    let mut buf = [0u8; 1024];
+    loop {
+        let n = match socket.read(&mut buf).await {
+            Ok(0) | Err(_) => return,
+            Ok(n) => n,
+        };
+        if socket.write_all(&buf[..n]).await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn read_head(socket: &mut TcpStream) -> Option<String> {
+    let mut head = Vec::new();
+    let mut byte = [0u8; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        if socket.read(&mut byte).await.ok()? == 0 {
+            return None;
+        }
+        head.push(byte[0]);
+    }
+
+    Some(String::from_utf8_lossy(&head).into_owned())
+}
+
+fn get_free_port() -> u16 {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("Failed to bind");
+    listener.local_addr().unwrap().port()
+}
+
+async fn wait_for_proxy(addr: &str) {
+    let start = std::time::Instant::now();
+
+    while start.elapsed() < Duration::from_secs(5) {
+        if TcpStream::connect(addr).await.is_ok() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("Proxy did not start at {addr} within timeout");
+}
+
+async fn start_proxy(backend: SocketAddr) -> String {
+    let proxy_port = get_free_port();
+
+    let config_content = WS_CONFIG_TEMPLATE
+        .replace("__PROXY_PORT__", &proxy_port.to_string())
+        .replace("__BACKEND__", &backend.to_string());
+
+    let mut config_file = NamedTempFile::new().expect("Failed to create temp config file");
+    write!(config_file, "{}", config_content).expect("Failed to write config content");
+
+    let cli = Cli {
+        validate_configs: false,
+        threads_per_service: None,
+        config_entry: Some(config_file.path().to_path_buf()),
+        daemonize: false,
+        upgrade: false,
+        pidfile: None,
+        profile: None,
+        upgrade_socket: None,
+        command: None,
+    };
+
+    let mut app_ctx = AppContext::bootstrap(cli)
+        .await
+        .expect("Failed to bootstrap AppContext");
+    let services = app_ctx
+        .build_services()
+        .await
+        .expect("Failed to build services");
+
+    let (mut server, _watcher) = app_ctx.ready();
+    server.add_services(services);
+    server.bootstrap();
+
+    thread::spawn(move || {
+        server.run_forever();
+    });
+
+    let proxy_addr = format!("127.0.0.1:{proxy_port}");
+    wait_for_proxy(&proxy_addr).await;
+    proxy_addr
+}
+
+#[tokio::test]
+async fn test_websocket_upgrade_is_proxied() {
+    let backend = spawn_ws_backend().await;
+    let proxy_addr = start_proxy(backend).await;
+
+    let mut client = TcpStream::connect(&proxy_addr).await.unwrap();
+    client
+        .write_all(format!("GET /ws HTTP/1.1\r\nHost: {proxy_addr}\r\n{HANDSHAKE}\r\n").as_bytes())
+        .await
+        .unwrap();
+
+    let head = tokio::time::timeout(Duration::from_secs(5), read_head(&mut client))
+        .await
+        .expect("Upgrade response must arrive")
+        .expect("Connection closed before the response");
+    assert!(
+        head.starts_with("HTTP/1.1 101"),
+        "Unexpected response: {head}"
+    );
+    assert!(head.to_ascii_lowercase().contains("upgrade: websocket"));
+
+    // Both directions stay open after the switch, frames or not.
+    for message in [&b"\x81\x05hello"[..], &b"\x81\x05again"[..]] {
+        client.write_all(message).await.unwrap();
+
+        let mut echoed = vec![0u8; message.len()];
+        tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut echoed))
+            .await
+            .expect("Echo must arrive without waiting for the connection to end")
+            .unwrap();
+        assert_eq!(echoed, message);
+    }
+}
+
+#[tokio::test]
+async fn test_upgrade_headers_dropped_when_not_allowed() {
+    let backend = spawn_ws_backend().await;
+    let proxy_addr = start_proxy(backend).await;
+
+    let mut client = TcpStream::connect(&proxy_addr).await.unwrap();
+    client
+        .write_all(
+            format!("GET /plain HTTP/1.1\r\nHost: {proxy_addr}\r\n{HANDSHAKE}\r\n").as_bytes(),
+        )
+        .await
+        .unwrap();
+
+    let head = tokio::time::timeout(Duration::from_secs(5), read_head(&mut client))
+        .await
+        .expect("Response must arrive")
+        .expect("Connection closed before the response");
+    assert!(
+        head.starts_with("HTTP/1.1 200"),
+        "Unexpected response: {head}"
+    );
+
+    let mut body = [0u8; 10];
+    client.read_exact(&mut body).await.unwrap();
+    assert_eq!(&body, b"no upgrade");
+}